@@ -1,4 +1,5 @@
 use std::cmp::min;
+use std::convert::TryFrom;
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::mem;
@@ -8,12 +9,24 @@ use std::ptr;
 use std::result::Result as StdResult;
 use std::slice;
 
+#[cfg(feature = "aes-gcm-siv")]
+use aes_gcm_siv::aead::generic_array::GenericArray;
+#[cfg(feature = "aes-gcm-siv")]
+use aes_gcm_siv::aead::{AeadInPlace, KeyInit};
+#[cfg(feature = "aes-gcm-siv")]
+use aes_gcm_siv::Aes256GcmSiv;
+#[cfg(feature = "argon2")]
+use argon2::{Algorithm, Argon2, Params, Version};
 use serde::de::{self, Deserializer};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 
 use error::{Error, Result};
 
+#[cfg(feature = "crypto-rust")]
+use super::crypto_rust::*;
+
+#[cfg(not(feature = "crypto-rust"))]
 extern "C" {
     // Initialisation
     // --------------
@@ -69,6 +82,18 @@ extern "C" {
         memlimit: usize,
         alg: i32,
     ) -> i32;
+    #[cfg(not(feature = "scrypt"))]
+    fn crypto_pwhash_scryptsalsa208sha256_ll(
+        passwd: *const u8,
+        passwdlen: usize,
+        salt: *const u8,
+        saltlen: usize,
+        n: u64,
+        r: u32,
+        p: u32,
+        buf: *mut u8,
+        buflen: usize,
+    ) -> i32;
 
     // key derivation
     // --------------
@@ -143,6 +168,23 @@ extern "C" {
         k: *const u8,
     ) -> i32;
 
+    // Sealed box
+    // ----------
+    fn crypto_box_keypair(pk: *mut u8, sk: *mut u8) -> i32;
+    fn crypto_box_seal(
+        c: *mut u8,
+        m: *const u8,
+        mlen: u64,
+        pk: *const u8,
+    ) -> i32;
+    fn crypto_box_seal_open(
+        m: *mut u8,
+        c: *const u8,
+        clen: u64,
+        pk: *const u8,
+        sk: *const u8,
+    ) -> i32;
+
     // Helpers
     // -------
     fn sodium_memzero(pnt: *mut u8, len: usize);
@@ -184,6 +226,11 @@ impl<T: Sized> SafeBox<T> {
         unsafe { slice::from_raw_parts(self.as_ptr(), self.len()) }
     }
 
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len()) }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         mem::size_of::<T>()
@@ -562,14 +609,203 @@ impl From<i32> for MemLimit {
     }
 }
 
+/// Custom Argon2id password hash parameters.
+///
+/// [`OpsLimit`] and [`MemLimit`] presets cover common use cases, these
+/// parameters can be used instead to tune the password hashing cost
+/// precisely. They are set by [`RepoOpener::kdf_params`].
+///
+/// - `mem_kib`: memory size in KiB, must be at least 8 times of
+///   `parallelism` and not larger than 4 GiB
+/// - `iterations`: number of passes over the memory, must be at least 1
+/// - `parallelism`: degree of parallelism, must be between 1 and 64
+///
+/// libsodium only supports a single lane, so `parallelism` must be 1 unless
+/// Cargo feature `argon2` or `crypto-rust` is enabled, otherwise
+/// `Error::InvalidArgument` is returned.
+///
+/// [`OpsLimit`]: enum.OpsLimit.html
+/// [`MemLimit`]: enum.MemLimit.html
+/// [`RepoOpener::kdf_params`]: struct.RepoOpener.html#method.kdf_params
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KdfParams {
+    mem_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    // maximum memory size, 4 GiB
+    const MAX_MEM_KIB: u32 = 4 * 1024 * 1024;
+
+    // maximum degree of parallelism
+    const MAX_PARALLELISM: u32 = 64;
+
+    pub(crate) fn new(mem_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        KdfParams {
+            mem_kib,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// Returns memory size in KiB.
+    #[inline]
+    pub fn mem_kib(&self) -> u32 {
+        self.mem_kib
+    }
+
+    /// Returns number of iterations.
+    #[inline]
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Returns degree of parallelism.
+    #[inline]
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    // validate parameters
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.parallelism == 0
+            || self.parallelism > Self::MAX_PARALLELISM
+            || self.iterations == 0
+            || self.mem_kib < 8 * self.parallelism
+            || self.mem_kib > Self::MAX_MEM_KIB
+        {
+            return Err(Error::InvalidCost);
+        }
+
+        // multiple lanes are not supported by libsodium
+        #[cfg(not(feature = "argon2"))]
+        {
+            if self.parallelism > 1 {
+                return Err(Error::InvalidArgument);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// scrypt password hash parameters.
+///
+/// They are set by [`RepoOpener::scrypt_params`].
+///
+/// - `log_n`: base 2 logarithm of the CPU/memory cost `N`, must be at
+///   least 1
+/// - `r`: block size, must be at least 1
+/// - `p`: degree of parallelism, must be at least 1
+///
+/// The memory used is `128 * r * 2^log_n` bytes, which must not be larger
+/// than 4 GiB.
+///
+/// [`RepoOpener::scrypt_params`]: struct.RepoOpener.html#method.scrypt_params
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScryptParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl ScryptParams {
+    // maximum memory size in bytes, 4 GiB
+    const MAX_MEM: u64 = 4 * 1024 * 1024 * 1024;
+
+    pub(crate) fn new(log_n: u8, r: u32, p: u32) -> Self {
+        ScryptParams { log_n, r, p }
+    }
+
+    /// Returns base 2 logarithm of the CPU/memory cost.
+    #[inline]
+    pub fn log_n(&self) -> u8 {
+        self.log_n
+    }
+
+    /// Returns block size.
+    #[inline]
+    pub fn r(&self) -> u32 {
+        self.r
+    }
+
+    /// Returns degree of parallelism.
+    #[inline]
+    pub fn p(&self) -> u32 {
+        self.p
+    }
+
+    // convert to scrypt crate parameters, also validate them
+    #[cfg(feature = "scrypt")]
+    fn to_params(self) -> Result<scrypt::Params> {
+        self.check_limits()?;
+        scrypt::Params::new(self.log_n, self.r, self.p, HASH_SIZE)
+            .map_err(|_| Error::InvalidCost)
+    }
+
+    fn check_limits(&self) -> Result<()> {
+        if self.log_n == 0
+            || self.log_n >= 64
+            || self.r == 0
+            || self.p == 0
+            || u64::from(self.r) * u64::from(self.p) >= 1 << 30
+            || (128 * u64::from(self.r)).saturating_mul(1 << self.log_n)
+                > Self::MAX_MEM
+        {
+            return Err(Error::InvalidCost);
+        }
+        Ok(())
+    }
+
+    // validate parameters
+    #[inline]
+    pub(crate) fn check(&self) -> Result<()> {
+        #[cfg(feature = "scrypt")]
+        {
+            self.to_params().map(|_| ())
+        }
+        #[cfg(not(feature = "scrypt"))]
+        {
+            self.check_limits()
+        }
+    }
+}
+
+/// Password hashing algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id, either with [`OpsLimit`] and [`MemLimit`] presets or with
+    /// custom [`KdfParams`]. This is the default.
+    ///
+    /// [`OpsLimit`]: enum.OpsLimit.html
+    /// [`MemLimit`]: enum.MemLimit.html
+    /// [`KdfParams`]: struct.KdfParams.html
+    Argon2id,
+
+    /// scrypt with [`ScryptParams`].
+    ///
+    /// [`ScryptParams`]: struct.ScryptParams.html
+    Scrypt,
+}
+
 /// Password hashing cost consists of [`OpsLimit`] and [`MemLimit`].
 ///
+/// If custom [`KdfParams`] is set, it is used instead of the presets. If
+/// [`ScryptParams`] is set, scrypt is used instead of Argon2id.
+///
 /// [`OpsLimit`]: enum.OpsLimit.html
 /// [`MemLimit`]: enum.MemLimit.html
+/// [`KdfParams`]: struct.KdfParams.html
+/// [`ScryptParams`]: struct.ScryptParams.html
 #[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
 pub struct Cost {
     pub ops_limit: OpsLimit,
     pub mem_limit: MemLimit,
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+    #[serde(default)]
+    pub scrypt: Option<ScryptParams>,
 }
 
 impl Cost {
@@ -579,6 +815,28 @@ impl Cost {
         Cost {
             ops_limit,
             mem_limit,
+            kdf: None,
+            scrypt: None,
+        }
+    }
+
+    /// Returns the password hashing algorithm.
+    #[inline]
+    pub fn kdf_alg(&self) -> Kdf {
+        if self.scrypt.is_some() {
+            Kdf::Scrypt
+        } else {
+            Kdf::Argon2id
+        }
+    }
+
+    // validate custom parameters, only one algorithm can be specified
+    pub(crate) fn check(&self) -> Result<()> {
+        match (self.kdf, self.scrypt) {
+            (Some(_), Some(_)) => Err(Error::InvalidCost),
+            (Some(kdf), None) => kdf.check(),
+            (None, Some(scrypt)) => scrypt.check(),
+            (None, None) => Ok(()),
         }
     }
 
@@ -610,6 +868,8 @@ impl Cost {
                 2 => MemLimit::Sensitive,
                 _ => return Err(Error::InvalidCost),
             },
+            kdf: None,
+            scrypt: None,
         })
     }
 }
@@ -642,13 +902,21 @@ impl Default for Key {
     }
 }
 
+/// Sealed box key pair, and the overhead added to each sealed message
+pub const BOX_PUBLIC_KEY_SIZE: usize = 32;
+pub const BOX_SECRET_KEY_SIZE: usize = 32;
+pub const BOX_SEAL_SIZE: usize = 48;
+pub type BoxPublicKey = [u8; BOX_PUBLIC_KEY_SIZE];
+pub type BoxSecretKey = SafeBox<[u8; BOX_SECRET_KEY_SIZE]>;
+
 /// Crypto cipher primitives.
 ///
 /// See <https://download.libsodium.org/doc/secret-key_cryptography/aead> for
 /// more details.
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub enum Cipher {
-    /// XChaCha20-Poly1305-IETF
+    /// XChaCha20-Poly1305-IETF, it uses 192-bit nonces, so randomly
+    /// generated nonces are safe at any volume size.
     /// - Key size: 256 bits
     /// - Nonce size: 192 bits
     /// - Block size: 512 bits
@@ -662,15 +930,66 @@ pub enum Cipher {
     /// - Block size: 128 bits
     /// - MAC size: 128 bits
     Aes = 1,
+
+    /// AES256-GCM-SIV, nonce-misuse-resistant AES. Unlike `Aes`, reusing a
+    /// nonce only reveals whether two messages are identical, so it is safe
+    /// even if the random nonce generator is rewound, for example after a
+    /// crash or a VM snapshot restore. It doesn't require hardware support.
+    /// It needs Cargo feature `aes-gcm-siv` or `crypto-rust`, otherwise
+    /// `Error::InvalidCipher` is returned when it is used.
+    /// - Key size: 256 bits
+    /// - Nonce size: 96 bits
+    /// - Block size: 128 bits
+    /// - MAC size: 128 bits
+    AesGcmSiv = 2,
+
+    /// Integrity-only mode, data is not encrypted but authenticated by
+    /// keyed BLAKE2b, so any tampering is still detected. It is for public
+    /// data which needs tamper-evidence and versioning at near-zero CPU
+    /// cost. Keys in super block are still encrypted by XChaCha20-Poly1305.
+    /// - Key size: 256 bits
+    /// - Nonce size: none
+    /// - MAC size: 128 bits
+    Integrity = 3,
 }
 
 impl Cipher {
     pub(crate) const BYTES_LEN: usize = 1;
 
+    /// Returns the nonce strategy used by this cipher.
+    #[inline]
+    pub fn nonce_strategy(self) -> NonceStrategy {
+        match self {
+            Cipher::Xchacha => NonceStrategy::Random,
+            Cipher::Aes => NonceStrategy::RandomExtended,
+            Cipher::AesGcmSiv => NonceStrategy::MisuseResistant,
+            Cipher::Integrity => NonceStrategy::Deterministic,
+        }
+    }
+
+    /// Returns `false` if this cipher doesn't encrypt data.
+    #[inline]
+    pub fn is_confidential(self) -> bool {
+        self != Cipher::Integrity
+    }
+
+    // cipher used to encrypt keys, integrity-only cipher cannot protect
+    // keys so XChaCha20 is used instead
+    #[inline]
+    pub(crate) fn key_cipher(self) -> Cipher {
+        if self.is_confidential() {
+            self
+        } else {
+            Cipher::Xchacha
+        }
+    }
+
     pub(crate) fn from_u8(s: u8) -> Result<Self> {
         Ok(match s {
             0 => Cipher::Xchacha,
             1 => Cipher::Aes,
+            2 => Cipher::AesGcmSiv,
+            3 => Cipher::Integrity,
             _ => return Err(Error::InvalidCipher),
         })
     }
@@ -681,6 +1000,8 @@ impl Into<u8> for Cipher {
         match self {
             Cipher::Xchacha => 0,
             Cipher::Aes => 1,
+            Cipher::AesGcmSiv => 2,
+            Cipher::Integrity => 3,
         }
     }
 }
@@ -690,16 +1011,22 @@ impl Into<i32> for Cipher {
         match self {
             Cipher::Xchacha => 0,
             Cipher::Aes => 1,
+            Cipher::AesGcmSiv => 2,
+            Cipher::Integrity => 3,
         }
     }
 }
 
-impl From<i32> for Cipher {
-    fn from(n: i32) -> Self {
+impl TryFrom<i32> for Cipher {
+    type Error = Error;
+
+    fn try_from(n: i32) -> Result<Self> {
         match n {
-            0 => Cipher::Xchacha,
-            1 => Cipher::Aes,
-            _ => unimplemented!(),
+            0 => Ok(Cipher::Xchacha),
+            1 => Ok(Cipher::Aes),
+            2 => Ok(Cipher::AesGcmSiv),
+            3 => Ok(Cipher::Integrity),
+            _ => Err(Error::InvalidSuperBlk),
         }
     }
 }
@@ -710,6 +1037,33 @@ impl Default for Cipher {
     }
 }
 
+/// Nonce strategy.
+///
+/// A new nonce is randomly generated for each encryption and stored along
+/// with the cipher text, no nonce state needs to be kept across sessions. The
+/// strategy of a repository is recorded in its super block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NonceStrategy {
+    /// 192-bit random nonce, used by `Cipher::Xchacha`. The collision
+    /// probability is negligible even after 2^80 encryptions with the same
+    /// key, so it is safe at any volume size.
+    Random,
+
+    /// 224-bit random nonce, used by `Cipher::Aes`. The first 128 bits and
+    /// the key derive a sub-key by HChaCha20, the last 96 bits are used as
+    /// AES-GCM nonce with the sub-key. This extends the 96-bit AES-GCM nonce
+    /// so it is as safe as `Random`.
+    RandomExtended,
+
+    /// 96-bit random nonce, used by `Cipher::AesGcmSiv`. The nonce is short,
+    /// but nonce collision only reveals whether two messages are identical.
+    MisuseResistant,
+
+    /// No nonce, used by `Cipher::Integrity`. Data is not encrypted, so the
+    /// same message always has the same authentication tag.
+    Deterministic,
+}
+
 /// Authentication tag size
 const ATAG_SIZE: usize = 16;
 
@@ -718,6 +1072,7 @@ const ATAG_SIZE: usize = 16;
 /// is larger than Xchacha, we combine them into a single nonce type.
 const AES_NONCE_SIZE: usize = 28;
 const XCHACHA_NONCE_SIZE: usize = 24;
+const AES_GCM_SIV_NONCE_SIZE: usize = 12;
 type Nonce = [u8; AES_NONCE_SIZE];
 
 // encrypt/decrypt function type
@@ -744,6 +1099,229 @@ type DecryptFn = unsafe extern "C" fn(
     k: *const u8,
 ) -> i32;
 
+// AES256-GCM-SIV is not provided by libsodium, these functions wrap the
+// RustCrypto implementation as libsodium AEAD functions
+#[cfg(feature = "aes-gcm-siv")]
+unsafe extern "C" fn aes256gcmsiv_encrypt(
+    c: *mut u8,
+    clen_p: *const u64,
+    m: *const u8,
+    mlen: u64,
+    ad: *const u8,
+    adlen: u64,
+    _nsec: *const u8,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let mlen = mlen as usize;
+    let cipher = Aes256GcmSiv::new(GenericArray::from_slice(
+        slice::from_raw_parts(k, KEY_SIZE),
+    ));
+    ptr::copy(m, c, mlen);
+    match cipher.encrypt_in_place_detached(
+        GenericArray::from_slice(slice::from_raw_parts(
+            npub,
+            AES_GCM_SIV_NONCE_SIZE,
+        )),
+        slice::from_raw_parts(ad, adlen as usize),
+        slice::from_raw_parts_mut(c, mlen),
+    ) {
+        Ok(tag) => {
+            ptr::copy(tag.as_ptr(), c.add(mlen), ATAG_SIZE);
+            *(clen_p as *mut u64) = (mlen + ATAG_SIZE) as u64;
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+#[cfg(feature = "aes-gcm-siv")]
+unsafe extern "C" fn aes256gcmsiv_decrypt(
+    m: *mut u8,
+    mlen_p: *const u64,
+    _nsec: *const u8,
+    c: *const u8,
+    clen: u64,
+    ad: *const u8,
+    adlen: u64,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let clen = clen as usize;
+    if clen < ATAG_SIZE {
+        return -1;
+    }
+    let mlen = clen - ATAG_SIZE;
+    let cipher = Aes256GcmSiv::new(GenericArray::from_slice(
+        slice::from_raw_parts(k, KEY_SIZE),
+    ));
+    ptr::copy(c, m, mlen);
+    match cipher.decrypt_in_place_detached(
+        GenericArray::from_slice(slice::from_raw_parts(
+            npub,
+            AES_GCM_SIV_NONCE_SIZE,
+        )),
+        slice::from_raw_parts(ad, adlen as usize),
+        slice::from_raw_parts_mut(m, mlen),
+        GenericArray::from_slice(slice::from_raw_parts(c.add(mlen), ATAG_SIZE)),
+    ) {
+        Ok(_) => {
+            *(mlen_p as *mut u64) = mlen as u64;
+            0
+        }
+        Err(_) => {
+            sodium_memzero(m, mlen);
+            -1
+        }
+    }
+}
+
+// hash password using scrypt, the RustCrypto implementation is used if it is
+// enabled, otherwise libsodium is used
+#[cfg(feature = "scrypt")]
+fn scrypt_hash(
+    passwd: &str,
+    salt: &Salt,
+    params: ScryptParams,
+    out: &mut [u8],
+) -> Result<()> {
+    let params = params.to_params()?;
+    scrypt::scrypt(passwd.as_bytes(), &salt.0, &params, out)
+        .map_err(|_| Error::Hashing)
+}
+
+#[cfg(not(feature = "scrypt"))]
+fn scrypt_hash(
+    passwd: &str,
+    salt: &Salt,
+    params: ScryptParams,
+    out: &mut [u8],
+) -> Result<()> {
+    unsafe {
+        match crypto_pwhash_scryptsalsa208sha256_ll(
+            passwd.as_ptr(),
+            passwd.len(),
+            salt.0.as_ptr(),
+            salt.0.len(),
+            1u64 << params.log_n,
+            params.r,
+            params.p,
+            out.as_mut_ptr(),
+            out.len(),
+        ) {
+            0 => Ok(()),
+            _ => Err(Error::Hashing),
+        }
+    }
+}
+
+// hash password using Argon2id with custom parameters, the RustCrypto
+// implementation is used if it is enabled, otherwise libsodium is used and
+// it only supports single lane
+#[cfg(feature = "argon2")]
+fn argon2id_hash(
+    passwd: &str,
+    salt: &Salt,
+    kdf: KdfParams,
+    out: &mut [u8],
+) -> Result<()> {
+    let params = Params::new(
+        kdf.mem_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(out.len()),
+    )
+    .map_err(|_| Error::InvalidCost)?;
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passwd.as_bytes(), &salt.0, out)
+        .map_err(|_| Error::Hashing)
+}
+
+#[cfg(not(feature = "argon2"))]
+fn argon2id_hash(
+    passwd: &str,
+    salt: &Salt,
+    kdf: KdfParams,
+    out: &mut [u8],
+) -> Result<()> {
+    unsafe {
+        match crypto_pwhash(
+            out.as_mut_ptr(),
+            out.len() as u64,
+            passwd.as_ptr(),
+            passwd.len() as u64,
+            salt.0.as_ptr(),
+            u64::from(kdf.iterations),
+            kdf.mem_kib as usize * 1024,
+            2, // version 1.3 of the Argon2id algorithm
+        ) {
+            0 => Ok(()),
+            _ => Err(Error::Hashing),
+        }
+    }
+}
+
+// integrity-only cipher doesn't encrypt message, its tag is the keyed
+// BLAKE2b hash of the digest of associated data and message, these functions
+// wrap it as libsodium AEAD functions
+unsafe fn integrity_tag(
+    m: *const u8,
+    mlen: u64,
+    ad: *const u8,
+    adlen: u64,
+    k: *const u8,
+) -> Hash {
+    let mut state = Crypto::hash_init();
+    Crypto::hash_update(&mut state, &adlen.to_le_bytes());
+    Crypto::hash_update(&mut state, slice::from_raw_parts(ad, adlen as usize));
+    Crypto::hash_update(&mut state, slice::from_raw_parts(m, mlen as usize));
+    let digest = Crypto::hash_final(&mut state);
+    Crypto::hash_raw(digest.as_ptr(), HASH_SIZE, k, KEY_SIZE)
+}
+
+unsafe extern "C" fn integrity_encrypt(
+    c: *mut u8,
+    clen_p: *const u64,
+    m: *const u8,
+    mlen: u64,
+    ad: *const u8,
+    adlen: u64,
+    _nsec: *const u8,
+    _npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let tag = integrity_tag(m, mlen, ad, adlen, k);
+    ptr::copy(m, c, mlen as usize);
+    ptr::copy(tag.as_ptr(), c.add(mlen as usize), ATAG_SIZE);
+    *(clen_p as *mut u64) = mlen + ATAG_SIZE as u64;
+    0
+}
+
+unsafe extern "C" fn integrity_decrypt(
+    m: *mut u8,
+    mlen_p: *const u64,
+    _nsec: *const u8,
+    c: *const u8,
+    clen: u64,
+    ad: *const u8,
+    adlen: u64,
+    _npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let clen = clen as usize;
+    if clen < ATAG_SIZE {
+        return -1;
+    }
+    let mlen = clen - ATAG_SIZE;
+    let tag = integrity_tag(c, mlen as u64, ad, adlen, k);
+    if sodium_memcmp(tag.as_ptr(), c.add(mlen), ATAG_SIZE) != 0 {
+        return -1;
+    }
+    ptr::copy(c, m, mlen);
+    *(mlen_p as *mut u64) = mlen as u64;
+    0
+}
+
 /// Crypto
 #[derive(Debug, Clone)]
 pub struct Crypto {
@@ -791,6 +1369,21 @@ impl Crypto {
                     dec_fn: crypto_aead_aes256gcm_decrypt,
                 })
             }
+            #[cfg(feature = "aes-gcm-siv")]
+            Cipher::AesGcmSiv => Ok(Crypto {
+                cost,
+                cipher,
+                enc_fn: aes256gcmsiv_encrypt,
+                dec_fn: aes256gcmsiv_decrypt,
+            }),
+            #[cfg(not(feature = "aes-gcm-siv"))]
+            Cipher::AesGcmSiv => Err(Error::InvalidCipher),
+            Cipher::Integrity => Ok(Crypto {
+                cost,
+                cipher,
+                enc_fn: integrity_encrypt,
+                dec_fn: integrity_decrypt,
+            }),
         }
     }
 
@@ -938,6 +1531,24 @@ impl Crypto {
         pwdhash.salt = salt.clone();
         pwdhash.cost = self.cost;
 
+        // use scrypt if it is specified
+        if let Some(sp) = self.cost.scrypt {
+            sp.check()?;
+            let value = unsafe {
+                slice::from_raw_parts_mut(pwdhash.value.as_mut_ptr(), KEY_SIZE)
+            };
+            return scrypt_hash(passwd, salt, sp, value).map(|_| pwdhash);
+        }
+
+        // use custom parameters if they are specified
+        if let Some(kdf) = self.cost.kdf {
+            kdf.check()?;
+            let value = unsafe {
+                slice::from_raw_parts_mut(pwdhash.value.as_mut_ptr(), KEY_SIZE)
+            };
+            return argon2id_hash(passwd, salt, kdf, value).map(|_| pwdhash);
+        }
+
         unsafe {
             match crypto_pwhash(
                 pwdhash.value.as_mut_ptr(),
@@ -967,6 +1578,60 @@ impl Crypto {
         key
     }
 
+    // ----------
+    // Sealed box
+    // ----------
+    /// Generate sealed box key pair
+    pub fn gen_box_keypair() -> (BoxPublicKey, BoxSecretKey) {
+        let mut pk = [0u8; BOX_PUBLIC_KEY_SIZE];
+        let mut sk = BoxSecretKey::new_empty();
+        unsafe {
+            crypto_box_keypair(pk.as_mut_ptr(), sk.as_mut_ptr());
+        }
+        (pk, sk)
+    }
+
+    /// Seal a key using public key, the sealed key can only be opened by
+    /// the secret key owner
+    pub fn seal_key(key: &Key, pk: &BoxPublicKey) -> Result<Vec<u8>> {
+        let mut sealed = vec![0u8; KEY_SIZE + BOX_SEAL_SIZE];
+        unsafe {
+            match crypto_box_seal(
+                sealed.as_mut_ptr(),
+                key.as_ptr(),
+                KEY_SIZE as u64,
+                pk.as_ptr(),
+            ) {
+                0 => Ok(sealed),
+                _ => Err(Error::Encrypt),
+            }
+        }
+    }
+
+    /// Open a sealed key using key pair
+    pub fn open_sealed_key(
+        sealed: &[u8],
+        pk: &BoxPublicKey,
+        sk: &BoxSecretKey,
+    ) -> Result<Key> {
+        if sealed.len() != KEY_SIZE + BOX_SEAL_SIZE {
+            return Err(Error::Decrypt);
+        }
+        let mut key = Key::new_empty();
+        unsafe {
+            match crypto_box_seal_open(
+                key.as_mut_ptr(),
+                sealed.as_ptr(),
+                sealed.len() as u64,
+                pk.as_ptr(),
+                sk.as_ptr(),
+            ) {
+                0 => Ok(key),
+                _ => Err(Error::Decrypt),
+            }
+        }
+    }
+
     /// Key derivation
     pub fn derive_from_key(key: &Key, subkey_id: u64) -> Result<Key> {
         let mut subkey = Key::new_empty();
@@ -994,6 +1659,8 @@ impl Crypto {
         match self.cipher {
             Cipher::Xchacha => XCHACHA_NONCE_SIZE,
             Cipher::Aes => AES_NONCE_SIZE,
+            Cipher::AesGcmSiv => AES_GCM_SIV_NONCE_SIZE,
+            Cipher::Integrity => 0,
         }
     }
 
@@ -1026,7 +1693,7 @@ impl Crypto {
 
     #[inline]
     pub fn decrypted_len(&self, ctxt_len: usize) -> usize {
-        ctxt_len - self.nonce_size() - ATAG_SIZE
+        ctxt_len.saturating_sub(self.nonce_size() + ATAG_SIZE)
     }
 
     /// Encrypt message with specified key
@@ -1037,17 +1704,29 @@ impl Crypto {
         key: &Key,
         ad: &[u8],
     ) -> Result<usize> {
-        let nonce_size = self.nonce_size();
-        let p_ctxt = ctxt.as_mut_ptr();
-        let mut clen: u64 = 0;
-
         // AES extended nonce is longer than Xchacha, so we can use it
         // for both of the ciphers
         let mut nonce: Nonce = [0u8; AES_NONCE_SIZE];
         Crypto::random_buf(&mut nonce);
+        self.encrypt_with_nonce(ctxt, msg, key, ad, &nonce)
+    }
+
+    // encrypt message using the specified nonce, only the leading nonce
+    // size bytes of the nonce are used
+    fn encrypt_with_nonce(
+        &self,
+        ctxt: &mut [u8],
+        msg: &[u8],
+        key: &Key,
+        ad: &[u8],
+        nonce: &Nonce,
+    ) -> Result<usize> {
+        let nonce_size = self.nonce_size();
+        let p_ctxt = ctxt.as_mut_ptr();
+        let mut clen: u64 = 0;
 
         let result = match self.cipher {
-            Cipher::Xchacha => unsafe {
+            Cipher::Xchacha | Cipher::AesGcmSiv | Cipher::Integrity => unsafe {
                 (self.enc_fn)(
                     p_ctxt.add(nonce_size),
                     &mut clen as *mut u64,
@@ -1119,6 +1798,29 @@ impl Crypto {
         self.encrypt_raw(dst, msg, key, &[0u8; 0])
     }
 
+    /// Encrypt message deterministically directly to dest buffer
+    ///
+    /// The nonce is the keyed hash of message using a sub-key derived from
+    /// the key, so the same message encrypted by the same key always has the
+    /// same cipher text. It can be decrypted as usual.
+    pub fn encrypt_convergent_to(
+        &self,
+        dst: &mut [u8],
+        msg: &[u8],
+        key: &Key,
+    ) -> Result<usize> {
+        let nonce_key = Crypto::derive_from_key(key, 0)?;
+        let hash = Crypto::hash_raw(
+            msg.as_ptr(),
+            msg.len(),
+            nonce_key.as_ptr(),
+            KEY_SIZE,
+        );
+        let mut nonce: Nonce = [0u8; AES_NONCE_SIZE];
+        nonce.copy_from_slice(&hash[..AES_NONCE_SIZE]);
+        self.encrypt_with_nonce(dst, msg, key, &[0u8; 0], &nonce)
+    }
+
     /// Decrypt message with specified key
     pub fn decrypt_raw(
         &self,
@@ -1129,10 +1831,16 @@ impl Crypto {
     ) -> Result<usize> {
         let mut msglen = msg.len() as u64;
         let nonce_size = self.nonce_size();
+
+        // cipher text might be encrypted by another cipher which has shorter
+        // nonce, it is too short to be decrypted
+        if ctxt.len() < nonce_size + ATAG_SIZE {
+            return Err(Error::Decrypt);
+        }
         let nonce = &ctxt[0..nonce_size];
 
         let result = match self.cipher {
-            Cipher::Xchacha => unsafe {
+            Cipher::Xchacha | Cipher::AesGcmSiv | Cipher::Integrity => unsafe {
                 (self.dec_fn)(
                     msg.as_mut_ptr(),
                     &mut msglen as *mut u64,
@@ -1242,4 +1950,258 @@ mod tests {
         }
         assert!(crypto.decrypt_with_ad(&ctxt, &key, &ad).is_err());
     }
+
+    fn from_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // test vectors are generated by libsodium, all crypto implementations
+    // must produce the same output
+    #[test]
+    fn libsodium_compat() {
+        Crypto::init().unwrap();
+
+        let mut key = Key::new_empty();
+        key.copy(&(0..KEY_SIZE as u8).collect::<Vec<u8>>());
+        let mut hash_key = HashKey::new_empty();
+        hash_key.copy(key.as_slice());
+
+        // generic hash
+        let hash = from_hex(
+            "0cced64700068f379801b0b4ea1b2454f637d40e9d02752a47e631ad9c6890e9",
+        );
+        assert_eq!(&Crypto::hash(b"zbox")[..], &hash[..]);
+        let mut state = Crypto::hash_init();
+        Crypto::hash_update(&mut state, b"zb");
+        Crypto::hash_update(&mut state, b"ox");
+        assert_eq!(&Crypto::hash_final(&mut state)[..], &hash[..]);
+        assert_eq!(
+            &Crypto::hash(b"")[..],
+            &from_hex(
+                "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+            )[..]
+        );
+        assert_eq!(
+            &Crypto::hash_with_key(b"zbox", &hash_key)[..],
+            &from_hex(
+                "e119a1a35110843603dfba07f36b9feda3be0ad91535168d5cbfdb49dcd2bf14"
+            )[..]
+        );
+
+        // key derivation
+        assert_eq!(
+            Crypto::derive_from_key(&key, 42).unwrap().as_slice(),
+            &from_hex(
+                "f2d03e55539b5a97115c5a615ddfc2294c6a00928f9f329b66daa752bb344715"
+            )[..]
+        );
+
+        // deterministic random
+        let mut buf = [0u8; 40];
+        let seed = RandomSeed::from(key.as_slice());
+        Crypto::random_buf_deterministic(&mut buf, &seed);
+        assert_eq!(
+            &buf[..],
+            &from_hex(
+                "0d8e6cc68715648926732e7ea73250cfaf2d58422083904c841a8ba33b98\
+                 6111f346ba50723a68ae"
+            )[..]
+        );
+
+        // password hash, use minimum cost to make test fast
+        let mut out = [0u8; HASH_SIZE];
+        let salt = [7u8; SALT_SIZE];
+        let pwd = b"pwd";
+        let result = unsafe {
+            crypto_pwhash(
+                out.as_mut_ptr(),
+                HASH_SIZE as u64,
+                pwd.as_ptr(),
+                pwd.len() as u64,
+                salt.as_ptr(),
+                1,
+                8192,
+                2,
+            )
+        };
+        assert_eq!(result, 0);
+        assert_eq!(
+            &out[..],
+            &from_hex(
+                "170dfa0c7a5b42ace132ab94820f8497753b9047cb11bfcea0fadbf81205caf7"
+            )[..]
+        );
+
+        // password hash with custom parameters
+        let salt = Salt(salt);
+        let mut out = [0u8; HASH_SIZE];
+        argon2id_hash("pwd", &salt, KdfParams::new(8, 1, 1), &mut out).unwrap();
+        assert_eq!(
+            &out[..],
+            &from_hex(
+                "170dfa0c7a5b42ace132ab94820f8497753b9047cb11bfcea0fadbf81205caf7"
+            )[..]
+        );
+        scrypt_hash("pwd", &salt, ScryptParams::new(10, 8, 2), &mut out)
+            .unwrap();
+        assert_eq!(
+            &out[..],
+            &from_hex(
+                "129d8bfee169395a86b4b5c0a0e4995c37e90ffec07881d0ede3b79adb15b390"
+            )[..]
+        );
+
+        // decryption
+        let crypto = Crypto::new(Cost::default(), Cipher::Xchacha).unwrap();
+        let ctxt = from_hex(
+            "73a39df5486bbb149b55e020a6fd28efa6779c1bfdfdf537f0ea7d4791675c2f\
+             703996c081a5d09d5e610e98",
+        );
+        assert_eq!(
+            crypto.decrypt_with_ad(&ctxt, &key, b"ad").unwrap(),
+            b"zbox"
+        );
+
+        if Crypto::is_aes_hardware_available() {
+            let crypto = Crypto::new(Cost::default(), Cipher::Aes).unwrap();
+            let ctxt = from_hex(
+                "0afb140ad69e672126ef5507dd8771a6df282ed46e50dbd64380036f064a8b\
+                 9faccc0cb3be346ee6da5e4da942436aef",
+            );
+            assert_eq!(
+                crypto.decrypt_with_ad(&ctxt, &key, b"ad").unwrap(),
+                b"zbox"
+            );
+        }
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn aes_gcm_siv() {
+        Crypto::init().unwrap();
+
+        let crypto = Crypto::new(Cost::default(), Cipher::AesGcmSiv).unwrap();
+        let key = Crypto::gen_master_key();
+        let msg = vec![3u8; 100];
+        let ad = vec![42u8; 4];
+        let ctxt = crypto.encrypt_with_ad(&msg, &key, &ad).unwrap();
+        assert_eq!(ctxt.len(), crypto.encrypted_len(msg.len()));
+        assert_eq!(crypto.decrypt_with_ad(&ctxt, &key, &ad).unwrap(), msg);
+        assert!(crypto.decrypt_with_ad(&ctxt, &key, &[]).is_err());
+
+        // test vector from RFC 8452, appendix C.2
+        let mut key = Key::new_empty();
+        key.copy(&from_hex(
+            "0100000000000000000000000000000000000000000000000000000000000000",
+        ));
+        let ctxt = from_hex(
+            "030000000000000000000000\
+             07f5f4169bbf55a8400cd47ea6fd400f",
+        );
+        assert!(crypto.decrypt(&ctxt, &key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn integrity() {
+        Crypto::init().unwrap();
+
+        let crypto = Crypto::new(Cost::default(), Cipher::Integrity).unwrap();
+        let key = Crypto::gen_master_key();
+        let msg = vec![3u8; 100];
+        let ad = vec![42u8; 4];
+        let ctxt = crypto.encrypt_with_ad(&msg, &key, &ad).unwrap();
+        assert_eq!(ctxt.len(), crypto.encrypted_len(msg.len()));
+        assert_eq!(&ctxt[..msg.len()], &msg[..]);
+        assert_eq!(crypto.decrypt_with_ad(&ctxt, &key, &ad).unwrap(), msg);
+        assert!(crypto.decrypt_with_ad(&ctxt, &key, &[]).is_err());
+
+        // any changes to message or key should fail authentication
+        let mut tampered = ctxt.clone();
+        tampered[0] ^= 1;
+        assert!(crypto.decrypt_with_ad(&tampered, &key, &ad).is_err());
+        let key2 = Crypto::gen_master_key();
+        assert!(crypto.decrypt_with_ad(&ctxt, &key2, &ad).is_err());
+    }
+
+    #[test]
+    fn convergent() {
+        Crypto::init().unwrap();
+
+        let crypto = Crypto::new(Cost::default(), Cipher::Xchacha).unwrap();
+        let key = Crypto::gen_master_key();
+        let msg = vec![3u8; 100];
+        let mut ctxt = vec![0u8; crypto.encrypted_len(msg.len())];
+        let mut ctxt2 = ctxt.clone();
+        let len = crypto.encrypt_convergent_to(&mut ctxt, &msg, &key).unwrap();
+        crypto
+            .encrypt_convergent_to(&mut ctxt2, &msg, &key)
+            .unwrap();
+        assert_eq!(len, ctxt.len());
+        assert_eq!(ctxt, ctxt2);
+        assert_eq!(crypto.decrypt(&ctxt, &key).unwrap(), msg);
+
+        // different message or key has different cipher text
+        crypto
+            .encrypt_convergent_to(&mut ctxt2, &[4u8; 100], &key)
+            .unwrap();
+        assert_ne!(ctxt, ctxt2);
+        let key2 = Crypto::gen_master_key();
+        crypto
+            .encrypt_convergent_to(&mut ctxt2, &msg, &key2)
+            .unwrap();
+        assert_ne!(ctxt, ctxt2);
+    }
+
+    #[test]
+    fn sealed_box() {
+        Crypto::init().unwrap();
+
+        let (pk, sk) = Crypto::gen_box_keypair();
+        let key = Crypto::gen_master_key();
+        let sealed = Crypto::seal_key(&key, &pk).unwrap();
+        assert_eq!(sealed.len(), KEY_SIZE + BOX_SEAL_SIZE);
+        assert_eq!(Crypto::open_sealed_key(&sealed, &pk, &sk).unwrap(), key);
+
+        // wrong key pair cannot open it
+        let (pk2, sk2) = Crypto::gen_box_keypair();
+        assert!(Crypto::open_sealed_key(&sealed, &pk2, &sk2).is_err());
+
+        // key sealed by libsodium
+        let mut sk = BoxSecretKey::new_empty();
+        sk.copy(&(0..32).collect::<Vec<u8>>());
+        let mut pk = [0u8; BOX_PUBLIC_KEY_SIZE];
+        pk.copy_from_slice(&from_hex(
+            "8f40c5adb68f25624ae5b214ea767a6ec94d829d3d7b5e1ad1ba6f3e2138285f",
+        ));
+        let sealed = from_hex(
+            "90fd0ef6b79e44e5993bf40463e27714246667dc07011cdef076403314400a78\
+             f9f2c648121d86da64a64ec5034d9a1a0e71d6c1033caf13b042d32e5a7cc253\
+             d3684c747634549d4146fb409d0ea09b",
+        );
+        let key = Crypto::open_sealed_key(&sealed, &pk, &sk).unwrap();
+        assert_eq!(key.as_slice(), &(32..64).collect::<Vec<u8>>()[..]);
+    }
+
+    #[test]
+    fn cipher_from_i32() {
+        for cipher in [
+            Cipher::Xchacha,
+            Cipher::Aes,
+            Cipher::AesGcmSiv,
+            Cipher::Integrity,
+        ]
+        .iter()
+        {
+            let n: i32 = (*cipher).into();
+            assert_eq!(Cipher::try_from(n).unwrap(), *cipher);
+        }
+
+        // unknown value and head extension flag are not ciphers
+        assert_eq!(Cipher::try_from(4).unwrap_err(), Error::InvalidSuperBlk);
+        assert_eq!(Cipher::try_from(0x80).unwrap_err(), Error::InvalidSuperBlk);
+        assert_eq!(Cipher::try_from(-1).unwrap_err(), Error::InvalidSuperBlk);
+    }
 }
@@ -0,0 +1,583 @@
+//! Pure Rust implementation of the libsodium functions used by `Crypto`.
+//!
+//! The functions here have the same signatures and produce the same output
+//! as their libsodium counterparts, so a repo created with libsodium can be
+//! opened by this implementation and vice versa.
+
+use std::alloc::{alloc, dealloc, Layout};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use argon2::{Algorithm, Argon2, Params, Version};
+use blake2::digest::consts::U32;
+use blake2::digest::{Digest, Mac};
+use blake2::{Blake2b, Blake2bMac};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use chacha20poly1305::XChaCha20Poly1305;
+use crypto_box::aead::OsRng;
+use crypto_box::{PublicKey, SecretKey};
+use getrandom::getrandom;
+
+// output size of generic hash and key derivation, both are 32 bytes
+const HASH_SIZE: usize = 32;
+
+// authentication tag size
+const ATAG_SIZE: usize = 16;
+
+// nonce sizes
+const XCHACHA_NONCE_SIZE: usize = 24;
+const AES_NONCE_SIZE: usize = 12;
+
+// multi-part generic hash state
+type Blake2b256 = Blake2b<U32>;
+
+// memory allocated by sodium_malloc has a header to store the size
+const ALLOC_ALIGN: usize = 16;
+
+#[inline]
+unsafe fn as_slice<'a>(p: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(p, len)
+    }
+}
+
+#[inline]
+unsafe fn as_mut_slice<'a>(p: *mut u8, len: usize) -> &'a mut [u8] {
+    if len == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(p, len)
+    }
+}
+
+// Initialisation
+// --------------
+pub unsafe extern "C" fn sodium_init() -> i32 {
+    0
+}
+
+// random
+// ----------
+pub unsafe extern "C" fn randombytes_buf(buf: *mut u8, size: usize) {
+    getrandom(as_mut_slice(buf, size)).expect("Get random bytes failed");
+}
+
+pub unsafe extern "C" fn randombytes_random() -> u32 {
+    let mut buf = [0u8; 4];
+    randombytes_buf(buf.as_mut_ptr(), buf.len());
+    u32::from_le_bytes(buf)
+}
+
+pub unsafe extern "C" fn randombytes_uniform(upper_bound: u32) -> u32 {
+    if upper_bound < 2 {
+        return 0;
+    }
+
+    // reject the values below 2^32 % upper_bound to avoid modulo bias
+    let min = upper_bound.wrapping_neg() % upper_bound;
+    loop {
+        let r = randombytes_random();
+        if r >= min {
+            return r % upper_bound;
+        }
+    }
+}
+
+pub unsafe extern "C" fn randombytes_buf_deterministic(
+    buf: *mut u8,
+    size: usize,
+    seed: *const u8,
+) {
+    let buf = as_mut_slice(buf, size);
+    for b in buf.iter_mut() {
+        *b = 0;
+    }
+    let mut stream = ChaCha20::new(
+        GenericArray::from_slice(as_slice(seed, 32)),
+        GenericArray::from_slice(b"LibsodiumDRG"),
+    );
+    stream.apply_keystream(buf);
+}
+
+// generic hash
+// -------------
+pub unsafe extern "C" fn crypto_generichash(
+    out: *mut u8,
+    outlen: usize,
+    inbuf: *const u8,
+    inlen: u64,
+    key: *const u8,
+    keylen: usize,
+) -> i32 {
+    if outlen != HASH_SIZE {
+        return -1;
+    }
+    let out = as_mut_slice(out, outlen);
+    let inbuf = as_slice(inbuf, inlen as usize);
+    if keylen == 0 {
+        out.copy_from_slice(&Blake2b256::digest(inbuf));
+    } else {
+        let mut mac = match Blake2bMac::<U32>::new_with_salt_and_personal(
+            as_slice(key, keylen),
+            &[],
+            &[],
+        ) {
+            Ok(mac) => mac,
+            Err(_) => return -1,
+        };
+        Mac::update(&mut mac, inbuf);
+        out.copy_from_slice(&mac.finalize().into_bytes());
+    }
+    0
+}
+
+pub unsafe extern "C" fn crypto_generichash_init(
+    state: *mut u8,
+    _key: *const u8,
+    keylen: usize,
+    outlen: usize,
+) -> i32 {
+    // only un-keyed multi-part hashing is used
+    if keylen != 0 || outlen != HASH_SIZE {
+        return -1;
+    }
+    assert!(
+        super::crypto::HASH_STATE_SIZE >= ::std::mem::size_of::<Blake2b256>()
+    );
+    ptr::write(state as *mut Blake2b256, Blake2b256::new());
+    0
+}
+
+pub unsafe extern "C" fn crypto_generichash_update(
+    state: *mut u8,
+    inbuf: *const u8,
+    inlen: u64,
+) -> i32 {
+    let state = &mut *(state as *mut Blake2b256);
+    Digest::update(state, as_slice(inbuf, inlen as usize));
+    0
+}
+
+pub unsafe extern "C" fn crypto_generichash_final(
+    state: *mut u8,
+    out: *mut u8,
+    outlen: usize,
+) -> i32 {
+    if outlen != HASH_SIZE {
+        return -1;
+    }
+    let state = ptr::read(state as *mut Blake2b256);
+    as_mut_slice(out, outlen).copy_from_slice(&state.finalize());
+    0
+}
+
+// password hash
+// -------------
+pub unsafe extern "C" fn crypto_pwhash(
+    out: *mut u8,
+    outlen: u64,
+    passwd: *const u8,
+    passwdlen: u64,
+    salt: *const u8,
+    opslimit: u64,
+    memlimit: usize,
+    alg: i32,
+) -> i32 {
+    // only Argon2id version 1.3 is supported
+    if alg != 2 {
+        return -1;
+    }
+    let params = match Params::new(
+        (memlimit / 1024) as u32,
+        opslimit as u32,
+        1,
+        Some(outlen as usize),
+    ) {
+        Ok(params) => params,
+        Err(_) => return -1,
+    };
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    match argon2.hash_password_into(
+        as_slice(passwd, passwdlen as usize),
+        as_slice(salt, 16),
+        as_mut_slice(out, outlen as usize),
+    ) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+// key derivation
+// --------------
+pub unsafe extern "C" fn crypto_kdf_keygen(key: *mut u8) {
+    randombytes_buf(key, HASH_SIZE);
+}
+
+pub unsafe extern "C" fn crypto_kdf_derive_from_key(
+    subkey: *mut u8,
+    subkey_len: usize,
+    subkey_id: u64,
+    ctx: *const u8,
+    key: *const u8,
+) -> i32 {
+    if subkey_len != HASH_SIZE {
+        return -1;
+    }
+    let mac = match Blake2bMac::<U32>::new_with_salt_and_personal(
+        as_slice(key, HASH_SIZE),
+        &subkey_id.to_le_bytes(),
+        as_slice(ctx, 8),
+    ) {
+        Ok(mac) => mac,
+        Err(_) => return -1,
+    };
+    as_mut_slice(subkey, subkey_len)
+        .copy_from_slice(&mac.finalize().into_bytes());
+    0
+}
+
+// AEAD crypto
+// -----------
+unsafe fn aead_encrypt<A: AeadInPlace>(
+    cipher: &A,
+    c: *mut u8,
+    clen_p: *const u64,
+    m: *const u8,
+    mlen: u64,
+    ad: &[u8],
+    npub: &[u8],
+) -> i32 {
+    let mlen = mlen as usize;
+    let buf = as_mut_slice(c, mlen + ATAG_SIZE);
+    ptr::copy(m, buf.as_mut_ptr(), mlen);
+    match cipher.encrypt_in_place_detached(
+        GenericArray::from_slice(npub),
+        ad,
+        &mut buf[..mlen],
+    ) {
+        Ok(tag) => {
+            buf[mlen..].copy_from_slice(&tag);
+            if !clen_p.is_null() {
+                *(clen_p as *mut u64) = (mlen + ATAG_SIZE) as u64;
+            }
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+unsafe fn aead_decrypt<A: AeadInPlace>(
+    cipher: &A,
+    m: *mut u8,
+    mlen_p: *const u64,
+    c: *const u8,
+    clen: u64,
+    ad: &[u8],
+    npub: &[u8],
+) -> i32 {
+    let clen = clen as usize;
+    if clen < ATAG_SIZE {
+        return -1;
+    }
+    let mlen = clen - ATAG_SIZE;
+    let ctxt = as_slice(c, clen);
+    let buf = as_mut_slice(m, mlen);
+    buf.copy_from_slice(&ctxt[..mlen]);
+    match cipher.decrypt_in_place_detached(
+        GenericArray::from_slice(npub),
+        ad,
+        buf,
+        GenericArray::from_slice(&ctxt[mlen..]),
+    ) {
+        Ok(_) => {
+            if !mlen_p.is_null() {
+                *(mlen_p as *mut u64) = mlen as u64;
+            }
+            0
+        }
+        Err(_) => {
+            sodium_memzero(m, mlen);
+            -1
+        }
+    }
+}
+
+// XChaCha20-Poly1305 crypto
+// -------------------------
+pub unsafe extern "C" fn crypto_aead_xchacha20poly1305_ietf_encrypt(
+    c: *mut u8,
+    clen_p: *const u64,
+    m: *const u8,
+    mlen: u64,
+    ad: *const u8,
+    adlen: u64,
+    _nsec: *const u8,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let cipher =
+        XChaCha20Poly1305::new(GenericArray::from_slice(as_slice(k, 32)));
+    aead_encrypt(
+        &cipher,
+        c,
+        clen_p,
+        m,
+        mlen,
+        as_slice(ad, adlen as usize),
+        as_slice(npub, XCHACHA_NONCE_SIZE),
+    )
+}
+
+pub unsafe extern "C" fn crypto_aead_xchacha20poly1305_ietf_decrypt(
+    m: *mut u8,
+    mlen_p: *const u64,
+    _nsec: *const u8,
+    c: *const u8,
+    clen: u64,
+    ad: *const u8,
+    adlen: u64,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let cipher =
+        XChaCha20Poly1305::new(GenericArray::from_slice(as_slice(k, 32)));
+    aead_decrypt(
+        &cipher,
+        m,
+        mlen_p,
+        c,
+        clen,
+        as_slice(ad, adlen as usize),
+        as_slice(npub, XCHACHA_NONCE_SIZE),
+    )
+}
+
+// AES256-GCM crypto
+// -----------------
+// Same as libsodium, AES is only reported as available when the CPU has
+// AES-NI and carry-less multiplication instructions.
+pub unsafe extern "C" fn crypto_aead_aes256gcm_is_available() -> i32 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("aes")
+            && is_x86_feature_detected!("pclmulqdq")
+        {
+            return 1;
+        }
+    }
+    0
+}
+
+// nonce extension, this is HChaCha20 with a custom constant
+pub unsafe extern "C" fn crypto_core_hchacha20(
+    out: *mut u8,
+    inbuf: *const u8,
+    k: *const u8,
+    c: *const u8,
+) -> i32 {
+    #[inline]
+    fn load(buf: &[u8], words: &mut [u32]) {
+        for (w, b) in words.iter_mut().zip(buf.chunks(4)) {
+            *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+    }
+
+    #[inline]
+    fn quarter_round(
+        x: &mut [u32; 16],
+        a: usize,
+        b: usize,
+        c: usize,
+        d: usize,
+    ) {
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(16);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(12);
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(8);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(7);
+    }
+
+    let mut x = [0u32; 16];
+    load(as_slice(c, 16), &mut x[0..4]);
+    load(as_slice(k, 32), &mut x[4..12]);
+    load(as_slice(inbuf, 16), &mut x[12..16]);
+
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+
+    let out = as_mut_slice(out, 32);
+    for (i, &w) in x[0..4].iter().chain(x[12..16].iter()).enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+    }
+    sodium_memzero(x.as_mut_ptr() as *mut u8, 64);
+    0
+}
+
+pub unsafe extern "C" fn crypto_aead_aes256gcm_encrypt(
+    c: *mut u8,
+    clen_p: *const u64,
+    m: *const u8,
+    mlen: u64,
+    ad: *const u8,
+    adlen: u64,
+    _nsec: *const u8,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(as_slice(k, 32)));
+    aead_encrypt(
+        &cipher,
+        c,
+        clen_p,
+        m,
+        mlen,
+        as_slice(ad, adlen as usize),
+        as_slice(npub, AES_NONCE_SIZE),
+    )
+}
+
+pub unsafe extern "C" fn crypto_aead_aes256gcm_decrypt(
+    m: *mut u8,
+    mlen_p: *const u64,
+    _nsec: *const u8,
+    c: *const u8,
+    clen: u64,
+    ad: *const u8,
+    adlen: u64,
+    npub: *const u8,
+    k: *const u8,
+) -> i32 {
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(as_slice(k, 32)));
+    aead_decrypt(
+        &cipher,
+        m,
+        mlen_p,
+        c,
+        clen,
+        as_slice(ad, adlen as usize),
+        as_slice(npub, AES_NONCE_SIZE),
+    )
+}
+
+// Sealed box
+// ----------
+pub unsafe extern "C" fn crypto_box_keypair(pk: *mut u8, sk: *mut u8) -> i32 {
+    let mut bytes = [0u8; 32];
+    randombytes_buf(bytes.as_mut_ptr(), bytes.len());
+    let secret = SecretKey::from_bytes(bytes);
+    sodium_memzero(bytes.as_mut_ptr(), bytes.len());
+    as_mut_slice(pk, 32).copy_from_slice(secret.public_key().as_bytes());
+    as_mut_slice(sk, 32).copy_from_slice(&secret.to_bytes());
+    0
+}
+
+pub unsafe extern "C" fn crypto_box_seal(
+    c: *mut u8,
+    m: *const u8,
+    mlen: u64,
+    pk: *const u8,
+) -> i32 {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(as_slice(pk, 32));
+    let public = PublicKey::from_bytes(bytes);
+    match public.seal(&mut OsRng, as_slice(m, mlen as usize)) {
+        Ok(ctxt) => {
+            as_mut_slice(c, ctxt.len()).copy_from_slice(&ctxt);
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+pub unsafe extern "C" fn crypto_box_seal_open(
+    m: *mut u8,
+    c: *const u8,
+    clen: u64,
+    _pk: *const u8,
+    sk: *const u8,
+) -> i32 {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(as_slice(sk, 32));
+    let secret = SecretKey::from_bytes(bytes);
+    sodium_memzero(bytes.as_mut_ptr(), bytes.len());
+    match secret.unseal(as_slice(c, clen as usize)) {
+        Ok(mut msg) => {
+            as_mut_slice(m, msg.len()).copy_from_slice(&msg);
+            sodium_memzero(msg.as_mut_ptr(), msg.len());
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+// Helpers
+// -------
+pub unsafe extern "C" fn sodium_memzero(pnt: *mut u8, len: usize) {
+    for i in 0..len {
+        ptr::write_volatile(pnt.add(i), 0);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+// constant time comparison, returns 0 if the two buffers are equal
+pub unsafe extern "C" fn sodium_memcmp(
+    b1: *const u8,
+    b2: *const u8,
+    len: usize,
+) -> i32 {
+    let mut d = 0u8;
+    for i in 0..len {
+        d |= ptr::read_volatile(b1.add(i)) ^ ptr::read_volatile(b2.add(i));
+    }
+    if d == 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+pub unsafe extern "C" fn sodium_malloc(size: usize) -> *mut u8 {
+    let layout = match Layout::from_size_align(size + ALLOC_ALIGN, ALLOC_ALIGN)
+    {
+        Ok(layout) => layout,
+        Err(_) => return ptr::null_mut(),
+    };
+    let base = alloc(layout);
+    if base.is_null() {
+        return base;
+    }
+    ptr::write(base as *mut usize, size);
+    base.add(ALLOC_ALIGN)
+}
+
+// memory is wiped before it is released
+pub unsafe extern "C" fn sodium_free(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    let base = ptr.sub(ALLOC_ALIGN);
+    let size = ptr::read(base as *const usize);
+    sodium_memzero(ptr, size);
+    dealloc(
+        base,
+        Layout::from_size_align_unchecked(size + ALLOC_ALIGN, ALLOC_ALIGN),
+    );
+}
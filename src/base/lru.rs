@@ -100,6 +100,12 @@ where
         })
     }
 
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.used = 0;
+    }
+
     fn remove_lru(&mut self) -> Option<V> {
         let pin_ckr = self.pin_ckr.clone();
         let ret = self
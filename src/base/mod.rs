@@ -2,6 +2,8 @@
 //!
 
 pub(crate) mod crypto;
+#[cfg(feature = "crypto-rust")]
+mod crypto_rust;
 pub(crate) mod lru;
 pub(crate) mod lz4;
 mod refcnt;
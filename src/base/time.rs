@@ -28,10 +28,22 @@ impl Time {
     pub fn to_system_time(&self) -> SystemTime {
         UNIX_EPOCH + self.0
     }
+
+    // convert from system time, time before unix epoch is clamped to epoch
+    #[inline]
+    pub fn from_system_time(time: SystemTime) -> Self {
+        Time(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+    }
+
+    // time elapsed since this time, zero if clock went backwards
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        Time::now().0.checked_sub(self.0).unwrap_or_default()
+    }
 }
 
 impl Debug for Time {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Time({})", &self.0.as_secs())
+        write!(f, "Time({}.{:09})", self.0.as_secs(), self.0.subsec_nanos())
     }
 }
@@ -2,6 +2,9 @@ use std::time::Duration;
 
 #[cfg(any(
     feature = "storage-file",
+    feature = "storage-sqlite",
+    feature = "storage-sled",
+    feature = "storage-container",
     all(feature = "storage-zbox", not(target_arch = "wasm32"))
 ))]
 use error::{Error, Result};
@@ -70,6 +73,7 @@ pub fn speed_str(duration: &Duration, data_len: usize) -> String {
 /// Ensure all parents dir are created along the path
 #[cfg(any(
     feature = "storage-file",
+    feature = "storage-container",
     all(feature = "storage-zbox", not(target_arch = "wasm32"))
 ))]
 pub fn ensure_parents_dir(path: &std::path::Path) -> Result<()> {
@@ -100,3 +104,33 @@ pub fn remove_empty_parent_dir(path: &std::path::Path) -> Result<()> {
     }
     Ok(())
 }
+
+/// Get available free space in bytes on the file system containing `path`,
+/// returns `None` if it cannot be determined on this platform
+#[cfg(any(
+    feature = "storage-file",
+    feature = "storage-sqlite",
+    feature = "storage-sled",
+    feature = "storage-container"
+))]
+pub fn disk_free_space(path: &std::path::Path) -> Result<Option<u64>> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::InvalidArgument)?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+        if result != 0 {
+            return Err(Error::from(std::io::Error::last_os_error()));
+        }
+        Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
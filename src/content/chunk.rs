@@ -39,6 +39,11 @@ impl Chunk {
         self.pos + self.len
     }
 
+    #[inline]
+    pub fn refcnt(&self) -> u32 {
+        self.refcnt.val()
+    }
+
     #[inline]
     pub fn is_orphan(&self) -> bool {
         self.refcnt.val() == 0
@@ -1,9 +1,10 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
 use std::io::{
-    Error as IoError, ErrorKind, Read, Result as IoResult, Seek, SeekFrom,
-    Write,
+    self, Error as IoError, ErrorKind, Read, Result as IoResult, Seek,
+    SeekFrom, Write,
 };
 use std::sync::Arc;
 
@@ -12,13 +13,17 @@ use super::entry::{CutableList, EntryList};
 use super::merkle_tree::{Leaves, MerkleTree, Writer as MerkleTreeWriter};
 use super::segment::Writer as SegWriter;
 use super::span::{Extent, Span};
-use super::{StoreRef, StoreWeakRef};
+use super::{Store, StoreRef, StoreWeakRef};
 use base::crypto::{Crypto, Hash};
 use error::{Error, Result};
 use trans::cow::{CowCache, CowRef, Cowable, IntoCow};
 use trans::{Eid, Finish, Id, TxMgrRef, TxMgrWeakRef, Txid};
 use volume::VolumeWeakRef;
 
+/// Data block in segment data, that is the whole segment data and the block
+/// range in it
+pub type DataBlock = (Arc<Vec<u8>>, usize, usize);
+
 /// Content
 #[derive(Default, Clone, Deserialize, Serialize)]
 pub struct Content {
@@ -79,6 +84,184 @@ impl Content {
         Ok(())
     }
 
+    // clone a range of content as a stage content starting at `offset`, the
+    // cloned range refers to the same segment chunks, so no data is written,
+    // but the range is read to build its merkle tree leaves
+    pub fn clone_range(
+        &self,
+        from: usize,
+        len: usize,
+        offset: usize,
+        store: &StoreRef,
+    ) -> Result<Content> {
+        assert!(len > 0 && from + len <= self.len());
+        let end = from + len;
+        let mut ents = self.ents.clone();
+
+        // cut entry list to the range
+        {
+            let store = store.read().unwrap();
+            if end < ents.end_offset() {
+                let pos = ents.locate(end);
+                let seg_ref = store.get_seg(ents[pos].seg_id())?;
+                let seg = seg_ref.read().unwrap();
+                ents.split_off(end, &seg);
+            }
+            if from > 0 {
+                let pos = ents.locate(from);
+                let seg_ref = store.get_seg(ents[pos].seg_id())?;
+                let seg = seg_ref.read().unwrap();
+                ents.split_to(from, &seg);
+            }
+        }
+        ents.seek(SeekFrom::Start(offset as u64))?;
+
+        // build merkle tree leaves for the range
+        let mut rdr = Reader::new(self.clone(), &Arc::downgrade(store));
+        rdr.seek(SeekFrom::Start(from as u64))?;
+        let mut mtree_wtr = MerkleTreeWriter::new();
+        mtree_wtr.seek(SeekFrom::Start(offset as u64))?;
+        let copied = io::copy(&mut rdr.take(len as u64), &mut mtree_wtr)?;
+        assert_eq!(copied as usize, len);
+
+        Ok(Content {
+            ents,
+            mtree: MerkleTree::new(),
+            leaves: mtree_wtr.finish_with_leaves(),
+        })
+    }
+
+    // get the data block containing the offset, it is returned as the whole
+    // segment data and the range of block in it, the block starts at the
+    // offset and ends at the end of the span
+    pub fn block_at(
+        &self,
+        offset: usize,
+        store: &Store,
+    ) -> Result<Option<DataBlock>> {
+        let ent = match self.ents.iter().find(|e| offset < e.end_offset()) {
+            Some(ent) => ent,
+            None => return Ok(None),
+        };
+        let span = ent
+            .iter()
+            .find(|s| offset < s.end_offset())
+            .ok_or(Error::Corrupted)?;
+        let seg_ref = store.get_seg(ent.seg_id())?;
+        let seg = seg_ref.read().unwrap();
+        let over_span = offset - span.offset;
+        let start = span.offset_in_seg(&seg) + over_span;
+        let end = start + span.len - over_span;
+        let segdata_ref = store.get_segdata(seg.data_id())?;
+        let segdata = segdata_ref.read().unwrap();
+        Ok(Some((segdata.data(), start, end)))
+    }
+
+    // load data of segments referred by this content into cache, loading
+    // stops when the loaded data fills the cache
+    pub fn prefetch(&self, store: &Store) -> Result<()> {
+        let mut loaded = 0;
+        let mut data_ids = HashSet::new();
+        for ent in self.ents.iter() {
+            if loaded >= Store::SEG_DATA_CACHE_SIZE {
+                break;
+            }
+            let seg_ref = store.get_seg(ent.seg_id())?;
+            let seg = seg_ref.read().unwrap();
+            if data_ids.insert(seg.data_id().clone()) {
+                let segdata_ref = store.get_segdata(seg.data_id())?;
+                let segdata = segdata_ref.read().unwrap();
+                loaded += segdata.len();
+            }
+        }
+        Ok(())
+    }
+
+    // remove data of segments referred by this content from cache
+    pub fn evict(&self, store: &Store) -> Result<()> {
+        for ent in self.ents.iter() {
+            let seg_ref = store.get_seg(ent.seg_id())?;
+            let seg = seg_ref.read().unwrap();
+            store.remove_segdata_from_cache(seg.data_id());
+        }
+        Ok(())
+    }
+
+    // get data locations of content, each location is a tuple of offset,
+    // length, segment id and position in segment
+    fn locations(
+        &self,
+        store: &Store,
+    ) -> Result<Vec<(usize, usize, Eid, usize)>> {
+        let mut locs = Vec::new();
+        for ent in self.ents.iter() {
+            let seg_ref = store.get_seg(ent.seg_id())?;
+            let seg = seg_ref.read().unwrap();
+            for span in ent.iter() {
+                locs.push((
+                    span.offset,
+                    span.len,
+                    ent.seg_id().clone(),
+                    span.offset_in_seg(&seg),
+                ));
+            }
+        }
+        Ok(locs)
+    }
+
+    // compare with another content and return the changed extents as
+    // offset and length pairs, an area is unchanged if both contents refer
+    // to the same data in the same segment
+    pub fn diff(
+        &self,
+        other: &Content,
+        store: &StoreRef,
+    ) -> Result<Vec<(usize, usize)>> {
+        let (locs, other_locs) = {
+            let store = store.read().unwrap();
+            (self.locations(&store)?, other.locations(&store)?)
+        };
+        let mut diffs: Vec<(usize, usize)> = Vec::new();
+        let mut add_diff = |offset: usize, len: usize| {
+            if let Some(last) = diffs.last_mut() {
+                if last.0 + last.1 == offset {
+                    last.1 += len;
+                    return;
+                }
+            }
+            diffs.push((offset, len));
+        };
+
+        // walk through both contents, the offset moves to the nearest end
+        // of locations in each step
+        let (mut i, mut j, mut offset) = (0, 0, 0);
+        while i < locs.len() && j < other_locs.len() {
+            let (off, len, ref seg_id, pos) = locs[i];
+            let (oth_off, oth_len, ref oth_seg_id, oth_pos) = other_locs[j];
+            let end = min(off + len, oth_off + oth_len);
+            if seg_id != oth_seg_id
+                || pos + offset - off != oth_pos + offset - oth_off
+            {
+                add_diff(offset, end - offset);
+            }
+            offset = end;
+            if offset == off + len {
+                i += 1;
+            }
+            if offset == oth_off + oth_len {
+                j += 1;
+            }
+        }
+
+        // the rest of longer content is changed
+        let len = max(self.len(), other.len());
+        if offset < len {
+            add_diff(offset, len - offset);
+        }
+
+        Ok(diffs)
+    }
+
     pub fn truncate(&mut self, at: usize, store: &StoreRef) -> Result<()> {
         // truncate content
         {
@@ -97,6 +280,12 @@ impl Content {
         Ok(())
     }
 
+    // count chunk references made by this content
+    #[inline]
+    pub fn count_chunk_refs(&self, refs: &mut HashMap<(Eid, usize), u32>) {
+        self.ents.count_chunk_refs(refs)
+    }
+
     // build reference between content and segment
     #[inline]
     pub fn link(&self, store: &StoreRef, txmgr: &TxMgrRef) -> Result<()> {
@@ -159,6 +348,16 @@ pub struct Reader {
     pos: u64,
     content: Content,
     store: StoreWeakRef,
+
+    // load data of the next segment when starting reading a segment
+    read_ahead: bool,
+
+    // remove data of the previous segment from cache when starting reading
+    // a segment
+    drop_behind: bool,
+
+    // data id of the segment last read
+    last_data: Option<Eid>,
 }
 
 impl Reader {
@@ -167,6 +366,34 @@ impl Reader {
             pos: 0,
             content,
             store: store.clone(),
+            read_ahead: false,
+            drop_behind: false,
+            last_data: None,
+        }
+    }
+
+    #[inline]
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.read_ahead = read_ahead;
+    }
+
+    #[inline]
+    pub fn set_drop_behind(&mut self, drop_behind: bool) {
+        self.drop_behind = drop_behind;
+    }
+
+    // load data of the segment referred by the entry at index into cache,
+    // errors are ignored as the data will be loaded again when it is read
+    fn load_ahead(&self, idx: usize, store: &Store) {
+        let ent = match self.content.ents.iter().nth(idx) {
+            Some(ent) => ent,
+            None => return,
+        };
+        if let Ok(seg_ref) = store.get_seg(ent.seg_id()) {
+            let seg = seg_ref.read().unwrap();
+            if let Err(err) = store.get_segdata(seg.data_id()) {
+                debug!("read ahead segment failed: {}", err);
+            }
         }
     }
 }
@@ -182,14 +409,29 @@ impl Read for Reader {
         let start = self.pos as usize;
         let mut buf_read = 0;
 
-        for ent in self
+        for (idx, ent) in self
             .content
             .ents
             .iter()
-            .skip_while(|e| e.end_offset() <= start)
+            .enumerate()
+            .skip_while(|(_, e)| e.end_offset() <= start)
         {
             let seg_ref = map_io_err!(store.get_seg(ent.seg_id()))?;
             let seg = seg_ref.read().unwrap();
+
+            // apply caching hints when moving to another segment
+            if self.last_data.as_ref() != Some(seg.data_id()) {
+                if let Some(last_data) = self.last_data.take() {
+                    if self.drop_behind {
+                        store.remove_segdata_from_cache(&last_data);
+                    }
+                }
+                if self.read_ahead {
+                    self.load_ahead(idx + 1, &store);
+                }
+                self.last_data = Some(seg.data_id().clone());
+            }
+
             let segdata_ref = map_io_err!(store.get_segdata(seg.data_id()))?;
             let segdata = segdata_ref.read().unwrap();
 
@@ -252,6 +494,7 @@ impl Writer {
     pub fn new(
         txid: Txid,
         chk_map: ChunkMap,
+        domain: Option<Eid>,
         store: &StoreWeakRef,
         txmgr: &TxMgrWeakRef,
         vol: &VolumeWeakRef,
@@ -260,7 +503,7 @@ impl Writer {
             txid,
             ctn: Content::new(),
             chk_map,
-            seg_wtr: SegWriter::new(txid, store, txmgr, vol),
+            seg_wtr: SegWriter::new(txid, domain, store, txmgr, vol),
             mtree_wtr: MerkleTreeWriter::new(),
             store: store.clone(),
         }
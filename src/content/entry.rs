@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::io::{Result as IoResult, Seek, SeekFrom};
 use std::ops::Index;
 use std::slice::Iter;
@@ -294,6 +295,18 @@ impl EntryList {
         Ok((head, tail))
     }
 
+    // count chunk references made by this entry list, the map key is segment
+    // id and chunk index in that segment
+    pub fn count_chunk_refs(&self, refs: &mut HashMap<(Eid, usize), u32>) {
+        for ent in self.ents.iter() {
+            for span in ent.spans.iter() {
+                for idx in span.begin..span.end {
+                    *refs.entry((ent.seg_id.clone(), idx)).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
     // create reference relationship between content and segment
     pub fn link(&self, store: &Store, txmgr: &TxMgrRef) -> Result<()> {
         for ent in self.ents.iter() {
@@ -330,9 +343,12 @@ impl EntryList {
                 // if segment is not used anymore, remove it
                 Segment::remove(&mut seg_cow, txmgr)?;
                 chk_map.remove_segment(seg_cow.id());
-            } else if seg_cow.is_shrinkable() {
+                store.remove_seg_from_cache(seg_cow.id());
+            } else if seg_cow.is_shrinkable() && store.is_seg_unlocked(&seg_cow)
+            {
                 // shrink segment if it is small enough and remove retired
-                // chunks from chunk map
+                // chunks from chunk map, segment in a locked key domain is
+                // left as is
                 let retired = Segment::shrink(&mut seg_cow, store, txmgr)?;
                 chk_map.remove_chunks(seg_cow.id(), &retired);
             }
@@ -21,7 +21,9 @@ use volume::{
 pub struct SegData {
     id: Eid,
     action: Option<Action>,
-    data: Vec<u8>,
+
+    // data is shared by block references handed out to readers
+    data: Arc<Vec<u8>>,
 }
 
 impl SegData {
@@ -29,7 +31,7 @@ impl SegData {
         SegData {
             id: id.clone(),
             action: None,
-            data: Vec::new(),
+            data: Arc::default(),
         }
     }
 
@@ -64,6 +66,18 @@ impl SegData {
         )
     }
 
+    // get shared reference to the whole segment data
+    #[inline]
+    pub fn data(&self) -> Arc<Vec<u8>> {
+        self.data.clone()
+    }
+
+    // segment data length, in bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
     // Check if seg data is in transaction
     #[inline]
     pub fn in_trans(&self) -> bool {
@@ -78,12 +92,12 @@ impl SegData {
         Ok(SegData {
             id: id.clone(),
             action: None,
-            data: buf,
+            data: Arc::new(buf),
         })
     }
 
-    fn save(&self, vol: &VolumeWeakRef) -> Result<()> {
-        let mut wtr = VolWriter::new(&self.id, vol)?;
+    fn save(&self, vol: &VolumeWeakRef, domain: Option<&Eid>) -> Result<()> {
+        let mut wtr = VolWriter::for_content(&self.id, vol, domain)?;
         wtr.write_all(&self.data[..])?;
         wtr.finish()?;
         Ok(())
@@ -223,6 +237,10 @@ pub struct Segment {
     used: usize, // currently used segment data length, in bytes
     data_id: Eid,
     chunks: Vec<Chunk>,
+
+    // key domain which encrypts segment data, none for the data key
+    #[serde(default)]
+    domain: Option<Eid>,
 }
 
 impl Segment {
@@ -235,6 +253,7 @@ impl Segment {
             used: 0,
             data_id: Eid::new(),
             chunks: Vec::new(),
+            domain: None,
         }
     }
 
@@ -263,6 +282,11 @@ impl Segment {
         self.used < self.len >> 2
     }
 
+    #[inline]
+    pub fn domain(&self) -> Option<&Eid> {
+        self.domain.as_ref()
+    }
+
     // create a new chunk and append to segment
     fn append_chunk(&mut self, data_len: usize) {
         let chunk = Chunk::new(self.len, data_len);
@@ -365,8 +389,8 @@ impl Segment {
         let new_data_id = Eid::new();
         let mut new_seg_data = SegData::new(&new_data_id);
         let vol = store.get_vol_weak();
-        new_seg_data.data = buf;
-        new_seg_data.save(&vol)?;
+        new_seg_data.data = Arc::new(buf);
+        new_seg_data.save(&vol, seg.domain.as_ref())?;
         SegData::add_to_trans(&new_data_id, Action::New, txid, txmgr)?;
 
         // update segment's length and its associated segment data id
@@ -443,6 +467,7 @@ pub struct Writer {
     txid: Txid,
     seg: SegRef,
     data_wtr: Option<VolWriter>, // segment data writer
+    domain: Option<Eid>,
     txmgr: TxMgrWeakRef,
     store: StoreWeakRef,
     vol: VolumeWeakRef,
@@ -451,6 +476,7 @@ pub struct Writer {
 impl Writer {
     pub fn new(
         txid: Txid,
+        domain: Option<Eid>,
         store: &StoreWeakRef,
         txmgr: &TxMgrWeakRef,
         vol: &VolumeWeakRef,
@@ -459,6 +485,7 @@ impl Writer {
             txid,
             seg: Arc::default(),
             data_wtr: None,
+            domain,
             txmgr: txmgr.clone(),
             store: store.clone(),
             vol: vol.clone(),
@@ -474,7 +501,8 @@ impl Writer {
         let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
 
         // create a new segment
-        let seg = Segment::new();
+        let mut seg = Segment::new();
+        seg.domain = self.domain.clone();
 
         // add a segment data stub to tx, the actual data will be directly
         // written using volume writer instead of writing to the segment data
@@ -487,7 +515,11 @@ impl Writer {
         }
 
         // and then create a new segment data writer and add segment to tx
-        self.data_wtr = Some(VolWriter::new(&seg.data_id, &self.vol)?);
+        self.data_wtr = Some(VolWriter::for_content(
+            &seg.data_id,
+            &self.vol,
+            seg.domain.as_ref(),
+        )?);
         self.seg = seg.into_cow(&txmgr)?;
 
         // inject segment to segment cache in store
@@ -9,7 +9,7 @@ use super::content::{
     Cache as ContentCache, ContentRef, Writer as ContentWriter,
 };
 use super::segment::{
-    Cache as SegCache, DataCache as SegDataCache, SegDataRef, SegRef,
+    Cache as SegCache, DataCache as SegDataCache, SegDataRef, SegRef, Segment,
 };
 use super::Content;
 use base::crypto::Hash;
@@ -73,7 +73,7 @@ impl Store {
     const SEG_CACHE_SIZE: usize = 16;
 
     // segment data cache size, in bytes
-    const SEG_DATA_CACHE_SIZE: usize = 16 * 1024 * 1024;
+    pub(super) const SEG_DATA_CACHE_SIZE: usize = 16 * 1024 * 1024;
 
     // default content cache size
     const CONTENT_CACHE_SIZE: usize = 16;
@@ -114,11 +114,30 @@ impl Store {
         Arc::downgrade(&self.vol)
     }
 
+    // check if segment data can be accessed, segment in a locked key domain
+    // cannot be shrank
+    pub fn is_seg_unlocked(&self, seg: &Segment) -> bool {
+        match seg.domain() {
+            Some(domain) => {
+                let vol = self.vol.read().unwrap();
+                vol.is_domain_unlocked(domain)
+            }
+            None => true,
+        }
+    }
+
     #[inline]
     pub fn get_seg(&self, seg_id: &Eid) -> Result<SegRef> {
         self.seg_cache.get(seg_id, &self.vol)
     }
 
+    // remove segment from segment cache, used when the segment is going to
+    // be deleted
+    #[inline]
+    pub fn remove_seg_from_cache(&self, seg_id: &Eid) -> Option<SegRef> {
+        self.seg_cache.remove(seg_id)
+    }
+
     // inject intermediate segment to segment cache
     #[inline]
     pub fn inject_seg_to_cache(&self, seg: &SegRef) {
@@ -143,16 +162,74 @@ impl Store {
         self.content_cache.get(content_id, &self.vol)
     }
 
+    /// Get content reference count in store
+    ///
+    /// Returns `None` if file deduplication is not enabled.
+    pub fn content_refcnt(&self, content: &Content) -> Option<u32> {
+        if !self.dedup_file {
+            return None;
+        }
+        self.content_map
+            .get(content.hash())
+            .map(|ent| ent.refcnt.val())
+    }
+
+    /// Get chunk length and its reference count
+    pub fn chunk_refcnt(
+        &self,
+        seg_id: &Eid,
+        idx: usize,
+    ) -> Result<(usize, u32)> {
+        let seg_ref = self.get_seg(seg_id)?;
+        let seg = seg_ref.read().unwrap();
+        let chunk = &seg[idx];
+        Ok((chunk.len, chunk.refcnt()))
+    }
+
+    /// Get content id and reference count of all contents in store
+    ///
+    /// Returns an empty list if file deduplication is not enabled.
+    pub fn content_refcnts(&self) -> Vec<(Eid, u32)> {
+        self.content_map
+            .values()
+            .map(|ent| (ent.content_id.clone(), ent.refcnt.val()))
+            .collect()
+    }
+
+    /// Get segment data id and reference count of each chunk in segment
+    pub fn seg_refcnts(&self, seg_id: &Eid) -> Result<(Eid, Vec<u32>)> {
+        let seg_ref = self.get_seg(seg_id)?;
+        let seg = seg_ref.read().unwrap();
+        let refcnts =
+            (0..seg.chunk_cnt()).map(|idx| seg[idx].refcnt()).collect();
+        Ok((seg.data_id().clone(), refcnts))
+    }
+
+    /// Get length of each chunk in segment
+    pub fn seg_chunk_lens(&self, seg_id: &Eid) -> Result<Vec<usize>> {
+        let seg_ref = self.get_seg(seg_id)?;
+        let seg = seg_ref.read().unwrap();
+        let lens = (0..seg.chunk_cnt()).map(|idx| seg[idx].len).collect();
+        Ok(lens)
+    }
+
     /// Dedup content based on its hash
+    ///
+    /// Content in a key domain is never deduped, as `shared` is false for
+    /// it, because content cannot be shared across key domains.
     pub fn dedup_content(
         store: &StoreRef,
         content: &Content,
+        shared: bool,
     ) -> Result<(bool, Eid)> {
         let mut store = store.write().unwrap();
 
-        if !store.dedup_file {
-            let ctn = content.clone().into_cow(&store.txmgr)?;
-            let ctn = ctn.read().unwrap();
+        if !store.dedup_file || !shared {
+            // the new content is put in cache, so it can be found before
+            // it is committed
+            let ctn_ref = content.clone().into_cow(&store.txmgr)?;
+            store.content_cache.insert(&ctn_ref);
+            let ctn = ctn_ref.read().unwrap();
             return Ok((true, ctn.id().clone()));
         }
 
@@ -177,15 +254,23 @@ impl Store {
 
     /// Decrease content reference in store
     ///
-    /// If the content is not used anymore, remove and return it.
+    /// If the content is not used anymore, remove and return it. Content is
+    /// never shared if file deduplication is not enabled, so it is always
+    /// returned. It is the same for content not shared, which is in a key
+    /// domain.
     pub fn deref_content(
         store: &StoreRef,
         content_id: &Eid,
+        shared: bool,
     ) -> Result<Option<ContentRef>> {
         let mut store = store.write().unwrap();
 
-        if !store.dedup_file {
-            return Ok(None);
+        if !store.dedup_file || !shared {
+            // store is not in transaction, so remove the content from cache
+            // here rather than when store is committed
+            let ctn_ref = store.get_content(content_id)?;
+            store.content_cache.remove(content_id);
+            return Ok(Some(ctn_ref));
         }
 
         let txmgr = store.txmgr.clone();
@@ -243,6 +328,7 @@ impl Writer {
     pub fn new(
         txid: Txid,
         chk_map: ChunkMap,
+        domain: Option<Eid>,
         txmgr: &TxMgrWeakRef,
         store: &StoreWeakRef,
     ) -> Result<Self> {
@@ -251,7 +337,8 @@ impl Writer {
             let store = store.read().unwrap();
             (store.chunker_params.clone(), Arc::downgrade(&store.vol))
         };
-        let ctn_wtr = ContentWriter::new(txid, chk_map, store, txmgr, &vol);
+        let ctn_wtr =
+            ContentWriter::new(txid, chk_map, domain, store, txmgr, &vol);
         Ok(Writer {
             inner: Chunker::new(params, ctn_wtr),
         })
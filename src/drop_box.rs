@@ -0,0 +1,318 @@
+//! Drop box for write-only ingestion using public key.
+
+use std::cmp::min;
+use std::error::Error as StdError;
+use std::fmt::{self, Debug};
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::mem;
+
+use base::crypto::{
+    BoxPublicKey, BoxSecretKey, Cipher, Cost, Crypto, Key, BOX_PUBLIC_KEY_SIZE,
+    BOX_SEAL_SIZE, KEY_SIZE,
+};
+use error::{Error, Result};
+
+// drop package magic number and version
+const MAGIC: [u8; 8] = *b"ZBOXDRP1";
+
+// maximum plaintext size of a frame
+const FRAME_SIZE: usize = 64 * 1024;
+
+// frame flags, the last frame marks the end of package
+const FLAG_DATA: u8 = 0;
+const FLAG_LAST: u8 = 1;
+
+// maximum file name length
+const MAX_NAME_LEN: usize = 255;
+
+// file name must be a single path component
+fn check_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.contains('\0')
+}
+
+// frames are always encrypted with XChaCha20-Poly1305, so a package can be
+// created on any machine regardless of the repository cipher
+#[inline]
+fn frame_crypto() -> Result<Crypto> {
+    Crypto::new(Cost::default(), Cipher::Xchacha)
+}
+
+// read exact bytes, a truncated package is treated as corrupted
+fn read_exact<R: Read>(rdr: &mut R, buf: &mut [u8]) -> Result<()> {
+    rdr.read_exact(buf).map_err(|err| {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            Error::Corrupted
+        } else {
+            Error::from(err)
+        }
+    })
+}
+
+/// Public key of a repository drop box.
+///
+/// It is returned by [`Repo::enable_drop_box`] and can be distributed to
+/// clients, who can create drop packages with it using [`DropWriter`] but
+/// cannot read anything from the repository.
+///
+/// [`Repo::enable_drop_box`]: struct.Repo.html#method.enable_drop_box
+/// [`DropWriter`]: struct.DropWriter.html
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DropPublicKey(BoxPublicKey);
+
+impl DropPublicKey {
+    #[inline]
+    pub(crate) fn new(pk: BoxPublicKey) -> Self {
+        DropPublicKey(pk)
+    }
+
+    /// Creates a public key from bytes.
+    ///
+    /// Returns [`Error::InvalidArgument`] if the length of `buf` is not 32.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() != BOX_PUBLIC_KEY_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let mut pk = [0u8; BOX_PUBLIC_KEY_SIZE];
+        pk.copy_from_slice(buf);
+        Ok(DropPublicKey(pk))
+    }
+
+    /// Returns bytes of the public key.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for DropPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        f.debug_tuple("DropPublicKey").field(&hex).finish()
+    }
+}
+
+/// A writer which creates a drop package using drop box public key.
+///
+/// A drop package contains one file, it is encrypted with a random content
+/// key which is sealed by the drop box public key, so only the repository
+/// owner can open it. The package can be stored or sent anywhere and then
+/// imported into the repository by [`Repo::import_drop`].
+///
+/// [`finish`] must be called to complete the package, otherwise the package
+/// is incomplete and will be rejected when importing.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// use std::io::Write;
+/// use zbox::DropWriter;
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// let pk = repo.enable_drop_box("pwd")?;
+///
+/// // client only needs the public key to create a drop package
+/// let mut wtr = DropWriter::new(&pk, "report.txt", Vec::new())?;
+/// wtr.write_all(b"Hello, world!")?;
+/// let package = wtr.finish()?;
+///
+/// // repository owner imports the package
+/// let path = repo.import_drop(&package[..], "/")?;
+/// assert_eq!(path.to_str().unwrap(), "/report.txt");
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`Repo::import_drop`]: struct.Repo.html#method.import_drop
+/// [`finish`]: struct.DropWriter.html#method.finish
+pub struct DropWriter<W: Write> {
+    inner: W,
+    crypto: Crypto,
+    key: Key,
+    buf: Vec<u8>,
+    seq: u64,
+}
+
+impl<W: Write> DropWriter<W> {
+    /// Creates a drop package writer for file `name`.
+    ///
+    /// `name` is the file name used when the package is imported, it must
+    /// be a single path component no longer than 255 bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `name` is invalid.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn new(pk: &DropPublicKey, name: &str, mut inner: W) -> Result<Self> {
+        if !check_name(name) {
+            return Err(Error::InvalidArgument);
+        }
+
+        // write header: magic and sealed content key
+        let key = Crypto::gen_master_key();
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&Crypto::seal_key(&key, &pk.0)?)?;
+
+        // the first frame is file name
+        let mut wtr = DropWriter {
+            inner,
+            crypto: frame_crypto()?,
+            key,
+            buf: Vec::with_capacity(FRAME_SIZE),
+            seq: 0,
+        };
+        wtr.write_frame(name.as_bytes(), FLAG_DATA)?;
+
+        Ok(wtr)
+    }
+
+    // encrypt and write a frame, frame sequence number is authenticated to
+    // prevent frames from being reordered
+    fn write_frame(&mut self, data: &[u8], flag: u8) -> Result<()> {
+        let mut msg = Vec::with_capacity(1 + data.len());
+        msg.push(flag);
+        msg.extend_from_slice(data);
+        let ctxt = self.crypto.encrypt_with_ad(
+            &msg,
+            &self.key,
+            &self.seq.to_le_bytes(),
+        )?;
+        self.inner.write_all(&(ctxt.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&ctxt)?;
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// Completes the drop package and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let data = mem::take(&mut self.buf);
+        self.write_frame(&data, FLAG_LAST)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DropWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let len = min(buf.len(), FRAME_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() >= FRAME_SIZE {
+            let data = mem::take(&mut self.buf);
+            map_io_err!(self.write_frame(&data, FLAG_DATA))?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Debug for DropWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DropWriter")
+            .field("seq", &self.seq)
+            .finish()
+    }
+}
+
+// drop package reader, it is used by repository owner to open package
+pub(crate) struct DropReader<R: Read> {
+    inner: R,
+    crypto: Crypto,
+    key: Key,
+    seq: u64,
+    name: String,
+    done: bool,
+}
+
+impl<R: Read> DropReader<R> {
+    pub fn new(
+        mut inner: R,
+        pk: &BoxPublicKey,
+        sk: &BoxSecretKey,
+    ) -> Result<Self> {
+        // read header and open sealed content key
+        let mut magic = [0u8; 8];
+        read_exact(&mut inner, &mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::Corrupted);
+        }
+        let mut sealed = vec![0u8; KEY_SIZE + BOX_SEAL_SIZE];
+        read_exact(&mut inner, &mut sealed)?;
+        let key = Crypto::open_sealed_key(&sealed, pk, sk)?;
+
+        let mut rdr = DropReader {
+            inner,
+            crypto: frame_crypto()?,
+            key,
+            seq: 0,
+            name: String::new(),
+            done: false,
+        };
+
+        // read file name from the first frame
+        let (flag, name) = rdr.read_frame()?;
+        if flag != FLAG_DATA {
+            return Err(Error::Corrupted);
+        }
+        rdr.name = String::from_utf8(name).map_err(|_| Error::Corrupted)?;
+        if !check_name(&rdr.name) {
+            return Err(Error::InvalidPath);
+        }
+
+        Ok(rdr)
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // read and decrypt a frame, return its flag and data
+    fn read_frame(&mut self) -> Result<(u8, Vec<u8>)> {
+        let mut len_buf = [0u8; 4];
+        read_exact(&mut self.inner, &mut len_buf)?;
+        let len = u32::from_le_bytes(len_buf) as usize;
+        if len > self.crypto.encrypted_len(1 + FRAME_SIZE) {
+            return Err(Error::Corrupted);
+        }
+        let mut ctxt = vec![0u8; len];
+        read_exact(&mut self.inner, &mut ctxt)?;
+        let mut msg = self.crypto.decrypt_with_ad(
+            &ctxt,
+            &self.key,
+            &self.seq.to_le_bytes(),
+        )?;
+        if msg.is_empty() {
+            return Err(Error::Corrupted);
+        }
+        self.seq += 1;
+        let flag = msg.remove(0);
+        Ok((flag, msg))
+    }
+
+    // read next chunk of file content, return none at the end of package
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let (flag, data) = self.read_frame()?;
+        match flag {
+            FLAG_DATA => {}
+            FLAG_LAST => self.done = true,
+            _ => return Err(Error::Corrupted),
+        }
+        Ok(Some(data))
+    }
+}
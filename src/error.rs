@@ -7,19 +7,35 @@ use std::result;
 use rmp_serde::decode::Error as DecodeError;
 use rmp_serde::encode::Error as EncodeError;
 
+use fs::PathViolation;
+
 #[cfg(feature = "storage-sqlite")]
 use libsqlite3_sys::Error as SqliteError;
 
 #[cfg(feature = "storage-redis")]
 use redis::RedisError;
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(feature = "storage-sled")]
+use sled::Error as SledError;
+
+#[cfg(feature = "keychain")]
+use keyring::Error as KeyringError;
+
+#[cfg(any(
+    feature = "storage-zbox",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 use http::{Error as HttpError, StatusCode};
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
 use serde_json::Error as JsonError;
 
-#[cfg(feature = "storage-zbox-native")]
+#[cfg(any(
+    feature = "storage-zbox-native",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 use reqwest::Error as ReqwestError;
 
 #[cfg(feature = "storage-zbox-android")]
@@ -41,6 +57,9 @@ pub enum Error {
     InvalidCipher,
     Encrypt,
     Decrypt,
+    WeakPassword,
+    KeyProvider,
+    DomainLocked,
 
     InvalidUri,
     InvalidSuperBlk,
@@ -51,6 +70,7 @@ pub enum Error {
     RepoOpened,
     RepoClosed,
     RepoExists,
+    Busy,
 
     InTrans,
     NotInTrans,
@@ -71,6 +91,9 @@ pub enum Error {
     NotFile,
     NotEmpty,
     NoVersion,
+    QuotaExceeded,
+    PathViolation(PathViolation),
+    NoSpace,
 
     ReadOnly,
     CannotRead,
@@ -78,6 +101,7 @@ pub enum Error {
     NotWrite,
     NotFinish,
     Closed,
+    Locked,
 
     Encode(EncodeError),
     Decode(DecodeError),
@@ -90,14 +114,32 @@ pub enum Error {
     #[cfg(feature = "storage-redis")]
     Redis(RedisError),
 
-    #[cfg(feature = "storage-zbox")]
+    #[cfg(feature = "storage-sled")]
+    Sled(SledError),
+
+    #[cfg(feature = "keychain")]
+    Keychain(KeyringError),
+
+    #[cfg(any(
+        feature = "storage-zbox",
+        feature = "storage-gcs",
+        feature = "storage-http"
+    ))]
     Http(HttpError),
-    #[cfg(feature = "storage-zbox")]
+    #[cfg(any(
+        feature = "storage-zbox",
+        feature = "storage-gcs",
+        feature = "storage-http"
+    ))]
     HttpStatus(StatusCode),
-    #[cfg(feature = "storage-zbox")]
+    #[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
     Json(JsonError),
 
-    #[cfg(feature = "storage-zbox-native")]
+    #[cfg(any(
+        feature = "storage-zbox-native",
+        feature = "storage-gcs",
+        feature = "storage-http"
+    ))]
     Reqwest(ReqwestError),
 
     #[cfg(feature = "storage-zbox-android")]
@@ -120,6 +162,9 @@ impl Display for Error {
             Error::InvalidCipher => write!(f, "Invalid cipher"),
             Error::Encrypt => write!(f, "Encrypt error"),
             Error::Decrypt => write!(f, "Decrypt error"),
+            Error::WeakPassword => write!(f, "Password is too weak"),
+            Error::KeyProvider => write!(f, "Key provider mismatch"),
+            Error::DomainLocked => write!(f, "Key domain is locked"),
 
             Error::InvalidUri => write!(f, "Invalid Uri"),
             Error::InvalidSuperBlk => write!(f, "Invalid super block"),
@@ -130,6 +175,7 @@ impl Display for Error {
             Error::RepoOpened => write!(f, "Repo is opened"),
             Error::RepoClosed => write!(f, "Repo is closed"),
             Error::RepoExists => write!(f, "Repo already exists"),
+            Error::Busy => write!(f, "Storage is busy"),
 
             Error::InTrans => write!(f, "Already in transaction"),
             Error::NotInTrans => write!(f, "Not in transaction"),
@@ -150,6 +196,11 @@ impl Display for Error {
             Error::NotFile => write!(f, "Path is not file"),
             Error::NotEmpty => write!(f, "Directory is not empty"),
             Error::NoVersion => write!(f, "File has no version"),
+            Error::QuotaExceeded => write!(f, "Quota exceeded"),
+            Error::PathViolation(ref reason) => {
+                write!(f, "Invalid path: {}", reason)
+            }
+            Error::NoSpace => write!(f, "No space left in storage"),
 
             Error::ReadOnly => write!(f, "Opened as read only"),
             Error::CannotRead => write!(f, "Cannot read file"),
@@ -157,6 +208,7 @@ impl Display for Error {
             Error::NotWrite => write!(f, "File does not write yet"),
             Error::NotFinish => write!(f, "File does not finish yet"),
             Error::Closed => write!(f, "File is closed"),
+            Error::Locked => write!(f, "File is locked"),
 
             Error::Encode(ref err) => err.fmt(f),
             Error::Decode(ref err) => err.fmt(f),
@@ -169,16 +221,34 @@ impl Display for Error {
             #[cfg(feature = "storage-redis")]
             Error::Redis(ref err) => err.fmt(f),
 
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(feature = "storage-sled")]
+            Error::Sled(ref err) => err.fmt(f),
+
+            #[cfg(feature = "keychain")]
+            Error::Keychain(ref err) => err.fmt(f),
+
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Http(ref err) => err.fmt(f),
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::HttpStatus(status_code) => {
                 write!(f, "Http status {}", status_code)
             }
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
             Error::Json(ref err) => err.fmt(f),
 
-            #[cfg(feature = "storage-zbox-native")]
+            #[cfg(any(
+                feature = "storage-zbox-native",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Reqwest(ref err) => err.fmt(f),
 
             #[cfg(feature = "storage-zbox-android")]
@@ -203,6 +273,9 @@ impl StdError for Error {
             Error::InvalidCipher => "Invalid cipher",
             Error::Encrypt => "Encrypt error",
             Error::Decrypt => "Decrypt error",
+            Error::WeakPassword => "Password is too weak",
+            Error::KeyProvider => "Key provider mismatch",
+            Error::DomainLocked => "Key domain is locked",
 
             Error::InvalidUri => "Invalid Uri",
             Error::InvalidSuperBlk => "Invalid super block",
@@ -213,6 +286,7 @@ impl StdError for Error {
             Error::RepoOpened => "Repo is opened",
             Error::RepoClosed => "Repo is closed",
             Error::RepoExists => "Repo already exists",
+            Error::Busy => "Storage is busy",
 
             Error::InTrans => "Already in transaction",
             Error::NotInTrans => "Not in transaction",
@@ -233,6 +307,9 @@ impl StdError for Error {
             Error::NotFile => "Path is not file",
             Error::NotEmpty => "Directory is not empty",
             Error::NoVersion => "File has no version",
+            Error::QuotaExceeded => "Quota exceeded",
+            Error::PathViolation(_) => "Invalid path",
+            Error::NoSpace => "No space left in storage",
 
             Error::ReadOnly => "Opened as read only",
             Error::CannotRead => "Cannot read file",
@@ -240,6 +317,7 @@ impl StdError for Error {
             Error::NotWrite => "File does not write yet",
             Error::NotFinish => "File does not finish yet",
             Error::Closed => "File is closed",
+            Error::Locked => "File is locked",
 
             Error::Encode(ref err) => err.description(),
             Error::Decode(ref err) => err.description(),
@@ -252,14 +330,32 @@ impl StdError for Error {
             #[cfg(feature = "storage-redis")]
             Error::Redis(ref err) => err.description(),
 
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(feature = "storage-sled")]
+            Error::Sled(ref err) => err.description(),
+
+            #[cfg(feature = "keychain")]
+            Error::Keychain(ref err) => err.description(),
+
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Http(ref err) => err.description(),
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::HttpStatus(_) => "Http status error",
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
             Error::Json(ref err) => err.description(),
 
-            #[cfg(feature = "storage-zbox-native")]
+            #[cfg(any(
+                feature = "storage-zbox-native",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Reqwest(ref err) => err.description(),
 
             #[cfg(feature = "storage-zbox-android")]
@@ -283,12 +379,26 @@ impl StdError for Error {
             #[cfg(feature = "storage-redis")]
             Error::Redis(ref err) => Some(err),
 
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(feature = "storage-sled")]
+            Error::Sled(ref err) => Some(err),
+
+            #[cfg(feature = "keychain")]
+            Error::Keychain(ref err) => Some(err),
+
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Http(ref err) => Some(err),
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
             Error::Json(ref err) => Some(err),
 
-            #[cfg(feature = "storage-zbox-native")]
+            #[cfg(any(
+                feature = "storage-zbox-native",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Reqwest(ref err) => Some(err),
 
             #[cfg(feature = "storage-zbox-android")]
@@ -337,21 +447,43 @@ impl From<RedisError> for Error {
     }
 }
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(feature = "storage-sled")]
+impl From<SledError> for Error {
+    fn from(err: SledError) -> Error {
+        Error::Sled(err)
+    }
+}
+
+#[cfg(feature = "keychain")]
+impl From<KeyringError> for Error {
+    fn from(err: KeyringError) -> Error {
+        Error::Keychain(err)
+    }
+}
+
+#[cfg(any(
+    feature = "storage-zbox",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 impl From<HttpError> for Error {
     fn from(err: HttpError) -> Error {
         Error::Http(err)
     }
 }
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
 impl From<JsonError> for Error {
     fn from(err: JsonError) -> Error {
         Error::Json(err)
     }
 }
 
-#[cfg(feature = "storage-zbox-native")]
+#[cfg(any(
+    feature = "storage-zbox-native",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 impl From<ReqwestError> for Error {
     fn from(err: ReqwestError) -> Error {
         Error::Reqwest(err)
@@ -378,6 +510,9 @@ impl Into<i32> for Error {
             Error::InvalidCipher => -1014,
             Error::Encrypt => -1015,
             Error::Decrypt => -1016,
+            Error::WeakPassword => -1017,
+            Error::KeyProvider => -1018,
+            Error::DomainLocked => -1019,
 
             Error::InvalidUri => -1020,
             Error::InvalidSuperBlk => -1021,
@@ -388,6 +523,7 @@ impl Into<i32> for Error {
             Error::RepoOpened => -1026,
             Error::RepoClosed => -1027,
             Error::RepoExists => -1028,
+            Error::Busy => -1029,
 
             Error::InTrans => -1030,
             Error::NotInTrans => -1031,
@@ -408,6 +544,9 @@ impl Into<i32> for Error {
             Error::NotFile => -1058,
             Error::NotEmpty => -1059,
             Error::NoVersion => -1060,
+            Error::QuotaExceeded => -1061,
+            Error::PathViolation(_) => -1062,
+            Error::NoSpace => -1063,
 
             Error::ReadOnly => -1070,
             Error::CannotRead => -1071,
@@ -415,6 +554,7 @@ impl Into<i32> for Error {
             Error::NotWrite => -1073,
             Error::NotFinish => -1074,
             Error::Closed => -1075,
+            Error::Locked => -1076,
 
             Error::Encode(_) => -2000,
             Error::Decode(_) => -2010,
@@ -427,14 +567,32 @@ impl Into<i32> for Error {
             #[cfg(feature = "storage-redis")]
             Error::Redis(_) => -2050,
 
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(feature = "storage-sled")]
+            Error::Sled(_) => -2055,
+
+            #[cfg(feature = "keychain")]
+            Error::Keychain(_) => -2070,
+
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Http(_) => -2060,
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::HttpStatus(_) => -2061,
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
             Error::Json(_) => -2062,
 
-            #[cfg(feature = "storage-zbox-native")]
+            #[cfg(any(
+                feature = "storage-zbox-native",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             Error::Reqwest(_) => -2063,
 
             #[cfg(feature = "storage-zbox-android")]
@@ -459,6 +617,9 @@ impl PartialEq for Error {
             (&Error::InvalidCipher, &Error::InvalidCipher) => true,
             (&Error::Encrypt, &Error::Encrypt) => true,
             (&Error::Decrypt, &Error::Decrypt) => true,
+            (&Error::WeakPassword, &Error::WeakPassword) => true,
+            (&Error::KeyProvider, &Error::KeyProvider) => true,
+            (&Error::DomainLocked, &Error::DomainLocked) => true,
 
             (&Error::InvalidUri, &Error::InvalidUri) => true,
             (&Error::InvalidSuperBlk, &Error::InvalidSuperBlk) => true,
@@ -469,6 +630,7 @@ impl PartialEq for Error {
             (&Error::RepoOpened, &Error::RepoOpened) => true,
             (&Error::RepoClosed, &Error::RepoClosed) => true,
             (&Error::RepoExists, &Error::RepoExists) => true,
+            (&Error::Busy, &Error::Busy) => true,
 
             (&Error::InTrans, &Error::InTrans) => true,
             (&Error::NotInTrans, &Error::NotInTrans) => true,
@@ -489,6 +651,11 @@ impl PartialEq for Error {
             (&Error::NotFile, &Error::NotFile) => true,
             (&Error::NotEmpty, &Error::NotEmpty) => true,
             (&Error::NoVersion, &Error::NoVersion) => true,
+            (&Error::QuotaExceeded, &Error::QuotaExceeded) => true,
+            (&Error::PathViolation(ref a), &Error::PathViolation(ref b)) => {
+                a == b
+            }
+            (&Error::NoSpace, &Error::NoSpace) => true,
 
             (&Error::ReadOnly, &Error::ReadOnly) => true,
             (&Error::CannotRead, &Error::CannotRead) => true,
@@ -496,6 +663,7 @@ impl PartialEq for Error {
             (&Error::NotWrite, &Error::NotWrite) => true,
             (&Error::NotFinish, &Error::NotFinish) => true,
             (&Error::Closed, &Error::Closed) => true,
+            (&Error::Locked, &Error::Locked) => true,
 
             (&Error::Encode(_), &Error::Encode(_)) => true,
             (&Error::Decode(_), &Error::Decode(_)) => true,
@@ -510,10 +678,24 @@ impl PartialEq for Error {
                 a.kind() == b.kind()
             }
 
-            #[cfg(feature = "storage-zbox")]
+            #[cfg(feature = "storage-sled")]
+            (&Error::Sled(ref a), &Error::Sled(ref b)) => a == b,
+
+            #[cfg(feature = "keychain")]
+            (&Error::Keychain(_), &Error::Keychain(_)) => true,
+
+            #[cfg(any(
+                feature = "storage-zbox",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             (&Error::HttpStatus(a), &Error::HttpStatus(b)) => a == b,
 
-            #[cfg(feature = "storage-zbox-native")]
+            #[cfg(any(
+                feature = "storage-zbox-native",
+                feature = "storage-gcs",
+                feature = "storage-http"
+            ))]
             (&Error::Reqwest(ref a), &Error::Reqwest(ref b)) => {
                 a.status() == b.status()
             }
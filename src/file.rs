@@ -1,21 +1,38 @@
+use std::cmp::min;
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
-use std::io::{self, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{
+    self, Error as IoError, ErrorKind, IoSlice, IoSliceMut, Read, Seek,
+    SeekFrom, Write,
+};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 use super::{Error, Result};
+use base::utils::align_ceil_chunk;
 use fs::fnode::{
-    Fnode, Metadata, Reader as FnodeReader, Version, Writer as FnodeWriter,
+    DedupInfo, Fnode, FnodeRef, Metadata, Reader as FnodeReader, Version,
+    Writer as FnodeWriter,
 };
-use fs::Handle;
-use trans::{TxHandle, TxMgr};
+use fs::{new_lock_owner, FsRef, FsWeakRef, Handle, LockKind};
+use trace::{Op, Tracer, TracerRef};
+use trans::{Id, TxHandle, TxMgr};
+use volume::{VolumeRef, BLK_SIZE};
 
 /// A reader for a specific vesion of file content.
 ///
 /// This reader can be obtained by [`version_reader`] method, and it
-/// implements [`Read`] trait.
+/// implements [`Read`] and [`Seek`] traits, so an old version can be
+/// randomly accessed as well as the current version.
 ///
 /// [`version_reader`]: struct.File.html#method.version_reader
 /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`Seek`]: https://doc.rust-lang.org/std/io/trait.Seek.html
 #[derive(Debug)]
 pub struct VersionReader {
     handle: Handle,
@@ -55,6 +72,111 @@ impl Seek for VersionReader {
     }
 }
 
+// temporary file created by repo, it is removed when dropped unless it is
+// persisted, if removal failed it will be removed when the repo is opened
+// next time
+struct TempFile {
+    fs: FsWeakRef,
+    fnode: FnodeRef,
+    persisted: bool,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.persisted {
+            return;
+        }
+        if let Some(fs) = self.fs.upgrade() {
+            let fnode = mem::replace(&mut self.fnode, FnodeRef::default());
+            if let Err(err) = fs.write().unwrap().remove_temp(fnode) {
+                warn!("remove temporary file failed: {}", err);
+            }
+        }
+    }
+}
+
+/// A borrowed reference to a block of file content.
+///
+/// It is returned by [`File::read_block_ref`] and dereferences to the bytes
+/// of the block. The block shares the decrypted data in the content cache,
+/// so no data is copied. It borrows the file it is read from, so the file
+/// cannot be written or dropped while the block is alive.
+///
+/// [`File::read_block_ref`]: struct.File.html#method.read_block_ref
+pub struct BlockRef<'a> {
+    data: Arc<Vec<u8>>,
+    start: usize,
+    end: usize,
+    _file: PhantomData<&'a File>,
+}
+
+impl<'a> Deref for BlockRef<'a> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.data[self.start..self.end]
+    }
+}
+
+impl<'a> AsRef<[u8]> for BlockRef<'a> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<'a> Debug for BlockRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockRef")
+            .field("len", &(self.end - self.start))
+            .finish()
+    }
+}
+
+/// Advice about the expected access pattern of a file.
+///
+/// It is given to [`File::advise`] to tune how file content is read and
+/// cached. Advice is only a hint, it never changes the content read from a
+/// file.
+///
+/// [`File::advise`]: struct.File.html#method.advise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No specific access pattern, this is the default.
+    Normal,
+
+    /// Content will be read sequentially from lower offsets to higher ones.
+    ///
+    /// The next data segment is loaded ahead when reading moves to a new
+    /// segment, and data already read is removed from cache, so streaming
+    /// a large file doesn't evict other cached data.
+    Sequential,
+
+    /// Content will be read in random order.
+    ///
+    /// No data is loaded ahead, this is same as `Normal` currently.
+    Random,
+
+    /// Content will be read soon.
+    ///
+    /// Data of the current version is loaded into cache immediately, as much
+    /// as the cache can hold.
+    WillNeed,
+
+    /// Content will not be read soon.
+    ///
+    /// Data of the current version is removed from cache immediately.
+    DontNeed,
+}
+
+impl Default for Advice {
+    #[inline]
+    fn default() -> Self {
+        Advice::Normal
+    }
+}
+
 /// A reference to an opened file in the repository.
 ///
 /// An instance of a `File` can be read and/or written depending on what options
@@ -148,6 +270,10 @@ impl Seek for VersionReader {
 ///   happened during [`write`], that transaction will be aborted. Thus, you
 ///   should not call [`finish`] after any failed [`write`].
 ///
+///   If the `File` is dropped before [`finish`], the transaction is aborted
+///   and the written data is discarded, unless the file is opened with
+///   [`auto_commit`], which finishes the write on drop.
+///
 ///   Because transactions is thread local, multi-part write should be done in
 ///   one transaction.
 ///
@@ -211,6 +337,20 @@ impl Seek for VersionReader {
 ///   # foo().unwrap();
 ///   ```
 ///
+/// # Durability
+///
+/// There are three levels of durability when writing to a file, which trade
+/// latency for safety:
+///
+/// - [`flush`] only moves written data through the internal chunking buffer,
+///   nothing is guaranteed to reach the storage.
+/// - [`sync_data`] also writes buffered content data of an unfinished
+///   multi-part write to the storage, but the data is not part of the file
+///   until the write is finished.
+/// - [`sync_all`] finishes the multi-part write and commits it, both content
+///   and metadata are persisted. [`finish`] and [`write_once`] give the same
+///   guarantee.
+///
 /// To gurantee atomicity, ZboxFS uses transaction when updating file so the
 /// data either be wholly persisted or nothing has been written.
 ///
@@ -317,7 +457,10 @@ impl Seek for VersionReader {
 /// [`VersionReader`]: struct.VersionReader.html
 /// [`version_limit`]: struct.OpenOptions.html#method.version_limit
 /// [`finish`]: struct.File.html#method.finish
+/// [`auto_commit`]: struct.OpenOptions.html#method.auto_commit
 /// [`write_once`]: struct.File.html#method.write_once
+/// [`sync_data`]: struct.File.html#method.sync_data
+/// [`sync_all`]: struct.File.html#method.sync_all
 pub struct File {
     handle: Handle,
     pos: SeekFrom, // must always be SeekFrom::Start
@@ -326,9 +469,24 @@ pub struct File {
     tx_handle: Option<TxHandle>,
     can_read: bool,
     can_write: bool,
+    tracer: Option<(TracerRef, u64)>,
+    lock_owner: usize,
+    auto_commit: bool,
+    accessed: bool,
+    advice: Advice,
+
+    // storage blocks reserved by allocate() and not used by writes yet
+    reserved: AtomicUsize,
+
+    // temporary file which is not persisted yet, it must be the last field
+    // so it is dropped after file handle is released
+    temp: Option<TempFile>,
 }
 
 impl File {
+    // maximum length of a version tag, in bytes
+    const MAX_TAG_LEN: usize = 255;
+
     pub(super) fn new(
         handle: Handle,
         pos: SeekFrom,
@@ -343,6 +501,45 @@ impl File {
             tx_handle: None,
             can_read,
             can_write,
+            tracer: None,
+            lock_owner: new_lock_owner(),
+            auto_commit: false,
+            accessed: false,
+            advice: Advice::default(),
+            reserved: AtomicUsize::new(0),
+            temp: None,
+        }
+    }
+
+    // set operation tracer and file handle id in trace
+    #[inline]
+    pub(super) fn set_tracer(&mut self, tracer: &TracerRef, fid: u64) {
+        self.tracer = Some((tracer.clone(), fid));
+    }
+
+    // set whether pending write is finished when file is dropped
+    #[inline]
+    pub(super) fn set_auto_commit(&mut self, auto_commit: bool) {
+        self.auto_commit = auto_commit;
+    }
+
+    // mark this file as a temporary file
+    #[inline]
+    pub(super) fn set_temp(&mut self, fs: &FsRef) {
+        self.temp = Some(TempFile {
+            fs: Arc::downgrade(fs),
+            fnode: self.handle.fnode.clone(),
+            persisted: false,
+        });
+    }
+
+    // record an operation to trace if this file is traced
+    #[inline]
+    fn trace<F: FnOnce(&Tracer, u64) -> Op>(&self, f: F) {
+        if let Some((ref tracer, fid)) = self.tracer {
+            let mut tracer = tracer.lock().unwrap();
+            let op = f(&tracer, fid);
+            tracer.record(&op);
         }
     }
 
@@ -369,6 +566,18 @@ impl File {
         Ok(fnode.history())
     }
 
+    /// Queries deduplication information about the file.
+    ///
+    /// This reports how many data chunks of the current version are shared
+    /// with other versions or files, and the size of data exclusively used by
+    /// this file, which would be reclaimed if this file is deleted.
+    pub fn dedup_info(&self) -> Result<DedupInfo> {
+        self.check_closed()?;
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let fnode = self.handle.fnode.read().unwrap();
+        fnode.dedup_info(&store)
+    }
+
     /// Returns the current content version number.
     pub fn curr_version(&self) -> Result<usize> {
         self.check_closed()?;
@@ -384,11 +593,40 @@ impl File {
 
     /// Get a reader of the specified version.
     ///
-    /// The returned reader implements [`Read`] trait. To get the version
-    /// number, first call [`history`] to get the list of all versions and
-    /// then choose the version number from it.
+    /// The returned reader implements [`Read`] and [`Seek`] traits. To get
+    /// the version number, first call [`history`] to get the list of all
+    /// versions and then choose the version number from it.
+    ///
+    /// # Examples
+    ///
+    /// Read the last 3 bytes of the previous version.
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// use std::io::{Read, Seek, SeekFrom};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .version_limit(2)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo bar")?;
+    /// file.write_once(b"baz")?;
+    ///
+    /// let ver_num = file.curr_version()? - 1;
+    /// let mut rdr = file.version_reader(ver_num)?;
+    /// rdr.seek(SeekFrom::End(-3))?;
+    /// let mut buf = String::new();
+    /// rdr.read_to_string(&mut buf)?;
+    /// assert_eq!(buf, "bar");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
     ///
     /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+    /// [`Seek`]: https://doc.rust-lang.org/std/io/trait.Seek.html
     /// [`history`]: struct.File.html#method.history
     pub fn version_reader(&self, ver_num: usize) -> Result<VersionReader> {
         self.check_closed()?;
@@ -398,6 +636,214 @@ impl File {
         VersionReader::new(&self.handle, ver_num)
     }
 
+    /// Returns the changed byte ranges between two versions.
+    ///
+    /// Each changed range is returned as an offset and length pair, sorted
+    /// by offset and not overlapping. If the versions have different
+    /// lengths, the bytes beyond the shorter one are included as changed.
+    ///
+    /// The ranges are calculated from the data chunks referred by each
+    /// version, without reading content. Changed data is detected at chunk
+    /// granularity, so a range may contain some unchanged bytes, and data
+    /// written again with identical bytes is reported as changed if it is
+    /// not deduplicated.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if either version doesn't exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .version_limit(4)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo")?;
+    /// let ver_num = file.curr_version()?;
+    /// file.write_once(b"bar")?;
+    ///
+    /// let diffs = file.diff_versions(ver_num, file.curr_version()?)?;
+    /// assert_eq!(diffs, vec![(3, 3)]);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::NoVersion`]: enum.Error.html
+    pub fn diff_versions(
+        &self,
+        ver_num: usize,
+        other_ver_num: usize,
+    ) -> Result<Vec<(usize, usize)>> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let fnode = self.handle.fnode.read().unwrap();
+        fnode.diff_versions(ver_num, other_ver_num, &store)
+    }
+
+    /// Restores a previous version as the current version.
+    ///
+    /// The content of the specified version is added as a new version, so
+    /// the versions in between are kept in history, subject to the version
+    /// limit. No data is copied, the new version shares the content of the
+    /// restored one. It does nothing if the specified version is already the
+    /// current version.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if the version doesn't exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// # use std::io::{Read, Seek, SeekFrom};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .version_limit(4)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo")?;
+    /// let ver_num = file.curr_version()?;
+    /// file.write_once(b"bar")?;
+    ///
+    /// file.restore_version(ver_num)?;
+    /// let mut content = String::new();
+    /// file.seek(SeekFrom::Start(0))?;
+    /// file.read_to_string(&mut content)?;
+    /// assert_eq!(content, "foo");
+    /// assert_eq!(file.curr_version()?, ver_num + 2);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::NoVersion`]: enum.Error.html
+    pub fn restore_version(&mut self, ver_num: usize) -> Result<()> {
+        self.trace(|_, fid| Op::RestoreVersion { fid, ver_num });
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            Fnode::restore_version(self.handle.clone(), ver_num)
+        })?;
+        self.handle.notify_modify();
+
+        // re-create reader if there is an existing reader
+        if self.rdr.is_some() {
+            self.renew_reader()?;
+        }
+
+        Ok(())
+    }
+
+    /// Attaches a tag to the specified version.
+    ///
+    /// A tag is a human-readable label, such as `v1-release`, which can be
+    /// used to find the version later by [`version_by_tag`]. Tags are unique
+    /// within a file, if another version already has the same tag, it is
+    /// moved to the specified version. A version can only have one tag, its
+    /// existing tag is replaced.
+    ///
+    /// Tags don't prevent versions from being removed when the version limit
+    /// is reached, the tag is removed along with its version.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if the version doesn't exist, and
+    /// [`Error::InvalidArgument`] if `tag` is empty or longer than 255 bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .version_limit(4)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo")?;
+    /// let ver_num = file.curr_version()?;
+    /// file.tag_version(ver_num, "v1-release")?;
+    /// file.write_once(b"bar")?;
+    ///
+    /// let ver = file.version_by_tag("v1-release")?;
+    /// assert_eq!(ver.num(), ver_num);
+    /// assert_eq!(ver.tag(), Some("v1-release"));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`version_by_tag`]: struct.File.html#method.version_by_tag
+    /// [`Error::NoVersion`]: enum.Error.html
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn tag_version(&mut self, ver_num: usize, tag: &str) -> Result<()> {
+        if tag.is_empty() || tag.len() > Self::MAX_TAG_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        self.set_version_tag(ver_num, Some(tag))
+    }
+
+    /// Removes the tag from the specified version.
+    ///
+    /// It does nothing if the version has no tag.
+    pub fn untag_version(&mut self, ver_num: usize) -> Result<()> {
+        self.set_version_tag(ver_num, None)
+    }
+
+    fn set_version_tag(
+        &mut self,
+        ver_num: usize,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        self.check_closed()?;
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode_cow = self.handle.fnode.write().unwrap();
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            fnode.tag_version(ver_num, tag)
+        })
+    }
+
+    /// Returns the version which has the specified tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if no version has the tag.
+    ///
+    /// [`Error::NoVersion`]: enum.Error.html
+    pub fn version_by_tag(&self, tag: &str) -> Result<Version> {
+        self.check_closed()?;
+        let fnode = self.handle.fnode.read().unwrap();
+        fnode.tagged_ver(tag).cloned().ok_or(Error::NoVersion)
+    }
+
     // calculate the seek position from the start based on file current size
     fn seek_pos(&self, pos: SeekFrom) -> SeekFrom {
         let curr_len = self.curr_len();
@@ -431,7 +877,7 @@ impl File {
                 let pos = pos as usize;
                 if pos > curr_len {
                     // append zeros by setting file length
-                    self.set_len(pos)?;
+                    self.set_len_inner(pos)?;
 
                     // then seek to new EOF
                     self.pos = self.seek_pos(SeekFrom::End(0));
@@ -455,6 +901,22 @@ impl File {
         Ok(())
     }
 
+    // update access time if it is stale, this is done in its own
+    // transaction
+    fn touch(&self) -> Result<()> {
+        if !self.handle.fnode.read().unwrap().is_atime_stale() {
+            return Ok(());
+        }
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode_cow = self.handle.fnode.write().unwrap();
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            fnode.touch();
+            Ok(())
+        })
+    }
+
     // re-create reader on latest version
     fn renew_reader(&mut self) -> Result<()> {
         let mut rdr = FnodeReader::new_current(
@@ -463,6 +925,75 @@ impl File {
         )?;
         rdr.seek(self.pos)?;
         self.rdr = Some(rdr);
+        self.apply_advice();
+        Ok(())
+    }
+
+    // apply access pattern advice to the reader
+    fn apply_advice(&mut self) {
+        let sequential = self.advice == Advice::Sequential;
+        if let Some(ref mut rdr) = self.rdr {
+            rdr.set_read_ahead(sequential);
+            rdr.set_drop_behind(sequential);
+        }
+    }
+
+    /// Gives advice about the expected access pattern of the file.
+    ///
+    /// `Normal`, `Sequential` and `Random` advice applies to subsequent
+    /// reads through this `File` instance, until another one of them is
+    /// given. `WillNeed` and `DontNeed` advice applies to the current
+    /// version immediately and doesn't change how this file is read later.
+    ///
+    /// Advice is only a hint to tune cache usage, it doesn't affect other
+    /// `File` instances, including the ones created by [`try_clone`]. See
+    /// [`Advice`] for more details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    /// use zbox::Advice;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// # repo.create_file("/foo.txt")?.write_once(b"foo")?;
+    /// let mut file = repo.open_file("/foo.txt")?;
+    ///
+    /// // stream the file once without polluting the cache
+    /// file.advise(Advice::Sequential)?;
+    /// let mut buf = Vec::new();
+    /// file.read_to_end(&mut buf)?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`try_clone`]: struct.File.html#method.try_clone
+    /// [`Advice`]: enum.Advice.html
+    pub fn advise(&mut self, advice: Advice) -> Result<()> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        match advice {
+            Advice::Normal | Advice::Sequential | Advice::Random => {
+                self.advice = advice;
+                self.apply_advice();
+            }
+            Advice::WillNeed | Advice::DontNeed => {
+                let store =
+                    self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+                let fnode = self.handle.fnode.read().unwrap();
+                if advice == Advice::WillNeed {
+                    fnode.prefetch(&store)?;
+                } else {
+                    fnode.evict(&store)?;
+                }
+            }
+        }
         Ok(())
     }
 
@@ -480,6 +1011,11 @@ impl File {
     /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
     /// [`Error::NotWrite`]: enum.Error.html
     pub fn finish(&mut self) -> Result<()> {
+        self.trace(|_, fid| Op::Finish { fid });
+        self.finish_inner()
+    }
+
+    fn finish_inner(&mut self) -> Result<()> {
         self.check_closed()?;
 
         match self.wtr.take() {
@@ -491,6 +1027,7 @@ impl File {
                     end_pos = wtr.finish()?;
                     Ok(())
                 })?;
+                self.handle.notify_modify();
 
                 // set position
                 self.pos = SeekFrom::Start(end_pos as u64);
@@ -516,6 +1053,7 @@ impl File {
     /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
     /// [`finish`]: struct.File.html#method.finish
     pub fn write_once(&mut self, buf: &[u8]) -> Result<()> {
+        self.trace(|tr, fid| Op::write_once(tr, fid, buf));
         self.check_closed()?;
         match self.wtr {
             Some(_) => Err(Error::NotFinish),
@@ -533,9 +1071,89 @@ impl File {
                     },
                     None => unreachable!(),
                 }
-                self.finish()
+                self.use_reserved(buf.len());
+                self.finish_inner()
+            }
+        }
+    }
+
+    /// Writes buffered content data to the storage.
+    ///
+    /// Data written by an unfinished multi-part write is pushed through the
+    /// internal buffer and persisted to the storage, so the following
+    /// [`finish`] has less to do. The data is not part of the file until the
+    /// write is finished, it is discarded if the repository is closed or
+    /// crashed before that. Metadata is not changed.
+    ///
+    /// This method does nothing on the file content if there is no
+    /// unfinished write. See [`sync_all`] to also commit the write.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::prelude::*;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_all(b"Hello, ")?;
+    /// file.sync_data()?;
+    /// file.write_all(b"world!")?;
+    /// file.sync_all()?;
+    /// assert_eq!(file.metadata()?.content_len(), 13);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`sync_all`]: struct.File.html#method.sync_all
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.check_closed()?;
+        if !self.can_write {
+            return Ok(());
+        }
+
+        if let Some(ref mut wtr) = self.wtr {
+            let result = match self.tx_handle {
+                Some(ref tx_handle) => tx_handle.run(|| {
+                    wtr.flush()?;
+                    Ok(())
+                }),
+                None => unreachable!(),
+            };
+            if let Err(err) = result {
+                // the tx has been aborted, clean up writer and tx handle
+                self.wtr.take();
+                self.tx_handle.take();
+                return Err(err);
             }
         }
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = txmgr.read().unwrap();
+        txmgr.flush_vol()
+    }
+
+    /// Writes buffered content data and commits metadata to the storage.
+    ///
+    /// If there is an unfinished multi-part write, it is finished and a new
+    /// version is created, as [`finish`] does. After this method returned,
+    /// all data written to this file is persisted.
+    ///
+    /// See [`sync_data`] for a cheaper alternative which doesn't commit.
+    ///
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`sync_data`]: struct.File.html#method.sync_data
+    pub fn sync_all(&mut self) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            // finish commits transaction, which also flushes volume
+            return self.finish();
+        }
+        self.sync_data()
     }
 
     /// Truncates or extends the underlying file, create a new version of
@@ -548,11 +1166,38 @@ impl File {
     ///
     /// This method is atomic.
     ///
+    /// The current cursor position is not changed. If it is beyond the new
+    /// end of file after shrinking, the gap is filled with 0s on next write.
+    ///
     /// # Errors
     ///
     /// This method will return an error if the file is not opened for writing
     /// or not finished writing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_once(b"Hello, world!")?;
+    ///
+    /// file.set_len(5)?;
+    /// assert_eq!(file.metadata()?.content_len(), 5);
+    /// file.set_len(10)?;
+    /// assert_eq!(file.metadata()?.content_len(), 10);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
     pub fn set_len(&mut self, len: usize) -> Result<()> {
+        self.trace(|_, fid| Op::SetLen { fid, len });
+        self.set_len_inner(len)
+    }
+
+    fn set_len_inner(&mut self, len: usize) -> Result<()> {
         self.check_closed()?;
         if self.wtr.is_some() {
             return Err(Error::NotFinish);
@@ -567,6 +1212,7 @@ impl File {
         tx_handle.run_all_exclusive(|| {
             Fnode::set_len(self.handle.clone(), len, tx_handle.txid)
         })?;
+        self.handle.notify_modify();
 
         // re-create reader if there is an existing reader
         if self.rdr.is_some() {
@@ -575,10 +1221,632 @@ impl File {
 
         Ok(())
     }
+
+    /// Reserves storage blocks for the file to grow to `len` bytes.
+    ///
+    /// Call this before a large write so it can fail fast on insufficient
+    /// space, instead of failing mid-way after a lot of data has been
+    /// written. Blocks are reserved in the volume allocator without writing
+    /// any data, they are counted as used space of the storage, so other
+    /// reservations cannot take them. File content is not changed.
+    ///
+    /// The reservation is used up as data is written through this `File`
+    /// instance, and the remaining is released when it is dropped. Calling
+    /// this method again only reserves the blocks not reserved yet.
+    ///
+    /// Quotas of the parent directories, see [`Repo::set_quota`], are checked
+    /// but not reserved. The space needed is estimated from `len`, as the
+    /// actual space used depends on deduplication and compression.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::QuotaExceeded`] if growing the file to `len` bytes
+    /// would exceed a quota, and [`Error::NoSpace`] if there are not enough
+    /// free blocks left in the storage.
+    ///
+    /// This method will return an error if the file is not opened for writing
+    /// or not finished writing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Error, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir("/uploads")?;
+    /// repo.set_quota("/uploads", 1024)?;
+    ///
+    /// let mut file = repo.create_file("/uploads/foo.bin")?;
+    /// file.allocate(1024)?;
+    /// assert_eq!(file.allocate(1025).unwrap_err(), Error::QuotaExceeded);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::set_quota`]: struct.Repo.html#method.set_quota
+    /// [`Error::QuotaExceeded`]: enum.Error.html
+    /// [`Error::NoSpace`]: enum.Error.html
+    pub fn allocate(&self, len: usize) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+
+        let (curr_len, usage) = {
+            let fnode = self.handle.fnode.read().unwrap();
+            (fnode.curr_len(), fnode.new_version_usage(len))
+        };
+        if len <= curr_len {
+            return Ok(());
+        }
+        Fnode::check_quota(&self.handle.fnode, usage)?;
+
+        let blk_cnt = align_ceil_chunk(len - curr_len, BLK_SIZE);
+        let reserved = self.reserved.load(Ordering::SeqCst);
+        if blk_cnt <= reserved {
+            return Ok(());
+        }
+
+        let vol = self.volume()?;
+        vol.read().unwrap().reserve(blk_cnt - reserved)?;
+        self.reserved
+            .fetch_add(blk_cnt - reserved, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    // get the volume this file is stored in
+    fn volume(&self) -> Result<VolumeRef> {
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let vol = store.read().unwrap().get_vol_weak();
+        vol.upgrade().ok_or(Error::RepoClosed)
+    }
+
+    // use up reserved blocks by data written
+    fn use_reserved(&self, len: usize) {
+        let blk_cnt = align_ceil_chunk(len, BLK_SIZE);
+        let prev = self
+            .reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |reserved| {
+                Some(reserved.saturating_sub(blk_cnt))
+            })
+            .unwrap();
+        self.release_reserved(min(prev, blk_cnt));
+    }
+
+    // release reserved blocks back to the volume allocator
+    fn release_reserved(&self, blk_cnt: usize) {
+        if blk_cnt == 0 {
+            return;
+        }
+        match self.volume() {
+            Ok(vol) => vol.read().unwrap().release(blk_cnt),
+            Err(err) => warn!("release reserved blocks failed: {}", err),
+        }
+    }
+
+    /// Creates a new `File` instance that shares the same underlying file.
+    ///
+    /// The new instance has its own cursor, which is set to the beginning of
+    /// file, and the same read and write permissions. Unfinished multi-part
+    /// write is not shared.
+    ///
+    /// Together with [`read_at`] and [`write_at`], this can be used to do
+    /// positional I/O on the same file from multiple threads.
+    ///
+    /// [`read_at`]: struct.File.html#method.read_at
+    /// [`write_at`]: struct.File.html#method.write_at
+    pub fn try_clone(&self) -> Result<File> {
+        self.check_closed()?;
+        let mut file = File::new(
+            self.handle.clone(),
+            SeekFrom::Start(0),
+            self.can_read,
+            self.can_write,
+        );
+        file.tracer = self.tracer.clone();
+        file.auto_commit = self.auto_commit;
+        Ok(file)
+    }
+
+    /// Acquires an exclusive advisory lock on the file, blocking until the
+    /// lock can be acquired.
+    ///
+    /// Locks are advisory, they don't prevent reading or writing the file,
+    /// but only coordinate with other `File` instances which also acquire
+    /// locks. Locks are scoped to the [`Repo`] instance, so they are only
+    /// seen by files opened from the same repository instance.
+    ///
+    /// Each `File` instance, including one created by [`try_clone`], is a
+    /// separate lock owner. A lock is held until [`unlock`] is called or the
+    /// `File` is dropped. If this `File` already holds a shared lock, it is
+    /// converted to an exclusive lock when no others hold shared locks.
+    ///
+    /// Use [`try_lock_exclusive`] to acquire the lock without blocking.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, Error};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let file = repo.create_file("/foo.txt")?;
+    /// let file2 = repo.open_file("/foo.txt")?;
+    ///
+    /// file.lock_exclusive()?;
+    /// assert_eq!(file2.try_lock_shared().unwrap_err(), Error::Locked);
+    /// file.unlock()?;
+    /// file2.try_lock_shared()?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo`]: struct.Repo.html
+    /// [`try_clone`]: struct.File.html#method.try_clone
+    /// [`unlock`]: struct.File.html#method.unlock
+    /// [`try_lock_exclusive`]: struct.File.html#method.try_lock_exclusive
+    #[inline]
+    pub fn lock_exclusive(&self) -> Result<()> {
+        self.lock(LockKind::Exclusive, true)
+    }
+
+    /// Acquires a shared advisory lock on the file, blocking until the lock
+    /// can be acquired.
+    ///
+    /// More than one `File` can hold a shared lock at the same time, but not
+    /// when an exclusive lock is held. If this `File` already holds an
+    /// exclusive lock, it is converted to a shared lock.
+    ///
+    /// See [`lock_exclusive`] for more details.
+    ///
+    /// [`lock_exclusive`]: struct.File.html#method.lock_exclusive
+    #[inline]
+    pub fn lock_shared(&self) -> Result<()> {
+        self.lock(LockKind::Shared, true)
+    }
+
+    /// Tries to acquire an exclusive advisory lock on the file.
+    ///
+    /// Returns [`Error::Locked`] if the lock is held by others.
+    ///
+    /// [`Error::Locked`]: enum.Error.html
+    #[inline]
+    pub fn try_lock_exclusive(&self) -> Result<()> {
+        self.lock(LockKind::Exclusive, false)
+    }
+
+    /// Tries to acquire a shared advisory lock on the file.
+    ///
+    /// Returns [`Error::Locked`] if an exclusive lock is held by others.
+    ///
+    /// [`Error::Locked`]: enum.Error.html
+    #[inline]
+    pub fn try_lock_shared(&self) -> Result<()> {
+        self.lock(LockKind::Shared, false)
+    }
+
+    /// Releases the advisory lock held by this `File`.
+    ///
+    /// It does nothing if this `File` doesn't hold a lock.
+    pub fn unlock(&self) -> Result<()> {
+        self.check_closed()?;
+        self.release_lock();
+        Ok(())
+    }
+
+    /// Acquires an advisory lock on a byte range of the file, blocking until
+    /// the lock can be acquired.
+    ///
+    /// The range starts at `offset` and spans `len` bytes, zero `len` means
+    /// the range extends to the end of file, however large the file grows.
+    /// The range can go beyond the end of file. An exclusive range lock
+    /// conflicts with any lock held by others on an overlapping range, while
+    /// shared range locks can overlap with each other.
+    ///
+    /// Like POSIX record locks, range locks held by this `File` are replaced
+    /// by the new lock on the overlapped part, so a lock can be converted or
+    /// split. Range locks are independent of the whole-file locks acquired
+    /// by [`lock_exclusive`] and [`lock_shared`], and have the same scope and
+    /// lifetime. Deadlocks are not detected.
+    ///
+    /// Use [`try_lock_range`] to acquire the lock without blocking.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, Error};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let file = repo.create_file("/foo.txt")?;
+    /// let file2 = repo.open_file("/foo.txt")?;
+    ///
+    /// file.lock_range(0, 100, true)?;
+    /// file2.try_lock_range(100, 0, true)?;
+    /// assert_eq!(
+    ///     file2.try_lock_range(50, 100, false).unwrap_err(),
+    ///     Error::Locked
+    /// );
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`lock_exclusive`]: struct.File.html#method.lock_exclusive
+    /// [`lock_shared`]: struct.File.html#method.lock_shared
+    /// [`try_lock_range`]: struct.File.html#method.try_lock_range
+    #[inline]
+    pub fn lock_range(
+        &self,
+        offset: u64,
+        len: u64,
+        exclusive: bool,
+    ) -> Result<()> {
+        self.lock_range_with(offset, len, exclusive, true)
+    }
+
+    /// Tries to acquire an advisory lock on a byte range of the file.
+    ///
+    /// Returns [`Error::Locked`] if the lock conflicts with locks held by
+    /// others. See [`lock_range`] for more details.
+    ///
+    /// [`Error::Locked`]: enum.Error.html
+    /// [`lock_range`]: struct.File.html#method.lock_range
+    #[inline]
+    pub fn try_lock_range(
+        &self,
+        offset: u64,
+        len: u64,
+        exclusive: bool,
+    ) -> Result<()> {
+        self.lock_range_with(offset, len, exclusive, false)
+    }
+
+    /// Releases the advisory lock held by this `File` on a byte range.
+    ///
+    /// The range is specified in the same way as [`lock_range`]. Only the
+    /// part of locks within the range is released, it does nothing if no
+    /// lock is held on the range.
+    ///
+    /// [`lock_range`]: struct.File.html#method.lock_range
+    pub fn unlock_range(&self, offset: u64, len: u64) -> Result<()> {
+        self.check_closed()?;
+        let id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle
+            .locks
+            .unlock_range(&id, self.lock_owner, offset, len);
+        Ok(())
+    }
+
+    fn lock(&self, kind: LockKind, wait: bool) -> Result<()> {
+        self.check_closed()?;
+        let id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle.locks.lock(&id, self.lock_owner, kind, wait)
+    }
+
+    fn lock_range_with(
+        &self,
+        offset: u64,
+        len: u64,
+        exclusive: bool,
+        wait: bool,
+    ) -> Result<()> {
+        self.check_closed()?;
+        let kind = if exclusive {
+            LockKind::Exclusive
+        } else {
+            LockKind::Shared
+        };
+        let id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle.locks.lock_range(
+            &id,
+            self.lock_owner,
+            kind,
+            offset,
+            len,
+            wait,
+        )
+    }
+
+    fn release_lock(&self) {
+        let id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle.locks.unlock(&id, self.lock_owner);
+    }
+
+    /// Links a temporary file into the directory tree at the specified path.
+    ///
+    /// The file created by [`Repo::create_temp_file`] is given a path, so it
+    /// won't be removed when it is dropped. If `path` already exists, it is
+    /// replaced like [`Repo::rename`] does, so a file can be written to a
+    /// temporary file first and then replace the old one in one step.
+    ///
+    /// `path` must be an absolute path and its parent directory must exist.
+    /// This file can still be used after it is persisted.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if this is not a temporary file or
+    /// it has already been persisted, and [`Error::NotFinish`] if multi-part
+    /// write is not finished.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"old content")?;
+    ///
+    /// let mut file = repo.create_temp_file()?;
+    /// file.write_once(b"new content")?;
+    /// file.persist("/foo.txt")?;
+    /// assert_eq!(repo.metadata("/foo.txt")?.content_len(), 11);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::create_temp_file`]: struct.Repo.html#method.create_temp_file
+    /// [`Repo::rename`]: struct.Repo.html#method.rename
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    /// [`Error::NotFinish`]: enum.Error.html
+    pub fn persist<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace(|_, fid| Op::Persist {
+            fid,
+            path: path.as_ref().to_path_buf(),
+        });
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        let fs = match self.temp {
+            Some(ref temp) => temp.fs.clone(),
+            None => return Err(Error::InvalidArgument),
+        };
+        let fs = fs.upgrade().ok_or(Error::RepoClosed)?;
+        fs.write()
+            .unwrap()
+            .persist_temp(&self.handle.fnode, path.as_ref())?;
+        if let Some(mut temp) = self.temp.take() {
+            temp.persisted = true;
+        }
+        Ok(())
+    }
+
+    /// Reads bytes from the current version at the given offset.
+    ///
+    /// Returns the number of bytes read, which can be less than the buffer
+    /// size, and `0` if `offset` is at or beyond the end of file.
+    ///
+    /// Unlike [`Read`], this method doesn't change the file cursor, so it
+    /// can be used to read from clones of a file in multiple threads, see
+    /// [`try_clone`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_once(b"Hello, world!")?;
+    ///
+    /// let mut buf = [0u8; 5];
+    /// assert_eq!(file.read_at(&mut buf, 7)?, 5);
+    /// assert_eq!(&buf, b"world");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+    /// [`try_clone`]: struct.File.html#method.try_clone
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        let mut rdr = FnodeReader::new_current(
+            self.handle.fnode.clone(),
+            &self.handle.store,
+        )?;
+        rdr.seek(SeekFrom::Start(offset))?;
+        let read = rdr.read(buf)?;
+        Ok(read)
+    }
+
+    /// Reads a block of content at the given offset without copying.
+    ///
+    /// File content is stored as extents of contiguous data, this method
+    /// returns a reference to the data in the extent containing `offset`,
+    /// starting at `offset` and ending at the end of that extent. An extent
+    /// can span multiple data chunks written together. Calling this method
+    /// repeatedly with the offset after the returned block iterates through
+    /// the whole content. An empty block is returned if `offset` is at or
+    /// beyond the end of file.
+    ///
+    /// Like [`read_at`], this method doesn't change the file cursor and it
+    /// reads the current version of the file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// # repo.create_file("/foo.txt")?.write_once(b"Hello, world!")?;
+    /// let file = repo.open_file("/foo.txt")?;
+    ///
+    /// let mut offset = 0;
+    /// let mut content = Vec::new();
+    /// loop {
+    ///     let blk = file.read_block_ref(offset)?;
+    ///     if blk.is_empty() {
+    ///         break;
+    ///     }
+    ///     content.extend_from_slice(&blk);
+    ///     offset += blk.len() as u64;
+    /// }
+    /// assert_eq!(content, b"Hello, world!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`read_at`]: struct.File.html#method.read_at
+    pub fn read_block_ref(&self, offset: u64) -> Result<BlockRef<'_>> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let fnode = self.handle.fnode.read().unwrap();
+        let (data, start, end) = fnode
+            .block_at(offset as usize, &store)?
+            .unwrap_or_else(|| (Arc::default(), 0, 0));
+        Ok(BlockRef {
+            data,
+            start,
+            end,
+            _file: PhantomData,
+        })
+    }
+
+    /// Writes a buffer at the given offset and create a new version.
+    ///
+    /// All bytes in the buffer are written and its length is returned. If
+    /// `offset` is beyond the end of file, the gap is filled with 0s.
+    ///
+    /// Unlike [`Write`], this method doesn't change the file cursor, so it
+    /// can be used to write to clones of a file in multiple threads, see
+    /// [`try_clone`]. Writes from different threads are applied one after
+    /// another, each creates a new version.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file is not opened for writing
+    /// or not finished writing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_once(b"Hello, world!")?;
+    ///
+    /// file.write_at(b"zbox!", 7)?;
+    /// let mut buf = [0u8; 13];
+    /// file.read_at(&mut buf, 0)?;
+    /// assert_eq!(&buf, b"Hello, zbox!!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`try_clone`]: struct.File.html#method.try_clone
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.trace(|tr, fid| Op::write_at(tr, fid, buf, offset));
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let mut wtr =
+                FnodeWriter::new(self.handle.clone(), tx_handle.txid)?;
+
+            // append zeros if offset is beyond EOF
+            let curr_len = self.curr_len() as u64;
+            if offset > curr_len {
+                wtr.seek(SeekFrom::Start(curr_len))?;
+                let mut zeros = io::repeat(0).take(offset - curr_len);
+                io::copy(&mut zeros, &mut wtr)?;
+            } else {
+                wtr.seek(SeekFrom::Start(offset))?;
+            }
+
+            wtr.write_all(buf)?;
+            wtr.finish()?;
+            Ok(())
+        })?;
+        self.handle.notify_modify();
+        self.use_reserved(buf.len());
+
+        Ok(buf.len())
+    }
+
+    // write all data from a reader as a new version, which replaces the whole
+    // current content, return the number of bytes written
+    pub(crate) fn write_version<R: Read>(
+        &mut self,
+        src: &mut R,
+    ) -> Result<u64> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        let mut len = 0;
+        tx_handle.run_all_exclusive(|| {
+            let mut wtr =
+                FnodeWriter::new(self.handle.clone(), tx_handle.txid)?;
+            len = io::copy(src, &mut wtr)?;
+            wtr.finish_replace()?;
+            Ok(())
+        })?;
+        self.handle.notify_modify();
+        self.use_reserved(len as usize);
+        self.pos = SeekFrom::Start(len);
+
+        // re-create reader if there is an existing reader
+        if self.rdr.is_some() {
+            self.renew_reader()?;
+        }
+
+        Ok(len)
+    }
 }
 
 impl Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.trace(|_, fid| Op::Read {
+            fid,
+            len: buf.len(),
+        });
         map_io_err!(self.check_closed())?;
         if !self.can_read {
             return Err(IoError::new(
@@ -587,37 +1855,79 @@ impl Read for File {
             ));
         }
 
-        // if reader is not created yet, create a new reader and seek to
-        // the current file position
-        if self.rdr.is_none() {
+        // if reader is not created yet or not on the latest version, create
+        // a new reader and seek to the current file position
+        let is_stale = match self.rdr {
+            Some(ref rdr) => {
+                let fnode = self.handle.fnode.read().unwrap();
+                rdr.version_num() != fnode.curr_ver_num()
+            }
+            None => true,
+        };
+        if is_stale {
             map_io_err!(self.renew_reader())?;
         }
 
-        match self.rdr {
+        let read = match self.rdr {
             Some(ref mut rdr) => {
                 let read = rdr.read(buf)?;
                 let new_pos = rdr.seek(SeekFrom::Current(0)).unwrap();
                 self.pos = SeekFrom::Start(new_pos);
-                Ok(read)
+                read
             }
             None => unreachable!(),
+        };
+
+        // access time is updated at most once for each opened file, failure
+        // is not fatal, for example, there is a pending write in this thread
+        if self.handle.atime && !self.accessed {
+            self.accessed = true;
+            if let Err(err) = self.touch() {
+                debug!("update access time failed: {}", err);
+            }
+        }
+
+        Ok(read)
+    }
+
+    // fill buffers in order until reaching the end of file
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let mut read = 0;
+        for buf in bufs.iter_mut().filter(|buf| !buf.is_empty()) {
+            let len = self.read(buf)?;
+            read += len;
+            if len < buf.len() {
+                break;
+            }
         }
+        Ok(read)
     }
 }
 
-impl Write for File {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+impl File {
+    // write buffers in order, stop at the first buffer which is partially
+    // written, return the total number of bytes written
+    fn write_bufs<B: Deref<Target = [u8]>>(
+        &mut self,
+        bufs: &[B],
+    ) -> io::Result<usize> {
         map_io_err!(self.check_closed())?;
         if self.wtr.is_none() {
             map_io_err!(self.begin_write())?;
         }
 
         let mut ret = 0;
-        map_io_err!(match self.wtr {
+        let written = map_io_err!(match self.wtr {
             Some(ref mut wtr) => match self.tx_handle {
                 Some(ref tx_handle) => tx_handle
                     .run(|| {
-                        ret = wtr.write(buf)?;
+                        for buf in bufs.iter() {
+                            let written = wtr.write(buf)?;
+                            ret += written;
+                            if written < buf.len() {
+                                break;
+                            }
+                        }
                         Ok(())
                     })
                     .map(|_| ret),
@@ -631,7 +1941,26 @@ impl Write for File {
             self.wtr.take();
             self.tx_handle.take();
             Err(err)
-        }))
+        }))?;
+        self.use_reserved(written);
+        Ok(written)
+    }
+}
+
+impl Write for File {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.trace(|tr, fid| Op::write(tr, fid, buf));
+        self.write_bufs(&[buf])
+    }
+
+    // buffers are written in order directly, so records assembled from
+    // multiple buffers don't need to be copied to a contiguous buffer first
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            self.trace(|tr, fid| Op::write(tr, fid, buf));
+        }
+        self.write_bufs(bufs)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -671,12 +2000,43 @@ impl Seek for File {
         };
 
         match self.pos {
-            SeekFrom::Start(pos) => Ok(pos),
+            SeekFrom::Start(pos) => {
+                self.trace(|_, fid| Op::Seek { fid, pos });
+                Ok(pos)
+            }
             _ => unreachable!(),
         }
     }
 }
 
+impl Drop for File {
+    fn drop(&mut self) {
+        // finish pending write in auto commit mode, otherwise or if thread
+        // is panicking, abort it
+        if self.wtr.is_some() {
+            if self.auto_commit && !thread::panicking() {
+                if let Err(err) = self.finish_inner() {
+                    warn!("finish pending write failed: {}", err);
+                }
+            } else {
+                self.wtr.take();
+                if let Some(tx_handle) = self.tx_handle.take() {
+                    if let Err(err) = tx_handle.rollback() {
+                        warn!("abort pending write failed: {}", err);
+                    }
+                }
+            }
+        }
+
+        let reserved = self.reserved.swap(0, Ordering::SeqCst);
+        self.release_reserved(reserved);
+
+        self.trace(|_, fid| Op::Close { fid });
+        let id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle.locks.unlock_all(&id, self.lock_owner);
+    }
+}
+
 impl Debug for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("File")
@@ -685,6 +2045,7 @@ impl Debug for File {
             .field("wtr", &self.wtr)
             .field("can_read", &self.can_read)
             .field("can_write", &self.can_write)
+            .field("temp", &self.temp.is_some())
             .finish()
     }
 }
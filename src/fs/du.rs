@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+
+use super::fnode::{Cache as FnodeCache, Fnode, FnodeRef};
+use content::StoreRef;
+use error::Result;
+use trans::Eid;
+use volume::VolumeRef;
+
+/// Disk usage of a directory tree or a file.
+///
+/// See [`Repo::du`] for more details.
+///
+/// [`Repo::du`]: struct.Repo.html#method.du
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    logical_size: u64,
+    version_size: u64,
+    physical_size: u64,
+    files: usize,
+    dirs: usize,
+}
+
+impl DiskUsage {
+    /// Returns total size of current versions of all files, in bytes.
+    #[inline]
+    pub fn logical_size(&self) -> u64 {
+        self.logical_size
+    }
+
+    /// Returns total size of all previous versions of files, in bytes.
+    ///
+    /// This is the overhead of keeping file version history.
+    #[inline]
+    pub fn version_overhead(&self) -> u64 {
+        self.version_size
+    }
+
+    /// Returns size of deduplicated data referred by all versions of files,
+    /// in bytes.
+    ///
+    /// Data shared by multiple versions or files is only counted once. This
+    /// is the size before compression and doesn't include encryption
+    /// overhead.
+    #[inline]
+    pub fn physical_size(&self) -> u64 {
+        self.physical_size
+    }
+
+    /// Returns number of files, including symbolic links and special files.
+    #[inline]
+    pub fn file_count(&self) -> usize {
+        self.files
+    }
+
+    /// Returns number of directories, including the directory itself.
+    #[inline]
+    pub fn dir_count(&self) -> usize {
+        self.dirs
+    }
+}
+
+// disk usage counter
+pub(super) struct UsageCounter<'a> {
+    store: &'a StoreRef,
+    fcache: &'a FnodeCache,
+    vol: &'a VolumeRef,
+
+    usage: DiskUsage,
+
+    // contents and chunks already counted, they can be shared
+    ctns: HashSet<Eid>,
+    chunks: HashSet<(Eid, usize)>,
+
+    // chunk lengths of loaded segments
+    seg_lens: HashMap<Eid, Vec<usize>>,
+}
+
+impl<'a> UsageCounter<'a> {
+    pub fn new(
+        store: &'a StoreRef,
+        fcache: &'a FnodeCache,
+        vol: &'a VolumeRef,
+    ) -> Self {
+        UsageCounter {
+            store,
+            fcache,
+            vol,
+            usage: DiskUsage::default(),
+            ctns: HashSet::new(),
+            chunks: HashSet::new(),
+            seg_lens: HashMap::new(),
+        }
+    }
+
+    // count unique chunks referred by a content
+    fn count_content(&mut self, ctn_id: &Eid) -> Result<()> {
+        if !self.ctns.insert(ctn_id.clone()) {
+            return Ok(());
+        }
+
+        let mut refs = HashMap::new();
+        {
+            let store = self.store.read().unwrap();
+            let ctn_ref = store.get_content(ctn_id)?;
+            let ctn = ctn_ref.read().unwrap();
+            ctn.count_chunk_refs(&mut refs);
+        }
+
+        for (seg_id, idx) in refs.into_keys() {
+            if self.chunks.contains(&(seg_id.clone(), idx)) {
+                continue;
+            }
+            if !self.seg_lens.contains_key(&seg_id) {
+                let store = self.store.read().unwrap();
+                let lens = store.seg_chunk_lens(&seg_id)?;
+                self.seg_lens.insert(seg_id.clone(), lens);
+            }
+            let len = self.seg_lens[&seg_id].get(idx).cloned().unwrap_or(0);
+            self.usage.physical_size += len as u64;
+            self.chunks.insert((seg_id, idx));
+        }
+
+        Ok(())
+    }
+
+    // count disk usage of the tree under a fnode
+    pub fn count(mut self, fnode_ref: &FnodeRef) -> Result<DiskUsage> {
+        let mut stack = vec![fnode_ref.clone()];
+        while let Some(fnode_ref) = stack.pop() {
+            let is_dir = fnode_ref.read().unwrap().is_dir();
+            if is_dir {
+                self.usage.dirs += 1;
+                let names = fnode_ref.read().unwrap().children_names();
+                for name in names {
+                    let child =
+                        Fnode::child(&fnode_ref, &name, self.fcache, self.vol)?;
+                    stack.push(child);
+                }
+                continue;
+            }
+
+            self.usage.files += 1;
+            let vers = fnode_ref.read().unwrap().history();
+            if let Some((curr, prevs)) = vers.split_last() {
+                self.usage.logical_size += curr.content_len() as u64;
+                for ver in prevs {
+                    self.usage.version_size += ver.content_len() as u64;
+                }
+            }
+            for ver in vers.iter() {
+                self.count_content(ver.content_id())?;
+            }
+        }
+
+        Ok(self.usage)
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::fnode::{Cache as FnodeCache, Fnode, FnodeRef};
+use content::StoreRef;
+use error::Result;
+use volume::VolumeRef;
+
+// metadata exporter, which writes metadata of each entry in a tree as a line
+// of JSON object
+pub(super) struct MetaExporter<'a> {
+    store: &'a StoreRef,
+    fcache: &'a FnodeCache,
+    vol: &'a VolumeRef,
+}
+
+impl<'a> MetaExporter<'a> {
+    pub fn new(
+        store: &'a StoreRef,
+        fcache: &'a FnodeCache,
+        vol: &'a VolumeRef,
+    ) -> Self {
+        MetaExporter { store, fcache, vol }
+    }
+
+    // write metadata of one entry
+    fn export_entry(
+        &self,
+        fnode_ref: &FnodeRef,
+        path: &Path,
+        w: &mut dyn Write,
+    ) -> Result<()> {
+        let fnode = fnode_ref.read().unwrap();
+        let meta = fnode.metadata();
+        let ftype: String = meta.file_type().into();
+
+        let mut line = String::from("{\"path\":");
+        push_str(&mut line, &path.to_string_lossy());
+        line.push_str(",\"type\":");
+        push_str(&mut line, &ftype);
+        line.push_str(&format!(
+            ",\"size\":{},\"ctime\":{},\"mtime\":{}",
+            meta.content_len(),
+            unix_time(meta.created_at()),
+            unix_time(meta.modified_at())
+        ));
+
+        if meta.is_file() {
+            let vers = fnode.history();
+            line.push_str(&format!(",\"versions\":{}", vers.len()));
+            if let Some(ver) = vers.last() {
+                let store = self.store.read().unwrap();
+                let ctn_ref = store.get_content(ver.content_id())?;
+                let ctn = ctn_ref.read().unwrap();
+                line.push_str(",\"hash\":");
+                push_str(&mut line, &ctn.hash().to_string());
+            }
+        }
+        line.push_str("}\n");
+
+        w.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    // export metadata of all entries in the tree under a fnode, parent
+    // directories come before their children
+    pub fn export(
+        &self,
+        fnode_ref: &FnodeRef,
+        path: &Path,
+        w: &mut dyn Write,
+    ) -> Result<()> {
+        let mut stack = vec![(fnode_ref.clone(), path.to_path_buf())];
+        while let Some((fnode_ref, path)) = stack.pop() {
+            self.export_entry(&fnode_ref, &path, w)?;
+
+            let is_dir = fnode_ref.read().unwrap().is_dir();
+            if is_dir {
+                let mut names = fnode_ref.read().unwrap().children_names();
+                names.sort();
+                for name in names.iter().rev() {
+                    let child =
+                        Fnode::child(&fnode_ref, name, self.fcache, self.vol)?;
+                    stack.push((child, path.join(name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// append a string as JSON string literal
+fn push_str(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                line.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+// format time as seconds since unix epoch with fractional part
+fn unix_time(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", d.as_secs(), d.subsec_nanos())
+}
@@ -1,34 +1,55 @@
 #![allow(clippy::module_inception)]
 
 use std::cmp::min;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{self, Debug};
 use std::io::{Read, Result as IoResult, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::SystemTime;
 
-use super::{Handle, Options};
+use super::{DirOptions, Handle, Options};
+use base::crypto::Hash;
 use base::lru::{CountMeter, Lru, PinChecker};
 use base::Time;
 use content::{
-    ChunkMap, Content, ContentReader, Store, StoreRef, StoreWeakRef,
+    ChunkMap, Content, ContentReader, DataBlock, Store, StoreRef, StoreWeakRef,
     Writer as StoreWriter,
 };
 use error::{Error, Result};
 use trans::cow::{Cow, CowCache, CowRef, CowWeakRef, Cowable, IntoCow};
 use trans::trans::{Action, Transable};
-use trans::{Eid, Id, TxMgrRef, Txid};
+use trans::{Eid, EntryCounts, Id, TxMgrRef, Txid};
 use volume::VolumeRef;
 
 // maximum sub nodes for a fnode
 const SUB_NODES_CNT: usize = 8;
 
+// access time is only updated when it is older than modification time or
+// older than this interval, in seconds
+const ATIME_INTERVAL: u64 = 24 * 60 * 60;
+
+// get change sequence number from current transaction id, it is monotonic
+// across the whole repo
+#[inline]
+fn change_seq() -> u64 {
+    Txid::current_or_empty().val()
+}
+
 /// A structure representing a type of file with accessors for each file type.
+///
+/// Besides regular file and directory, special file types such as symbolic
+/// link, FIFO, socket and device node can also be stored. Those special files
+/// have no content, only their attributes are kept in metadata.
 #[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
 pub enum FileType {
     File,
     Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
 }
 
 impl FileType {
@@ -41,6 +62,23 @@ impl FileType {
     pub fn is_dir(self) -> bool {
         self == FileType::Dir
     }
+
+    /// Test whether this file type represents a symbolic link.
+    pub fn is_symlink(self) -> bool {
+        self == FileType::Symlink
+    }
+
+    /// Test whether this file type represents a special file, that is,
+    /// FIFO, socket or device node.
+    pub fn is_special(self) -> bool {
+        match self {
+            FileType::Fifo
+            | FileType::Socket
+            | FileType::CharDevice
+            | FileType::BlockDevice => true,
+            _ => false,
+        }
+    }
 }
 
 impl Default for FileType {
@@ -54,6 +92,11 @@ impl Into<i32> for FileType {
         match self {
             FileType::File => 0,
             FileType::Dir => 1,
+            FileType::Symlink => 2,
+            FileType::Fifo => 3,
+            FileType::Socket => 4,
+            FileType::CharDevice => 5,
+            FileType::BlockDevice => 6,
         }
     }
 }
@@ -63,6 +106,11 @@ impl Into<String> for FileType {
         match self {
             FileType::File => String::from("File"),
             FileType::Dir => String::from("Dir"),
+            FileType::Symlink => String::from("Symlink"),
+            FileType::Fifo => String::from("Fifo"),
+            FileType::Socket => String::from("Socket"),
+            FileType::CharDevice => String::from("CharDevice"),
+            FileType::BlockDevice => String::from("BlockDevice"),
         }
     }
 }
@@ -92,15 +140,24 @@ pub struct Version {
     content_id: Eid, // content id
     content_len: usize,
     ctime: Time,
+    #[serde(default)]
+    seq: u64, // repo-wise change sequence number
+    #[serde(default)]
+    content_hash: Option<Hash>,
+    #[serde(default)]
+    tag: Option<String>,
 }
 
 impl Version {
-    fn new(num: usize, content_id: &Eid, len: usize) -> Self {
+    fn new(num: usize, content_id: &Eid, content: &Content) -> Self {
         Version {
             num,
             content_id: content_id.clone(),
-            content_len: len,
+            content_len: content.len(),
             ctime: Time::now(),
+            seq: change_seq(),
+            content_hash: Some(content.hash().clone()),
+            tag: None,
         }
     }
 
@@ -116,10 +173,158 @@ impl Version {
         self.content_len
     }
 
+    // content id of this version
+    #[inline]
+    pub(super) fn content_id(&self) -> &Eid {
+        &self.content_id
+    }
+
     /// Returns the creation time of this version of content.
     pub fn created_at(&self) -> SystemTime {
         self.ctime.to_system_time()
     }
+
+    /// Returns the change sequence number when this version was created.
+    ///
+    /// The sequence number monotonically increases across the whole
+    /// repository, it can be used to order versions which are created at the
+    /// same time. Versions created before this number was introduced have
+    /// sequence number zero.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the cryptographic digest of this version of content.
+    ///
+    /// The digest is the root of a BLAKE2b Merkle tree built on the content
+    /// and it doesn't depend on the repository key, so same content always
+    /// has same digest, even in different repositories. It can be used to
+    /// compare files without reading their content.
+    ///
+    /// Returns `None` for versions created by ZboxFS before this digest was
+    /// recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut repo2 = RepoOpener::new().create(true).open("mem://bar", "pwd2")?;
+    /// repo.create_file("/foo")?.write_once(b"Hello")?;
+    /// repo2.create_file("/bar")?.write_once(b"Hello")?;
+    ///
+    /// let ver = repo.history("/foo")?.pop().unwrap();
+    /// let ver2 = repo2.history("/bar")?.pop().unwrap();
+    /// assert!(ver.content_hash().is_some());
+    /// assert_eq!(ver.content_hash(), ver2.content_hash());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn content_hash(&self) -> Option<&[u8]> {
+        self.content_hash.as_ref().map(|hash| &hash[..])
+    }
+
+    /// Returns the tag attached to this version, if any.
+    ///
+    /// See [`File::tag_version`] for more details.
+    ///
+    /// [`File::tag_version`]: struct.File.html#method.tag_version
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_ref().map(String::as_str)
+    }
+}
+
+/// POSIX permission bits and ownership of a file.
+///
+/// Zbox doesn't enforce these attributes, they are only stored along with
+/// files so that they can be round-tripped when a repository is used as a
+/// backup target of a file system. Only the lower 12 bits of `mode`, which
+/// are permission bits, setuid, setgid and sticky bits, are kept.
+///
+/// On Unix, it can be converted from `std::fs::Metadata`.
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize,
+)]
+pub struct Permissions {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl Permissions {
+    // mask for permission bits, setuid, setgid and sticky bits
+    const MODE_MASK: u32 = 0o7777;
+
+    /// Creates permissions from mode bits, user ID and group ID.
+    pub fn new(mode: u32, uid: u32, gid: u32) -> Self {
+        Permissions {
+            mode: mode & Self::MODE_MASK,
+            uid,
+            gid,
+        }
+    }
+
+    /// Returns the permission mode bits, such as `0o644`.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Returns the owner user ID.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the owner group ID.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+}
+
+#[cfg(unix)]
+impl<'a> From<&'a ::std::fs::Metadata> for Permissions {
+    fn from(md: &'a ::std::fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Permissions::new(md.mode(), md.uid(), md.gid())
+    }
+}
+
+/// Byte quota of a directory.
+///
+/// Usage of a directory is the total size of all retained versions of files
+/// in its directory tree, before deduplication and compression. Writes which
+/// would make usage exceed the quota limit fail with
+/// [`Error::QuotaExceeded`].
+///
+/// See [`Repo::set_quota`] for more details.
+///
+/// [`Error::QuotaExceeded`]: enum.Error.html
+/// [`Repo::set_quota`]: struct.Repo.html#method.set_quota
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct Quota {
+    limit: u64,
+    used: u64,
+}
+
+impl Quota {
+    #[inline]
+    pub(super) fn new(limit: u64, used: u64) -> Self {
+        Quota { limit, used }
+    }
+
+    /// Returns the quota limit, in bytes.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns the current usage, in bytes.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
 }
 
 /// Metadata information about a file or a directory.
@@ -135,8 +340,12 @@ pub struct Metadata {
     ftype: FileType,
     content_len: usize,
     curr_version: usize,
+    rdev: u64,
+    perms: Option<Permissions>,
     ctime: Time,
     mtime: Time,
+    atime: Option<Time>,
+    seq: u64,
 }
 
 impl Metadata {
@@ -155,6 +364,29 @@ impl Metadata {
         self.ftype == FileType::File
     }
 
+    /// Returns whether this metadata is for a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.ftype == FileType::Symlink
+    }
+
+    /// Returns the device ID listed in this metadata.
+    ///
+    /// This is only meaningful for device node, for other file types it is
+    /// always zero.
+    pub fn rdev(&self) -> u64 {
+        self.rdev
+    }
+
+    /// Returns the permissions listed in this metadata.
+    ///
+    /// This returns `None` if permissions have never been set, see
+    /// [`Repo::set_permissions`].
+    ///
+    /// [`Repo::set_permissions`]: struct.Repo.html#method.set_permissions
+    pub fn permissions(&self) -> Option<Permissions> {
+        self.perms
+    }
+
     /// Returns the size of the current version of file, in bytes, this
     /// metadata is for.
     pub fn content_len(&self) -> usize {
@@ -175,6 +407,62 @@ impl Metadata {
     pub fn modified_at(&self) -> SystemTime {
         self.mtime.to_system_time()
     }
+
+    /// Returns the last access time listed in this metadata.
+    ///
+    /// Access time is only tracked when it is enabled by
+    /// [`RepoOpener::atime`]. This returns `None` if the file has never been
+    /// read while access time tracking is enabled.
+    ///
+    /// [`RepoOpener::atime`]: struct.RepoOpener.html#method.atime
+    pub fn accessed_at(&self) -> Option<SystemTime> {
+        self.atime.map(|t| t.to_system_time())
+    }
+
+    /// Returns the change sequence number of the last modification listed in
+    /// this metadata.
+    ///
+    /// The sequence number monotonically increases across the whole
+    /// repository, see [`Version::seq`].
+    ///
+    /// [`Version::seq`]: struct.Version.html#method.seq
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+/// Deduplication information about a file.
+///
+/// This structure is returned from the [`File::dedup_info`] and reports how
+/// the file's data chunks are shared with other files and versions.
+///
+/// [`File::dedup_info`]: struct.File.html#method.dedup_info
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DedupInfo {
+    chunks: usize,
+    shared_chunks: usize,
+    exclusive_size: usize,
+}
+
+impl DedupInfo {
+    /// Returns the number of distinct data chunks in current version.
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    /// Returns the number of data chunks in current version which are also
+    /// used by other versions or other files.
+    pub fn shared_chunks(&self) -> usize {
+        self.shared_chunks
+    }
+
+    /// Returns the size of data chunks, in bytes, which are only used by
+    /// this file, including all of its versions.
+    ///
+    /// This is the amount of data would be reclaimed if this file is deleted.
+    pub fn exclusive_size(&self) -> usize {
+        self.exclusive_size
+    }
 }
 
 /// Entries returned by the [`read_dir`] function.
@@ -207,6 +495,12 @@ impl DirEntry {
     pub fn metadata(&self) -> Metadata {
         self.metadata
     }
+
+    // replace metadata, used when a symbolic link is followed
+    #[inline]
+    pub(super) fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
 }
 
 type SubNodes = Lru<
@@ -227,6 +521,42 @@ pub struct Fnode {
     vers: VecDeque<Version>,
     chk_map: ChunkMap,
 
+    // directory default options
+    #[serde(default)]
+    dir_opts: DirOptions,
+
+    // device ID for device node
+    #[serde(default)]
+    rdev: u64,
+
+    // target path for symbolic link
+    #[serde(default)]
+    link_target: Option<PathBuf>,
+
+    // POSIX permissions and ownership
+    #[serde(default)]
+    perms: Option<Permissions>,
+
+    // directory byte quota
+    #[serde(default)]
+    quota: Option<Quota>,
+
+    // change sequence number of last modification
+    #[serde(default)]
+    seq: u64,
+
+    // last access time
+    #[serde(default)]
+    atime: Option<Time>,
+
+    // key domain whose key encrypts file content, inherited from parent
+    #[serde(default)]
+    key_domain: Option<Eid>,
+
+    // key check of the key domain, only set on the domain root directory
+    #[serde(default)]
+    domain_check: Option<Vec<u8>>,
+
     // parent fnode
     #[serde(skip_serializing, skip_deserializing, default)]
     parent: Option<FnodeRef>,
@@ -249,42 +579,62 @@ impl Fnode {
             kids: Vec::new(),
             vers: VecDeque::new(),
             chk_map: ChunkMap::new(opts.dedup_chunk),
+            dir_opts: DirOptions::default(),
+            rdev: 0,
+            link_target: None,
+            perms: None,
+            quota: None,
+            seq: change_seq(),
+            atime: None,
+            key_domain: None,
+            domain_check: None,
             parent: None,
             sub_nodes: Self::default_sub_nodes(),
         }
     }
 
-    /// Create new fnode under parent
+    /// Create new fnode under parent in a key domain
     pub fn new_under(
         parent: &FnodeRef,
         name: &str,
         ftype: FileType,
         opts: Options,
+        domain: Option<Eid>,
         txmgr: &TxMgrRef,
         store: &StoreRef,
     ) -> Result<FnodeRef> {
-        let kid = {
+        {
             let mut pfnode_cow = parent.write().unwrap();
             let pfnode = pfnode_cow.make_mut(txmgr)?;
             if !pfnode.is_dir() {
                 return Err(Error::NotDir);
             }
+        }
 
-            // create child fnode and add the initial version
-            let mut kid = Fnode::new(ftype, opts);
-            if kid.is_file() {
-                kid.add_version(Content::new(), store, txmgr)?;
-            }
-
-            kid.into_cow(txmgr)?
-        };
-
-        // add child to parent
+        // create child fnode and add it to parent
+        let kid = Fnode::new_detached(ftype, opts, domain, txmgr, store)?;
         Fnode::add_child(parent, &kid, name, txmgr)?;
 
         Ok(kid)
     }
 
+    /// Create new fnode without parent in a key domain, the initial version
+    /// is added if it is a regular file
+    pub fn new_detached(
+        ftype: FileType,
+        opts: Options,
+        domain: Option<Eid>,
+        txmgr: &TxMgrRef,
+        store: &StoreRef,
+    ) -> Result<FnodeRef> {
+        let mut fnode = Fnode::new(ftype, opts);
+        fnode.key_domain = domain;
+        if fnode.is_file() {
+            fnode.add_version(Content::new(), store, txmgr)?;
+        }
+        fnode.into_cow(txmgr)
+    }
+
     #[inline]
     fn default_sub_nodes() -> SubNodes {
         Lru::new(SUB_NODES_CNT)
@@ -302,6 +652,12 @@ impl Fnode {
         self.ftype == FileType::Dir
     }
 
+    /// Check if fnode is symbolic link
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.ftype == FileType::Symlink
+    }
+
     /// Check if fnode is root
     #[inline]
     pub fn is_root(&self) -> bool {
@@ -314,8 +670,12 @@ impl Fnode {
             ftype: self.ftype,
             content_len: self.curr_len(),
             curr_version: self.curr_ver_num(),
+            rdev: self.rdev,
+            perms: self.perms,
             ctime: self.ctime,
             mtime: self.mtime,
+            atime: self.atime,
+            seq: self.seq,
         }
     }
 
@@ -324,10 +684,43 @@ impl Fnode {
     pub fn curr_len(&self) -> usize {
         match self.ftype {
             FileType::File => self.curr_ver().content_len(),
-            FileType::Dir => 0,
+            FileType::Symlink => self
+                .link_target
+                .as_ref()
+                .map(|target| target.as_os_str().len())
+                .unwrap_or(0),
+            _ => 0,
         }
     }
 
+    /// Get total size of all versions
+    #[inline]
+    pub fn versions_len(&self) -> u64 {
+        self.vers.iter().map(|v| v.content_len() as u64).sum()
+    }
+
+    /// Get entry counts of this fnode itself, excluding its children
+    pub fn entry_counts(&self) -> EntryCounts {
+        if self.is_dir() {
+            EntryCounts {
+                dirs: 1,
+                ..Default::default()
+            }
+        } else {
+            EntryCounts {
+                files: 1,
+                versions: self.versions_cnt() as i64,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Get number of versions
+    #[inline]
+    pub fn versions_cnt(&self) -> usize {
+        self.vers.len()
+    }
+
     /// Get fnode version list
     #[inline]
     pub fn history(&self) -> Vec<Version> {
@@ -340,6 +733,241 @@ impl Fnode {
         self.opts
     }
 
+    /// Get symbolic link target
+    #[inline]
+    pub fn get_link_target(&self) -> Option<&Path> {
+        self.link_target.as_ref().map(|target| target.as_path())
+    }
+
+    /// Set special file attributes
+    #[inline]
+    pub fn set_special_attrs(&mut self, rdev: u64, link_target: Option<&Path>) {
+        self.rdev = rdev;
+        self.link_target = link_target.map(|target| target.to_path_buf());
+        self.seq = change_seq();
+    }
+
+    /// Set permissions and ownership
+    #[inline]
+    pub fn set_permissions(&mut self, perms: Permissions) {
+        self.perms = Some(perms);
+        self.seq = change_seq();
+    }
+
+    /// Get directory quota
+    #[inline]
+    pub fn quota(&self) -> Option<Quota> {
+        self.quota
+    }
+
+    /// Set or remove directory quota
+    #[inline]
+    pub fn set_quota(&mut self, quota: Option<Quota>) {
+        self.quota = quota;
+        self.seq = change_seq();
+    }
+
+    /// Get key domain the fnode is in
+    #[inline]
+    pub fn key_domain(&self) -> Option<&Eid> {
+        self.key_domain.as_ref()
+    }
+
+    /// Get key check of key domain, only domain root has it
+    #[inline]
+    pub fn domain_check(&self) -> Option<&[u8]> {
+        self.domain_check.as_ref().map(|check| &check[..])
+    }
+
+    /// Make this directory the root of a key domain
+    #[inline]
+    pub fn set_key_domain(&mut self, domain: &Eid, check: Vec<u8>) {
+        self.key_domain = Some(domain.clone());
+        self.domain_check = Some(check);
+        self.seq = change_seq();
+    }
+
+    /// Check if fnode is in the tree under a root
+    pub fn is_under(fnode: &FnodeRef, root: &FnodeRef) -> bool {
+        let mut curr = Some(fnode.clone());
+        while let Some(node) = curr {
+            if Arc::ptr_eq(&node, root) {
+                return true;
+            }
+            curr = node.read().unwrap().parent.clone();
+        }
+        false
+    }
+
+    /// Check if fnode or any of its ancestors has quota
+    pub fn under_quota(fnode: &FnodeRef) -> bool {
+        let mut curr = Some(fnode.clone());
+        while let Some(node) = curr {
+            let node = node.read().unwrap();
+            if node.quota.is_some() {
+                return true;
+            }
+            curr = node.parent.clone();
+        }
+        false
+    }
+
+    /// Charge usage change of fnode to quotas of its ancestors, this must be
+    /// called in a transaction
+    ///
+    /// The change is also added to the repo total usage when the transaction
+    /// is committed. If usage increases beyond any quota, `QuotaExceeded`
+    /// error is returned.
+    pub fn charge_quota(
+        fnode: &FnodeRef,
+        delta: i64,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+
+        txmgr.read().unwrap().add_usage(delta)?;
+
+        let mut curr = fnode.read().unwrap().parent.clone();
+        while let Some(dir) = curr {
+            let mut dir_cow = dir.write().unwrap();
+            if let Some(quota) = dir_cow.quota {
+                let used = if delta < 0 {
+                    quota.used.saturating_sub(delta.unsigned_abs())
+                } else {
+                    quota.used + delta as u64
+                };
+                if delta > 0 && used > quota.limit {
+                    return Err(Error::QuotaExceeded);
+                }
+                dir_cow.make_mut(txmgr)?.quota = Some(Quota { used, ..quota });
+            }
+            curr = dir_cow.parent.clone();
+        }
+        Ok(())
+    }
+
+    /// Check if usage change of fnode fits in quotas of its ancestors
+    ///
+    /// Nothing is charged, `QuotaExceeded` error is returned if usage
+    /// increase would exceed any quota.
+    pub fn check_quota(fnode: &FnodeRef, delta: i64) -> Result<()> {
+        if delta <= 0 {
+            return Ok(());
+        }
+
+        let mut curr = fnode.read().unwrap().parent.clone();
+        while let Some(dir) = curr {
+            let dir = dir.read().unwrap();
+            if let Some(quota) = dir.quota {
+                if quota.used + delta as u64 > quota.limit {
+                    return Err(Error::QuotaExceeded);
+                }
+            }
+            curr = dir.parent.clone();
+        }
+        Ok(())
+    }
+
+    /// Get usage change if a new version of `len` bytes is added
+    ///
+    /// The version retired by version limit is taken into account, but not
+    /// versions expired by retention policy.
+    pub fn new_version_usage(&self, len: usize) -> i64 {
+        let mut delta = len as i64;
+        if self.vers.len() >= self.opts.version_limit as usize {
+            if let Some(ver) = self.vers.front() {
+                delta -= ver.content_len() as i64;
+            }
+        }
+        delta
+    }
+
+    /// Set creation and modification time, `None` leaves it unchanged
+    pub fn set_times(&mut self, ctime: Option<Time>, mtime: Option<Time>) {
+        if let Some(ctime) = ctime {
+            self.ctime = ctime;
+        }
+        if let Some(mtime) = mtime {
+            self.mtime = mtime;
+        }
+        self.seq = change_seq();
+    }
+
+    // check if access time is stale and should be updated, it is stale if
+    // it is older than the last modification or not updated for a day
+    pub fn is_atime_stale(&self) -> bool {
+        match self.atime {
+            Some(atime) => {
+                atime.to_system_time() < self.mtime.to_system_time()
+                    || atime.elapsed().as_secs() >= ATIME_INTERVAL
+            }
+            None => true,
+        }
+    }
+
+    /// Update access time to now
+    #[inline]
+    pub fn touch(&mut self) {
+        self.atime = Some(Time::now());
+    }
+
+    // update modified time and change sequence number
+    #[inline]
+    fn set_modified(&mut self, mtime: Time) {
+        self.mtime = mtime;
+        self.seq = change_seq();
+    }
+
+    /// Get directory default options
+    #[inline]
+    pub fn get_dir_opts(&self) -> DirOptions {
+        self.dir_opts
+    }
+
+    /// Set directory default options
+    #[inline]
+    pub fn set_dir_opts(&mut self, dir_opts: DirOptions) {
+        self.dir_opts = dir_opts;
+        self.seq = change_seq();
+    }
+
+    /// Copy options, times, permissions and directory options from another
+    /// fnode
+    ///
+    /// Chunk deduplication option is kept as chunk map has been created by
+    /// it. Quota, key domain and versions are not copied.
+    pub fn copy_attrs(&mut self, src: &Fnode) {
+        self.opts = Options {
+            dedup_chunk: self.opts.dedup_chunk,
+            ..src.opts
+        };
+        self.ctime = src.ctime;
+        self.mtime = src.mtime;
+        self.perms = src.perms;
+        self.dir_opts = src.dir_opts;
+        self.seq = change_seq();
+    }
+
+    /// Get options inherited from a directory and all its ancestors
+    pub fn inherited_opts(dir: &FnodeRef, base: Options) -> Options {
+        let mut chain = Vec::new();
+        let mut curr = Some(dir.clone());
+        while let Some(fnode_ref) = curr {
+            let fnode = fnode_ref.read().unwrap();
+            chain.push(fnode.dir_opts);
+            curr = fnode.parent.clone();
+        }
+
+        // apply from root down, so the nearest directory wins
+        let mut opts = base;
+        for dir_opts in chain.iter().rev() {
+            dir_opts.apply(&mut opts);
+        }
+        opts
+    }
+
     /// Load root fnode
     #[inline]
     pub fn load_root(root_id: &Eid, vol: &VolumeRef) -> Result<FnodeRef> {
@@ -407,10 +1035,18 @@ impl Fnode {
             .load_child(name, parent.clone(), cache, vol)
     }
 
-    fn children_names(&self) -> Vec<String> {
+    pub(super) fn children_names(&self) -> Vec<String> {
         self.kids.iter().map(|ref k| k.name.clone()).collect()
     }
 
+    // get children id, name and whether it is a directory
+    pub(super) fn children_ids(&self) -> Vec<(Eid, String, bool)> {
+        self.kids
+            .iter()
+            .map(|k| (k.id.clone(), k.name.clone(), k.ftype == FileType::Dir))
+            .collect()
+    }
+
     /// Get children dir entry list
     pub fn read_dir(
         parent: FnodeRef,
@@ -418,6 +1054,17 @@ impl Fnode {
         cache: &Cache,
         vol: &VolumeRef,
     ) -> Result<Vec<DirEntry>> {
+        Self::read_dir_nodes(parent, path, cache, vol)
+            .map(|nodes| nodes.into_iter().map(|(ent, _)| ent).collect())
+    }
+
+    /// Get children dir entry list along with children fnodes
+    pub fn read_dir_nodes(
+        parent: FnodeRef,
+        path: &Path,
+        cache: &Cache,
+        vol: &VolumeRef,
+    ) -> Result<Vec<(DirEntry, FnodeRef)>> {
         let mut par = parent.write().unwrap();
         let par = par.make_mut_naive();
         if !par.is_dir() {
@@ -445,12 +1092,15 @@ impl Fnode {
         for name in child_names.iter() {
             let child_ref =
                 par.load_child(&name, parent.clone(), cache, vol)?;
-            let child = child_ref.read().unwrap();
-            ret.push(DirEntry {
-                path: parent_path.join(name),
-                metadata: child.metadata(),
-                name: name.clone(),
-            });
+            let ent = {
+                let child = child_ref.read().unwrap();
+                DirEntry {
+                    path: parent_path.join(name),
+                    metadata: child.metadata(),
+                    name: name.clone(),
+                }
+            };
+            ret.push((ent, child_ref));
         }
 
         Ok(ret)
@@ -476,7 +1126,7 @@ impl Fnode {
         // add to parent's sub node list and update modified time
         par.sub_nodes
             .insert(name.to_string(), Arc::downgrade(child));
-        par.mtime = Time::now();
+        par.set_modified(Time::now());
 
         Ok(())
     }
@@ -501,12 +1151,73 @@ impl Fnode {
                     par.sub_nodes.remove(name);
                 }
                 par.kids.remove(child_idx);
+                par.set_modified(Time::now());
                 Ok(())
             }
             None => Err(Error::IsRoot),
         }
     }
 
+    /// Release reference to parent fnode, this is used when the fnode is
+    /// removed along with its parent
+    #[inline]
+    pub fn detach(&mut self) {
+        self.parent = None;
+    }
+
+    /// Get absolute path of fnode by walking up its parents, return `None`
+    /// if the fnode or any of its ancestors has been removed, or it is a
+    /// detached file such as temporary file
+    pub fn path(fnode: &FnodeRef) -> Option<PathBuf> {
+        let mut names = Vec::new();
+        let mut curr = fnode.clone();
+        loop {
+            let (id, parent) = {
+                let node = curr.read().unwrap();
+                match node.parent {
+                    Some(ref parent) => (node.id().clone(), parent.clone()),
+                    None if node.is_dir() => break,
+                    None => return None,
+                }
+            };
+            {
+                let par = parent.read().unwrap();
+                let kid = par.kids.iter().find(|c| c.id == id)?;
+                names.push(kid.name.clone());
+            }
+            curr = parent;
+        }
+
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        Some(path)
+    }
+
+    /// Attach a tag to a version, or remove its tag if `tag` is `None`,
+    /// the same tag on other versions is removed
+    pub fn tag_version(
+        &mut self,
+        ver_num: usize,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        if !self.vers.iter().any(|v| v.num == ver_num) {
+            return Err(Error::NoVersion);
+        }
+        for ver in self.vers.iter_mut() {
+            if ver.num == ver_num {
+                ver.tag = tag.map(str::to_string);
+            } else if tag.is_some() && ver.tag() == tag {
+                ver.tag = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get version with the specified tag
+    pub fn tagged_ver(&self, tag: &str) -> Option<&Version> {
+        self.vers.iter().find(|v| v.tag() == Some(tag))
+    }
+
     /// get a specified version
     pub fn ver(&self, ver_num: usize) -> Option<&Version> {
         self.vers.iter().find(|v| v.num == ver_num)
@@ -517,6 +1228,70 @@ impl Fnode {
         self.vers.back().unwrap()
     }
 
+    /// Get deduplication information
+    pub fn dedup_info(&self, store: &StoreRef) -> Result<DedupInfo> {
+        let mut info = DedupInfo::default();
+        if self.vers.is_empty() {
+            return Ok(info);
+        }
+
+        let store = store.read().unwrap();
+
+        // count how many versions are using each content
+        let mut ctn_vers: HashMap<&Eid, u32> = HashMap::new();
+        for ver in self.vers.iter() {
+            *ctn_vers.entry(&ver.content_id).or_insert(0) += 1;
+        }
+
+        // count chunk references made by all contents of this file, chunks
+        // in the content deduped with other files are shared as well
+        let mut file_refs = HashMap::new();
+        let mut curr_refs = HashMap::new();
+        let mut shared = HashSet::new();
+        for (ctn_id, ver_cnt) in ctn_vers.iter() {
+            let ctn_ref = store.get_content(ctn_id)?;
+            let ctn = ctn_ref.read().unwrap();
+            let ctn_refcnt = store.content_refcnt(&ctn).unwrap_or(*ver_cnt);
+            let mut refs = HashMap::new();
+            ctn.count_chunk_refs(&mut refs);
+            if **ctn_id == self.curr_ver().content_id {
+                curr_refs = refs.clone();
+                if ctn_refcnt > 1 {
+                    // content is used by other versions or files
+                    info.shared_chunks = curr_refs.len();
+                }
+            }
+            for (loc, cnt) in refs {
+                if ctn_refcnt > *ver_cnt {
+                    shared.insert(loc.clone());
+                }
+                *file_refs.entry(loc).or_insert(0) += cnt;
+            }
+        }
+
+        // chunk is exclusive to this file if all of its references are
+        // made by this file
+        for (loc, cnt) in file_refs.iter() {
+            let (len, refcnt) = store.chunk_refcnt(&loc.0, loc.1)?;
+            if refcnt <= *cnt && !shared.contains(loc) {
+                info.exclusive_size += len;
+            }
+        }
+
+        // count shared chunks in current version
+        info.chunks = curr_refs.len();
+        if info.shared_chunks == 0 {
+            for (loc, cnt) in curr_refs.iter() {
+                let (_, refcnt) = store.chunk_refcnt(&loc.0, loc.1)?;
+                if refcnt > *cnt {
+                    info.shared_chunks += 1;
+                }
+            }
+        }
+
+        Ok(info)
+    }
+
     /// Get current version number
     pub fn curr_ver_num(&self) -> usize {
         if self.vers.is_empty() {
@@ -539,7 +1314,11 @@ impl Fnode {
             .ok_or(Error::NoVersion)?;
         let ver = self.vers.remove(idx).unwrap();
 
-        if let Some(ctn) = Store::deref_content(store, &ver.content_id)? {
+        if let Some(ctn) = Store::deref_content(
+            store,
+            &ver.content_id,
+            self.key_domain.is_none(),
+        )? {
             // content is not used anymore, remove it
             let mut content = ctn.write().unwrap();
             content.unlink(&mut self.chk_map, store, txmgr)?;
@@ -572,12 +1351,12 @@ impl Fnode {
         assert!(self.is_file());
 
         // try to dedup content in store
-        let (no_dup, deduped_id) = Store::dedup_content(store, &content)?;
+        let (no_dup, deduped_id) =
+            Store::dedup_content(store, &content, self.key_domain.is_none())?;
 
         // create a new version and append to version list
-        let ver =
-            Version::new(self.curr_ver_num() + 1, &deduped_id, content.len());
-        self.mtime = ver.ctime;
+        let ver = Version::new(self.curr_ver_num() + 1, &deduped_id, &content);
+        self.set_modified(ver.ctime);
         self.vers.push_back(ver);
 
         // if content is not duplicated, link the content
@@ -591,9 +1370,56 @@ impl Fnode {
             self.remove_version(retire, store, txmgr)?;
         }
 
+        // evict versions expired by retention policy
+        let vers: Vec<(usize, SystemTime)> =
+            self.vers.iter().map(|v| (v.num, v.created_at())).collect();
+        let now = Time::now().to_system_time();
+        let expired = self.opts.retention.retired(&vers, now);
+        for ver_num in expired {
+            self.remove_version(ver_num, store, txmgr)?;
+        }
+
         Ok(no_dup)
     }
 
+    // replace all versions with versions of another fnode, content is
+    // cloned so its chunks are shared rather than copied, version numbers,
+    // creation times and tags are kept
+    pub fn copy_versions(
+        &mut self,
+        src: &Fnode,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        assert!(self.is_file() && src.is_file());
+        self.clear_versions(store, txmgr)?;
+
+        for src_ver in src.vers.iter() {
+            let content = {
+                let store = store.read().unwrap();
+                let ctn_ref = store.get_content(&src_ver.content_id)?;
+                let ctn = ctn_ref.read().unwrap();
+                ctn.clone()
+            };
+            let (no_dup, deduped_id) = Store::dedup_content(
+                store,
+                &content,
+                self.key_domain.is_none(),
+            )?;
+            self.vers.push_back(Version {
+                content_id: deduped_id,
+                ..src_ver.clone()
+            });
+            if no_dup {
+                content.link(store, txmgr)?;
+            }
+        }
+        self.mtime = src.mtime;
+        self.seq = change_seq();
+
+        Ok(())
+    }
+
     /// Get reader for sepcified version number
     pub fn version_reader(
         &self,
@@ -611,6 +1437,26 @@ impl Fnode {
         Ok(ContentReader::new(content, store))
     }
 
+    /// Get changed extents between two versions
+    pub fn diff_versions(
+        &self,
+        ver_num: usize,
+        other_ver_num: usize,
+        store: &StoreRef,
+    ) -> Result<Vec<(usize, usize)>> {
+        let ver = self.ver(ver_num).ok_or(Error::NoVersion)?;
+        let other_ver = self.ver(other_ver_num).ok_or(Error::NoVersion)?;
+        let (ctn, other_ctn) = {
+            let store = store.read().unwrap();
+            let ctn = store.get_content(&ver.content_id)?;
+            let other_ctn = store.get_content(&other_ver.content_id)?;
+            let ctn = ctn.read().unwrap().clone();
+            let other_ctn = other_ctn.read().unwrap().clone();
+            (ctn, other_ctn)
+        };
+        ctn.diff(&other_ctn, store)
+    }
+
     /// Clone a new current content
     pub fn clone_current_content(&self, store: &StoreRef) -> Result<Content> {
         let store = store.read().unwrap();
@@ -619,6 +1465,34 @@ impl Fnode {
         Ok(content.clone())
     }
 
+    /// Load current content data into cache
+    pub fn prefetch(&self, store: &StoreRef) -> Result<()> {
+        let store = store.read().unwrap();
+        let ctn_ref = store.get_content(&self.curr_ver().content_id)?;
+        let ctn = ctn_ref.read().unwrap();
+        ctn.prefetch(&store)
+    }
+
+    /// Get the data block containing the offset in current content
+    pub fn block_at(
+        &self,
+        offset: usize,
+        store: &StoreRef,
+    ) -> Result<Option<DataBlock>> {
+        let store = store.read().unwrap();
+        let ctn_ref = store.get_content(&self.curr_ver().content_id)?;
+        let ctn = ctn_ref.read().unwrap();
+        ctn.block_at(offset, &store)
+    }
+
+    /// Remove current content data from cache
+    pub fn evict(&self, store: &StoreRef) -> Result<()> {
+        let store = store.read().unwrap();
+        let ctn_ref = store.get_content(&self.curr_ver().content_id)?;
+        let ctn = ctn_ref.read().unwrap();
+        ctn.evict(&store)
+    }
+
     /// Set file to specified length
     ///
     /// if new length is equal to old length, do nothing
@@ -645,17 +1519,69 @@ impl Fnode {
             // truncate
             let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
             let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+            let (delta, vers_delta) = {
+                let mut fnode_cow = handle.fnode.write().unwrap();
+                let old_len = fnode_cow.versions_len() as i64;
+                let old_cnt = fnode_cow.versions_cnt() as i64;
+                let new_ctn = {
+                    let mut ctn = fnode_cow.clone_current_content(&store)?;
+                    ctn.truncate(len, &store)?;
+                    ctn
+                };
+
+                // dedup content, if it is not duplicated then link the
+                // content
+                let fnode = fnode_cow.make_mut(&txmgr)?;
+                fnode.add_version(new_ctn, &store, &txmgr)?;
+                (
+                    fnode.versions_len() as i64 - old_len,
+                    fnode.versions_cnt() as i64 - old_cnt,
+                )
+            };
+            Fnode::charge_quota(&handle.fnode, delta, &txmgr)?;
+            txmgr
+                .read()
+                .unwrap()
+                .add_counts(EntryCounts::versions(vers_delta))?;
+        }
+
+        Ok(())
+    }
+
+    /// Add content of a previous version as a new version
+    ///
+    /// if the version is already the current version, do nothing
+    pub fn restore_version(handle: Handle, ver_num: usize) -> Result<()> {
+        let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let (delta, vers_delta) = {
             let mut fnode_cow = handle.fnode.write().unwrap();
-            let new_ctn = {
-                let mut ctn = fnode_cow.clone_current_content(&store)?;
-                ctn.truncate(len, &store)?;
-                ctn
+            let ctn = {
+                let ver = fnode_cow.ver(ver_num).ok_or(Error::NoVersion)?;
+                if ver.num == fnode_cow.curr_ver_num() {
+                    return Ok(());
+                }
+                let store = store.read().unwrap();
+                let ctn_ref = store.get_content(&ver.content_id)?;
+                let ctn = ctn_ref.read().unwrap();
+                ctn.clone()
             };
+            let old_len = fnode_cow.versions_len() as i64;
+            let old_cnt = fnode_cow.versions_cnt() as i64;
 
-            // dedup content, if it is not duplicated then link the content
+            // the content is either deduped or linked again
             let fnode = fnode_cow.make_mut(&txmgr)?;
-            fnode.add_version(new_ctn, &store, &txmgr)?;
-        }
+            fnode.add_version(ctn, &store, &txmgr)?;
+            (
+                fnode.versions_len() as i64 - old_len,
+                fnode.versions_cnt() as i64 - old_cnt,
+            )
+        };
+        Fnode::charge_quota(&handle.fnode, delta, &txmgr)?;
+        txmgr
+            .read()
+            .unwrap()
+            .add_counts(EntryCounts::versions(vers_delta))?;
 
         Ok(())
     }
@@ -668,9 +1594,14 @@ impl Debug for Fnode {
             .field("opts", &self.opts)
             .field("ctime", &self.ctime)
             .field("mtime", &self.mtime)
+            .field("atime", &self.atime)
+            .field("seq", &self.seq)
             .field("kids", &self.kids)
             .field("vers", &self.vers)
             .field("chk_map", &self.chk_map)
+            .field("dir_opts", &self.dir_opts)
+            .field("rdev", &self.rdev)
+            .field("link_target", &self.link_target)
             .field("sub_nodes", &self.sub_nodes)
             .finish()
     }
@@ -736,6 +1667,16 @@ impl Reader {
     pub fn version_num(&self) -> usize {
         self.ver
     }
+
+    #[inline]
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.rdr.set_read_ahead(read_ahead);
+    }
+
+    #[inline]
+    pub fn set_drop_behind(&mut self, drop_behind: bool) {
+        self.rdr.set_drop_behind(drop_behind);
+    }
 }
 
 impl Read for Reader {
@@ -761,39 +1702,76 @@ pub struct Writer {
 
 impl Writer {
     pub fn new(handle: Handle, txid: Txid) -> Result<Self> {
-        let chk_map = {
+        let (chk_map, domain) = {
             let f = handle.fnode.read().unwrap();
-            f.chk_map.clone()
+            (f.chk_map.clone(), f.key_domain.clone())
         };
-        let inner =
-            StoreWriter::new(txid, chk_map, &handle.txmgr, &handle.store)?;
+        let inner = StoreWriter::new(
+            txid,
+            chk_map,
+            domain,
+            &handle.txmgr,
+            &handle.store,
+        )?;
         Ok(Writer { inner, handle })
     }
 
+    #[inline]
     pub fn finish(self) -> Result<usize> {
+        self.finish_with(false)
+    }
+
+    // finish writing and truncate the new version at the end of written
+    // data, so the written data replaces the whole current content
+    #[inline]
+    pub fn finish_replace(self) -> Result<usize> {
+        self.finish_with(true)
+    }
+
+    fn finish_with(self, replace: bool) -> Result<usize> {
         let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
         let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
         let (stg_ctn, chk_map) = self.inner.finish()?;
         let handle = &self.handle;
 
-        let mut fnode_cow = handle.fnode.write().unwrap();
+        let (delta, vers_delta) = {
+            let mut fnode_cow = handle.fnode.write().unwrap();
+            let old_len = fnode_cow.versions_len() as i64;
+            let old_cnt = fnode_cow.versions_cnt() as i64;
 
-        // merge stage content to current content
-        let merged_ctn = {
-            let mut ctn = fnode_cow.clone_current_content(&store)?;
-            ctn.merge_from(&stg_ctn, &store)?;
-            ctn
-        };
+            // merge stage content to current content
+            let merged_ctn = {
+                let mut ctn = fnode_cow.clone_current_content(&store)?;
+                ctn.merge_from(&stg_ctn, &store)?;
+                if replace && ctn.len() > stg_ctn.end_offset() {
+                    ctn.truncate(stg_ctn.end_offset(), &store)?;
+                }
+                ctn
+            };
 
-        // dedup content and add deduped content as a new version
-        let fnode = fnode_cow.make_mut(&txmgr)?;
-        if !fnode.add_version(merged_ctn, &store, &txmgr)? {
-            // content is duplicated, weak unlink the stage content
-            stg_ctn.unlink_weak(&mut fnode.chk_map, &store, &txmgr)?;
-        }
+            // update fnode chunk map first, so segments removed along with
+            // retired versions are also removed from the new chunk map
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            fnode.chk_map = chk_map;
+
+            // dedup content and add deduped content as a new version
+            if !fnode.add_version(merged_ctn, &store, &txmgr)? {
+                // content is duplicated, weak unlink the stage content
+                stg_ctn.unlink_weak(&mut fnode.chk_map, &store, &txmgr)?;
+            }
+
+            (
+                fnode.versions_len() as i64 - old_len,
+                fnode.versions_cnt() as i64 - old_cnt,
+            )
+        };
 
-        // udpate fnode chunk map
-        fnode.chk_map = chk_map;
+        // charge size change to quotas and count new version
+        Fnode::charge_quota(&handle.fnode, delta, &txmgr)?;
+        txmgr
+            .read()
+            .unwrap()
+            .add_counts(EntryCounts::versions(vers_delta))?;
 
         Ok(stg_ctn.end_offset())
     }
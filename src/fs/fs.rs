@@ -1,20 +1,40 @@
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::cmp::min;
+use std::io::Write;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
 
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
+use super::du::{DiskUsage, UsageCounter};
+use super::export::MetaExporter;
 use super::fnode::{
-    Cache as FnodeCache, DirEntry, FileType, Fnode, FnodeRef, Metadata, Version,
+    Cache as FnodeCache, DirEntry, FileType, Fnode, FnodeRef, Metadata,
+    Permissions, Quota, Version,
 };
-use super::{Config, Handle, Options};
-use base::crypto::Cost;
-use base::IntoRef;
-use content::{Store, StoreRef};
+use super::lock::FileLocksRef;
+use super::migrate::Migrator;
+use super::policy::{PasswordCheck, PathPolicy};
+use super::snapshot::{Snapshot, Snapshots, SnapshotsRef};
+use super::temp::{TempFiles, TempFilesRef};
+use super::trash::{Trash, TrashEntry};
+use super::verify::{Verifier, VerifyReport};
+use super::walk::WalkDir;
+use super::watch::{WatchEvent, Watchers, WatchersRef};
+use super::{Config, DirOptions, Handle, Options};
+use base::crypto::{BoxPublicKey, BoxSecretKey, Cipher, Cost, Key, KEY_SIZE};
+use base::{IntoRef, Time};
+use content::{Content, Store, StoreRef};
 use error::{Error, Result};
 use trans::cow::IntoCow;
-use trans::{Eid, Id, TxMgr, TxMgrRef};
-use volume::{Info as VolumeInfo, Volume, VolumeRef};
+use trans::{Eid, EntryCounts, Id, TxMgr, TxMgrRef};
+use volume::{
+    Credential, Health, Info as VolumeInfo, OpenPhase, RetryPolicy, Space,
+    Volume, VolumeRef,
+};
 
 // mask secrets in uri
 fn mask_uri(uri: &str) -> String {
@@ -32,6 +52,10 @@ pub struct Info {
     pub opts: Options,
     pub vol_info: VolumeInfo,
     pub read_only: bool,
+    pub used_blocks: usize,
+    pub logical_len: u64,
+    pub counts: EntryCounts,
+    pub retained_wals: usize,
 }
 
 /// Shutter
@@ -95,6 +119,19 @@ impl Payload {
     }
 }
 
+// how a directory tree is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyMode {
+    // copy current file content only, like copying files
+    Content,
+
+    // copy everything to a new snapshot tree
+    Snapshot,
+
+    // restore everything from a snapshot tree to the live tree
+    Restore,
+}
+
 /// File system
 #[derive(Debug)]
 pub struct Fs {
@@ -104,14 +141,52 @@ pub struct Fs {
     txmgr: TxMgrRef,
     vol: VolumeRef,
     shutter: ShutterRef,
+    watchers: WatchersRef,
+    locks: FileLocksRef,
+    snaps: Option<SnapshotsRef>,
     opts: Options,
     read_only: bool,
+
+    // main root id if this is a frozen view, whose root is a snapshot tree
+    // removed when the view is dropped
+    view: Option<Eid>,
+
+    // trash entry expiry, none if trash is disabled
+    trash: Option<Duration>,
+
+    // root of the detached tree holding trash entries, none if nothing has
+    // ever been moved to trash
+    trash_root: Option<FnodeRef>,
+
+    // list of detached temporary files, none if no temporary files have
+    // ever been created
+    temps: Option<TempFilesRef>,
+
+    // policy for paths of newly created entries
+    path_policy: PathPolicy,
+
+    // validator for new passwords
+    pwd_check: Option<PasswordCheck>,
+
+    // whether access time tracking is enabled
+    atime: bool,
 }
 
 impl Fs {
     // default cache size
     const FNODE_CACHE_SIZE: usize = 16;
 
+    // maximum number of symbolic links followed when resolving a link
+    const MAX_LINK_FOLLOWS: usize = 40;
+
+    // number of entities re-encrypted between data key rotation checkpoints
+    const CHECKPOINT_INTERVAL: usize = 1024;
+
+    // names of original path link and removed entry in each trash entry
+    // directory
+    const TRASH_ORIGIN: &'static str = "origin";
+    const TRASH_DATA: &'static str = "data";
+
     /// Check if fs exists
     pub fn exists(uri: &str) -> Result<bool> {
         let vol = Volume::new(uri)?;
@@ -119,7 +194,7 @@ impl Fs {
     }
 
     /// Create new fs
-    pub fn create(uri: &str, pwd: &str, cfg: &Config) -> Result<Fs> {
+    pub fn create(uri: &str, cred: Credential, cfg: &Config) -> Result<Fs> {
         let root_id = Eid::new();
         let walq_id = Eid::new();
         let store_id = Eid::new();
@@ -127,9 +202,10 @@ impl Fs {
 
         // create and initialise volume
         let mut vol = Volume::new(uri)?;
+        vol.set_retry_policy(cfg.retry);
         info!("create repo: {}", mask_uri(&vol.info().uri));
 
-        vol.init(pwd, cfg, &payload.seri()?)?;
+        vol.init(cred, cfg, &payload.seri()?)?;
 
         let vol = vol.into_ref();
 
@@ -160,19 +236,32 @@ impl Fs {
             txmgr,
             vol,
             shutter: Shutter::new(),
+            watchers: Watchers::default().into_ref(),
+            locks: FileLocksRef::default(),
+            snaps: None,
             opts: cfg.opts,
             read_only: false,
+            view: None,
+            trash: None,
+            trash_root: None,
+            temps: None,
+            path_policy: PathPolicy::default(),
+            pwd_check: None,
+            atime: false,
         })
     }
 
     /// Open fs
     pub fn open(
         uri: &str,
-        pwd: &str,
+        cred: Credential,
         read_only: bool,
         force: bool,
+        retry: RetryPolicy,
+        progress: &dyn Fn(OpenPhase),
     ) -> Result<Fs> {
         let mut vol = Volume::new(uri)?;
+        vol.set_retry_policy(retry);
 
         info!(
             "open repo: {}, read_only: {}",
@@ -181,32 +270,66 @@ impl Fs {
         );
 
         // open volume
-        let payload = vol.open(pwd, force)?;
+        let payload = vol.open(cred, read_only, force, progress)?;
         let vol = vol.into_ref();
 
         // deserialize payload
         let payload = Payload::deseri(&payload)?;
 
         // open transaction manager
-        let txmgr = TxMgr::open(&payload.walq_id, &vol)?.into_ref();
+        progress(OpenPhase::WalRedo);
+        let txmgr = TxMgr::open(&payload.walq_id, &vol, read_only)?.into_ref();
 
         // create other file sytem components
+        progress(OpenPhase::TreeLoad);
         let store = Store::open(&payload.store_id, &txmgr, &vol)?;
         let root = Fnode::load_root(&payload.root_id, &vol)?;
+        let snaps = Snapshots::load(&payload.root_id, &vol)?;
+        let trash_root = Trash::load(&payload.root_id, &vol)?;
+        let temps = TempFiles::load(&payload.root_id, &vol)?;
         let fcache = FnodeCache::new(Self::FNODE_CACHE_SIZE);
 
-        info!("repo opened");
-
-        Ok(Fs {
+        let fs = Fs {
             root,
             fcache,
             store,
             txmgr,
             vol,
             shutter: Shutter::new(),
+            watchers: Watchers::default().into_ref(),
+            locks: FileLocksRef::default(),
+            snaps,
             opts: payload.opts,
             read_only,
-        })
+            view: None,
+            trash: None,
+            trash_root,
+            temps,
+            path_policy: PathPolicy::default(),
+            pwd_check: None,
+            atime: false,
+        };
+
+        if !read_only {
+            fs.remove_stale_views()?;
+            fs.remove_stale_temps()?;
+        }
+
+        // repo created by older versions doesn't track total usage and entry
+        // counts, so calculate them once here and they will be saved in next
+        // transaction
+        if fs.txmgr.read().unwrap().logical_len().is_none() {
+            let len = fs.children_usage(&fs.root, Path::new("/"))?;
+            fs.txmgr.write().unwrap().set_logical_len(len);
+        }
+        if fs.txmgr.read().unwrap().counts().is_none() {
+            let counts = fs.count_children(&fs.root, Path::new("/"))?;
+            fs.txmgr.write().unwrap().set_counts(counts);
+        }
+
+        info!("repo opened");
+
+        Ok(fs)
     }
 
     #[inline]
@@ -214,19 +337,95 @@ impl Fs {
         self.read_only
     }
 
-    #[inline]
-    pub fn get_opts(&self) -> Options {
-        self.opts
+    /// Reload latest committed state of a read-only fs
+    ///
+    /// All files and directories opened before are closed.
+    pub fn refresh(&mut self) -> Result<()> {
+        if !self.read_only || self.view.is_some() {
+            return Ok(());
+        }
+
+        let walq_id = self.txmgr.read().unwrap().walq_id().clone();
+        let store_id = self.store.read().unwrap().id().clone();
+        let root_id = self.root.read().unwrap().id().clone();
+
+        // reload storage and reopen all components on top of it
+        self.vol.write().unwrap().refresh()?;
+        let txmgr = TxMgr::open(&walq_id, &self.vol, true)?.into_ref();
+        let store = Store::open(&store_id, &txmgr, &self.vol)?;
+        let root = Fnode::load_root(&root_id, &self.vol)?;
+        let snaps = Snapshots::load(&root_id, &self.vol)?;
+        let trash_root = Trash::load(&root_id, &self.vol)?;
+        let temps = TempFiles::load(&root_id, &self.vol)?;
+
+        // close previously opened files
+        self.shutter.write().unwrap().close();
+
+        self.root = root;
+        self.fcache = FnodeCache::new(Self::FNODE_CACHE_SIZE);
+        self.store = store;
+        self.txmgr = txmgr;
+        self.shutter = Shutter::new();
+        self.snaps = snaps;
+        self.trash_root = trash_root;
+        self.temps = temps;
+
+        if self.txmgr.read().unwrap().logical_len().is_none() {
+            let len = self.children_usage(&self.root, Path::new("/"))?;
+            self.txmgr.write().unwrap().set_logical_len(len);
+        }
+        if self.txmgr.read().unwrap().counts().is_none() {
+            let counts = self.count_children(&self.root, Path::new("/"))?;
+            self.txmgr.write().unwrap().set_counts(counts);
+        }
+
+        debug!("repo refreshed");
+
+        Ok(())
     }
 
     /// Get file system information
     pub fn info(&self) -> Info {
         let vol = self.vol.read().unwrap();
+        let used_blocks = {
+            let allocator = vol.get_allocator();
+            let allocator = allocator.read().unwrap();
+            allocator.used_blocks()
+        };
+        let txmgr = self.txmgr.read().unwrap();
         Info {
             opts: self.opts,
             vol_info: vol.info(),
             read_only: self.read_only,
+            used_blocks,
+            logical_len: txmgr.logical_len().unwrap_or(0),
+            counts: txmgr.counts().unwrap_or_default(),
+            retained_wals: txmgr.retained_wals(),
+        }
+    }
+
+    /// Get underlying storage space usage
+    pub fn space(&self) -> Result<Space> {
+        let mut space = self.vol.read().unwrap().space()?;
+
+        // quota on root directory caps free space of the whole repo
+        if let Some(quota) = self.root.read().unwrap().quota() {
+            let quota_free = quota.limit().saturating_sub(quota.used());
+            let free = match space.total {
+                Some(total) => total.saturating_sub(space.used).min(quota_free),
+                None => quota_free,
+            };
+            space.total = Some(space.used + free);
         }
+
+        Ok(space)
+    }
+
+    /// Check underlying storage health
+    #[inline]
+    pub fn health_check(&self) -> Result<Health> {
+        let vol = self.vol.read().unwrap();
+        vol.health_check()
     }
 
     /// Reset volume password
@@ -239,11 +438,167 @@ impl Fs {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
+        if let Some(ref pwd_check) = self.pwd_check {
+            pwd_check.check(new_pwd)?;
+        }
 
         let mut vol = self.vol.write().unwrap();
         vol.reset_password(old_pwd, new_pwd, cost)
     }
 
+    /// Reset volume password using recovery key
+    pub fn reset_password_with_recovery_key(
+        &mut self,
+        recovery: &[u8],
+        recovery_pwd: &str,
+        new_pwd: &str,
+        cost: Cost,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if let Some(ref pwd_check) = self.pwd_check {
+            pwd_check.check(new_pwd)?;
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.reset_password_with_recovery(recovery, recovery_pwd, new_pwd, cost)
+    }
+
+    /// Replace master key with a new random key
+    pub fn rotate_master_key(&mut self, pwd: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut vol = self.vol.write().unwrap();
+        vol.rotate_master_key(pwd)
+    }
+
+    /// Update volume label, description and application tag
+    pub fn set_label(
+        &mut self,
+        pwd: &str,
+        label: &str,
+        description: &str,
+        app_tag: &str,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.set_label(pwd, label, description, app_tag)
+    }
+
+    /// Update compression setting
+    pub fn set_compress(&mut self, pwd: &str, compress: bool) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.set_compress(pwd, compress)
+    }
+
+    /// Update password hint
+    pub fn set_password_hint(&mut self, pwd: &str, hint: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.set_password_hint(pwd, hint)
+    }
+
+    /// Enable drop box, return its public key
+    pub fn enable_drop_box(&mut self, pwd: &str) -> Result<BoxPublicKey> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.enable_drop_box(pwd)
+    }
+
+    /// Enable convergent encryption for new file content
+    pub fn enable_convergent(&mut self, pwd: &str, key: &[u8]) -> Result<()> {
+        if key.len() != KEY_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+
+        // content is not encrypted at all in integrity-only mode
+        if !vol.info().cipher.is_confidential() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut conv_key = Key::new_empty();
+        conv_key.copy(key);
+        vol.enable_convergent(pwd, conv_key)
+    }
+
+    /// Export recovery key protected by recovery password
+    pub fn export_recovery_key(
+        &mut self,
+        pwd: &str,
+        recovery_pwd: &str,
+    ) -> Result<Vec<u8>> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.export_recovery_key(pwd, recovery_pwd)
+    }
+
+    /// Get drop box key pair, none if drop box is not enabled
+    #[inline]
+    pub fn drop_box_keys(&self) -> Option<(BoxPublicKey, BoxSecretKey)> {
+        let vol = self.vol.read().unwrap();
+        vol.info().drop_box
+    }
+
+    /// Seal fs permanently, it becomes read-only immediately
+    pub fn seal(&mut self, pwd: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        {
+            let mut vol = self.vol.write().unwrap();
+            vol.seal(pwd)?;
+        }
+        self.read_only = true;
+        info!("repo sealed");
+
+        Ok(())
+    }
+
+    /// Derive volume key from password without opening fs
+    #[inline]
+    pub fn derive_key(uri: &str, pwd: &str) -> Result<Key> {
+        let mut vol = Volume::new(uri)?;
+        vol.derive_key(pwd)
+    }
+
+    /// Get password hint without opening fs
+    #[inline]
+    pub fn password_hint(uri: &str) -> Result<Option<String>> {
+        let mut vol = Volume::new(uri)?;
+        vol.password_hint()
+    }
+
+    /// Get key provider URI without opening fs
+    #[inline]
+    pub fn key_provider_uri(uri: &str) -> Result<Option<String>> {
+        let mut vol = Volume::new(uri)?;
+        vol.key_provider_uri()
+    }
+
     /// Repair possibly damaged super block
     #[inline]
     pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
@@ -282,12 +637,67 @@ impl Fs {
     /// Open fnode
     pub fn open_fnode(&mut self, path: &Path) -> Result<Handle> {
         let fnode = self.resolve(path)?;
-        Ok(Handle {
+        Ok(self.new_handle(fnode))
+    }
+
+    // create handle of an opened fnode
+    fn new_handle(&self, fnode: FnodeRef) -> Handle {
+        Handle {
             fnode,
             store: Arc::downgrade(&self.store),
             txmgr: Arc::downgrade(&self.txmgr),
             shutter: self.shutter.clone(),
-        })
+            watchers: self.watchers.clone(),
+            locks: self.locks.clone(),
+            atime: self.atime && !self.read_only,
+        }
+    }
+
+    /// Get entity id of fnode at path
+    pub fn path_to_id(&self, path: &Path) -> Result<Eid> {
+        let fnode_ref = self.resolve(path)?;
+        let fnode = fnode_ref.read().unwrap();
+        Ok(fnode.id().clone())
+    }
+
+    /// Get current path of fnode by its entity id
+    ///
+    /// The fnode cache is tried first, if it is not there the whole tree is
+    /// searched.
+    pub fn id_to_path(&self, id: &Eid) -> Result<PathBuf> {
+        if let Some(fnode_ref) = self.fcache.peek(id) {
+            // cached fnode could belong to a snapshot tree, so make sure the
+            // path resolves to the same fnode in current tree
+            if let Some(path) = Fnode::path(&fnode_ref) {
+                let found = self
+                    .resolve(&path)
+                    .map(|f| f.read().unwrap().id() == id)
+                    .unwrap_or(false);
+                if found {
+                    return Ok(path);
+                }
+            }
+        }
+
+        // search the tree depth first, only directories need to be loaded
+        if self.root.read().unwrap().id() == id {
+            return Ok(PathBuf::from("/"));
+        }
+        let mut stack = vec![(self.root.clone(), PathBuf::from("/"))];
+        while let Some((dir, path)) = stack.pop() {
+            let kids = dir.read().unwrap().children_ids();
+            for (kid_id, name, is_dir) in kids {
+                if kid_id == *id {
+                    return Ok(path.join(name));
+                }
+                if is_dir {
+                    let child =
+                        Fnode::child(&dir, &name, &self.fcache, &self.vol)?;
+                    stack.push((child, path.join(name)));
+                }
+            }
+        }
+        Err(Error::NotFound)
     }
 
     /// Create fnode
@@ -301,6 +711,7 @@ impl Fs {
             return Err(Error::ReadOnly);
         }
 
+        self.path_policy.check(path)?;
         let (parent, name) = self.resolve_parent(path)?;
 
         {
@@ -316,251 +727,2123 @@ impl Fs {
         let mut fnode = FnodeRef::default();
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
         tx_handle.run_all_exclusive(|| {
-            fnode = Fnode::new_under(
-                &parent,
-                &name,
-                ftype,
-                opts,
-                &self.txmgr,
-                &self.store,
-            )?;
+            fnode = self.new_fnode(&parent, &name, ftype, opts)?;
             Ok(())
         })?;
+        self.notify(WatchEvent::Create(path.to_path_buf()));
 
         Ok(fnode)
     }
 
-    /// Recursively create directories along the path
-    pub fn create_dir_all(&mut self, path: &Path) -> Result<()> {
-        match self.create_fnode(path, FileType::Dir, Options::default()) {
-            Ok(_) => return Ok(()),
-            Err(ref err) if *err == Error::NotFound => {}
-            Err(err) => return Err(err),
+    /// Create special fnode, such as symbolic link, FIFO and etc.
+    pub fn create_special_fnode(
+        &mut self,
+        path: &Path,
+        ftype: FileType,
+        rdev: u64,
+        link_target: Option<&Path>,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
-        match path.parent() {
-            Some(p) => self.create_dir_all(p)?,
-            None => return Err(Error::IsRoot),
+
+        self.path_policy.check(path)?;
+        let (parent, name) = self.resolve_parent(path)?;
+
+        {
+            let parent = parent.read().unwrap();
+            if !parent.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if parent.has_child(&name) {
+                return Err(Error::AlreadyExists);
+            }
         }
-        self.create_fnode(path, FileType::Dir, Options::default())?;
-        Ok(())
-    }
 
-    /// Read directory entries
-    pub fn read_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
-        let parent = self.resolve(path)?;
-        Fnode::read_dir(parent, path, &self.fcache, &self.vol)
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let fnode_ref =
+                self.new_fnode(&parent, &name, ftype, Options::default())?;
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_special_attrs(rdev, link_target);
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Create(path.to_path_buf()));
+
+        Ok(())
     }
 
-    /// Get metadata of specified path
-    pub fn metadata(&self, path: &Path) -> Result<Metadata> {
+    /// Read symbolic link target
+    pub fn read_link(&self, path: &Path) -> Result<PathBuf> {
         let fnode_ref = self.resolve(path)?;
         let fnode = fnode_ref.read().unwrap();
-        Ok(fnode.metadata())
+        fnode
+            .get_link_target()
+            .map(|target| target.to_path_buf())
+            .ok_or(Error::InvalidArgument)
     }
 
-    /// Get file version list of specified path
-    pub fn history(&self, path: &Path) -> Result<Vec<Version>> {
-        let fnode_ref = self.resolve(path)?;
-        let fnode = fnode_ref.read().unwrap();
-        if fnode.is_dir() {
-            return Err(Error::IsDir);
+    /// Resolve symbolic link at path to its final target fnode, relative
+    /// link target is relative to the link's parent directory
+    pub fn follow_link(&self, path: &Path) -> Result<FnodeRef> {
+        let mut path = path.to_path_buf();
+        for _ in 0..Self::MAX_LINK_FOLLOWS {
+            let target = self.read_link(&path)?;
+            let target = path.parent().ok_or(Error::IsRoot)?.join(target);
+
+            // normalise target path, '..' beyond root stays at root
+            path = PathBuf::from("/");
+            for comp in target.components() {
+                match comp {
+                    Component::Normal(name) => path.push(name),
+                    Component::ParentDir => {
+                        path.pop();
+                    }
+                    _ => {}
+                }
+            }
+
+            let fnode_ref = self.resolve(&path)?;
+            if !fnode_ref.read().unwrap().is_symlink() {
+                return Ok(fnode_ref);
+            }
         }
-        Ok(fnode.history())
+        Err(Error::NotFound)
     }
 
-    /// Copy a regular file to another
-    pub fn copy(&mut self, from: &Path, to: &Path) -> Result<()> {
-        if self.read_only {
-            return Err(Error::ReadOnly);
+    /// Watch changes on path
+    pub fn watch(
+        &self,
+        path: &Path,
+        recursive: bool,
+    ) -> Result<Receiver<WatchEvent>> {
+        self.resolve(path)?;
+        let mut watchers = self.watchers.write().unwrap();
+        Ok(watchers.add(path, recursive))
+    }
+
+    // deliver a committed change event to watchers
+    #[inline]
+    fn notify(&self, event: WatchEvent) {
+        let mut watchers = self.watchers.write().unwrap();
+        if !watchers.is_empty() {
+            watchers.notify(event);
         }
+    }
 
-        let opts;
-        let src = self.resolve(from)?;
-        {
-            let fnode = src.read().unwrap();
-            if !fnode.is_file() {
-                return Err(Error::NotFile);
+    /// Walk directory recursively
+    #[inline]
+    pub fn walk_dir<'a>(fs: &'a FsRef, path: &Path) -> WalkDir<'a> {
+        WalkDir::new(fs, path)
+    }
+
+    // get roots of the main tree, trash tree and all snapshot trees,
+    // including those held by frozen views, temporary files are included
+    // as well
+    fn all_roots(&self) -> Result<Vec<FnodeRef>> {
+        let (mut roots, temps) = match self.view {
+            Some(ref root_id) => {
+                let mut roots = vec![Fnode::load_root(root_id, &self.vol)?];
+                roots.extend(Trash::load(root_id, &self.vol)?);
+                (roots, TempFiles::load(root_id, &self.vol)?)
+            }
+            None => {
+                let mut roots = vec![self.root.clone()];
+                roots.extend(self.trash_root.clone());
+                (roots, self.temps.clone())
+            }
+        };
+        if let Some(temps) = temps {
+            for id in temps.read().unwrap().list() {
+                roots.push(Fnode::load_root(id, &self.vol)?);
             }
-            opts = fnode.get_opts();
         }
+        if let Some(ref snaps) = self.snaps {
+            let snaps = snaps.read().unwrap();
+            for snap in snaps.list() {
+                roots.push(Fnode::load_root(snap.root_id(), &self.vol)?);
+            }
+        }
+        Ok(roots)
+    }
 
-        let tgt = {
-            match self.open_fnode(to) {
-                Ok(tgt) => {
-                    {
-                        // if target and source are same fnode, do nothing
+    /// Verify integrity of the whole file system
+    pub fn verify(&self) -> Result<VerifyReport> {
+        // snapshot trees share contents with the root tree, so they are
+        // verified as well to count all content references
+        let roots = self.all_roots()?;
+        Verifier::new(&self.store, &self.fcache, &self.vol).verify(&roots)
+    }
+
+    /// Reclaim space occupied by deleted data, return the number of
+    /// recycled transactions
+    ///
+    /// All retained committed transactions are recycled first, so data
+    /// deleted by them is removed from the volume. The underlying storage
+    /// is then compacted.
+    pub fn compact(
+        &mut self,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let recycled = {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.recycle_all()?
+        };
+        let mut vol = self.vol.write().unwrap();
+        vol.compact(progress)?;
+        Ok(recycled)
+    }
+
+    /// Re-encrypt the whole file system using a new cipher
+    pub fn migrate_cipher(&mut self, pwd: &str, cipher: Cipher) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // recycle committed wals first, so no deleted data is left behind
+        {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.recycle_all()?;
+        }
+
+        {
+            let mut vol = self.vol.write().unwrap();
+            vol.begin_cipher_migration(pwd, cipher)?;
+        }
+
+        let (roots, ids) = self.reencrypt_targets()?;
+        let cnt = self.reencrypt_all(&roots, &ids, &mut |_, _| Ok(()))?;
+
+        let mut vol = self.vol.write().unwrap();
+        vol.end_cipher_migration(pwd)?;
+        debug!(
+            "cipher migrated to {:?}, {} entities re-encrypted",
+            cipher, cnt
+        );
+        Ok(())
+    }
+
+    /// Re-encrypt the whole file system using a new data key, return the
+    /// number of re-encrypted entities, including those re-encrypted before
+    /// the rotation was interrupted
+    pub fn reencrypt(
+        &mut self,
+        pwd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // recycle committed wals first, so no deleted data is left behind
+        {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.recycle_all()?;
+        }
+
+        // start a new rotation or resume from the last checkpoint, password
+        // hash is kept so password is not hashed again for each checkpoint
+        let (base, pwd_key) = {
+            let mut vol = self.vol.write().unwrap();
+            vol.begin_key_rotation(pwd)?
+        };
+
+        // count entities first, so progress can be reported
+        let (roots, ids) = self.reencrypt_targets()?;
+        let total = Migrator::new(&self.store, &self.fcache, &self.vol)
+            .count(&roots, &ids)?;
+
+        // save checkpoint periodically while entities are re-encrypted
+        let vol = self.vol.clone();
+        let mut ckpt = base;
+        let cnt = self.reencrypt_all(&roots, &ids, &mut |done, cnt| {
+            progress(done, total);
+            if base + cnt >= ckpt + Self::CHECKPOINT_INTERVAL {
+                ckpt = base + cnt;
+                let mut vol = vol.write().unwrap();
+                vol.checkpoint_key_rotation(&pwd_key, ckpt)?;
+            }
+            Ok(())
+        })?;
+
+        let mut vol = self.vol.write().unwrap();
+        vol.end_key_rotation(&pwd_key)?;
+        debug!("data key rotated, {} entities re-encrypted", base + cnt);
+        Ok(base + cnt)
+    }
+
+    // collect all tree roots and standalone entities to be re-encrypted
+    fn reencrypt_targets(&self) -> Result<(Vec<FnodeRef>, Vec<Eid>)> {
+        let roots = self.all_roots()?;
+        let mut ids = vec![self.store.read().unwrap().id().clone()];
+        if let Some(ref snaps) = self.snaps {
+            ids.push(snaps.read().unwrap().id().clone());
+        }
+        if let Some(ref temps) = self.temps {
+            ids.push(temps.read().unwrap().id().clone());
+        }
+        Ok((roots, ids))
+    }
+
+    // re-encrypt all entities and wals which are not encrypted by current
+    // cipher and key, return the number of re-encrypted entities
+    //
+    // `hook` is called with the number of visited and re-encrypted entities
+    // after each entity is visited
+    fn reencrypt_all(
+        &self,
+        roots: &[FnodeRef],
+        ids: &[Eid],
+        hook: &mut dyn FnMut(usize, usize) -> Result<()>,
+    ) -> Result<usize> {
+        let mut cnt = Migrator::new(&self.store, &self.fcache, &self.vol)
+            .with_hook(hook)
+            .migrate(roots, ids)?;
+
+        // migration might have generated new wals, recycle them and then
+        // re-encrypt the wal queue
+        let mut txmgr = self.txmgr.write().unwrap();
+        txmgr.recycle_all()?;
+        cnt += txmgr.reencrypt_wals()?;
+
+        Ok(cnt)
+    }
+
+    /// Get default options for a new fnode to be created at path, which are
+    /// inherited from its parent directories
+    pub fn get_inherited_opts(&self, path: &Path) -> Result<Options> {
+        let (parent, _) = self.resolve_parent(path)?;
+        Ok(Fnode::inherited_opts(&parent, self.opts))
+    }
+
+    /// Get directory default options
+    pub fn dir_opts(&self, path: &Path) -> Result<DirOptions> {
+        let fnode_ref = self.resolve(path)?;
+        let fnode = fnode_ref.read().unwrap();
+        if !fnode.is_dir() {
+            return Err(Error::NotDir);
+        }
+        Ok(fnode.get_dir_opts())
+    }
+
+    /// Set directory default options
+    pub fn set_dir_opts(
+        &mut self,
+        path: &Path,
+        dir_opts: DirOptions,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+        }
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_dir_opts(dir_opts);
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Set permissions and ownership
+    pub fn set_permissions(
+        &mut self,
+        path: &Path,
+        perms: Permissions,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_permissions(perms);
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Set creation and modification time
+    pub fn set_times(
+        &mut self,
+        path: &Path,
+        ctime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_times(
+                ctime.map(Time::from_system_time),
+                mtime.map(Time::from_system_time),
+            );
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Recursively create directories along the path
+    pub fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        match self.create_fnode(path, FileType::Dir, Options::default()) {
+            Ok(_) => return Ok(()),
+            Err(ref err) if *err == Error::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        match path.parent() {
+            Some(p) => self.create_dir_all(p)?,
+            None => return Err(Error::IsRoot),
+        }
+        self.create_fnode(path, FileType::Dir, Options::default())?;
+        Ok(())
+    }
+
+    /// Read directory entries
+    pub fn read_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
+        let parent = self.resolve(path)?;
+        Fnode::read_dir(parent, path, &self.fcache, &self.vol)
+    }
+
+    /// Read entries and fnodes of directory fnode at path
+    pub fn read_dir_nodes(
+        &self,
+        fnode: FnodeRef,
+        path: &Path,
+    ) -> Result<Vec<(DirEntry, FnodeRef)>> {
+        Fnode::read_dir_nodes(fnode, path, &self.fcache, &self.vol)
+    }
+
+    /// Get metadata of specified path
+    pub fn metadata(&self, path: &Path) -> Result<Metadata> {
+        let fnode_ref = self.resolve(path)?;
+        let fnode = fnode_ref.read().unwrap();
+        Ok(fnode.metadata())
+    }
+
+    /// Get file version list of specified path
+    pub fn history(&self, path: &Path) -> Result<Vec<Version>> {
+        let fnode_ref = self.resolve(path)?;
+        let fnode = fnode_ref.read().unwrap();
+        if fnode.is_dir() {
+            return Err(Error::IsDir);
+        }
+        Ok(fnode.history())
+    }
+
+    /// Copy a regular file to another
+    pub fn copy(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let opts;
+        let src = self.resolve(from)?;
+        {
+            let fnode = src.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+            opts = fnode.get_opts();
+        }
+        let (tgt_parent, _) = self.resolve_parent(to)?;
+        Self::check_key_domain(&src, &tgt_parent)?;
+
+        let tgt = {
+            match self.open_fnode(to) {
+                Ok(tgt) => {
+                    {
+                        // if target and source are same fnode, do nothing
                         if Arc::ptr_eq(&tgt.fnode, &src) {
                             return Ok(());
                         }
 
-                        let fnode = tgt.fnode.read().unwrap();
-                        if !fnode.is_file() {
-                            return Err(Error::NotFile);
-                        }
-                    }
-                    tgt
-                }
-                Err(ref err) if *err == Error::NotFound => {
-                    // target file doesn't exist
-                    self.create_fnode(to, FileType::File, opts)?;
-                    self.open_fnode(to)?
-                }
-                Err(err) => return Err(err),
+                        let fnode = tgt.fnode.read().unwrap();
+                        if !fnode.is_file() {
+                            return Err(Error::NotFile);
+                        }
+                    }
+                    tgt
+                }
+                Err(ref err) if *err == Error::NotFound => {
+                    // target file doesn't exist
+                    self.create_fnode(to, FileType::File, opts)?;
+                    self.open_fnode(to)?
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            // get current version of source
+            let ctn = {
+                let fnode = src.read().unwrap();
+                fnode.clone_current_content(&self.store)?
+            };
+
+            // then add it to target
+            self.add_version(&tgt.fnode, ctn)
+        })?;
+        self.notify(WatchEvent::Modify(to.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Clone a file to a new file, the new file shares content with source
+    /// and its options and permissions are copied from source
+    pub fn clone_file(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let src = self.resolve(from)?;
+        let (opts, perms) = {
+            let fnode = src.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+            (fnode.get_opts(), fnode.metadata().permissions())
+        };
+
+        self.path_policy.check(to)?;
+        let (parent, name) = self.resolve_parent(to)?;
+        {
+            let parent = parent.read().unwrap();
+            if !parent.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if parent.has_child(&name) {
+                return Err(Error::AlreadyExists);
+            }
+        }
+        Self::check_key_domain(&src, &parent)?;
+
+        // create the file and add the cloned content in one transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let tgt = self.new_fnode(&parent, &name, FileType::File, opts)?;
+            if let Some(perms) = perms {
+                let mut fnode_cow = tgt.write().unwrap();
+                let fnode = fnode_cow.make_mut(&self.txmgr)?;
+                fnode.set_permissions(perms);
+            }
+            let ctn = {
+                let fnode = src.read().unwrap();
+                fnode.clone_current_content(&self.store)?
+            };
+            self.add_version(&tgt, ctn)
+        })?;
+        self.notify(WatchEvent::Create(to.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Copy a range of one file's current content into another file by
+    /// cloning its content extents, return the number of bytes copied
+    pub fn copy_file_range(
+        &mut self,
+        src: &Path,
+        src_off: usize,
+        dst: &Path,
+        dst_off: usize,
+        len: usize,
+    ) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let src_fnode = self.resolve(src)?;
+        let src_len = {
+            let fnode = src_fnode.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+            fnode.curr_len()
+        };
+
+        let tgt = self.open_fnode(dst)?;
+        Self::check_key_domain(&src_fnode, &tgt.fnode)?;
+        {
+            let fnode = tgt.fnode.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+
+            // holes are not supported, so destination range must start
+            // within the file
+            if dst_off > fnode.curr_len() {
+                return Err(Error::InvalidArgument);
+            }
+        }
+
+        // copy stops at the end of source file
+        let len = min(len, src_len.saturating_sub(src_off));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            // clone source range as stage content
+            let stg_ctn = {
+                let fnode = src_fnode.read().unwrap();
+                let ctn = fnode.clone_current_content(&self.store)?;
+                ctn.clone_range(src_off, len, dst_off, &self.store)?
+            };
+
+            // merge it to target current content and add as a new version
+            let ctn = {
+                let fnode = tgt.fnode.read().unwrap();
+                let mut ctn = fnode.clone_current_content(&self.store)?;
+                ctn.merge_from(&stg_ctn, &self.store)?;
+                ctn
+            };
+            self.add_content(&tgt.fnode, ctn).map(|_| ())
+        })?;
+        self.notify(WatchEvent::Modify(dst.to_path_buf()));
+
+        Ok(len)
+    }
+
+    /// Copy a dir to another recursively
+    pub fn copy_dir_all(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // if target and source are same fnode, do nothing
+        if from == to {
+            return Ok(());
+        }
+
+        if to.starts_with(from) {
+            return Err(Error::InvalidArgument);
+        }
+
+        // sanity check source and target
+        let src = self.resolve(from)?;
+        {
+            let fnode = src.read().unwrap();
+            if !fnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+        }
+        let tgt = match self.resolve(to) {
+            Ok(tgt) => {
+                assert!(!Arc::ptr_eq(&tgt, &src));
+                {
+                    let fnode = tgt.read().unwrap();
+                    if !fnode.is_dir() {
+                        return Err(Error::NotDir);
+                    }
+                }
+                Some(tgt)
+            }
+            Err(ref err) if *err == Error::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        if tgt.is_none() {
+            self.path_policy.check(to)?;
+        }
+        let (tgt_parent, name) = self.resolve_parent(to)?;
+
+        // copy the whole dir tree in one transaction
+        let mut events = Vec::new();
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            // create target dir if it doesn't exist
+            let tgt = match tgt {
+                Some(tgt) => tgt,
+                None => {
+                    events.push(WatchEvent::Create(to.to_path_buf()));
+                    self.new_fnode(
+                        &tgt_parent,
+                        &name,
+                        FileType::Dir,
+                        Options::default(),
+                    )?
+                }
+            };
+            self.copy_tree(&src, from, &tgt, to, CopyMode::Content, &mut events)
+        })?;
+        for event in events {
+            self.notify(event);
+        }
+
+        Ok(())
+    }
+
+    // copy children of source dir fnode at `from` to target dir fnode at
+    // `to` recursively, changes are added to `events`, this must be called
+    // in a transaction
+    //
+    // Snapshot and restore copies keep key domains, times, permissions,
+    // directory options and quotas, snapshot copies also keep all file
+    // versions. Restore copies only add a new version to files whose
+    // content differs from the source.
+    fn copy_tree(
+        &self,
+        src: &FnodeRef,
+        from: &Path,
+        tgt: &FnodeRef,
+        to: &Path,
+        mode: CopyMode,
+        events: &mut Vec<WatchEvent>,
+    ) -> Result<()> {
+        for (child, child_src) in self.read_dir_nodes(src.clone(), from)? {
+            let child_from = child.path();
+            let name = child.file_name();
+            let md = child.metadata();
+            let child_to = to.join(name);
+
+            // get existing child in target dir
+            let tgt_child = {
+                let has_child = tgt.read().unwrap().has_child(name);
+                if has_child {
+                    Some(Fnode::child(tgt, name, &self.fcache, &self.vol)?)
+                } else {
+                    None
+                }
+            };
+            let event = match tgt_child {
+                Some(_) if md.is_dir() => None,
+                Some(_) => Some(WatchEvent::Modify(child_to.clone())),
+                None => Some(WatchEvent::Create(child_to.clone())),
+            };
+
+            match md.file_type() {
+                FileType::File => {
+                    let tgt_child = match tgt_child {
+                        Some(tgt_child) => {
+                            if !tgt_child.read().unwrap().is_file() {
+                                return Err(Error::NotFile);
+                            }
+                            Self::check_key_domain(&child_src, &tgt_child)?;
+                            tgt_child
+                        }
+                        None if mode == CopyMode::Content => {
+                            Self::check_key_domain(&child_src, tgt)?;
+                            let opts = child_src.read().unwrap().get_opts();
+                            self.new_fnode(tgt, name, FileType::File, opts)?
+                        }
+                        None => {
+                            // new file gets the whole version history
+                            let tgt_child =
+                                self.new_fnode_like(tgt, name, &child_src)?;
+                            self.copy_versions(&tgt_child, &child_src)?;
+                            self.copy_attrs(&tgt_child, &child_src)?;
+                            events.extend(event);
+                            continue;
+                        }
+                    };
+
+                    // skip restoring file whose content is not changed
+                    let src = child_src.read().unwrap();
+                    if mode == CopyMode::Restore
+                        && self
+                            .same_content(&src, &tgt_child.read().unwrap())?
+                    {
+                        drop(src);
+                        self.copy_attrs(&tgt_child, &child_src)?;
+                        continue;
+                    }
+
+                    // add source current version to target, the content is
+                    // cloned so its chunks are shared rather than copied
+                    let ctn = src.clone_current_content(&self.store)?;
+                    drop(src);
+                    self.add_version(&tgt_child, ctn)?;
+                    if mode == CopyMode::Restore {
+                        self.copy_attrs(&tgt_child, &child_src)?;
+                    }
+                }
+                FileType::Dir => {
+                    let tgt_child = match tgt_child {
+                        Some(tgt_child) => {
+                            if !tgt_child.read().unwrap().is_dir() {
+                                return Err(Error::NotDir);
+                            }
+                            tgt_child
+                        }
+                        None if mode == CopyMode::Content => self.new_fnode(
+                            tgt,
+                            name,
+                            FileType::Dir,
+                            Options::default(),
+                        )?,
+                        None => self.new_fnode_like(tgt, name, &child_src)?,
+                    };
+                    self.copy_tree(
+                        &child_src, child_from, &tgt_child, &child_to, mode,
+                        events,
+                    )?;
+                }
+                ftype => {
+                    // special file has no content, so re-create it
+                    if let Some(tgt_child) = tgt_child {
+                        if tgt_child.read().unwrap().is_dir() {
+                            return Err(Error::NotFile);
+                        }
+                        self.remove_fnode(&tgt_child)?;
+                    }
+                    let link_target = child_src
+                        .read()
+                        .unwrap()
+                        .get_link_target()
+                        .map(Path::to_path_buf);
+                    let fnode_ref = if mode == CopyMode::Content {
+                        self.new_fnode(tgt, name, ftype, Options::default())?
+                    } else {
+                        self.new_fnode_like(tgt, name, &child_src)?
+                    };
+                    {
+                        let mut fnode_cow = fnode_ref.write().unwrap();
+                        let fnode = fnode_cow.make_mut(&self.txmgr)?;
+                        fnode.set_special_attrs(
+                            md.rdev(),
+                            link_target.as_deref(),
+                        );
+                    }
+                    if mode != CopyMode::Content {
+                        self.copy_attrs(&fnode_ref, &child_src)?;
+                    }
+                }
+            }
+
+            events.extend(event);
+        }
+
+        // directory attributes are copied after its children, so its quota
+        // usage can be counted from them
+        if mode != CopyMode::Content {
+            self.copy_attrs(tgt, src)?;
+            self.copy_quota(tgt, src, to)?;
+        }
+
+        Ok(())
+    }
+
+    // create a new fnode like source fnode under parent, it is in the key
+    // domain of source fnode, this must be called in a transaction
+    fn new_fnode_like(
+        &self,
+        parent: &FnodeRef,
+        name: &str,
+        src: &FnodeRef,
+    ) -> Result<FnodeRef> {
+        let (ftype, opts, domain, check) = {
+            let src = src.read().unwrap();
+            (
+                src.metadata().file_type(),
+                src.get_opts(),
+                src.key_domain().cloned(),
+                src.domain_check().map(|check| check.to_vec()),
+            )
+        };
+        let fnode_ref =
+            self.new_fnode_in(parent, name, ftype, opts, domain.clone())?;
+        if let (Some(domain), Some(check)) = (domain, check) {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_key_domain(&domain, check);
+        }
+        Ok(fnode_ref)
+    }
+
+    // check if two files have the same current content
+    fn same_content(&self, src: &Fnode, tgt: &Fnode) -> Result<bool> {
+        let (src_ver, tgt_ver) =
+            match (src.ver(src.curr_ver_num()), tgt.ver(tgt.curr_ver_num())) {
+                (Some(src_ver), Some(tgt_ver)) => (src_ver, tgt_ver),
+                _ => return Ok(false),
+            };
+        if src_ver.content_len() != tgt_ver.content_len() {
+            return Ok(false);
+        }
+        if src_ver.content_id() == tgt_ver.content_id() {
+            return Ok(true);
+        }
+
+        // content is not shared without file dedup, so compare its hash
+        let store = self.store.read().unwrap();
+        let src_ctn = store.get_content(src_ver.content_id())?;
+        let tgt_ctn = store.get_content(tgt_ver.content_id())?;
+        let src_ctn = src_ctn.read().unwrap();
+        let tgt_ctn = tgt_ctn.read().unwrap();
+        Ok(src_ctn.hash() == tgt_ctn.hash())
+    }
+
+    // copy attributes of source fnode to target fnode, this must be called
+    // in a transaction
+    fn copy_attrs(&self, tgt: &FnodeRef, src: &FnodeRef) -> Result<()> {
+        let src = src.read().unwrap();
+        let mut tgt_cow = tgt.write().unwrap();
+        tgt_cow.make_mut(&self.txmgr)?.copy_attrs(&src);
+        Ok(())
+    }
+
+    // copy quota limit of source dir to target dir, usage of target is
+    // counted from its children if it had no quota, this must be called in
+    // a transaction
+    fn copy_quota(
+        &self,
+        tgt: &FnodeRef,
+        src: &FnodeRef,
+        to: &Path,
+    ) -> Result<()> {
+        let limit = src.read().unwrap().quota().map(|quota| quota.limit());
+        let curr = tgt.read().unwrap().quota();
+        let quota = match (limit, curr) {
+            (Some(limit), Some(curr)) => Some(Quota::new(limit, curr.used())),
+            (Some(limit), None) => {
+                Some(Quota::new(limit, self.children_usage(tgt, to)?))
+            }
+            (None, None) => return Ok(()),
+            (None, Some(_)) => None,
+        };
+        let mut tgt_cow = tgt.write().unwrap();
+        tgt_cow.make_mut(&self.txmgr)?.set_quota(quota);
+        Ok(())
+    }
+
+    // replace all versions of target file with versions of source file,
+    // this must be called in a transaction
+    fn copy_versions(&self, tgt: &FnodeRef, src: &FnodeRef) -> Result<()> {
+        let (delta, vers_delta) = {
+            let src = src.read().unwrap();
+            let mut fnode_cow = tgt.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            let old_len = fnode.versions_len() as i64;
+            let old_cnt = fnode.versions_cnt() as i64;
+            fnode.copy_versions(&src, &self.store, &self.txmgr)?;
+            (
+                fnode.versions_len() as i64 - old_len,
+                fnode.versions_cnt() as i64 - old_cnt,
+            )
+        };
+        Fnode::charge_quota(tgt, delta, &self.txmgr)?;
+        self.add_counts(tgt, EntryCounts::versions(vers_delta))?;
+        Ok(())
+    }
+
+    // create a new fnode under parent in parent's key domain and count it,
+    // this must be called in a transaction
+    fn new_fnode(
+        &self,
+        parent: &FnodeRef,
+        name: &str,
+        ftype: FileType,
+        opts: Options,
+    ) -> Result<FnodeRef> {
+        let domain = parent.read().unwrap().key_domain().cloned();
+        self.new_fnode_in(parent, name, ftype, opts, domain)
+    }
+
+    // create a new fnode under parent in a key domain and count it, this
+    // must be called in a transaction
+    fn new_fnode_in(
+        &self,
+        parent: &FnodeRef,
+        name: &str,
+        ftype: FileType,
+        opts: Options,
+        domain: Option<Eid>,
+    ) -> Result<FnodeRef> {
+        let fnode_ref = Fnode::new_under(
+            parent,
+            name,
+            ftype,
+            opts,
+            domain,
+            &self.txmgr,
+            &self.store,
+        )?;
+        let counts = fnode_ref.read().unwrap().entry_counts();
+        self.add_counts(&fnode_ref, counts)?;
+        Ok(fnode_ref)
+    }
+
+    // add entry counts change of a fnode to current transaction, changes in
+    // snapshot trees are not counted, this must be called before the fnode
+    // is removed from its parent
+    fn add_counts(
+        &self,
+        fnode_ref: &FnodeRef,
+        delta: EntryCounts,
+    ) -> Result<()> {
+        if Fnode::is_under(fnode_ref, &self.root) {
+            self.txmgr.read().unwrap().add_counts(delta)?;
+        }
+        Ok(())
+    }
+
+    // add content as a new version to file and charge its size change to
+    // quotas, this must be called in a transaction
+    fn add_version(&self, fnode_ref: &FnodeRef, ctn: Content) -> Result<()> {
+        let result = self.add_content(fnode_ref, ctn)?;
+        assert!(!(self.opts.dedup_file && result));
+        Ok(())
+    }
+
+    // add content as a new version of a file, return true if the content is
+    // not duplicated
+    fn add_content(&self, fnode_ref: &FnodeRef, ctn: Content) -> Result<bool> {
+        let (result, delta, vers_delta) = {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            let old_len = fnode.versions_len() as i64;
+            let old_cnt = fnode.versions_cnt() as i64;
+            let result = fnode.add_version(ctn, &self.store, &self.txmgr)?;
+            (
+                result,
+                fnode.versions_len() as i64 - old_len,
+                fnode.versions_cnt() as i64 - old_cnt,
+            )
+        };
+        Fnode::charge_quota(fnode_ref, delta, &self.txmgr)?;
+        self.add_counts(fnode_ref, EntryCounts::versions(vers_delta))?;
+        Ok(result)
+    }
+
+    // get usage of a fnode, that is total size of all file versions in its
+    // tree, usage tracked by quota is used for directories having quota
+    fn usage(&self, fnode_ref: &FnodeRef, path: &Path) -> Result<u64> {
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_dir() {
+                return Ok(fnode.versions_len());
+            }
+            if let Some(quota) = fnode.quota() {
+                return Ok(quota.used());
+            }
+        }
+        self.children_usage(fnode_ref, path)
+    }
+
+    // get total usage of all children of a directory
+    fn children_usage(&self, fnode_ref: &FnodeRef, path: &Path) -> Result<u64> {
+        let mut used = 0;
+        for (ent, child) in self.read_dir_nodes(fnode_ref.clone(), path)? {
+            used += self.usage(&child, ent.path())?;
+        }
+        Ok(used)
+    }
+
+    // count files, directories and file versions in the tree under a
+    // directory, the directory itself is not counted
+    fn count_children(
+        &self,
+        fnode_ref: &FnodeRef,
+        path: &Path,
+    ) -> Result<EntryCounts> {
+        let mut counts = EntryCounts::default();
+        for (ent, child) in self.read_dir_nodes(fnode_ref.clone(), path)? {
+            counts += child.read().unwrap().entry_counts();
+            if ent.metadata().is_dir() {
+                counts += self.count_children(&child, ent.path())?;
+            }
+        }
+        Ok(counts)
+    }
+
+    // count a fnode and the tree under it
+    fn count_tree(
+        &self,
+        fnode_ref: &FnodeRef,
+        path: &Path,
+    ) -> Result<EntryCounts> {
+        let (mut counts, is_dir) = {
+            let fnode = fnode_ref.read().unwrap();
+            (fnode.entry_counts(), fnode.is_dir())
+        };
+        if is_dir {
+            counts += self.count_children(fnode_ref, path)?;
+        }
+        Ok(counts)
+    }
+
+    /// Get disk usage of the tree under a path
+    pub fn du(&self, path: &Path) -> Result<DiskUsage> {
+        let fnode_ref = self.resolve(path)?;
+        UsageCounter::new(&self.store, &self.fcache, &self.vol)
+            .count(&fnode_ref)
+    }
+
+    /// Export metadata of the whole tree as lines of JSON objects
+    pub fn export_metadata(&self, w: &mut dyn Write) -> Result<()> {
+        let root = self.resolve(Path::new("/"))?;
+        MetaExporter::new(&self.store, &self.fcache, &self.vol).export(
+            &root,
+            Path::new("/"),
+            w,
+        )
+    }
+
+    /// Set or remove quota of a directory
+    pub fn set_quota(&mut self, path: &Path, limit: Option<u64>) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+        if !fnode_ref.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
+        }
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let quota = match limit {
+                Some(limit) => {
+                    let used = self.children_usage(&fnode_ref, path)?;
+                    Some(Quota::new(limit, used))
+                }
+                None => None,
+            };
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_quota(quota);
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Make an empty directory the root of a key domain, or unlock the key
+    /// domain if the directory is already a domain root
+    pub fn set_key_domain(&mut self, path: &Path, key: &[u8]) -> Result<()> {
+        if key.len() != KEY_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let mut domain_key = Key::new_empty();
+        domain_key.copy(key);
+
+        let fnode_ref = self.resolve(path)?;
+        let (domain, check) = {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if fnode.domain_check().is_none() && fnode.children_cnt() > 0 {
+                return Err(Error::NotEmpty);
+            }
+            (
+                fnode.key_domain().cloned(),
+                fnode.domain_check().map(|check| check.to_vec()),
+            )
+        };
+
+        // unlock existing key domain
+        if let (&Some(ref domain), &Some(ref check)) = (&domain, &check) {
+            let mut vol = self.vol.write().unwrap();
+            return vol.unlock_key_domain(domain, domain_key, check);
+        }
+
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // content would not be encrypted by domain key in integrity-only mode
+        if !self.vol.read().unwrap().info().cipher.is_confidential() {
+            return Err(Error::InvalidArgument);
+        }
+
+        // new key domain nested in a locked one can only be created with
+        // the outer domain unlocked
+        self.check_key_domain_unlocked(domain.as_ref())?;
+
+        // create a new key domain and save it to the directory
+        let (domain, check) =
+            self.vol.write().unwrap().new_key_domain(domain_key)?;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let mut fnode_cow = fnode_ref.write().unwrap();
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.set_key_domain(&domain, check.clone());
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Get key domain a new file created at path will be in
+    pub fn key_domain_of(&self, path: &Path) -> Result<Option<Eid>> {
+        let (parent, _) = self.resolve_parent(path)?;
+        let parent = parent.read().unwrap();
+        Ok(parent.key_domain().cloned())
+    }
+
+    /// Check if a key domain is unlocked, content not in any key domain can
+    /// always be accessed
+    pub fn check_key_domain_unlocked(
+        &self,
+        domain: Option<&Eid>,
+    ) -> Result<()> {
+        match domain {
+            Some(domain)
+                if !self.vol.read().unwrap().is_domain_unlocked(domain) =>
+            {
+                Err(Error::DomainLocked)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // check if content can be shared between two fnodes, file content is
+    // encrypted by the key domain it is in, so it cannot be shared across
+    // key domains
+    fn check_key_domain(src: &FnodeRef, tgt: &FnodeRef) -> Result<()> {
+        if !Self::same_key_domain(src, tgt) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    // check if two fnodes are in the same key domain
+    fn same_key_domain(src: &FnodeRef, tgt: &FnodeRef) -> bool {
+        let src = src.read().unwrap();
+        let tgt = tgt.read().unwrap();
+        src.key_domain() == tgt.key_domain()
+    }
+
+    /// Get quota of a directory
+    pub fn quota(&self, path: &Path) -> Result<Option<Quota>> {
+        let fnode_ref = self.resolve(path)?;
+        let fnode = fnode_ref.read().unwrap();
+        if !fnode.is_dir() {
+            return Err(Error::NotDir);
+        }
+        Ok(fnode.quota())
+    }
+
+    // remove a non-dir fnode and its versions, this must be called in a
+    // transaction
+    fn remove_fnode(&self, fnode_ref: &FnodeRef) -> Result<()> {
+        let (len, counts) = {
+            let fnode = fnode_ref.read().unwrap();
+            (fnode.versions_len(), fnode.entry_counts())
+        };
+        Fnode::charge_quota(fnode_ref, -(len as i64), &self.txmgr)?;
+        self.add_counts(fnode_ref, -counts)?;
+
+        // detached fnode, such as temporary file, has no parent
+        if !fnode_ref.read().unwrap().is_root() {
+            Fnode::remove_from_parent(fnode_ref, &self.txmgr)?;
+        }
+
+        let mut fnode = fnode_ref.write().unwrap();
+        fnode
+            .make_mut(&self.txmgr)?
+            .clear_versions(&self.store, &self.txmgr)?;
+        fnode.make_del(&self.txmgr)?;
+        self.fcache.remove(fnode.id());
+        Ok(())
+    }
+
+    /// Remove a regular file or special file
+    pub fn remove_file(&mut self, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if fnode.is_dir() {
+                return Err(Error::NotFile);
+            }
+        }
+        if self.use_trash(path) {
+            return self.move_to_trash(fnode_ref, path);
+        }
+
+        // begin and run transaction, fnode ref is moved into the closure so
+        // it is released before commit
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || fs.remove_fnode(&fnode_ref))?;
+        self.notify(WatchEvent::Remove(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Remove a regular file or special file and shred its data
+    ///
+    /// The file is always removed permanently, trash is not used. All
+    /// committed transactions are then recycled with shredding turned on,
+    /// so the blocks freed by the removal are overwritten before deletion.
+    pub fn shred(&mut self, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if fnode.is_dir() {
+                return Err(Error::NotFile);
+            }
+        }
+
+        // recycle committed wals first, this also makes sure no other
+        // transaction is in progress before the file is removed
+        {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.recycle_all()?;
+        }
+
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || fs.remove_fnode(&fnode_ref))?;
+        self.notify(WatchEvent::Remove(path.to_path_buf()));
+
+        // recycle the removal with shredding turned on, shredding must be
+        // turned off again whether it succeeded or not
+        self.vol.write().unwrap().set_shred(true);
+        let result = self.txmgr.write().unwrap().recycle_all();
+        self.vol.write().unwrap().set_shred(false);
+        result.map(|_| ())
+    }
+
+    /// Remove an existing empty directory
+    pub fn remove_dir(&mut self, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if fnode.is_root() {
+                return Err(Error::IsRoot);
+            }
+            if fnode.children_cnt() > 0 {
+                return Err(Error::NotEmpty);
+            }
+        }
+        if self.use_trash(path) {
+            return self.move_to_trash(fnode_ref, path);
+        }
+
+        // begin and run transaction, fnode ref is moved into the closure so
+        // it is released before commit
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(move || {
+            fs.add_counts(
+                &fnode_ref,
+                -fnode_ref.read().unwrap().entry_counts(),
+            )?;
+            Fnode::remove_from_parent(&fnode_ref, &fs.txmgr)?;
+            let mut fnode = fnode_ref.write().unwrap();
+            fnode.make_del(&fs.txmgr)?;
+            fs.fcache.remove(fnode.id());
+            Ok(())
+        })?;
+        self.notify(WatchEvent::Remove(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Remove an existing directory recursively
+    pub fn remove_dir_all(&mut self, path: &Path) -> Result<()> {
+        // move the whole directory to trash at once
+        if self.use_trash(path) && path.parent().is_some() {
+            let fnode_ref = self.resolve(path)?;
+            if !fnode_ref.read().unwrap().is_dir() {
+                return Err(Error::NotDir);
+            }
+            return self.move_to_trash(fnode_ref, path);
+        }
+
+        for child in self.read_dir(path)? {
+            let child_path = child.path();
+            match child.metadata().file_type() {
+                FileType::Dir => self.remove_dir_all(&child_path)?,
+                _ => self.remove_file(&child_path)?,
+            }
+        }
+        match self.remove_dir(path) {
+            Ok(_) => Ok(()),
+            Err(ref err) if *err == Error::IsRoot => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // remove a fnode and its whole sub tree, this must be called in a
+    // transaction
+    fn remove_tree(&self, fnode_ref: &FnodeRef, path: &Path) -> Result<()> {
+        let is_dir = fnode_ref.read().unwrap().is_dir();
+        if is_dir {
+            for (ent, child) in self.read_dir_nodes(fnode_ref.clone(), path)? {
+                self.remove_tree(&child, ent.path())?;
+            }
+        }
+
+        let (len, counts, is_root) = {
+            let fnode = fnode_ref.read().unwrap();
+            (fnode.versions_len(), fnode.entry_counts(), fnode.is_root())
+        };
+        Fnode::charge_quota(fnode_ref, -(len as i64), &self.txmgr)?;
+        if !is_root {
+            self.add_counts(fnode_ref, -counts)?;
+            Fnode::remove_from_parent(fnode_ref, &self.txmgr)?;
+        }
+
+        let mut fnode_cow = fnode_ref.write().unwrap();
+        {
+            let fnode = fnode_cow.make_mut(&self.txmgr)?;
+            fnode.clear_versions(&self.store, &self.txmgr)?;
+
+            // parent may be removed in the same transaction, so release it
+            // to let it be deleted
+            fnode.detach();
+        }
+        fnode_cow.make_del(&self.txmgr)?;
+        self.fcache.remove(fnode_cow.id());
+        Ok(())
+    }
+
+    // check if an entry should be moved to trash rather than removed
+    #[inline]
+    fn use_trash(&self, path: &Path) -> bool {
+        self.trash.is_some()
+    }
+
+    /// Set path policy for newly created entries
+    #[inline]
+    pub fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.path_policy = policy;
+    }
+
+    /// Set validator for new passwords
+    #[inline]
+    pub fn set_password_check(&mut self, pwd_check: Option<PasswordCheck>) {
+        self.pwd_check = pwd_check;
+    }
+
+    /// Enable or disable access time tracking
+    #[inline]
+    pub fn set_atime(&mut self, atime: bool) {
+        self.atime = atime;
+    }
+
+    /// Enable or disable trash, expired trash entries are removed
+    pub fn set_trash(&mut self, expiry: Option<Duration>) -> Result<()> {
+        self.trash = expiry;
+        if self.trash.is_some() && !self.read_only {
+            self.purge_trash()?;
+        }
+        Ok(())
+    }
+
+    // move a fnode to a new trash entry
+    fn move_to_trash(
+        &mut self,
+        fnode_ref: FnodeRef,
+        path: &Path,
+    ) -> Result<()> {
+        let id = Eid::new().to_string()[..16].to_string();
+        let root_id = self.root.read().unwrap().id().clone();
+        let mut trash_root = self.trash_root.clone();
+
+        // entries in trash are not counted
+        let counts = self.count_tree(&fnode_ref, path)?;
+
+        // each trash entry is a directory which has the removed fnode and a
+        // symbolic link to its original path
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            if trash_root.is_none() {
+                trash_root = Some(Trash::create(&root_id, &self.txmgr)?);
+            }
+            let trash = trash_root.as_ref().unwrap();
+            let entry =
+                self.new_fnode(trash, &id, FileType::Dir, Options::default())?;
+            let origin = self.new_fnode(
+                &entry,
+                Self::TRASH_ORIGIN,
+                FileType::Symlink,
+                Options::default(),
+            )?;
+            {
+                let mut origin_cow = origin.write().unwrap();
+                let origin = origin_cow.make_mut(&self.txmgr)?;
+                origin.set_special_attrs(0, Some(path));
+            }
+            self.add_counts(&fnode_ref, -counts)?;
+            self.move_fnode(&fnode_ref, path, &entry, Self::TRASH_DATA)
+        })?;
+        self.trash_root = trash_root;
+        self.notify(WatchEvent::Remove(path.to_path_buf()));
+
+        // release the moved fnode so that it can be purged if expired
+        drop(fnode_ref);
+        self.purge_trash()
+    }
+
+    // move a fnode to be a child of target directory and move its usage
+    // between quotas, this must be called in a transaction
+    fn move_fnode(
+        &self,
+        src: &FnodeRef,
+        from: &Path,
+        tgt_parent: &FnodeRef,
+        name: &str,
+    ) -> Result<()> {
+        let used = if Fnode::under_quota(src) || Fnode::under_quota(tgt_parent)
+        {
+            self.usage(src, from)? as i64
+        } else {
+            0
+        };
+        Fnode::charge_quota(src, -used, &self.txmgr)?;
+
+        // detached fnode, such as temporary file, has no parent
+        if !src.read().unwrap().is_root() {
+            Fnode::remove_from_parent(src, &self.txmgr)?;
+        }
+
+        Fnode::add_child(tgt_parent, src, name, &self.txmgr)?;
+        Fnode::charge_quota(src, used, &self.txmgr)
+    }
+
+    // get trash entries along with their directory fnodes, sorted by
+    // removal time
+    fn trash_entries(&self) -> Result<Vec<(TrashEntry, FnodeRef)>> {
+        let trash = match self.trash_root {
+            Some(ref trash) => trash.clone(),
+            None => return Ok(Vec::new()),
+        };
+
+        let mut entries = Vec::new();
+        for (ent, entry) in self.read_dir_nodes(trash, Path::new("/"))? {
+            let origin = Fnode::child(
+                &entry,
+                Self::TRASH_ORIGIN,
+                &self.fcache,
+                &self.vol,
+            )?;
+            let origin = match origin.read().unwrap().get_link_target() {
+                Some(origin) => origin.to_path_buf(),
+                None => continue,
+            };
+
+            // data could have been moved back while its entry directory
+            // failed to be removed
+            let data = match Fnode::child(
+                &entry,
+                Self::TRASH_DATA,
+                &self.fcache,
+                &self.vol,
+            ) {
+                Ok(data) => data,
+                Err(ref err) if *err == Error::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let ftype = data.read().unwrap().metadata().file_type();
+
+            let trash_ent = TrashEntry::new(
+                ent.file_name(),
+                &origin,
+                ftype,
+                ent.metadata().created_at(),
+            );
+            entries.push((trash_ent, entry));
+        }
+        entries.sort_by_key(|(ent, _)| ent.deleted_at());
+
+        Ok(entries)
+    }
+
+    // remove trash entries which are expired
+    fn purge_trash(&mut self) -> Result<()> {
+        let expiry = match self.trash {
+            Some(expiry) => expiry,
+            None => return Ok(()),
+        };
+        let now = SystemTime::now();
+        let expired: Vec<_> = self
+            .trash_entries()?
+            .into_iter()
+            .filter(|(ent, _)| {
+                now.duration_since(ent.deleted_at())
+                    .map(|age| age >= expiry)
+                    .unwrap_or(false)
+            })
+            .collect();
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        debug!("purge {} expired trash entries", expired.len());
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || {
+            for (ent, entry) in expired {
+                let path = Path::new("/").join(ent.id());
+                fs.remove_tree(&entry, &path)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Get list of trash entries, sorted by removal time
+    pub fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        let entries = self.trash_entries()?;
+        Ok(entries.into_iter().map(|(ent, _)| ent).collect())
+    }
+
+    /// Restore the latest removed entry of a path from trash
+    pub fn restore_from_trash(&mut self, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let (ent, entry) = self
+            .trash_entries()?
+            .into_iter()
+            .filter(|(ent, _)| ent.path() == path)
+            .last()
+            .ok_or(Error::NotFound)?;
+        match self.resolve(path) {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(ref err) if *err == Error::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let parent_path = path.parent().ok_or(Error::IsRoot)?;
+        match self.resolve(parent_path) {
+            Ok(_) => {}
+            Err(ref err) if *err == Error::NotFound => {
+                self.create_dir_all(parent_path)?
+            }
+            Err(err) => return Err(err),
+        }
+        let (parent, name) = self.resolve_parent(path)?;
+        let entry_path = Path::new("/").join(ent.id());
+        let data_path = entry_path.join(Self::TRASH_DATA);
+        let data =
+            Fnode::child(&entry, Self::TRASH_DATA, &self.fcache, &self.vol)?;
+        let counts = self.count_tree(&data, &data_path)?;
+
+        // move the entry back first, its trash directory cannot be removed
+        // in the same transaction because the moved fnode still refers to it
+        // until the transaction is completed
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            self.move_fnode(&data, &data_path, &parent, &name)?;
+            self.add_counts(&data, counts)
+        })?;
+        drop(data);
+        self.notify(WatchEvent::Create(path.to_path_buf()));
+
+        // then remove the trash directory, entry dir is moved into the
+        // closure so it is released before commit
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || fs.remove_tree(&entry, &entry_path))
+    }
+
+    /// Permanently remove all trash entries
+    pub fn empty_trash(&mut self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let trash = match self.trash_root {
+            Some(ref trash) => trash.clone(),
+            None => return Ok(()),
+        };
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || {
+            for (ent, entry) in fs.read_dir_nodes(trash, Path::new("/"))? {
+                fs.remove_tree(&entry, ent.path())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Create an anonymous temporary file, return its handle
+    #[inline]
+    pub fn create_temp_file(&mut self, domain: Option<Eid>) -> Result<Handle> {
+        let opts = Fnode::inherited_opts(&self.root, self.opts);
+        self.create_temp_file_with(opts, domain)
+    }
+
+    /// Create an anonymous temporary file with the specified options, its
+    /// content is encrypted by the key domain it is going to be moved into
+    pub fn create_temp_file_with(
+        &mut self,
+        opts: Options,
+        domain: Option<Eid>,
+    ) -> Result<Handle> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let root_id = self.root.read().unwrap().id().clone();
+        let mut temps = self.temps.clone();
+        let mut fnode = FnodeRef::default();
+
+        // temporary file is not in any directory, its id is recorded so it
+        // can be removed if it is left by a crash
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            if temps.is_none() {
+                temps = Some(TempFiles::create(&root_id, &self.txmgr)?);
             }
-        };
+            fnode = Fnode::new_detached(
+                FileType::File,
+                opts,
+                domain,
+                &self.txmgr,
+                &self.store,
+            )?;
+            let temps = temps.as_ref().unwrap();
+            let mut temps_cow = temps.write().unwrap();
+            temps_cow
+                .make_mut(&self.txmgr)?
+                .add(fnode.read().unwrap().id());
+            Ok(())
+        })?;
+        self.temps = temps;
 
-        // begin and run transaction
-        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
-        tx_handle.run_all_exclusive(|| {
-            // get current version of source
-            let ctn = {
-                let fnode = src.read().unwrap();
-                fnode.clone_current_content(&self.store)?
-            };
+        Ok(self.new_handle(fnode))
+    }
 
-            // then add it to target
-            let mut fnode_cow = tgt.fnode.write().unwrap();
-            let fnode = fnode_cow.make_mut(&self.txmgr)?;
-            let result = fnode.add_version(ctn, &self.store, &self.txmgr)?;
-            assert!(!(self.opts.dedup_file && result));
+    /// Link a temporary file into the tree at path, existing file at path
+    /// is replaced
+    pub fn persist_temp(&mut self, temp: &FnodeRef, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
 
+        let temps = self.temps.clone().ok_or(Error::InvalidArgument)?;
+        let (tgt, tgt_parent, name) = self.check_move(temp, path)?;
+        let (id, counts) = {
+            let fnode = temp.read().unwrap();
+            (fnode.id().clone(), fnode.entry_counts())
+        };
+
+        // temporary file is counted once it is linked into the tree
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            if let Some(tgt) = tgt {
+                self.remove_replaced(tgt)?;
+            }
+            self.move_fnode(temp, path, &tgt_parent, &name)?;
+            self.add_counts(temp, counts)?;
+            let mut temps_cow = temps.write().unwrap();
+            temps_cow.make_mut(&self.txmgr)?.remove(&id);
             Ok(())
         })?;
+        self.notify(WatchEvent::Create(path.to_path_buf()));
 
         Ok(())
     }
 
-    /// Copy a dir to another recursively
-    pub fn copy_dir_all(&mut self, from: &Path, to: &Path) -> Result<()> {
+    /// Remove a temporary file which is not linked into the tree
+    pub fn remove_temp(&mut self, temp: FnodeRef) -> Result<()> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
-        // if target and source are same fnode, do nothing
-        if from == to {
-            return Ok(());
-        }
+        let temps = self.temps.clone().ok_or(Error::InvalidArgument)?;
+        let id = temp.read().unwrap().id().clone();
 
-        if to.starts_with(from) {
-            return Err(Error::InvalidArgument);
+        // temp fnode is moved into the closure so it is released before
+        // commit
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || {
+            fs.remove_fnode(&temp)?;
+            let mut temps_cow = temps.write().unwrap();
+            temps_cow.make_mut(&fs.txmgr)?.remove(&id);
+            Ok(())
+        })
+    }
+
+    /// Add current content of a temporary file as a new version of a file,
+    /// the file is created if it doesn't exist
+    pub fn publish_temp(&mut self, temp: &FnodeRef, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
 
-        // sanity check source and target
-        {
-            let src = self.resolve(from)?;
-            {
-                let fnode = src.read().unwrap();
-                if !fnode.is_dir() {
-                    return Err(Error::NotDir);
+        let tgt = match self.resolve(path) {
+            Ok(tgt) => {
+                if !tgt.read().unwrap().is_file() {
+                    return Err(Error::NotFile);
                 }
+                Some(tgt)
             }
-
-            match self.resolve(to) {
-                Ok(tgt) => {
-                    assert!(!Arc::ptr_eq(&tgt, &src));
-                    let fnode = tgt.read().unwrap();
-                    if !fnode.is_dir() {
-                        return Err(Error::NotDir);
-                    }
-                }
-                Err(ref err) if *err == Error::NotFound => {
-                    // create target dir if it doesn't exist
-                    self.create_fnode(to, FileType::Dir, Options::default())?;
-                }
-                Err(err) => return Err(err),
+            Err(ref err) if *err == Error::NotFound => {
+                self.path_policy.check(path)?;
+                None
             }
+            Err(err) => return Err(err),
+        };
+        let created = tgt.is_none();
+        let opts = self.get_inherited_opts(path)?;
+        let (parent, name) = self.resolve_parent(path)?;
+        if !parent.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
         }
+        Self::check_key_domain(temp, &parent)?;
 
-        // copy dir tree
-        for child in self.read_dir(from)? {
-            let child_from = child.path();
-            let child_to = to.join(child.file_name());
-            match child.metadata().file_type() {
-                FileType::File => self.copy(&child_from, &child_to)?,
-                FileType::Dir => self.copy_dir_all(&child_from, &child_to)?,
-            }
+        // create the file and add the new version in one transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let tgt = match tgt {
+                Some(tgt) => tgt,
+                None => self.new_fnode(&parent, &name, FileType::File, opts)?,
+            };
+            let ctn = {
+                let fnode = temp.read().unwrap();
+                fnode.clone_current_content(&self.store)?
+            };
+            self.add_version(&tgt, ctn)
+        })?;
+
+        if created {
+            self.notify(WatchEvent::Create(path.to_path_buf()));
+        } else {
+            self.notify(WatchEvent::Modify(path.to_path_buf()));
         }
 
         Ok(())
     }
 
-    /// Remove a regular file
-    pub fn remove_file(&mut self, path: &Path) -> Result<()> {
+    // remove temporary files which were not persisted or removed before
+    // the repo was closed, only the recorded ones are removed
+    fn remove_stale_temps(&self) -> Result<()> {
+        let temps = match self.temps {
+            Some(ref temps) => temps.clone(),
+            None => return Ok(()),
+        };
+        let ids = temps.read().unwrap().list().to_vec();
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        debug!("remove {} stale temporary files", ids.len());
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || {
+            for id in ids.iter() {
+                let temp = match Fnode::load_root(id, &self.vol) {
+                    Ok(temp) => temp,
+                    Err(ref err) if *err == Error::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+                self.remove_fnode(&temp)?;
+            }
+            let mut temps_cow = temps.write().unwrap();
+            temps_cow.make_mut(&self.txmgr)?.clear();
+            Ok(())
+        })
+    }
+
+    /// Take a named snapshot of the whole file system
+    pub fn snapshot(&mut self, name: &str) -> Result<()> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
+        if name.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        if let Some(ref snaps) = self.snaps {
+            if snaps.read().unwrap().get(name).is_some() {
+                return Err(Error::AlreadyExists);
+            }
+        }
+        self.take_snapshot(name)?;
+        Ok(())
+    }
 
-        let fnode_ref = self.resolve(path)?;
-        {
-            let fnode = fnode_ref.read().unwrap();
-            if !fnode.is_file() {
-                return Err(Error::NotFile);
+    // copy the whole tree to a new snapshot and return its root id, an
+    // empty name is used for frozen views
+    fn take_snapshot(&mut self, name: &str) -> Result<Eid> {
+        let root_id = self.root.read().unwrap().id().clone();
+        let mut snaps = self.snaps.clone();
+        let mut snap_root_id = Eid::new_empty();
+
+        // copy the whole tree to a detached root in one transaction, the
+        // content is cloned so its chunks are shared rather than copied, but
+        // every fnode is copied so the cost grows with the tree size
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let snap_root =
+                Fnode::new(FileType::Dir, self.opts).into_cow(&self.txmgr)?;
+            let root = Path::new("/");
+            self.copy_tree(
+                &self.root,
+                root,
+                &snap_root,
+                root,
+                CopyMode::Snapshot,
+                &mut Vec::new(),
+            )?;
+
+            if snaps.is_none() {
+                snaps = Some(Snapshots::create(&root_id, &self.txmgr)?);
+            }
+            let snaps = snaps.as_ref().unwrap();
+            let mut snaps_cow = snaps.write().unwrap();
+            let snap_root = snap_root.read().unwrap();
+            snaps_cow.make_mut(&self.txmgr)?.add(name, snap_root.id());
+            snap_root_id = snap_root.id().clone();
+            Ok(())
+        })?;
+        self.snaps = snaps;
+
+        Ok(snap_root_id)
+    }
+
+    /// Freeze current state of the file system to a read-only view
+    pub fn freeze_view(&mut self) -> Result<Fs> {
+        // nothing can be changed through a read-only file system, so the
+        // view can simply share its tree
+        let (root, view, trash_root) = if self.read_only {
+            (self.root.clone(), None, self.trash_root.clone())
+        } else {
+            let root_id = self.root.read().unwrap().id().clone();
+            let snap_root_id = self.take_snapshot("")?;
+            let root = Fnode::load_root(&snap_root_id, &self.vol)?;
+            (root, Some(root_id), None)
+        };
+
+        Ok(Fs {
+            root,
+            fcache: self.fcache.clone(),
+            store: self.store.clone(),
+            txmgr: self.txmgr.clone(),
+            vol: self.vol.clone(),
+            shutter: Shutter::new(),
+            watchers: Watchers::default().into_ref(),
+            locks: FileLocksRef::default(),
+            snaps: self.snaps.clone(),
+            opts: self.opts,
+            read_only: true,
+            view,
+            trash: None,
+            trash_root,
+            temps: None,
+            path_policy: PathPolicy::default(),
+            pwd_check: None,
+            atime: false,
+        })
+    }
+
+    // remove the snapshot tree held by a frozen view
+    fn release_view(&mut self) -> Result<()> {
+        let snaps = match self.snaps {
+            Some(ref snaps) => snaps.clone(),
+            None => return Ok(()),
+        };
+        let root = mem::take(&mut self.root);
+        let root_id = root.read().unwrap().id().clone();
+
+        // root is moved into the closure so it is released before commit
+        let fs = &*self;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(move || {
+            fs.remove_tree(&root, Path::new("/"))?;
+            let mut snaps_cow = snaps.write().unwrap();
+            snaps_cow.make_mut(&fs.txmgr)?.remove_view(&root_id);
+            Ok(())
+        })
+    }
+
+    // remove snapshot trees of frozen views which were not released, for
+    // example, due to a crash
+    fn remove_stale_views(&self) -> Result<()> {
+        let snaps = match self.snaps {
+            Some(ref snaps) => snaps.clone(),
+            None => return Ok(()),
+        };
+        let mut roots = Vec::new();
+        for snap in snaps.read().unwrap().list() {
+            if snap.is_view() {
+                roots.push(Fnode::load_root(snap.root_id(), &self.vol)?);
             }
         }
+        if roots.is_empty() {
+            return Ok(());
+        }
 
-        // begin and run transaction
+        debug!("remove {} stale frozen views", roots.len());
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
         tx_handle.run_all_exclusive(move || {
-            Fnode::remove_from_parent(&fnode_ref, &self.txmgr)?;
-            let mut fnode = fnode_ref.write().unwrap();
-            fnode
-                .make_mut(&self.txmgr)?
-                .clear_versions(&self.store, &self.txmgr)?;
-            fnode.make_del(&self.txmgr)?;
-            self.fcache.remove(fnode.id());
+            let mut snaps_cow = snaps.write().unwrap();
+            let snaps = snaps_cow.make_mut(&self.txmgr)?;
+            for root in roots {
+                let root_id = root.read().unwrap().id().clone();
+                self.remove_tree(&root, Path::new("/"))?;
+                snaps.remove_view(&root_id);
+            }
             Ok(())
-        })?;
+        })
+    }
 
-        Ok(())
+    /// Get list of all snapshots
+    pub fn list_snapshots(&self) -> Vec<Snapshot> {
+        self.snaps
+            .as_ref()
+            .map(|snaps| {
+                let snaps = snaps.read().unwrap();
+                snaps
+                    .list()
+                    .iter()
+                    .filter(|snap| !snap.is_view())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    /// Remove an existing empty directory
-    pub fn remove_dir(&mut self, path: &Path) -> Result<()> {
+    /// Remove a named snapshot
+    pub fn remove_snapshot(&mut self, name: &str) -> Result<()> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
-        let fnode_ref = self.resolve(path)?;
-        {
-            let fnode = fnode_ref.read().unwrap();
-            if !fnode.is_dir() {
-                return Err(Error::NotDir);
-            }
-            if fnode.is_root() {
-                return Err(Error::IsRoot);
-            }
-            if fnode.children_cnt() > 0 {
-                return Err(Error::NotEmpty);
-            }
-        }
+        let snaps = self.snaps.clone().ok_or(Error::NotFound)?;
+        let snap_root = {
+            let snaps = snaps.read().unwrap();
+            let snap = snaps.get(name).ok_or(Error::NotFound)?;
+            Fnode::load_root(snap.root_id(), &self.vol)?
+        };
 
-        // begin and run transaction
+        // begin and run transaction, snapshot root is moved into the
+        // closure so it is released before commit
+        let fs = &*self;
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
-        tx_handle.run_all(move || {
-            Fnode::remove_from_parent(&fnode_ref, &self.txmgr)?;
-            let mut fnode = fnode_ref.write().unwrap();
-            fnode.make_del(&self.txmgr)?;
-            self.fcache.remove(fnode.id());
+        tx_handle.run_all_exclusive(move || {
+            fs.remove_tree(&snap_root, Path::new("/"))?;
+            let mut snaps_cow = snaps.write().unwrap();
+            snaps_cow.make_mut(&fs.txmgr)?.remove(name);
             Ok(())
-        })?;
+        })
+    }
 
+    // remove children of target dir which don't exist in source dir or
+    // have different type or key domain recursively, removals are added to
+    // `events`, this must be called in a transaction
+    fn prune_tree(
+        &self,
+        src: &FnodeRef,
+        tgt: &FnodeRef,
+        to: &Path,
+        events: &mut Vec<WatchEvent>,
+    ) -> Result<()> {
+        for (child, child_tgt) in self.read_dir_nodes(tgt.clone(), to)? {
+            let name = child.file_name();
+            let ftype = child.metadata().file_type();
+            let child_src = {
+                let has_child = src.read().unwrap().has_child(name);
+                if has_child {
+                    Some(Fnode::child(src, name, &self.fcache, &self.vol)?)
+                } else {
+                    None
+                }
+            };
+
+            match child_src {
+                Some(ref child_src)
+                    if ftype == FileType::Dir
+                        && child_src.read().unwrap().is_dir()
+                        && Self::same_key_domain(child_src, &child_tgt) =>
+                {
+                    self.prune_tree(
+                        child_src,
+                        &child_tgt,
+                        child.path(),
+                        events,
+                    )?;
+                }
+                Some(ref child_src)
+                    if ftype == FileType::File
+                        && child_src.read().unwrap().is_file() => {}
+                _ => {
+                    self.remove_tree(&child_tgt, child.path())?;
+                    events.push(WatchEvent::Remove(child.path().to_path_buf()));
+                }
+            }
+        }
         Ok(())
     }
 
-    /// Remove an existing directory recursively
-    pub fn remove_dir_all(&mut self, path: &Path) -> Result<()> {
-        for child in self.read_dir(path)? {
-            let child_path = child.path();
-            match child.metadata().file_type() {
-                FileType::File => self.remove_file(&child_path)?,
-                FileType::Dir => self.remove_dir_all(&child_path)?,
-            }
+    /// Roll back the whole file system to a named snapshot
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
-        match self.remove_dir(path) {
-            Ok(_) => Ok(()),
-            Err(ref err) if *err == Error::IsRoot => Ok(()),
-            Err(err) => Err(err),
+
+        let snap_root = {
+            let snaps = self.snaps.as_ref().ok_or(Error::NotFound)?;
+            let snaps = snaps.read().unwrap();
+            let snap = snaps.get(name).ok_or(Error::NotFound)?;
+            Fnode::load_root(snap.root_id(), &self.vol)?
+        };
+
+        // remove entries not in the snapshot and then copy the snapshot
+        // over, changed files get a new version so their history is kept
+        let mut events = Vec::new();
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            let root = Path::new("/");
+            self.prune_tree(&snap_root, &self.root, root, &mut events)?;
+            self.copy_tree(
+                &snap_root,
+                root,
+                &self.root,
+                root,
+                CopyMode::Restore,
+                &mut events,
+            )
+        })?;
+        for event in events {
+            self.notify(event);
         }
+
+        Ok(())
     }
 
     /// Rename a file or directory to new name
@@ -569,6 +2852,9 @@ impl Fs {
             return Err(Error::ReadOnly);
         }
 
+        // source must exist, even if it is renamed to itself
+        let src = self.resolve(from)?;
+
         if from == to {
             return Ok(());
         }
@@ -577,61 +2863,91 @@ impl Fs {
             return Err(Error::InvalidArgument);
         }
 
-        let src = self.resolve(from)?;
+        if src.read().unwrap().is_root() {
+            return Err(Error::IsRoot);
+        }
+
+        let (tgt, tgt_parent, name) = self.check_move(&src, to)?;
+
+        // begin and run transaction
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            // remove target if it exists
+            if let Some(tgt) = tgt {
+                self.remove_replaced(tgt)?;
+            }
+
+            // and then move source to target
+            self.move_fnode(&src, from, &tgt_parent, &name)
+        })?;
+        self.notify(WatchEvent::Rename(from.to_path_buf(), to.to_path_buf()));
+
+        Ok(())
+    }
+
+    // check if source can be moved to path, return the existing target to
+    // be replaced, target parent and name
+    fn check_move(
+        &self,
+        src: &FnodeRef,
+        to: &Path,
+    ) -> Result<(Option<FnodeRef>, FnodeRef, String)> {
         let tgt = match self.resolve(to) {
             Ok(tgt) => Some(tgt),
             Err(ref err) if *err == Error::NotFound => None,
             Err(err) => return Err(err),
         };
+        if tgt.is_none() {
+            self.path_policy.check(to)?;
+        }
 
-        {
+        if let Some(ref tgt_fnode) = tgt {
             let src_fnode = src.read().unwrap();
-            if src_fnode.is_root() {
+            let tgt_fnode = tgt_fnode.read().unwrap();
+            if tgt_fnode.is_root() {
                 return Err(Error::IsRoot);
             }
-
-            if let Some(ref tgt_fnode) = tgt {
-                let tgt_fnode = tgt_fnode.read().unwrap();
-                if tgt_fnode.is_root() {
-                    return Err(Error::IsRoot);
-                }
-                if src_fnode.is_file() && tgt_fnode.is_dir() {
-                    return Err(Error::IsDir);
+            if !src_fnode.is_dir() && tgt_fnode.is_dir() {
+                return Err(Error::IsDir);
+            }
+            if src_fnode.is_dir() {
+                if !tgt_fnode.is_dir() {
+                    return Err(Error::NotDir);
                 }
-                if src_fnode.is_dir() {
-                    if tgt_fnode.is_file() {
-                        return Err(Error::NotDir);
-                    }
-                    if tgt_fnode.children_cnt() > 0 {
-                        return Err(Error::NotEmpty);
-                    }
+                if tgt_fnode.children_cnt() > 0 {
+                    return Err(Error::NotEmpty);
                 }
             }
         }
 
         let (tgt_parent, name) = self.resolve_parent(to)?;
 
-        // begin and run transaction
-        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
-            // remove from source
-            Fnode::remove_from_parent(&src, &self.txmgr)?;
+        // key domain root can be moved anywhere, but other fnodes must stay
+        // in the same key domain
+        if src.read().unwrap().domain_check().is_none() {
+            Self::check_key_domain(src, &tgt_parent)?;
+        }
 
-            // remove target if it exists
-            if let Some(tgt_fnode) = tgt {
-                Fnode::remove_from_parent(&tgt_fnode, &self.txmgr)?;
-                let mut tgt_fnode = tgt_fnode.write().unwrap();
-                if tgt_fnode.is_file() {
-                    tgt_fnode
-                        .make_mut(&self.txmgr)?
-                        .clear_versions(&self.store, &self.txmgr)?;
-                }
-                tgt_fnode.make_del(&self.txmgr)?;
-                self.fcache.remove(tgt_fnode.id());
-            }
+        Ok((tgt, tgt_parent, name))
+    }
 
-            // and then add to target
-            Fnode::add_child(&tgt_parent, &src, &name, &self.txmgr)
-        })
+    // remove target replaced by a move, this must be called in a transaction
+    fn remove_replaced(&self, tgt: FnodeRef) -> Result<()> {
+        let (len, counts) = {
+            let fnode = tgt.read().unwrap();
+            (fnode.versions_len(), fnode.entry_counts())
+        };
+        Fnode::charge_quota(&tgt, -(len as i64), &self.txmgr)?;
+        self.add_counts(&tgt, -counts)?;
+        Fnode::remove_from_parent(&tgt, &self.txmgr)?;
+        let mut tgt_fnode = tgt.write().unwrap();
+        if tgt_fnode.is_file() {
+            tgt_fnode
+                .make_mut(&self.txmgr)?
+                .clear_versions(&self.store, &self.txmgr)?;
+        }
+        tgt_fnode.make_del(&self.txmgr)?;
+        self.fcache.remove(tgt_fnode.id());
+        Ok(())
     }
 
     /// Destroy the whole file system
@@ -644,8 +2960,19 @@ impl Fs {
     }
 }
 
+impl IntoRef for Fs {}
+
+pub type FsRef = Arc<RwLock<Fs>>;
+pub type FsWeakRef = Weak<RwLock<Fs>>;
+
 impl Drop for Fs {
     fn drop(&mut self) {
+        if self.view.is_some() {
+            if let Err(err) = self.release_view() {
+                warn!("release frozen view failed: {}", err);
+            }
+        }
+
         let mut shutter = self.shutter.write().unwrap();
         shutter.close();
         info!("repo closed");
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use error::{Error, Result};
+use trans::Eid;
+
+// next lock owner id
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(1);
+
+// allocate a new lock owner id, each opened file is a lock owner
+#[inline]
+pub fn new_lock_owner() -> usize {
+    NEXT_OWNER.fetch_add(1, Ordering::Relaxed)
+}
+
+// advisory lock kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+// byte-range lock, range is [start, end)
+#[derive(Debug, Clone)]
+struct RangeLock {
+    owner: usize,
+    kind: LockKind,
+    start: u64,
+    end: u64,
+}
+
+impl RangeLock {
+    #[inline]
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+// lock state of a file, a file can have either one exclusive lock owner or
+// multiple shared lock owners, byte-range locks are independent of the
+// whole-file locks
+#[derive(Debug, Default)]
+struct LockState {
+    exclusive: Option<usize>,
+    shared: HashSet<usize>,
+    ranges: Vec<RangeLock>,
+}
+
+impl LockState {
+    // check if owner can acquire the lock, lock already held by the same
+    // owner can be converted
+    fn can_lock(&self, owner: usize, kind: LockKind) -> bool {
+        let no_excl = self.exclusive.unwrap_or(owner) == owner;
+        match kind {
+            LockKind::Shared => no_excl,
+            LockKind::Exclusive => {
+                no_excl && self.shared.iter().all(|&o| o == owner)
+            }
+        }
+    }
+
+    fn lock(&mut self, owner: usize, kind: LockKind) {
+        self.unlock(owner);
+        match kind {
+            LockKind::Shared => {
+                self.shared.insert(owner);
+            }
+            LockKind::Exclusive => self.exclusive = Some(owner),
+        }
+    }
+
+    fn unlock(&mut self, owner: usize) {
+        if self.exclusive == Some(owner) {
+            self.exclusive = None;
+        }
+        self.shared.remove(&owner);
+    }
+
+    // check if owner can acquire the range lock, ranges already locked by
+    // the same owner are replaced
+    fn can_lock_range(
+        &self,
+        owner: usize,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+    ) -> bool {
+        !self.ranges.iter().any(|r| {
+            r.owner != owner
+                && r.overlaps(start, end)
+                && (r.kind == LockKind::Exclusive
+                    || kind == LockKind::Exclusive)
+        })
+    }
+
+    fn lock_range(
+        &mut self,
+        owner: usize,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+    ) {
+        self.unlock_range(owner, start, end);
+        self.ranges.push(RangeLock {
+            owner,
+            kind,
+            start,
+            end,
+        });
+    }
+
+    // release range locked by owner, partially released lock is split
+    fn unlock_range(&mut self, owner: usize, start: u64, end: u64) {
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        for r in self.ranges.drain(..) {
+            if r.owner != owner || !r.overlaps(start, end) {
+                ranges.push(r);
+                continue;
+            }
+            if r.start < start {
+                ranges.push(RangeLock {
+                    end: start,
+                    ..r.clone()
+                });
+            }
+            if end < r.end {
+                ranges.push(RangeLock { start: end, ..r });
+            }
+        }
+        self.ranges = ranges;
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.exclusive.is_none()
+            && self.shared.is_empty()
+            && self.ranges.is_empty()
+    }
+}
+
+// advisory file locks held in a repo, keyed by fnode id
+#[derive(Debug, Default)]
+pub struct FileLocks {
+    locks: Mutex<HashMap<Eid, LockState>>,
+    released: Condvar,
+}
+
+impl FileLocks {
+    // acquire a lock on a file, if the lock is held by others, either wait
+    // until it is released or return Error::Locked
+    pub fn lock(
+        &self,
+        id: &Eid,
+        owner: usize,
+        kind: LockKind,
+        wait: bool,
+    ) -> Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        loop {
+            {
+                let state = locks.entry(id.clone()).or_default();
+                if state.can_lock(owner, kind) {
+                    state.lock(owner, kind);
+                    return Ok(());
+                }
+            }
+            if !wait {
+                return Err(Error::Locked);
+            }
+            locks = self.released.wait(locks).unwrap();
+        }
+    }
+
+    // acquire a lock on a byte range of file, the range starts at `offset`
+    // and spans `len` bytes, zero `len` means to the end of file, if the
+    // range is locked by others, either wait until it is released or return
+    // Error::Locked
+    pub fn lock_range(
+        &self,
+        id: &Eid,
+        owner: usize,
+        kind: LockKind,
+        offset: u64,
+        len: u64,
+        wait: bool,
+    ) -> Result<()> {
+        let (start, end) = lock_range_bounds(offset, len);
+        let mut locks = self.locks.lock().unwrap();
+        loop {
+            {
+                let state = locks.entry(id.clone()).or_default();
+                if state.can_lock_range(owner, kind, start, end) {
+                    state.lock_range(owner, kind, start, end);
+                    return Ok(());
+                }
+            }
+            if !wait {
+                return Err(Error::Locked);
+            }
+            locks = self.released.wait(locks).unwrap();
+        }
+    }
+
+    // release whole-file lock held by the owner on a file, do nothing if the
+    // owner doesn't hold it
+    #[inline]
+    pub fn unlock(&self, id: &Eid, owner: usize) {
+        self.update(id, |state| state.unlock(owner));
+    }
+
+    // release byte range locked by the owner on a file
+    #[inline]
+    pub fn unlock_range(&self, id: &Eid, owner: usize, offset: u64, len: u64) {
+        let (start, end) = lock_range_bounds(offset, len);
+        self.update(id, |state| state.unlock_range(owner, start, end));
+    }
+
+    // release all locks held by the owner on a file
+    #[inline]
+    pub fn unlock_all(&self, id: &Eid, owner: usize) {
+        self.update(id, |state| {
+            state.unlock(owner);
+            state.unlock_range(owner, 0, u64::MAX);
+        });
+    }
+
+    // update lock state of a file and wake up waiters
+    fn update<F>(&self, id: &Eid, f: F)
+    where
+        F: FnOnce(&mut LockState),
+    {
+        let mut locks = self.locks.lock().unwrap();
+        let is_empty = match locks.get_mut(id) {
+            Some(state) => {
+                f(state);
+                state.is_empty()
+            }
+            None => return,
+        };
+        if is_empty {
+            locks.remove(id);
+        }
+        self.released.notify_all();
+    }
+}
+
+pub type FileLocksRef = Arc<FileLocks>;
+
+// convert lock range to [start, end) bounds
+#[inline]
+fn lock_range_bounds(offset: u64, len: u64) -> (u64, u64) {
+    if len == 0 {
+        (offset, u64::MAX)
+    } else {
+        (offset, offset.saturating_add(len))
+    }
+}
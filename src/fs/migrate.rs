@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+
+use super::fnode::{Cache as FnodeCache, Fnode, FnodeRef};
+use content::StoreRef;
+use error::Result;
+use trans::{Eid, Id};
+use volume::{Arm, VolumeRef};
+
+// cipher migrator, which re-encrypts all entities reachable from the trees
+// using current volume cipher and data key
+pub(super) struct Migrator<'a> {
+    store: &'a StoreRef,
+    fcache: &'a FnodeCache,
+    vol: &'a VolumeRef,
+
+    // number of visited and re-encrypted entities
+    visited: usize,
+    cnt: usize,
+
+    // only count entities without re-encrypting them
+    dry_run: bool,
+
+    // called with the number of visited and re-encrypted entities after
+    // each entity is visited
+    hook: Option<&'a mut dyn FnMut(usize, usize) -> Result<()>>,
+
+    // contents and segments already migrated, they can be shared
+    ctns: HashSet<Eid>,
+    segs: HashSet<Eid>,
+}
+
+impl<'a> Migrator<'a> {
+    pub fn new(
+        store: &'a StoreRef,
+        fcache: &'a FnodeCache,
+        vol: &'a VolumeRef,
+    ) -> Self {
+        Migrator {
+            store,
+            fcache,
+            vol,
+            visited: 0,
+            cnt: 0,
+            dry_run: false,
+            hook: None,
+            ctns: HashSet::new(),
+            segs: HashSet::new(),
+        }
+    }
+
+    pub fn with_hook(
+        mut self,
+        hook: &'a mut dyn FnMut(usize, usize) -> Result<()>,
+    ) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // re-encrypt an entity
+    fn migrate_entity(&mut self, id: &Eid) -> Result<()> {
+        self.visited += 1;
+        if self.dry_run {
+            return Ok(());
+        }
+        {
+            let mut vol = self.vol.write().unwrap();
+            if vol.reencrypt(id)? {
+                self.cnt += 1;
+            }
+        }
+        if let Some(ref mut hook) = self.hook {
+            hook(self.visited, self.cnt)?;
+        }
+        Ok(())
+    }
+
+    // re-encrypt both arms of a cow entity
+    fn migrate_cow(&mut self, id: &Eid) -> Result<()> {
+        let (left_arm_id, right_arm_id) = Arm::both_eid(id);
+        self.migrate_entity(&left_arm_id)?;
+        self.migrate_entity(&right_arm_id)
+    }
+
+    // re-encrypt a content and its segments
+    fn migrate_content(&mut self, ctn_id: &Eid) -> Result<()> {
+        if !self.ctns.insert(ctn_id.clone()) {
+            return Ok(());
+        }
+        self.migrate_cow(ctn_id)?;
+
+        let mut refs = HashMap::new();
+        {
+            let store = self.store.read().unwrap();
+            let ctn_ref = store.get_content(ctn_id)?;
+            let ctn = ctn_ref.read().unwrap();
+            ctn.count_chunk_refs(&mut refs);
+        }
+
+        for (seg_id, _) in refs.keys() {
+            if !self.segs.insert(seg_id.clone()) {
+                continue;
+            }
+            self.migrate_cow(seg_id)?;
+            let (data_id, _) = {
+                let store = self.store.read().unwrap();
+                store.seg_refcnts(seg_id)?
+            };
+            self.migrate_entity(&data_id)?;
+        }
+
+        Ok(())
+    }
+
+    // count entities of the given ids and all entities in the trees under
+    // roots, nothing is re-encrypted
+    pub fn count(mut self, roots: &[FnodeRef], ids: &[Eid]) -> Result<usize> {
+        self.dry_run = true;
+        self.walk(roots, ids)?;
+        Ok(self.visited)
+    }
+
+    // re-encrypt entities of the given ids and all entities in the trees
+    // under roots, return the number of re-encrypted entities
+    pub fn migrate(mut self, roots: &[FnodeRef], ids: &[Eid]) -> Result<usize> {
+        self.walk(roots, ids)?;
+        Ok(self.cnt)
+    }
+
+    // visit entities of the given ids and all entities in the trees under
+    // roots
+    fn walk(&mut self, roots: &[FnodeRef], ids: &[Eid]) -> Result<()> {
+        for id in ids {
+            self.migrate_cow(id)?;
+        }
+
+        let mut stack: Vec<_> = roots.to_vec();
+        while let Some(fnode_ref) = stack.pop() {
+            let (id, is_dir) = {
+                let fnode = fnode_ref.read().unwrap();
+                (fnode.id().clone(), fnode.is_dir())
+            };
+            self.migrate_cow(&id)?;
+
+            if is_dir {
+                let names = fnode_ref.read().unwrap().children_names();
+                for name in names {
+                    let child =
+                        Fnode::child(&fnode_ref, &name, self.fcache, self.vol)?;
+                    stack.push(child);
+                }
+            } else {
+                let vers = fnode_ref.read().unwrap().history();
+                for ver in vers.iter() {
+                    self.migrate_content(ver.content_id())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,15 +2,39 @@
 //! fs module document
 //!
 
+mod du;
+mod export;
 pub mod fnode;
 mod fs;
+mod lock;
+mod migrate;
+mod policy;
+mod snapshot;
+mod temp;
+mod trash;
+mod verify;
+mod walk;
+mod watch;
 
-pub use self::fnode::{DirEntry, FileType, Fnode, FnodeRef, Metadata, Version};
-pub use self::fs::{Fs, ShutterRef};
+pub use self::du::DiskUsage;
+pub use self::fnode::{
+    DirEntry, FileType, Fnode, FnodeRef, Metadata, Permissions, Quota, Version,
+};
+pub use self::fs::{Fs, FsRef, FsWeakRef, ShutterRef};
+pub use self::lock::{new_lock_owner, FileLocksRef, LockKind};
+pub use self::policy::{
+    PasswordCheck, PathPolicy, PathViolation, RetentionPolicy,
+};
+pub use self::snapshot::Snapshot;
+pub use self::trash::TrashEntry;
+pub use self::verify::{Problem, ProblemKind, VerifyReport};
+pub use self::walk::WalkDir;
+pub use self::watch::{WatchEvent, WatchersRef};
 
 use base::crypto::{Cipher, Cost, Crypto};
 use content::StoreWeakRef;
 use trans::TxMgrWeakRef;
+use volume::RetryPolicy;
 
 // Default file versoin limit
 const DEFAULT_VERSION_LIMIT: u8 = 1;
@@ -21,6 +45,8 @@ pub struct Options {
     pub version_limit: u8,
     pub dedup_chunk: bool,
     pub dedup_file: bool,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
 }
 
 impl Default for Options {
@@ -29,6 +55,95 @@ impl Default for Options {
             version_limit: DEFAULT_VERSION_LIMIT,
             dedup_chunk: false,
             dedup_file: false,
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+/// Default options of a directory.
+///
+/// These options are set on a directory by [`Repo::set_dir_options`] and
+/// are inherited by all files created beneath it, including files in its
+/// sub-directories. An option which is not set falls back to the nearest
+/// ancestor directory which has it set, and then to the repository-wise
+/// setting.
+///
+/// Options specified in [`OpenOptions`] still take precedence when creating a
+/// file.
+///
+/// Compression is not a directory option, because it is applied to the
+/// underlying storage blocks, which are shared by all files. It can only be
+/// set for the whole repository by [`RepoOpener::compress`].
+///
+/// [`Repo::set_dir_options`]: struct.Repo.html#method.set_dir_options
+/// [`OpenOptions`]: struct.OpenOptions.html
+/// [`RepoOpener::compress`]: struct.RepoOpener.html#method.compress
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct DirOptions {
+    version_limit: Option<u8>,
+    dedup_chunk: Option<bool>,
+    #[serde(default)]
+    retention: Option<RetentionPolicy>,
+}
+
+impl DirOptions {
+    /// Creates a blank new set of directory options, no option is set.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the default maximum number of file versions.
+    ///
+    /// The `version_limit` must be within [1, 255].
+    pub fn version_limit(&mut self, version_limit: u8) -> &mut Self {
+        self.version_limit = Some(version_limit);
+        self
+    }
+
+    /// Sets the default option for file data chunk deduplication.
+    pub fn dedup_chunk(&mut self, dedup_chunk: bool) -> &mut Self {
+        self.dedup_chunk = Some(dedup_chunk);
+        self
+    }
+
+    /// Sets the default file version retention policy.
+    pub fn retention(&mut self, retention: RetentionPolicy) -> &mut Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Returns the default maximum number of file versions if it is set.
+    #[inline]
+    pub fn get_version_limit(&self) -> Option<u8> {
+        self.version_limit
+    }
+
+    /// Returns the default option for file data chunk deduplication if it is
+    /// set.
+    #[inline]
+    pub fn get_dedup_chunk(&self) -> Option<bool> {
+        self.dedup_chunk
+    }
+
+    /// Returns the default file version retention policy if it is set.
+    #[inline]
+    pub fn get_retention(&self) -> Option<RetentionPolicy> {
+        self.retention
+    }
+
+    // overwrite options with the ones set in this directory options
+    fn apply(&self, opts: &mut Options) {
+        if let Some(version_limit) = self.version_limit {
+            opts.version_limit = version_limit;
+        }
+        if let Some(dedup_chunk) = self.dedup_chunk {
+            opts.dedup_chunk = dedup_chunk;
+        }
+        if let Some(retention) = self.retention {
+            opts.retention = retention;
         }
     }
 }
@@ -39,7 +154,17 @@ pub struct Config {
     pub cost: Cost,
     pub cipher: Cipher,
     pub compress: bool,
+    #[serde(default)]
+    pub sector_size: usize,
     pub opts: Options,
+    pub label: String,
+    pub description: String,
+    pub app_tag: String,
+    pub pwd_hint: String,
+
+    // retry policy is not persisted, it only applies to current session
+    #[serde(skip)]
+    pub retry: RetryPolicy,
 }
 
 impl Default for Config {
@@ -52,7 +177,13 @@ impl Default for Config {
                 Cipher::Xchacha
             },
             compress: false,
+            sector_size: 0,
             opts: Options::default(),
+            label: String::new(),
+            description: String::new(),
+            app_tag: String::new(),
+            pwd_hint: String::new(),
+            retry: RetryPolicy::default(),
         }
     }
 }
@@ -64,4 +195,23 @@ pub struct Handle {
     pub store: StoreWeakRef,
     pub txmgr: TxMgrWeakRef,
     pub shutter: ShutterRef,
+    pub watchers: WatchersRef,
+    pub locks: FileLocksRef,
+
+    // whether access time is updated when file is read
+    pub atime: bool,
+}
+
+impl Handle {
+    /// Notify watchers that file has been modified, this should be called
+    /// after the modification is committed
+    pub fn notify_modify(&self) {
+        let mut watchers = self.watchers.write().unwrap();
+        if watchers.is_empty() {
+            return;
+        }
+        if let Some(path) = Fnode::path(&self.fnode) {
+            watchers.notify(WatchEvent::Modify(path));
+        }
+    }
 }
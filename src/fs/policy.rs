@@ -0,0 +1,410 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use error::{Error, Result};
+
+/// Reason of a path rejected by [`PathPolicy`]
+///
+/// [`PathPolicy`]: struct.PathPolicy.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathViolation {
+    /// Path component is longer than the maximum length in bytes
+    NameTooLong { name: String, max: usize },
+
+    /// Path has more components than the maximum depth
+    TooDeep { depth: usize, max: usize },
+
+    /// Path component contains a forbidden character
+    ForbiddenChar { name: String, ch: char },
+
+    /// Path component is a reserved name
+    ReservedName(String),
+}
+
+impl Display for PathViolation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            PathViolation::NameTooLong { ref name, max } => {
+                write!(f, "name '{}' is longer than {} bytes", name, max)
+            }
+            PathViolation::TooDeep { depth, max } => {
+                write!(f, "depth {} is deeper than {}", depth, max)
+            }
+            PathViolation::ForbiddenChar { ref name, ch } => {
+                write!(f, "name '{}' contains forbidden {:?}", name, ch)
+            }
+            PathViolation::ReservedName(ref name) => {
+                write!(f, "name '{}' is reserved", name)
+            }
+        }
+    }
+}
+
+/// Path validation policy
+///
+/// The policy is checked when a file, directory or special file is created,
+/// and when an entry is renamed or copied to a new path. Only the new path
+/// itself is checked, existing entries and entries moved or copied along
+/// with a directory are not. Paths breaking the policy are rejected with
+/// [`Error::PathViolation`], which tells the reason.
+///
+/// The default policy doesn't restrict anything. Use [`portable`] to keep
+/// paths valid on common file systems, including Windows.
+///
+/// [`Error::PathViolation`]: enum.Error.html
+/// [`portable`]: struct.PathPolicy.html#method.portable
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathPolicy {
+    /// Maximum length of a path component in bytes
+    pub max_name_len: Option<usize>,
+
+    /// Maximum number of components of a path, excluding the root
+    pub max_depth: Option<usize>,
+
+    /// Characters cannot be used in a path component
+    pub forbidden_chars: Vec<char>,
+
+    /// Names cannot be used as a path component
+    ///
+    /// Names are matched case-insensitively, with and without the extension,
+    /// for example, reserved name `nul` also rejects `NUL.txt`.
+    pub reserved_names: Vec<String>,
+}
+
+impl PathPolicy {
+    // file names reserved by Windows
+    const WINDOWS_RESERVED_NAMES: &'static [&'static str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5",
+        "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5",
+        "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    /// Create a policy which keeps paths portable.
+    ///
+    /// Path components are limited to 255 bytes, characters forbidden by
+    /// Windows, including ASCII control characters, and file names reserved
+    /// by Windows, such as `CON` and `LPT1`, are rejected.
+    pub fn portable() -> Self {
+        let mut forbidden_chars: Vec<char> =
+            (0u8..0x20).map(char::from).collect();
+        forbidden_chars.extend(&['<', '>', ':', '"', '\\', '|', '?', '*']);
+        PathPolicy {
+            max_name_len: Some(255),
+            max_depth: None,
+            forbidden_chars,
+            reserved_names: Self::WINDOWS_RESERVED_NAMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+
+    // check a single path component
+    fn check_name(&self, name: &str) -> Result<()> {
+        if let Some(max) = self.max_name_len {
+            if name.len() > max {
+                return Err(Error::PathViolation(PathViolation::NameTooLong {
+                    name: name.to_string(),
+                    max,
+                }));
+            }
+        }
+
+        if let Some(ch) =
+            name.chars().find(|c| self.forbidden_chars.contains(c))
+        {
+            return Err(Error::PathViolation(PathViolation::ForbiddenChar {
+                name: name.to_string(),
+                ch,
+            }));
+        }
+
+        let stem = name.split('.').next().unwrap_or(name);
+        if self.reserved_names.iter().any(|r| {
+            r.eq_ignore_ascii_case(name) || r.eq_ignore_ascii_case(stem)
+        }) {
+            return Err(Error::PathViolation(PathViolation::ReservedName(
+                name.to_string(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check if a path is allowed by this policy.
+    pub fn check(&self, path: &Path) -> Result<()> {
+        let mut depth = 0;
+        for comp in path.components() {
+            if let Component::Normal(name) = comp {
+                depth += 1;
+                self.check_name(&name.to_string_lossy())?;
+            }
+        }
+
+        if let Some(max) = self.max_depth {
+            if depth > max {
+                return Err(Error::PathViolation(PathViolation::TooDeep {
+                    depth,
+                    max,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Time based file version retention policy
+///
+/// Besides the maximum number of versions set by `version_limit`, versions
+/// can also be removed by their age. Versions older than [`keep_for`] are
+/// removed, and versions older than [`thin_after`] are thinned out so only
+/// the newest version in each time interval is kept, for example, "keep one
+/// version per day after a week". Intervals are aligned to the Unix epoch,
+/// so a day interval is a UTC calendar day.
+///
+/// The policy is applied to a file when a new version is added to it, the
+/// current version is always kept. Tagged versions are not exempted. The
+/// default policy doesn't remove any version.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// use std::time::Duration;
+/// use zbox::RetentionPolicy;
+/// # fn foo() -> Result<()> {
+/// # init_env();
+///
+/// const DAY: u64 = 24 * 60 * 60;
+/// let mut policy = RetentionPolicy::new();
+/// policy
+///     .keep_for(Duration::from_secs(30 * DAY))
+///     .thin_after(Duration::from_secs(7 * DAY), Duration::from_secs(DAY));
+///
+/// let mut repo = RepoOpener::new()
+///     .create(true)
+///     .version_limit(255)
+///     .retention(policy)
+///     .open("mem://foo", "pwd")?;
+/// assert_eq!(repo.info()?.retention(), policy);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`keep_for`]: struct.RetentionPolicy.html#method.keep_for
+/// [`thin_after`]: struct.RetentionPolicy.html#method.thin_after
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct RetentionPolicy {
+    max_age: Option<Duration>,
+    thin: Option<(Duration, Duration)>,
+}
+
+impl RetentionPolicy {
+    /// Creates a new policy which doesn't remove any version.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum age of versions, older versions are removed.
+    pub fn keep_for(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the age after which only one version is kept per `interval`.
+    ///
+    /// The `interval` must not be zero.
+    pub fn thin_after(
+        &mut self,
+        age: Duration,
+        interval: Duration,
+    ) -> &mut Self {
+        self.thin = Some((age, interval));
+        self
+    }
+
+    /// Returns the maximum age of versions if it is set.
+    #[inline]
+    pub fn get_keep_for(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Returns the thinning age and interval if they are set.
+    #[inline]
+    pub fn get_thin_after(&self) -> Option<(Duration, Duration)> {
+        self.thin
+    }
+
+    /// Check if this policy is valid
+    #[inline]
+    pub fn check(&self) -> Result<()> {
+        match self.thin {
+            Some((_, interval)) if interval == Duration::default() => {
+                Err(Error::InvalidArgument)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // get version numbers to be removed, versions are ordered from the
+    // oldest to the newest, and the last one is the current version
+    pub(super) fn retired(
+        &self,
+        vers: &[(usize, SystemTime)],
+        now: SystemTime,
+    ) -> Vec<usize> {
+        let mut retired = Vec::new();
+        let mut last_period = None;
+
+        // newer version comes first, so it is kept in its period, the
+        // current version and versions not old enough are not thinned
+        for (idx, &(num, ctime)) in vers.iter().rev().enumerate() {
+            let age = now.duration_since(ctime).unwrap_or_default();
+            if let Some(max_age) = self.max_age {
+                if idx > 0 && age > max_age {
+                    retired.push(num);
+                    continue;
+                }
+            }
+            if let Some((thin_age, interval)) = self.thin {
+                if idx > 0 && age > thin_age {
+                    let since_epoch =
+                        ctime.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    let period = since_epoch.as_nanos() / interval.as_nanos();
+                    if last_period == Some(period) {
+                        retired.push(num);
+                        continue;
+                    }
+                    last_period = Some(period);
+                }
+            }
+        }
+
+        retired
+    }
+}
+
+// password validator set by application, it returns false if the password
+// is too weak
+#[derive(Clone)]
+pub struct PasswordCheck(Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl PasswordCheck {
+    pub fn new<F>(validator: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        PasswordCheck(Arc::new(validator))
+    }
+
+    #[inline]
+    pub fn check(&self, pwd: &str) -> Result<()> {
+        if (self.0)(pwd) {
+            Ok(())
+        } else {
+            Err(Error::WeakPassword)
+        }
+    }
+}
+
+impl Debug for PasswordCheck {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("PasswordCheck").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violation(policy: &PathPolicy, path: &str) -> PathViolation {
+        match policy.check(Path::new(path)).unwrap_err() {
+            Error::PathViolation(reason) => reason,
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn retention_policy() {
+        const DAY: u64 = 24 * 60 * 60;
+        let day = Duration::from_secs(DAY);
+        let now = UNIX_EPOCH + day * 100;
+        let vers: Vec<(usize, SystemTime)> = [
+            (1, 50 * DAY),
+            (2, 80 * DAY),
+            (3, 80 * DAY + 10),
+            (4, 90 * DAY),
+            (5, 90 * DAY + 10),
+            (6, 99 * DAY),
+            (7, 99 * DAY + 10),
+        ]
+        .iter()
+        .map(|&(num, secs)| (num, UNIX_EPOCH + Duration::from_secs(secs)))
+        .collect();
+
+        let mut policy = RetentionPolicy::new();
+        assert!(policy.retired(&vers, now).is_empty());
+
+        policy.keep_for(day * 30);
+        assert_eq!(policy.retired(&vers, now), vec![1]);
+
+        policy.thin_after(day * 7, day);
+        assert_eq!(policy.retired(&vers, now), vec![4, 2, 1]);
+
+        // current version is always kept
+        assert!(policy.retired(&vers[..1], now).is_empty());
+
+        policy.thin_after(day, Duration::default());
+        assert_eq!(policy.check().unwrap_err(), Error::InvalidArgument);
+    }
+
+    #[test]
+    fn path_policy() {
+        let policy = PathPolicy::default();
+        policy.check(Path::new("/a:b/CON/?")).unwrap();
+
+        let policy = PathPolicy::portable();
+        policy.check(Path::new("/foo/bar.txt")).unwrap();
+        policy.check(Path::new("/console/nul_file")).unwrap();
+        assert_eq!(
+            violation(&policy, "/foo/a:b"),
+            PathViolation::ForbiddenChar {
+                name: "a:b".to_string(),
+                ch: ':',
+            }
+        );
+        assert_eq!(
+            violation(&policy, "/foo/a\tb"),
+            PathViolation::ForbiddenChar {
+                name: "a\tb".to_string(),
+                ch: '\t',
+            }
+        );
+        assert_eq!(
+            violation(&policy, "/Nul.tar.gz"),
+            PathViolation::ReservedName("Nul.tar.gz".to_string())
+        );
+        let name = "x".repeat(256);
+        assert_eq!(
+            violation(&policy, &format!("/{}", name)),
+            PathViolation::NameTooLong { name, max: 255 }
+        );
+
+        let policy = PathPolicy {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        policy.check(Path::new("/foo/bar")).unwrap();
+        assert_eq!(
+            violation(&policy, "/foo/bar/baz"),
+            PathViolation::TooDeep { depth: 3, max: 2 }
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::SystemTime;
+
+use base::crypto::{Crypto, HashKey, HASHKEY_SIZE};
+use base::Time;
+use error::{Error, Result};
+use trans::cow::{Cow, CowRef, Cowable, IntoCow};
+use trans::{Eid, TxMgrRef};
+use volume::VolumeRef;
+
+/// A named snapshot of the whole repository.
+///
+/// Snapshots are created by [`Repo::snapshot`] and listed by
+/// [`Repo::list_snapshots`].
+///
+/// [`Repo::snapshot`]: struct.Repo.html#method.snapshot
+/// [`Repo::list_snapshots`]: struct.Repo.html#method.list_snapshots
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Snapshot {
+    name: String,
+    root_id: Eid,
+    ctime: Time,
+}
+
+impl Snapshot {
+    /// Returns the name of this snapshot.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the time when this snapshot was taken.
+    #[inline]
+    pub fn created_at(&self) -> SystemTime {
+        self.ctime.to_system_time()
+    }
+
+    #[inline]
+    pub(super) fn root_id(&self) -> &Eid {
+        &self.root_id
+    }
+
+    // frozen views are kept as snapshots without name
+    #[inline]
+    pub(super) fn is_view(&self) -> bool {
+        self.name.is_empty()
+    }
+}
+
+/// Snapshot list
+///
+/// Each snapshot holds a detached copy of the whole directory tree, which
+/// shares file content with the tree it was copied from. The list is saved
+/// as an entity whose id is derived from root fnode id, so it doesn't need
+/// to be referred from super block.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Snapshots {
+    snaps: Vec<Snapshot>,
+}
+
+impl Snapshots {
+    // hash key to derive snapshot list id
+    const ID_HASH_KEY: [u8; HASHKEY_SIZE] = [43u8; HASHKEY_SIZE];
+
+    // derive snapshot list id from root fnode id
+    fn derive_id(root_id: &Eid) -> Eid {
+        let mut hash_key = HashKey::new_empty();
+        hash_key.copy(&Self::ID_HASH_KEY[..]);
+        let hash = Crypto::hash_with_key(root_id.as_ref(), &hash_key);
+        Eid::from_slice(&hash)
+    }
+
+    /// Load snapshot list of a file system, `None` if no snapshots have
+    /// ever been taken
+    pub fn load(
+        root_id: &Eid,
+        vol: &VolumeRef,
+    ) -> Result<Option<SnapshotsRef>> {
+        let id = Self::derive_id(root_id);
+        match Cow::<Snapshots>::load(&id, vol) {
+            Ok(snaps) => Ok(Some(snaps)),
+            Err(ref err) if *err == Error::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Create an empty snapshot list, this must be called in a transaction
+    pub fn create(root_id: &Eid, txmgr: &TxMgrRef) -> Result<SnapshotsRef> {
+        let id = Self::derive_id(root_id);
+        Snapshots::default().into_cow_with_id(&id, txmgr)
+    }
+
+    #[inline]
+    pub fn list(&self) -> &[Snapshot] {
+        &self.snaps
+    }
+
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Snapshot> {
+        self.snaps
+            .iter()
+            .find(|snap| !snap.is_view() && snap.name == name)
+    }
+
+    pub fn add(&mut self, name: &str, root_id: &Eid) {
+        self.snaps.push(Snapshot {
+            name: name.to_string(),
+            root_id: root_id.clone(),
+            ctime: Time::now(),
+        });
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Snapshot> {
+        let idx = self
+            .snaps
+            .iter()
+            .position(|snap| !snap.is_view() && snap.name == name)?;
+        Some(self.snaps.remove(idx))
+    }
+
+    pub fn remove_view(&mut self, root_id: &Eid) -> Option<Snapshot> {
+        let idx = self
+            .snaps
+            .iter()
+            .position(|snap| snap.is_view() && snap.root_id == *root_id)?;
+        Some(self.snaps.remove(idx))
+    }
+}
+
+impl Cowable for Snapshots {}
+
+impl<'de> IntoCow<'de> for Snapshots {}
+
+/// Snapshot list reference type
+pub type SnapshotsRef = CowRef<Snapshots>;
@@ -0,0 +1,78 @@
+use base::crypto::{Crypto, HashKey, HASHKEY_SIZE};
+use error::{Error, Result};
+use trans::cow::{Cow, CowRef, Cowable, IntoCow};
+use trans::{Eid, TxMgrRef};
+use volume::VolumeRef;
+
+/// Temporary file list
+///
+/// Temporary files are detached fnodes which are not in any directory, so
+/// their ids are kept in this list until they are either linked into the
+/// tree or removed. Those left by a crash are removed when the file system
+/// is opened next time. The list is saved as an entity whose id is derived
+/// from root fnode id, so it doesn't need to be referred from super block.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TempFiles {
+    ids: Vec<Eid>,
+}
+
+impl TempFiles {
+    // hash key to derive temporary file list id
+    const ID_HASH_KEY: [u8; HASHKEY_SIZE] = [53u8; HASHKEY_SIZE];
+
+    // derive temporary file list id from root fnode id
+    fn derive_id(root_id: &Eid) -> Eid {
+        let mut hash_key = HashKey::new_empty();
+        hash_key.copy(&Self::ID_HASH_KEY[..]);
+        let hash = Crypto::hash_with_key(root_id.as_ref(), &hash_key);
+        Eid::from_slice(&hash)
+    }
+
+    /// Load temporary file list of a file system, `None` if no temporary
+    /// files have ever been created
+    pub fn load(
+        root_id: &Eid,
+        vol: &VolumeRef,
+    ) -> Result<Option<TempFilesRef>> {
+        let id = Self::derive_id(root_id);
+        match Cow::<TempFiles>::load(&id, vol) {
+            Ok(temps) => Ok(Some(temps)),
+            Err(ref err) if *err == Error::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Create an empty temporary file list, this must be called in a
+    /// transaction
+    pub fn create(root_id: &Eid, txmgr: &TxMgrRef) -> Result<TempFilesRef> {
+        let id = Self::derive_id(root_id);
+        TempFiles::default().into_cow_with_id(&id, txmgr)
+    }
+
+    #[inline]
+    pub fn list(&self) -> &[Eid] {
+        &self.ids
+    }
+
+    #[inline]
+    pub fn add(&mut self, id: &Eid) {
+        self.ids.push(id.clone());
+    }
+
+    #[inline]
+    pub fn remove(&mut self, id: &Eid) {
+        self.ids.retain(|temp| temp != id);
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+}
+
+impl Cowable for TempFiles {}
+
+impl<'de> IntoCow<'de> for TempFiles {}
+
+/// Temporary file list reference type
+pub type TempFilesRef = CowRef<TempFiles>;
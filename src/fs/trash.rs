@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::fnode::{FileType, Fnode, FnodeRef};
+use super::Options;
+use base::crypto::{Crypto, HashKey, HASHKEY_SIZE};
+use error::{Error, Result};
+use trans::cow::{Cow, IntoCow};
+use trans::{Eid, TxMgrRef};
+use volume::VolumeRef;
+
+/// An entry removed to trash.
+///
+/// Trash entries are listed by [`Repo::list_trash`] and can be restored by
+/// [`Repo::restore_from_trash`]. See [`RepoOpener::trash`] for more details.
+///
+/// [`Repo::list_trash`]: struct.Repo.html#method.list_trash
+/// [`Repo::restore_from_trash`]: struct.Repo.html#method.restore_from_trash
+/// [`RepoOpener::trash`]: struct.RepoOpener.html#method.trash
+#[derive(Debug, Clone)]
+pub struct TrashEntry {
+    id: String,
+    path: PathBuf,
+    ftype: FileType,
+    deleted_at: SystemTime,
+}
+
+impl TrashEntry {
+    pub(super) fn new(
+        id: &str,
+        path: &Path,
+        ftype: FileType,
+        deleted_at: SystemTime,
+    ) -> Self {
+        TrashEntry {
+            id: id.to_string(),
+            path: path.to_path_buf(),
+            ftype,
+            deleted_at,
+        }
+    }
+
+    /// Returns the name of the directory holding this entry in trash.
+    #[inline]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the path where this entry was removed from.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the file type of this entry.
+    #[inline]
+    pub fn file_type(&self) -> FileType {
+        self.ftype
+    }
+
+    /// Returns the time when this entry was removed.
+    #[inline]
+    pub fn deleted_at(&self) -> SystemTime {
+        self.deleted_at
+    }
+}
+
+/// Trash root
+///
+/// Removed entries are kept in a directory tree detached from the main
+/// tree, so they are never listed along with other entries and their names
+/// cannot collide with user entries. Its root fnode id is derived from root
+/// fnode id, so it doesn't need to be referred from super block.
+pub struct Trash;
+
+impl Trash {
+    // hash key to derive trash root id
+    const ID_HASH_KEY: [u8; HASHKEY_SIZE] = [47u8; HASHKEY_SIZE];
+
+    // derive trash root id from root fnode id
+    fn derive_id(root_id: &Eid) -> Eid {
+        let mut hash_key = HashKey::new_empty();
+        hash_key.copy(&Self::ID_HASH_KEY[..]);
+        let hash = Crypto::hash_with_key(root_id.as_ref(), &hash_key);
+        Eid::from_slice(&hash)
+    }
+
+    /// Load trash root of a file system, `None` if nothing has ever been
+    /// moved to trash
+    pub fn load(root_id: &Eid, vol: &VolumeRef) -> Result<Option<FnodeRef>> {
+        let id = Self::derive_id(root_id);
+        match Cow::<Fnode>::load(&id, vol) {
+            Ok(root) => Ok(Some(root)),
+            Err(ref err) if *err == Error::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Create an empty trash root, this must be called in a transaction
+    pub fn create(root_id: &Eid, txmgr: &TxMgrRef) -> Result<FnodeRef> {
+        let id = Self::derive_id(root_id);
+        Fnode::new(FileType::Dir, Options::default())
+            .into_cow_with_id(&id, txmgr)
+    }
+}
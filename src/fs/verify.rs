@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use super::fnode::{Cache as FnodeCache, Fnode, FnodeRef};
+use content::StoreRef;
+use error::Result;
+use trans::Eid;
+use volume::VolumeRef;
+
+/// Kind of a problem found by repository verification.
+///
+/// See [`Repo::verify`] for more details.
+///
+/// [`Repo::verify`]: struct.Repo.html#method.verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProblemKind {
+    /// File or directory node cannot be loaded
+    BrokenFnode,
+
+    /// Version numbers of a file are not in increasing order
+    BrokenVersionChain,
+
+    /// Content of a version cannot be loaded
+    MissingContent,
+
+    /// Length of a version doesn't match its content length
+    LengthMismatch,
+
+    /// Segment referred by a content cannot be loaded
+    MissingSegment,
+
+    /// Content refers to a chunk beyond the end of its segment
+    ChunkOutOfRange,
+
+    /// Data of a segment doesn't exist in the volume
+    MissingSegData,
+
+    /// Reference count is less than the number of actual references, the
+    /// referred data might be deleted while it is still in use
+    RefCountMismatch,
+}
+
+impl Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProblemKind::BrokenFnode => write!(f, "Broken file node"),
+            ProblemKind::BrokenVersionChain => {
+                write!(f, "Broken version chain")
+            }
+            ProblemKind::MissingContent => write!(f, "Missing content"),
+            ProblemKind::LengthMismatch => {
+                write!(f, "Version and content length mismatch")
+            }
+            ProblemKind::MissingSegment => write!(f, "Missing segment"),
+            ProblemKind::ChunkOutOfRange => write!(f, "Chunk out of range"),
+            ProblemKind::MissingSegData => write!(f, "Missing segment data"),
+            ProblemKind::RefCountMismatch => {
+                write!(f, "Reference count mismatch")
+            }
+        }
+    }
+}
+
+/// A problem found by repository verification.
+///
+/// See [`Repo::verify`] for more details.
+///
+/// [`Repo::verify`]: struct.Repo.html#method.verify
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    kind: ProblemKind,
+    path: Option<PathBuf>,
+    version: Option<usize>,
+}
+
+impl Problem {
+    fn new(
+        kind: ProblemKind,
+        path: Option<&Path>,
+        version: Option<usize>,
+    ) -> Self {
+        Problem {
+            kind,
+            path: path.map(Path::to_path_buf),
+            version,
+        }
+    }
+
+    /// Returns the kind of this problem.
+    #[inline]
+    pub fn kind(&self) -> ProblemKind {
+        self.kind
+    }
+
+    /// Returns the path of the file or directory having this problem.
+    ///
+    /// Returns `None` if the problem cannot be attributed to a path, for
+    /// example, a reference count mismatch in the file deduplication map.
+    #[inline]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the file version number having this problem, if any.
+    #[inline]
+    pub fn version(&self) -> Option<usize> {
+        self.version
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(ref path) = self.path {
+            write!(f, ": {}", path.display())?;
+        }
+        if let Some(version) = self.version {
+            write!(f, " (version {})", version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of repository verification.
+///
+/// This structure is returned from [`Repo::verify`], it contains the number
+/// of checked objects and all the problems found.
+///
+/// [`Repo::verify`]: struct.Repo.html#method.verify
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    dirs: usize,
+    files: usize,
+    versions: usize,
+    contents: usize,
+    segments: usize,
+    orphan_chunks: usize,
+    orphan_contents: usize,
+    problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no problems were found.
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Returns the problems found.
+    #[inline]
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Returns the number of checked directories, including the root.
+    #[inline]
+    pub fn dirs(&self) -> usize {
+        self.dirs
+    }
+
+    /// Returns the number of checked files, including symbolic links and
+    /// special files.
+    #[inline]
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Returns the number of checked file versions.
+    #[inline]
+    pub fn versions(&self) -> usize {
+        self.versions
+    }
+
+    /// Returns the number of checked distinct contents.
+    #[inline]
+    pub fn contents(&self) -> usize {
+        self.contents
+    }
+
+    /// Returns the number of checked segments.
+    #[inline]
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Returns the number of chunks having more references than actually
+    /// made by file contents.
+    ///
+    /// These chunks are not data corruption, but the space they occupy cannot
+    /// be reclaimed until their extra references are removed.
+    #[inline]
+    pub fn orphan_chunks(&self) -> usize {
+        self.orphan_chunks
+    }
+
+    /// Returns the number of deduplicated contents having more references
+    /// than actually made by file versions.
+    ///
+    /// Like orphan chunks, these contents are not data corruption but
+    /// occupy space which cannot be reclaimed.
+    #[inline]
+    pub fn orphan_contents(&self) -> usize {
+        self.orphan_contents
+    }
+}
+
+// repository verifier
+pub(super) struct Verifier<'a> {
+    store: &'a StoreRef,
+    fcache: &'a FnodeCache,
+    vol: &'a VolumeRef,
+    report: VerifyReport,
+
+    // number of versions referring to each content
+    ctn_vers: HashMap<Eid, u32>,
+
+    // chunk references made by all contents, grouped by segment, along with
+    // a path referring to the segment
+    seg_refs: HashMap<Eid, (PathBuf, HashMap<usize, u32>)>,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(
+        store: &'a StoreRef,
+        fcache: &'a FnodeCache,
+        vol: &'a VolumeRef,
+    ) -> Self {
+        Verifier {
+            store,
+            fcache,
+            vol,
+            report: VerifyReport::default(),
+            ctn_vers: HashMap::new(),
+            seg_refs: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    fn problem(
+        &mut self,
+        kind: ProblemKind,
+        path: Option<&Path>,
+        version: Option<usize>,
+    ) {
+        self.report.problems.push(Problem::new(kind, path, version));
+    }
+
+    // verify the whole trees under roots, which are the root, trash root,
+    // snapshot roots and temporary files
+    pub fn verify(mut self, roots: &[FnodeRef]) -> Result<VerifyReport> {
+        let mut stack: Vec<_> = roots
+            .iter()
+            .map(|root| (PathBuf::from("/"), root.clone()))
+            .collect();
+        while let Some((path, fnode_ref)) = stack.pop() {
+            let is_dir = fnode_ref.read().unwrap().is_dir();
+            if is_dir {
+                self.report.dirs += 1;
+                let names = fnode_ref.read().unwrap().children_names();
+                for name in names {
+                    let child_path = path.join(&name);
+                    match Fnode::child(&fnode_ref, &name, self.fcache, self.vol)
+                    {
+                        Ok(child) => stack.push((child_path, child)),
+                        Err(_) => self.problem(
+                            ProblemKind::BrokenFnode,
+                            Some(&child_path),
+                            None,
+                        ),
+                    }
+                }
+            } else {
+                self.report.files += 1;
+                self.verify_versions(&fnode_ref, &path)?;
+            }
+        }
+
+        self.verify_segments()?;
+        self.verify_content_map();
+
+        Ok(self.report)
+    }
+
+    // verify version chain and contents of a file
+    fn verify_versions(
+        &mut self,
+        fnode_ref: &FnodeRef,
+        path: &Path,
+    ) -> Result<()> {
+        let vers = fnode_ref.read().unwrap().history();
+        let mut last_num = 0;
+
+        for ver in vers.iter() {
+            self.report.versions += 1;
+            if ver.num() <= last_num {
+                self.problem(
+                    ProblemKind::BrokenVersionChain,
+                    Some(path),
+                    Some(ver.num()),
+                );
+            }
+            last_num = ver.num();
+
+            // content shared by multiple versions is checked only once
+            let ctn_id = ver.content_id();
+            let ver_cnt = self.ctn_vers.entry(ctn_id.clone()).or_insert(0);
+            *ver_cnt += 1;
+            if *ver_cnt > 1 {
+                continue;
+            }
+            self.report.contents += 1;
+
+            let ctn_ref = {
+                let store = self.store.read().unwrap();
+                store.get_content(ctn_id)
+            };
+            let ctn_ref = match ctn_ref {
+                Ok(ctn_ref) => ctn_ref,
+                Err(_) => {
+                    self.problem(
+                        ProblemKind::MissingContent,
+                        Some(path),
+                        Some(ver.num()),
+                    );
+                    continue;
+                }
+            };
+            let ctn = ctn_ref.read().unwrap();
+            if ctn.len() != ver.content_len() {
+                self.problem(
+                    ProblemKind::LengthMismatch,
+                    Some(path),
+                    Some(ver.num()),
+                );
+            }
+
+            // collect chunk references made by the content
+            let mut refs = HashMap::new();
+            ctn.count_chunk_refs(&mut refs);
+            for ((seg_id, idx), cnt) in refs {
+                let seg_refs = self
+                    .seg_refs
+                    .entry(seg_id)
+                    .or_insert_with(|| (path.to_path_buf(), HashMap::new()));
+                *seg_refs.1.entry(idx).or_insert(0) += cnt;
+            }
+        }
+
+        Ok(())
+    }
+
+    // verify segments against chunk references made by contents
+    fn verify_segments(&mut self) -> Result<()> {
+        let seg_refs: Vec<_> = self.seg_refs.drain().collect();
+
+        for (seg_id, (path, refs)) in seg_refs {
+            self.report.segments += 1;
+
+            let result = {
+                let store = self.store.read().unwrap();
+                store.seg_refcnts(&seg_id)
+            };
+            let (data_id, refcnts) = match result {
+                Ok(result) => result,
+                Err(_) => {
+                    self.problem(
+                        ProblemKind::MissingSegment,
+                        Some(&path),
+                        None,
+                    );
+                    continue;
+                }
+            };
+
+            let exists = {
+                let vol = self.vol.read().unwrap();
+                vol.contains(&data_id)?
+            };
+            if !exists {
+                self.problem(ProblemKind::MissingSegData, Some(&path), None);
+            }
+
+            if refs.keys().any(|idx| *idx >= refcnts.len()) {
+                self.problem(ProblemKind::ChunkOutOfRange, Some(&path), None);
+            }
+
+            let mut mismatch = false;
+            for (idx, refcnt) in refcnts.iter().enumerate() {
+                let cnt = refs.get(&idx).cloned().unwrap_or(0);
+                if *refcnt < cnt {
+                    mismatch = true;
+                } else if *refcnt > cnt {
+                    self.report.orphan_chunks += 1;
+                }
+            }
+            if mismatch {
+                self.problem(ProblemKind::RefCountMismatch, Some(&path), None);
+            }
+        }
+
+        Ok(())
+    }
+
+    // verify content reference counts in file deduplication map
+    fn verify_content_map(&mut self) {
+        let refcnts = {
+            let store = self.store.read().unwrap();
+            store.content_refcnts()
+        };
+
+        for (ctn_id, refcnt) in refcnts {
+            let cnt = self.ctn_vers.get(&ctn_id).cloned().unwrap_or(0);
+            if refcnt < cnt {
+                self.problem(ProblemKind::RefCountMismatch, None, None);
+            } else if refcnt > cnt {
+                self.report.orphan_contents += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::vec::IntoIter;
+
+use super::fnode::{DirEntry, FnodeRef};
+use super::fs::FsRef;
+use error::Result;
+
+/// A depth-first iterator over entries of a directory tree.
+///
+/// This iterator is created by [`Repo::walk_dir`]. Entries are yielded in
+/// pre-order, that is, a directory is yielded before its children. The
+/// directory being walked itself is not yielded.
+///
+/// Each directory is read only once and its children are loaded straight
+/// from the directory, so walking a tree doesn't need to resolve every path
+/// from the root as repeated [`Repo::read_dir`] calls do.
+///
+/// [`Repo::walk_dir`]: struct.Repo.html#method.walk_dir
+/// [`Repo::read_dir`]: struct.Repo.html#method.read_dir
+pub struct WalkDir<'a> {
+    fs: &'a FsRef,
+    root: PathBuf,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    started: bool,
+
+    // pending entries of each directory being walked, and the directories
+    // themselves which are used to detect symbolic link loops
+    stack: Vec<IntoIter<(DirEntry, FnodeRef)>>,
+    ancestors: Vec<FnodeRef>,
+}
+
+impl<'a> WalkDir<'a> {
+    pub(super) fn new(fs: &'a FsRef, path: &Path) -> Self {
+        WalkDir {
+            fs,
+            root: path.to_path_buf(),
+            max_depth: None,
+            follow_links: false,
+            started: false,
+            stack: Vec::new(),
+            ancestors: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum depth of entries to yield.
+    ///
+    /// Children of the directory being walked are at depth 1, setting this
+    /// to 1 yields the same entries as [`Repo::read_dir`]. By default, there
+    /// is no depth limit.
+    ///
+    /// [`Repo::read_dir`]: struct.Repo.html#method.read_dir
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Sets whether to follow symbolic links.
+    ///
+    /// When following links, a symbolic link is yielded with metadata of its
+    /// target, and a link pointing to a directory is walked into as if it
+    /// was a directory. Dangling links and links pointing to one of their
+    /// ancestor directories are yielded as links and not walked into.
+    ///
+    /// By default, symbolic links are not followed.
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    // push directory entries to stack if it is within max depth and not
+    // walked yet
+    fn push_dir(&mut self, fnode: FnodeRef, path: &Path) -> Result<()> {
+        if let Some(depth) = self.max_depth {
+            if self.stack.len() >= depth {
+                return Ok(());
+            }
+        }
+        if self.ancestors.iter().any(|a| Arc::ptr_eq(a, &fnode)) {
+            return Ok(());
+        }
+        let ents = {
+            let fs = self.fs.read().unwrap();
+            fs.read_dir_nodes(fnode.clone(), path)?
+        };
+        self.stack.push(ents.into_iter());
+        self.ancestors.push(fnode);
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for WalkDir<'a> {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Result<DirEntry>> {
+        if !self.started {
+            self.started = true;
+            let root = self.root.clone();
+            let resolved = self.fs.read().unwrap().resolve(&root);
+            if let Err(err) =
+                resolved.and_then(|fnode| self.push_dir(fnode, &root))
+            {
+                return Some(Err(err));
+            }
+        }
+
+        loop {
+            let (mut ent, mut fnode) = match self.stack.last_mut()?.next() {
+                Some(next) => next,
+                None => {
+                    self.stack.pop();
+                    self.ancestors.pop();
+                    continue;
+                }
+            };
+
+            if self.follow_links && ent.metadata().is_symlink() {
+                let target = self.fs.read().unwrap().follow_link(ent.path());
+                if let Ok(target) = target {
+                    ent.set_metadata(target.read().unwrap().metadata());
+                    fnode = target;
+                }
+            }
+
+            if ent.metadata().is_dir() {
+                if let Err(err) = self.push_dir(fnode, ent.path()) {
+                    return Some(Err(err));
+                }
+            }
+
+            return Some(Ok(ent));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, RwLock};
+
+use base::IntoRef;
+
+/// A change event delivered to watchers.
+///
+/// Events are emitted after the change is committed, so an aborted change
+/// never produces an event. Paths are absolute paths in the repository.
+///
+/// See [`Repo::watch`] for more details.
+///
+/// [`Repo::watch`]: struct.Repo.html#method.watch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A file or directory is created
+    Create(PathBuf),
+
+    /// File content or metadata of a file or directory is modified
+    Modify(PathBuf),
+
+    /// A file or directory is removed
+    Remove(PathBuf),
+
+    /// A file or directory is renamed, from the first path to the second
+    Rename(PathBuf, PathBuf),
+}
+
+impl WatchEvent {
+    // check if this event should be delivered to a watch
+    fn matches(&self, path: &Path, recursive: bool) -> bool {
+        let is_under = |p: &Path| {
+            if recursive {
+                p.starts_with(path)
+            } else {
+                p == path || p.parent() == Some(path)
+            }
+        };
+        match *self {
+            WatchEvent::Create(ref p)
+            | WatchEvent::Modify(ref p)
+            | WatchEvent::Remove(ref p) => is_under(p),
+            WatchEvent::Rename(ref from, ref to) => {
+                is_under(from) || is_under(to)
+            }
+        }
+    }
+}
+
+// a single watch on a path
+struct Watch {
+    path: PathBuf,
+    recursive: bool,
+    tx: Sender<WatchEvent>,
+}
+
+/// Watch list
+#[derive(Default)]
+pub struct Watchers {
+    watches: Vec<Watch>,
+}
+
+impl Watchers {
+    /// Add a watch on path and return its event receiver
+    pub fn add(
+        &mut self,
+        path: &Path,
+        recursive: bool,
+    ) -> Receiver<WatchEvent> {
+        let (tx, rx) = channel();
+        self.watches.push(Watch {
+            path: path.to_path_buf(),
+            recursive,
+            tx,
+        });
+        rx
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Deliver event to matched watches, watches whose receiver has been
+    /// dropped are removed
+    pub fn notify(&mut self, event: WatchEvent) {
+        self.watches.retain(|watch| {
+            !event.matches(&watch.path, watch.recursive)
+                || watch.tx.send(event.clone()).is_ok()
+        });
+    }
+}
+
+impl Debug for Watchers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchers")
+            .field("watches", &self.watches.len())
+            .finish()
+    }
+}
+
+impl IntoRef for Watchers {}
+
+pub type WatchersRef = Arc<RwLock<Watchers>>;
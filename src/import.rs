@@ -0,0 +1,305 @@
+//! Host directory tree import.
+
+use std::fmt::{self, Debug};
+use std::fs::{self, Metadata as HostMetadata};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use error::{Error, Result};
+#[cfg(unix)]
+use fs::{FileType, Permissions};
+use repo::{OpenOptions, Repo};
+
+// entry filter and progress callback
+type Filter = Box<dyn FnMut(&Path) -> bool>;
+type Progress = Box<dyn FnMut(usize, usize)>;
+
+/// Options and flags which can be used to configure how a host directory tree
+/// is imported.
+///
+/// This builder exposes the ability to configure how a directory tree on the
+/// host file system is imported into a repository. [`Repo::import_dir`] is
+/// an alias which imports with default options.
+///
+/// Generally speaking, when using `ImportOptions`, you'll first call [`new`],
+/// then chain calls to methods to set each option, then call [`import`],
+/// passing the repository, the host directory path and the repository
+/// directory path.
+///
+/// # Examples
+///
+/// Import all files except hidden ones, reporting progress as the import
+/// proceeds.
+///
+/// ```no_run
+/// # #![allow(unused_mut, unused_variables)]
+/// # use zbox::{init_env, Result, RepoOpener};
+/// # use zbox::ImportOptions;
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// let report = ImportOptions::new()
+///     .filter(|path| {
+///         !path.file_name().unwrap().to_string_lossy().starts_with('.')
+///     })
+///     .progress(|done, total| println!("imported {}/{}", done, total))
+///     .import(&mut repo, "/home/alice/docs", "/docs")?;
+/// println!("imported {} bytes", report.bytes());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Repo::import_dir`]: struct.Repo.html#method.import_dir
+/// [`new`]: struct.ImportOptions.html#method.new
+/// [`import`]: struct.ImportOptions.html#method.import
+#[derive(Default)]
+pub struct ImportOptions {
+    filter: Option<Filter>,
+    progress: Option<Progress>,
+}
+
+impl ImportOptions {
+    /// Creates a blank new set of options ready for configuration.
+    ///
+    /// By default, all entries are imported and no progress is reported.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a filter to select entries to be imported.
+    ///
+    /// The filter is called with the host path of each entry, entries for
+    /// which it returns `false` are skipped. If a directory is skipped, its
+    /// whole sub-tree is skipped as well.
+    pub fn filter<F>(&mut self, filter: F) -> &mut ImportOptions
+    where
+        F: FnMut(&Path) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Sets a callback to report import progress.
+    ///
+    /// The callback is called with the number of imported entries and the
+    /// total number of entries to be imported, each time an entry is
+    /// imported.
+    pub fn progress<F>(&mut self, progress: F) -> &mut ImportOptions
+    where
+        F: FnMut(usize, usize) + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Imports a host directory tree to a repository directory.
+    ///
+    /// All files, directories and symbolic links under `host_path` are
+    /// imported to `repo_path`, which is created if it doesn't exist. The
+    /// directory `host_path` itself is not imported, but its children are.
+    /// On Unix, permissions, ownership and special files are imported as
+    /// well. Modification times are preserved.
+    ///
+    /// Existing directories in repository are merged, and a new version is
+    /// created for an existing regular file. Existing symbolic links and
+    /// special files are replaced.
+    ///
+    /// Each entry is imported in its own transaction, so importing a huge
+    /// directory tree never builds up a single huge transaction. This also
+    /// means this method is not atomic, entries imported before an error
+    /// happened are kept.
+    ///
+    /// `repo_path` must be an absolute path.
+    pub fn import<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        repo: &mut Repo,
+        host_path: P,
+        repo_path: Q,
+    ) -> Result<ImportReport> {
+        let host_path = host_path.as_ref();
+        let repo_path = repo_path.as_ref();
+        if !fs::metadata(host_path)?.is_dir() {
+            return Err(Error::NotDir);
+        }
+        if !repo.path_exists(repo_path)? {
+            repo.create_dir_all(repo_path)?;
+        }
+
+        let ents = self.scan(host_path)?;
+        let total = ents.len();
+        let mut report = ImportReport::default();
+        let mut dirs = Vec::new();
+
+        for (idx, (path, meta)) in ents.into_iter().enumerate() {
+            let rel_path = path.strip_prefix(host_path).unwrap();
+            let dst = repo_path.join(rel_path);
+            if meta.is_dir() {
+                if !repo.path_exists(&dst)? {
+                    repo.create_dir(&dst)?;
+                }
+                report.dirs += 1;
+                dirs.push((dst, meta));
+            } else {
+                report.bytes += import_file(repo, &path, &dst, &meta)?;
+                report.files += 1;
+            }
+            if let Some(ref mut progress) = self.progress {
+                progress(idx + 1, total);
+            }
+        }
+
+        // set directory times after all their children are imported,
+        // children first
+        for (dst, meta) in dirs.iter().rev() {
+            set_attrs(repo, dst, meta)?;
+        }
+
+        Ok(report)
+    }
+
+    // scan host directory tree, return entry paths and their metadata
+    // sorted by path, so parent directories come before their children
+    fn scan(
+        &mut self,
+        host_path: &Path,
+    ) -> Result<Vec<(PathBuf, HostMetadata)>> {
+        let mut ents = Vec::new();
+        let mut stack = vec![host_path.to_path_buf()];
+
+        while let Some(dir) = stack.pop() {
+            for ent in fs::read_dir(&dir)? {
+                let path = ent?.path();
+                if let Some(ref mut filter) = self.filter {
+                    if !filter(&path) {
+                        continue;
+                    }
+                }
+                let meta = fs::symlink_metadata(&path)?;
+                if meta.is_dir() {
+                    stack.push(path.clone());
+                }
+                ents.push((path, meta));
+            }
+        }
+
+        ents.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(ents)
+    }
+}
+
+impl Debug for ImportOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImportOptions")
+            .field("filter", &self.filter.is_some())
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+// import a non-directory entry, return the number of content bytes imported
+fn import_file(
+    repo: &mut Repo,
+    path: &Path,
+    dst: &Path,
+    meta: &HostMetadata,
+) -> Result<u64> {
+    let ftype = meta.file_type();
+    let mut len = 0;
+
+    if ftype.is_file() {
+        // content is written from the beginning, so existing file needs
+        // to be truncated if it is longer
+        let truncate = repo
+            .metadata(dst)
+            .map(|m| m.content_len() as u64 > meta.len())
+            .unwrap_or(false);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(truncate)
+            .open(repo, dst)?;
+        let mut src = fs::File::open(path)?;
+        len = io::copy(&mut src, &mut file)?;
+        if len > 0 {
+            file.finish()?;
+        }
+    } else {
+        // symbolic links and special files cannot be updated in place
+        if repo.path_exists(dst)? {
+            repo.remove_file(dst)?;
+        }
+        if ftype.is_symlink() {
+            repo.symlink(fs::read_link(path)?, dst)?;
+        } else {
+            #[cfg(unix)]
+            {
+                let ftype = if ftype.is_fifo() {
+                    FileType::Fifo
+                } else if ftype.is_socket() {
+                    FileType::Socket
+                } else if ftype.is_char_device() {
+                    FileType::CharDevice
+                } else {
+                    FileType::BlockDevice
+                };
+                repo.create_node(dst, ftype, meta.rdev())?;
+            }
+            #[cfg(not(unix))]
+            return Ok(0);
+        }
+    }
+
+    set_attrs(repo, dst, meta)?;
+    Ok(len)
+}
+
+// set permissions and modification time of an imported entry
+fn set_attrs(repo: &mut Repo, dst: &Path, meta: &HostMetadata) -> Result<()> {
+    #[cfg(unix)]
+    repo.set_permissions(
+        dst,
+        Permissions::new(meta.mode() & 0o7777, meta.uid(), meta.gid()),
+    )?;
+    if let Ok(mtime) = meta.modified() {
+        repo.set_mtime(dst, mtime)?;
+    }
+    Ok(())
+}
+
+/// Result of a directory tree import.
+///
+/// This structure is returned from the [`ImportOptions::import`] and
+/// [`Repo::import_dir`], it reports the number of entries and bytes imported.
+///
+/// [`ImportOptions::import`]: struct.ImportOptions.html#method.import
+/// [`Repo::import_dir`]: struct.Repo.html#method.import_dir
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportReport {
+    dirs: usize,
+    files: usize,
+    bytes: u64,
+}
+
+impl ImportReport {
+    /// Returns the number of imported directories.
+    #[inline]
+    pub fn dirs(&self) -> usize {
+        self.dirs
+    }
+
+    /// Returns the number of imported files, including symbolic links and
+    /// special files.
+    #[inline]
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Returns the total content size in bytes of imported regular files.
+    #[inline]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
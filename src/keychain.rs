@@ -0,0 +1,95 @@
+//! OS keychain helpers for derived key.
+
+use std::ptr;
+
+use keyring::{Entry, Error as KeyringError};
+
+use base::crypto::Key;
+use error::{Error, Result};
+use repo::DerivedKey;
+
+// keychain service name for all repositories, repository URI is used as
+// the account name
+const SERVICE: &str = "zbox";
+
+// open keychain entry for a repository
+#[inline]
+fn entry(uri: &str) -> Result<Entry> {
+    Entry::new(SERVICE, uri).map_err(Error::from)
+}
+
+// zero out secret returned from keychain
+fn zero(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+}
+
+impl DerivedKey {
+    /// Stores the key in OS keychain for the repository at URI.
+    ///
+    /// The key is stored in macOS Keychain, Windows Credential Manager or
+    /// Secret Service on Linux and BSD, so an application can offer
+    /// "remember this repository" without persisting plaintext password.
+    /// An existing key of the same repository is replaced.
+    ///
+    /// This method is only available when `keychain` feature is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use zbox::{init_env, Result, Repo, RepoOpener, DerivedKey};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let uri = "file:///path/to/repo";
+    /// let key = Repo::derive_key(uri, "pwd")?;
+    /// key.save_to_keychain(uri)?;
+    ///
+    /// // later, open the repository without asking for password
+    /// if let Some(key) = DerivedKey::load_from_keychain(uri)? {
+    ///     let repo = RepoOpener::new().open_with_key(uri, &key)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn save_to_keychain(&self, uri: &str) -> Result<()> {
+        entry(uri)?.set_secret(self.0.as_slice())?;
+        Ok(())
+    }
+
+    /// Loads the key of the repository at URI from OS keychain.
+    ///
+    /// Returns `None` if no key is stored for the repository. The loaded key
+    /// is not verified, a stale key only fails when it is used, for example
+    /// after the password is reset.
+    ///
+    /// This method is only available when `keychain` feature is enabled.
+    pub fn load_from_keychain(uri: &str) -> Result<Option<DerivedKey>> {
+        let mut secret = match entry(uri)?.get_secret() {
+            Ok(secret) => secret,
+            Err(KeyringError::NoEntry) => return Ok(None),
+            Err(err) => return Err(Error::from(err)),
+        };
+        let result = if secret.len() == Key::new_empty().len() {
+            let mut key = Key::new_empty();
+            key.copy(&secret);
+            Ok(Some(DerivedKey(key)))
+        } else {
+            Err(Error::Decrypt)
+        };
+        zero(&mut secret);
+        result
+    }
+
+    /// Removes the key of the repository at URI from OS keychain.
+    ///
+    /// It does nothing if no key is stored for the repository.
+    ///
+    /// This method is only available when `keychain` feature is enabled.
+    pub fn remove_from_keychain(uri: &str) -> Result<()> {
+        match entry(uri)?.delete_credential() {
+            Ok(_) | Err(KeyringError::NoEntry) => Ok(()),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+}
@@ -128,21 +128,49 @@ macro_rules! from_io_err {
 
 mod base;
 mod content;
+mod drop_box;
 mod error;
 mod file;
 mod fs;
+mod import;
+#[cfg(feature = "keychain")]
+mod keychain;
+mod multipart;
 mod repo;
+mod tar;
+mod trace;
 mod trans;
+mod transfer;
 mod version;
 mod volume;
 
-pub use self::base::crypto::{Cipher, MemLimit, OpsLimit};
+pub use self::base::crypto::{
+    Cipher, Crypto, Kdf, KdfParams, Key, MemLimit, NonceStrategy, OpsLimit,
+    ScryptParams,
+};
 pub use self::base::{init_env, zbox_version};
+pub use self::drop_box::{DropPublicKey, DropWriter};
 pub use self::error::{Error, Result};
-pub use self::file::{File, VersionReader};
-pub use self::fs::fnode::{DirEntry, FileType, Metadata, Version};
-pub use self::repo::{OpenOptions, Repo, RepoInfo, RepoOpener};
+pub use self::file::{Advice, BlockRef, File, VersionReader};
+pub use self::fs::fnode::{
+    DedupInfo, DirEntry, FileType, Metadata, Permissions, Quota, Version,
+};
+pub use self::fs::{
+    DirOptions, DiskUsage, PathPolicy, PathViolation, Problem, ProblemKind,
+    RetentionPolicy, Snapshot, TrashEntry, VerifyReport, WalkDir, WatchEvent,
+};
+pub use self::import::{ImportOptions, ImportReport};
+pub use self::multipart::MultiPartWriter;
+pub use self::repo::{
+    CompactReport, DerivedKey, OpenOptions, RecoveryKey, Repo, RepoInfo,
+    RepoOpener, StatFs, StorageHealth,
+};
 pub use self::trans::Eid;
+pub use self::transfer::{transfer, TransferOptions};
+pub use self::volume::{
+    register_storage, Health, KeyProvider, OpenPhase, RetryClass, RetryPolicy,
+    Space, Span, Storable, StorageFactory, BLK_SIZE,
+};
 
 #[macro_use]
 extern crate lazy_static;
@@ -150,21 +178,63 @@ extern crate lazy_static;
 #[cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
 pub use self::volume::FaultyController;
 
+#[cfg(feature = "storage-file")]
+extern crate fs2;
+
 #[cfg(feature = "storage-sqlite")]
 extern crate libsqlite3_sys;
 
 #[cfg(feature = "storage-redis")]
 extern crate redis;
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(feature = "storage-sled")]
+extern crate sled;
+
+#[cfg(any(
+    feature = "storage-zbox",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 extern crate http;
 
-#[cfg(feature = "storage-zbox")]
+#[cfg(any(feature = "storage-zbox", feature = "storage-gcs"))]
 extern crate serde_json;
 
-#[cfg(feature = "storage-zbox-native")]
+#[cfg(any(
+    feature = "storage-zbox-native",
+    feature = "storage-gcs",
+    feature = "storage-http"
+))]
 extern crate reqwest;
 
+#[cfg(feature = "storage-gcs")]
+extern crate jsonwebtoken;
+
+#[cfg(feature = "crypto-rust")]
+extern crate aes_gcm;
+#[cfg(feature = "aes-gcm-siv")]
+extern crate aes_gcm_siv;
+#[cfg(feature = "argon2")]
+extern crate argon2;
+#[cfg(feature = "crypto-rust")]
+extern crate blake2;
+#[cfg(feature = "crypto-rust")]
+extern crate chacha20;
+#[cfg(feature = "crypto-rust")]
+extern crate chacha20poly1305;
+#[cfg(feature = "crypto-rust")]
+extern crate crypto_box;
+#[cfg(feature = "crypto-rust")]
+extern crate getrandom;
+#[cfg(feature = "scrypt")]
+extern crate scrypt;
+
+#[cfg(feature = "keychain")]
+extern crate keyring;
+
+#[cfg(unix)]
+extern crate libc;
+
 #[cfg(target_os = "android")]
 extern crate jni;
 
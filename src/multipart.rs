@@ -0,0 +1,189 @@
+//! Multi-part writer for huge files.
+
+use std::error::Error as StdError;
+use std::fmt::{self, Debug};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+use error::{Error, Result};
+use file::File;
+use fs::{FnodeRef, FsWeakRef};
+
+/// A writer which writes a huge file in multiple parts.
+///
+/// Data written by [`File`] in one multi-part write is committed in a single
+/// transaction, so writing a multi-gigabyte file pins a huge transaction
+/// until [`finish`] is called. `MultiPartWriter` writes data to a temporary
+/// file instead, and commits it part by part, each part in its own bounded
+/// transaction. When all data is written, [`publish`] adds the whole written
+/// content as a new version of the target file in one atomic step.
+///
+/// A part is committed automatically when its size reaches the part size,
+/// which can be set by [`set_part_size`], or explicitly by calling
+/// [`commit_part`]. Committed parts are durable, but they are not visible in
+/// the target file until published. If the writer is dropped without being
+/// published, all written data is discarded.
+///
+/// It is created by [`Repo::multi_part_writer`].
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// use std::io::Write;
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// let mut wtr = repo.multi_part_writer("/foo.bin")?;
+/// wtr.set_part_size(4096);
+/// for _ in 0..10 {
+///     wtr.write_all(&[42u8; 1000])?;
+/// }
+/// assert_eq!(wtr.committed_parts(), 2);
+///
+/// // nothing is visible before publishing
+/// assert!(!repo.path_exists("/foo.bin")?);
+/// wtr.publish()?;
+/// assert_eq!(repo.metadata("/foo.bin")?.content_len(), 10_000);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`File`]: struct.File.html
+/// [`finish`]: struct.File.html#method.finish
+/// [`publish`]: struct.MultiPartWriter.html#method.publish
+/// [`set_part_size`]: struct.MultiPartWriter.html#method.set_part_size
+/// [`commit_part`]: struct.MultiPartWriter.html#method.commit_part
+/// [`Repo::multi_part_writer`]: struct.Repo.html#method.multi_part_writer
+pub struct MultiPartWriter {
+    fs: FsWeakRef,
+    path: PathBuf,
+    temp: FnodeRef,
+    file: File,
+    part_size: usize,
+    part_len: usize,
+    parts: usize,
+    len: usize,
+}
+
+impl MultiPartWriter {
+    // default part size, 64MB
+    const DEFAULT_PART_SIZE: usize = 64 * 1024 * 1024;
+
+    pub(super) fn new(
+        fs: FsWeakRef,
+        path: &Path,
+        temp: FnodeRef,
+        file: File,
+    ) -> Self {
+        MultiPartWriter {
+            fs,
+            path: path.to_path_buf(),
+            temp,
+            file,
+            part_size: Self::DEFAULT_PART_SIZE,
+            part_len: 0,
+            parts: 0,
+            len: 0,
+        }
+    }
+
+    /// Sets the size of each part in bytes.
+    ///
+    /// A part is committed when the data written since last commit reaches
+    /// this size. Default is 64MB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `part_size` is zero.
+    pub fn set_part_size(&mut self, part_size: usize) -> &mut Self {
+        assert!(part_size > 0);
+        self.part_size = part_size;
+        self
+    }
+
+    /// Returns the number of parts committed so far.
+    #[inline]
+    pub fn committed_parts(&self) -> usize {
+        self.parts
+    }
+
+    /// Returns the total number of bytes written so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing has been written.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Commits the data written since last commit as a part.
+    ///
+    /// It does nothing if no data is written since last commit.
+    pub fn commit_part(&mut self) -> Result<()> {
+        if self.part_len == 0 {
+            return Ok(());
+        }
+        self.file.finish()?;
+        self.part_len = 0;
+        self.parts += 1;
+        Ok(())
+    }
+
+    /// Publishes all written data as a new version of the target file.
+    ///
+    /// The remaining data is committed as the last part first. If the target
+    /// file doesn't exist, it is created. Creating the file and adding the
+    /// new version are done in one transaction, so the target file is either
+    /// fully updated or not changed at all.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFile`] if the target path exists but is not a
+    /// regular file, and [`Error::NotFound`] if its parent directory doesn't
+    /// exist.
+    ///
+    /// [`Error::NotFile`]: enum.Error.html
+    /// [`Error::NotFound`]: enum.Error.html
+    pub fn publish(mut self) -> Result<()> {
+        self.commit_part()?;
+        let fs = self.fs.upgrade().ok_or(Error::RepoClosed)?;
+        let mut fs = fs.write().unwrap();
+        fs.publish_temp(&self.temp, &self.path)
+    }
+}
+
+impl Write for MultiPartWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let len = buf.len().min(self.part_size - self.part_len);
+        let written = self.file.write(&buf[..len])?;
+        self.part_len += written;
+        self.len += written;
+        if self.part_len >= self.part_size {
+            map_io_err!(self.commit_part())?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if self.part_len == 0 {
+            return Ok(());
+        }
+        self.file.flush()
+    }
+}
+
+impl Debug for MultiPartWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiPartWriter")
+            .field("path", &self.path)
+            .field("part_size", &self.part_size)
+            .field("parts", &self.parts)
+            .field("len", &self.len)
+            .finish()
+    }
+}
@@ -1,14 +1,32 @@
+use std::cmp::min;
 use std::fmt::{self, Debug};
-use std::io::SeekFrom;
-use std::path::Path;
-use std::time::SystemTime;
+use std::io::{Read, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use super::{File, Result};
-use base::crypto::{Cipher, Cost, MemLimit, OpsLimit};
-use base::{self, Time};
+use base::crypto::{
+    Cipher, Cost, Kdf, KdfParams, Key, MemLimit, NonceStrategy, OpsLimit,
+    ScryptParams,
+};
+use base::{self, IntoRef, Time};
+use drop_box::{DropPublicKey, DropReader};
 use error::Error;
-use fs::{Config, DirEntry, FileType, Fs, Metadata, Options, Version};
+use fs::{
+    Config, DirEntry, DirOptions, DiskUsage, FileType, Fs, FsRef, Metadata,
+    Options, PasswordCheck, PathPolicy, Permissions, Quota, RetentionPolicy,
+    Snapshot, TrashEntry, VerifyReport, Version, WalkDir, WatchEvent,
+};
+use import::{ImportOptions, ImportReport};
+use multipart::MultiPartWriter;
+use tar::TarWriter;
+use trace::{Op, TraceOpts, Tracer, TracerRef};
 use trans::Eid;
+use transfer::TransferOptions;
+use volume::{Credential, KeyProvider, OpenPhase, RetryPolicy, BLK_SIZE};
 
 /// A builder used to create a repository [`Repo`] in various manners.
 ///
@@ -68,9 +86,32 @@ pub struct RepoOpener {
     create_new: bool,
     read_only: bool,
     force: bool,
+    trash: bool,
+    trash_expiry: Option<Duration>,
+    lock_wait: Option<Duration>,
+    path_policy: PathPolicy,
+    pwd_check: Option<PasswordCheck>,
+    progress: Option<OpenProgress>,
+    atime: bool,
+}
+
+// repo open progress callback
+#[derive(Clone)]
+struct OpenProgress(Arc<dyn Fn(OpenPhase) + Send + Sync>);
+
+impl Debug for OpenProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("OpenProgress").finish()
+    }
 }
 
 impl RepoOpener {
+    // default trash entry expiry, 30 days
+    const DEFAULT_TRASH_EXPIRY: u64 = 30 * 24 * 60 * 60;
+
+    // interval between retries when waiting for repo lock, in milliseconds
+    const LOCK_RETRY_INTERVAL: u64 = 100;
+
     /// Creates a blank new set of options ready for configuration.
     #[inline]
     pub fn new() -> Self {
@@ -95,11 +136,97 @@ impl RepoOpener {
         self
     }
 
+    /// Sets custom Argon2id password hash parameters.
+    ///
+    /// This option is only used for creating a repository. The parameters
+    /// take precedence over [`ops_limit`] and [`mem_limit`], they are stored
+    /// in super block and used each time the repository is opened. See
+    /// [`KdfParams`] for the valid range of each parameter, invalid
+    /// parameters will cause [`Error::InvalidCost`] when opening.
+    ///
+    /// `parallelism` greater than 1 needs Cargo feature `argon2` or
+    /// `crypto-rust`, because libsodium only supports a single lane.
+    /// Otherwise [`Error::InvalidArgument`] is returned when opening.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// // 32 MiB memory, 3 iterations, 1 lane
+    /// let repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .kdf_params(32 * 1024, 3, 1)
+    ///     .open("mem://foo", "pwd")?;
+    /// let kdf = repo.info()?.kdf_params().unwrap();
+    /// assert_eq!(kdf.iterations(), 3);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`ops_limit`]: struct.RepoOpener.html#method.ops_limit
+    /// [`mem_limit`]: struct.RepoOpener.html#method.mem_limit
+    /// [`KdfParams`]: struct.KdfParams.html
+    /// [`Error::InvalidCost`]: enum.Error.html
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn kdf_params(
+        &mut self,
+        mem_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> &mut Self {
+        self.cfg.cost.kdf =
+            Some(KdfParams::new(mem_kib, iterations, parallelism));
+        self
+    }
+
+    /// Uses scrypt as password hash algorithm instead of Argon2id.
+    ///
+    /// This option is only used for creating a repository. The algorithm
+    /// and its parameters are recorded in super block and verified each
+    /// time the repository is opened. See [`ScryptParams`] for the meaning
+    /// of each parameter. Invalid parameters, or setting this together with
+    /// [`kdf_params`], will cause [`Error::InvalidCost`] when opening.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, Kdf};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// // N = 2^14, r = 8, p = 1
+    /// let repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .scrypt_params(14, 8, 1)
+    ///     .open("mem://foo", "pwd")?;
+    /// assert_eq!(repo.info()?.kdf(), Kdf::Scrypt);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`ScryptParams`]: struct.ScryptParams.html
+    /// [`kdf_params`]: struct.RepoOpener.html#method.kdf_params
+    /// [`Error::InvalidCost`]: enum.Error.html
+    pub fn scrypt_params(&mut self, log_n: u8, r: u32, p: u32) -> &mut Self {
+        self.cfg.cost.scrypt = Some(ScryptParams::new(log_n, r, p));
+        self
+    }
+
     /// Sets the crypto cipher encrypts the repository.
     ///
     /// This option is only used for creating a repository. `Cipher::Aes` is
     /// the default if CPU supports AES-NI instructions, otherwise it will fall
     /// back to `Cipher::Xchacha`.
+    /// `Cipher::AesGcmSiv` can be chosen if nonce reuse is a concern, for
+    /// example when the storage might be restored from a snapshot, it needs
+    /// Cargo feature `aes-gcm-siv` or `crypto-rust`.
+    /// `Cipher::Integrity` creates an integrity-only repository, whose data
+    /// is stored unencrypted but still authenticated, so tampering is
+    /// detected while files, versions and transactions work as usual. The
+    /// password is still needed to open it.
     pub fn cipher(&mut self, cipher: Cipher) -> &mut Self {
         self.cfg.cipher = cipher;
         self
@@ -131,12 +258,32 @@ impl RepoOpener {
     /// This options indicates whether the LZ4 compression should be used in
     /// the repository. Default is false.
     ///
-    /// This option is only used when creating a repository.
+    /// This option is only used when creating a repository, use
+    /// [`Repo::set_compress`] to change it later.
+    ///
+    /// [`Repo::set_compress`]: struct.Repo.html#method.set_compress
     pub fn compress(&mut self, compress: bool) -> &mut Self {
         self.cfg.compress = compress;
         self
     }
 
+    /// Sets the sector size in bytes.
+    ///
+    /// Some storages, such as the file storage, group blocks into sector
+    /// files. Larger sectors mean fewer files and less sector metadata, which
+    /// suits repositories holding large files. The size must be a power of 2
+    /// within [512KB, 256MB]. Default is 0, which means using the storage's
+    /// default size, 32MB for the file storage. Storages without sectors
+    /// ignore this option.
+    ///
+    /// Block size is fixed and cannot be changed by this option.
+    ///
+    /// This option is only used when creating a repository.
+    pub fn sector_size(&mut self, sector_size: usize) -> &mut Self {
+        self.cfg.sector_size = sector_size;
+        self
+    }
+
     /// Sets the default maximum number of file version.
     ///
     /// The `version_limit` must be within [1, 255], default is 1. This
@@ -150,6 +297,23 @@ impl RepoOpener {
         self
     }
 
+    /// Sets the default file version retention policy.
+    ///
+    /// Versions are removed by their age according to the policy, besides
+    /// the limit set by [`version_limit`]. This setting is a repository-wise
+    /// setting, individual file can overwrite it by setting [`retention`] in
+    /// [`OpenOptions`]. Default policy doesn't remove any version.
+    ///
+    /// This option is only used when creating a repository.
+    ///
+    /// [`version_limit`]: struct.RepoOpener.html#method.version_limit
+    /// [`retention`]: struct.OpenOptions.html#method.retention
+    /// [`OpenOptions`]: struct.OpenOptions.html
+    pub fn retention(&mut self, retention: RetentionPolicy) -> &mut Self {
+        self.cfg.opts.retention = retention;
+        self
+    }
+
     /// Sets the default option for file data chunk deduplication.
     ///
     /// This option indicates whether data chunk should be deduped when
@@ -175,14 +339,84 @@ impl RepoOpener {
         self
     }
 
+    /// Sets the repository label.
+    ///
+    /// The label is a short name to identify the repository, it can be changed
+    /// later by [`Repo::set_label`]. This option is only used for creating a
+    /// repository. Default is empty.
+    ///
+    /// [`Repo::set_label`]: struct.Repo.html#method.set_label
+    pub fn label(&mut self, label: &str) -> &mut Self {
+        self.cfg.label = label.to_string();
+        self
+    }
+
+    /// Sets the repository description.
+    ///
+    /// This option is only used for creating a repository. Default is empty.
+    pub fn description(&mut self, description: &str) -> &mut Self {
+        self.cfg.description = description.to_string();
+        self
+    }
+
+    /// Sets the application tag of the repository.
+    ///
+    /// Application tag can be used to identify which application the
+    /// repository belongs to. This option is only used for creating a
+    /// repository. Default is empty.
+    pub fn app_tag(&mut self, app_tag: &str) -> &mut Self {
+        self.cfg.app_tag = app_tag.to_string();
+        self
+    }
+
+    /// Sets the password hint.
+    ///
+    /// The password hint is stored in super block **without encryption**, so
+    /// it can be retrieved by [`Repo::password_hint`] before the repository
+    /// is opened. Never put any confidential data in it. It cannot be longer
+    /// than 255 bytes.
+    ///
+    /// This option is only used for creating a repository. Default is empty,
+    /// which means no password hint.
+    ///
+    /// [`Repo::password_hint`]: struct.Repo.html#method.password_hint
+    pub fn password_hint(&mut self, hint: &str) -> &mut Self {
+        self.cfg.pwd_hint = hint.to_string();
+        self
+    }
+
     /// Sets the option for read-only mode.
     ///
     /// This option cannot be true with either `create` or `create_new` is true.
+    ///
+    /// A read-only repository can be opened while another process holds it
+    /// open for writing, as long as the storage supports shared opening.
+    /// Currently only file storage supports it. Use [`Repo::refresh`] to see
+    /// changes committed by the writer after opening.
+    ///
+    /// While any read-only repository is open, the writer keeps deleted data
+    /// in place and skips storage compaction, the space is reclaimed after
+    /// all readers are closed.
+    ///
+    /// [`Repo::refresh`]: struct.Repo.html#method.refresh
     pub fn read_only(&mut self, read_only: bool) -> &mut Self {
         self.read_only = read_only;
         self
     }
 
+    /// Sets the retry policy for failed requests to underlying storage.
+    ///
+    /// Network-based storages, such as Google Cloud Storage, Redis and
+    /// read-only HTTP storage, retry transient failures as specified by this
+    /// policy, so they don't abort whole transactions. Other storages ignore
+    /// this option. [`RetryPolicy::default()`] is the default.
+    ///
+    /// [`RetryPolicy::default()`]: struct.RetryPolicy.html#impl-Default
+    pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.cfg.retry = policy;
+        self
+    }
+
     /// Sets the option to open repo regardless repo lock.
     ///
     /// Normally, repo will be exclusively locked once it is opened. But when
@@ -194,6 +428,225 @@ impl RepoOpener {
         self
     }
 
+    /// Sets how long to wait for the repo lock when opening.
+    ///
+    /// By default, opening a repository which is already opened elsewhere
+    /// fails immediately with [`Error::RepoOpened`]. When this option is set,
+    /// opening is retried until the repo lock is released or `timeout`
+    /// expires, [`Error::RepoOpened`] is returned if the lock is still held
+    /// then. This option is ignored if [`force`] is set.
+    ///
+    /// [`Error::RepoOpened`]: enum.Error.html
+    /// [`force`]: struct.RepoOpener.html#method.force
+    pub fn lock_wait(&mut self, timeout: Duration) -> &mut Self {
+        self.lock_wait = Some(timeout);
+        self
+    }
+
+    /// Sets the path validation policy.
+    ///
+    /// Paths of newly created files and directories, as well as the target
+    /// paths of rename and copy, are checked against this policy and
+    /// rejected with [`Error::PathViolation`] if they break it. This is
+    /// useful to keep a repository portable, so it can be exported to other
+    /// file systems later. See [`PathPolicy`] for more details.
+    ///
+    /// This option is not persisted, it only applies to the current session.
+    /// Default is [`PathPolicy::default`], which doesn't restrict anything.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::{Error, PathPolicy, PathViolation};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    ///
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .path_policy(PathPolicy::portable())
+    ///     .open("mem://foo", "pwd")?;
+    ///
+    /// assert_eq!(
+    ///     repo.create_dir("/aux").unwrap_err(),
+    ///     Error::PathViolation(PathViolation::ReservedName("aux".to_string()))
+    /// );
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::PathViolation`]: enum.Error.html
+    /// [`PathPolicy`]: struct.PathPolicy.html
+    /// [`PathPolicy::default`]: struct.PathPolicy.html
+    pub fn path_policy(&mut self, policy: PathPolicy) -> &mut Self {
+        self.path_policy = policy;
+        self
+    }
+
+    /// Sets the option for tracking file access time.
+    ///
+    /// When it is enabled, the access time of a file is updated when it is
+    /// read, and can be retrieved by [`Metadata::accessed_at`]. Like the
+    /// `relatime` mount option, the access time is only updated when it is
+    /// older than the last modification time or older than one day, so
+    /// reading a file doesn't always write to the repository.
+    ///
+    /// Access time is not tracked for a read-only repository. This option is
+    /// not persisted, it only applies to the current session. Default is
+    /// `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    ///
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .atime(true)
+    ///     .open("mem://foo", "pwd")?;
+    ///
+    /// repo.create_file("/foo.txt")?.write_once(b"foo")?;
+    /// assert!(repo.metadata("/foo.txt")?.accessed_at().is_none());
+    ///
+    /// let mut buf = Vec::new();
+    /// repo.open_file("/foo.txt")?.read_to_end(&mut buf)?;
+    /// assert!(repo.metadata("/foo.txt")?.accessed_at().is_some());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Metadata::accessed_at`]: struct.Metadata.html#method.accessed_at
+    pub fn atime(&mut self, atime: bool) -> &mut Self {
+        self.atime = atime;
+        self
+    }
+
+    /// Sets a callback to report progress of opening a repository.
+    ///
+    /// Opening a repository can take a while, as deriving key from password
+    /// is intentionally slow and a large repository needs more time to load.
+    /// The callback is called with an [`OpenPhase`] when each phase starts,
+    /// so a progress indicator can be shown meanwhile. Phases are not
+    /// reported when a new repository is created.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::OpenPhase;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    ///
+    /// let repo = RepoOpener::new()
+    ///     .on_progress(|phase| match phase {
+    ///         OpenPhase::KeyDerivation => println!("checking password..."),
+    ///         OpenPhase::SuperBlock => println!("opening storage..."),
+    ///         OpenPhase::WalRedo => println!("recovering..."),
+    ///         OpenPhase::TreeLoad => println!("loading..."),
+    ///     })
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`OpenPhase`]: enum.OpenPhase.html
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(OpenPhase) + Send + Sync + 'static,
+    {
+        self.progress = Some(OpenProgress(Arc::new(callback)));
+        self
+    }
+
+    /// Sets a validator to check strength of new passwords.
+    ///
+    /// The validator is called with the password when a new repository is
+    /// created, and with the new password when it is changed by
+    /// [`Repo::reset_password`]. It returns `false` to reject the password,
+    /// then [`Error::WeakPassword`] is returned. Passwords of existing
+    /// repositories are not checked when opening them.
+    ///
+    /// This option is not persisted, it only applies to the current session.
+    /// By default, any password is accepted.
+    ///
+    /// # Examples
+    ///
+    /// Reject short and common passwords.
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::Error;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut opener = RepoOpener::new();
+    /// opener.create(true).password_validator(|pwd| {
+    ///     pwd.len() >= 12 && !["password", "123456"].contains(&pwd)
+    /// });
+    ///
+    /// assert_eq!(
+    ///     opener.open("mem://foo", "password").unwrap_err(),
+    ///     Error::WeakPassword
+    /// );
+    /// let repo = opener.open("mem://foo", "correct horse battery")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::reset_password`]: struct.Repo.html#method.reset_password
+    /// [`Error::WeakPassword`]: enum.Error.html
+    pub fn password_validator<F>(&mut self, validator: F) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.pwd_check = Some(PasswordCheck::new(validator));
+        self
+    }
+
+    /// Sets the option for trash mode.
+    ///
+    /// When trash mode is enabled, files and directories removed by
+    /// [`Repo::remove_file`], [`Repo::remove_dir`] and
+    /// [`Repo::remove_dir_all`] are moved to trash rather than being removed
+    /// permanently. They can be listed by [`Repo::list_trash`] and restored
+    /// by [`Repo::restore_from_trash`]. Trash is kept outside the directory
+    /// tree, so it never shows up in directory listings and doesn't reserve
+    /// any path.
+    ///
+    /// Trash entries which are older than [`trash_expiry`] are removed
+    /// permanently when the repository is opened and when another entry is
+    /// moved to trash. This option is not persisted, it only applies to the
+    /// current session. Default is false.
+    ///
+    /// [`Repo::remove_file`]: struct.Repo.html#method.remove_file
+    /// [`Repo::remove_dir`]: struct.Repo.html#method.remove_dir
+    /// [`Repo::remove_dir_all`]: struct.Repo.html#method.remove_dir_all
+    /// [`Repo::list_trash`]: struct.Repo.html#method.list_trash
+    /// [`Repo::restore_from_trash`]: struct.Repo.html#method.restore_from_trash
+    /// [`trash_expiry`]: struct.RepoOpener.html#method.trash_expiry
+    pub fn trash(&mut self, trash: bool) -> &mut Self {
+        self.trash = trash;
+        self
+    }
+
+    /// Sets how long entries are kept in trash.
+    ///
+    /// This option is only used when trash mode is enabled, see [`trash`].
+    /// Default is 30 days.
+    ///
+    /// [`trash`]: struct.RepoOpener.html#method.trash
+    pub fn trash_expiry(&mut self, expiry: Duration) -> &mut Self {
+        self.trash_expiry = Some(expiry);
+        self
+    }
+
     /// Opens a repository at URI with the password and options specified by
     /// `self`.
     ///
@@ -225,8 +678,34 @@ impl RepoOpener {
     ///
     ///   For example, `file://./foo/bar` or `file://C:/Users/foo bar/dir`.
     ///
+    ///   Data blocks are stored in fixed-size sector files no larger than 32
+    ///   MiB, so the repository can also be put on file systems which have
+    ///   file size limit, such as FAT32 or exFAT on USB drives and SD cards.
+    ///
     ///   This storage must be enabled by Cargo feature `storage-file`.
     ///
+    /// - Single file container storage, URI identifier is `container://`
+    ///
+    ///   After the identifier is the path to a container file. The whole
+    ///   repository is stored in this single file, so it can be easily copied,
+    ///   attached or sent as one artifact.
+    ///
+    ///   For example, `container://./foo/bar.zbox`.
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-container`.
+    ///
+    /// - Read-only HTTP storage, URI identifier is `http://` or `https://`
+    ///
+    ///   The URI is the URL of a container file published on a web server,
+    ///   such as a static file host or a CDN. The super block and blocks are
+    ///   fetched using HTTP range requests, so the web server must support
+    ///   them. This storage is read-only, the repository must be opened with
+    ///   [`read_only`] set to true.
+    ///
+    ///   For example, `https://example.com/repos/bar.zbox`.
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-http`.
+    ///
     /// - SQLite storage, URI identifier is `sqlite://`
     ///
     ///   After the identifier is the path to a SQLite database file. It can also
@@ -234,17 +713,135 @@ impl RepoOpener {
     ///
     ///   For example, `sqlite://./foobar.sqlite`.
     ///
+    ///   Below URI parameters can be used to configure the database
+    ///   connection:
+    ///
+    ///   - `journal`: journal mode, can be `delete`, `truncate`, `persist`,
+    ///     `memory`, `wal` or `off`
+    ///   - `busy_timeout`: time in milliseconds to wait when the database is
+    ///     locked by other connections
+    ///
+    ///   For example, `sqlite://./foobar.sqlite?journal=wal&busy_timeout=5000`.
+    ///
+    ///   URI parameters start after the last `?`, so parameter values cannot
+    ///   contain `?`. If the file path itself contains `?`, a `?` must be
+    ///   appended even if there is no parameter, for example,
+    ///   `sqlite://./foo?bar.sqlite?`. Versions before URI parameters were
+    ///   supported took the whole location as file path, a repository at
+    ///   such a path must now be opened in this way, otherwise the part after
+    ///   `?` is taken as parameters and [`Error::InvalidUri`] is returned if
+    ///   they are not valid.
+    ///
     ///   This storage must be enabled by Cargo feature `storage-sqlite`.
     ///
+    ///   The SQLite database file itself can also be encrypted using SQLCipher
+    ///   by URI identifier `sqlcipher://`. The database key, which is separate
+    ///   from repository password, must be specified by URI parameter `key`.
+    ///   For example, `sqlcipher://./foobar.sqlite?key=secret`.
+    ///   The key is not saved in the repository, it is also removed from the
+    ///   URI returned by [`RepoInfo::uri`].
+    ///
+    ///   SQLCipher support must be enabled by Cargo feature
+    ///   `storage-sqlcipher`, which requires SQLCipher library installed.
+    ///
     /// - Redis storage, URI identifier is `redis://`
     ///
     ///   After the identifier is the path to a Redis instance. Unix socket is
     ///   supported. The URI format is:
     ///
-    ///   `redis://[+unix+][:<passwd>@]<hostname>[:port][/<db>]`
+    ///   `redis://[+unix+][[<user>]:<passwd>@]<hostname>[:port][/<db>]`
+    ///
+    ///   If user name is specified, it will be used with password for Redis
+    ///   ACL authentication.
+    ///
+    ///   TLS connection is supported by URI identifier `rediss://`, add
+    ///   `#insecure` to the end of URI to skip certificate verification. TLS
+    ///   support must be enabled by Cargo feature `storage-redis-tls`. The
+    ///   URI format is:
+    ///
+    ///   `rediss://[[<user>]:<passwd>@]<hostname>[:port][/<db>][#insecure]`
+    ///
+    ///   Redis Sentinel is supported by URI identifier `redis+sentinel://`,
+    ///   the master is discovered from the sentinels and re-discovered after
+    ///   failover. The URI format is:
+    ///
+    ///   `redis+sentinel://[[<user>]:<passwd>@]<host>[:port][,<host>[:port]...]/<master>[/<db>]`
+    ///
+    ///   Redis Cluster is supported by URI identifier `redis+cluster://`, keys
+    ///   are routed to the nodes serving their hash slots. The URI format is:
+    ///
+    ///   `redis+cluster://[[<user>]:<passwd>@]<host>[:port][,<host>[:port]...]`
     ///
     ///   This storage must be enabled by Cargo feature `storage-redis`.
     ///
+    /// - Sled storage, URI identifier is `sled://`
+    ///
+    ///   After the identifier is the path to a sled database directory. It
+    ///   is a pure-Rust embedded key-value store, so no C library is needed.
+    ///
+    ///   For example, `sled://./foobar.sled`.
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-sled`.
+    ///
+    /// - Google Cloud Storage, URI identifier is `gs://`
+    ///
+    ///   After the identifier is the bucket name and an optional object name
+    ///   prefix. The URI format is:
+    ///
+    ///   `gs://<bucket>[/<prefix>][?credentials=<path>&token=<token>&endpoint=<url>&retry=<n>]`
+    ///
+    ///   Service account key file is specified by `credentials` parameter, or
+    ///   by the `GOOGLE_APPLICATION_CREDENTIALS` environment variable. An
+    ///   OAuth 2.0 access token can also be given directly by `token`
+    ///   parameter. Failed requests are retried as specified by
+    ///   [`retry_policy`], and `retry` parameter overrides its maximum number
+    ///   of retries.
+    ///
+    ///   For example, `gs://my-bucket/repos/foo`.
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-gcs`.
+    ///
+    /// Any of the above storages can be fronted by a local on-disk block cache,
+    /// by adding `cache+` to the URI identifier. This is useful for remote
+    /// storages, as blocks are read from local cache without round trips. The
+    /// cache is configured by the below URI parameters, which are removed
+    /// before passing the URI to the underlying storage:
+    ///
+    /// - `cache_dir`: directory to put cache file in, default is system
+    ///   temporary directory
+    /// - `cache_size`: cache capacity in MB, default is `64mb`
+    /// - `cache_policy`: cache eviction policy, `lru` or `fifo`, default is
+    ///   `lru`
+    ///
+    /// For example, `cache+gs://my-bucket/foo?cache_size=512mb`.
+    ///
+    /// The cache is write-through and not kept after the repository is
+    /// closed. It must be enabled by Cargo feature `storage-cache`.
+    ///
+    /// Similarly, storage throughput can be limited by adding `throttle+` to
+    /// the URI identifier, so background writes don't saturate the network
+    /// link to a remote storage. The limits are set by the below URI
+    /// parameters, at least one of them must be specified:
+    ///
+    /// - `throttle_bps`: bytes per second, with optional `kb` or `mb` unit
+    /// - `throttle_ops`: storage operations per second
+    ///
+    /// For example, `throttle+gs://my-bucket/foo?throttle_bps=2mb`. It can
+    /// be combined with cache, such as `cache+throttle+gs://my-bucket/foo`,
+    /// and must be enabled by Cargo feature `storage-throttle`.
+    ///
+    /// Blocks can also be striped across multiple storages, by using URI
+    /// identifier `stripe://` followed by the member storage URIs separated
+    /// by `|`. For example, `stripe://file:///mnt/a|gs://my-bucket/foo`.
+    ///
+    /// Blocks are distributed to members in round-robin order, and the stripe
+    /// mapping is saved along with super block in every member, so member
+    /// URIs can be specified in any order when opening the repository. It
+    /// must be enabled by Cargo feature `storage-stripe`.
+    ///
+    /// Custom storages registered by [`register_storage`] can be used with
+    /// their registered URI identifiers as well.
+    ///
     /// After a repository is opened, all of the other methods provided by
     /// ZboxFS will be thread-safe.
     ///
@@ -255,31 +852,241 @@ impl RepoOpener {
     ///
     /// Open a memory based repository without enable `create` option will
     /// return an error.
+    ///
+    /// [`read_only`]: struct.RepoOpener.html#method.read_only
+    /// [`retry_policy`]: struct.RepoOpener.html#method.retry_policy
+    /// [`register_storage`]: fn.register_storage.html
+    /// [`RepoInfo::uri`]: struct.RepoInfo.html#method.uri
+    /// [`Error::InvalidUri`]: enum.Error.html
+    #[inline]
     pub fn open(&self, uri: &str, pwd: &str) -> Result<Repo> {
-        // version limit must be greater than 0
-        if self.cfg.opts.version_limit == 0 {
+        self.open_with(uri, Credential::Password(pwd))
+    }
+
+    /// Opens a repository at URI with a key derived from its password.
+    ///
+    /// The key is derived by [`Repo::derive_key`], so the slow password
+    /// hashing is skipped. This is useful for services which open the same
+    /// repository repeatedly. All the options are applied as [`open`] does,
+    /// but a new repository cannot be created as the password is unknown.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`InvalidArgument`] error if the repository doesn't exist and
+    /// `create` option is set, or `create_new` option is set. Returns
+    /// [`Decrypt`] error if the key doesn't match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::Repo;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    ///
+    /// let key = Repo::derive_key("mem://foo", "pwd")?;
+    /// let repo = RepoOpener::new().open_with_key("mem://foo", &key)?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+    /// [`open`]: struct.RepoOpener.html#method.open
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`Decrypt`]: enum.Error.html#variant.Decrypt
+    #[inline]
+    pub fn open_with_key(&self, uri: &str, key: &DerivedKey) -> Result<Repo> {
+        if self.create_new {
             return Err(Error::InvalidArgument);
         }
+        self.open_with(uri, Credential::Key(&key.0))
+    }
 
-        if self.create {
-            if self.read_only {
-                return Err(Error::InvalidArgument);
-            }
-            if Repo::exists(uri)? {
-                if self.create_new {
-                    return Err(Error::RepoExists);
-                }
-                Repo::open(uri, pwd, self.read_only, self.force)
-            } else {
-                Repo::create(uri, pwd, &self.cfg)
-            }
-        } else {
-            Repo::open(uri, pwd, self.read_only, self.force)
-        }
+    /// Opens a repository at URI using a key provider instead of password.
+    ///
+    /// The volume key is unwrapped by the key provider, for example a
+    /// hardware token, so no password is needed. A new repository can be
+    /// created in this way, its volume key is generated randomly and wrapped
+    /// by the key provider. See [`KeyProvider`] for more details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyProvider`] error if the repository is not
+    /// protected by a key provider. Returns [`Decrypt`] error if the
+    /// unwrapped key doesn't match.
+    ///
+    /// [`KeyProvider`]: trait.KeyProvider.html
+    /// [`Error::KeyProvider`]: enum.Error.html#variant.KeyProvider
+    /// [`Decrypt`]: enum.Error.html#variant.Decrypt
+    #[inline]
+    pub fn open_with_provider(
+        &self,
+        uri: &str,
+        provider: &dyn KeyProvider,
+    ) -> Result<Repo> {
+        self.open_with(uri, Credential::Provider(provider))
     }
-}
 
-/// Options and flags which can be used to configure how a file is opened.
+    /// Opens a repository at URI using a recovery key and its recovery
+    /// password.
+    ///
+    /// The recovery key is exported by [`Repo::export_recovery_key`], it
+    /// gives full access to the repository without the repository password,
+    /// for example, when the password is forgotten. All the options are
+    /// applied as [`open`] does, but a new repository cannot be created.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`InvalidArgument`] error if the repository doesn't exist and
+    /// `create` option is set, `create_new` option is set, or the recovery
+    /// key is malformed. Returns [`Decrypt`] error if the recovery password
+    /// is wrong, or the recovery key doesn't belong to the repository or is
+    /// outdated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let key = repo.export_recovery_key("pwd", "escrow pwd")?;
+    /// drop(repo);
+    ///
+    /// // the repository password is not needed
+    /// let repo = RepoOpener::new().open_with_recovery_key(
+    ///     "mem://foo",
+    ///     &key,
+    ///     "escrow pwd",
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::export_recovery_key`]: struct.Repo.html#method.export_recovery_key
+    /// [`open`]: struct.RepoOpener.html#method.open
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`Decrypt`]: enum.Error.html#variant.Decrypt
+    #[inline]
+    pub fn open_with_recovery_key(
+        &self,
+        uri: &str,
+        key: &RecoveryKey,
+        recovery_pwd: &str,
+    ) -> Result<Repo> {
+        if self.create_new {
+            return Err(Error::InvalidArgument);
+        }
+        self.open_with(uri, Credential::Recovery(&key.0, recovery_pwd))
+    }
+
+    // open repo with password, derived key or key provider
+    fn open_with(&self, uri: &str, cred: Credential) -> Result<Repo> {
+        // version limit must be greater than 0
+        if self.cfg.opts.version_limit == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.cfg.opts.retention.check()?;
+        self.cfg.cost.check()?;
+
+        check_label(&self.cfg.label, &self.cfg.description, &self.cfg.app_tag)?;
+        if self.cfg.pwd_hint.len() > MAX_LABEL_LEN {
+            return Err(Error::InvalidArgument);
+        }
+
+        let progress = |phase| {
+            if let Some(ref progress) = self.progress {
+                (progress.0)(phase);
+            }
+        };
+        let open_repo = || {
+            if self.create {
+                if self.read_only {
+                    return Err(Error::InvalidArgument);
+                }
+                if Repo::exists(uri)? {
+                    if self.create_new {
+                        return Err(Error::RepoExists);
+                    }
+                    Repo::open(
+                        uri,
+                        cred,
+                        self.read_only,
+                        self.force,
+                        self.cfg.retry,
+                        &progress,
+                    )
+                } else {
+                    match cred {
+                        Credential::Password(pwd) => {
+                            if let Some(ref pwd_check) = self.pwd_check {
+                                pwd_check.check(pwd)?;
+                            }
+                            Repo::create(uri, cred, &self.cfg)
+                        }
+                        Credential::Provider(_) => {
+                            Repo::create(uri, cred, &self.cfg)
+                        }
+                        Credential::Key(_) | Credential::Recovery(..) => {
+                            Err(Error::InvalidArgument)
+                        }
+                    }
+                }
+            } else {
+                Repo::open(
+                    uri,
+                    cred,
+                    self.read_only,
+                    self.force,
+                    self.cfg.retry,
+                    &progress,
+                )
+            }
+        };
+
+        // retry opening until repo lock is released or wait is timed out
+        let mut deadline = None;
+        let repo = loop {
+            match open_repo() {
+                Err(ref err) if *err == Error::RepoOpened => {
+                    let timeout = self.lock_wait.ok_or(Error::RepoOpened)?;
+                    let now = Instant::now();
+                    let deadline = *deadline.get_or_insert(now + timeout);
+                    if now >= deadline {
+                        return Err(Error::RepoOpened);
+                    }
+                    let interval =
+                        Duration::from_millis(Self::LOCK_RETRY_INTERVAL);
+                    thread::sleep(min(interval, deadline - now));
+                }
+                result => break result?,
+            }
+        };
+
+        if self.trash {
+            let expiry = self.trash_expiry.unwrap_or_else(|| {
+                Duration::from_secs(Self::DEFAULT_TRASH_EXPIRY)
+            });
+            repo.fs.write().unwrap().set_trash(Some(expiry))?;
+        }
+        repo.fs
+            .write()
+            .unwrap()
+            .set_path_policy(self.path_policy.clone());
+        repo.fs
+            .write()
+            .unwrap()
+            .set_password_check(self.pwd_check.clone());
+        repo.fs.write().unwrap().set_atime(self.atime);
+
+        Ok(repo)
+    }
+}
+
+/// Options and flags which can be used to configure how a file is opened.
 ///
 /// This builder exposes the ability to configure how a [`File`] is opened and
 /// what operations are permitted on the opened file. The [`Repo::open_file`]
@@ -329,6 +1136,8 @@ pub struct OpenOptions {
     create_new: bool,
     version_limit: Option<u8>,
     dedup_chunk: Option<bool>,
+    retention: Option<RetentionPolicy>,
+    auto_commit: bool,
 }
 
 impl OpenOptions {
@@ -369,8 +1178,35 @@ impl OpenOptions {
 
     /// Sets the option for truncating a previous file.
     ///
+    /// If a file is successfully opened with this option set, its content is
+    /// truncated to 0 length when it is opened. Like [`set_len`], the
+    /// truncation is added as a new empty version, so previous content is
+    /// still kept in version history. Nothing is changed if the file is
+    /// already empty.
+    ///
     /// Note that setting `.write(true).truncate(true)` has the same effect as
     /// setting only `.truncate(true)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"foo")?;
+    ///
+    /// let mut file = OpenOptions::new()
+    ///     .truncate(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// assert_eq!(file.metadata()?.content_len(), 0);
+    /// file.write_once(b"bar")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`set_len`]: struct.File.html#method.set_len
     pub fn truncate(&mut self, truncate: bool) -> &mut OpenOptions {
         self.truncate = truncate;
         if truncate {
@@ -421,12 +1257,95 @@ impl OpenOptions {
     /// writing data to a file. It will fall back to repository's
     /// [`dedup_chunk`] if it is not set.
     ///
+    /// Deduplication needs to hash every chunk written, which is pure
+    /// overhead for data unlikely to be duplicated, such as compressed or
+    /// random data. Setting this option to false turns it off for such files
+    /// while leaving it on for the others in the repository.
+    ///
+    /// This option only applies when the file is created, an existing file
+    /// keeps the setting it was created with.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .dedup_chunk(true)
+    ///     .open("mem://foo", "pwd")?;
+    ///
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .dedup_chunk(false)
+    ///     .open(&mut repo, "/random.bin")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
     /// [`dedup_chunk`]: struct.RepoOpener.html#method.dedup_chunk
     pub fn dedup_chunk(&mut self, dedup_chunk: bool) -> &mut OpenOptions {
         self.dedup_chunk = Some(dedup_chunk);
         self
     }
 
+    /// Sets the file version retention policy.
+    ///
+    /// It will fall back to repository's [`retention`] if it is not set.
+    ///
+    /// [`retention`]: struct.RepoOpener.html#method.retention
+    pub fn retention(
+        &mut self,
+        retention: RetentionPolicy,
+    ) -> &mut OpenOptions {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Sets the option for finishing pending write when the file is dropped.
+    ///
+    /// Data written by [`Write`] is not committed until [`finish`] is called.
+    /// This option, when true, means that dropping the [`File`] finishes
+    /// the pending write and creates a new version, as if [`finish`] is
+    /// called. If the file is dropped while the thread is panicking, the
+    /// pending write is aborted instead, so partially written data is never
+    /// committed. Errors during finishing are logged but otherwise ignored,
+    /// call [`finish`] explicitly to handle them.
+    ///
+    /// Default is false, in which case dropping the file discards the
+    /// pending write.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, OpenOptions};
+    /// use std::io::Write;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// {
+    ///     let mut file = OpenOptions::new()
+    ///         .create(true)
+    ///         .auto_commit(true)
+    ///         .open(&mut repo, "/foo.txt")?;
+    ///     file.write_all(b"Hello, World!")?;
+    /// }
+    /// assert_eq!(repo.metadata("/foo.txt")?.content_len(), 13);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`File`]: struct.File.html
+    pub fn auto_commit(&mut self, auto_commit: bool) -> &mut OpenOptions {
+        self.auto_commit = auto_commit;
+        self
+    }
+
     /// Opens a file at path with the options specified by `self`.
     pub fn open<P: AsRef<Path>>(
         &self,
@@ -439,7 +1358,106 @@ impl OpenOptions {
                 return Err(Error::InvalidArgument);
             }
         }
-        open_file_with_options(&mut repo.fs, path, self)
+        if let Some(ref retention) = self.retention {
+            retention.check()?;
+        }
+        let path = path.as_ref();
+        let mut file = open_file_with_options(&repo.fs, path, self)?;
+        if let Some(ref tracer) = repo.tracer {
+            let mut tr = tracer.lock().unwrap();
+            let fid = tr.next_fid();
+            tr.record(&Op::Open {
+                fid,
+                path: path.to_path_buf(),
+                opts: TraceOpts::from(self),
+            });
+            file.set_tracer(tracer, fid);
+        }
+        Ok(file)
+    }
+}
+
+impl<'a> From<&'a OpenOptions> for TraceOpts {
+    fn from(opts: &'a OpenOptions) -> Self {
+        TraceOpts {
+            read: opts.read,
+            write: opts.write,
+            append: opts.append,
+            truncate: opts.truncate,
+            create: opts.create,
+            create_new: opts.create_new,
+            version_limit: opts.version_limit,
+            dedup_chunk: opts.dedup_chunk,
+        }
+    }
+}
+
+impl<'a> From<&'a TraceOpts> for OpenOptions {
+    fn from(opts: &'a TraceOpts) -> Self {
+        OpenOptions {
+            read: opts.read,
+            write: opts.write,
+            append: opts.append,
+            truncate: opts.truncate,
+            create: opts.create,
+            create_new: opts.create_new,
+            version_limit: opts.version_limit,
+            dedup_chunk: opts.dedup_chunk,
+            retention: None,
+            auto_commit: false,
+        }
+    }
+}
+
+/// Key derived from a repository password.
+///
+/// It is returned by [`Repo::derive_key`] and can be used to open the
+/// repository by [`RepoOpener::open_with_key`]. The key is held in protected
+/// memory and never printed by its `Debug` output.
+///
+/// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+/// [`RepoOpener::open_with_key`]: struct.RepoOpener.html#method.open_with_key
+#[derive(Clone)]
+pub struct DerivedKey(pub(crate) Key);
+
+impl Debug for DerivedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DerivedKey").finish()
+    }
+}
+
+/// Recovery key of a repository.
+///
+/// It is the master key of a repository encrypted by a recovery password,
+/// the repository password itself is not stored in it. It is returned by
+/// [`Repo::export_recovery_key`] and can be used to open the repository by
+/// [`RepoOpener::open_with_recovery_key`]. Its bytes can be stored anywhere,
+/// but it must be kept apart from the recovery password.
+///
+/// [`Repo::export_recovery_key`]: struct.Repo.html#method.export_recovery_key
+/// [`RepoOpener::open_with_recovery_key`]: struct.RepoOpener.html#method.open_with_recovery_key
+#[derive(Clone, PartialEq, Eq)]
+pub struct RecoveryKey(Vec<u8>);
+
+impl RecoveryKey {
+    /// Creates a recovery key from bytes.
+    ///
+    /// The bytes are not validated until the recovery key is used.
+    #[inline]
+    pub fn from_bytes(buf: &[u8]) -> Self {
+        RecoveryKey(buf.to_vec())
+    }
+
+    /// Returns bytes of the recovery key.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for RecoveryKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RecoveryKey").finish()
     }
 }
 
@@ -458,11 +1476,25 @@ pub struct RepoInfo {
     cost: Cost,
     cipher: Cipher,
     compress: bool,
+    sector_size: usize,
     version_limit: u8,
+    retention: RetentionPolicy,
     dedup_chunk: bool,
     dedup_file: bool,
     read_only: bool,
+    sealed: bool,
+    convergent: bool,
+    reencrypt_checkpoint: Option<usize>,
     ctime: Time,
+    label: String,
+    description: String,
+    app_tag: String,
+    used_blocks: usize,
+    logical_size: u64,
+    files: u64,
+    dirs: u64,
+    versions: u64,
+    retained_txs: usize,
 }
 
 impl RepoInfo {
@@ -485,6 +1517,8 @@ impl RepoInfo {
     ///
     /// This string may contain confidential data for a particular storage, such
     /// as Zbox Cloud storage, you might need to mask it as your wish.
+    ///
+    /// The SQLCipher database key is removed from the URI.
     #[inline]
     pub fn uri(&self) -> &str {
         &self.uri
@@ -502,24 +1536,73 @@ impl RepoInfo {
         self.cost.mem_limit
     }
 
+    /// Returns custom password hash parameters, or `None` if the presets
+    /// are used.
+    ///
+    /// See [`RepoOpener::kdf_params`] for more details.
+    ///
+    /// [`RepoOpener::kdf_params`]: struct.RepoOpener.html#method.kdf_params
+    #[inline]
+    pub fn kdf_params(&self) -> Option<KdfParams> {
+        self.cost.kdf
+    }
+
+    /// Returns the password hash algorithm.
+    #[inline]
+    pub fn kdf(&self) -> Kdf {
+        self.cost.kdf_alg()
+    }
+
+    /// Returns scrypt parameters, or `None` if scrypt is not used.
+    ///
+    /// See [`RepoOpener::scrypt_params`] for more details.
+    ///
+    /// [`RepoOpener::scrypt_params`]: struct.RepoOpener.html#method.scrypt_params
+    #[inline]
+    pub fn scrypt_params(&self) -> Option<ScryptParams> {
+        self.cost.scrypt
+    }
+
     /// Returns repository password encryption cipher.
     #[inline]
     pub fn cipher(&self) -> Cipher {
         self.cipher
     }
 
+    /// Returns the nonce strategy of the cipher used by this repository.
+    ///
+    /// See [`NonceStrategy`] for more details.
+    ///
+    /// [`NonceStrategy`]: enum.NonceStrategy.html
+    #[inline]
+    pub fn nonce_strategy(&self) -> NonceStrategy {
+        self.cipher.nonce_strategy()
+    }
+
     /// Returns whether compression is enabled.
     #[inline]
     pub fn compress(&self) -> bool {
         self.compress
     }
 
+    /// Returns the sector size in bytes, 0 means the storage default.
+    #[inline]
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
     /// Returns the default maximum number of file versions.
     #[inline]
     pub fn version_limit(&self) -> u8 {
         self.version_limit
     }
 
+    /// Returns the default file version retention policy.
+    #[inline]
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
     /// Returns whether the file data chunk deduplication is enabled.
     #[inline]
     pub fn dedup_chunk(&self) -> bool {
@@ -538,19 +1621,260 @@ impl RepoInfo {
         self.read_only
     }
 
+    /// Returns whether this repository is sealed.
+    ///
+    /// See [`Repo::seal`] for more details.
+    ///
+    /// [`Repo::seal`]: struct.Repo.html#method.seal
+    #[inline]
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Returns whether convergent encryption is enabled.
+    ///
+    /// See [`Repo::enable_convergent_encryption`] for more details.
+    ///
+    /// [`Repo::enable_convergent_encryption`]: struct.Repo.html#method.enable_convergent_encryption
+    #[inline]
+    pub fn is_convergent(&self) -> bool {
+        self.convergent
+    }
+
+    /// Returns the number of entities re-encrypted as of the last
+    /// checkpoint, or `None` if no re-encryption is in progress.
+    ///
+    /// An interrupted re-encryption can be resumed by [`Repo::reencrypt`].
+    ///
+    /// [`Repo::reencrypt`]: struct.Repo.html#method.reencrypt
+    #[inline]
+    pub fn reencrypt_checkpoint(&self) -> Option<usize> {
+        self.reencrypt_checkpoint
+    }
+
     /// Returns the creation time of this repository.
     #[inline]
     pub fn created_at(&self) -> SystemTime {
         self.ctime.to_system_time()
     }
+
+    /// Returns the label of this repository.
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the description of this repository.
+    #[inline]
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the application tag of this repository.
+    #[inline]
+    pub fn app_tag(&self) -> &str {
+        &self.app_tag
+    }
+
+    /// Returns the number of data blocks currently in use.
+    ///
+    /// For repositories created by older versions which didn't track block
+    /// usage, this starts from the number of blocks ever allocated.
+    #[inline]
+    pub fn used_blocks(&self) -> usize {
+        self.used_blocks
+    }
+
+    /// Returns the size in bytes of data blocks currently in use.
+    ///
+    /// This is the encrypted, compressed and padded data size written to the
+    /// storage, excluding storage-specific overhead such as indexes and
+    /// metadata. Use [`stat_fs`] to get the actual space used by the storage.
+    ///
+    /// [`stat_fs`]: struct.Repo.html#method.stat_fs
+    #[inline]
+    pub fn physical_size(&self) -> u64 {
+        self.used_blocks as u64 * BLK_SIZE as u64
+    }
+
+    /// Returns the total size in bytes of all file versions.
+    ///
+    /// All retained versions of a file are counted, deduplication and
+    /// compression are not taken into account.
+    #[inline]
+    pub fn logical_size(&self) -> u64 {
+        self.logical_size
+    }
+
+    /// Returns the number of files, including symbolic links and special
+    /// files.
+    ///
+    /// This number and the other entry counts are maintained as changes are
+    /// committed, so they are cheap to get. Entries in trash and snapshots
+    /// are not counted.
+    #[inline]
+    pub fn file_count(&self) -> u64 {
+        self.files
+    }
+
+    /// Returns the number of directories, excluding the root directory.
+    #[inline]
+    pub fn dir_count(&self) -> u64 {
+        self.dirs
+    }
+
+    /// Returns the total number of retained versions of all files.
+    #[inline]
+    pub fn version_count(&self) -> u64 {
+        self.versions
+    }
+
+    /// Returns the number of committed transactions whose write-ahead logs
+    /// are still retained.
+    ///
+    /// Data deleted or replaced by these transactions is kept for recovery
+    /// until the logs are recycled, so it is still counted in
+    /// [`used_blocks`]. Use [`Repo::compact`] to recycle them immediately.
+    ///
+    /// [`used_blocks`]: #method.used_blocks
+    /// [`Repo::compact`]: struct.Repo.html#method.compact
+    #[inline]
+    pub fn retained_txs(&self) -> usize {
+        self.retained_txs
+    }
+}
+
+/// Storage space statistics of a repository.
+///
+/// This structure is returned from the [`Repo::stat_fs`] and reports the space
+/// usage of underlying storage, all sizes are in bytes. The total capacity
+/// depends on storage type, for example, it is the used space plus disk free
+/// space for file storage and the `maxmemory` setting for Redis storage.
+///
+/// [`Repo::stat_fs`]: struct.Repo.html#method.stat_fs
+#[derive(Debug, Clone, Copy)]
+pub struct StatFs {
+    total: Option<u64>,
+    used: u64,
+}
+
+impl StatFs {
+    /// Returns the total capacity of underlying storage.
+    ///
+    /// Returns `None` if the storage has no known capacity limit.
+    #[inline]
+    pub fn total_space(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Returns the space already used by underlying storage.
+    #[inline]
+    pub fn used_space(&self) -> u64 {
+        self.used
+    }
+
+    /// Returns the free space available in underlying storage.
+    ///
+    /// Returns `None` if the storage has no known capacity limit.
+    #[inline]
+    pub fn free_space(&self) -> Option<u64> {
+        self.total.map(|total| total.saturating_sub(self.used))
+    }
+}
+
+/// Result of repository compaction.
+///
+/// This structure is returned from the [`Repo::compact`] and reports how much
+/// space was reclaimed.
+///
+/// [`Repo::compact`]: struct.Repo.html#method.compact
+#[derive(Debug, Clone, Copy)]
+pub struct CompactReport {
+    reclaimed_blocks: usize,
+    reclaimed_space: u64,
+    recycled_txs: usize,
+}
+
+impl CompactReport {
+    /// Returns the number of data blocks freed.
+    #[inline]
+    pub fn reclaimed_blocks(&self) -> usize {
+        self.reclaimed_blocks
+    }
+
+    /// Returns the number of bytes given back by underlying storage.
+    ///
+    /// This is the decrease of storage used space, it is always 0 for the
+    /// storages which cannot report their space usage.
+    #[inline]
+    pub fn reclaimed_space(&self) -> u64 {
+        self.reclaimed_space
+    }
+
+    /// Returns the number of retained committed transactions recycled.
+    #[inline]
+    pub fn recycled_txs(&self) -> usize {
+        self.recycled_txs
+    }
+}
+
+/// Health status of the underlying storage of a repository.
+///
+/// This structure is returned from the [`Repo::check_storage`]. The latencies
+/// are measured by a single round trip to the storage, so they are only a
+/// rough indication of the storage responsiveness.
+///
+/// [`Repo::check_storage`]: struct.Repo.html#method.check_storage
+#[derive(Debug, Clone, Copy)]
+pub struct StorageHealth {
+    read_latency: Duration,
+    write_latency: Option<Duration>,
+}
+
+impl StorageHealth {
+    /// Returns the round trip time of reading from underlying storage.
+    #[inline]
+    pub fn read_latency(&self) -> Duration {
+        self.read_latency
+    }
+
+    /// Returns the round trip time of writing to underlying storage.
+    ///
+    /// Returns `None` if the storage is not writable.
+    #[inline]
+    pub fn write_latency(&self) -> Option<Duration> {
+        self.write_latency
+    }
+
+    /// Returns whether underlying storage is writable.
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.write_latency.is_some()
+    }
+}
+
+// maximum length of repo label, description, application tag and password
+// hint
+const MAX_LABEL_LEN: usize = 255;
+
+// check repo label, description and application tag are not too long
+fn check_label(label: &str, description: &str, app_tag: &str) -> Result<()> {
+    if label.len() > MAX_LABEL_LEN
+        || description.len() > MAX_LABEL_LEN
+        || app_tag.len() > MAX_LABEL_LEN
+    {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
 }
 
 // open a regular file with options
 fn open_file_with_options<P: AsRef<Path>>(
-    fs: &mut Fs,
+    fs: &FsRef,
     path: P,
     open_opts: &OpenOptions,
 ) -> Result<File> {
+    let mut fs = fs.write().unwrap();
     if fs.is_read_only()
         && (open_opts.write
             || open_opts.append
@@ -563,6 +1887,11 @@ fn open_file_with_options<P: AsRef<Path>>(
 
     let path = path.as_ref();
 
+    // file content in a locked key domain cannot be accessed
+    if let Ok(domain) = fs.key_domain_of(path) {
+        fs.check_key_domain_unlocked(domain.as_ref())?;
+    }
+
     match fs.resolve(path) {
         Ok(_) => {
             if open_opts.create_new {
@@ -570,13 +1899,16 @@ fn open_file_with_options<P: AsRef<Path>>(
             }
         }
         Err(ref err) if *err == Error::NotFound && open_opts.create => {
-            let mut opts = fs.get_opts();
+            let mut opts = fs.get_inherited_opts(path)?;
             if let Some(version_limit) = open_opts.version_limit {
                 opts.version_limit = version_limit;
             }
             if let Some(dedup_chunk) = open_opts.dedup_chunk {
                 opts.dedup_chunk = dedup_chunk;
             }
+            if let Some(retention) = open_opts.retention {
+                opts.retention = retention;
+            }
             fs.create_fnode(path, FileType::File, opts)?;
         }
         Err(err) => return Err(err),
@@ -589,8 +1921,12 @@ fn open_file_with_options<P: AsRef<Path>>(
         if fnode.is_dir() {
             return Err(Error::IsDir);
         }
+        if !fnode.is_file() {
+            return Err(Error::NotFile);
+        }
         curr_len = fnode.curr_len();
     }
+    drop(fs);
 
     let pos = if open_opts.append {
         SeekFrom::Start(curr_len as u64)
@@ -598,6 +1934,7 @@ fn open_file_with_options<P: AsRef<Path>>(
         SeekFrom::Start(0)
     };
     let mut file = File::new(handle, pos, open_opts.read, open_opts.write);
+    file.set_auto_commit(open_opts.auto_commit);
 
     if open_opts.truncate && curr_len > 0 {
         file.set_len(0)?;
@@ -616,13 +1953,19 @@ fn open_file_with_options<P: AsRef<Path>>(
 ///
 /// ZboxFS supports a variety of underlying storages, which are listed below.
 ///
-/// | Storage            | URI identifier  | Cargo Feature       |
-/// | ------------------ | --------------- | ------------------- |
-/// | Memory             | "mem://"        | N/A                 |
-/// | OS file system     | "file://"       | storage-file        |
-/// | SQLite             | "sqlite://"     | storage-sqlite      |
-/// | Redis              | "redis://"      | storage-redis       |
-/// | Zbox Cloud Storage | "zbox://"       | storage-zbox-native |
+/// | Storage              | URI identifier  | Cargo Feature       |
+/// | -------------------- | --------------- | ------------------- |
+/// | Memory               | "mem://"        | N/A                 |
+/// | OS file system       | "file://"       | storage-file        |
+/// | Single file          | "container://"  | storage-container   |
+/// | Read-only HTTP       | "http(s)://"    | storage-http        |
+/// | SQLite               | "sqlite://"     | storage-sqlite      |
+/// | SQLCipher            | "sqlcipher://"  | storage-sqlcipher   |
+/// | Redis                | "redis://"      | storage-redis       |
+/// | Redis over TLS       | "rediss://"     | storage-redis-tls   |
+/// | Sled                 | "sled://"       | storage-sled        |
+/// | Google Cloud Storage | "gs://"         | storage-gcs         |
+/// | Zbox Cloud Storage   | "zbox://"       | storage-zbox-native |
 ///
 /// \* Visit [zbox.io](https://zbox.io) to learn more about Zbox Cloud Storage.
 ///
@@ -646,12 +1989,31 @@ fn open_file_with_options<P: AsRef<Path>>(
 /// [RepoOpener](struct.RepoOpener.html#method.open).
 ///
 /// `Repo` can only be opened once at a time. After opened, it keeps locked
-/// from other open attempts until it goes out of scope. Once `Repo` is dropped,
-/// any opened files or version readers won't be accessible.
+/// from other open attempts until it goes out of scope. Once `Repo` and all
+/// its clones are dropped, any opened files or version readers won't be
+/// accessible.
 ///
 /// Optionally, `Repo` can be opened in [`read-only`] mode if you only need
 /// read access.
 ///
+/// # Sharing between threads
+///
+/// `Repo` is cheap to clone, all clones are handles to the same opened
+/// repository, so they can be sent to different threads without wrapping
+/// `Repo` in a mutex. The repository is closed when the last handle is
+/// dropped.
+///
+/// Operations on `Repo` are serialized by a lock on the file system. The
+/// methods only reading the file system, such as [`metadata`] and
+/// [`read_dir`], hold it shared and can run in parallel, while the methods
+/// changing it, including opening files, hold it exclusively. Reading and
+/// writing through opened [`File`]s don't take this lock, so different files
+/// can be accessed concurrently. Changes to the same file are still done
+/// in transactions one at a time.
+///
+/// Tracing started by [`start_trace`] only applies to the handle it is
+/// started on and the handles cloned from it afterwards.
+///
 /// # Examples
 ///
 /// Create an OS file system based repository.
@@ -699,8 +2061,14 @@ fn open_file_with_options<P: AsRef<Path>>(
 /// [`init_env`]: fn.init_env.html
 /// [`RepoOpener`]: struct.RepoOpener.html
 /// [`read-only`]: struct.RepoOpener.html#method.read_only
+/// [`metadata`]: struct.Repo.html#method.metadata
+/// [`read_dir`]: struct.Repo.html#method.read_dir
+/// [`File`]: struct.File.html
+/// [`start_trace`]: struct.Repo.html#method.start_trace
+#[derive(Clone)]
 pub struct Repo {
-    fs: Fs,
+    fs: FsRef,
+    tracer: Option<TracerRef>,
 }
 
 impl Repo {
@@ -712,26 +2080,91 @@ impl Repo {
 
     // create repo
     #[inline]
-    fn create(uri: &str, pwd: &str, cfg: &Config) -> Result<Repo> {
-        let fs = Fs::create(uri, pwd, cfg)?;
-        Ok(Repo { fs })
+    fn create(uri: &str, cred: Credential, cfg: &Config) -> Result<Repo> {
+        let fs = Fs::create(uri, cred, cfg)?;
+        Ok(Repo {
+            fs: fs.into_ref(),
+            tracer: None,
+        })
     }
 
     // open repo
     #[inline]
     fn open(
         uri: &str,
-        pwd: &str,
+        cred: Credential,
         read_only: bool,
         force: bool,
+        retry: RetryPolicy,
+        progress: &dyn Fn(OpenPhase),
     ) -> Result<Repo> {
-        let fs = Fs::open(uri, pwd, read_only, force)?;
-        Ok(Repo { fs })
+        let fs = Fs::open(uri, cred, read_only, force, retry, progress)?;
+        Ok(Repo {
+            fs: fs.into_ref(),
+            tracer: None,
+        })
+    }
+
+    // record an operation to trace if tracing is started
+    #[inline]
+    fn trace(&self, op: Op) {
+        if let Some(ref tracer) = self.tracer {
+            let mut tracer = tracer.lock().unwrap();
+            tracer.record(&op);
+        }
+    }
+
+    /// Start recording operations to a trace file.
+    ///
+    /// Once started, the public API calls which modify the repository, such as
+    /// creating directories, opening and writing files, are recorded to the
+    /// trace file at `path` on OS file system. The trace file can be replayed
+    /// against a fresh repository by [`replay_trace`], which is useful to
+    /// reproduce bugs.
+    ///
+    /// File content is **not** recorded, only its length and a seed are saved
+    /// in trace. The seed is a hash of the content keyed by a random key,
+    /// which is generated for each trace and never saved, so it cannot be used
+    /// to confirm a guess of the content. Files opened before tracing started
+    /// are not traced.
+    ///
+    /// If the trace file already exists, it will be truncated.
+    ///
+    /// [`replay_trace`]: struct.Repo.html#method.replay_trace
+    pub fn start_trace<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let tracer = Tracer::new(path.as_ref())?;
+        self.tracer = Some(Arc::new(Mutex::new(tracer)));
+        Ok(())
+    }
+
+    /// Stop recording operations to trace file.
+    ///
+    /// Files opened during tracing will continue being traced until they are
+    /// closed.
+    #[inline]
+    pub fn stop_trace(&mut self) {
+        self.tracer.take();
+    }
+
+    /// Replay a trace file recorded by [`start_trace`] against this
+    /// repository.
+    ///
+    /// The file content is re-generated from the length and hash recorded in
+    /// trace, so it is different from the original content. Errors returned
+    /// from the replayed operations are ignored, as the original calls might
+    /// also failed.
+    ///
+    /// Returns the number of operations replayed.
+    ///
+    /// [`start_trace`]: struct.Repo.html#method.start_trace
+    #[inline]
+    pub fn replay_trace<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        Tracer::replay(path.as_ref(), self)
     }
 
     /// Get repository metadata information.
     pub fn info(&self) -> Result<RepoInfo> {
-        let meta = self.fs.info();
+        let meta = self.fs.read().unwrap().info();
         Ok(RepoInfo {
             volume_id: meta.vol_info.id.clone(),
             ver: meta.vol_info.ver.clone(),
@@ -739,244 +2172,2325 @@ impl Repo {
             cost: meta.vol_info.cost,
             cipher: meta.vol_info.cipher,
             compress: meta.vol_info.compress,
+            sector_size: meta.vol_info.sector_size,
             version_limit: meta.opts.version_limit,
+            retention: meta.opts.retention,
             dedup_chunk: meta.opts.dedup_chunk,
             dedup_file: meta.opts.dedup_file,
             read_only: meta.read_only,
+            sealed: meta.vol_info.sealed,
+            convergent: meta.vol_info.convergent,
+            reencrypt_checkpoint: meta.vol_info.rotated,
             ctime: meta.vol_info.ctime,
+            label: meta.vol_info.label.clone(),
+            description: meta.vol_info.description.clone(),
+            app_tag: meta.vol_info.app_tag.clone(),
+            used_blocks: meta.used_blocks,
+            logical_size: meta.logical_len,
+            files: meta.counts.files.max(0) as u64,
+            dirs: meta.counts.dirs.max(0) as u64,
+            versions: meta.counts.versions.max(0) as u64,
+            retained_txs: meta.retained_wals,
         })
     }
 
-    /// Reset password for the repository.
+    /// Reloads the latest committed state of a read-only repository.
     ///
-    /// Note: if this method failed due to IO error, super block might be
-    /// damaged. If it is the case, use
-    /// [repair_super_block](struct.Repo.html#method.repair_super_block)
-    /// to restore super block before re-opening the repo.
-    #[inline]
-    pub fn reset_password(
-        &mut self,
-        old_pwd: &str,
-        new_pwd: &str,
-        ops_limit: OpsLimit,
-        mem_limit: MemLimit,
-    ) -> Result<()> {
-        let cost = Cost::new(ops_limit, mem_limit);
-        self.fs.reset_password(old_pwd, new_pwd, cost)
-    }
-
-    /// Repair possibly damaged super block.
+    /// When a repository is opened in read-only mode alongside a writer, it
+    /// keeps seeing the state at the time it was opened. Call this method to
+    /// pick up changes committed by the writer since then.
     ///
-    /// This method will try to repair super block using backup. One scenario
-    /// is when [reset_password](struct.Repo.html#method.reset_password) failed
-    /// due to IO error, super block might be damaged. Using this method can
-    /// restore the damaged super block from backup. If super block is all
-    /// good, this method is no-op.
+    /// All files and directories opened before refreshing are closed, and
+    /// must be opened again. This method does nothing if the repository is
+    /// not read-only.
     ///
-    /// This method is not useful for memory-based storage and must be called
-    /// when repo is closed.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .read_only(true)
+    ///     .open("file:///path/to/repo", "pwd")?;
+    ///
+    /// // ... writer in another process commits changes ...
+    ///
+    /// repo.refresh()?;
+    /// # Ok(())
+    /// # }
+    /// ```
     #[inline]
-    pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
-        Fs::repair_super_block(uri, pwd)
+    pub fn refresh(&mut self) -> Result<()> {
+        self.fs.write().unwrap().refresh()
     }
 
-    /// Returns whether the path points at an existing entity in repository.
+    /// Get storage space statistics of the repository.
     ///
-    /// `path` must be an absolute path.
-    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        Ok(self
-            .fs
-            .resolve(path.as_ref())
-            .map(|_| true)
-            .unwrap_or(false))
-    }
-
-    /// Returns whether the path exists in repository and is pointing at
-    /// a regular file.
+    /// This queries underlying storage for its capacity and usage, which can
+    /// be used to implement such as `statfs` in a FUSE file system. File
+    /// storage reports free space of its disk, Redis storage reports its
+    /// `maxmemory` setting. Storage which cannot report its usage, or whose
+    /// usage is too expensive to measure like file storage, reports the size
+    /// of blocks in use.
     ///
-    /// `path` must be an absolute path.
-    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        match self.fs.resolve(path.as_ref()) {
-            Ok(fnode_ref) => {
-                let fnode = fnode_ref.read().unwrap();
-                Ok(fnode.is_file())
-            }
-            Err(_) => Ok(false),
-        }
+    /// If a [quota] is set on the root directory, free space is capped by
+    /// the quota as well.
+    ///
+    /// [quota]: struct.Repo.html#method.set_quota
+    pub fn stat_fs(&self) -> Result<StatFs> {
+        let space = self.fs.read().unwrap().space()?;
+        Ok(StatFs {
+            total: space.total,
+            used: space.used,
+        })
     }
 
-    /// Returns whether the path exists in repository and is pointing at
-    /// a directory.
+    /// Reclaim space occupied by deleted files and expired versions.
     ///
-    /// `path` must be an absolute path.
-    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        match self.fs.resolve(path.as_ref()) {
-            Ok(fnode_ref) => {
-                let fnode = fnode_ref.read().unwrap();
-                Ok(fnode.is_dir())
-            }
-            Err(_) => Ok(false),
-        }
+    /// Data deleted by the most recent transactions is retained for
+    /// recovery, this recycles all of them so their data is removed from the
+    /// underlying storage. The storage is then compacted, for example, file
+    /// storage removes empty sectors and rewrites partially used ones, and
+    /// SQLite storage vacuums its database file. Either way, the reclaimed
+    /// space is returned to the host file system.
+    ///
+    /// `progress` is called with the number of processed and total work units
+    /// as compaction proceeds, the unit depends on storage type.
+    ///
+    /// File storage doesn't compact its sectors while the repository is also
+    /// opened in read-only mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ReadOnly`] if the repository is opened in read-only mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let report = repo.compact(|done, total| {
+    ///     println!("compacting {}/{}", done, total);
+    /// })?;
+    /// println!("reclaimed {} bytes", report.reclaimed_space());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    pub fn compact<F>(&mut self, mut progress: F) -> Result<CompactReport>
+    where
+        F: FnMut(usize, usize),
+    {
+        let used_blocks = self.fs.read().unwrap().info().used_blocks;
+        let used_space = self.fs.read().unwrap().space()?.used;
+
+        let recycled_txs = self.fs.write().unwrap().compact(&mut progress)?;
+
+        let meta = self.fs.read().unwrap().info();
+        let space = self.fs.read().unwrap().space()?;
+        Ok(CompactReport {
+            reclaimed_blocks: used_blocks.saturating_sub(meta.used_blocks),
+            reclaimed_space: used_space.saturating_sub(space.used),
+            recycled_txs,
+        })
     }
 
-    /// Create a file in read-write mode.
+    /// Check health of the underlying storage.
     ///
-    /// This method will create a file if it does not exist, and will
-    /// truncate it if it does. See the
-    /// [`OpenOptions::open`](struct.OpenOptions.html#method.open) method for
-    /// more details.
+    /// This verifies the storage is reachable by reading from it, and checks
+    /// its writability by writing and then deleting a small probe. It can be
+    /// used to detect a degraded remote storage before starting a large
+    /// transaction.
     ///
-    /// `path` must be an absolute path.
+    /// # Errors
     ///
-    /// This method is **not** atomic.
-    #[inline]
-    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
-        OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .open(self, path)
+    /// Any error occurred while reading or writing storage is returned, except
+    /// that a read-only storage is reported as not writable.
+    pub fn check_storage(&self) -> Result<StorageHealth> {
+        let health = self.fs.read().unwrap().health_check()?;
+        Ok(StorageHealth {
+            read_latency: health.read_latency,
+            write_latency: health.write_latency,
+        })
     }
 
-    /// Attempts to open a file in read-only mode.
+    /// Verify integrity of the repository metadata.
     ///
-    /// `path` must be an absolute path.
+    /// This walks the whole directory tree and trees of all [snapshots],
+    /// checks version chain of each file, and checks that all contents,
+    /// segments and segment data referred by file versions exist in the
+    /// repository. Chunk and content reference counts are also checked
+    /// against the actual references.
     ///
-    /// See the [`OpenOptions::open`] method for more details.
+    /// Problems found are collected in the returned [`VerifyReport`] rather
+    /// than surfacing as [`Corrupted`] error when the data is read. File data
+    /// itself is not read, so it doesn't detect tampered data blocks, which
+    /// are still detected when being read.
     ///
     /// # Errors
-    /// This method will return an error if path does not already exist.
-    /// Other errors may also be returned according to [`OpenOptions::open`].
+    ///
+    /// An error is returned only if the verification cannot continue, for
+    /// example, the underlying storage is not accessible.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # #![allow(unused_mut, unused_variables)]
     /// # use zbox::{init_env, Result, RepoOpener};
     /// # fn foo() -> Result<()> {
     /// # init_env();
     /// # let mut repo = RepoOpener::new()
     /// #     .create(true)
     /// #     .open("mem://foo", "pwd")?;
-    /// let mut f = repo.open_file("foo.txt")?;
+    /// let report = repo.verify()?;
+    /// for problem in report.problems() {
+    ///     println!("{}", problem);
+    /// }
     /// # Ok(())
     /// # }
+    /// # foo().unwrap();
     /// ```
     ///
-    /// [`OpenOptions::open`]: struct.OpenOptions.html#method.open
-    #[inline]
-    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
-        OpenOptions::new().open(self, path)
+    /// [snapshots]: struct.Repo.html#method.snapshot
+    /// [`VerifyReport`]: struct.VerifyReport.html
+    /// [`Corrupted`]: enum.Error.html#variant.Corrupted
+    pub fn verify(&self) -> Result<VerifyReport> {
+        self.fs.read().unwrap().verify()
     }
 
-    /// Creates a new, empty directory at the specified path.
+    /// Takes a named snapshot of the whole repository.
     ///
-    /// `path` must be an absolute path.
+    /// A snapshot captures a consistent point-in-time state of the whole
+    /// directory tree, including all versions of every file, metadata such
+    /// as times and permissions, directory options, quotas and key domains.
+    /// It is taken in one transaction and file content is shared with the
+    /// live tree rather than copied.
     ///
-    /// This method is atomic.
+    /// Although no file content is copied, every file and directory entry
+    /// is, so taking a snapshot costs O(n) time and space, where n is the
+    /// number of entries in the repository. Snapshot contents are kept
+    /// intact by later writes, version pruning and [`compact`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AlreadyExists`] if a snapshot with the same name exists, or
+    /// [`InvalidArgument`] if `name` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.snapshot("before-upgrade")?;
+    ///
+    /// for snap in repo.list_snapshots() {
+    ///     println!("{} {:?}", snap.name(), snap.created_at());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`AlreadyExists`]: enum.Error.html#variant.AlreadyExists
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`compact`]: struct.Repo.html#method.compact
     #[inline]
-    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs
-            .create_fnode(path.as_ref(), FileType::Dir, Options::default())
-            .map(|_| ())
+    pub fn snapshot(&mut self, name: &str) -> Result<()> {
+        self.fs.write().unwrap().snapshot(name)
     }
 
-    /// Recursively create a directory and all of its parent components if they
-    /// are missing.
+    /// Returns all snapshots of the repository, in the order they were
+    /// taken.
     ///
-    /// `path` must be an absolute path.
+    /// See [`snapshot`] for more details.
     ///
-    /// This method is **not** atomic in whole, but creating each entry is
-    /// atomic.
+    /// [`snapshot`]: struct.Repo.html#method.snapshot
     #[inline]
-    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.create_dir_all(path.as_ref())
+    pub fn list_snapshots(&self) -> Vec<Snapshot> {
+        self.fs.read().unwrap().list_snapshots()
     }
 
-    /// Returns a vector of all the entries within a directory.
+    /// Rolls back the whole repository to a named snapshot.
     ///
-    /// `path` must be an absolute path.
+    /// The directory tree is restored to the state captured by the snapshot
+    /// in one new transaction. Entries which don't exist in the snapshot are
+    /// removed and existing files whose content has changed get the snapshot
+    /// content as a new version, so their version history is kept. Files
+    /// removed since the snapshot are restored with their version history.
+    /// Metadata of all entries is restored as well. The snapshot itself and
+    /// all other snapshots, including those taken after it, are kept too.
+    ///
+    /// This method is atomic, nothing is changed if any errors happened.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NotFound`] if the snapshot doesn't exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.snapshot("before-upgrade")?;
+    /// repo.create_dir("/new-dir")?;
+    ///
+    /// repo.rollback_to("before-upgrade")?;
+    /// assert!(!repo.path_exists("/new-dir")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`NotFound`]: enum.Error.html#variant.NotFound
     #[inline]
-    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
-        self.fs.read_dir(path.as_ref())
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        self.fs.write().unwrap().rollback_to(name)
     }
 
-    /// Get the metadata about a file or directory at specified path.
+    /// Removes a named snapshot.
     ///
-    /// `path` must be an absolute path.
+    /// Content which is only referred by the snapshot is released.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NotFound`] if the snapshot doesn't exist.
+    ///
+    /// [`NotFound`]: enum.Error.html#variant.NotFound
     #[inline]
-    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
-        self.fs.metadata(path.as_ref())
+    pub fn remove_snapshot(&mut self, name: &str) -> Result<()> {
+        self.fs.write().unwrap().remove_snapshot(name)
     }
 
-    /// Return a vector of history versions of a regular file at specified path.
+    /// Returns all entries in trash, in the order they were removed.
     ///
-    /// `path` must be an absolute path to a regular file.
+    /// See [`RepoOpener::trash`] for more details.
+    ///
+    /// [`RepoOpener::trash`]: struct.RepoOpener.html#method.trash
     #[inline]
-    pub fn history<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Version>> {
-        self.fs.history(path.as_ref())
+    pub fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        self.fs.read().unwrap().list_trash()
     }
 
-    /// Copies the content of one file to another.
-    ///
-    /// This method will **overwrite** the content of `to`.
+    /// Restores an entry removed from `path` from trash.
     ///
-    /// `from` and `to` must be absolute paths to regular files.
+    /// If the path was removed more than once, the latest removed entry is
+    /// restored. Missing parent directories of `path` are created.
     ///
-    /// If `from` and `to` both point to the same file, this method is no-op.
+    /// `path` must be an absolute path.
     ///
-    /// This method is **not** atomic.
-    #[inline]
-    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
-        &mut self,
-        from: P,
-        to: Q,
-    ) -> Result<()> {
-        self.fs.copy(from.as_ref(), to.as_ref())
-    }
-
-    /// Copies a directory to another recursively.
+    /// # Errors
     ///
-    /// This method will **overwrite** the content of files in `to` with
-    /// the files in `from` which have same relative location.
+    /// Returns [`NotFound`] if no entry removed from `path` is in trash, or
+    /// [`AlreadyExists`] if `path` already exists.
     ///
-    /// `from` and `to` must be absolute paths to directories.
+    /// # Examples
     ///
-    /// If `to` is not empty, the entire directory tree of `from` will be
-    /// merged to `to`.
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .trash(true)
+    ///     .open("mem://foo", "pwd")?;
     ///
-    /// This method will stop if any errors happened.
+    /// repo.create_dir("/dir")?;
+    /// repo.remove_dir("/dir")?;
+    /// assert_eq!(repo.list_trash()?.len(), 1);
     ///
-    /// If `from` and `to` both point to the same directory, this method is
-    /// no-op.
+    /// repo.restore_from_trash("/dir")?;
+    /// assert!(repo.is_dir("/dir")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
     ///
-    /// This method is **not** atomic.
+    /// [`NotFound`]: enum.Error.html#variant.NotFound
+    /// [`AlreadyExists`]: enum.Error.html#variant.AlreadyExists
     #[inline]
-    pub fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+    pub fn restore_from_trash<P: AsRef<Path>>(
         &mut self,
-        from: P,
-        to: Q,
+        path: P,
     ) -> Result<()> {
-        self.fs.copy_dir_all(from.as_ref(), to.as_ref())
+        self.fs.write().unwrap().restore_from_trash(path.as_ref())
     }
 
-    /// Removes a regular file from the repository.
-    ///
-    /// `path` must be an absolute path.
+    /// Permanently removes all entries in trash.
     ///
     /// This method is atomic.
     #[inline]
-    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_file(path.as_ref())
+    pub fn empty_trash(&mut self) -> Result<()> {
+        self.fs.write().unwrap().empty_trash()
     }
 
-    /// Remove an existing empty directory.
+    /// Returns a read-only view of the repository pinned to its current
+    /// state.
     ///
-    /// `path` must be an absolute path.
+    /// The returned view is a read-only [`Repo`], which keeps seeing the
+    /// directory tree as it is now, regardless of changes made afterwards
+    /// through this repository. It is useful for backup scans which must
+    /// see a consistent tree while the repository keeps being written.
     ///
-    /// This method is atomic.
+    /// The view is backed by an unnamed snapshot, so it is cheap to create
+    /// and doesn't show up in [`list_snapshots`]. Content held by the view
+    /// is released when it is dropped. If a view is not released properly,
+    /// for example, due to a crash, it is removed when the repository is
+    /// opened next time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    /// let view = repo.freeze_view()?;
+    ///
+    /// repo.remove_dir("/dir")?;
+    /// assert!(view.is_dir("/dir")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo`]: struct.Repo.html
+    /// [`list_snapshots`]: struct.Repo.html#method.list_snapshots
+    pub fn freeze_view(&mut self) -> Result<Repo> {
+        let fs = self.fs.write().unwrap().freeze_view()?;
+        Ok(Repo {
+            fs: fs.into_ref(),
+            tracer: None,
+        })
+    }
+
+    /// Reset password for the repository.
+    ///
+    /// The password hash cost is replaced by `ops_limit` and `mem_limit`,
+    /// custom parameters set by [`RepoOpener::kdf_params`] or
+    /// [`RepoOpener::scrypt_params`] are discarded.
+    ///
+    /// Only super block is rewritten, no data is re-encrypted. Use
+    /// [`rotate_data_key`] to re-encrypt data if the keys might have been
+    /// exposed.
+    ///
+    /// Note: if this method failed due to IO error, super block might be
+    /// damaged. If it is the case, use
+    /// [repair_super_block](struct.Repo.html#method.repair_super_block)
+    /// to restore super block before re-opening the repo.
+    ///
+    /// If a password validator is set by
+    /// [`RepoOpener::password_validator`], the new password is checked by it
+    /// first and [`Error::WeakPassword`] is returned if it is rejected.
+    ///
+    /// [`RepoOpener::kdf_params`]: struct.RepoOpener.html#method.kdf_params
+    /// [`RepoOpener::scrypt_params`]: struct.RepoOpener.html#method.scrypt_params
+    /// [`RepoOpener::password_validator`]: struct.RepoOpener.html#method.password_validator
+    /// [`Error::WeakPassword`]: enum.Error.html
+    /// [`rotate_data_key`]: struct.Repo.html#method.rotate_data_key
+    #[inline]
+    pub fn reset_password(
+        &mut self,
+        old_pwd: &str,
+        new_pwd: &str,
+        ops_limit: OpsLimit,
+        mem_limit: MemLimit,
+    ) -> Result<()> {
+        let cost = Cost::new(ops_limit, mem_limit);
+        self.fs
+            .write()
+            .unwrap()
+            .reset_password(old_pwd, new_pwd, cost)
+    }
+
+    /// Reset password for the repository using a recovery key.
+    ///
+    /// This is like [`reset_password`], but the old password is not needed.
+    /// Instead, the master key is unwrapped by the recovery key exported by
+    /// [`export_recovery_key`] and its recovery password, and then wrapped
+    /// by the new password. The recovery key stays valid afterwards.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadOnly`]: the repository is opened in read-only mode
+    /// - [`InvalidArgument`]: the recovery key is malformed
+    /// - [`Decrypt`]: the recovery password is wrong, or the recovery key
+    ///   doesn't belong to the repository or is outdated
+    /// - [`KeyProvider`]: the repository is protected by a key provider
+    ///   rather than password
+    /// - [`WeakPassword`]: the new password is rejected by the validator
+    ///   set by [`RepoOpener::password_validator`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener, OpsLimit, MemLimit};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let key = repo.export_recovery_key("pwd", "escrow pwd")?;
+    ///
+    /// repo.reset_password_with_recovery_key(
+    ///     &key,
+    ///     "escrow pwd",
+    ///     "new pwd",
+    ///     OpsLimit::Interactive,
+    ///     MemLimit::Interactive,
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`reset_password`]: struct.Repo.html#method.reset_password
+    /// [`export_recovery_key`]: struct.Repo.html#method.export_recovery_key
+    /// [`RepoOpener::password_validator`]: struct.RepoOpener.html#method.password_validator
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`Decrypt`]: enum.Error.html#variant.Decrypt
+    /// [`KeyProvider`]: enum.Error.html#variant.KeyProvider
+    /// [`WeakPassword`]: enum.Error.html#variant.WeakPassword
+    pub fn reset_password_with_recovery_key(
+        &mut self,
+        key: &RecoveryKey,
+        recovery_pwd: &str,
+        new_pwd: &str,
+        ops_limit: OpsLimit,
+        mem_limit: MemLimit,
+    ) -> Result<()> {
+        let cost = Cost::new(ops_limit, mem_limit);
+        self.fs.write().unwrap().reset_password_with_recovery_key(
+            &key.0,
+            recovery_pwd,
+            new_pwd,
+            cost,
+        )
+    }
+
+    /// Replace the master key of the repository with a new random key.
+    ///
+    /// The master key encrypts super block and is wrapped by the password
+    /// hash. Rotating it only rewrites super block, data is not
+    /// re-encrypted because it is encrypted by the data keys stored in
+    /// super block. The password stays the same, but the password hash salt
+    /// is renewed, so keys derived by [`Repo::derive_key`] before cannot
+    /// open the repository any more.
+    ///
+    /// A new repository uses the password hash as master key directly. This
+    /// method converts it to use a random master key wrapped by the password
+    /// hash, which is kept in an extended super block format. Versions of
+    /// zbox before the conversion was introduced cannot open the repository
+    /// afterwards, they return [`InvalidCipher`] error.
+    ///
+    /// Note: like [reset_password](struct.Repo.html#method.reset_password),
+    /// if this method failed due to IO error, super block might be damaged.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadOnly`]: the repository is opened in read-only mode
+    /// - [`KeyProvider`]: the repository is protected by key provider
+    ///
+    /// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+    /// [`InvalidCipher`]: enum.Error.html#variant.InvalidCipher
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    /// [`KeyProvider`]: enum.Error.html#variant.KeyProvider
+    #[inline]
+    pub fn rotate_master_key(&mut self, pwd: &str) -> Result<()> {
+        self.fs.write().unwrap().rotate_master_key(pwd)
+    }
+
+    /// Update label, description and application tag of the repository.
+    ///
+    /// These metadata are stored in super block, so the repository password
+    /// is required. Each of them cannot be longer than 255 bytes.
+    ///
+    /// Note: like [reset_password](struct.Repo.html#method.reset_password),
+    /// if this method failed due to IO error, super block might be damaged.
+    pub fn set_label(
+        &mut self,
+        pwd: &str,
+        label: &str,
+        description: &str,
+        app_tag: &str,
+    ) -> Result<()> {
+        check_label(label, description, app_tag)?;
+        self.fs
+            .write()
+            .unwrap()
+            .set_label(pwd, label, description, app_tag)
+    }
+
+    /// Update data compression setting of the repository.
+    ///
+    /// The new setting only applies to data written afterwards. Compression
+    /// flag is stored along with each piece of content, so existing content
+    /// stays readable regardless of this setting. Compression is useful for
+    /// remote storages where network bandwidth dominates.
+    ///
+    /// This setting is stored in super block, so the repository password
+    /// is required.
+    pub fn set_compress(&mut self, pwd: &str, compress: bool) -> Result<()> {
+        self.fs.write().unwrap().set_compress(pwd, compress)
+    }
+
+    /// Re-encrypt the whole repository using another cipher.
+    ///
+    /// All data and metadata in the repository, including file versions and
+    /// snapshots, are decrypted and then encrypted again using `cipher`. The
+    /// keys are not changed, so the repository password stays the same.
+    /// This setting is stored in super block, so the repository password is
+    /// required.
+    ///
+    /// The migration can be interrupted, for example by an IO error or a
+    /// crash. Until it is completed, data encrypted by either cipher are
+    /// readable and new data is encrypted using `cipher`. Call this method
+    /// again with the same `cipher` to resume the migration.
+    ///
+    /// Note that the internal metadata of some storages keep using the
+    /// cipher with which the repository was created.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadOnly`]: the repository is opened in read-only mode
+    /// - [`InvalidArgument`]: a migration to another cipher or a data key
+    ///   rotation is in progress
+    /// - [`NoAesHardware`]: `cipher` is `Cipher::Aes` but CPU doesn't
+    ///   support it
+    /// - [`InTrans`]: there are transactions in progress
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Cipher, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .cipher(Cipher::Xchacha)
+    ///     .open("mem://foo", "pwd")?;
+    ///
+    /// repo.migrate_cipher("pwd", Cipher::Aes)?;
+    /// assert_eq!(repo.info()?.cipher(), Cipher::Aes);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`NoAesHardware`]: enum.Error.html#variant.NoAesHardware
+    /// [`InTrans`]: enum.Error.html#variant.InTrans
+    #[inline]
+    pub fn migrate_cipher(&mut self, pwd: &str, cipher: Cipher) -> Result<()> {
+        self.fs.write().unwrap().migrate_cipher(pwd, cipher)
+    }
+
+    /// Re-encrypt the whole repository using a new random data key.
+    ///
+    /// Unlike [`rotate_master_key`], this method walks all blocks in the
+    /// repository, including file versions and snapshots, decrypts them and
+    /// then encrypts them again using a freshly generated data key. It
+    /// should be used when the keys are suspected to be compromised. The
+    /// master key is replaced as well, like [`rotate_master_key`] does, so
+    /// derived keys and recovery keys exported before cannot be used any
+    /// more. The password and cipher stay the same. Return the number of
+    /// re-encrypted entities.
+    ///
+    /// `progress` is called with the number of processed and total entities
+    /// as re-encryption proceeds. Progress is also checkpointed in the
+    /// volume periodically, see [`RepoInfo::reencrypt_checkpoint`].
+    ///
+    /// The re-encryption can be interrupted, for example by an IO error or
+    /// a crash. Until it is completed, data encrypted by either key are
+    /// readable and new data is encrypted using the new key. Call this
+    /// method again to resume the re-encryption, entities already
+    /// re-encrypted are skipped.
+    ///
+    /// Note that the data key with which the repository was created is kept
+    /// in super block forever, because the internal metadata of some
+    /// storages, such as block index, keep using it. Those metadata stay
+    /// readable to anyone who has that key and the storage, but file
+    /// content and repository metadata are not.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadOnly`]: the repository is opened in read-only mode
+    /// - [`InvalidArgument`]: a cipher migration is in progress
+    /// - [`InTrans`]: there are transactions in progress
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"foo")?;
+    ///
+    /// let cnt = repo.reencrypt("pwd", |done, total| {
+    ///     println!("re-encrypting {}/{}", done, total);
+    /// })?;
+    /// assert!(cnt > 0);
+    /// assert!(repo.info()?.reencrypt_checkpoint().is_none());
+    ///
+    /// let mut content = String::new();
+    /// repo.open_file("/foo.txt")?.read_to_string(&mut content)?;
+    /// assert_eq!(content, "foo");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`rotate_master_key`]: struct.Repo.html#method.rotate_master_key
+    /// [`RepoInfo::reencrypt_checkpoint`]: struct.RepoInfo.html#method.reencrypt_checkpoint
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`InTrans`]: enum.Error.html#variant.InTrans
+    pub fn reencrypt<F>(&mut self, pwd: &str, mut progress: F) -> Result<usize>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.fs.write().unwrap().reencrypt(pwd, &mut progress)
+    }
+
+    /// Re-encrypt the whole repository using a new random data key.
+    ///
+    /// This is the same as [`reencrypt`] but without progress reporting.
+    ///
+    /// [`reencrypt`]: struct.Repo.html#method.reencrypt
+    #[inline]
+    pub fn rotate_data_key(&mut self, pwd: &str) -> Result<()> {
+        self.reencrypt(pwd, |_, _| {}).map(|_| ())
+    }
+
+    /// Seals the repository permanently.
+    ///
+    /// A sealed repository can only be opened in [`read-only`] mode, any
+    /// attempt to open it for writing fails with [`ReadOnly`] error, even if
+    /// [`force`] is set. The seal is stored in super block, so it is
+    /// enforced by the repository format itself and cannot be removed. This
+    /// is useful for archives which must stay immutable.
+    ///
+    /// This repository becomes read-only once it is sealed, including files
+    /// opened for writing before, any change made through them, even a
+    /// pending write, fails with [`ReadOnly`] error. `pwd` must be the
+    /// current password of the repository.
+    ///
+    /// The seal is only honoured by this release and later ones. Older
+    /// releases ignore it and can still open a sealed repository for
+    /// writing, so don't rely on the seal if the repository could be opened
+    /// by them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ReadOnly`] error if this repository is opened in read-only
+    /// mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::Error;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    ///
+    /// repo.create_file("/foo.txt")?.write_once(b"Hello")?;
+    /// repo.seal("pwd")?;
+    /// assert!(repo.info()?.is_sealed());
+    /// assert_eq!(repo.create_dir("/bar").unwrap_err(), Error::ReadOnly);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`read-only`]: struct.RepoOpener.html#method.read_only
+    /// [`force`]: struct.RepoOpener.html#method.force
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    #[inline]
+    pub fn seal(&mut self, pwd: &str) -> Result<()> {
+        self.fs.write().unwrap().seal(pwd)
+    }
+
+    /// Update password hint of the repository.
+    ///
+    /// The password hint is stored in super block **without encryption**,
+    /// see [`RepoOpener::password_hint`] for details. An empty `hint` removes
+    /// the password hint.
+    ///
+    /// [`RepoOpener::password_hint`]: struct.RepoOpener.html#method.password_hint
+    pub fn set_password_hint(&mut self, pwd: &str, hint: &str) -> Result<()> {
+        if hint.len() > MAX_LABEL_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        self.fs.write().unwrap().set_password_hint(pwd, hint)
+    }
+
+    /// Enables drop box of the repository and returns its public key.
+    ///
+    /// A drop box key pair is generated and stored in the encrypted super
+    /// block. Clients who have the public key can create drop packages by
+    /// [`DropWriter`], which can only be imported by [`import_drop`] using
+    /// the secret key. If the drop box is already enabled, its public key is
+    /// returned.
+    ///
+    /// The public key cannot be retrieved without password, because anyone
+    /// who can write the storage could replace an unauthenticated copy with
+    /// their own key. It should be distributed to clients through a channel
+    /// they trust, for example, by publishing [`DropPublicKey::as_bytes`]
+    /// along with other signed configuration.
+    ///
+    /// [`DropPublicKey::as_bytes`]: struct.DropPublicKey.html#method.as_bytes
+    /// [`DropWriter`]: struct.DropWriter.html
+    /// [`import_drop`]: struct.Repo.html#method.import_drop
+    #[inline]
+    pub fn enable_drop_box(&mut self, pwd: &str) -> Result<DropPublicKey> {
+        let pk = self.fs.write().unwrap().enable_drop_box(pwd)?;
+        Ok(DropPublicKey::new(pk))
+    }
+
+    /// Returns the drop box public key of the repository.
+    ///
+    /// Returns `None` if drop box is not enabled. See [`enable_drop_box`]
+    /// for more details.
+    ///
+    /// [`enable_drop_box`]: struct.Repo.html#method.enable_drop_box
+    #[inline]
+    pub fn drop_public_key(&self) -> Option<DropPublicKey> {
+        let fs = self.fs.read().unwrap();
+        fs.drop_box_keys().map(|(pk, _)| DropPublicKey::new(pk))
+    }
+
+    /// Enables convergent encryption using a shared secret `key`.
+    ///
+    /// By default, each block is encrypted with a random nonce, so the same
+    /// content never produces the same cipher text. With convergent
+    /// encryption, file content is encrypted deterministically by `key`
+    /// instead of the repository's own data key, with a nonce derived from
+    /// the content itself. Identical content chunks written by repositories
+    /// sharing the same `key` then produce identical blocks, which lets a
+    /// deduplicating storage provider store them only once.
+    ///
+    /// `key` must be 32 bytes long and it is stored in super block, so it
+    /// only needs to be provided once. It cannot be changed after it is set,
+    /// enabling it again with the same key does nothing.
+    ///
+    /// # Security
+    ///
+    /// Convergent encryption trades confidentiality for deduplication.
+    /// Anyone who holds `key`, or can get content written by a repository
+    /// using it, is able to confirm whether a **known** piece of content is
+    /// stored, simply by encrypting it and looking for the same blocks. This
+    /// is known as the confirmation-of-a-file attack, and it also allows
+    /// guessing content with low entropy, such as a form letter with only a
+    /// few unknown fields. Storage provider can also see which blocks are
+    /// shared between repositories. Only enable it when all parties sharing
+    /// the key are trusted with this.
+    ///
+    /// Only file content written after it is enabled is encrypted
+    /// convergently, metadata is still encrypted by the data key, and
+    /// content in [key domains] keeps using its domain key. Deduplication
+    /// across repositories also requires the same cipher and compression
+    /// setting, and that the content is written in the same way so it is
+    /// split into the same chunks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`InvalidArgument`] if `key` is not 32 bytes long, if a
+    /// different convergent key is already set, or if the repository is in
+    /// integrity-only mode.
+    ///
+    /// [key domains]: struct.Repo.html#method.set_key_domain
+    /// [`InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    #[inline]
+    pub fn enable_convergent_encryption(
+        &mut self,
+        pwd: &str,
+        key: &[u8],
+    ) -> Result<()> {
+        self.fs.write().unwrap().enable_convergent(pwd, key)
+    }
+
+    /// Exports a recovery key of the repository.
+    ///
+    /// The recovery key is the master key of the repository encrypted by
+    /// `recovery_pwd`, which should be different from the repository
+    /// password `pwd`. An organisation can escrow the recovery key and the
+    /// recovery password separately, so the repository can still be opened
+    /// by [`RepoOpener::open_with_recovery_key`] if the user forgets the
+    /// password. The repository password is never stored. A new password
+    /// can then be set by [`reset_password_with_recovery_key`].
+    ///
+    /// The recovery key stays valid after the password is reset by
+    /// [`reset_password`], but it becomes outdated once the master key is
+    /// replaced by [`rotate_master_key`], so a new recovery key should be
+    /// exported after that. Repositories using the password hash as master
+    /// key directly are converted to use a wrapped master key first, see
+    /// [`rotate_master_key`] for the format change.
+    ///
+    /// Anyone who has both the recovery key and the recovery password gets
+    /// full access to the repository.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadOnly`]: the repository is opened in read-only mode
+    /// - [`KeyProvider`]: the repository is protected by a key provider
+    ///   rather than password
+    ///
+    /// [`RepoOpener::open_with_recovery_key`]: struct.RepoOpener.html#method.open_with_recovery_key
+    /// [`reset_password_with_recovery_key`]: struct.Repo.html#method.reset_password_with_recovery_key
+    /// [`reset_password`]: struct.Repo.html#method.reset_password
+    /// [`rotate_master_key`]: struct.Repo.html#method.rotate_master_key
+    /// [`ReadOnly`]: enum.Error.html#variant.ReadOnly
+    /// [`KeyProvider`]: enum.Error.html#variant.KeyProvider
+    #[inline]
+    pub fn export_recovery_key(
+        &mut self,
+        pwd: &str,
+        recovery_pwd: &str,
+    ) -> Result<RecoveryKey> {
+        self.fs
+            .write()
+            .unwrap()
+            .export_recovery_key(pwd, recovery_pwd)
+            .map(RecoveryKey)
+    }
+
+    /// Derives a key from the password of the repository at URI.
+    ///
+    /// The key can be used by [`RepoOpener::open_with_key`] to open the
+    /// repository without hashing the password again, which is intentionally
+    /// slow. It is kept in protected memory and zeroed when dropped.
+    ///
+    /// The key is only valid until the password is reset. It doesn't verify
+    /// the password, a wrong password only fails when the key is used.
+    ///
+    /// [`RepoOpener::open_with_key`]: struct.RepoOpener.html#method.open_with_key
+    #[inline]
+    pub fn derive_key(uri: &str, pwd: &str) -> Result<DerivedKey> {
+        Fs::derive_key(uri, pwd).map(DerivedKey)
+    }
+
+    /// Returns the password hint of the repository at URI.
+    ///
+    /// This method doesn't need password, so it can be used to show the hint
+    /// to user before the repository is opened, for example, after a failed
+    /// open due to wrong password. Returns `None` if no password hint is set.
+    #[inline]
+    pub fn password_hint(uri: &str) -> Result<Option<String>> {
+        Fs::password_hint(uri)
+    }
+
+    /// Returns the key provider URI of the repository at URI.
+    ///
+    /// This method doesn't need password, it can be used to find out which
+    /// key provider should be used to open the repository. Returns `None` if
+    /// the repository is protected by password.
+    ///
+    /// See [`KeyProvider`] for more details.
+    ///
+    /// [`KeyProvider`]: trait.KeyProvider.html
+    #[inline]
+    pub fn key_provider_uri(uri: &str) -> Result<Option<String>> {
+        Fs::key_provider_uri(uri)
+    }
+
+    /// Repair possibly damaged super block.
+    ///
+    /// This method will try to repair super block using backup. One scenario
+    /// is when [reset_password](struct.Repo.html#method.reset_password) failed
+    /// due to IO error, super block might be damaged. Using this method can
+    /// restore the damaged super block from backup. If super block is all
+    /// good, this method is no-op.
+    ///
+    /// This method is not useful for memory-based storage and must be called
+    /// when repo is closed.
+    #[inline]
+    pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
+        Fs::repair_super_block(uri, pwd)
+    }
+
+    /// Returns whether the path points at an existing entity in repository.
+    ///
+    /// `path` must be an absolute path.
+    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(self
+            .fs
+            .read()
+            .unwrap()
+            .resolve(path.as_ref())
+            .map(|_| true)
+            .unwrap_or(false))
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a regular file.
+    ///
+    /// `path` must be an absolute path.
+    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        match self.fs.read().unwrap().resolve(path.as_ref()) {
+            Ok(fnode_ref) => {
+                let fnode = fnode_ref.read().unwrap();
+                Ok(fnode.is_file())
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a directory.
+    ///
+    /// `path` must be an absolute path.
+    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        match self.fs.read().unwrap().resolve(path.as_ref()) {
+            Ok(fnode_ref) => {
+                let fnode = fnode_ref.read().unwrap();
+                Ok(fnode.is_dir())
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Create a file in read-write mode.
+    ///
+    /// This method will create a file if it does not exist, and will
+    /// truncate it if it does. See the
+    /// [`OpenOptions::open`](struct.OpenOptions.html#method.open) method for
+    /// more details.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is **not** atomic.
+    #[inline]
+    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .open(self, path)
+    }
+
+    /// Creates an anonymous temporary file in read-write mode.
+    ///
+    /// The returned file has no path in the directory tree visible to
+    /// applications, it is removed when dropped, unless it is linked into
+    /// the tree by [`File::persist`]. Temporary files left by a crash are
+    /// removed when the repository is opened next time.
+    ///
+    /// Temporary files are kept outside the directory tree, so they never
+    /// show up in directory listings and are not counted in entry counts.
+    /// They use the repository's default file options.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_temp_file()?;
+    /// file.write_once(b"Hello, world!")?;
+    /// file.persist("/foo.txt")?;
+    /// assert!(repo.is_file("/foo.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`File::persist`]: struct.File.html#method.persist
+    pub fn create_temp_file(&mut self) -> Result<File> {
+        let handle = self.fs.write().unwrap().create_temp_file(None)?;
+        let mut file = File::new(handle, SeekFrom::Start(0), true, true);
+        file.set_temp(&self.fs);
+        if let Some(ref tracer) = self.tracer {
+            let mut tr = tracer.lock().unwrap();
+            let fid = tr.next_fid();
+            tr.record(&Op::CreateTemp { fid });
+            file.set_tracer(tracer, fid);
+        }
+        Ok(file)
+    }
+
+    /// Creates a writer which writes a huge file in multiple parts.
+    ///
+    /// Data is written to a temporary file and committed part by part, it is
+    /// added to the file at `path` as a new version when the writer is
+    /// published. See [`MultiPartWriter`] for more details.
+    ///
+    /// `path` must be an absolute path, its parent directory must exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFile`] if `path` exists but is not a regular
+    /// file.
+    ///
+    /// [`MultiPartWriter`]: struct.MultiPartWriter.html
+    /// [`Error::NotFile`]: enum.Error.html
+    pub fn multi_part_writer<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<MultiPartWriter> {
+        let path = path.as_ref();
+        let handle = {
+            let mut fs = self.fs.write().unwrap();
+            match fs.resolve(path) {
+                Ok(fnode) => {
+                    if !fnode.read().unwrap().is_file() {
+                        return Err(Error::NotFile);
+                    }
+                }
+                Err(ref err) if *err == Error::NotFound => {}
+                Err(err) => return Err(err),
+            }
+
+            // temporary file only needs to keep the latest version
+            let mut opts = fs.get_inherited_opts(path)?;
+            opts.version_limit = 1;
+            opts.retention = RetentionPolicy::default();
+            let domain = fs.key_domain_of(path)?;
+            fs.create_temp_file_with(opts, domain)?
+        };
+        let temp = handle.fnode.clone();
+        let mut file = File::new(handle, SeekFrom::Start(0), true, true);
+        file.set_temp(&self.fs);
+        Ok(MultiPartWriter::new(
+            Arc::downgrade(&self.fs),
+            path,
+            temp,
+            file,
+        ))
+    }
+
+    /// Imports a drop package into directory `dir`.
+    ///
+    /// The package is created by [`DropWriter`] using the public key of this
+    /// repository's drop box. The file is created in `dir` using the file
+    /// name in the package and its path is returned. Nothing is created if
+    /// the package cannot be fully decrypted.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if drop box is not enabled,
+    /// [`Error::AlreadyExists`] if the file already exists, [`Error::Decrypt`]
+    /// if the package is not for this repository, and [`Error::Corrupted`] if
+    /// the package is damaged or incomplete.
+    ///
+    /// [`DropWriter`]: struct.DropWriter.html
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    /// [`Error::AlreadyExists`]: enum.Error.html
+    /// [`Error::Decrypt`]: enum.Error.html
+    /// [`Error::Corrupted`]: enum.Error.html
+    pub fn import_drop<R: Read, P: AsRef<Path>>(
+        &mut self,
+        reader: R,
+        dir: P,
+    ) -> Result<PathBuf> {
+        let (pk, sk) = self
+            .fs
+            .read()
+            .unwrap()
+            .drop_box_keys()
+            .ok_or(Error::InvalidArgument)?;
+        let mut rdr = DropReader::new(reader, &pk, &sk)?;
+        let path = dir.as_ref().join(rdr.name());
+        if self.path_exists(&path)? {
+            return Err(Error::AlreadyExists);
+        }
+
+        // write to a temporary file in the key domain of target and persist
+        // it when all data is decrypted, the temporary file is removed if
+        // failed
+        let mut file = {
+            let mut fs = self.fs.write().unwrap();
+            let domain = fs.key_domain_of(&path)?;
+            let handle = fs.create_temp_file(domain)?;
+            let mut file = File::new(handle, SeekFrom::Start(0), true, true);
+            file.set_temp(&self.fs);
+            file
+        };
+        while let Some(data) = rdr.next_chunk()? {
+            file.write_all(&data)?;
+        }
+        file.finish()?;
+        file.persist(&path)?;
+
+        Ok(path)
+    }
+
+    /// Attempts to open a file in read-only mode.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// See the [`OpenOptions::open`] method for more details.
+    ///
+    /// # Errors
+    /// This method will return an error if path does not already exist.
+    /// Other errors may also be returned according to [`OpenOptions::open`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let mut f = repo.open_file("foo.txt")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`OpenOptions::open`]: struct.OpenOptions.html#method.open
+    #[inline]
+    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        OpenOptions::new().open(self, path)
+    }
+
+    /// Returns the entity id of a file or directory.
+    ///
+    /// The id is a stable handle, like an inode number. It is not changed
+    /// when the entry is renamed or moved, or its content is modified, so
+    /// long-running applications can keep referring to the entry by it. See
+    /// [`id_to_path`] and [`open_file_by_id`].
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?;
+    /// let id = repo.path_to_id("/foo.txt")?;
+    ///
+    /// repo.rename("/foo.txt", "/bar.txt")?;
+    /// assert_eq!(repo.path_to_id("/bar.txt")?, id);
+    /// assert_eq!(repo.id_to_path(&id)?.to_str().unwrap(), "/bar.txt");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`id_to_path`]: struct.Repo.html#method.id_to_path
+    /// [`open_file_by_id`]: struct.Repo.html#method.open_file_by_id
+    #[inline]
+    pub fn path_to_id<P: AsRef<Path>>(&self, path: P) -> Result<Eid> {
+        self.fs.read().unwrap().path_to_id(path.as_ref())
+    }
+
+    /// Returns the current path of a file or directory by its entity id.
+    ///
+    /// Recently used entries are found quickly, otherwise the whole directory
+    /// tree is searched.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if no entry has the id, for example it
+    /// has been removed or moved to trash.
+    ///
+    /// [`Error::NotFound`]: enum.Error.html
+    #[inline]
+    pub fn id_to_path(&self, id: &Eid) -> Result<PathBuf> {
+        self.fs.read().unwrap().id_to_path(id)
+    }
+
+    /// Attempts to open a file in read-only mode by its entity id.
+    ///
+    /// This is the same as [`open_file`] on the current path of the file,
+    /// see [`path_to_id`] for how to get the id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if no entry has the id.
+    ///
+    /// [`open_file`]: struct.Repo.html#method.open_file
+    /// [`path_to_id`]: struct.Repo.html#method.path_to_id
+    /// [`Error::NotFound`]: enum.Error.html
+    #[inline]
+    pub fn open_file_by_id(&mut self, id: &Eid) -> Result<File> {
+        let path = self.id_to_path(id)?;
+        self.open_file(path)
+    }
+
+    /// Creates a new, empty directory at the specified path.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    #[inline]
+    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace(Op::CreateDir(path.as_ref().to_path_buf()));
+        self.fs
+            .write()
+            .unwrap()
+            .create_fnode(path.as_ref(), FileType::Dir, Options::default())
+            .map(|_| ())
+    }
+
+    /// Recursively create a directory and all of its parent components if they
+    /// are missing.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is **not** atomic in whole, but creating each entry is
+    /// atomic.
+    #[inline]
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace(Op::CreateDirAll(path.as_ref().to_path_buf()));
+        self.fs.write().unwrap().create_dir_all(path.as_ref())
+    }
+
+    /// Creates a new symbolic link at `link` which points to `target`.
+    ///
+    /// The `target` is stored as it is and will not be resolved, so it can be
+    /// a relative path or even point to a non-existing entity. Symbolic link
+    /// is not followed when resolving path in repository.
+    ///
+    /// `link` must be an absolute path.
+    ///
+    /// This method is atomic.
+    pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        target: P,
+        link: Q,
+    ) -> Result<()> {
+        self.trace(Op::Symlink {
+            target: target.as_ref().to_path_buf(),
+            link: link.as_ref().to_path_buf(),
+        });
+        self.fs.write().unwrap().create_special_fnode(
+            link.as_ref(),
+            FileType::Symlink,
+            0,
+            Some(target.as_ref()),
+        )
+    }
+
+    /// Reads a symbolic link, returning the path it points to.
+    ///
+    /// `path` must be an absolute path to a symbolic link.
+    #[inline]
+    pub fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        self.fs.read().unwrap().read_link(path.as_ref())
+    }
+
+    /// Creates a new special file node at the specified path.
+    ///
+    /// The `ftype` must be one of [`FileType::Fifo`], [`FileType::Socket`],
+    /// [`FileType::CharDevice`] and [`FileType::BlockDevice`]. The `rdev` is
+    /// the device ID for device node, it should be zero for other types.
+    ///
+    /// Special file node has no content, only its type and attributes are
+    /// stored, which can be retrieved by [`metadata`].
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`FileType::Fifo`]: enum.FileType.html#variant.Fifo
+    /// [`FileType::Socket`]: enum.FileType.html#variant.Socket
+    /// [`FileType::CharDevice`]: enum.FileType.html#variant.CharDevice
+    /// [`FileType::BlockDevice`]: enum.FileType.html#variant.BlockDevice
+    /// [`metadata`]: struct.Repo.html#method.metadata
+    pub fn create_node<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        ftype: FileType,
+        rdev: u64,
+    ) -> Result<()> {
+        self.trace(Op::CreateNode {
+            path: path.as_ref().to_path_buf(),
+            ftype,
+            rdev,
+        });
+        if !ftype.is_special() {
+            return Err(Error::InvalidArgument);
+        }
+        self.fs.write().unwrap().create_special_fnode(
+            path.as_ref(),
+            ftype,
+            rdev,
+            None,
+        )
+    }
+
+    /// Sets permissions and ownership of a file or directory.
+    ///
+    /// The permissions are only stored in metadata and not enforced by Zbox,
+    /// they can be read back by [`Metadata::permissions`].
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, Permissions};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?;
+    /// repo.set_permissions("/foo.txt", Permissions::new(0o644, 1000, 1000))?;
+    /// let perms = repo.metadata("/foo.txt")?.permissions().unwrap();
+    /// assert_eq!(perms.mode(), 0o644);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Metadata::permissions`]: struct.Metadata.html#method.permissions
+    pub fn set_permissions<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        perms: Permissions,
+    ) -> Result<()> {
+        self.fs
+            .write()
+            .unwrap()
+            .set_permissions(path.as_ref(), perms)
+    }
+
+    /// Sets creation and modification time of a file or directory.
+    ///
+    /// A time set to `None` is left unchanged. This is useful for backup and
+    /// synchronisation tools to preserve original timestamps.
+    ///
+    /// Note that writing a new version to a file will update its
+    /// modification time again.
+    ///
+    /// `path` must be an absolute path.
+    pub fn set_times<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        created: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.trace(Op::SetTimes {
+            path: path.as_ref().to_path_buf(),
+            created,
+            modified,
+        });
+        self.fs
+            .write()
+            .unwrap()
+            .set_times(path.as_ref(), created, modified)
+    }
+
+    /// Sets modification time of a file or directory.
+    ///
+    /// This is a shortcut of [`set_times`] which only changes modification
+    /// time.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// [`set_times`]: struct.Repo.html#method.set_times
+    pub fn set_mtime<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        modified: SystemTime,
+    ) -> Result<()> {
+        self.set_times(path, None, Some(modified))
+    }
+
+    /// Updates modification time of a file or directory to now, or creates
+    /// an empty file if it doesn't exist.
+    ///
+    /// Unlike [`create_file`], an existing file will not be truncated.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.touch("/foo.txt")?;
+    /// assert!(repo.is_file("/foo.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`create_file`]: struct.Repo.html#method.create_file
+    pub fn touch<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.path_exists(path)? {
+            self.set_mtime(path, SystemTime::now())
+        } else {
+            OpenOptions::new().create(true).open(self, path).map(|_| ())
+        }
+    }
+
+    /// Sets a byte quota on a directory.
+    ///
+    /// Usage of a directory is the total size of all retained versions of
+    /// files in its directory tree, before deduplication and compression.
+    /// Once a quota is set, a change which would make usage exceed `limit`
+    /// fails with [`Error::QuotaExceeded`] when it is committed, and the
+    /// change is aborted. This includes writing to files, copying and
+    /// renaming into the directory.
+    ///
+    /// Setting a quota on a directory which already has one replaces it.
+    /// The limit can be lower than current usage, in which case only changes
+    /// which increase usage will fail.
+    ///
+    /// `path` must be an absolute path to a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Error, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir("/uploads")?;
+    /// repo.set_quota("/uploads", 4)?;
+    ///
+    /// let mut file = repo.create_file("/uploads/foo.txt")?;
+    /// assert_eq!(file.write_once(b"Hello!").unwrap_err(), Error::QuotaExceeded);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::QuotaExceeded`]: enum.Error.html
+    pub fn set_quota<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        limit: u64,
+    ) -> Result<()> {
+        self.fs
+            .write()
+            .unwrap()
+            .set_quota(path.as_ref(), Some(limit))
+    }
+
+    /// Removes quota of a directory.
+    ///
+    /// `path` must be an absolute path to a directory.
+    pub fn remove_quota<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs.write().unwrap().set_quota(path.as_ref(), None)
+    }
+
+    /// Returns quota of a directory along with its current usage.
+    ///
+    /// This returns `None` if no quota is set on the directory.
+    ///
+    /// `path` must be an absolute path to a directory.
+    pub fn quota<P: AsRef<Path>>(&self, path: P) -> Result<Option<Quota>> {
+        self.fs.read().unwrap().quota(path.as_ref())
+    }
+
+    /// Makes a directory the root of a key domain, or unlocks it.
+    ///
+    /// Content of files in a key domain is encrypted by the domain's own
+    /// 32-byte `key` instead of the repo data key, so a single repo can host
+    /// data for multiple parties. The key is never stored in the repo, only
+    /// a key check is saved to verify it. File names and metadata are still
+    /// encrypted by the repo key.
+    ///
+    /// If `path` is not a domain root yet, it must be an empty directory and
+    /// it becomes a new key domain, which can be nested in another one. New
+    /// files and directories created in it join the domain. Content in a
+    /// key domain is not deduplicated with files outside the domain.
+    ///
+    /// If `path` is a domain root, the key domain is unlocked with `key`.
+    /// Key domains are locked whenever the repo is opened, reading or
+    /// writing file content in a locked domain fails with
+    /// [`Error::DomainLocked`], but metadata can still be accessed.
+    ///
+    /// Files can be renamed within a key domain and a domain root directory
+    /// can be moved anywhere, but file content cannot be moved, copied or
+    /// cloned across key domains. To revoke a party's access, create a new
+    /// key domain and copy its data into the new domain via reading and
+    /// writing, then remove the old one. Other domains are not affected.
+    ///
+    /// Cipher migration and verification need all key domains unlocked,
+    /// data key rotation doesn't.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir("/teamA")?;
+    /// repo.set_key_domain("/teamA", &[42u8; 32])?;
+    ///
+    /// // content of this file is encrypted by the domain key
+    /// let mut file = repo.create_file("/teamA/foo.txt")?;
+    /// file.write_once(b"Hello, world!")?;
+    ///
+    /// let mut content = String::new();
+    /// repo.open_file("/teamA/foo.txt")?.read_to_string(&mut content)?;
+    /// assert_eq!(content, "Hello, world!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `key` is not 32 bytes long or
+    /// the repo is in integrity-only mode, [`Error::NotEmpty`] if the
+    /// directory to become a domain root is not empty,
+    /// [`Error::DomainLocked`] if the new key domain is nested in a locked
+    /// one, and [`Error::Decrypt`] if `key` doesn't match the existing key
+    /// domain.
+    ///
+    /// [`Error::DomainLocked`]: enum.Error.html
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    /// [`Error::NotEmpty`]: enum.Error.html
+    /// [`Error::Decrypt`]: enum.Error.html
+    pub fn set_key_domain<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        key: &[u8],
+    ) -> Result<()> {
+        self.fs.write().unwrap().set_key_domain(path.as_ref(), key)
+    }
+
+    /// Returns disk usage of a directory tree or a file.
+    ///
+    /// This walks the whole tree under `path` and reports its logical size,
+    /// the overhead of keeping previous file versions and the deduplicated
+    /// size of data referred by all versions. Data shared with other trees
+    /// is counted in each of them, so call this on each sub-directory to find
+    /// what is consuming space.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// for entry in repo.read_dir("/")? {
+    ///     let du = repo.du(entry.path())?;
+    ///     println!(
+    ///         "{}: {} bytes, {} bytes in history, {} bytes stored",
+    ///         entry.path().display(),
+    ///         du.logical_size(),
+    ///         du.version_overhead(),
+    ///         du.physical_size()
+    ///     );
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn du<P: AsRef<Path>>(&self, path: P) -> Result<DiskUsage> {
+        self.fs.read().unwrap().du(path.as_ref())
+    }
+
+    /// Sets default options of a directory.
+    ///
+    /// Files created beneath this directory, including in its sub-directories,
+    /// will inherit these options unless they are overwritten by a nearer
+    /// directory or by [`OpenOptions`]. Existing files are not affected.
+    ///
+    /// `path` must be an absolute path to a directory.
+    ///
+    /// [`OpenOptions`]: struct.OpenOptions.html
+    pub fn set_dir_options<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        dir_opts: &DirOptions,
+    ) -> Result<()> {
+        // version limit must be greater than 0
+        if let Some(version_limit) = dir_opts.get_version_limit() {
+            if version_limit == 0 {
+                return Err(Error::InvalidArgument);
+            }
+        }
+        if let Some(ref retention) = dir_opts.get_retention() {
+            retention.check()?;
+        }
+        self.fs
+            .write()
+            .unwrap()
+            .set_dir_opts(path.as_ref(), *dir_opts)
+    }
+
+    /// Returns the default options set on a directory.
+    ///
+    /// Only options set directly on this directory are returned, inherited
+    /// options are not included.
+    ///
+    /// `path` must be an absolute path to a directory.
+    #[inline]
+    pub fn dir_options<P: AsRef<Path>>(&self, path: P) -> Result<DirOptions> {
+        self.fs.read().unwrap().dir_opts(path.as_ref())
+    }
+
+    /// Returns a vector of all the entries within a directory.
+    ///
+    /// `path` must be an absolute path.
+    #[inline]
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        self.fs.read().unwrap().read_dir(path.as_ref())
+    }
+
+    /// Returns an iterator which walks a directory tree recursively.
+    ///
+    /// Entries are yielded in depth-first order, and the directory itself is
+    /// not yielded. Recursion depth and symbolic link policy can be set on
+    /// the returned [`WalkDir`].
+    ///
+    /// The directory is resolved when the iterator is first advanced, so an
+    /// invalid `path` results in an error as the first item.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/foo/bar")?;
+    /// repo.create_file("/foo/bar/baz.txt")?;
+    ///
+    /// for entry in repo.walk_dir("/foo").max_depth(2) {
+    ///     println!("{}", entry?.path().display());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`WalkDir`]: struct.WalkDir.html
+    #[inline]
+    pub fn walk_dir<P: AsRef<Path>>(&self, path: P) -> WalkDir<'_> {
+        Fs::walk_dir(&self.fs, path.as_ref())
+    }
+
+    /// Exports a directory tree as a tar archive.
+    ///
+    /// All files, directories, symbolic links and special files under `path`
+    /// are written to `w` in POSIX tar format, with decrypted file content
+    /// of their current versions. Entry paths in the archive are relative to
+    /// `path`, which itself is not included. Permissions, ownership and
+    /// modification times are preserved, default permissions are used if
+    /// they are not set. Sockets cannot be stored in tar archive, so they
+    /// are skipped.
+    ///
+    /// `path` must be an absolute path to a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/foo/bar")?;
+    /// repo.create_file("/foo/bar/baz.txt")?.write_once(b"Hello")?;
+    ///
+    /// let mut archive = Vec::new();
+    /// repo.export_tar("/foo", &mut archive)?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn export_tar<P: AsRef<Path>, W: Write>(
+        &mut self,
+        path: P,
+        w: W,
+    ) -> Result<()> {
+        let root = path.as_ref();
+        if !self.fs.read().unwrap().metadata(root)?.is_dir() {
+            return Err(Error::NotDir);
+        }
+        let ents = Fs::walk_dir(&self.fs, root).collect::<Result<Vec<_>>>()?;
+
+        let mut tar = TarWriter::new(w);
+        for ent in ents {
+            let meta = ent.metadata();
+            let rel_path = ent
+                .path()
+                .strip_prefix(root)
+                .ok()
+                .and_then(Path::to_str)
+                .ok_or(Error::InvalidPath)?;
+            match meta.file_type() {
+                FileType::File => {
+                    let mut file = OpenOptions::new().open(self, ent.path())?;
+                    tar.append_file(rel_path, &meta, &mut file)?;
+                }
+                FileType::Dir => tar.append_dir(rel_path, &meta)?,
+                FileType::Symlink => {
+                    let target =
+                        self.fs.read().unwrap().read_link(ent.path())?;
+                    let target = target.to_str().ok_or(Error::InvalidPath)?;
+                    tar.append_symlink(rel_path, &meta, target)?;
+                }
+                FileType::Socket => {}
+                _ => tar.append_special(rel_path, &meta)?,
+            }
+        }
+        tar.finish()?;
+        Ok(())
+    }
+
+    /// Exports metadata of the whole directory tree as JSON.
+    ///
+    /// Metadata of each entry is written to `w` as a JSON object on its own
+    /// line (NDJSON), parent directories come before their children. This is
+    /// useful for external indexing and auditing tools, only metadata is
+    /// exported and file content is never decrypted.
+    ///
+    /// Each object has below fields:
+    ///
+    /// - `path`: absolute path of the entry
+    /// - `type`: file type, such as `File`, `Dir` or `Symlink`
+    /// - `size`: content size of the current version, in bytes
+    /// - `ctime` and `mtime`: creation and modification time, in seconds
+    ///   since Unix epoch
+    /// - `versions`: number of versions, regular files only
+    /// - `hash`: hex encoded hash of current content, regular files only
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir("/foo")?;
+    /// repo.create_file("/foo/bar.txt")?.write_once(b"Hello")?;
+    ///
+    /// let mut json = Vec::new();
+    /// repo.export_metadata(&mut json)?;
+    /// let json = String::from_utf8(json).unwrap();
+    /// assert_eq!(json.lines().count(), 3);
+    /// assert!(json.contains(r#""path":"/foo/bar.txt","type":"File""#));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn export_metadata<W: Write>(&self, mut w: W) -> Result<()> {
+        self.fs.read().unwrap().export_metadata(&mut w)
+    }
+
+    /// Imports a host directory tree to the repository.
+    ///
+    /// This is a shortcut of [`ImportOptions::import`] with default options,
+    /// all entries under `host_path` are imported to `repo_path`. See
+    /// [`ImportOptions`] for more details and options to filter entries and
+    /// report progress.
+    ///
+    /// `repo_path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let report = repo.import_dir("/home/alice/docs", "/docs")?;
+    /// println!("imported {} files", report.files());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`ImportOptions::import`]: struct.ImportOptions.html#method.import
+    /// [`ImportOptions`]: struct.ImportOptions.html
+    #[inline]
+    pub fn import_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        host_path: P,
+        repo_path: Q,
+    ) -> Result<ImportReport> {
+        ImportOptions::new().import(self, host_path, repo_path)
+    }
+
+    /// Copies the whole repository to a new repository at URI.
+    ///
+    /// A new repository is created at `uri` with password `pwd`, using the
+    /// same cipher, password hashing cost, compression, deduplication and
+    /// version limit settings, as well as label, description and application
+    /// tag. Then all directories, files and their version history, symbolic
+    /// links and special files are copied to it, together with permissions,
+    /// times, directory options and quotas. The new repository is returned,
+    /// opened in read-write mode.
+    ///
+    /// This can be used to migrate a repository to another storage backend.
+    /// Data is copied through this process only, so decrypted content is
+    /// never written to the host file system.
+    ///
+    /// Snapshots, trash entries and password hint are not copied. This
+    /// method is not atomic, if it failed the partially copied repository is
+    /// left at `uri` and can be removed by [`Repo::destroy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RepoExists`] error if a repository already exists at `uri`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    /// repo.create_file("/dir/foo.txt")?.write_once(b"Hello")?;
+    ///
+    /// let copy = repo.clone_to("mem://bar", "new pwd")?;
+    /// assert!(copy.is_file("/dir/foo.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Repo::destroy`]: struct.Repo.html#method.destroy
+    /// [`RepoExists`]: enum.Error.html#variant.RepoExists
+    pub fn clone_to(&mut self, uri: &str, pwd: &str) -> Result<Repo> {
+        let info = self.info()?;
+        let mut dst = RepoOpener::new()
+            .create_new(true)
+            .ops_limit(info.ops_limit())
+            .mem_limit(info.mem_limit())
+            .cipher(info.cipher())
+            .compress(info.compress())
+            .sector_size(info.sector_size())
+            .version_limit(info.version_limit())
+            .dedup_chunk(info.dedup_chunk())
+            .dedup_file(info.dedup_file())
+            .label(info.label())
+            .description(info.description())
+            .app_tag(info.app_tag())
+            .open(uri, pwd)?;
+
+        let root = Path::new("/");
+        let ents = Fs::walk_dir(&self.fs, root).collect::<Result<Vec<_>>>()?;
+        let mut attrs = vec![(root.to_path_buf(), self.metadata(root)?)];
+        self.clone_dir_attrs(&mut dst, root)?;
+
+        for ent in ents {
+            let path = ent.path();
+            let meta = ent.metadata();
+            match meta.file_type() {
+                FileType::File => {
+                    TransferOptions::new()
+                        .history(true)
+                        .transfer(self, path, &mut dst, path)?;
+                }
+                FileType::Dir => {
+                    dst.create_dir(path)?;
+                    self.clone_dir_attrs(&mut dst, path)?;
+                }
+                FileType::Symlink => {
+                    let target = self.read_link(path)?;
+                    dst.symlink(target, path)?;
+                }
+                ftype => dst.create_node(path, ftype, meta.rdev())?,
+            }
+            attrs.push((path.to_path_buf(), meta));
+        }
+
+        // set attributes from the bottom up, so directory times are not
+        // changed by adding children afterwards
+        for (path, meta) in attrs.iter().rev() {
+            if let Some(perms) = meta.permissions() {
+                dst.set_permissions(path, perms)?;
+            }
+            dst.set_times(
+                path,
+                Some(meta.created_at()),
+                Some(meta.modified_at()),
+            )?;
+        }
+
+        Ok(dst)
+    }
+
+    // copy directory options and quota to another repo
+    fn clone_dir_attrs(&self, dst: &mut Repo, path: &Path) -> Result<()> {
+        let dir_opts = self.dir_options(path)?;
+        if dir_opts != DirOptions::default() {
+            dst.set_dir_options(path, &dir_opts)?;
+        }
+        if let Some(quota) = self.quota(path)? {
+            dst.set_quota(path, quota.limit())?;
+        }
+        Ok(())
+    }
+
+    /// Watches changes on a file or directory.
+    ///
+    /// Returns a channel receiver which receives a [`WatchEvent`] each time
+    /// a change is committed to the watched path. Changes made by [`File`]
+    /// handles, such as writing a new version, are delivered as well.
+    ///
+    /// If `recursive` is `false`, only changes to the path itself and its
+    /// direct children are delivered. Otherwise, changes to all descendants
+    /// are delivered.
+    ///
+    /// Drop the receiver to stop watching. The channel is disconnected when
+    /// the repository is closed.
+    ///
+    /// `path` must be an absolute path to an existing file or directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener, WatchEvent};
+    /// # use std::path::PathBuf;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let rx = repo.watch("/", true)?;
+    /// repo.create_dir("/foo")?;
+    /// assert_eq!(rx.recv().unwrap(), WatchEvent::Create(PathBuf::from("/foo")));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`WatchEvent`]: enum.WatchEvent.html
+    /// [`File`]: struct.File.html
+    pub fn watch<P: AsRef<Path>>(
+        &self,
+        path: P,
+        recursive: bool,
+    ) -> Result<Receiver<WatchEvent>> {
+        self.fs.read().unwrap().watch(path.as_ref(), recursive)
+    }
+
+    /// Get the metadata about a file or directory at specified path.
+    ///
+    /// `path` must be an absolute path.
+    #[inline]
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        self.fs.read().unwrap().metadata(path.as_ref())
+    }
+
+    /// Return a vector of history versions of a regular file at specified path.
+    ///
+    /// `path` must be an absolute path to a regular file.
+    #[inline]
+    pub fn history<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Version>> {
+        self.fs.read().unwrap().history(path.as_ref())
+    }
+
+    /// Copies the content of one file to another.
+    ///
+    /// This method will **overwrite** the content of `to`.
+    ///
+    /// `from` and `to` must be absolute paths to regular files.
+    ///
+    /// File content is not duplicated, the current version of `from` is added
+    /// to `to` as a new version which shares the same content chunks, so the
+    /// copy is cheap in time and space. Later writes to either file only
+    /// store the changed chunks, the other file is not affected.
+    ///
+    /// If `from` and `to` both point to the same file, this method is no-op.
+    ///
+    /// This method is **not** atomic, use [`clone_file`] to atomically create
+    /// a copy.
+    ///
+    /// [`clone_file`]: struct.Repo.html#method.clone_file
+    #[inline]
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.trace(Op::Copy(
+            from.as_ref().to_path_buf(),
+            to.as_ref().to_path_buf(),
+        ));
+        self.fs.write().unwrap().copy(from.as_ref(), to.as_ref())
+    }
+
+    /// Creates a new file as a copy-on-write clone of another file.
+    ///
+    /// Like [`copy`], the current version of `from` is shared by the new file
+    /// at `to` without duplicating content chunks, and later writes to either
+    /// file don't affect the other. Version history of `from` is not cloned.
+    /// The new file also has the same options and permissions as `from`.
+    ///
+    /// `from` must be an absolute path to a regular file, and `to` must be an
+    /// absolute path which doesn't exist.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyExists`] if `to` already exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo")?.write_once(b"Hello, world!")?;
+    /// repo.clone_file("/foo", "/bar")?;
+    ///
+    /// let mut content = String::new();
+    /// repo.open_file("/bar")?.read_to_string(&mut content)?;
+    /// assert_eq!(content, "Hello, world!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`copy`]: struct.Repo.html#method.copy
+    /// [`Error::AlreadyExists`]: enum.Error.html
+    #[inline]
+    pub fn clone_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.trace(Op::CloneFile(
+            from.as_ref().to_path_buf(),
+            to.as_ref().to_path_buf(),
+        ));
+        self.fs
+            .write()
+            .unwrap()
+            .clone_file(from.as_ref(), to.as_ref())
+    }
+
+    /// Copies a range of bytes from one file to another.
+    ///
+    /// `len` bytes starting at `src_off` in the current version of `src` are
+    /// copied to `dst` starting at `dst_off`, overwriting existing content and
+    /// extending `dst` if needed. Copy stops at the end of `src`, the number
+    /// of bytes copied is returned.
+    ///
+    /// The copied range refers to the same content chunks as `src`, so data
+    /// is never encrypted or written again and the copy is cheap in time and
+    /// space. The range is only read once to update the content hash of
+    /// `dst`.
+    ///
+    /// `src` and `dst` must be absolute paths to existing regular files, they
+    /// can be the same file. A new version of `dst` is created.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `dst_off` is beyond the end of
+    /// `dst`, as holes in a file are not supported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo")?.write_once(b"Hello, world!")?;
+    /// repo.create_file("/bar")?.write_once(b"Bye, zbox")?;
+    ///
+    /// let copied = repo.copy_file_range("/foo", 7, "/bar", 5, 100)?;
+    /// assert_eq!(copied, 6);
+    ///
+    /// let mut content = String::new();
+    /// repo.open_file("/bar")?.read_to_string(&mut content)?;
+    /// assert_eq!(content, "Bye, world!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn copy_file_range<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        src: P,
+        src_off: u64,
+        dst: Q,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.trace(Op::CopyFileRange {
+            src: src.as_ref().to_path_buf(),
+            src_off,
+            dst: dst.as_ref().to_path_buf(),
+            dst_off,
+            len,
+        });
+        self.fs
+            .write()
+            .unwrap()
+            .copy_file_range(
+                src.as_ref(),
+                src_off as usize,
+                dst.as_ref(),
+                dst_off as usize,
+                len as usize,
+            )
+            .map(|copied| copied as u64)
+    }
+
+    /// Copies a directory to another recursively.
+    ///
+    /// This method will **overwrite** the content of files in `to` with
+    /// the files in `from` which have same relative location.
+    ///
+    /// `from` and `to` must be absolute paths to directories.
+    ///
+    /// If `to` is not empty, the entire directory tree of `from` will be
+    /// merged to `to`.
+    ///
+    /// File content is not duplicated, copied files share the same content
+    /// chunks with source files, so the copy is cheap in space.
+    ///
+    /// If `from` and `to` both point to the same directory, this method is
+    /// no-op.
+    ///
+    /// This method is atomic, nothing is copied if any errors happened.
+    #[inline]
+    pub fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.trace(Op::CopyDirAll(
+            from.as_ref().to_path_buf(),
+            to.as_ref().to_path_buf(),
+        ));
+        self.fs
+            .write()
+            .unwrap()
+            .copy_dir_all(from.as_ref(), to.as_ref())
+    }
+
+    /// Removes a regular file from the repository.
+    ///
+    /// This method can also remove special files, such as symbolic link, FIFO
+    /// and etc.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// If trash mode is enabled, it is moved to trash rather than being
+    /// removed, see [`RepoOpener::trash`].
+    ///
+    /// This method is atomic.
+    ///
+    /// [`RepoOpener::trash`]: struct.RepoOpener.html#method.trash
+    #[inline]
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace(Op::RemoveFile(path.as_ref().to_path_buf()));
+        self.fs.write().unwrap().remove_file(path.as_ref())
+    }
+
+    /// Removes a regular file and shreds its data.
+    ///
+    /// Beyond removing the file, all storage blocks which held the
+    /// ciphertext of its current and historical versions are overwritten
+    /// with random bytes before they are freed, for applications with strict
+    /// data destruction requirements. It can also remove special files.
+    ///
+    /// `path` must be an absolute path. The file is removed permanently even
+    /// if trash mode is enabled.
+    ///
+    /// Only data exclusively owned by the file is shredded. Data shared with
+    /// other files, such as deduplicated content, clones made by
+    /// [`clone_file`] or snapshots, is still in use and left intact. Whether
+    /// overwritten blocks are unrecoverable also depends on the storage,
+    /// file storage overwrites blocks in place, while storages which cannot
+    /// do that, like object stores and databases, only delete them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InTrans`] if any other transaction is in progress,
+    /// nothing is removed in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_file("/secret.txt")?.write_once(b"top secret")?;
+    /// repo.shred("/secret.txt")?;
+    /// assert!(!repo.path_exists("/secret.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`clone_file`]: struct.Repo.html#method.clone_file
+    /// [`Error::InTrans`]: enum.Error.html
+    #[inline]
+    pub fn shred<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace(Op::Shred(path.as_ref().to_path_buf()));
+        self.fs.write().unwrap().shred(path.as_ref())
+    }
+
+    /// Remove an existing empty directory.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// If trash mode is enabled, it is moved to trash rather than being
+    /// removed, see [`RepoOpener::trash`].
+    ///
+    /// This method is atomic.
+    ///
+    /// [`RepoOpener::trash`]: struct.RepoOpener.html#method.trash
     #[inline]
     pub fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_dir(path.as_ref())
+        self.trace(Op::RemoveDir(path.as_ref().to_path_buf()));
+        self.fs.write().unwrap().remove_dir(path.as_ref())
     }
 
     /// Removes a directory at this path, after removing all its children.
@@ -984,26 +4498,42 @@ impl Repo {
     ///
     /// `path` must be an absolute path.
     ///
+    /// If trash mode is enabled, the whole directory is moved to trash in
+    /// one go rather than being removed, see [`RepoOpener::trash`]. Removing
+    /// the root directory moves each of its children to trash.
+    ///
     /// This method is **not** atomic in whole, but removing each entry is
     /// atomic.
+    ///
+    /// [`RepoOpener::trash`]: struct.RepoOpener.html#method.trash
     #[inline]
     pub fn remove_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_dir_all(path.as_ref())
+        self.trace(Op::RemoveDirAll(path.as_ref().to_path_buf()));
+        self.fs.write().unwrap().remove_dir_all(path.as_ref())
     }
 
     /// Rename a file or directory to a new name, replacing the original file
     /// if `to` already exists.
     ///
-    /// `from` and `to` must be absolute paths.
+    /// `from` and `to` must be absolute paths. This follows the semantics of
+    /// [`std::fs::rename`]: a file cannot replace a directory, and a directory
+    /// can only replace an empty directory. The whole directory subtree is
+    /// moved along with `from`, file version history is kept as well.
     ///
     /// This method is atomic.
+    ///
+    /// [`std::fs::rename`]: https://doc.rust-lang.org/std/fs/fn.rename.html
     #[inline]
     pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
         &mut self,
         from: P,
         to: Q,
     ) -> Result<()> {
-        self.fs.rename(from.as_ref(), to.as_ref())
+        self.trace(Op::Rename(
+            from.as_ref().to_path_buf(),
+            to.as_ref().to_path_buf(),
+        ));
+        self.fs.write().unwrap().rename(from.as_ref(), to.as_ref())
     }
 
     /// Permanently destroy a repository specified by `uri`.
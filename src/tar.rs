@@ -0,0 +1,255 @@
+//! Tar archive writer.
+//!
+//! Entries are written in POSIX ustar format. Paths, link targets and
+//! attributes which don't fit in ustar header fields are written in a PAX
+//! extended header preceding the entry, which is understood by all common
+//! tar implementations.
+
+use std::io::{self, Read, Write};
+use std::time::UNIX_EPOCH;
+
+use error::{Error, Result};
+use fs::{FileType, Metadata};
+
+// tar block size
+const BLOCK_SIZE: usize = 512;
+
+// maximum values can be stored in octal numeric header fields
+const MAX_OCTAL_7: u64 = 0o7_777_777;
+const MAX_OCTAL_11: u64 = 0o77_777_777_777;
+
+// default permission modes if they are not set in metadata
+const DEFAULT_FILE_MODE: u32 = 0o644;
+const DEFAULT_DIR_MODE: u32 = 0o755;
+const DEFAULT_LINK_MODE: u32 = 0o777;
+
+// ustar header
+struct Header([u8; BLOCK_SIZE]);
+
+impl Header {
+    fn new() -> Self {
+        let mut hdr = Header([0u8; BLOCK_SIZE]);
+        hdr.0[257..263].copy_from_slice(b"ustar\0");
+        hdr.0[263..265].copy_from_slice(b"00");
+        hdr
+    }
+
+    fn set_bytes(&mut self, offset: usize, len: usize, val: &[u8]) {
+        debug_assert!(val.len() <= len);
+        self.0[offset..offset + val.len()].copy_from_slice(val);
+    }
+
+    // set a numeric field in zero-padded octal followed by a NUL
+    fn set_octal(&mut self, offset: usize, len: usize, val: u64) {
+        let s = format!("{:01$o}", val, len - 1);
+        self.set_bytes(offset, len, s.as_bytes());
+    }
+
+    fn set_path(&mut self, prefix: &str, name: &str) {
+        self.set_bytes(0, 100, name.as_bytes());
+        self.set_bytes(345, 155, prefix.as_bytes());
+    }
+
+    fn set_type(&mut self, flag: u8) {
+        self.0[156] = flag;
+    }
+
+    // calculate and set checksum, this must be the last step
+    fn finish(mut self) -> [u8; BLOCK_SIZE] {
+        self.0[148..156].copy_from_slice(b"        ");
+        let sum: u32 = self.0.iter().map(|b| u32::from(*b)).sum();
+        let s = format!("{:06o}\0 ", sum);
+        self.0[148..156].copy_from_slice(s.as_bytes());
+        self.0
+    }
+}
+
+// append a PAX extended header record
+fn pax_record(buf: &mut Vec<u8>, key: &str, val: &str) {
+    // record length includes the length digits itself
+    let base = key.len() + val.len() + 3;
+    let mut len = base + 1;
+    while base + len.to_string().len() != len {
+        len = base + len.to_string().len();
+    }
+    buf.extend_from_slice(format!("{} {}={}\n", len, key, val).as_bytes());
+}
+
+// split path into ustar prefix and name fields, None if it doesn't fit
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.char_indices()
+        .filter(|&(_, c)| c == '/')
+        .map(|(idx, _)| (&path[..idx], &path[idx + 1..]))
+        .find(|&(prefix, name)| {
+            prefix.len() <= 155 && !name.is_empty() && name.len() <= 100
+        })
+}
+
+// split device id into major and minor numbers, using Linux encoding
+fn split_rdev(rdev: u64) -> (u64, u64) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major, minor)
+}
+
+/// Tar archive writer
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TarWriter { inner }
+    }
+
+    // write header of an entry, preceded by a PAX extended header if needed
+    fn write_header(
+        &mut self,
+        path: &str,
+        meta: &Metadata,
+        size: u64,
+        link: &str,
+    ) -> Result<()> {
+        let (flag, default_mode) = match meta.file_type() {
+            FileType::File => (b'0', DEFAULT_FILE_MODE),
+            FileType::Dir => (b'5', DEFAULT_DIR_MODE),
+            FileType::Symlink => (b'2', DEFAULT_LINK_MODE),
+            FileType::CharDevice => (b'3', DEFAULT_FILE_MODE),
+            FileType::BlockDevice => (b'4', DEFAULT_FILE_MODE),
+            FileType::Fifo => (b'6', DEFAULT_FILE_MODE),
+            FileType::Socket => return Err(Error::InvalidArgument),
+        };
+        let (mode, uid, gid) =
+            meta.permissions().map_or((default_mode, 0, 0), |perms| {
+                (perms.mode() & 0o7777, perms.uid(), perms.gid())
+            });
+        let mtime = meta
+            .modified_at()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut hdr = Header::new();
+        let mut pax = Vec::new();
+
+        match split_path(path) {
+            Some((prefix, name)) => hdr.set_path(prefix, name),
+            None => {
+                pax_record(&mut pax, "path", path);
+                let mut end = 100;
+                while !path.is_char_boundary(end) {
+                    end -= 1;
+                }
+                hdr.set_path("", &path[..end]);
+            }
+        }
+        if link.len() > 100 {
+            pax_record(&mut pax, "linkpath", link);
+        } else {
+            hdr.set_bytes(157, 100, link.as_bytes());
+        }
+        for &(key, val, offset) in
+            &[("uid", u64::from(uid), 108), ("gid", u64::from(gid), 116)]
+        {
+            if val > MAX_OCTAL_7 {
+                pax_record(&mut pax, key, &val.to_string());
+            } else {
+                hdr.set_octal(offset, 8, val);
+            }
+        }
+        if size > MAX_OCTAL_11 {
+            pax_record(&mut pax, "size", &size.to_string());
+        } else {
+            hdr.set_octal(124, 12, size);
+        }
+        hdr.set_octal(100, 8, u64::from(mode));
+        hdr.set_octal(136, 12, mtime.min(MAX_OCTAL_11));
+        hdr.set_type(flag);
+        if flag == b'3' || flag == b'4' {
+            let (major, minor) = split_rdev(meta.rdev());
+            hdr.set_octal(329, 8, major.min(MAX_OCTAL_7));
+            hdr.set_octal(337, 8, minor.min(MAX_OCTAL_7));
+        }
+
+        if !pax.is_empty() {
+            let mut pax_hdr = Header::new();
+            pax_hdr.set_bytes(0, 100, b"././@PaxHeader");
+            pax_hdr.set_octal(100, 8, 0o644);
+            pax_hdr.set_octal(108, 8, 0);
+            pax_hdr.set_octal(116, 8, 0);
+            pax_hdr.set_octal(124, 12, pax.len() as u64);
+            pax_hdr.set_octal(136, 12, mtime.min(MAX_OCTAL_11));
+            pax_hdr.set_type(b'x');
+            self.inner.write_all(&pax_hdr.finish())?;
+            self.inner.write_all(&pax)?;
+            self.write_padding(pax.len() as u64)?;
+        }
+
+        self.inner.write_all(&hdr.finish())?;
+        Ok(())
+    }
+
+    // pad entry data to block boundary
+    fn write_padding(&mut self, size: u64) -> Result<()> {
+        let rem = (size % BLOCK_SIZE as u64) as usize;
+        if rem > 0 {
+            self.inner.write_all(&[0u8; BLOCK_SIZE][rem..])?;
+        }
+        Ok(())
+    }
+
+    /// Append a directory, `path` must be relative
+    pub fn append_dir(&mut self, path: &str, meta: &Metadata) -> Result<()> {
+        let mut path = path.to_string();
+        path.push('/');
+        self.write_header(&path, meta, 0, "")
+    }
+
+    /// Append a regular file with its content read from `data`, which must
+    /// have exactly the content length in metadata
+    pub fn append_file<R: Read>(
+        &mut self,
+        path: &str,
+        meta: &Metadata,
+        data: &mut R,
+    ) -> Result<()> {
+        let size = meta.content_len() as u64;
+        self.write_header(path, meta, size, "")?;
+        let copied = io::copy(&mut data.take(size), &mut self.inner)?;
+        if copied != size {
+            return Err(Error::Io(io::Error::from(
+                io::ErrorKind::UnexpectedEof,
+            )));
+        }
+        self.write_padding(size)
+    }
+
+    /// Append a symbolic link pointing to `target`
+    pub fn append_symlink(
+        &mut self,
+        path: &str,
+        meta: &Metadata,
+        target: &str,
+    ) -> Result<()> {
+        self.write_header(path, meta, 0, target)
+    }
+
+    /// Append a special file, socket is not supported by tar format
+    pub fn append_special(
+        &mut self,
+        path: &str,
+        meta: &Metadata,
+    ) -> Result<()> {
+        self.write_header(path, meta, 0, "")
+    }
+
+    /// Write end of archive marker and flush
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.write_all(&[0u8; BLOCK_SIZE * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
@@ -0,0 +1,370 @@
+//! Operation trace recording and replay.
+//!
+//! The tracer records public API calls made against a repository to a trace
+//! file. File content is never recorded, only its length and a seed derived
+//! from its keyed hash. The hash key is randomly generated for each trace and
+//! never saved, so the seed cannot be used to confirm a guess of the content.
+//! When replaying, the content is re-generated from the seed, so identical
+//! content in the original calls is still identical in replay.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::fs::{self, File as OsFile};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rmp_serde::{decode, Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use base::crypto::{Crypto, Hash, HashKey, RandomSeed};
+use error::{Error, Result};
+use file::File;
+use fs::FileType;
+use repo::{OpenOptions, Repo};
+
+/// File open options recorded in trace
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TraceOpts {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub truncate: bool,
+    pub create: bool,
+    pub create_new: bool,
+    pub version_limit: Option<u8>,
+    pub dedup_chunk: Option<bool>,
+}
+
+/// Traced operation
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Op {
+    CreateDir(PathBuf),
+    CreateDirAll(PathBuf),
+    RemoveFile(PathBuf),
+    RemoveDir(PathBuf),
+    RemoveDirAll(PathBuf),
+    Rename(PathBuf, PathBuf),
+    Copy(PathBuf, PathBuf),
+    CopyDirAll(PathBuf, PathBuf),
+    Open {
+        fid: u64,
+        path: PathBuf,
+        opts: TraceOpts,
+    },
+    Read {
+        fid: u64,
+        len: usize,
+    },
+    Write {
+        fid: u64,
+        len: usize,
+        seed: Hash,
+    },
+    Seek {
+        fid: u64,
+        pos: u64,
+    },
+    Finish {
+        fid: u64,
+    },
+    WriteOnce {
+        fid: u64,
+        len: usize,
+        seed: Hash,
+    },
+    SetLen {
+        fid: u64,
+        len: usize,
+    },
+    Close {
+        fid: u64,
+    },
+    WriteAt {
+        fid: u64,
+        offset: u64,
+        len: usize,
+        seed: Hash,
+    },
+    RestoreVersion {
+        fid: u64,
+        ver_num: usize,
+    },
+    CreateTemp {
+        fid: u64,
+    },
+    Persist {
+        fid: u64,
+        path: PathBuf,
+    },
+    CopyFileRange {
+        src: PathBuf,
+        src_off: u64,
+        dst: PathBuf,
+        dst_off: u64,
+        len: u64,
+    },
+    CloneFile(PathBuf, PathBuf),
+    SetTimes {
+        path: PathBuf,
+        created: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    },
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    CreateNode {
+        path: PathBuf,
+        ftype: FileType,
+        rdev: u64,
+    },
+    Shred(PathBuf),
+}
+
+impl Op {
+    // create a write op, the content is replaced by its length and seed
+    #[inline]
+    pub fn write(tracer: &Tracer, fid: u64, buf: &[u8]) -> Self {
+        Op::Write {
+            fid,
+            len: buf.len(),
+            seed: tracer.seed(buf),
+        }
+    }
+
+    // create a write once op
+    #[inline]
+    pub fn write_once(tracer: &Tracer, fid: u64, buf: &[u8]) -> Self {
+        Op::WriteOnce {
+            fid,
+            len: buf.len(),
+            seed: tracer.seed(buf),
+        }
+    }
+
+    // create a positional write op
+    #[inline]
+    pub fn write_at(
+        tracer: &Tracer,
+        fid: u64,
+        buf: &[u8],
+        offset: u64,
+    ) -> Self {
+        Op::WriteAt {
+            fid,
+            offset,
+            len: buf.len(),
+            seed: tracer.seed(buf),
+        }
+    }
+}
+
+// generate replay content from seed
+fn gen_content(len: usize, seed: &Hash) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    Crypto::random_buf_deterministic(&mut buf, &RandomSeed::from(seed));
+    buf
+}
+
+/// Operation tracer
+pub struct Tracer {
+    file: OsFile,
+    next_fid: u64,
+
+    // random key to hash content into replay seed, it is never saved
+    hash_key: HashKey,
+}
+
+impl Tracer {
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut hash_key = HashKey::new_empty();
+        Crypto::random_buf(hash_key.as_mut_slice());
+        Ok(Tracer {
+            file,
+            next_fid: 0,
+            hash_key,
+        })
+    }
+
+    // allocate a new file handle id
+    #[inline]
+    pub fn next_fid(&mut self) -> u64 {
+        self.next_fid += 1;
+        self.next_fid
+    }
+
+    // derive replay seed from content, same content has the same seed in
+    // one trace
+    #[inline]
+    pub fn seed(&self, buf: &[u8]) -> Hash {
+        Crypto::hash_with_key(buf, &self.hash_key)
+    }
+
+    // append an op to trace file, the op is flushed immediately so trace can
+    // still be used if the process crashed
+    pub fn record(&mut self, op: &Op) {
+        let mut buf = Vec::new();
+        let result = op
+            .serialize(&mut Serializer::new(&mut buf))
+            .map_err(Error::from)
+            .and_then(|_| self.file.write_all(&buf).map_err(Error::from));
+        if let Err(err) = result {
+            warn!("record trace op {:?} failed: {}", op, err);
+        }
+    }
+
+    /// Replay trace file against a repo, return number of ops replayed
+    ///
+    /// Errors returned from replayed operations are ignored, as the original
+    /// calls might also fail.
+    pub fn replay(path: &Path, repo: &mut Repo) -> Result<usize> {
+        let file = OsFile::open(path)?;
+        let mut de = Deserializer::new(BufReader::new(file));
+        let mut files: HashMap<u64, File> = HashMap::new();
+        let mut cnt = 0;
+
+        loop {
+            let op: Op = match Deserialize::deserialize(&mut de) {
+                Ok(op) => op,
+                Err(decode::Error::InvalidMarkerRead(ref err))
+                    if err.kind() == ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(err) => return Err(Error::from(err)),
+            };
+
+            let result = Self::replay_op(&op, repo, &mut files);
+            if let Err(err) = result {
+                debug!("replay trace op {:?} failed: {}", op, err);
+            }
+            cnt += 1;
+        }
+
+        Ok(cnt)
+    }
+
+    fn replay_op(
+        op: &Op,
+        repo: &mut Repo,
+        files: &mut HashMap<u64, File>,
+    ) -> Result<()> {
+        match *op {
+            Op::CreateDir(ref path) => repo.create_dir(path),
+            Op::CreateDirAll(ref path) => repo.create_dir_all(path),
+            Op::RemoveFile(ref path) => repo.remove_file(path),
+            Op::RemoveDir(ref path) => repo.remove_dir(path),
+            Op::RemoveDirAll(ref path) => repo.remove_dir_all(path),
+            Op::Rename(ref from, ref to) => repo.rename(from, to),
+            Op::Copy(ref from, ref to) => repo.copy(from, to),
+            Op::CopyDirAll(ref from, ref to) => repo.copy_dir_all(from, to),
+            Op::Open {
+                fid,
+                ref path,
+                ref opts,
+            } => {
+                let file = OpenOptions::from(opts).open(repo, path)?;
+                files.insert(fid, file);
+                Ok(())
+            }
+            Op::Read { fid, len } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                let mut buf = vec![0u8; len];
+                file.read(&mut buf)?;
+                Ok(())
+            }
+            Op::Write { fid, len, ref seed } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.write_all(&gen_content(len, seed))?;
+                Ok(())
+            }
+            Op::Seek { fid, pos } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.seek(SeekFrom::Start(pos))?;
+                Ok(())
+            }
+            Op::Finish { fid } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.finish()
+            }
+            Op::WriteOnce { fid, len, ref seed } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.write_once(&gen_content(len, seed))
+            }
+            Op::SetLen { fid, len } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.set_len(len)
+            }
+            Op::Close { fid } => {
+                files.remove(&fid);
+                Ok(())
+            }
+            Op::WriteAt {
+                fid,
+                offset,
+                len,
+                ref seed,
+            } => {
+                let file = files.get(&fid).ok_or(Error::NotFound)?;
+                file.write_at(&gen_content(len, seed), offset)?;
+                Ok(())
+            }
+            Op::RestoreVersion { fid, ver_num } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.restore_version(ver_num)
+            }
+            Op::CreateTemp { fid } => {
+                let file = repo.create_temp_file()?;
+                files.insert(fid, file);
+                Ok(())
+            }
+            Op::Persist { fid, ref path } => {
+                let file = files.get_mut(&fid).ok_or(Error::NotFound)?;
+                file.persist(path)
+            }
+            Op::CopyFileRange {
+                ref src,
+                src_off,
+                ref dst,
+                dst_off,
+                len,
+            } => repo
+                .copy_file_range(src, src_off, dst, dst_off, len)
+                .map(|_| ()),
+            Op::CloneFile(ref from, ref to) => repo.clone_file(from, to),
+            Op::SetTimes {
+                ref path,
+                created,
+                modified,
+            } => repo.set_times(path, created, modified),
+            Op::Symlink {
+                ref target,
+                ref link,
+            } => repo.symlink(target, link),
+            Op::CreateNode {
+                ref path,
+                ftype,
+                rdev,
+            } => repo.create_node(path, ftype, rdev),
+            Op::Shred(ref path) => repo.shred(path),
+        }
+    }
+}
+
+impl Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("next_fid", &self.next_fid)
+            .finish()
+    }
+}
+
+/// Tracer reference type
+pub type TracerRef = Arc<Mutex<Tracer>>;
@@ -387,6 +387,14 @@ where
                     result
                 }
                 Action::Delete => {
+                    // if the object was updated before deleted, replace
+                    // the old inner object with the updated one, so the
+                    // references held by the old one are released
+                    if self.has_other() {
+                        let new_inner = self.other_mut().take();
+                        *self.curr_mut() = new_inner;
+                    }
+
                     // notify inner object
                     self.inner_mut().on_commit(vol)?;
 
@@ -417,7 +425,7 @@ where
     fn abort(&mut self) {
         match self.action {
             Some(action) => {
-                if let Action::Update = action {
+                if let Action::Update | Action::Delete = action {
                     // discard the new inner object
                     self.other_mut().take();
                 }
@@ -512,6 +520,12 @@ where
         Ok(cow_ref)
     }
 
+    // get from cache only, without loading it from volume
+    pub fn peek(&self, id: &Eid) -> Option<CowRef<T>> {
+        let mut lru = self.lru.write().unwrap();
+        lru.get_refresh(id).cloned()
+    }
+
     pub fn insert(&self, cow: &CowRef<T>) {
         let mut lru = self.lru.write().unwrap();
         let id = {
@@ -550,13 +564,14 @@ mod tests {
     use base::init_env;
     use fs::Config;
     use trans::{Eid, TxMgr};
-    use volume::Volume;
+    use volume::{Credential, Volume};
 
     fn setup_vol(loc: &str) -> VolumeRef {
         init_env();
         let uri = format!("mem://{}", loc);
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         vol.into_ref()
     }
 
@@ -12,7 +12,7 @@ mod wal;
 pub use self::eid::{Eid, Id};
 pub use self::txid::Txid;
 pub use self::txmgr::{TxHandle, TxMgr, TxMgrRef, TxMgrWeakRef};
-pub use self::wal::EntityType;
+pub use self::wal::{EntityType, EntryCounts};
 
 use std::io::Write;
 
@@ -3,7 +3,7 @@ use std::sync::{Arc, RwLock};
 
 use linked_hash_map::LinkedHashMap;
 
-use super::wal::Wal;
+use super::wal::{EntryCounts, Wal};
 use super::{Eid, EntityType, Id, Txid};
 use base::IntoRef;
 use error::{Error, Result};
@@ -34,6 +34,12 @@ pub struct Trans {
     wal: Wal,
     wal_armor: VolumeWalArmor<Wal>,
     wal_saved: bool,
+
+    // change of total file versions size made in this transaction
+    usage: i64,
+
+    // changes of entry counts made in this transaction
+    counts: EntryCounts,
 }
 
 impl Trans {
@@ -44,6 +50,8 @@ impl Trans {
             wal: Wal::new(txid),
             wal_armor: VolumeWalArmor::new(vol),
             wal_saved: false,
+            usage: 0,
+            counts: EntryCounts::default(),
         }
     }
 
@@ -52,6 +60,26 @@ impl Trans {
         self.wal.clone()
     }
 
+    #[inline]
+    pub fn usage(&self) -> i64 {
+        self.usage
+    }
+
+    #[inline]
+    pub fn add_usage(&mut self, delta: i64) {
+        self.usage += delta;
+    }
+
+    #[inline]
+    pub fn counts(&self) -> EntryCounts {
+        self.counts
+    }
+
+    #[inline]
+    pub fn add_counts(&mut self, delta: EntryCounts) {
+        self.counts += delta;
+    }
+
     #[inline]
     pub fn begin_trans(&mut self) -> Result<()> {
         self.wal_armor.save_item(&mut self.wal)
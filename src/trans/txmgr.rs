@@ -5,7 +5,7 @@ use std::sync::{Arc, Mutex, RwLock, Weak};
 use linked_hash_map::LinkedHashMap;
 
 use super::trans::{Action, Trans, TransRef, TransableRef};
-use super::wal::{EntityType, WalQueueMgr};
+use super::wal::{EntityType, EntryCounts, WalQueueMgr};
 use super::{Eid, Txid};
 use base::IntoRef;
 use error::{Error, Result};
@@ -37,12 +37,24 @@ impl TxMgr {
     }
 
     /// Open transaction manager
-    pub fn open(walq_id: &Eid, vol: &VolumeRef) -> Result<Self> {
+    ///
+    /// Read-only transaction manager doesn't recover failed transactions,
+    /// that is left to the writer.
+    pub fn open(
+        walq_id: &Eid,
+        vol: &VolumeRef,
+        read_only: bool,
+    ) -> Result<Self> {
         let mut txmgr = TxMgr::new(walq_id, vol);
-        txmgr.walq_mgr.open(walq_id)?;
+        txmgr.walq_mgr.open(walq_id, read_only)?;
         Ok(txmgr)
     }
 
+    #[inline]
+    pub fn walq_id(&self) -> &Eid {
+        self.walq_mgr.walq_id()
+    }
+
     /// Begin a transaction
     pub fn begin_trans(txmgr: &TxMgrRef) -> Result<TxHandle> {
         // check if current thread is already in transaction
@@ -52,6 +64,12 @@ impl TxMgr {
 
         let mut tm = txmgr.write().unwrap();
 
+        // sealed volume cannot be changed, this also applies to files opened
+        // before it was sealed
+        if tm.vol.read().unwrap().is_sealed() {
+            return Err(Error::ReadOnly);
+        }
+
         // try to redo abort tx if any tx failed abortion before,
         tm.walq_mgr.hot_redo_abort()?;
 
@@ -109,6 +127,86 @@ impl TxMgr {
         tx.add_entity(id, entity, action, ent_type, arm)
     }
 
+    /// Add file versions size change to current transaction
+    pub fn add_usage(&self, delta: i64) -> Result<()> {
+        let txid = Txid::current()?;
+        let txref = self.txs.get(&txid).ok_or(Error::NoTrans)?;
+        let mut tx = txref.write().unwrap();
+        tx.add_usage(delta);
+        Ok(())
+    }
+
+    /// Add entry counts change to current transaction
+    pub fn add_counts(&self, delta: EntryCounts) -> Result<()> {
+        let txid = Txid::current()?;
+        let txref = self.txs.get(&txid).ok_or(Error::NoTrans)?;
+        let mut tx = txref.write().unwrap();
+        tx.add_counts(delta);
+        Ok(())
+    }
+
+    /// Get total size of all file versions, `None` if it is unknown
+    #[inline]
+    pub fn logical_len(&self) -> Option<u64> {
+        self.walq_mgr.logical_len()
+    }
+
+    /// Set total size of all file versions
+    #[inline]
+    pub fn set_logical_len(&mut self, len: u64) {
+        self.walq_mgr.set_logical_len(len)
+    }
+
+    /// Get numbers of files, directories and file versions, `None` if they
+    /// are unknown
+    #[inline]
+    pub fn counts(&self) -> Option<EntryCounts> {
+        self.walq_mgr.counts()
+    }
+
+    /// Set numbers of files, directories and file versions
+    #[inline]
+    pub fn set_counts(&mut self, counts: EntryCounts) {
+        self.walq_mgr.set_counts(counts)
+    }
+
+    /// Flush underlying volume, so data written so far is persisted to
+    /// storage
+    #[inline]
+    pub fn flush_vol(&self) -> Result<()> {
+        let mut vol = self.vol.write().unwrap();
+        vol.flush()
+    }
+
+    /// Get number of committed wals which are not recycled yet
+    #[inline]
+    pub fn retained_wals(&self) -> usize {
+        self.walq_mgr.retained_wals()
+    }
+
+    /// Recycle all committed wals, so that data deleted by them are
+    /// removed from volume immediately
+    ///
+    /// This must be called when there is no transaction in progress, it
+    /// returns the number of recycled wals.
+    pub fn recycle_all(&mut self) -> Result<usize> {
+        if !self.txs.is_empty() {
+            return Err(Error::InTrans);
+        }
+        self.walq_mgr.recycle_all()
+    }
+
+    /// Re-encrypt wal queue and retained wals using current volume cipher
+    ///
+    /// This must be called when there is no transaction in progress, it
+    /// returns the number of re-encrypted wals.
+    pub fn reencrypt_wals(&mut self) -> Result<usize> {
+        if !self.txs.is_empty() {
+            return Err(Error::InTrans);
+        }
+        self.walq_mgr.reencrypt()
+    }
+
     #[inline]
     fn remove_trans(&mut self, txid: Txid) {
         self.txs.remove(&txid);
@@ -118,14 +216,22 @@ impl TxMgr {
 
     // commit transaction
     fn commit_trans(&mut self, txid: Txid) -> Result<()> {
+        // transaction begun before volume was sealed cannot be committed
+        if self.vol.read().unwrap().is_sealed() {
+            self.abort_trans(txid);
+            return Err(Error::ReadOnly);
+        }
+
         let result = {
             let tx_ref = self.txs.get(&txid).unwrap().clone();
             let mut tx = tx_ref.write().unwrap();
 
             // commit tx, if any errors then abort the tx
+            let usage = tx.usage();
+            let counts = tx.counts();
             match tx
                 .commit(&self.vol)
-                .and_then(|wal| self.walq_mgr.commit_trans(wal))
+                .and_then(|wal| self.walq_mgr.commit_trans(wal, usage, counts))
             {
                 Ok(_) => {
                     tx.complete_commit();
@@ -232,7 +338,7 @@ impl TxHandle {
     where
         F: FnOnce() -> Result<()>,
     {
-        let _ = EXCL_TX_LOCK.lock().unwrap();
+        let _lock = EXCL_TX_LOCK.lock().unwrap();
         self.run_all(oper)
     }
 
@@ -244,6 +350,15 @@ impl TxHandle {
         tm.commit_trans(self.txid)
     }
 
+    /// Abort a transaction which is not going to be committed
+    #[inline]
+    pub fn rollback(&self) -> Result<()> {
+        let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let mut tm = txmgr.write().unwrap();
+        tm.abort_trans(self.txid);
+        Ok(())
+    }
+
     /// Abort a transaction
     fn abort(&self, err: Error) -> Result<()> {
         let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
@@ -269,13 +384,14 @@ mod tests {
     use fs::Config;
     use trans::cow::{CowRef, Cowable, IntoCow};
     use trans::TxMgr;
-    use volume::{ArmAccess, Volume};
+    use volume::{ArmAccess, Credential, Volume};
 
     fn setup_mem_vol(loc: &str) -> VolumeRef {
         init_env();
         let uri = format!("mem://{}", loc);
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         vol.into_ref()
     }
 
@@ -285,7 +401,8 @@ mod tests {
         let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
         let uri = format!("file://{}", tmpdir.path().display());
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         (vol.into_ref(), tmpdir)
     }
 
@@ -295,7 +412,8 @@ mod tests {
         let uri = "zbox://accessKey456@repo456?cache_type=mem&cache_size=1mb"
             .to_string();
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         vol.into_ref()
     }
 
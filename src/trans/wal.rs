@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::ops::{AddAssign, Neg};
 
 use linked_hash_map::LinkedHashMap;
 
@@ -19,6 +20,49 @@ pub enum EntityType {
     Direct,
 }
 
+/// Numbers of files, directories and file versions
+///
+/// It is used for both the totals in a repo and the changes made by a
+/// transaction, so the numbers are signed.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct EntryCounts {
+    pub files: i64,
+    pub dirs: i64,
+    pub versions: i64,
+}
+
+impl EntryCounts {
+    #[inline]
+    pub fn versions(versions: i64) -> Self {
+        EntryCounts {
+            versions,
+            ..Default::default()
+        }
+    }
+}
+
+impl AddAssign for EntryCounts {
+    fn add_assign(&mut self, other: EntryCounts) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.versions += other.versions;
+    }
+}
+
+impl Neg for EntryCounts {
+    type Output = EntryCounts;
+
+    fn neg(self) -> EntryCounts {
+        EntryCounts {
+            files: -self.files,
+            dirs: -self.dirs,
+            versions: -self.versions,
+        }
+    }
+}
+
 /// Wal entry
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Entry {
@@ -92,16 +136,12 @@ impl Wal {
     }
 
     // recycle tx entries in a wal
-    fn recycle(
-        &self,
-        wal_armor: &VolumeWalArmor<Self>,
-        vol: &VolumeRef,
-    ) -> Result<()> {
+    fn recycle(&self, vol: &VolumeRef) -> Result<()> {
         for ent in self.entries.values() {
             match ent.action {
                 Action::New | Action::Update => {} // do nothing
                 Action::Delete => match ent.ent_type {
-                    EntityType::Cow => wal_armor.remove_all_arms(&ent.id)?,
+                    EntityType::Cow => Arm::remove_all(&ent.id, vol)?,
                     EntityType::Direct => {
                         let mut vol = vol.write().unwrap();
                         vol.del(&ent.id)?;
@@ -205,6 +245,18 @@ struct WalQueue {
     // in-progress tx id list
     doing: HashSet<Txid>,
 
+    // used block count and total size of all file versions, they are
+    // None if the wal queue was created by older versions
+    #[serde(default)]
+    blk_used: Option<usize>,
+    #[serde(default)]
+    logical_len: Option<u64>,
+
+    // numbers of files, directories and file versions, None if the wal
+    // queue was created by older versions
+    #[serde(default)]
+    counts: Option<EntryCounts>,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     aborting: HashMap<Txid, Wal>,
 
@@ -234,6 +286,9 @@ impl WalQueue {
             blk_wmark: 0,
             done: VecDeque::new(),
             doing: HashSet::new(),
+            blk_used: Some(0),
+            logical_len: Some(0),
+            counts: Some(EntryCounts::default()),
             aborting: HashMap::new(),
             wal_armor: VolumeWalArmor::new(vol),
             allocator,
@@ -284,7 +339,7 @@ impl WalQueue {
         match self.wal_armor.load_item(&retiree_id) {
             Ok(retiree) => {
                 // recycle and remove the wal
-                retiree.recycle(&self.wal_armor, &self.vol)?;
+                retiree.recycle(&self.vol)?;
                 self.wal_armor.remove_all_arms(&retiree_id)
             }
             Err(ref err) if *err == Error::NotFound => {
@@ -296,7 +351,21 @@ impl WalQueue {
         }
     }
 
-    fn commit_trans(&mut self, wal: Wal) -> Result<()> {
+    // recycle all the committed trans
+    fn recycle_all(&mut self) -> Result<()> {
+        while !self.done.is_empty() {
+            self.recycle_trans()?;
+            self.done.pop_front();
+        }
+        Ok(())
+    }
+
+    fn commit_trans(
+        &mut self,
+        wal: Wal,
+        usage: i64,
+        counts: EntryCounts,
+    ) -> Result<()> {
         // recycle the retired trans
         while self.done.len() >= Self::COMMITTED_QUEUE_SIZE {
             self.recycle_trans()?;
@@ -307,6 +376,18 @@ impl WalQueue {
         self.doing.remove(&wal.txid);
         self.done.push_back(wal.txid);
 
+        // apply the usage change made by this tx
+        if let Some(len) = self.logical_len {
+            self.logical_len = Some(if usage < 0 {
+                len.saturating_sub(usage.unsigned_abs())
+            } else {
+                len + usage as u64
+            });
+        }
+        if let Some(ref mut curr) = self.counts {
+            *curr += counts;
+        }
+
         Ok(())
     }
 
@@ -445,21 +526,23 @@ impl WalQueueMgr {
         }
     }
 
-    pub fn open(&mut self, walq_id: &Eid) -> Result<()> {
+    pub fn open(&mut self, walq_id: &Eid, read_only: bool) -> Result<()> {
         // load wal queue
         self.walq = self.walq_armor.load_item(walq_id)?;
         self.walq.open(&self.vol);
 
-        // restore watermarks
+        // restore watermarks and used block count, the block watermark
+        // is used as the used block count if it is not recorded
         let (txid_wmark, blk_wmark) = self.walq.watermarks();
         self.txid_wmark = Txid::from(txid_wmark);
         {
             let mut allocator = self.allocator.write().unwrap();
             allocator.set_block_wmark(blk_wmark);
+            allocator.set_used_blocks(self.walq.blk_used.unwrap_or(blk_wmark));
         }
 
         // now redo abort tx if any
-        if self.walq.has_doing() {
+        if !read_only && self.walq.has_doing() {
             self.backup_walq();
             self.walq
                 .cold_redo_abort()
@@ -485,11 +568,42 @@ impl WalQueueMgr {
         Ok(())
     }
 
+    #[inline]
+    pub fn walq_id(&self) -> &Eid {
+        self.walq.id()
+    }
+
     #[inline]
     pub fn next_txid(&mut self) -> Txid {
         self.txid_wmark.next()
     }
 
+    #[inline]
+    pub fn logical_len(&self) -> Option<u64> {
+        self.walq.logical_len
+    }
+
+    #[inline]
+    pub fn set_logical_len(&mut self, len: u64) {
+        self.walq.logical_len = Some(len);
+    }
+
+    #[inline]
+    pub fn counts(&self) -> Option<EntryCounts> {
+        self.walq.counts
+    }
+
+    #[inline]
+    pub fn set_counts(&mut self, counts: EntryCounts) {
+        self.walq.counts = Some(counts);
+    }
+
+    // number of committed wals which are not recycled yet
+    #[inline]
+    pub fn retained_wals(&self) -> usize {
+        self.walq.done.len()
+    }
+
     #[inline]
     fn backup_walq(&mut self) {
         self.walq_backup = Some(self.walq.clone());
@@ -501,12 +615,14 @@ impl WalQueueMgr {
     }
 
     fn save_walq(&mut self) -> Result<()> {
-        // get current block watermark and set it to wal queue
-        let blk_wmark = {
+        // get current block watermark and used block count and set them
+        // to wal queue
+        let (blk_wmark, blk_used) = {
             let allocator = self.allocator.read().unwrap();
-            allocator.block_wmark()
+            (allocator.block_wmark(), allocator.used_blocks())
         };
         self.walq.set_watermarks(self.txid_wmark.val(), blk_wmark);
+        self.walq.blk_used = Some(blk_used);
 
         // flush volume then save wal queue
         {
@@ -525,10 +641,15 @@ impl WalQueueMgr {
         })
     }
 
-    pub fn commit_trans(&mut self, wal: Wal) -> Result<()> {
+    pub fn commit_trans(
+        &mut self,
+        wal: Wal,
+        usage: i64,
+        counts: EntryCounts,
+    ) -> Result<()> {
         self.backup_walq();
         self.walq
-            .commit_trans(wal)
+            .commit_trans(wal, usage, counts)
             .and_then(|_| self.save_walq())
             .or_else(|err| {
                 // if commit failed, restore the walq backup
@@ -537,6 +658,39 @@ impl WalQueueMgr {
             })
     }
 
+    // recycle all committed wals, return the number of recycled wals
+    pub fn recycle_all(&mut self) -> Result<usize> {
+        let cnt = self.walq.done.len();
+        self.backup_walq();
+        self.walq
+            .recycle_all()
+            .and_then(|_| self.save_walq())
+            .map(|_| cnt)
+            .or_else(|err| {
+                self.restore_walq();
+                Err(err)
+            })
+    }
+
+    // re-encrypt wal queue and all retained wals using current volume
+    // cipher, return the number of re-encrypted wals
+    pub fn reencrypt(&mut self) -> Result<usize> {
+        let mut ids = vec![self.walq.id.clone()];
+        ids.extend(self.walq.done.iter().map(|txid| Wal::derive_id(*txid)));
+
+        let mut vol = self.vol.write().unwrap();
+        let mut cnt = 0;
+        for id in ids {
+            let (left_arm_id, right_arm_id) = Arm::both_eid(&id);
+            for arm_id in &[left_arm_id, right_arm_id] {
+                if vol.reencrypt_wal(arm_id)? {
+                    cnt += 1;
+                }
+            }
+        }
+        Ok(cnt)
+    }
+
     #[inline]
     pub fn begin_abort(&mut self, wal: &Wal) {
         self.walq.begin_abort(wal)
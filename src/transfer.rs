@@ -0,0 +1,177 @@
+//! File transfer between repositories.
+
+use std::cmp::{max, min};
+use std::path::Path;
+
+use error::{Error, Result};
+use repo::{OpenOptions, Repo};
+
+/// Options and flags which can be used to configure how a file is
+/// transferred between two repositories.
+///
+/// This builder exposes the ability to configure how a file is transferred
+/// from one repository to another. [`transfer`] is an alias which transfers
+/// with default options.
+///
+/// Generally speaking, when using `TransferOptions`, you'll first call
+/// [`new`], then chain calls to methods to set each option, then call
+/// [`transfer`](struct.TransferOptions.html#method.transfer), passing the
+/// source repository and path, and the destination repository and path.
+///
+/// # Examples
+///
+/// Transfer a file with all its versions.
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// use zbox::TransferOptions;
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// let mut src = RepoOpener::new()
+///     .create(true)
+///     .version_limit(4)
+///     .open("mem://src", "pwd")?;
+/// let mut dst = RepoOpener::new().create(true).open("mem://dst", "pwd2")?;
+///
+/// let mut file = src.create_file("/foo.txt")?;
+/// file.write_once(b"foo")?;
+/// file.write_once(b"bar")?;
+///
+/// TransferOptions::new()
+///     .history(true)
+///     .transfer(&mut src, "/foo.txt", &mut dst, "/foo.txt")?;
+/// assert_eq!(dst.history("/foo.txt")?.len(), 3);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`transfer`]: fn.transfer.html
+/// [`new`]: struct.TransferOptions.html#method.new
+#[derive(Debug, Default)]
+pub struct TransferOptions {
+    history: bool,
+}
+
+impl TransferOptions {
+    /// Creates a blank new set of options ready for configuration.
+    ///
+    /// By default, only the current version is transferred.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the option for transferring version history.
+    ///
+    /// If it is set, all versions of the file are transferred from the
+    /// oldest to the newest. The version limit of the destination file is
+    /// raised if needed, so no version is dropped.
+    pub fn history(&mut self, history: bool) -> &mut TransferOptions {
+        self.history = history;
+        self
+    }
+
+    /// Transfers a regular file from one repository to another.
+    ///
+    /// File content is read from `src_repo` and written to `dst_repo` chunk by
+    /// chunk, so decrypted content is never written to the host file system.
+    /// Permissions and modification time are preserved. The source file is
+    /// left untouched, remove it afterwards to move the file.
+    ///
+    /// Each version is written in its own transaction, so this method is not
+    /// atomic when transferring version history.
+    ///
+    /// `src_path` and `dst_path` must be absolute paths, and `dst_path`'s
+    /// parent directory must exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFile`] if `src_path` is not a regular file, and
+    /// [`Error::AlreadyExists`] if `dst_path` already exists.
+    ///
+    /// [`Error::NotFile`]: enum.Error.html
+    /// [`Error::AlreadyExists`]: enum.Error.html
+    pub fn transfer<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        src_repo: &mut Repo,
+        src_path: P,
+        dst_repo: &mut Repo,
+        dst_path: Q,
+    ) -> Result<()> {
+        let src_path = src_path.as_ref();
+        let dst_path = dst_path.as_ref();
+        if !src_repo.is_file(src_path)? {
+            return Err(Error::NotFile);
+        }
+
+        let src = src_repo.open_file(src_path)?;
+        let meta = src.metadata()?;
+        let mut vers = src.history()?;
+        if !self.history {
+            vers = vers.split_off(vers.len() - 1);
+        }
+
+        // keep all transferred versions in destination file
+        let version_limit = min(
+            max(vers.len(), dst_repo.info()?.version_limit() as usize),
+            u8::MAX as usize,
+        ) as u8;
+        let mut dst = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .version_limit(version_limit)
+            .open(dst_repo, dst_path)?;
+
+        for (idx, ver) in vers.iter().enumerate() {
+            // newly created file already has an empty version
+            if idx == 0 && ver.content_len() == 0 {
+                continue;
+            }
+            let mut rdr = src.version_reader(ver.num())?;
+            dst.write_version(&mut rdr)?;
+        }
+        drop(dst);
+
+        if let Some(perms) = meta.permissions() {
+            dst_repo.set_permissions(dst_path, perms)?;
+        }
+        dst_repo.set_mtime(dst_path, meta.modified_at())
+    }
+}
+
+/// Transfers a regular file from one repository to another.
+///
+/// This is a shortcut of [`TransferOptions::transfer`] with default options,
+/// only the current version of the file is transferred. See
+/// [`TransferOptions`] for more details and the option to transfer version
+/// history.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// let mut src = RepoOpener::new().create(true).open("mem://src", "pwd")?;
+/// let mut dst = RepoOpener::new().create(true).open("mem://dst", "pwd2")?;
+///
+/// src.create_file("/foo.txt")?.write_once(b"Hello")?;
+/// zbox::transfer(&mut src, "/foo.txt", &mut dst, "/bar.txt")?;
+/// assert!(dst.is_file("/bar.txt")?);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`TransferOptions::transfer`]: struct.TransferOptions.html#method.transfer
+/// [`TransferOptions`]: struct.TransferOptions.html
+#[inline]
+pub fn transfer<P: AsRef<Path>, Q: AsRef<Path>>(
+    src_repo: &mut Repo,
+    src_path: P,
+    dst_repo: &mut Repo,
+    dst_path: Q,
+) -> Result<()> {
+    TransferOptions::new().transfer(src_repo, src_path, dst_repo, dst_path)
+}
@@ -3,12 +3,16 @@ use std::ops::Index;
 use std::slice::Iter;
 
 use super::{BLKS_PER_FRAME, BLK_SIZE, FRAME_SIZE};
+use trans::Eid;
 
 /// Block span
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
 pub struct Span {
-    pub begin: usize, // begin block index
-    pub cnt: usize,   // number of blocks in the span
+    /// Begin block index
+    pub begin: usize,
+
+    /// Number of blocks in the span
+    pub cnt: usize,
 }
 
 impl Span {
@@ -98,6 +102,26 @@ impl LocSpan {
 pub struct Addr {
     pub len: usize,
     pub list: Vec<LocSpan>,
+
+    // whether the blocks are LZ4 compressed, none for addresses written
+    // before this flag was introduced, which follow the volume setting
+    #[serde(default)]
+    pub compress: Option<bool>,
+
+    // key domain whose key encrypts the blocks, none for blocks encrypted
+    // by the repo data key
+    #[serde(default)]
+    pub domain: Option<Eid>,
+
+    // whether the blocks are encrypted deterministically by the convergent
+    // key rather than the data key
+    #[serde(default)]
+    pub convergent: bool,
+
+    // whether the address and its blocks are encrypted by the cipher and key
+    // being migrated from, this is set when the address is read
+    #[serde(skip)]
+    pub legacy: bool,
 }
 
 impl Addr {
@@ -193,6 +217,10 @@ mod tests {
         let addr = Addr {
             len: 3,
             list: vec![lspan.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 1);
@@ -204,6 +232,10 @@ mod tests {
         let addr = Addr {
             len: FRAME_SIZE,
             list: vec![lspan.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 1);
@@ -215,6 +247,10 @@ mod tests {
         let addr = Addr {
             len: FRAME_SIZE + 3,
             list: vec![lspan.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 2);
@@ -232,6 +268,10 @@ mod tests {
         let addr = Addr {
             len: BLK_SIZE + 3,
             list: vec![lspan.clone(), lspan2.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 1);
@@ -246,6 +286,10 @@ mod tests {
         let addr = Addr {
             len: BLK_SIZE + FRAME_SIZE,
             list: vec![lspan.clone(), lspan2.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 2);
@@ -268,6 +312,10 @@ mod tests {
         let addr = Addr {
             len: FRAME_SIZE * 2 + 3,
             list: vec![lspan.clone()],
+            compress: None,
+            domain: None,
+            convergent: false,
+            legacy: false,
         };
         let frms = addr.divide_to_frames();
         assert_eq!(frms.len(), 3);
@@ -287,4 +335,36 @@ mod tests {
             LocSpan::new(BLKS_PER_FRAME * 2, 1, FRAME_SIZE * 2)
         );
     }
+
+    #[test]
+    fn addr_compat() {
+        use rmp_serde::{Deserializer, Serializer};
+        use serde::{Deserialize, Serialize};
+
+        // address written without compression flag
+        #[derive(Serialize)]
+        struct OldAddr {
+            len: usize,
+            list: Vec<LocSpan>,
+        }
+
+        let old = OldAddr {
+            len: 3,
+            list: vec![LocSpan::new(0, 1, 0)],
+        };
+        let mut buf = Vec::new();
+        old.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        let mut de = Deserializer::new(&buf[..]);
+        let addr: Addr = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(addr.len, 3);
+        assert!(addr.compress.is_none());
+
+        let mut addr = addr;
+        addr.compress = Some(true);
+        let mut buf = Vec::new();
+        addr.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        let mut de = Deserializer::new(&buf[..]);
+        let addr: Addr = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(addr.compress, Some(true));
+    }
 }
@@ -7,6 +7,10 @@ use base::IntoRef;
 #[derive(Debug, Default)]
 pub struct Allocator {
     blk_wmark: usize,
+    blk_used: usize,
+
+    // blocks reserved ahead of writes, not allocated yet
+    blk_reserved: usize,
 }
 
 impl Allocator {
@@ -25,13 +29,49 @@ impl Allocator {
         self.blk_wmark = blk_wmark;
     }
 
+    #[inline]
+    pub fn used_blocks(&self) -> usize {
+        self.blk_used
+    }
+
+    #[inline]
+    pub fn set_used_blocks(&mut self, blk_used: usize) {
+        self.blk_used = blk_used;
+    }
+
+    #[inline]
+    pub fn reserved_blocks(&self) -> usize {
+        self.blk_reserved
+    }
+
+    // reserve blocks without allocating them, reserved blocks are counted
+    // as used space until they are released
+    #[inline]
+    pub fn reserve(&mut self, blk_cnt: usize) {
+        self.blk_reserved += blk_cnt;
+    }
+
+    // release reserved blocks
+    #[inline]
+    pub fn release(&mut self, blk_cnt: usize) {
+        self.blk_reserved = self.blk_reserved.saturating_sub(blk_cnt);
+    }
+
     // allocate continuous blocks
     #[inline]
     pub fn allocate(&mut self, blk_cnt: usize) -> Span {
         let begin = self.blk_wmark;
         self.blk_wmark += blk_cnt;
+        self.blk_used += blk_cnt;
         Span::new(begin, blk_cnt)
     }
+
+    // release blocks, blocks are never reused so only the used block
+    // count is decreased
+    #[inline]
+    pub fn free(&mut self, blk_cnt: usize) {
+        self.blk_used = self.blk_used.saturating_sub(blk_cnt);
+    }
 }
 
 impl IntoRef for Allocator {}
@@ -32,7 +32,7 @@ impl Arm {
     }
 
     #[inline]
-    fn both_eid(id: &Eid) -> (Eid, Eid) {
+    pub fn both_eid(id: &Eid) -> (Eid, Eid) {
         (Arm::Left.to_eid(id), Arm::Right.to_eid(id))
     }
 
@@ -251,7 +251,7 @@ mod tests {
     use super::*;
     use base::{init_env, IntoRef};
     use fs::Config;
-    use volume::Volume;
+    use volume::{Credential, Volume};
 
     #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     struct Item {
@@ -310,7 +310,8 @@ mod tests {
     fn volume_armor() {
         init_env();
         let mut vol = Volume::new("mem://volume_armor").unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         let varm = VolumeArmor::<Item>::new(&vol.into_ref());
 
         let mut item = Item::new();
@@ -0,0 +1,86 @@
+use std::fmt::Debug;
+
+use error::Result;
+
+/// A provider which protects repository key with an external key.
+///
+/// Instead of deriving the key from a password, a repository can be created
+/// and opened by [`RepoOpener::open_with_provider`]. In this case, a random
+/// volume key is generated and wrapped by the provider, only the wrapped key
+/// and the provider URI are stored in super block. The key encryption key
+/// (KEK) itself never needs to be on the host, for example it can be kept in
+/// a PKCS#11 token or a YubiKey, the provider only asks the token to wrap and
+/// unwrap the volume key.
+///
+/// Operations which need a password, such as [`Repo::reset_password`], are
+/// not available for the repository protected by a key provider, they will
+/// return [`Error::KeyProvider`] error.
+///
+/// # Examples
+///
+/// A toy provider using a software key, a real provider should call the
+/// hardware token instead.
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener, Repo};
+/// use zbox::KeyProvider;
+///
+/// #[derive(Debug)]
+/// struct XorProvider(u8);
+///
+/// impl KeyProvider for XorProvider {
+///     fn uri(&self) -> String {
+///         "pkcs11:token=demo;object=kek".to_string()
+///     }
+///
+///     fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+///         Ok(key.iter().map(|b| b ^ self.0).collect())
+///     }
+///
+///     fn unwrap_key(&self, wrapped: &[u8], key: &mut [u8]) -> Result<()> {
+///         for (k, w) in key.iter_mut().zip(wrapped) {
+///             *k = w ^ self.0;
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// let provider = XorProvider(42);
+/// let repo = RepoOpener::new()
+///     .create(true)
+///     .open_with_provider("mem://foo", &provider)?;
+/// assert_eq!(
+///     Repo::key_provider_uri("mem://foo")?.unwrap(),
+///     "pkcs11:token=demo;object=kek"
+/// );
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`RepoOpener::open_with_provider`]: struct.RepoOpener.html#method.open_with_provider
+/// [`Repo::reset_password`]: struct.Repo.html#method.reset_password
+/// [`Error::KeyProvider`]: enum.Error.html
+pub trait KeyProvider: Debug + Send + Sync {
+    /// Returns URI which identifies the key encryption key.
+    ///
+    /// It is stored in super block **without encryption**, so it can be
+    /// retrieved by [`Repo::key_provider_uri`] before the repository is
+    /// opened. A PKCS#11 URI is recommended, for example
+    /// `pkcs11:token=zbox;object=repo-kek`. Never put any confidential data,
+    /// such as PIN, in it.
+    ///
+    /// [`Repo::key_provider_uri`]: struct.Repo.html#method.key_provider_uri
+    fn uri(&self) -> String;
+
+    /// Wraps the volume key, returns the wrapped key.
+    fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Unwraps the wrapped volume key into `key`.
+    ///
+    /// The `key` buffer is in protected memory and its length is the volume
+    /// key size.
+    fn unwrap_key(&self, wrapped: &[u8], key: &mut [u8]) -> Result<()>;
+}
@@ -5,17 +5,24 @@
 mod address;
 mod allocator;
 mod armor;
+mod key_provider;
 mod storage;
 mod super_block;
 mod volume;
 
+pub use self::address::Span;
 pub use self::allocator::{Allocator, AllocatorRef};
 pub use self::armor::{
     Arm, ArmAccess, Armor, Seq, VolumeArmor, VolumeWalArmor,
 };
-pub use self::storage::StorageRef;
+pub use self::key_provider::KeyProvider;
+pub use self::storage::{
+    register_storage, Health, RetryClass, RetryPolicy, Space, Storable,
+    StorageFactory, StorageRef,
+};
+pub use self::super_block::Credential;
 pub use self::volume::{
-    Info, Reader, Volume, VolumeRef, VolumeWeakRef, Writer,
+    Info, OpenPhase, Reader, Volume, VolumeRef, VolumeWeakRef, Writer,
 };
 
 #[cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
@@ -0,0 +1,513 @@
+use std::fmt::{self, Debug};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use linked_hash_map::LinkedHashMap;
+
+use base::crypto::{Crypto, Key};
+use base::vio;
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{RetryPolicy, Space, Storable};
+use volume::BLK_SIZE;
+
+// default cache size, in MB
+const DEFAULT_CACHE_SIZE: usize = 64;
+
+/// Cache eviction policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    // evict the least recently used block
+    Lru,
+
+    // evict the earliest cached block
+    Fifo,
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lru" => Ok(Policy::Lru),
+            "fifo" => Ok(Policy::Fifo),
+            _ => Err(Error::InvalidUri),
+        }
+    }
+}
+
+/// Cache options
+#[derive(Debug, Clone)]
+pub struct CacheOpts {
+    dir: PathBuf,
+    size: usize, // in MB
+    policy: Policy,
+}
+
+impl Default for CacheOpts {
+    fn default() -> Self {
+        CacheOpts {
+            dir: std::env::temp_dir(),
+            size: DEFAULT_CACHE_SIZE,
+            policy: Policy::Lru,
+        }
+    }
+}
+
+// split cache parameters from underlying storage uri
+// example: gs://bucket?token=xxx&cache_dir=/tmp&cache_size=64mb
+// return: (
+//   underlying storage uri: "gs://bucket?token=xxx",
+//   cache options,
+// )
+pub fn parse_uri(uri: &str) -> Result<(String, CacheOpts)> {
+    let mut opts = CacheOpts::default();
+
+    let idx = match uri.find('?') {
+        Some(idx) => idx,
+        None => return Ok((uri.to_string(), opts)),
+    };
+
+    let mut params = Vec::new();
+    for param in uri[idx + 1..].split('&') {
+        if !param.starts_with("cache_") {
+            params.push(param);
+            continue;
+        }
+
+        let idx = param.find('=').ok_or(Error::InvalidUri)?;
+        let key = &param[..idx];
+        let value = &param[idx + 1..];
+        match key {
+            "cache_dir" => opts.dir = PathBuf::from(value),
+            "cache_size" => {
+                let value = value.to_lowercase();
+                let idx = value.find("mb").ok_or(Error::InvalidUri)?;
+                let size = value[..idx]
+                    .parse::<usize>()
+                    .map_err(|_| Error::InvalidUri)?;
+                if size < 1 {
+                    // cache size must >= 1MB
+                    return Err(Error::InvalidUri);
+                }
+                opts.size = size;
+            }
+            "cache_policy" => opts.policy = value.parse::<Policy>()?,
+            _ => return Err(Error::InvalidUri),
+        }
+    }
+
+    let mut inner_uri = uri[..idx].to_string();
+    if !params.is_empty() {
+        inner_uri.push('?');
+        inner_uri.push_str(&params.join("&"));
+    }
+
+    Ok((inner_uri, opts))
+}
+
+/// Cache Storage
+///
+/// This storage fronts an underlying storage, usually a remote one, with a
+/// local on-disk block cache. Blocks are cached in fixed-size slots of a
+/// cache file, which is created on connect and removed when the storage is
+/// dropped. All the other data are passed through to underlying storage.
+///
+/// The cache is write-through, so underlying storage always has the latest
+/// blocks.
+pub struct CacheStorage {
+    inner: Box<dyn Storable>,
+    opts: CacheOpts,
+    capacity: usize, // in blocks
+    path: PathBuf,   // cache file path
+    file: Option<vio::File>,
+    map: LinkedHashMap<usize, usize>, // block index -> cache slot
+    free_slots: Vec<usize>,
+    next_slot: usize,
+}
+
+impl CacheStorage {
+    pub fn new(inner: Box<dyn Storable>, opts: CacheOpts) -> Self {
+        let capacity = opts.size * 1024 * 1024 / BLK_SIZE;
+        let path = opts
+            .dir
+            .join(format!("zbox_cache_{}", Eid::new().to_string()));
+        CacheStorage {
+            inner,
+            opts,
+            capacity,
+            path,
+            file: None,
+            map: LinkedHashMap::new(),
+            free_slots: Vec::new(),
+            next_slot: 0,
+        }
+    }
+
+    #[inline]
+    fn file(&mut self) -> &mut vio::File {
+        match self.file {
+            Some(ref mut file) => file,
+            None => unreachable!(),
+        }
+    }
+
+    // get cache slot of a block
+    fn get_slot(&mut self, blk_idx: usize) -> Option<usize> {
+        match self.opts.policy {
+            Policy::Lru => self.map.get_refresh(&blk_idx).cloned(),
+            Policy::Fifo => self.map.get(&blk_idx).cloned(),
+        }
+    }
+
+    fn read_slot(&mut self, slot: usize, dst: &mut [u8]) -> Result<()> {
+        let file = self.file();
+        file.seek(SeekFrom::Start((slot * BLK_SIZE) as u64))?;
+        file.read_exact(dst)?;
+        Ok(())
+    }
+
+    // add a block to cache, evict a block if cache is full
+    fn insert(&mut self, blk_idx: usize, blk: &[u8]) -> Result<()> {
+        let slot = match self.map.get(&blk_idx).cloned() {
+            Some(slot) => slot,
+            None => {
+                let slot = if let Some(slot) = self.free_slots.pop() {
+                    slot
+                } else if self.next_slot < self.capacity {
+                    self.next_slot += 1;
+                    self.next_slot - 1
+                } else {
+                    self.map.pop_front().map(|(_, slot)| slot).unwrap()
+                };
+                self.map.insert(blk_idx, slot);
+                slot
+            }
+        };
+
+        let result = {
+            let file = self.file();
+            file.seek(SeekFrom::Start((slot * BLK_SIZE) as u64))
+                .and_then(|_| file.write_all(blk))
+        };
+        result.map_err(|err| {
+            // discard the block if it cannot be cached
+            self.remove(blk_idx);
+            Error::from(err)
+        })
+    }
+
+    fn remove(&mut self, blk_idx: usize) {
+        if let Some(slot) = self.map.remove(&blk_idx) {
+            self.free_slots.push(slot);
+        }
+    }
+
+    fn remove_cache_file(&mut self) {
+        if self.file.take().is_some() {
+            if let Err(err) = vio::remove_file(&self.path) {
+                warn!("remove cache file failed: {}", err);
+            }
+        }
+    }
+}
+
+impl Storable for CacheStorage {
+    #[inline]
+    fn exists(&self) -> Result<bool> {
+        self.inner.exists()
+    }
+
+    #[inline]
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.inner.set_retry_policy(policy);
+    }
+
+    #[inline]
+    fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        self.inner.set_sector_size(size)
+    }
+
+    fn connect(&mut self, force: bool) -> Result<()> {
+        self.inner.connect(force)?;
+        if self.file.is_none() {
+            vio::create_dir_all(&self.opts.dir)?;
+            let file = vio::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.inner.init(crypto, key)
+    }
+
+    #[inline]
+    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
+        self.inner.open(crypto, key, force)
+    }
+
+    #[inline]
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.inner.open_shared(crypto, key)
+    }
+
+    #[inline]
+    fn refresh(&mut self) -> Result<()> {
+        self.inner.refresh()
+    }
+
+    #[inline]
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        self.inner.get_super_block(suffix)
+    }
+
+    #[inline]
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        self.inner.put_super_block(super_blk, suffix)
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.inner.get_wal(id)
+    }
+
+    #[inline]
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        self.inner.put_wal(id, wal)
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.inner.del_wal(id)
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.inner.get_address(id)
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.inner.put_address(id, addr)
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.inner.del_address(id)
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let mut blk_idx = span.begin;
+        let mut read = 0;
+
+        while blk_idx < span.end() {
+            // read block from cache
+            if let Some(slot) = self.get_slot(blk_idx) {
+                self.read_slot(slot, &mut dst[read..read + BLK_SIZE])?;
+                blk_idx += 1;
+                read += BLK_SIZE;
+                continue;
+            }
+
+            // read a run of missing blocks from underlying storage in one
+            // request, then add them to cache
+            let begin = blk_idx;
+            while blk_idx < span.end() && !self.map.contains_key(&blk_idx) {
+                blk_idx += 1;
+            }
+            let miss = Span::new(begin, blk_idx - begin);
+            let buf = &mut dst[read..read + miss.bytes_len()];
+            self.inner.get_blocks(buf, miss)?;
+            for (idx, blk) in miss.into_iter().zip(buf.chunks(BLK_SIZE)) {
+                self.insert(idx, blk)?;
+            }
+            read += miss.bytes_len();
+        }
+
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
+        self.inner.put_blocks(span, blks)?;
+        for (idx, blk) in span.into_iter().zip(blks.chunks(BLK_SIZE)) {
+            self.insert(idx, blk)?;
+        }
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        for blk_idx in span {
+            self.remove(blk_idx);
+        }
+        self.inner.del_blocks(span)
+    }
+
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        // overwrite cached blocks before releasing their slots
+        let mut junk = vec![0u8; BLK_SIZE];
+        for blk_idx in span {
+            if self.map.contains_key(&blk_idx) {
+                Crypto::random_buf(&mut junk);
+                self.insert(blk_idx, &junk)?;
+            }
+            self.remove(blk_idx);
+        }
+        self.inner.shred_blocks(span)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        self.remove_cache_file();
+        self.inner.destroy()
+    }
+
+    #[inline]
+    fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.inner.compact(blk_wmark, progress)
+    }
+
+    #[inline]
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        self.inner.space(used_blocks)
+    }
+}
+
+impl Drop for CacheStorage {
+    fn drop(&mut self) {
+        self.remove_cache_file();
+    }
+}
+
+impl Debug for CacheStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CacheStorage")
+            .field("inner", &self.inner)
+            .field("opts", &self.opts)
+            .field("capacity", &self.capacity)
+            .field("cached", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::path::Path;
+
+    use self::tempdir::TempDir;
+
+    use super::*;
+
+    use base::init_env;
+    #[cfg(feature = "storage-mem")]
+    use volume::storage::mem::MemStorage;
+
+    #[test]
+    fn cache_parse_uri() {
+        let (uri, opts) = parse_uri("mem://foo").unwrap();
+        assert_eq!(uri, "mem://foo");
+        assert_eq!(opts.size, DEFAULT_CACHE_SIZE);
+        assert_eq!(opts.policy, Policy::Lru);
+
+        let (uri, opts) = parse_uri(
+            "gs://bucket?cache_dir=/tmp/foo&token=abc&cache_size=2mb\
+             &cache_policy=fifo&retry=1",
+        )
+        .unwrap();
+        assert_eq!(uri, "gs://bucket?token=abc&retry=1");
+        assert_eq!(opts.dir, Path::new("/tmp/foo"));
+        assert_eq!(opts.size, 2);
+        assert_eq!(opts.policy, Policy::Fifo);
+
+        let (uri, _) = parse_uri("gs://bucket?cache_size=2mb").unwrap();
+        assert_eq!(uri, "gs://bucket");
+
+        assert_eq!(
+            parse_uri("mem://foo?cache_size=0mb").unwrap_err(),
+            Error::InvalidUri
+        );
+        assert_eq!(
+            parse_uri("mem://foo?cache_size=2").unwrap_err(),
+            Error::InvalidUri
+        );
+        assert_eq!(
+            parse_uri("mem://foo?cache_policy=abc").unwrap_err(),
+            Error::InvalidUri
+        );
+        assert_eq!(
+            parse_uri("mem://foo?cache_foo=bar").unwrap_err(),
+            Error::InvalidUri
+        );
+    }
+
+    #[cfg(feature = "storage-mem")]
+    #[test]
+    fn cache_storage() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let opts = CacheOpts {
+            dir: tmpdir.path().to_path_buf(),
+            size: 1,
+            policy: Policy::Lru,
+        };
+        let inner = Box::new(MemStorage::new("cache_storage_test"));
+        let mut cs = CacheStorage::new(inner, opts);
+        cs.connect(false).unwrap();
+        cs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let capacity = cs.capacity;
+        let cnt = capacity + 10;
+        let mut blks = vec![0u8; BLK_SIZE * cnt];
+        for (idx, blk) in blks.chunks_mut(BLK_SIZE).enumerate() {
+            for b in blk.iter_mut() {
+                *b = idx as u8;
+            }
+        }
+        let mut dst = vec![0u8; BLK_SIZE * cnt];
+
+        // write more blocks than cache capacity
+        let span = Span::new(0, cnt);
+        cs.put_blocks(span, &blks).unwrap();
+        assert_eq!(cs.map.len(), capacity);
+        assert!(!cs.map.contains_key(&0));
+        assert!(cs.map.contains_key(&(cnt - 1)));
+
+        // read all blocks back, mixed from cache and underlying storage
+        cs.get_blocks(&mut dst, span).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        assert_eq!(cs.map.len(), capacity);
+
+        // deleted blocks are removed from cache
+        cs.del_blocks(Span::new(cnt - 2, 2)).unwrap();
+        assert!(!cs.map.contains_key(&(cnt - 1)));
+        assert_eq!(
+            cs.get_blocks(&mut dst[..BLK_SIZE], Span::new(cnt - 1, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // cache file is removed when storage is dropped
+        let path = cs.path.clone();
+        assert!(path.exists());
+        drop(cs);
+        assert!(!path.exists());
+    }
+}
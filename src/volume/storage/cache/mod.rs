@@ -0,0 +1,3 @@
+mod cache;
+
+pub use self::cache::{parse_uri, CacheStorage};
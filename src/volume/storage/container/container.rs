@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::table::{
+    Extent, FreeList, Sector, Table, BLKS_PER_SECTOR, SECTOR_SIZE,
+};
+use base::crypto::{Crypto, Key, HASH_SIZE};
+use base::utils;
+use base::vio;
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{Space, Storable};
+use volume::BLK_SIZE;
+
+// container file magic number
+const MAGIC: [u8; 8] = *b"ZBOXCTR1";
+
+// serialized header length: magic + generation + table extent + table hash
+const HEADER_LEN: usize = 8 + 8 + 16 + HASH_SIZE;
+
+// size of each header slot, the two header slots are written alternately
+const HEADER_SIZE: u64 = 4096;
+
+// size of each super block slot
+const SUPER_BLK_SLOT_SIZE: u64 = 2 * BLK_SIZE as u64;
+
+// start offset of super block slots
+const SUPER_BLK_START: u64 = 2 * HEADER_SIZE;
+
+// start offset of data area
+const DATA_START: u64 = SUPER_BLK_START + 2 * SUPER_BLK_SLOT_SIZE;
+
+// container header
+#[derive(Debug, Default)]
+pub(super) struct Header {
+    gen: u64,
+    table: Extent,
+    table_hash: [u8; HASH_SIZE],
+}
+
+impl Header {
+    fn seri(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[..8].copy_from_slice(&MAGIC);
+        buf[8..16].copy_from_slice(&self.gen.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.table.offset.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.table.len.to_le_bytes());
+        buf[32..].copy_from_slice(&self.table_hash);
+        buf
+    }
+
+    fn deseri(buf: &[u8]) -> Option<Self> {
+        if buf[..8] != MAGIC {
+            return None;
+        }
+        let mut n = [0u8; 8];
+        let mut read_u64 = |pos: usize| {
+            n.copy_from_slice(&buf[pos..pos + 8]);
+            u64::from_le_bytes(n)
+        };
+        let gen = read_u64(8);
+        let table = Extent::new(read_u64(16), read_u64(24));
+        let mut table_hash = [0u8; HASH_SIZE];
+        table_hash.copy_from_slice(&buf[32..HEADER_LEN]);
+        Some(Header {
+            gen,
+            table,
+            table_hash,
+        })
+    }
+}
+
+// load the latest valid header and its allocation table, `read_at` reads
+// bytes at the specified offset of container
+pub(super) fn load_table<F>(mut read_at: F) -> Result<(Header, Table)>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<()>,
+{
+    let mut latest: Option<(Header, Table)> = None;
+
+    for slot in 0..2 {
+        let mut buf = [0u8; HEADER_LEN];
+        if read_at(slot * HEADER_SIZE, &mut buf).is_err() {
+            continue;
+        }
+        let header = match Header::deseri(&buf) {
+            Some(header) => header,
+            None => continue,
+        };
+        if let Some((ref curr, _)) = latest {
+            if curr.gen >= header.gen {
+                continue;
+            }
+        }
+
+        // verify and load table
+        let mut table_buf = vec![0u8; header.table.len as usize];
+        if read_at(header.table.offset, &mut table_buf).is_err() {
+            continue;
+        }
+        if Crypto::hash(&table_buf)[..] != header.table_hash[..] {
+            warn!("container table hash mismatch, slot: {}", slot);
+            continue;
+        }
+        let table = match Table::deseri(&table_buf) {
+            Ok(table) => table,
+            Err(_) => continue,
+        };
+
+        latest = Some((header, table));
+    }
+
+    latest.ok_or(Error::Corrupted)
+}
+
+// read super block from its slot, `read_at` reads bytes at the specified
+// offset of container
+pub(super) fn load_super_blk<F>(suffix: u64, mut read_at: F) -> Result<Vec<u8>>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<()>,
+{
+    if suffix > 1 {
+        return Err(Error::InvalidArgument);
+    }
+    let offset = SUPER_BLK_START + suffix * SUPER_BLK_SLOT_SIZE;
+    let mut len = [0u8; 4];
+    read_at(offset, &mut len).map_err(|_| Error::NotFound)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 {
+        return Err(Error::NotFound);
+    }
+    let mut buf = vec![0u8; len];
+    read_at(offset + 4, &mut buf)?;
+    Ok(buf)
+}
+
+// blob type stored in container
+#[derive(Debug, Clone, Copy)]
+enum Blob {
+    Wal,
+    Address,
+}
+
+/// Container Storage
+///
+/// This storage keeps the whole repo in one single file. The file starts
+/// with two header slots and two super block slots, followed by the data
+/// area. Wals and addresses are stored as variable-length extents, while
+/// blocks are grouped in fixed-size sectors. An allocation table maps them
+/// to their extents.
+///
+/// The allocation table is written to a newly allocated extent on flush,
+/// then the header pointing to it is written to the older header slot.
+/// Extents freed after last flush are not reused until the next flush, so
+/// the persisted table always refers to valid data.
+pub struct ContainerStorage {
+    is_attached: bool, // attached to container file
+    path: PathBuf,
+    file: Option<vio::File>,
+    gen: u64,
+    table: Table,
+    table_ext: Extent,
+    free: FreeList,
+    pending: Vec<Extent>, // extents freed since last flush
+    dirty: bool,
+}
+
+impl ContainerStorage {
+    pub fn new(path: &Path) -> Self {
+        ContainerStorage {
+            is_attached: false,
+            path: path.to_path_buf(),
+            file: None,
+            gen: 0,
+            table: Table::default(),
+            table_ext: Extent::default(),
+            free: FreeList::new(DATA_START),
+            pending: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    #[inline]
+    fn file(&mut self) -> &mut vio::File {
+        match self.file {
+            Some(ref mut file) => file,
+            None => unreachable!(),
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let file = self.file();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                Error::Corrupted
+            } else {
+                Error::from(err)
+            }
+        })
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        let file = self.file();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(buf)?;
+        Ok(())
+    }
+
+    // load the latest valid header and its allocation table
+    fn load(&mut self) -> Result<()> {
+        let (header, table) =
+            load_table(|offset, buf| self.read_at(offset, buf))?;
+        let mut used = table.extents();
+        used.push(header.table);
+        self.free = FreeList::from_used(DATA_START, used);
+        self.gen = header.gen;
+        self.table = table;
+        self.table_ext = header.table;
+        self.pending.clear();
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    // persist allocation table and header
+    fn persist(&mut self) -> Result<()> {
+        if !self.dirty {
+            self.file().sync_data()?;
+            return Ok(());
+        }
+
+        // write table to a new extent
+        let table_buf = self.table.seri()?;
+        let table_ext = self.free.alloc(table_buf.len() as u64);
+        self.write_at(table_ext.offset, &table_buf)?;
+        self.file().sync_data()?;
+
+        // write header to the older header slot
+        let header = Header {
+            gen: self.gen + 1,
+            table: table_ext,
+            table_hash: {
+                let mut hash = [0u8; HASH_SIZE];
+                hash.copy_from_slice(&Crypto::hash(&table_buf));
+                hash
+            },
+        };
+        let slot = header.gen % 2;
+        self.write_at(slot * HEADER_SIZE, &header.seri())?;
+        self.file().sync_data()?;
+        self.gen = header.gen;
+
+        // now the old table and pending extents can be reused
+        let old_table_ext = self.table_ext;
+        self.free.free(old_table_ext);
+        for ext in self.pending.drain(..) {
+            self.free.free(ext);
+        }
+        self.table_ext = table_ext;
+        self.dirty = false;
+
+        // truncate unused space at the end of file
+        let end = self.free.end().max(DATA_START);
+        let file = self.file();
+        if file.metadata()?.len() > end {
+            file.set_len(end)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn blob_map(&mut self, blob: Blob) -> &mut HashMap<Eid, Extent> {
+        match blob {
+            Blob::Wal => &mut self.table.wals,
+            Blob::Address => &mut self.table.addrs,
+        }
+    }
+
+    fn get_blob(&mut self, blob: Blob, id: &Eid) -> Result<Vec<u8>> {
+        let ext = *self.blob_map(blob).get(id).ok_or(Error::NotFound)?;
+        let mut buf = vec![0u8; ext.len as usize];
+        self.read_at(ext.offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn put_blob(&mut self, blob: Blob, id: &Eid, data: &[u8]) -> Result<()> {
+        let ext = self.free.alloc(data.len() as u64);
+        self.write_at(ext.offset, data)?;
+        if let Some(old) = self.blob_map(blob).insert(id.clone(), ext) {
+            self.pending.push(old);
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn del_blob(&mut self, blob: Blob, id: &Eid) -> Result<()> {
+        if let Some(old) = self.blob_map(blob).remove(id) {
+            self.pending.push(old);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn lock_repo(&mut self, force: bool) -> Result<()> {
+        if self.table.locked {
+            // repo is locked
+            if force {
+                warn!("Repo was locked, forced to open");
+            } else {
+                return Err(Error::RepoOpened);
+            }
+        }
+        self.table.locked = true;
+        self.dirty = true;
+        self.persist()?;
+        self.is_attached = true;
+        Ok(())
+    }
+}
+
+impl Storable for ContainerStorage {
+    fn exists(&self) -> Result<bool> {
+        match vio::metadata(&self.path) {
+            Ok(_) => Ok(true),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        if self.file.is_none() {
+            utils::ensure_parents_dir(&self.path)?;
+            let file = vio::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&self.path)?;
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+        // reserve header and super block slots
+        self.file().set_len(DATA_START)?;
+        self.table = Table::default();
+        self.lock_repo(false)
+    }
+
+    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+        self.load()?;
+        self.lock_repo(force)
+    }
+
+    #[inline]
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        load_super_blk(suffix, |offset, buf| self.read_at(offset, buf))
+    }
+
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        if suffix > 1 || super_blk.len() as u64 + 4 > SUPER_BLK_SLOT_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let offset = SUPER_BLK_START + suffix * SUPER_BLK_SLOT_SIZE;
+        let mut buf = Vec::with_capacity(4 + super_blk.len());
+        buf.extend_from_slice(&(super_blk.len() as u32).to_le_bytes());
+        buf.extend_from_slice(super_blk);
+        self.write_at(offset, &buf)?;
+        self.file().sync_data()?;
+        Ok(())
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.get_blob(Blob::Wal, id)
+    }
+
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        // wal must be persistent, so persist table immediately
+        self.put_blob(Blob::Wal, id, wal)?;
+        self.persist()
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.del_blob(Blob::Wal, id)
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.get_blob(Blob::Address, id)
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.put_blob(Blob::Address, id, addr)
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.del_blob(Blob::Address, id)
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let mut read = 0;
+        for blk_idx in span {
+            let offset =
+                self.table.blk_offset(blk_idx).ok_or(Error::NotFound)?;
+            self.read_at(offset, &mut dst[read..read + BLK_SIZE])?;
+            read += BLK_SIZE;
+        }
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
+        let mut written = 0;
+        for blk_idx in span {
+            let sec_idx = blk_idx / BLKS_PER_SECTOR;
+            let bit = blk_idx % BLKS_PER_SECTOR;
+
+            // allocate a new sector if it doesn't exist yet
+            if !self.table.sectors.contains_key(&sec_idx) {
+                let ext = self.free.alloc(SECTOR_SIZE);
+                self.table.sectors.insert(
+                    sec_idx,
+                    Sector {
+                        offset: ext.offset,
+                        mask: 0,
+                    },
+                );
+            }
+
+            let offset = {
+                let sec = self.table.sectors.get_mut(&sec_idx).unwrap();
+                sec.mask |= 1 << bit;
+                sec.offset + (bit * BLK_SIZE) as u64
+            };
+            self.write_at(offset, &blks[written..written + BLK_SIZE])?;
+            written += BLK_SIZE;
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        for blk_idx in span {
+            let sec_idx = blk_idx / BLKS_PER_SECTOR;
+            let bit = blk_idx % BLKS_PER_SECTOR;
+            let is_empty = match self.table.sectors.get_mut(&sec_idx) {
+                Some(sec) => {
+                    sec.mask &= !(1 << bit);
+                    sec.mask == 0
+                }
+                None => continue,
+            };
+
+            // release the sector if all its blocks are deleted
+            if is_empty {
+                let sec = self.table.sectors.remove(&sec_idx).unwrap();
+                self.pending.push(Extent::new(sec.offset, SECTOR_SIZE));
+            }
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.persist()
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        self.connect(false)?;
+        if self.load().is_ok() && self.table.locked {
+            // repo is locked
+            warn!("Destroy an opened repo");
+        }
+        self.file.take();
+        self.is_attached = false;
+        vio::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    fn space(&mut self, _used_blocks: usize) -> Result<Space> {
+        let used = vio::metadata(&self.path)?.len();
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let free = utils::disk_free_space(dir)?;
+        Ok(Space {
+            total: free.map(|free| used + free),
+            used,
+        })
+    }
+}
+
+impl Drop for ContainerStorage {
+    fn drop(&mut self) {
+        // release repo lock and ignore the result
+        if self.is_attached {
+            self.table.locked = false;
+            self.dirty = true;
+            let _ = self.persist();
+            self.is_attached = false;
+        }
+    }
+}
+
+impl Debug for ContainerStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ContainerStorage")
+            .field("path", &self.path)
+            .field("gen", &self.gen)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+
+    use super::*;
+
+    use base::init_env;
+
+    #[test]
+    fn container_storage() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let path = tmpdir.path().join("repo.zbox");
+        let mut cs = ContainerStorage::new(&path);
+
+        assert!(!cs.exists().unwrap());
+        cs.connect(false).unwrap();
+        cs.init(Crypto::default(), Key::new_empty()).unwrap();
+        assert!(cs.exists().unwrap());
+
+        let id = Eid::new();
+        let buf = vec![1, 2, 3];
+        let blks = vec![42u8; BLK_SIZE * 3];
+        let mut dst = vec![0u8; BLK_SIZE * 3];
+
+        // super block
+        assert_eq!(cs.get_super_block(0).unwrap_err(), Error::NotFound);
+        cs.put_super_block(&buf, 0).unwrap();
+        let s = cs.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+
+        // wal
+        cs.put_wal(&id, &buf).unwrap();
+        let s = cs.get_wal(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        cs.del_wal(&id).unwrap();
+        assert_eq!(cs.get_wal(&id).unwrap_err(), Error::NotFound);
+
+        // address
+        cs.put_address(&id, &buf).unwrap();
+        let s = cs.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        cs.del_address(&id).unwrap();
+        assert_eq!(cs.get_address(&id).unwrap_err(), Error::NotFound);
+        cs.put_address(&id, &buf).unwrap();
+
+        // block, span across sector boundary
+        let span = Span::new(BLKS_PER_SECTOR - 1, 3);
+        cs.put_blocks(span, &blks).unwrap();
+        cs.get_blocks(&mut dst, span).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        cs.del_blocks(Span::new(BLKS_PER_SECTOR, 2)).unwrap();
+        assert_eq!(cs.get_blocks(&mut dst, span).unwrap_err(), Error::NotFound);
+        assert_eq!(
+            cs.get_blocks(&mut dst[..BLK_SIZE], Span::new(BLKS_PER_SECTOR, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+        cs.flush().unwrap();
+
+        // repo is locked
+        let mut cs2 = ContainerStorage::new(&path);
+        cs2.connect(false).unwrap();
+        assert_eq!(
+            cs2.open(Crypto::default(), Key::new_empty(), false)
+                .unwrap_err(),
+            Error::RepoOpened
+        );
+        drop(cs2);
+
+        // re-open
+        drop(cs);
+        let mut cs = ContainerStorage::new(&path);
+        cs.connect(false).unwrap();
+        cs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+
+        let s = cs.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        let s = cs.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        cs.get_blocks(&mut dst[..BLK_SIZE], Span::new(BLKS_PER_SECTOR - 1, 1))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE], &blks[..BLK_SIZE]);
+        assert_eq!(
+            cs.get_blocks(&mut dst[..BLK_SIZE], Span::new(BLKS_PER_SECTOR, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // destroy
+        cs.destroy().unwrap();
+        assert!(!cs.exists().unwrap());
+    }
+}
@@ -0,0 +1,428 @@
+use std::fmt::{self, Debug};
+use std::thread;
+use std::time::Duration;
+
+use http::header::RANGE;
+use http::StatusCode;
+use reqwest::{Client, RequestBuilder, Response};
+
+use super::container::{load_super_blk, load_table};
+use super::table::Table;
+use base::crypto::{Crypto, Key};
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{RetryClass, RetryPolicy, Storable};
+use volume::BLK_SIZE;
+
+// http request timeout, in seconds
+const TIMEOUT: u64 = 60;
+
+// get retry class of a response status, None if it should not be retried
+#[inline]
+fn retry_class(status: StatusCode) -> Option<RetryClass> {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        Some(RetryClass::Throttled)
+    } else if status.is_server_error() {
+        Some(RetryClass::ServerError)
+    } else {
+        None
+    }
+}
+
+// read response body
+fn read_body(resp: &mut Response) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    resp.copy_to(&mut buf)?;
+    Ok(buf)
+}
+
+/// Http Storage
+///
+/// This is a read-only storage which reads a container file published on
+/// a web server using http range requests. The web server must support
+/// range requests, which most static file hosts and CDNs do.
+///
+/// The container file is never locked, so it can be opened by any number
+/// of clients at the same time. All write operations will fail with
+/// `Error::ReadOnly`.
+pub struct HttpStorage {
+    url: String,
+    client: Client,
+    retry: RetryPolicy,
+    table: Option<Table>,
+}
+
+impl HttpStorage {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT))
+            .build()?;
+        Ok(HttpStorage {
+            url: url.to_string(),
+            client,
+            retry: RetryPolicy::default(),
+            table: None,
+        })
+    }
+
+    #[inline]
+    fn table(&self) -> &Table {
+        match self.table {
+            Some(ref table) => table,
+            None => unreachable!(),
+        }
+    }
+
+    // send request, retry with exponential backoff as per retry policy
+    fn send<F>(&self, build: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match build(&self.client).send() {
+                Ok(resp) => {
+                    let status = resp.status();
+                    match retry_class(status) {
+                        Some(class)
+                            if self.retry.should_retry(class, attempt) =>
+                        {
+                            debug!("http request got status {}, retry", status);
+                        }
+                        _ => return Ok(resp),
+                    }
+                }
+                Err(err) => {
+                    if !self.retry.should_retry(RetryClass::Transport, attempt)
+                    {
+                        return Err(Error::from(err));
+                    }
+                    debug!("http request failed: {}, retry", err);
+                }
+            }
+
+            thread::sleep(self.retry.backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    // read bytes at the specified offset of container file
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let range =
+            format!("bytes={}-{}", offset, offset + buf.len() as u64 - 1);
+        let mut resp = self
+            .send(|client| client.get(&self.url).header(RANGE, &range[..]))?;
+        match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let body = read_body(&mut resp)?;
+                if body.len() != buf.len() {
+                    return Err(Error::Corrupted);
+                }
+                buf.copy_from_slice(&body);
+                Ok(())
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => Err(Error::Corrupted),
+            StatusCode::NOT_FOUND => Err(Error::NotFound),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+}
+
+impl Storable for HttpStorage {
+    fn exists(&self) -> Result<bool> {
+        let resp = self.send(|client| client.head(&self.url))?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+
+    #[inline]
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    #[inline]
+    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn open(&mut self, _crypto: Crypto, _key: Key, _force: bool) -> Result<()> {
+        // read-only storage doesn't need to lock the repo
+        let (_, table) = load_table(|offset, buf| self.read_at(offset, buf))?;
+        self.table = Some(table);
+        Ok(())
+    }
+
+    #[inline]
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        load_super_blk(suffix, |offset, buf| self.read_at(offset, buf))
+    }
+
+    #[inline]
+    fn put_super_block(
+        &mut self,
+        _super_blk: &[u8],
+        _suffix: u64,
+    ) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        let ext = *self.table().wals.get(id).ok_or(Error::NotFound)?;
+        let mut buf = vec![0u8; ext.len as usize];
+        self.read_at(ext.offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[inline]
+    fn put_wal(&mut self, _id: &Eid, _wal: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    #[inline]
+    fn del_wal(&mut self, _id: &Eid) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        let ext = *self.table().addrs.get(id).ok_or(Error::NotFound)?;
+        let mut buf = vec![0u8; ext.len as usize];
+        self.read_at(ext.offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[inline]
+    fn put_address(&mut self, _id: &Eid, _addr: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    #[inline]
+    fn del_address(&mut self, _id: &Eid) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        // blocks adjacent in container file are read in one request,
+        // each run is (offset, block count)
+        let mut runs: Vec<(u64, usize)> = Vec::new();
+        for blk_idx in span {
+            let offset =
+                self.table().blk_offset(blk_idx).ok_or(Error::NotFound)?;
+            match runs.last_mut() {
+                Some(run) if run.0 + (run.1 * BLK_SIZE) as u64 == offset => {
+                    run.1 += 1;
+                }
+                _ => runs.push((offset, 1)),
+            }
+        }
+
+        let mut read = 0;
+        for (offset, cnt) in runs {
+            let len = cnt * BLK_SIZE;
+            self.read_at(offset, &mut dst[read..read + len])?;
+            read += len;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn put_blocks(&mut self, _span: Span, _blks: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    #[inline]
+    fn del_blocks(&mut self, _span: Span) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        // nothing to flush for read-only storage
+        Ok(())
+    }
+
+    #[inline]
+    fn destroy(&mut self) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+}
+
+impl Debug for HttpStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpStorage")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    use self::tempdir::TempDir;
+
+    use super::super::table::BLKS_PER_SECTOR;
+    use super::super::ContainerStorage;
+    use super::*;
+
+    use base::init_env;
+
+    // serve a file at path '/repo' with range request support, one request
+    // per connection
+    fn serve(path: PathBuf) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                // parse request line and range header
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let req: Vec<&str> = line.split(' ').collect();
+                let mut range: Option<(usize, usize)> = None;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end().to_ascii_lowercase();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if header.starts_with("range: bytes=") {
+                        let mut parts = header[13..].split('-');
+                        let start = parts.next().unwrap().parse().unwrap();
+                        let end = parts.next().unwrap().parse().unwrap();
+                        range = Some((start, end));
+                    }
+                }
+
+                let data = fs::read(&path).unwrap();
+                let (status, body) = if req[1] != "/repo" {
+                    ("404 Not Found", &data[..0])
+                } else {
+                    match range {
+                        Some((start, _)) if start >= data.len() => {
+                            ("416 Range Not Satisfiable", &data[..0])
+                        }
+                        Some((start, end)) => (
+                            "206 Partial Content",
+                            &data[start..=end.min(data.len() - 1)],
+                        ),
+                        None => ("200 OK", &data[..]),
+                    }
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                if req[0] != "HEAD" {
+                    stream.write_all(body).unwrap();
+                }
+            }
+        });
+
+        format!("http://{}/repo", addr)
+    }
+
+    #[test]
+    fn http_storage() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let path = tmpdir.path().join("repo.zbox");
+
+        let id = Eid::new();
+        let buf = vec![1, 2, 3];
+        let mut blks = vec![42u8; BLK_SIZE * 3];
+        blks[BLK_SIZE * 2..].copy_from_slice(&[43u8; BLK_SIZE]);
+        let mut dst = vec![0u8; BLK_SIZE * 3];
+
+        // create container and publish it
+        {
+            let mut cs = ContainerStorage::new(&path);
+            cs.connect(false).unwrap();
+            cs.init(Crypto::default(), Key::new_empty()).unwrap();
+            cs.put_super_block(&buf, 0).unwrap();
+            cs.put_super_block(&buf, 1).unwrap();
+            cs.put_wal(&id, &buf).unwrap();
+            cs.put_address(&id, &buf).unwrap();
+            cs.put_blocks(Span::new(BLKS_PER_SECTOR - 1, 3), &blks)
+                .unwrap();
+            cs.flush().unwrap();
+        }
+        let url = serve(path);
+
+        let hs = HttpStorage::new(&format!("{}.missing", url)).unwrap();
+        assert!(!hs.exists().unwrap());
+        let mut hs = HttpStorage::new(&url).unwrap();
+        assert!(hs.exists().unwrap());
+        hs.connect(false).unwrap();
+
+        // super block can be read before open
+        let s = hs.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        let s = hs.get_super_block(1).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+
+        hs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+
+        // wal and address
+        let s = hs.get_wal(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        let s = hs.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        assert_eq!(hs.get_wal(&Eid::new()).unwrap_err(), Error::NotFound);
+
+        // blocks across sector boundary
+        hs.get_blocks(&mut dst, Span::new(BLKS_PER_SECTOR - 1, 3))
+            .unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        hs.get_blocks(&mut dst[..BLK_SIZE], Span::new(BLKS_PER_SECTOR + 1, 1))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE], &blks[BLK_SIZE * 2..]);
+        assert_eq!(
+            hs.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // health check reports not writable
+        let health = hs.health_check().unwrap();
+        assert!(health.write_latency.is_none());
+
+        // write is not allowed
+        assert_eq!(hs.put_wal(&id, &buf).unwrap_err(), Error::ReadOnly);
+        assert_eq!(
+            hs.put_blocks(Span::new(0, 1), &blks[..BLK_SIZE])
+                .unwrap_err(),
+            Error::ReadOnly
+        );
+        assert_eq!(hs.destroy().unwrap_err(), Error::ReadOnly);
+        hs.flush().unwrap();
+    }
+}
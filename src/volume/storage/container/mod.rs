@@ -0,0 +1,10 @@
+mod container;
+mod table;
+
+#[cfg(feature = "storage-http")]
+mod http;
+
+pub use self::container::ContainerStorage;
+
+#[cfg(feature = "storage-http")]
+pub use self::http::HttpStorage;
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use error::Result;
+use trans::Eid;
+use volume::BLK_SIZE;
+
+// number of blocks in a block sector
+pub const BLKS_PER_SECTOR: usize = 64;
+
+// block sector size, in bytes
+pub const SECTOR_SIZE: u64 = (BLKS_PER_SECTOR * BLK_SIZE) as u64;
+
+/// Extent of bytes in container file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Extent {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Extent {
+    #[inline]
+    pub fn new(offset: u64, len: u64) -> Self {
+        Extent { offset, len }
+    }
+
+    #[inline]
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Block sector
+///
+/// A block sector is a contiguous extent can hold `BLKS_PER_SECTOR` blocks,
+/// the mask indicates which blocks are present in the sector.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+pub struct Sector {
+    pub offset: u64,
+    pub mask: u64,
+}
+
+/// Allocation table
+///
+/// The table maps wals, addresses and block sectors to their extents in the
+/// container file. It is persisted as a whole on flush.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Table {
+    pub locked: bool,
+    pub wals: HashMap<Eid, Extent>,
+    pub addrs: HashMap<Eid, Extent>,
+    pub sectors: BTreeMap<usize, Sector>,
+}
+
+impl Table {
+    pub fn seri(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.serialize(&mut Serializer::new(&mut buf))?;
+        Ok(buf)
+    }
+
+    pub fn deseri(buf: &[u8]) -> Result<Self> {
+        let mut de = Deserializer::new(buf);
+        let table: Table = Deserialize::deserialize(&mut de)?;
+        Ok(table)
+    }
+
+    // offset of a block in container file, None if it is not present
+    pub fn blk_offset(&self, blk_idx: usize) -> Option<u64> {
+        let bit = blk_idx % BLKS_PER_SECTOR;
+        match self.sectors.get(&(blk_idx / BLKS_PER_SECTOR)) {
+            Some(sec) if sec.mask & (1 << bit) != 0 => {
+                Some(sec.offset + (bit * BLK_SIZE) as u64)
+            }
+            _ => None,
+        }
+    }
+
+    // all extents referred by this table
+    pub fn extents(&self) -> Vec<Extent> {
+        self.wals
+            .values()
+            .chain(self.addrs.values())
+            .cloned()
+            .chain(
+                self.sectors
+                    .values()
+                    .map(|sec| Extent::new(sec.offset, SECTOR_SIZE)),
+            )
+            .collect()
+    }
+}
+
+/// Free space list
+///
+/// Free extents are indexed by offset and adjacent extents are always
+/// merged. Space is allocated using first fit, or appended to the end of
+/// file if no free extent is large enough.
+#[derive(Debug, Default)]
+pub struct FreeList {
+    free: BTreeMap<u64, u64>,
+    end: u64, // end of used space
+}
+
+impl FreeList {
+    // create an empty free list, allocation starts from `start` offset
+    pub fn new(start: u64) -> Self {
+        FreeList {
+            free: BTreeMap::new(),
+            end: start,
+        }
+    }
+
+    // build free list from the used extents
+    pub fn from_used(start: u64, mut used: Vec<Extent>) -> Self {
+        let mut list = FreeList::new(start);
+        used.sort_by_key(|ext| ext.offset);
+        for ext in used {
+            if ext.offset > list.end {
+                list.free.insert(list.end, ext.offset - list.end);
+            }
+            list.end = list.end.max(ext.end());
+        }
+        list
+    }
+
+    #[inline]
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn alloc(&mut self, len: u64) -> Extent {
+        let found = self
+            .free
+            .iter()
+            .find(|&(_, &free_len)| free_len >= len)
+            .map(|(&offset, &free_len)| (offset, free_len));
+
+        match found {
+            Some((offset, free_len)) => {
+                self.free.remove(&offset);
+                if free_len > len {
+                    self.free.insert(offset + len, free_len - len);
+                }
+                Extent::new(offset, len)
+            }
+            None => {
+                let ext = Extent::new(self.end, len);
+                self.end += len;
+                ext
+            }
+        }
+    }
+
+    pub fn free(&mut self, ext: Extent) {
+        if ext.len == 0 {
+            return;
+        }
+
+        let mut offset = ext.offset;
+        let mut len = ext.len;
+
+        // merge with previous free extent
+        let prev = self.free.range(..offset).next_back().map(|(&o, &l)| (o, l));
+        if let Some((prev_offset, prev_len)) = prev {
+            if prev_offset + prev_len == offset {
+                self.free.remove(&prev_offset);
+                offset = prev_offset;
+                len += prev_len;
+            }
+        }
+
+        // merge with next free extent
+        if let Some(next_len) = self.free.remove(&(offset + len)) {
+            len += next_len;
+        }
+
+        if offset + len == self.end {
+            // shrink used space if the free extent is at the end
+            self.end = offset;
+        } else {
+            self.free.insert(offset, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_list() {
+        let mut list = FreeList::new(100);
+        let a = list.alloc(10);
+        let b = list.alloc(20);
+        let c = list.alloc(30);
+        assert_eq!(a, Extent::new(100, 10));
+        assert_eq!(b, Extent::new(110, 20));
+        assert_eq!(c, Extent::new(130, 30));
+        assert_eq!(list.end(), 160);
+
+        // first fit reuses freed space
+        list.free(a);
+        assert_eq!(list.alloc(5), Extent::new(100, 5));
+        assert_eq!(list.alloc(10), Extent::new(160, 10));
+
+        // adjacent free extents are merged and the end is shrunk
+        list.free(b);
+        assert_eq!(list.alloc(25), Extent::new(105, 25));
+        list.free(Extent::new(160, 10));
+        list.free(c);
+        assert_eq!(list.end(), 130);
+
+        // rebuild from used extents
+        let list = FreeList::from_used(
+            100,
+            vec![Extent::new(150, 10), Extent::new(110, 20)],
+        );
+        assert_eq!(list.end(), 160);
+        assert_eq!(list.free.get(&100), Some(&10));
+        assert_eq!(list.free.get(&130), Some(&20));
+    }
+}
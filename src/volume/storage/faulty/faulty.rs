@@ -7,7 +7,7 @@ use trans::Eid;
 use volume::address::Span;
 use volume::storage::faulty_ctl::Controller;
 use volume::storage::mem::MemStorage;
-use volume::storage::Storable;
+use volume::storage::{Space, Storable};
 
 /// Faulty Storage
 ///
@@ -49,6 +49,18 @@ impl Storable for FaultyStorage {
         self.inner.open(crypto, key, force)
     }
 
+    #[inline]
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.ctlr.make_random_error()?;
+        self.inner.open_shared(crypto, key)
+    }
+
+    #[inline]
+    fn refresh(&mut self) -> Result<()> {
+        self.ctlr.make_random_error()?;
+        self.inner.refresh()
+    }
+
     #[inline]
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         self.ctlr.make_random_error()?;
@@ -115,6 +127,12 @@ impl Storable for FaultyStorage {
         self.inner.del_blocks(span)
     }
 
+    #[inline]
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        self.ctlr.make_random_error()?;
+        self.inner.shred_blocks(span)
+    }
+
     #[inline]
     fn flush(&mut self) -> Result<()> {
         self.ctlr.make_random_error()?;
@@ -125,6 +143,22 @@ impl Storable for FaultyStorage {
     fn destroy(&mut self) -> Result<()> {
         unimplemented!()
     }
+
+    #[inline]
+    fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.ctlr.make_random_error()?;
+        self.inner.compact(blk_wmark, progress)
+    }
+
+    #[inline]
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        self.ctlr.make_random_error()?;
+        self.inner.space(used_blocks)
+    }
 }
 
 impl Debug for FaultyStorage {
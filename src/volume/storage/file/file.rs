@@ -2,6 +2,8 @@ use std::fmt::{self, Debug};
 use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 
+use fs2::{lock_contended_error, FileExt};
+
 use super::file_armor::FileArmor;
 use super::sector::SectorMgr;
 use base::crypto::{Crypto, Key};
@@ -11,21 +13,38 @@ use error::{Error, Result};
 use trans::Eid;
 use volume::address::Span;
 use volume::storage::index_mgr::{IndexMgr, Lsmt, MemTab, Tab};
-use volume::storage::Storable;
+use volume::storage::{Space, Storable};
+use volume::BLK_SIZE;
 
 /// File Storage
+///
+/// Sector, index and wal files are named by their ids and fanned out into
+/// two levels of sub-directories, for example `data/ab/cd/abcd...`, so no
+/// directory holds too many entries even for large repos.
+///
+/// Readers hold a shared lock on the readers lock file while they are open.
+/// Writer only reclaims space, such as deleting blocks, shrinking sectors and
+/// removing index tabs, when it can lock that file exclusively. Otherwise the
+/// space is retired and reclaimed after all readers are closed.
 pub struct FileStorage {
     is_attached: bool, // attached to underlying os file system
     base: PathBuf,
     wal_base: PathBuf,
     idx_mgr: IndexMgr,
     sec_mgr: SectorMgr,
+
+    // readers lock file, shared by readers and only locked exclusively by
+    // writer while it is reclaiming space
+    readers_lock: Option<vio::File>,
 }
 
 impl FileStorage {
     // repo lock file name
     const REPO_LOCK_FILE_NAME: &'static str = ".repo_lock";
 
+    // readers lock file name
+    const READERS_LOCK_FILE_NAME: &'static str = ".repo_readers";
+
     // super block file name
     const SUPER_BLK_FILE_NAME: &'static str = "super_blk";
 
@@ -52,6 +71,7 @@ impl FileStorage {
             wal_base: base.join(Self::WAL_DIR),
             idx_mgr,
             sec_mgr: SectorMgr::new(&base.join(Self::DATA_DIR)),
+            readers_lock: None,
         }
     }
 
@@ -73,6 +93,11 @@ impl FileStorage {
         self.base.join(Self::REPO_LOCK_FILE_NAME)
     }
 
+    #[inline]
+    fn readers_lock_path(&self) -> PathBuf {
+        self.base.join(Self::READERS_LOCK_FILE_NAME)
+    }
+
     #[inline]
     fn index_dir(&self) -> PathBuf {
         self.base.join(Self::INDEX_DIR)
@@ -105,8 +130,80 @@ impl FileStorage {
             .create(true)
             .open(&lock_path)?;
         self.is_attached = true;
+
+        // readers lock file is kept, so readers don't need to create it
+        let readers_lock = vio::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.readers_lock_path())?;
+        self.readers_lock = Some(readers_lock);
+
         Ok(())
     }
+
+    // take a shared lock on readers lock file, which doesn't conflict with
+    // the exclusive repo lock held by writer but prevents writer from
+    // reclaiming space
+    fn lock_repo_shared(&mut self) -> Result<()> {
+        let path = self.readers_lock_path();
+        let readers_lock = match vio::OpenOptions::new().read(true).open(&path)
+        {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                // repo was not opened by writer since readers lock file
+                // was introduced
+                vio::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)?
+            }
+            Err(err) => return Err(Error::from(err)),
+        };
+        FileExt::lock_shared(&readers_lock)?;
+        self.readers_lock = Some(readers_lock);
+        Ok(())
+    }
+
+    // run an operation which may reclaim space, readers are locked out while
+    // it is running, the operation is given false if there are readers open
+    // and it must not reclaim any space then
+    fn reclaim<F>(&mut self, oper: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, bool) -> Result<()>,
+    {
+        let no_readers = match self.readers_lock {
+            Some(ref file) => match FileExt::try_lock_exclusive(file) {
+                Ok(_) => true,
+                Err(ref err)
+                    if err.raw_os_error()
+                        == lock_contended_error().raw_os_error() =>
+                {
+                    false
+                }
+                Err(err) => return Err(Error::from(err)),
+            },
+            None => true,
+        };
+
+        let result = oper(self, no_readers);
+
+        if no_readers {
+            if let Some(ref file) = self.readers_lock {
+                let _ = FileExt::unlock(file);
+            }
+        }
+
+        result
+    }
+
+    // reclaim space retired while there were readers
+    fn purge_retired(&mut self) -> Result<()> {
+        self.idx_mgr.purge_retired()?;
+        self.sec_mgr.purge_retired()
+    }
 }
 
 impl Storable for FileStorage {
@@ -119,6 +216,11 @@ impl Storable for FileStorage {
         }
     }
 
+    #[inline]
+    fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        self.sec_mgr.set_sector_size(size)
+    }
+
     #[inline]
     fn connect(&mut self, _force: bool) -> Result<()> {
         Ok(())
@@ -142,9 +244,26 @@ impl Storable for FileStorage {
     fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
         self.set_crypto_ctx(crypto, key.clone());
         self.idx_mgr.open()?;
+        self.sec_mgr.open()?;
         self.lock_repo(force)
     }
 
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        // take the lock first, so no space can be reclaimed after index
+        // is loaded
+        self.set_crypto_ctx(crypto, key);
+        self.lock_repo_shared()?;
+        self.idx_mgr.open()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        // index is flushed by writer after each transaction, and sectors may
+        // have been written since they were loaded
+        self.idx_mgr.open()?;
+        self.sec_mgr.clear_cache();
+        Ok(())
+    }
+
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         let path = self.super_block_path(suffix);
         let mut buf = Vec::new();
@@ -219,19 +338,66 @@ impl Storable for FileStorage {
         self.sec_mgr.read_blocks(dst, span)
     }
 
-    #[inline]
     fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
-        self.sec_mgr.write_blocks(span, blks)
+        // finished sector is shrunk only when there are no readers
+        self.reclaim(|fs, no_readers| {
+            fs.sec_mgr.write_blocks(span, blks, no_readers)
+        })
     }
 
-    #[inline]
     fn del_blocks(&mut self, span: Span) -> Result<()> {
-        self.sec_mgr.del_blocks(span)
+        self.reclaim(|fs, no_readers| {
+            if no_readers {
+                fs.sec_mgr.del_blocks(span)
+            } else {
+                fs.sec_mgr.retire_blocks(span, false);
+                Ok(())
+            }
+        })
+    }
+
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        self.reclaim(|fs, no_readers| {
+            if no_readers {
+                fs.sec_mgr.shred_blocks(span)?;
+                fs.sec_mgr.del_blocks(span)
+            } else {
+                fs.sec_mgr.retire_blocks(span, true);
+                Ok(())
+            }
+        })
+    }
+
+    fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.reclaim(|fs, no_readers| {
+            if !no_readers {
+                warn!("Repo has readers open, compaction is deferred");
+                return Ok(());
+            }
+            fs.purge_retired()?;
+            fs.sec_mgr.compact(blk_wmark, progress)
+        })
     }
 
-    #[inline]
     fn flush(&mut self) -> Result<()> {
-        self.idx_mgr.flush()
+        self.idx_mgr.flush()?;
+        self.sec_mgr.flush()?;
+
+        // reclaim retired space if all readers are closed
+        if self.idx_mgr.has_retired() || self.sec_mgr.has_retired() {
+            self.reclaim(|fs, no_readers| {
+                if no_readers {
+                    fs.purge_retired()
+                } else {
+                    Ok(())
+                }
+            })?;
+        }
+        Ok(())
     }
 
     #[inline]
@@ -242,6 +408,17 @@ impl Storable for FileStorage {
         vio::remove_dir_all(&self.base)?;
         Ok(())
     }
+
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        // used space is estimated from allocated blocks, walking the whole
+        // repo directory would be too slow for large repos
+        let used = (used_blocks * BLK_SIZE) as u64;
+        let free = utils::disk_free_space(&self.base)?;
+        Ok(Space {
+            total: free.map(|free| used + free),
+            used,
+        })
+    }
 }
 
 impl Drop for FileStorage {
@@ -275,6 +452,7 @@ mod tests {
     use base::init_env;
     use base::utils::speed_str;
     use error::Error;
+    use volume::storage::file::sector::{BLKS_PER_SECTOR, SECTOR_SIZE};
     use volume::BLK_SIZE;
 
     fn setup() -> (PathBuf, TempDir) {
@@ -463,6 +641,263 @@ mod tests {
         }
     }
 
+    // collect sizes of all files in a directory recursively
+    fn file_sizes(path: &Path, sizes: &mut Vec<u64>) {
+        for entry in fs::read_dir(path).unwrap() {
+            let entry = entry.unwrap();
+            let md = entry.metadata().unwrap();
+            if md.is_dir() {
+                file_sizes(&entry.path(), sizes);
+            } else {
+                sizes.push(md.len());
+            }
+        }
+    }
+
+    #[test]
+    fn data_file_size() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        // write more than 2 sectors of blocks, data should be spanned across
+        // sector files and no file can exceed sector size, so it is safe to
+        // put repo on file systems with file size limit, such as FAT32
+        let blk_cnt = BLKS_PER_SECTOR * 2 + 4;
+        let blks = vec![42u8; BLK_SIZE * blk_cnt];
+        fs.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+        fs.flush().unwrap();
+
+        let mut sizes = Vec::new();
+        file_sizes(&dir, &mut sizes);
+        assert!(sizes.iter().all(|size| *size <= SECTOR_SIZE as u64));
+        assert_eq!(
+            sizes
+                .iter()
+                .filter(|size| **size == SECTOR_SIZE as u64)
+                .count(),
+            2
+        );
+
+        let mut dst = vec![0u8; BLK_SIZE * blk_cnt];
+        fs.get_blocks(&mut dst, Span::new(0, blk_cnt)).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+    }
+
+    #[test]
+    fn custom_sector_size() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        for size in &[BLK_SIZE * 63, BLK_SIZE * 96, SECTOR_SIZE * 16] {
+            assert_eq!(
+                fs.set_sector_size(*size).unwrap_err(),
+                Error::InvalidArgument
+            );
+        }
+        let blks_per_sector = 64;
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blk_cnt = blks_per_sector * 2 + 4;
+        let blks = vec![42u8; BLK_SIZE * blk_cnt];
+        fs.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+        fs.flush().unwrap();
+
+        let mut sizes = Vec::new();
+        file_sizes(&dir.join(FileStorage::DATA_DIR), &mut sizes);
+        let sec_size = (BLK_SIZE * blks_per_sector) as u64;
+        assert_eq!(sizes.iter().filter(|size| **size == sec_size).count(), 2);
+
+        // re-open storage with same sector size
+        drop(fs);
+        let mut fs = FileStorage::new(&dir);
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        let mut dst = vec![0u8; BLK_SIZE * blk_cnt];
+        fs.get_blocks(&mut dst, Span::new(0, blk_cnt)).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        fs.del_blocks(Span::new(0, blks_per_sector)).unwrap();
+        assert_eq!(
+            fs.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+    }
+
+    #[test]
+    fn compact_sectors() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        let blks_per_sector = 64;
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blk_cnt = blks_per_sector * 2 + 4;
+        let blks = vec![42u8; BLK_SIZE * blk_cnt];
+        fs.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+        fs.flush().unwrap();
+
+        // total size of sector data files
+        fn data_size(path: &Path) -> u64 {
+            let mut size = 0;
+            for entry in fs::read_dir(path).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    size += data_size(&path);
+                } else if path.extension() == Some("data".as_ref()) {
+                    size += path.metadata().unwrap().len();
+                }
+            }
+            size
+        }
+        let data_dir = dir.join(FileStorage::DATA_DIR);
+        let data_size = || data_size(&data_dir);
+
+        // deleted blocks are not enough to shrink the sector on deletion,
+        // but compaction should shrink it
+        fs.del_blocks(Span::new(0, 40)).unwrap();
+        let size = data_size();
+        fs.compact(blk_cnt, &mut |_, _| {}).unwrap();
+        assert_eq!(size - data_size(), 40 * BLK_SIZE as u64);
+
+        // deleting all blocks in unfinished sector should keep it, and
+        // compaction should not remove it either
+        fs.del_blocks(Span::new(blks_per_sector * 2, 4)).unwrap();
+        let size = data_size();
+        fs.compact(blk_cnt, &mut |_, _| {}).unwrap();
+        assert_eq!(size, data_size());
+
+        // remaining blocks should still be there after re-open
+        drop(fs);
+        let mut fs = FileStorage::new(&dir);
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        let len = blks_per_sector * 2 - 40;
+        let mut dst = vec![0u8; BLK_SIZE * len];
+        fs.get_blocks(&mut dst, Span::new(40, len)).unwrap();
+        assert_eq!(&dst[..], &blks[..BLK_SIZE * len]);
+    }
+
+    #[test]
+    fn shred_blocks() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        fs.set_sector_size(BLK_SIZE * 64).unwrap();
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blks = vec![42u8; BLK_SIZE * 8];
+        fs.put_blocks(Span::new(0, 8), &blks).unwrap();
+        fs.shred_blocks(Span::new(2, 2)).unwrap();
+        fs.flush().unwrap();
+
+        // shredded blocks are deleted, others are intact
+        let mut dst = vec![0u8; BLK_SIZE * 2];
+        assert_eq!(
+            fs.get_blocks(&mut dst, Span::new(2, 2)).unwrap_err(),
+            Error::NotFound
+        );
+        fs.get_blocks(&mut dst, Span::new(0, 2)).unwrap();
+        assert_eq!(&dst[..], &blks[..BLK_SIZE * 2]);
+
+        // shredded blocks are overwritten in sector data file
+        fn find_data(path: &Path) -> Option<PathBuf> {
+            for entry in fs::read_dir(path).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    if let Some(found) = find_data(&path) {
+                        return Some(found);
+                    }
+                } else if path.extension() == Some("data".as_ref()) {
+                    return Some(path);
+                }
+            }
+            None
+        }
+        let data_path = find_data(&dir.join(FileStorage::DATA_DIR)).unwrap();
+        let data = fs::read(&data_path).unwrap();
+        assert_eq!(&data[..BLK_SIZE * 2], &blks[..BLK_SIZE * 2]);
+        assert_ne!(&data[BLK_SIZE * 2..BLK_SIZE * 4], &blks[..BLK_SIZE * 2]);
+        assert_eq!(&data[BLK_SIZE * 4..BLK_SIZE * 8], &blks[..BLK_SIZE * 4]);
+    }
+
+    #[test]
+    fn readers_lock() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        let blks_per_sector = 64;
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blk_cnt = blks_per_sector * 2;
+        let blks = vec![42u8; BLK_SIZE * blk_cnt];
+        fs.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+        fs.flush().unwrap();
+
+        // space is not reclaimed while a reader is open
+        let mut reader = FileStorage::new(&dir);
+        reader.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        reader
+            .open_shared(Crypto::default(), Key::new_empty())
+            .unwrap();
+        fs.del_blocks(Span::new(0, blks_per_sector)).unwrap();
+        fs.shred_blocks(Span::new(blks_per_sector, 2)).unwrap();
+        fs.flush().unwrap();
+        fs.compact(blk_cnt, &mut |_, _| {}).unwrap();
+        reader.refresh().unwrap();
+        let mut dst = vec![0u8; BLK_SIZE * blk_cnt];
+        reader.get_blocks(&mut dst, Span::new(0, blk_cnt)).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+
+        // retired space is reclaimed after reader is closed, even if writer
+        // is re-opened
+        drop(reader);
+        drop(fs);
+        let mut fs = FileStorage::new(&dir);
+        fs.set_sector_size(BLK_SIZE * blks_per_sector).unwrap();
+        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        fs.flush().unwrap();
+        let mut dst = vec![0u8; BLK_SIZE];
+        for idx in &[0, blks_per_sector] {
+            assert_eq!(
+                fs.get_blocks(&mut dst, Span::new(*idx, 1)).unwrap_err(),
+                Error::NotFound
+            );
+        }
+        fs.get_blocks(&mut dst, Span::new(blks_per_sector + 2, 1))
+            .unwrap();
+        assert_eq!(&dst[..], &blks[..BLK_SIZE]);
+    }
+
+    #[test]
+    fn sector_fan_out() {
+        let (dir, _tmpdir) = setup();
+        let mut fs = FileStorage::new(&dir);
+        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blk_cnt = BLKS_PER_SECTOR * 3;
+        let blks = vec![42u8; BLK_SIZE * blk_cnt];
+        fs.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+
+        // sector files must sit at the 3rd level under data dir
+        fn check_level(path: &Path, level: usize, files: &mut usize) {
+            for entry in fs::read_dir(path).unwrap() {
+                let entry = entry.unwrap();
+                let name = entry.file_name().into_string().unwrap();
+                if level < 2 {
+                    assert!(entry.metadata().unwrap().is_dir());
+                    assert_eq!(name.len(), 2);
+                    check_level(&entry.path(), level + 1, files);
+                } else {
+                    assert!(entry.metadata().unwrap().is_file());
+                    *files += 1;
+                }
+            }
+        }
+        let mut files = 0;
+        check_level(&dir.join(FileStorage::DATA_DIR), 0, &mut files);
+        assert!(files >= 3);
+    }
+
     #[test]
     fn index_manager() {
         let (dir, _tmpdir) = setup();
@@ -16,12 +16,16 @@ use volume::address::Span;
 use volume::storage::index_mgr::Accessor;
 use volume::{Arm, ArmAccess, Armor, Seq, BLK_SIZE};
 
-// how many blocks in a sector, must be 2^n and less than u16::MAX
+// default number of blocks in a sector, must be 2^n and less than u16::MAX
 pub const BLKS_PER_SECTOR: usize = 4 * 1024;
 
-// sector size, in bytes
+// default sector size, in bytes
 pub const SECTOR_SIZE: usize = BLK_SIZE * BLKS_PER_SECTOR;
 
+// min and max number of blocks in a sector
+pub const MIN_BLKS_PER_SECTOR: usize = 64;
+pub const MAX_BLKS_PER_SECTOR: usize = 32 * 1024;
+
 // block deletion mark
 const BLK_DELETE_MARK: u16 = u16::MAX;
 
@@ -46,13 +50,13 @@ struct Sector {
     // sector actual size in bytes, including deleted blocks
     actual_size: usize,
 
-    // block offset map, length is BLKS_PER_SECTOR, u16::MAX means deleted
+    // block offset map, length is blocks per sector, u16::MAX means deleted
     blk_map: Vec<u16>,
 }
 
 impl Sector {
     #[inline]
-    fn new(id: &Eid, idx: usize) -> Self {
+    fn new(id: &Eid, idx: usize, blks_per_sector: usize) -> Self {
         Sector {
             id: id.clone(),
             seq: 0,
@@ -60,7 +64,7 @@ impl Sector {
             idx,
             curr_size: 0,
             actual_size: 0,
-            blk_map: (0..BLKS_PER_SECTOR as u16).collect(),
+            blk_map: (0..blks_per_sector as u16).collect(),
         }
     }
 
@@ -77,7 +81,7 @@ impl Sector {
 
     // mark blocks as deleted
     fn mark_blocks_deletion(&mut self, span: Span) {
-        let insec_idx = span.begin % BLKS_PER_SECTOR;
+        let insec_idx = span.begin % self.blk_map.len();
         let mut deleted_size = 0;
 
         // mark blocks as deleted
@@ -144,6 +148,68 @@ impl Debug for Sector {
     }
 }
 
+// blocks retired while there are readers, they are deleted after all readers
+// are closed
+#[derive(Default, Clone, Deserialize, Serialize)]
+struct Retired {
+    id: Eid,
+    seq: u64,
+    arm: Arm,
+
+    // retired block spans and whether they need to be shredded
+    spans: Vec<(Span, bool)>,
+
+    #[serde(skip_serializing, skip_deserializing, default)]
+    is_changed: bool,
+}
+
+impl Id for Retired {
+    #[inline]
+    fn id(&self) -> &Eid {
+        &self.id
+    }
+
+    #[inline]
+    fn id_mut(&mut self) -> &mut Eid {
+        &mut self.id
+    }
+}
+
+impl Seq for Retired {
+    #[inline]
+    fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    #[inline]
+    fn inc_seq(&mut self) {
+        self.seq += 1
+    }
+}
+
+impl<'de> ArmAccess<'de> for Retired {
+    #[inline]
+    fn arm(&self) -> Arm {
+        self.arm
+    }
+
+    #[inline]
+    fn arm_mut(&mut self) -> &mut Arm {
+        &mut self.arm
+    }
+}
+
+impl Debug for Retired {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Retired")
+            .field("id", &self.id)
+            .field("seq", &self.seq)
+            .field("arm", &self.arm)
+            .field("spans.len", &self.spans.len())
+            .finish()
+    }
+}
+
 // sector manager
 pub struct SectorMgr {
     base: PathBuf,
@@ -157,6 +223,12 @@ pub struct SectorMgr {
     sec_data_cache: LinkedHashMap<usize, vio::File>,
 
     hash_key: HashKey,
+
+    // number of blocks in a sector
+    blks_per_sector: usize,
+
+    retired_armor: FileArmor<Retired>,
+    retired: Retired,
 }
 
 impl SectorMgr {
@@ -171,9 +243,31 @@ impl SectorMgr {
             sec_cache: Lru::new(SECTOR_CACHE_SIZE),
             sec_data_cache: LinkedHashMap::new(),
             hash_key: HashKey::new_empty(),
+            blks_per_sector: BLKS_PER_SECTOR,
+            retired_armor: FileArmor::new(base),
+            retired: Retired::default(),
         }
     }
 
+    // set sector size, 0 means the default size
+    pub fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        let size = if size == 0 { SECTOR_SIZE } else { size };
+        let blks = size / BLK_SIZE;
+        if size % BLK_SIZE != 0
+            || !blks.is_power_of_two()
+            || !(MIN_BLKS_PER_SECTOR..=MAX_BLKS_PER_SECTOR).contains(&blks)
+        {
+            return Err(Error::InvalidArgument);
+        }
+        self.blks_per_sector = blks;
+        Ok(())
+    }
+
+    #[inline]
+    fn sector_size(&self) -> usize {
+        self.blks_per_sector * BLK_SIZE
+    }
+
     #[inline]
     pub fn set_crypto_ctx(
         &mut self,
@@ -181,8 +275,37 @@ impl SectorMgr {
         key: Key,
         hash_key: HashKey,
     ) {
-        self.sec_armor.set_crypto_ctx(crypto, key);
+        self.sec_armor.set_crypto_ctx(crypto.clone(), key.clone());
+        self.retired_armor.set_crypto_ctx(crypto, key);
         self.hash_key = hash_key;
+
+        // no sector can have the max index, so use it for retired blocks
+        self.retired.id = self.sector_idx_to_id(usize::MAX);
+    }
+
+    // load retired blocks, it is only needed by writer
+    pub fn open(&mut self) -> Result<()> {
+        match self.retired_armor.load_item(&self.retired.id) {
+            Ok(retired) => self.retired = retired,
+            Err(ref err) if *err == Error::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    // save retired blocks if they are changed
+    pub fn flush(&mut self) -> Result<()> {
+        if self.retired.is_changed {
+            self.retired_armor.save_item(&mut self.retired)?;
+            self.retired.is_changed = false;
+        }
+        Ok(())
+    }
+
+    // clear cached sectors and sector data files, so they are reloaded
+    pub fn clear_cache(&mut self) {
+        self.sec_cache.clear();
+        self.sec_data_cache.clear();
     }
 
     // convert sector index to Eid
@@ -218,7 +341,8 @@ impl SectorMgr {
                     if create {
                         // if sector doesn't exist, create a new sector
                         // and save it to cache
-                        let mut sec = Sector::new(&sec_id, sec_idx);
+                        let mut sec =
+                            Sector::new(&sec_id, sec_idx, self.blks_per_sector);
                         self.sec_armor.save_item(&mut sec)?;
                         self.sec_cache.insert(sec_idx, sec);
                     } else {
@@ -272,14 +396,15 @@ impl SectorMgr {
     // read data blocks
     pub fn read_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
         assert_eq!(dst.len(), span.bytes_len());
+        let blks_per_sec = self.blks_per_sector;
 
         let mut read = 0;
-        for sec_span in span.divide_by(BLKS_PER_SECTOR) {
-            let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
+        for sec_span in span.divide_by(blks_per_sec) {
+            let sec_idx = sec_span.begin / blks_per_sec;
             let mut sec_data = self.open_sector_data(sec_idx, false)?;
             let blk_offset = {
                 let sec = self.open_sector(sec_idx, false)?;
-                let map_idx = sec_span.begin % BLKS_PER_SECTOR;
+                let map_idx = sec_span.begin % blks_per_sec;
                 let insec_idx = sec.blk_map[map_idx];
                 if sec.blk_map[map_idx..map_idx + sec_span.cnt]
                     .iter()
@@ -300,14 +425,21 @@ impl SectorMgr {
         Ok(())
     }
 
-    // write data blocks to sector
-    pub fn write_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
+    // write data blocks to sector, finished sector is shrunk only if
+    // `can_shrink` is true
+    pub fn write_blocks(
+        &mut self,
+        span: Span,
+        mut blks: &[u8],
+        can_shrink: bool,
+    ) -> Result<()> {
         assert_eq!(blks.len(), span.bytes_len());
+        let blks_per_sec = self.blks_per_sector;
 
-        for sec_span in span.divide_by(BLKS_PER_SECTOR) {
-            let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
+        for sec_span in span.divide_by(blks_per_sec) {
+            let sec_idx = sec_span.begin / blks_per_sec;
             let mut sec_data = self.open_sector_data(sec_idx, true)?;
-            let blk_offset = (sec_span.begin % BLKS_PER_SECTOR) * BLK_SIZE;
+            let blk_offset = (sec_span.begin % blks_per_sec) * BLK_SIZE;
 
             // write blocks bytes to sector data file
             let write_len = sec_span.bytes_len();
@@ -325,7 +457,7 @@ impl SectorMgr {
                 let sec = self.open_sector(sec_idx, true)?;
 
                 assert!(!sec.is_finished());
-                let map_idx = sec_span.begin % BLKS_PER_SECTOR;
+                let map_idx = sec_span.begin % blks_per_sec;
                 let mut corrected = 0;
                 for i in map_idx..map_idx + sec_span.cnt {
                     if sec.blk_map[i] == BLK_DELETE_MARK {
@@ -344,10 +476,11 @@ impl SectorMgr {
             }
 
             // if we reached the end of sector, mark it as finished
-            if sec_span.end() % BLKS_PER_SECTOR == 0 {
+            if sec_span.end() % blks_per_sec == 0 {
+                let sec_size = self.sector_size();
                 let is_shrinkable = {
                     let sec = self.open_sector(sec_idx, false)?;
-                    sec.curr_size = SECTOR_SIZE;
+                    sec.curr_size = sec_size;
                     sec.actual_size = BLK_SIZE
                         * sec
                             .blk_map
@@ -357,7 +490,7 @@ impl SectorMgr {
                     sec.is_shrinkable()
                 };
 
-                if is_shrinkable {
+                if is_shrinkable && can_shrink {
                     // shrink sector
                     self.shrink_sector(sec_idx)?;
                 } else {
@@ -422,10 +555,82 @@ impl SectorMgr {
         Ok(())
     }
 
+    // remove the whole sector, including sector and sector data file
+    fn remove_sector(&mut self, sec_idx: usize, sec_id: &Eid) -> Result<()> {
+        self.sec_armor.remove_all_arms(sec_id)?;
+        self.sec_cache.remove(&sec_idx);
+        self.sec_data_cache.remove(&sec_idx);
+        let sec_data_path = self.sector_data_path(sec_idx);
+        if sec_data_path.exists() {
+            vio::remove_file(&sec_data_path)?;
+            remove_empty_parent_dir(&sec_data_path)?;
+        }
+        Ok(())
+    }
+
+    // remove sector files left behind, such as shrink file of an interrupted
+    // shrinking and data file without sector
+    fn remove_leftovers(
+        &mut self,
+        sec_idx: usize,
+        has_sec: bool,
+    ) -> Result<()> {
+        let sec_data_path = self.sector_data_path(sec_idx);
+        let mut shrink_path = sec_data_path.clone();
+        shrink_path.set_extension(Self::SECTOR_SHRINK_EXT);
+        if shrink_path.exists() {
+            vio::remove_file(&shrink_path)?;
+        }
+        if !has_sec && sec_data_path.exists() {
+            self.sec_data_cache.remove(&sec_idx);
+            vio::remove_file(&sec_data_path)?;
+            remove_empty_parent_dir(&sec_data_path)?;
+        }
+        Ok(())
+    }
+
+    // overwrite data blocks with random bytes, deleted blocks are skipped
+    pub fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        let blks_per_sec = self.blks_per_sector;
+        let mut junk = vec![0u8; BLK_SIZE];
+
+        for sec_span in span.divide_by(blks_per_sec) {
+            let sec_idx = sec_span.begin / blks_per_sec;
+            let insec_idxes: Vec<u16> = match self.open_sector(sec_idx, false) {
+                Ok(sec) => {
+                    let map_idx = sec_span.begin % blks_per_sec;
+                    sec.blk_map[map_idx..map_idx + sec_span.cnt]
+                        .iter()
+                        .filter(|b| **b != BLK_DELETE_MARK)
+                        .cloned()
+                        .collect()
+                }
+                Err(ref err) if *err == Error::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+
+            let mut sec_data = match self.open_sector_data(sec_idx, false) {
+                Ok(sec_data) => sec_data,
+                Err(ref err) if *err == Error::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            for insec_idx in insec_idxes {
+                Crypto::random_buf(&mut junk);
+                let offset = u64::from(insec_idx) * BLK_SIZE as u64;
+                sec_data.seek(SeekFrom::Start(offset))?;
+                sec_data.write_all(&junk)?;
+            }
+            sec_data.sync_data()?;
+        }
+
+        Ok(())
+    }
+
     // delete data blocks
     pub fn del_blocks(&mut self, span: Span) -> Result<()> {
-        for sec_span in span.divide_by(BLKS_PER_SECTOR) {
-            let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
+        let blks_per_sec = self.blks_per_sector;
+        for sec_span in span.divide_by(blks_per_sec) {
+            let sec_idx = sec_span.begin / blks_per_sec;
             let sec_id;
             let actual_size;
             let is_finished;
@@ -456,11 +661,7 @@ impl SectorMgr {
             // if all blocks are deleted, remove the whole sector
             // including sector and sector data file
             if actual_size == 0 {
-                self.sec_armor.remove_all_arms(&sec_id)?;
-                let sec_data_path = self.sector_data_path(sec_idx);
-                vio::remove_file(&sec_data_path)?;
-                remove_empty_parent_dir(&sec_data_path)?;
-                self.sec_cache.remove(&sec_idx);
+                self.remove_sector(sec_idx, &sec_id)?;
             } else if is_shrinkable {
                 // shrink sector if possible
                 self.shrink_sector(sec_idx)?;
@@ -472,6 +673,79 @@ impl SectorMgr {
 
         Ok(())
     }
+
+    // retire data blocks, they are kept intact until purge_retired is called
+    pub fn retire_blocks(&mut self, span: Span, shred: bool) {
+        self.retired.spans.push((span, shred));
+        self.retired.is_changed = true;
+    }
+
+    #[inline]
+    pub fn has_retired(&self) -> bool {
+        !self.retired.spans.is_empty()
+    }
+
+    // delete all retired blocks, must not be called while there are readers
+    pub fn purge_retired(&mut self) -> Result<()> {
+        if !self.has_retired() {
+            return Ok(());
+        }
+
+        // saved retired blocks are only updated after all of them are
+        // deleted, deleting them again is harmless if it was interrupted
+        while let Some(&(span, shred)) = self.retired.spans.last() {
+            if shred {
+                self.shred_blocks(span)?;
+            }
+            self.del_blocks(span)?;
+            self.retired.spans.pop();
+        }
+        self.retired.is_changed = true;
+        self.flush()
+    }
+
+    // trim all finished sectors up to the block watermark, empty sectors are
+    // removed and sectors having deleted blocks are shrunk, so the space is
+    // returned to the host file system
+    pub fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let blks_per_sec = self.blks_per_sector;
+        let sec_cnt = blk_wmark.div_ceil(blks_per_sec);
+
+        for sec_idx in 0..sec_cnt {
+            // sector entirely below the watermark will not be written again
+            let is_closed = (sec_idx + 1) * blks_per_sec <= blk_wmark;
+            let sec = match self.open_sector(sec_idx, false) {
+                Ok(sec) => {
+                    let is_empty =
+                        sec.blk_map.iter().all(|b| *b == BLK_DELETE_MARK);
+                    let is_finished = sec.is_finished();
+                    Some((
+                        sec.id.clone(),
+                        is_empty && (is_finished || is_closed),
+                        is_finished && sec.actual_size < sec.curr_size,
+                    ))
+                }
+                Err(ref err) if *err == Error::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            self.remove_leftovers(sec_idx, sec.is_some())?;
+
+            match sec {
+                Some((sec_id, true, _)) => {
+                    self.remove_sector(sec_idx, &sec_id)?
+                }
+                Some((_, false, true)) => self.shrink_sector(sec_idx)?,
+                _ => {}
+            }
+            progress(sec_idx + 1, sec_cnt);
+        }
+
+        Ok(())
+    }
 }
 
 impl Debug for SectorMgr {
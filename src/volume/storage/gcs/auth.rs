@@ -0,0 +1,167 @@
+use std::fmt::{self, Debug};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::Client;
+use serde_json::from_slice;
+
+use error::{Error, Result};
+
+// oauth scope for reading and writing objects
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+// default oauth token endpoint
+const TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+// jwt assertion lifetime, in seconds
+const JWT_LIFETIME: u64 = 3600;
+
+// refresh access token this long before it expires
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+// service account key file
+#[derive(Deserialize)]
+pub struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+
+    #[serde(default)]
+    private_key_id: Option<String>,
+
+    #[serde(default)]
+    token_uri: Option<String>,
+}
+
+// jwt claims used to request an access token
+#[derive(Debug, Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+// oauth token endpoint response
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Authentication method used to access GCS
+pub enum Auth {
+    // no authentication, used for emulator
+    Anonymous,
+
+    // fixed access token provided by user
+    Token(String),
+
+    // service account, access token is requested and cached on demand
+    ServiceAccount {
+        key: ServiceAccountKey,
+        token: Option<(String, Instant)>,
+    },
+}
+
+impl Auth {
+    // load service account key from a json key file
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let buf = fs::read(path)?;
+        let key: ServiceAccountKey = from_slice(&buf)?;
+        Ok(Auth::ServiceAccount { key, token: None })
+    }
+
+    // get current access token, request a new one if it is missing
+    // or about to expire
+    pub fn token(&mut self, client: &Client) -> Result<Option<String>> {
+        match *self {
+            Auth::Anonymous => Ok(None),
+            Auth::Token(ref token) => Ok(Some(token.clone())),
+            Auth::ServiceAccount {
+                ref key,
+                ref mut token,
+            } => {
+                if let Some((ref token, expire_at)) = *token {
+                    if Instant::now() + REFRESH_MARGIN < expire_at {
+                        return Ok(Some(token.clone()));
+                    }
+                }
+                let (new_token, expires_in) = request_token(client, key)?;
+                let expire_at = Instant::now() + expires_in;
+                *token = Some((new_token.clone(), expire_at));
+                Ok(Some(new_token))
+            }
+        }
+    }
+
+    // discard cached access token, so it will be requested again
+    pub fn invalidate(&mut self) {
+        if let Auth::ServiceAccount { ref mut token, .. } = *self {
+            *token = None;
+        }
+    }
+}
+
+impl Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // don't expose access token and private key
+        match *self {
+            Auth::Anonymous => write!(f, "Anonymous"),
+            Auth::Token(_) => write!(f, "Token"),
+            Auth::ServiceAccount { ref key, .. } => f
+                .debug_struct("ServiceAccount")
+                .field("client_email", &key.client_email)
+                .finish(),
+        }
+    }
+}
+
+// request an access token using signed jwt assertion
+fn request_token(
+    client: &Client,
+    key: &ServiceAccountKey,
+) -> Result<(String, Duration)> {
+    let token_uri = key.token_uri.as_ref().map_or(TOKEN_URI, |s| s.as_str());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InvalidArgument)?
+        .as_secs();
+    let claims = Claims {
+        iss: &key.client_email,
+        scope: SCOPE,
+        aud: token_uri,
+        iat: now,
+        exp: now + JWT_LIFETIME,
+    };
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = key.private_key_id.clone();
+    let enc_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+        .map_err(|err| {
+            warn!("invalid service account private key: {}", err);
+            Error::InvalidArgument
+        })?;
+    let assertion = encode(&header, &claims, &enc_key).map_err(|err| {
+        warn!("sign jwt assertion failed: {}", err);
+        Error::InvalidArgument
+    })?;
+
+    let mut resp = client
+        .post(token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()?;
+    if !resp.status().is_success() {
+        return Err(Error::HttpStatus(resp.status()));
+    }
+    let mut buf = Vec::new();
+    resp.copy_to(&mut buf)?;
+    let token: TokenResponse = from_slice(&buf)?;
+
+    Ok((token.access_token, Duration::from_secs(token.expires_in)))
+}
@@ -0,0 +1,611 @@
+use std::fmt::{self, Debug};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use http::StatusCode;
+use reqwest::{Client, RequestBuilder, Response};
+use serde_json::from_slice;
+
+use super::auth::Auth;
+use base::crypto::{Crypto, Key};
+use base::IntoRef;
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{RetryClass, RetryPolicy, Storable};
+use volume::BLK_SIZE;
+
+// default GCS api endpoint
+const ENDPOINT: &str = "https://storage.googleapis.com";
+
+// http request timeout, in seconds
+const TIMEOUT: u64 = 60;
+
+// fields returned in object list response
+const LIST_FIELDS: &str = "items(name),nextPageToken";
+
+// object name for repo lock
+#[inline]
+fn repo_lock_name() -> String {
+    "repo_lock".to_string()
+}
+
+// object name for super block
+#[inline]
+fn super_blk_name(suffix: u64) -> String {
+    format!("super_blk/{}", suffix)
+}
+
+// object name for wal
+#[inline]
+fn wal_name(id: &Eid) -> String {
+    format!("wal/{}", id.to_string())
+}
+
+// object name for address
+#[inline]
+fn addr_name(id: &Eid) -> String {
+    format!("address/{}", id.to_string())
+}
+
+// object name for block
+#[inline]
+fn blk_name(blk_idx: usize) -> String {
+    format!("block/{}", blk_idx)
+}
+
+// percent-encode a string, only unreserved characters are kept
+fn encode(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => ret.push(b as char),
+            _ => ret.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    ret
+}
+
+// get retry class of a response status, None if it should not be retried
+#[inline]
+fn retry_class(status: StatusCode) -> Option<RetryClass> {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        Some(RetryClass::Throttled)
+    } else if status.is_server_error() {
+        Some(RetryClass::ServerError)
+    } else {
+        None
+    }
+}
+
+// read response body
+fn read_body(resp: &mut Response) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    resp.copy_to(&mut buf)?;
+    Ok(buf)
+}
+
+// object list response
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ObjectItem>,
+
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectItem {
+    name: String,
+}
+
+// parse uri
+// example: bucket/prefix?credentials=path&token=xxx&endpoint=url&retry=5
+// return: (
+//   bucket: String,
+//   prefix: String,
+//   auth: Auth,
+//   endpoint: String,
+//   max_retry: Option<usize>,
+// )
+fn parse_uri(
+    uri: &str,
+) -> Result<(String, String, Auth, String, Option<usize>)> {
+    if !uri.is_ascii() {
+        return Err(Error::InvalidUri);
+    }
+
+    // split path and parameters
+    let (path, params) = match uri.find('?') {
+        Some(idx) => (&uri[..idx], &uri[idx + 1..]),
+        None => (uri, ""),
+    };
+
+    // parse bucket name, required
+    let path = path.trim_matches('/');
+    let (bucket, prefix) = match path.find('/') {
+        Some(idx) => (&path[..idx], path[idx + 1..].trim_matches('/')),
+        None => (path, ""),
+    };
+    if bucket.is_empty() {
+        return Err(Error::InvalidUri);
+    }
+
+    // set default value for parameters
+    let mut credentials: Option<&str> = None;
+    let mut token: Option<&str> = None;
+    let mut endpoint = ENDPOINT;
+    let mut max_retry = None;
+
+    // parse parameters
+    if !params.is_empty() {
+        for param in params.split('&') {
+            let idx = param.find('=').ok_or(Error::InvalidUri)?;
+            let key = &param[..idx];
+            let value = &param[idx + 1..];
+            if value.is_empty() {
+                return Err(Error::InvalidUri);
+            }
+
+            match key {
+                "credentials" => credentials = Some(value),
+                "token" => token = Some(value),
+                "endpoint" => endpoint = value.trim_end_matches('/'),
+                "retry" => {
+                    max_retry = Some(
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| Error::InvalidUri)?,
+                    );
+                }
+                _ => return Err(Error::InvalidUri),
+            }
+        }
+    }
+
+    // determine authentication method, in the order of: access token,
+    // service account key file in uri, then the key file specified by
+    // environment variable
+    let auth = if let Some(token) = token {
+        Auth::Token(token.to_string())
+    } else if let Some(path) = credentials {
+        Auth::from_key_file(Path::new(path))?
+    } else if let Some(path) =
+        std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+    {
+        Auth::from_key_file(Path::new(&path))?
+    } else {
+        Auth::Anonymous
+    };
+
+    Ok((
+        bucket.to_string(),
+        prefix.to_string(),
+        auth,
+        endpoint.to_string(),
+        max_retry,
+    ))
+}
+
+/// Google Cloud Storage
+///
+/// Each super block, wal, address and block is stored as an individual
+/// object in the bucket, under an optional object name prefix.
+pub struct GcsStorage {
+    is_attached: bool, // attached to repo lock
+    bucket: String,
+    prefix: String,
+    endpoint: String,
+    max_retry: Option<usize>, // max retry specified in uri
+    retry: RetryPolicy,
+    client: Client,
+    auth: Mutex<Auth>,
+}
+
+impl GcsStorage {
+    pub fn new(uri: &str) -> Result<Self> {
+        // url format:
+        // gs://<bucket>[/<prefix>][?credentials=<path>&token=<token>
+        //     &endpoint=<url>&retry=<n>]
+        let (bucket, prefix, auth, endpoint, max_retry) = parse_uri(uri)?;
+        let client = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT))
+            .build()?;
+
+        Ok(GcsStorage {
+            is_attached: false,
+            bucket,
+            prefix,
+            endpoint,
+            max_retry,
+            retry: Self::retry_policy(RetryPolicy::default(), max_retry),
+            client,
+            auth: Mutex::new(auth),
+        })
+    }
+
+    // retry parameter in uri takes precedence over the retry policy
+    fn retry_policy(
+        mut policy: RetryPolicy,
+        max_retry: Option<usize>,
+    ) -> RetryPolicy {
+        if let Some(max_retry) = max_retry {
+            policy.max_attempts = max_retry + 1;
+        }
+        policy
+    }
+
+    // full object name with prefix
+    fn obj_name(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, name)
+        }
+    }
+
+    // object metadata or media url
+    fn obj_url(&self, name: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            encode(&self.bucket),
+            encode(&self.obj_name(name))
+        )
+    }
+
+    // send request with authentication, retry with exponential backoff as
+    // per retry policy
+    fn send<F>(&self, build: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let token = {
+                let mut auth = self.auth.lock().unwrap();
+                auth.token(&self.client)?
+            };
+            let mut req = build(&self.client);
+            if let Some(token) = token {
+                req = req.bearer_auth(token);
+            }
+
+            match req.send() {
+                Ok(resp) => {
+                    let status = resp.status();
+                    if status == StatusCode::UNAUTHORIZED {
+                        // access token might be revoked or expired
+                        self.auth.lock().unwrap().invalidate();
+                    }
+                    match retry_class(status) {
+                        Some(class)
+                            if self.retry.should_retry(class, attempt) =>
+                        {
+                            debug!("gcs request got status {}, retry", status);
+                        }
+                        _ => return Ok(resp),
+                    }
+                }
+                Err(err) => {
+                    if !self.retry.should_retry(RetryClass::Transport, attempt)
+                    {
+                        return Err(Error::from(err));
+                    }
+                    debug!("gcs request failed: {}, retry", err);
+                }
+            }
+
+            thread::sleep(self.retry.backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn get_object(&self, name: &str) -> Result<Vec<u8>> {
+        let url = self.obj_url(name);
+        let mut resp =
+            self.send(|client| client.get(&url).query(&[("alt", "media")]))?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Err(Error::NotFound),
+            status if status.is_success() => read_body(&mut resp),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+
+    fn has_object(&self, name: &str) -> Result<bool> {
+        let url = self.obj_url(name);
+        let resp = self.send(|client| client.get(&url))?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+
+    // upload an object, if `if_absent` is true the upload will fail with
+    // `AlreadyExists` error when the object already exists
+    fn put_object(
+        &self,
+        name: &str,
+        data: &[u8],
+        if_absent: bool,
+    ) -> Result<()> {
+        let url = format!(
+            "{}/upload/storage/v1/b/{}/o",
+            self.endpoint,
+            encode(&self.bucket)
+        );
+        let obj_name = self.obj_name(name);
+        let resp = self.send(|client| {
+            let mut req = client
+                .post(&url)
+                .query(&[("uploadType", "media"), ("name", obj_name.as_str())]);
+            if if_absent {
+                req = req.query(&[("ifGenerationMatch", "0")]);
+            }
+            req.header("Content-Type", "application/octet-stream")
+                .body(data.to_owned())
+        })?;
+        match resp.status() {
+            StatusCode::PRECONDITION_FAILED => Err(Error::AlreadyExists),
+            status if status.is_success() => Ok(()),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+
+    fn del_object(&self, name: &str) -> Result<()> {
+        let url = self.obj_url(name);
+        let resp = self.send(|client| client.delete(&url))?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            status => Err(Error::HttpStatus(status)),
+        }
+    }
+
+    // list full names of all objects belong to this storage
+    fn list_objects(&self) -> Result<Vec<String>> {
+        let url = format!(
+            "{}/storage/v1/b/{}/o",
+            self.endpoint,
+            encode(&self.bucket)
+        );
+        let prefix = self.obj_name("");
+        let mut names = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut resp = self.send(|client| {
+                let mut req = client.get(&url).query(&[
+                    ("prefix", prefix.as_str()),
+                    ("fields", LIST_FIELDS),
+                ]);
+                if let Some(ref page_token) = page_token {
+                    req = req.query(&[("pageToken", page_token.as_str())]);
+                }
+                req
+            })?;
+            if !resp.status().is_success() {
+                return Err(Error::HttpStatus(resp.status()));
+            }
+            let list: ObjectList = from_slice(&read_body(&mut resp)?)?;
+            names.extend(list.items.into_iter().map(|item| item.name));
+            match list.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn lock_repo(&mut self, force: bool) -> Result<()> {
+        let name = repo_lock_name();
+        match self.put_object(&name, &[], true) {
+            Ok(_) => {}
+            Err(ref err) if *err == Error::AlreadyExists => {
+                // repo is locked
+                if force {
+                    warn!("Repo was locked, forced to open");
+                } else {
+                    return Err(Error::RepoOpened);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+        self.is_attached = true;
+        Ok(())
+    }
+}
+
+impl Storable for GcsStorage {
+    fn exists(&self) -> Result<bool> {
+        // check super block existence to determine if repo exists
+        self.has_object(&super_blk_name(0))
+    }
+
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        // make sure the credentials are valid
+        let mut auth = self.auth.lock().unwrap();
+        auth.token(&self.client)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = Self::retry_policy(policy, self.max_retry);
+    }
+
+    #[inline]
+    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+        self.lock_repo(false)
+    }
+
+    #[inline]
+    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+        self.lock_repo(force)
+    }
+
+    #[inline]
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        self.get_object(&super_blk_name(suffix))
+    }
+
+    #[inline]
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        self.put_object(&super_blk_name(suffix), super_blk, false)
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.get_object(&wal_name(id))
+    }
+
+    #[inline]
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        self.put_object(&wal_name(id), wal, false)
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.del_object(&wal_name(id))
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.get_object(&addr_name(id))
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.put_object(&addr_name(id), addr, false)
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.del_object(&addr_name(id))
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let mut read = 0;
+        for blk_idx in span {
+            let blk = self.get_object(&blk_name(blk_idx))?;
+            if blk.len() != BLK_SIZE {
+                return Err(Error::Corrupted);
+            }
+            dst[read..read + BLK_SIZE].copy_from_slice(&blk);
+            read += BLK_SIZE;
+        }
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
+        for blk_idx in span {
+            self.put_object(&blk_name(blk_idx), &blks[..BLK_SIZE], false)?;
+            blks = &blks[BLK_SIZE..];
+        }
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        for blk_idx in span {
+            self.del_object(&blk_name(blk_idx))?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        if self.has_object(&repo_lock_name())? {
+            // repo is locked
+            warn!("Destroy an opened repo");
+        }
+
+        let prefix = self.obj_name("");
+        for name in self.list_objects()? {
+            self.del_object(&name[prefix.len()..])?;
+        }
+        self.is_attached = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for GcsStorage {
+    fn drop(&mut self) {
+        if self.is_attached {
+            // remove repo lock and ignore errors
+            let _ = self.del_object(&repo_lock_name());
+            self.is_attached = false;
+        }
+    }
+}
+
+impl Debug for GcsStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GcsStorage")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl IntoRef for GcsStorage {}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn gcs_parse_uri() {
+        assert_eq!(parse_uri("").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("/").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("中文").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("bucket?foo=bar").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("bucket?token=").unwrap_err(), Error::InvalidUri);
+        assert_eq!(
+            parse_uri("bucket?retry=abc").unwrap_err(),
+            Error::InvalidUri
+        );
+
+        let (bucket, prefix, _, endpoint, max_retry) =
+            parse_uri("bucket?token=abc").unwrap();
+        assert_eq!(bucket, "bucket");
+        assert!(prefix.is_empty());
+        assert_eq!(endpoint, ENDPOINT);
+        assert!(max_retry.is_none());
+
+        let (bucket, prefix, _, endpoint, max_retry) = parse_uri(
+            "bucket/foo/bar/?token=abc&endpoint=http://localhost:4443/\
+             &retry=2",
+        )
+        .unwrap();
+        assert_eq!(bucket, "bucket");
+        assert_eq!(prefix, "foo/bar");
+        assert_eq!(endpoint, "http://localhost:4443");
+        assert_eq!(max_retry, Some(2));
+    }
+
+    #[test]
+    fn gcs_encode() {
+        assert_eq!(encode("abc-123_.~"), "abc-123_.~");
+        assert_eq!(encode("foo/wal/1 2"), "foo%2Fwal%2F1%202");
+    }
+}
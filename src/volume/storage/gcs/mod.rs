@@ -0,0 +1,4 @@
+mod auth;
+mod gcs;
+
+pub use self::gcs::GcsStorage;
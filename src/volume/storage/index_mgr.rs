@@ -328,12 +328,9 @@ impl Level {
         self.tabs.remove(pos);
     }
 
-    fn clear(&mut self, tab_armor: &TabArmor) -> Result<()> {
-        for tab_info in self.tabs.iter() {
-            tab_armor.remove(&tab_info.id)?;
-        }
-        self.tabs.clear();
-        Ok(())
+    // clear level and move its tabs to retired list
+    fn clear(&mut self, retired: &mut Vec<Eid>) {
+        retired.extend(self.tabs.drain(..).map(|t| t.id));
     }
 }
 
@@ -348,6 +345,11 @@ pub struct Lsmt {
     arm: Arm,
     lvls: Vec<Level>,
 
+    // tabs replaced by compaction, they are not removed until no readers
+    // are using them
+    #[serde(default)]
+    retired: Vec<Eid>,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     tab_cache: Lru<Eid, Tab, CountMeter<Tab>, PinChecker<Tab>>,
 }
@@ -362,6 +364,7 @@ impl Lsmt {
             seq: 0,
             arm: Arm::default(),
             lvls: vec![Level::new(0)],
+            retired: Vec::new(),
             tab_cache: Lru::new(Self::TAB_CACHE_SIZE),
         }
     }
@@ -371,6 +374,7 @@ impl Lsmt {
         self.seq = lsmt.seq;
         self.arm = lsmt.arm;
         self.lvls = lsmt.lvls;
+        self.retired = lsmt.retired;
         Ok(())
     }
 
@@ -469,7 +473,7 @@ impl Lsmt {
             );
             // save merged tab and clear current level
             tab_armor.save(&mut tab)?;
-            self.lvls[curr].clear(tab_armor)?;
+            self.lvls[curr].clear(&mut self.retired);
 
             // create the next level
             let mut new_lvl = Level::new(next);
@@ -487,10 +491,10 @@ impl Lsmt {
         let overlap_tab = self.combine_tabs(&overlap, tab_armor)?;
         let merged = tab.merge(&overlap_tab);
 
-        // remove overlapping tabs in next level
+        // retire overlapping tabs in next level
         for tab_info in overlap.iter() {
-            tab_armor.remove(&tab_info.id)?;
             self.lvls[next].remove(&tab_info.id);
+            self.retired.push(tab_info.id.clone());
         }
 
         // save merged tab to next level
@@ -502,7 +506,7 @@ impl Lsmt {
         }
 
         // clear current level
-        self.lvls[curr].clear(tab_armor)?;
+        self.lvls[curr].clear(&mut self.retired);
 
         Ok(())
     }
@@ -573,6 +577,7 @@ impl Debug for Lsmt {
             .field("seq", &self.seq)
             .field("arm", &self.arm)
             .field("lvls", &self.lvls)
+            .field("retired.len", &self.retired.len())
             .finish()
     }
 }
@@ -790,6 +795,24 @@ impl IndexMgr {
         }
         Ok(())
     }
+
+    #[inline]
+    pub fn has_retired(&self) -> bool {
+        !self.lsmt.retired.is_empty()
+    }
+
+    // remove all retired tabs, must not be called while there are readers
+    pub fn purge_retired(&mut self) -> Result<()> {
+        if !self.has_retired() {
+            return Ok(());
+        }
+        for tab_id in self.lsmt.retired.iter() {
+            self.tab_armor.remove(tab_id)?;
+            self.lsmt.tab_cache.remove(tab_id);
+        }
+        self.lsmt.retired.clear();
+        self.lsmt_armor.save(&mut self.lsmt)
+    }
 }
 
 impl Debug for IndexMgr {
@@ -7,7 +7,7 @@ use base::IntoRef;
 use error::{Error, Result};
 use trans::Eid;
 use volume::address::Span;
-use volume::storage::Storable;
+use volume::storage::{Space, Storable};
 use volume::BLK_SIZE;
 
 // memory storage depot
@@ -204,6 +204,21 @@ impl Storable for MemStorage {
         }
         Ok(())
     }
+
+    fn space(&mut self, _used_blocks: usize) -> Result<Space> {
+        let storages = STORAGES.lock().unwrap();
+        let depot = storages.get(&self.loc).ok_or(Error::NotFound)?;
+        let used = depot.super_blk_map.values().map(Vec::len).sum::<usize>()
+            + depot.wal_map.values().map(Vec::len).sum::<usize>()
+            + depot.addr_map.values().map(Vec::len).sum::<usize>()
+            + depot.blk_map.len() * BLK_SIZE;
+
+        // memory storage has no capacity limit
+        Ok(Space {
+            total: None,
+            used: used as u64,
+        })
+    }
 }
 
 impl Drop for MemStorage {
@@ -1,9 +1,14 @@
 #![allow(clippy::module_inception)]
 
+mod registry;
+mod retry;
+mod sched;
 mod storage;
 
+pub use self::registry::{register_storage, StorageFactory};
+pub use self::retry::{RetryClass, RetryPolicy};
 pub use self::storage::{
-    Reader, Storage, StorageRef, WalReader, WalWriter, Writer,
+    strip_db_key, Reader, Storage, StorageRef, WalReader, WalWriter, Writer,
 };
 
 #[cfg(feature = "storage-mem")]
@@ -15,6 +20,18 @@ mod file;
 #[cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
 mod faulty_ctl;
 
+#[cfg(feature = "storage-container")]
+mod container;
+
+#[cfg(feature = "storage-cache")]
+mod cache;
+
+#[cfg(feature = "storage-stripe")]
+mod stripe;
+
+#[cfg(feature = "storage-throttle")]
+mod throttle;
+
 #[cfg(feature = "storage-faulty")]
 mod faulty;
 
@@ -27,6 +44,12 @@ mod sqlite;
 #[cfg(feature = "storage-redis")]
 mod redis;
 
+#[cfg(feature = "storage-sled")]
+mod sled;
+
+#[cfg(feature = "storage-gcs")]
+mod gcs;
+
 #[cfg(feature = "storage-zbox")]
 mod zbox;
 
@@ -34,58 +57,223 @@ mod zbox;
 mod index_mgr;
 
 use std::fmt::Debug;
+use std::time::Duration;
 
 use base::crypto::{Crypto, Key};
-use error::Result;
+use base::Time;
+use error::{Error, Result};
 use trans::Eid;
 use volume::address::Span;
+use volume::BLK_SIZE;
+
+/// Storage space usage, in bytes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Space {
+    /// Total capacity, None if the storage has no known capacity limit
+    pub total: Option<u64>,
+
+    /// Space already used
+    pub used: u64,
+}
+
+/// Storage health check result
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Health {
+    /// Round trip time of reading from storage
+    pub read_latency: Duration,
+
+    /// Round trip time of writing to storage, None if storage is read-only
+    pub write_latency: Option<Duration>,
+}
 
 /// Storable trait
+///
+/// This trait is implemented by the underlying storages. Custom storages can
+/// implement this trait and then be used by registering them using
+/// [`register_storage`].
+///
+/// Data read from and written to storage are already encrypted, storage
+/// doesn't need to encrypt them again.
+///
+/// [`register_storage`]: fn.register_storage.html
 pub trait Storable: Debug + Send + Sync {
-    // check if storage exists
+    /// Check if storage exists.
     fn exists(&self) -> Result<bool>;
 
-    // make connection to storage
+    /// Make connection to storage.
     fn connect(&mut self, force: bool) -> Result<()>;
 
-    // initial a storage
+    /// Initialise a storage.
     fn init(&mut self, crypto: Crypto, key: Key) -> Result<()>;
 
-    // open a storage
+    /// Open a storage.
+    ///
+    /// Storage should be locked exclusively, and `Error::RepoOpened` should
+    /// be returned if it is already locked and `force` is false.
     fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()>;
 
-    // super block read/write, must not buffered
-    // write no need to be atomic, but must gurantee any successful
-    // write is persistent
+    /// Open a storage for reading only.
+    ///
+    /// Storages supporting concurrent readers should take a shared lock, so
+    /// the storage can be opened by multiple readers while a writer holds
+    /// the exclusive lock. Writer must not reclaim any space while the shared
+    /// lock is held, as readers may still be reading it. The default
+    /// implementation opens storage exclusively by calling `open`.
+    #[inline]
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.open(crypto, key, false)
+    }
+
+    /// Reload storage state changed by another writer.
+    ///
+    /// This is called on a storage opened by `open_shared`, any cached index
+    /// or block layout should be reloaded so blocks committed by the writer
+    /// since then can be read. The default implementation does nothing.
+    #[inline]
+    fn refresh(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Read super block.
+    ///
+    /// Super block read/write must not be buffered. Write doesn't need to be
+    /// atomic, but any successful write must be persistent.
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>>;
+
+    /// Write super block.
     fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()>;
 
-    // wal read/write, must not buffered
-    // update no need to be atomic, but must gurantee any successful update
-    // is persistent.
-    // Note: wal deletion doesn't need to be persistent and can be buffered
+    /// Read wal.
+    ///
+    /// Wal read/write must not be buffered. Update doesn't need to be atomic,
+    /// but any successful update must be persistent.
+    ///
+    /// Wal deletion doesn't need to be persistent and can be buffered.
     fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>>;
+
+    /// Write wal.
     fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()>;
+
+    /// Delete wal.
     fn del_wal(&mut self, id: &Eid) -> Result<()>;
 
-    // address read/write, can be buffered
-    // storage doesn't need to gurantee update is persistent
+    /// Read address.
+    ///
+    /// Address read/write can be buffered, storage doesn't need to guarantee
+    /// update is persistent until flush.
     fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>>;
+
+    /// Write address.
     fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()>;
+
+    /// Delete address.
     fn del_address(&mut self, id: &Eid) -> Result<()>;
 
-    // block read/write, can be buffered
-    // storage doesn't need to gurantee update is persistent
+    /// Read blocks.
+    ///
+    /// Each block is `BLK_SIZE` bytes. Block read/write can be buffered,
+    /// storage doesn't need to guarantee update is persistent until flush.
+    ///
+    /// `Error::NotFound` should be returned if any block is not found.
     fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()>;
+
+    /// Write blocks.
     fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()>;
+
+    /// Delete blocks.
     fn del_blocks(&mut self, span: Span) -> Result<()>;
 
-    // flush possibly buffered wal, address and block to storage,
-    // storage must gurantee write is persistent
+    /// Overwrite blocks and then delete them.
+    ///
+    /// This is called instead of `del_blocks` when deleted data is being
+    /// shredded. Storages which keep deleted block data in place should
+    /// overwrite it so it cannot be recovered, the default implementation
+    /// only deletes blocks.
+    #[inline]
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        self.del_blocks(span)
+    }
+
+    /// Flush possibly buffered wal, address and block to storage, storage
+    /// must guarantee write is persistent.
     fn flush(&mut self) -> Result<()>;
 
-    // permanently destroy this storage
+    /// Permanently destroy this storage.
     fn destroy(&mut self) -> Result<()>;
+
+    /// Get storage space usage.
+    ///
+    /// `used_blocks` is the number of blocks currently allocated, storage
+    /// which cannot measure its usage cheaply can estimate from it. The
+    /// default implementation reports the size of those blocks as used
+    /// space, with no known capacity limit.
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        Ok(Space {
+            total: None,
+            used: (used_blocks * BLK_SIZE) as u64,
+        })
+    }
+
+    /// Set retry policy for failed requests.
+    ///
+    /// This is called before `connect`. Network-based storages should retry
+    /// failed requests as specified by the policy, the default
+    /// implementation ignores it.
+    #[inline]
+    fn set_retry_policy(&mut self, _policy: RetryPolicy) {}
+
+    /// Set sector size in bytes, 0 means the storage default.
+    ///
+    /// This is called before `init` and `open`, with the size persisted in
+    /// super block. Storages which group blocks into sectors should use it,
+    /// the default implementation ignores it.
+    #[inline]
+    fn set_sector_size(&mut self, _size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reclaim space occupied by deleted blocks.
+    ///
+    /// Blocks at or beyond `blk_wmark` have never been allocated. `progress`
+    /// is called with the number of processed and total work units as
+    /// compaction proceeds. Storages whose deletion frees space immediately
+    /// don't need to do anything, which is the default implementation.
+    #[inline]
+    fn compact(
+        &mut self,
+        _blk_wmark: usize,
+        _progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check storage connectivity, latency and writability.
+    ///
+    /// The default implementation reads super block and then writes and
+    /// deletes a probe wal. Storage which fails writes with
+    /// `Error::ReadOnly` is reported as not writable, any other error is
+    /// returned.
+    fn health_check(&mut self) -> Result<Health> {
+        let now = Time::now();
+        self.get_super_block(0)?;
+        let read_latency = now.elapsed();
+
+        let id = Eid::new();
+        let now = Time::now();
+        let write_latency = match self.put_wal(&id, &[0u8; 8]) {
+            Ok(_) => {
+                self.del_wal(&id)?;
+                Some(now.elapsed())
+            }
+            Err(Error::ReadOnly) => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Health {
+            read_latency,
+            write_latency,
+        })
+    }
 }
 
 /// Dummy storage
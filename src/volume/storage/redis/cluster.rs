@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use redis::{
+    self, Client, Cmd, Connection, ConnectionAddr, ConnectionInfo, ErrorKind,
+    FromRedisValue, RedisConnectionInfo, RedisError, Value,
+};
+
+use super::redis::{parse_nodes, Node};
+use error::{Error, Result};
+
+// default redis port
+const DEFAULT_PORT: u16 = 6379;
+
+// number of hash slots in a cluster
+const SLOTS: u16 = 16384;
+
+// max number of redirections for one command
+const MAX_REDIRECTS: usize = 5;
+
+// CRC16 (XMODEM) used by cluster key hashing
+fn crc16(buf: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in buf {
+        crc ^= u16::from(b) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+// get hash slot of a key, only the hash tag in key is hashed if present
+fn key_slot(key: &str) -> u16 {
+    let key = key.as_bytes();
+    let tag = key.iter().position(|&c| c == b'{').and_then(|open| {
+        key[open + 1..]
+            .iter()
+            .position(|&c| c == b'}')
+            .filter(|&len| len > 0)
+            .map(|len| &key[open + 1..open + 1 + len])
+    });
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+// get node address from a MOVED or ASK redirection error
+fn redirect_addr(err: &RedisError) -> Result<String> {
+    err.redirect_node()
+        .map(|(host, port)| format!("{}:{}", host, port))
+        .ok_or(Error::InvalidUri)
+}
+
+// parse node address in "<host>:<port>" format
+fn parse_addr(addr: &str) -> Result<(String, u16)> {
+    let idx = addr.rfind(':').ok_or(Error::InvalidUri)?;
+    let port = addr[idx + 1..]
+        .parse::<u16>()
+        .map_err(|_| Error::InvalidUri)?;
+    Ok((addr[..idx].to_string(), port))
+}
+
+// slot range served by a master node
+#[derive(Debug, Clone)]
+struct SlotRange {
+    start: u16,
+    end: u16,
+    addr: String,
+}
+
+impl SlotRange {
+    // parse a CLUSTER SLOTS reply item, which is:
+    // [start slot, end slot, [master host, master port, ...], replicas...]
+    fn parse(item: &Value) -> Option<Self> {
+        let items = match *item {
+            Value::Bulk(ref items) if items.len() >= 3 => items,
+            _ => return None,
+        };
+        let master = match items[2] {
+            Value::Bulk(ref master) if master.len() >= 2 => master,
+            _ => return None,
+        };
+        match (&items[0], &items[1], &master[0], &master[1]) {
+            (
+                Value::Int(start),
+                Value::Int(end),
+                Value::Data(host),
+                Value::Int(port),
+            ) => Some(SlotRange {
+                start: *start as u16,
+                end: *end as u16,
+                addr: format!("{}:{}", String::from_utf8_lossy(host), port),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Redis Cluster configuration
+#[derive(Debug, Clone)]
+pub struct ClusterCfg {
+    nodes: Vec<Node>,
+    redis: RedisConnectionInfo, // authentication info
+}
+
+impl ClusterCfg {
+    // location format:
+    // [[<user>]:<passwd>@]<host>[:port][,<host>[:port]...]
+    pub fn new(loc: &str) -> Result<Self> {
+        let (redis, nodes, path) = parse_nodes(loc, DEFAULT_PORT)?;
+        if !path.is_empty() {
+            // cluster only supports database 0
+            return Err(Error::InvalidUri);
+        }
+        Ok(ClusterCfg { nodes, redis })
+    }
+
+    fn connect_node(&self, host: &str, port: u16) -> Result<Connection> {
+        let info = ConnectionInfo {
+            addr: ConnectionAddr::Tcp(host.to_string(), port),
+            redis: self.redis.clone(),
+        };
+        let conn = Client::open(info)?.get_connection()?;
+        Ok(conn)
+    }
+
+    pub fn connect(&self) -> Result<ClusterConn> {
+        let mut conn = ClusterConn {
+            cfg: self.clone(),
+            slots: Vec::new(),
+            conns: HashMap::new(),
+        };
+        conn.refresh_slots()?;
+        Ok(conn)
+    }
+}
+
+/// Connection to Redis Cluster
+///
+/// Commands are routed to the node serving the key's hash slot, the slot
+/// map is refreshed when the cluster redirects a command to other node.
+pub struct ClusterConn {
+    cfg: ClusterCfg,
+    slots: Vec<SlotRange>,
+    conns: HashMap<String, Connection>,
+}
+
+impl ClusterConn {
+    // load slot map from any reachable node
+    fn refresh_slots(&mut self) -> Result<()> {
+        let mut last_err =
+            RedisError::from((ErrorKind::ResponseError, "No cluster node"));
+
+        for &(ref host, port) in self.cfg.nodes.iter() {
+            let ret = self.cfg.connect_node(host, port).and_then(|mut conn| {
+                redis::cmd("CLUSTER")
+                    .arg("SLOTS")
+                    .query::<Value>(&mut conn)
+                    .map_err(Error::from)
+            });
+            match ret {
+                Ok(Value::Bulk(items)) => {
+                    self.slots =
+                        items.iter().filter_map(SlotRange::parse).collect();
+                    return Ok(());
+                }
+                Ok(_) => {
+                    last_err = RedisError::from((
+                        ErrorKind::TypeError,
+                        "Invalid cluster slots",
+                    ));
+                }
+                Err(Error::Redis(err)) => {
+                    warn!("Redis node {}:{} failed: {}", host, port, err);
+                    last_err = err;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(Error::from(last_err))
+    }
+
+    // get address of the node serving a slot
+    fn slot_addr(&mut self, slot: u16) -> Result<String> {
+        let find = |slots: &[SlotRange]| {
+            slots
+                .iter()
+                .find(|range| range.start <= slot && slot <= range.end)
+                .map(|range| range.addr.clone())
+        };
+        if let Some(addr) = find(&self.slots) {
+            return Ok(addr);
+        }
+        self.refresh_slots()?;
+        find(&self.slots).ok_or_else(|| {
+            Error::from(RedisError::from((
+                ErrorKind::ResponseError,
+                "Cluster slot not served",
+            )))
+        })
+    }
+
+    // get connection to a node, connect to it if not connected yet
+    fn node_conn(&mut self, addr: &str) -> Result<&mut Connection> {
+        if !self.conns.contains_key(addr) {
+            let (host, port) = parse_addr(addr)?;
+            let conn = self.cfg.connect_node(&host, port)?;
+            self.conns.insert(addr.to_string(), conn);
+        }
+        Ok(self.conns.get_mut(addr).unwrap())
+    }
+
+    // run command on the node serving the key
+    pub fn query<T: FromRedisValue>(
+        &mut self,
+        cmd: &Cmd,
+        key: &str,
+    ) -> Result<T> {
+        let slot = key_slot(key);
+        let mut addr = self.slot_addr(slot)?;
+        let mut asking = false;
+
+        for _ in 0..MAX_REDIRECTS {
+            let ret = {
+                let conn = self.node_conn(&addr)?;
+                if asking {
+                    redis::cmd("ASKING").query::<()>(conn)?;
+                }
+                cmd.query(conn)
+            };
+
+            match ret {
+                Ok(val) => return Ok(val),
+                Err(err) => match err.kind() {
+                    ErrorKind::Moved => {
+                        // slot has been moved, reload slot map
+                        addr = redirect_addr(&err)?;
+                        asking = false;
+                        self.refresh_slots()?;
+                    }
+                    ErrorKind::Ask => {
+                        // slot is being migrated, ask the target node once
+                        addr = redirect_addr(&err)?;
+                        asking = true;
+                    }
+                    _ if err.is_io_error() => {
+                        // node is down, drop its connection and re-route
+                        warn!("Redis node {} failed: {}", addr, err);
+                        self.conns.remove(&addr);
+                        self.refresh_slots()?;
+                        addr = self.slot_addr(slot)?;
+                        asking = false;
+                    }
+                    _ => return Err(Error::from(err)),
+                },
+            }
+        }
+
+        Err(Error::from(RedisError::from((
+            ErrorKind::ResponseError,
+            "Too many cluster redirections",
+        ))))
+    }
+
+    // run command on all master nodes
+    pub fn query_all<T: FromRedisValue>(
+        &mut self,
+        cmd: &Cmd,
+    ) -> Result<Vec<T>> {
+        let mut addrs: Vec<String> =
+            self.slots.iter().map(|range| range.addr.clone()).collect();
+        addrs.sort();
+        addrs.dedup();
+
+        let mut ret = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let conn = self.node_conn(&addr)?;
+            ret.push(cmd.query(conn)?);
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cluster_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(key_slot("{foo}.bar"), 12182);
+        assert_eq!(key_slot("{}foo"), crc16(b"{}foo") % SLOTS);
+        assert!(key_slot("block:42") < SLOTS);
+    }
+
+    #[test]
+    fn cluster_cfg() {
+        let cfg = ClusterCfg::new(":pwd@host1:7000,host2").unwrap();
+        assert_eq!(
+            cfg.nodes,
+            vec![
+                ("host1".to_string(), 7000),
+                ("host2".to_string(), DEFAULT_PORT)
+            ]
+        );
+        assert_eq!(cfg.redis.password, Some("pwd".to_string()));
+        assert!(ClusterCfg::new("host1/1").is_err());
+        assert!(ClusterCfg::new("host1:x").is_err());
+        assert_eq!(
+            parse_addr("10.0.0.1:7001").unwrap(),
+            ("10.0.0.1".to_string(), 7001)
+        );
+    }
+}
@@ -1,3 +1,5 @@
+mod cluster;
 mod redis;
+mod sentinel;
 
 pub use self::redis::RedisStorage;
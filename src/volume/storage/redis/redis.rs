@@ -1,14 +1,20 @@
 use std::fmt::{self, Debug};
 use std::sync::Mutex;
+use std::thread;
 
-use redis::{self, Client, Commands, Connection};
+use redis::{
+    self, Client, Cmd, Connection, FromRedisValue, RedisConnectionInfo,
+};
+
+use super::cluster::{ClusterCfg, ClusterConn};
+use super::sentinel::{SentinelCfg, SentinelConn};
 
 use base::crypto::{Crypto, Key};
 use base::IntoRef;
 use error::{Error, Result};
 use trans::Eid;
 use volume::address::Span;
-use volume::storage::Storable;
+use volume::storage::{RetryClass, RetryPolicy, Space, Storable};
 use volume::BLK_SIZE;
 
 // redis key for repo lock
@@ -41,11 +47,119 @@ fn blk_key(blk_idx: usize) -> String {
     format!("block:{}", blk_idx)
 }
 
+// redis node host and port
+pub(super) type Node = (String, u16);
+
+// parse redis nodes in location
+// format: [[<user>]:<passwd>@]<host>[:port][,<host>[:port]...][/<path>]
+// return: (authentication info, list of (host, port), path)
+pub(super) fn parse_nodes(
+    loc: &str,
+    default_port: u16,
+) -> Result<(RedisConnectionInfo, Vec<Node>, &str)> {
+    let mut auth = RedisConnectionInfo::default();
+    let loc = match loc.rfind('@') {
+        Some(idx) => {
+            let sep = loc[..idx].find(':').ok_or(Error::InvalidUri)?;
+            if sep > 0 {
+                auth.username = Some(loc[..sep].to_string());
+            }
+            auth.password = Some(loc[sep + 1..idx].to_string());
+            &loc[idx + 1..]
+        }
+        None => loc,
+    };
+
+    let (hosts, path) = match loc.find('/') {
+        Some(idx) => (&loc[..idx], &loc[idx + 1..]),
+        None => (loc, ""),
+    };
+
+    let mut nodes = Vec::new();
+    for host in hosts.split(',') {
+        let node = match host.rfind(':') {
+            Some(idx) => {
+                let port = host[idx + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| Error::InvalidUri)?;
+                (host[..idx].to_string(), port)
+            }
+            None => (host.to_string(), default_port),
+        };
+        if node.0.is_empty() {
+            return Err(Error::InvalidUri);
+        }
+        nodes.push(node);
+    }
+
+    Ok((auth, nodes, path))
+}
+
+// redis deployment to connect to
+enum Target {
+    Single(Client),
+    Sentinel(SentinelCfg),
+    Cluster(ClusterCfg),
+}
+
+impl Target {
+    fn connect(&self) -> Result<Conn> {
+        match *self {
+            Target::Single(ref client) => {
+                Ok(Conn::Single(client.get_connection()?))
+            }
+            Target::Sentinel(ref cfg) => Ok(Conn::Sentinel(cfg.connect()?)),
+            Target::Cluster(ref cfg) => Ok(Conn::Cluster(cfg.connect()?)),
+        }
+    }
+}
+
+// redis connection
+enum Conn {
+    Single(Connection),
+    Sentinel(SentinelConn),
+    Cluster(ClusterConn),
+}
+
+impl Conn {
+    // run command on a key
+    fn query<T: FromRedisValue>(&mut self, cmd: &Cmd, key: &str) -> Result<T> {
+        match *self {
+            Conn::Single(ref mut conn) => cmd.query(conn).map_err(Error::from),
+            Conn::Sentinel(ref mut conn) => conn.query(cmd),
+            Conn::Cluster(ref mut conn) => conn.query(cmd, key),
+        }
+    }
+
+    // run command on all master nodes
+    fn query_all<T: FromRedisValue>(&mut self, cmd: &Cmd) -> Result<Vec<T>> {
+        match *self {
+            Conn::Single(ref mut conn) => Ok(vec![cmd.query(conn)?]),
+            Conn::Sentinel(ref mut conn) => Ok(vec![conn.query(cmd)?]),
+            Conn::Cluster(ref mut conn) => conn.query_all(cmd),
+        }
+    }
+
+    fn exists(&mut self, key: &str) -> Result<bool> {
+        self.query(redis::cmd("EXISTS").arg(key), key)
+    }
+}
+
+// check if error is caused by broken connection to redis
+#[inline]
+fn is_transport_error(err: &Error) -> bool {
+    match *err {
+        Error::Redis(ref err) => err.is_io_error(),
+        _ => false,
+    }
+}
+
 /// Redis Storage
 pub struct RedisStorage {
     is_attached: bool, // attached to redis
-    client: Client,
-    conn: Option<Mutex<Connection>>,
+    target: Target,
+    retry: RetryPolicy,
+    conn: Option<Mutex<Conn>>,
 }
 
 impl RedisStorage {
@@ -59,48 +173,89 @@ impl RedisStorage {
             format!("redis://{}", path)
         };
         let client = Client::open(url.as_str())?;
+        Ok(Self::with_target(Target::Single(client)))
+    }
 
-        Ok(RedisStorage {
+    // create storage on redis using TLS connection
+    // url format:
+    // rediss://[[<user>]:<passwd>@]<hostname>[:port][/<db>][#insecure]
+    #[cfg(feature = "storage-redis-tls")]
+    pub fn new_tls(path: &str) -> Result<Self> {
+        let url = format!("rediss://{}", path);
+        let client = Client::open(url.as_str())?;
+        Ok(Self::with_target(Target::Single(client)))
+    }
+
+    // create storage on master monitored by redis sentinels
+    pub fn new_sentinel(loc: &str) -> Result<Self> {
+        let cfg = SentinelCfg::new(loc)?;
+        Ok(Self::with_target(Target::Sentinel(cfg)))
+    }
+
+    // create storage on redis cluster
+    pub fn new_cluster(loc: &str) -> Result<Self> {
+        let cfg = ClusterCfg::new(loc)?;
+        Ok(Self::with_target(Target::Cluster(cfg)))
+    }
+
+    fn with_target(target: Target) -> Self {
+        RedisStorage {
             is_attached: false,
-            client,
+            target,
+            retry: RetryPolicy::default(),
             conn: None,
-        })
+        }
     }
 
-    fn get_bytes(&self, key: &str) -> Result<Vec<u8>> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                if !conn.exists::<&str, bool>(key)? {
-                    return Err(Error::NotFound);
+    // run commands on connection, retry on transport errors as per retry
+    // policy, connection is re-established before each retry
+    fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut Conn) -> Result<T>,
+    {
+        let mut conn = match self.conn {
+            Some(ref conn) => conn.lock().unwrap(),
+            None => unreachable!(),
+        };
+        let mut attempt = 1;
+
+        loop {
+            match f(&mut conn) {
+                Err(ref err)
+                    if is_transport_error(err)
+                        && self
+                            .retry
+                            .should_retry(RetryClass::Transport, attempt) =>
+                {
+                    debug!("redis command failed: {}, retry", err);
                 }
-                let ret = conn.get(key)?;
-                Ok(ret)
+                result => return result,
+            }
+
+            thread::sleep(self.retry.backoff(attempt));
+            attempt += 1;
+            match self.target.connect() {
+                Ok(new_conn) => *conn = new_conn,
+                Err(err) => debug!("redis reconnect failed: {}", err),
             }
-            None => unreachable!(),
         }
     }
 
-    fn set_bytes(&self, key: &str, val: &[u8]) -> Result<()> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                conn.set(key, val)?;
-                Ok(())
+    fn get_bytes(&self, key: &str) -> Result<Vec<u8>> {
+        self.run(|conn| {
+            if !conn.exists(key)? {
+                return Err(Error::NotFound);
             }
-            None => unreachable!(),
-        }
+            conn.query(redis::cmd("GET").arg(key), key)
+        })
+    }
+
+    fn set_bytes(&self, key: &str, val: &[u8]) -> Result<()> {
+        self.run(|conn| conn.query(redis::cmd("SET").arg(key).arg(val), key))
     }
 
     fn del(&self, key: &str) -> Result<()> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                conn.del(key)?;
-                Ok(())
-            }
-            None => unreachable!(),
-        }
+        self.run(|conn| conn.query(redis::cmd("DEL").arg(key), key))
     }
 
     fn lock_repo(&mut self, force: bool) -> Result<()> {
@@ -126,17 +281,22 @@ impl RedisStorage {
 impl Storable for RedisStorage {
     fn exists(&self) -> Result<bool> {
         // check super block existence to determine if repo exists
-        let mut conn = self.client.get_connection()?;
+        let mut conn = self.target.connect()?;
         let key = super_blk_key(0);
-        conn.exists::<&str, bool>(&key).map_err(Error::from)
+        conn.exists(&key)
     }
 
     fn connect(&mut self, _force: bool) -> Result<()> {
-        let conn = self.client.get_connection()?;
+        let conn = self.target.connect()?;
         self.conn = Some(Mutex::new(conn));
         Ok(())
     }
 
+    #[inline]
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
     #[inline]
     fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
         self.lock_repo(false)
@@ -240,11 +400,50 @@ impl Storable for RedisStorage {
             warn!("Destroy an opened repo");
         }
 
+        self.run(|conn| conn.query_all::<()>(&redis::cmd("FLUSHDB")))?;
+        Ok(())
+    }
+
+    fn space(&mut self, _used_blocks: usize) -> Result<Space> {
         match self.conn {
             Some(ref conn) => {
                 let mut conn = conn.lock().unwrap();
-                redis::cmd("FLUSHDB").execute(&mut *conn);
-                Ok(())
+
+                // get used memory from the 'used_memory' field in server
+                // info, summed up across all master nodes
+                let infos: Vec<String> =
+                    conn.query_all(redis::cmd("INFO").arg("memory"))?;
+                let used = infos
+                    .iter()
+                    .map(|info| {
+                        info.lines()
+                            .find(|line| line.starts_with("used_memory:"))
+                            .and_then(|line| {
+                                line.trim()[12..].parse::<u64>().ok()
+                            })
+                            .unwrap_or(0)
+                    })
+                    .sum();
+
+                // max memory zero means no memory limit, total is unknown
+                // if any node has no memory limit, or if CONFIG command is
+                // disabled, which is common on managed Redis services
+                let total = conn
+                    .query_all::<Vec<String>>(
+                        redis::cmd("CONFIG").arg("GET").arg("maxmemory"),
+                    )
+                    .ok()
+                    .and_then(|cfgs| {
+                        cfgs.iter()
+                            .map(|cfg| {
+                                cfg.get(1)
+                                    .and_then(|val| val.parse::<u64>().ok())
+                                    .filter(|max| *max > 0)
+                            })
+                            .sum()
+                    });
+
+                Ok(Space { total, used })
             }
             None => unreachable!(),
         }
@@ -276,6 +475,35 @@ mod tests {
     use super::*;
     use base::init_env;
 
+    #[test]
+    fn redis_parse_nodes() {
+        let (auth, nodes, path) =
+            parse_nodes("user:pwd@host1,host2:1234/foo", 6379).unwrap();
+        assert_eq!(auth.username, Some("user".to_string()));
+        assert_eq!(auth.password, Some("pwd".to_string()));
+        assert_eq!(
+            nodes,
+            vec![("host1".to_string(), 6379), ("host2".to_string(), 1234)]
+        );
+        assert_eq!(path, "foo");
+
+        let (auth, _, path) = parse_nodes(":pwd@host1", 6379).unwrap();
+        assert_eq!(auth.username, None);
+        assert_eq!(auth.password, Some("pwd".to_string()));
+        assert_eq!(path, "");
+
+        assert!(parse_nodes("pwd@host1", 6379).is_err());
+        assert!(parse_nodes("host1,", 6379).is_err());
+    }
+
+    #[cfg(feature = "storage-redis-tls")]
+    #[test]
+    fn redis_tls_uri() {
+        assert!(RedisStorage::new_tls("user:pwd@localhost:6380/1").is_ok());
+        assert!(RedisStorage::new_tls("localhost#insecure").is_ok());
+        assert!(RedisStorage::new_tls("localhost#foo").is_err());
+    }
+
     #[test]
     fn redis_storage() {
         init_env();
@@ -0,0 +1,158 @@
+use redis::{
+    self, Client, Cmd, Connection, ConnectionAddr, ConnectionInfo, ErrorKind,
+    FromRedisValue, RedisConnectionInfo, RedisError,
+};
+
+use super::redis::{parse_nodes, Node};
+use error::{Error, Result};
+
+// default sentinel port
+const DEFAULT_PORT: u16 = 26379;
+
+/// Redis Sentinel configuration
+#[derive(Debug, Clone)]
+pub struct SentinelCfg {
+    sentinels: Vec<Node>,
+    master: String,
+    redis: RedisConnectionInfo, // master authentication and database
+}
+
+impl SentinelCfg {
+    // location format:
+    // [[<user>]:<passwd>@]<host>[:port][,<host>[:port]...]/<master>[/<db>]
+    pub fn new(loc: &str) -> Result<Self> {
+        let (mut redis, sentinels, path) = parse_nodes(loc, DEFAULT_PORT)?;
+
+        let mut parts = path.splitn(2, '/');
+        let master = parts.next().unwrap_or("");
+        if master.is_empty() {
+            return Err(Error::InvalidUri);
+        }
+        if let Some(db) = parts.next().filter(|db| !db.is_empty()) {
+            redis.db = db.parse::<i64>().map_err(|_| Error::InvalidUri)?;
+        }
+
+        Ok(SentinelCfg {
+            sentinels,
+            master: master.to_string(),
+            redis,
+        })
+    }
+
+    // ask sentinels for the current master address
+    fn discover(&self) -> Result<Node> {
+        let mut last_err =
+            RedisError::from((ErrorKind::ResponseError, "No sentinel found"));
+
+        for &(ref host, port) in self.sentinels.iter() {
+            let info = ConnectionInfo {
+                addr: ConnectionAddr::Tcp(host.clone(), port),
+                redis: RedisConnectionInfo::default(),
+            };
+            let ret = Client::open(info)
+                .and_then(|client| client.get_connection())
+                .and_then(|mut conn| {
+                    redis::cmd("SENTINEL")
+                        .arg("get-master-addr-by-name")
+                        .arg(&self.master)
+                        .query::<Option<Node>>(&mut conn)
+                });
+            match ret {
+                Ok(Some(addr)) => return Ok(addr),
+                Ok(None) => {
+                    last_err = RedisError::from((
+                        ErrorKind::ResponseError,
+                        "Redis master not found",
+                    ));
+                }
+                Err(err) => {
+                    warn!("Redis sentinel {}:{} failed: {}", host, port, err);
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(Error::from(last_err))
+    }
+
+    // discover master and connect to it
+    fn connect_master(&self) -> Result<Connection> {
+        let (host, port) = self.discover()?;
+        debug!("Redis master {} is at {}:{}", self.master, host, port);
+        let info = ConnectionInfo {
+            addr: ConnectionAddr::Tcp(host, port),
+            redis: self.redis.clone(),
+        };
+        let conn = Client::open(info)?.get_connection()?;
+        Ok(conn)
+    }
+
+    pub fn connect(&self) -> Result<SentinelConn> {
+        let conn = self.connect_master()?;
+        Ok(SentinelConn {
+            cfg: self.clone(),
+            conn,
+        })
+    }
+}
+
+// check if error is caused by master failover
+fn is_failover(err: &RedisError) -> bool {
+    err.is_io_error() || err.kind() == ErrorKind::ReadOnly
+}
+
+/// Connection to the master monitored by Redis Sentinel
+///
+/// If the master is failed over, the new master will be discovered and
+/// command will be re-tried on it.
+pub struct SentinelConn {
+    cfg: SentinelCfg,
+    conn: Connection,
+}
+
+impl SentinelConn {
+    pub fn query<T: FromRedisValue>(&mut self, cmd: &Cmd) -> Result<T> {
+        match cmd.query(&mut self.conn) {
+            Err(ref err) if is_failover(err) => {
+                warn!("Redis master failed: {}, re-discovering", err);
+                self.conn = self.cfg.connect_master()?;
+                cmd.query(&mut self.conn).map_err(Error::from)
+            }
+            ret => ret.map_err(Error::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentinel_cfg() {
+        let cfg = SentinelCfg::new(":pwd@host1,host2:1234/mymaster/2").unwrap();
+        assert_eq!(
+            cfg.sentinels,
+            vec![
+                ("host1".to_string(), DEFAULT_PORT),
+                ("host2".to_string(), 1234)
+            ]
+        );
+        assert_eq!(cfg.master, "mymaster");
+        assert_eq!(cfg.redis.username, None);
+        assert_eq!(cfg.redis.password, Some("pwd".to_string()));
+        assert_eq!(cfg.redis.db, 2);
+
+        let cfg = SentinelCfg::new("user:pwd@host1/mymaster").unwrap();
+        assert_eq!(cfg.redis.username, Some("user".to_string()));
+        assert_eq!(cfg.redis.password, Some("pwd".to_string()));
+        assert_eq!(cfg.redis.db, 0);
+
+        let cfg = SentinelCfg::new("host1/mymaster").unwrap();
+        assert_eq!(cfg.redis.password, None);
+
+        assert!(SentinelCfg::new("host1").is_err());
+        assert!(SentinelCfg::new("host1/").is_err());
+        assert!(SentinelCfg::new("host1/mymaster/x").is_err());
+        assert!(SentinelCfg::new("pwd@host1/mymaster").is_err());
+    }
+}
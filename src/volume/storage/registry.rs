@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use super::Storable;
+use error::{Error, Result};
+
+/// Storage factory.
+///
+/// A storage factory creates a storage from the location part of URI, that
+/// is the part after `://`.
+pub type StorageFactory =
+    Box<dyn Fn(&str) -> Result<Box<dyn Storable>> + Send + Sync>;
+
+// built-in storage schemes, which cannot be registered
+const BUILTIN_SCHEMES: [&str; 14] = [
+    "mem",
+    "file",
+    "container",
+    "http",
+    "https",
+    "sqlite",
+    "sqlcipher",
+    "redis",
+    "rediss",
+    "gs",
+    "sled",
+    "stripe",
+    "faulty",
+    "zbox",
+];
+
+lazy_static! {
+    // registered custom storage factories
+    static ref REGISTRY: RwLock<HashMap<String, StorageFactory>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Register a custom storage scheme.
+///
+/// After registration, repository URI starts with `scheme://` will use the
+/// `factory` to create its underlying storage. The factory is called with
+/// the location part of URI, that is the part after `://`.
+///
+/// Scheme can only contain ASCII letters, digits, `-` and `.`.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if the scheme is invalid, or
+/// `Error::AlreadyExists` if the scheme is built-in or already registered.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use zbox::{register_storage, Result, Storable};
+/// # fn create_storage(loc: &str) -> Result<Box<dyn Storable>> {
+/// #     unimplemented!()
+/// # }
+/// register_storage("myscheme", create_storage).unwrap();
+/// ```
+pub fn register_storage<F>(scheme: &str, factory: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<Box<dyn Storable>> + Send + Sync + 'static,
+{
+    if scheme.is_empty()
+        || !scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(Error::InvalidArgument);
+    }
+    if BUILTIN_SCHEMES.contains(&scheme) {
+        return Err(Error::AlreadyExists);
+    }
+
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.contains_key(scheme) {
+        return Err(Error::AlreadyExists);
+    }
+    registry.insert(scheme.to_string(), Box::new(factory));
+
+    Ok(())
+}
+
+// create storage using registered factory
+pub(super) fn create_storage(
+    scheme: &str,
+    loc: &str,
+) -> Result<Box<dyn Storable>> {
+    let registry = REGISTRY.read().unwrap();
+    match registry.get(scheme) {
+        Some(factory) => factory(loc),
+        None => Err(Error::InvalidUri),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        assert_eq!(
+            register_storage("", |_| Err(Error::NotFound)).unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            register_storage("a+b", |_| Err(Error::NotFound)).unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            register_storage("file", |_| Err(Error::NotFound)).unwrap_err(),
+            Error::AlreadyExists
+        );
+
+        register_storage("registry.test", |loc| {
+            assert_eq!(loc, "foo");
+            Err(Error::NotFound)
+        })
+        .unwrap();
+        assert_eq!(
+            register_storage("registry.test", |_| Err(Error::NotFound))
+                .unwrap_err(),
+            Error::AlreadyExists
+        );
+        assert_eq!(
+            create_storage("registry.test", "foo").unwrap_err(),
+            Error::NotFound
+        );
+        assert_eq!(
+            create_storage("registry.unknown", "foo").unwrap_err(),
+            Error::InvalidUri
+        );
+    }
+}
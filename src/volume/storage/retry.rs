@@ -0,0 +1,119 @@
+use std::cmp::min;
+use std::time::Duration;
+
+/// Class of failed storage request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    /// Transport error, such as connection failure and timeout
+    Transport,
+
+    /// Request is throttled by storage, such as HTTP status 429
+    Throttled,
+
+    /// Storage server error, such as HTTP status 5xx
+    ServerError,
+}
+
+/// Retry policy for network-based storages
+///
+/// Failed requests are retried with exponential backoff. The delay before
+/// the first retry is `initial_delay`, and it is doubled on each following
+/// retry until `max_delay` is reached.
+///
+/// The default policy makes at most 6 attempts for a request, starts with
+/// 200 milliseconds delay capped at 10 seconds, and retries all classes of
+/// failure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts for a request, including the first one
+    pub max_attempts: usize,
+
+    /// Backoff delay before the first retry
+    pub initial_delay: Duration,
+
+    /// Maximum backoff delay between retries
+    pub max_delay: Duration,
+
+    /// Retry on transport errors
+    pub retry_transport: bool,
+
+    /// Retry on throttled requests
+    pub retry_throttled: bool,
+
+    /// Retry on storage server errors
+    pub retry_server_error: bool,
+}
+
+impl RetryPolicy {
+    /// Create a policy which never retries.
+    #[inline]
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether a request should be retried after its `attempt`-th
+    /// attempt failed with `class`, `attempt` starts from 1.
+    pub fn should_retry(&self, class: RetryClass, attempt: usize) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match class {
+            RetryClass::Transport => self.retry_transport,
+            RetryClass::Throttled => self.retry_throttled,
+            RetryClass::ServerError => self.retry_server_error,
+        }
+    }
+
+    /// Returns the backoff delay after the `attempt`-th attempt failed,
+    /// `attempt` starts from 1.
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let shift = min(attempt.saturating_sub(1), 31) as u32;
+        self.initial_delay
+            .checked_mul(1 << shift)
+            .map_or(self.max_delay, |delay| min(delay, self.max_delay))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 6,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            retry_transport: true,
+            retry_throttled: true,
+            retry_server_error: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_policy() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(RetryClass::Transport, 1));
+        assert!(policy.should_retry(RetryClass::ServerError, 5));
+        assert!(!policy.should_retry(RetryClass::Throttled, 6));
+        assert!(!RetryPolicy::never().should_retry(RetryClass::Transport, 1));
+
+        let policy = RetryPolicy {
+            retry_server_error: false,
+            ..Default::default()
+        };
+        assert!(policy.should_retry(RetryClass::Throttled, 1));
+        assert!(!policy.should_retry(RetryClass::ServerError, 1));
+
+        // exponential backoff capped by max delay
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(1600));
+        assert_eq!(policy.backoff(7), Duration::from_secs(10));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+}
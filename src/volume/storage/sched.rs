@@ -0,0 +1,91 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Fair IO scheduler
+///
+/// Storage access is guarded by a single lock which doesn't guarantee any
+/// fairness, so a thread streaming a large file can repeatedly re-acquire it
+/// and starve the others. Readers take a ticket from this scheduler before
+/// accessing storage and are served in the ticket order, thus concurrent
+/// readers get round-robin access to storage at frame granularity.
+#[derive(Debug, Default)]
+pub struct IoSched {
+    // next ticket number and the ticket number now serving
+    tickets: Mutex<(u64, u64)>,
+    cond: Condvar,
+}
+
+impl IoSched {
+    // wait for turn to access storage, the turn is ended when the returned
+    // guard is dropped
+    pub fn turn(&self) -> IoTurn<'_> {
+        let mut tickets = self.tickets.lock().unwrap();
+        let ticket = tickets.0;
+        tickets.0 = tickets.0.wrapping_add(1);
+        while tickets.1 != ticket {
+            tickets = self.cond.wait(tickets).unwrap();
+        }
+        IoTurn { sched: self }
+    }
+
+    // end current turn and wake up the waiting readers
+    fn end_turn(&self) {
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.1 = tickets.1.wrapping_add(1);
+        self.cond.notify_all();
+    }
+}
+
+/// IO scheduler reference type
+pub type IoSchedRef = Arc<IoSched>;
+
+/// IO turn guard
+#[derive(Debug)]
+pub struct IoTurn<'a> {
+    sched: &'a IoSched,
+}
+
+impl<'a> Drop for IoTurn<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.sched.end_turn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn io_sched() {
+        let sched = Arc::new(IoSched::default());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // hold a turn, so the readers below must wait in queue
+        let turn = sched.turn();
+
+        let mut children = Vec::new();
+        for i in 0..4 {
+            let sched = sched.clone();
+            let order = order.clone();
+            children.push(thread::spawn(move || {
+                let _turn = sched.turn();
+                order.lock().unwrap().push(i);
+            }));
+
+            // make sure the readers queue up in sequence
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(order.lock().unwrap().is_empty());
+
+        // end the turn and readers should be served in order
+        drop(turn);
+        for child in children {
+            child.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+}
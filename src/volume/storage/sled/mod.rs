@@ -0,0 +1,3 @@
+mod sled;
+
+pub use self::sled::SledStorage;
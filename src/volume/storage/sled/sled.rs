@@ -0,0 +1,353 @@
+use std::fmt::{self, Debug};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use sled::{Batch, Config, Db, Tree};
+
+use base::crypto::{Crypto, Key};
+use base::utils;
+use base::vio;
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{Space, Storable};
+use volume::BLK_SIZE;
+
+// key for repo lock in default tree
+const REPO_LOCK_KEY: &[u8] = b"repo_lock";
+
+// tree names
+const TREE_SUPER_BLOCK: &[u8] = b"super_block";
+const TREE_WALS: &[u8] = b"wals";
+const TREE_ADDRESSES: &[u8] = b"addresses";
+const TREE_BLOCKS: &[u8] = b"blocks";
+
+// block index key, big endian so blocks are ordered by index
+#[inline]
+fn blk_key(blk_idx: usize) -> [u8; 8] {
+    (blk_idx as u64).to_be_bytes()
+}
+
+// sled database and its trees
+struct Trees {
+    db: Db,
+    super_blk: Tree,
+    wals: Tree,
+    addrs: Tree,
+    blks: Tree,
+}
+
+impl Trees {
+    fn open(path: &Path) -> Result<Self> {
+        // periodic flush is disabled as flush is explicitly called when
+        // persistence is required, this also makes sure the db lock is
+        // released as soon as the storage is dropped
+        let db = Config::new().path(path).flush_every_ms(None).open()?;
+        let super_blk = db.open_tree(TREE_SUPER_BLOCK)?;
+        let wals = db.open_tree(TREE_WALS)?;
+        let addrs = db.open_tree(TREE_ADDRESSES)?;
+        let blks = db.open_tree(TREE_BLOCKS)?;
+        Ok(Trees {
+            db,
+            super_blk,
+            wals,
+            addrs,
+            blks,
+        })
+    }
+}
+
+/// Sled Storage
+pub struct SledStorage {
+    is_attached: bool, // attached to sled db
+    path: PathBuf,     // database directory path
+    trees: Option<Trees>,
+}
+
+impl SledStorage {
+    pub fn new(path: &Path) -> Self {
+        SledStorage {
+            is_attached: false,
+            path: path.to_path_buf(),
+            trees: None,
+        }
+    }
+
+    #[inline]
+    fn trees(&self) -> &Trees {
+        match self.trees {
+            Some(ref trees) => trees,
+            None => unreachable!(),
+        }
+    }
+
+    // get value from a tree
+    fn get_value<K: AsRef<[u8]>>(tree: &Tree, key: K) -> Result<Vec<u8>> {
+        match tree.get(key)? {
+            Some(val) => Ok(val.to_vec()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    fn lock_repo(&mut self, force: bool) -> Result<()> {
+        let db = &self.trees().db;
+        let locked = db
+            .compare_and_swap(REPO_LOCK_KEY, None as Option<&[u8]>, Some(&[1]))?
+            .is_err();
+        if locked {
+            // repo is locked
+            if force {
+                warn!("Repo was locked, forced to open");
+            } else {
+                return Err(Error::RepoOpened);
+            }
+        }
+        db.flush()?;
+        self.is_attached = true;
+        Ok(())
+    }
+}
+
+impl Storable for SledStorage {
+    fn exists(&self) -> Result<bool> {
+        match vio::metadata(&self.path) {
+            Ok(_) => Ok(true),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        if self.trees.is_none() {
+            self.trees = Some(Trees::open(&self.path)?);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+        self.lock_repo(false)
+    }
+
+    #[inline]
+    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+        self.lock_repo(force)
+    }
+
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        Self::get_value(&self.trees().super_blk, suffix.to_be_bytes())
+    }
+
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        let trees = self.trees();
+        trees.super_blk.insert(suffix.to_be_bytes(), super_blk)?;
+        trees.super_blk.flush()?;
+        Ok(())
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        Self::get_value(&self.trees().wals, id)
+    }
+
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        let trees = self.trees();
+        trees.wals.insert(id, wal)?;
+        trees.wals.flush()?;
+        Ok(())
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.trees().wals.remove(id)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        Self::get_value(&self.trees().addrs, id)
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.trees().addrs.insert(id, addr)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.trees().addrs.remove(id)?;
+        Ok(())
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let blks = &self.trees().blks;
+        let mut read = 0;
+        for blk_idx in span {
+            let blk = Self::get_value(blks, blk_key(blk_idx))?;
+            assert_eq!(blk.len(), BLK_SIZE);
+            dst[read..read + BLK_SIZE].copy_from_slice(&blk);
+            read += BLK_SIZE;
+        }
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
+        // write all blocks in one atomic batch
+        let mut batch = Batch::default();
+        for blk_idx in span {
+            batch.insert(&blk_key(blk_idx), &blks[..BLK_SIZE]);
+            blks = &blks[BLK_SIZE..];
+        }
+        self.trees().blks.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        let mut batch = Batch::default();
+        for blk_idx in span {
+            batch.remove(&blk_key(blk_idx));
+        }
+        self.trees().blks.apply_batch(batch)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.trees().db.flush()?;
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        if let Some(ref trees) = self.trees {
+            if trees.db.contains_key(REPO_LOCK_KEY)? {
+                // repo is locked
+                warn!("Destroy an opened repo");
+            }
+        }
+
+        // close db before removing its directory
+        self.trees.take();
+        self.is_attached = false;
+        vio::remove_dir_all(&self.path)?;
+        Ok(())
+    }
+
+    fn space(&mut self, _used_blocks: usize) -> Result<Space> {
+        let used = self.trees().db.size_on_disk()?;
+        let free = utils::disk_free_space(&self.path)?;
+        Ok(Space {
+            total: free.map(|free| used + free),
+            used,
+        })
+    }
+}
+
+impl Drop for SledStorage {
+    fn drop(&mut self) {
+        // release repo lock and ignore the result
+        if self.is_attached {
+            if let Some(ref trees) = self.trees {
+                let _ = trees.db.remove(REPO_LOCK_KEY);
+                let _ = trees.db.flush();
+            }
+            self.is_attached = false;
+        }
+    }
+}
+
+impl Debug for SledStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SledStorage")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+
+    use super::*;
+
+    use base::init_env;
+
+    #[test]
+    fn sled_storage() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let dir = tmpdir.path().join("storage");
+        let mut ss = SledStorage::new(&dir);
+
+        assert!(!ss.exists().unwrap());
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+        assert!(ss.exists().unwrap());
+
+        let id = Eid::new();
+        let buf = vec![1, 2, 3];
+        let blks = vec![42u8; BLK_SIZE * 3];
+        let mut dst = vec![0u8; BLK_SIZE * 3];
+
+        // super block
+        ss.put_super_block(&buf, 0).unwrap();
+        let s = ss.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+
+        // wal
+        ss.put_wal(&id, &buf).unwrap();
+        let s = ss.get_wal(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.del_wal(&id).unwrap();
+        assert_eq!(ss.get_wal(&id).unwrap_err(), Error::NotFound);
+
+        // address
+        ss.put_address(&id, &buf).unwrap();
+        let s = ss.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.del_address(&id).unwrap();
+        assert_eq!(ss.get_address(&id).unwrap_err(), Error::NotFound);
+
+        // block
+        let span = Span::new(0, 3);
+        ss.put_blocks(span, &blks).unwrap();
+        ss.get_blocks(&mut dst, span).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        ss.del_blocks(Span::new(1, 2)).unwrap();
+        assert_eq!(ss.get_blocks(&mut dst, span).unwrap_err(), Error::NotFound);
+        assert_eq!(
+            ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(1, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+        assert_eq!(
+            ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(2, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+        ss.flush().unwrap();
+
+        // re-open
+        drop(ss);
+        let mut ss = SledStorage::new(&dir);
+        ss.connect(false).unwrap();
+        ss.open(Crypto::default(), Key::new_empty(), false).unwrap();
+
+        let s = ss.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE], &blks[..BLK_SIZE]);
+        assert_eq!(
+            ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(1, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // destroy
+        ss.destroy().unwrap();
+        assert!(!ss.exists().unwrap());
+    }
+}
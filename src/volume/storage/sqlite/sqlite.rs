@@ -1,24 +1,75 @@
 use std::ffi::{CStr, CString};
 use std::fmt::{self, Debug};
 use std::os::raw::{c_int, c_void};
+use std::path::Path;
 use std::ptr;
-use std::thread::panicking;
+use std::str::FromStr;
+use std::thread::{self, panicking};
+use std::time::Duration;
 
 use libsqlite3_sys as ffi;
 
 use base::crypto::{Crypto, Key};
+use base::utils;
 use base::vio;
 use error::{Error, Result};
 use trans::Eid;
 use volume::address::Span;
-use volume::storage::Storable;
+use volume::storage::{Space, Storable};
 use volume::BLK_SIZE;
 
+// max number of retries when database is busy or locked
+const BUSY_RETRY_CNT: usize = 8;
+
+// initial delay before retry when database is busy, doubled on each retry
+const BUSY_RETRY_DELAY: u64 = 10; // in milliseconds
+
+// check if result code means database is busy or locked by others
+#[inline]
+fn is_busy(result: c_int) -> bool {
+    let code = result & 0xff; // primary result code
+    code == ffi::SQLITE_BUSY || code == ffi::SQLITE_LOCKED
+}
+
+// run sqlite function and retry with backoff if database is busy or locked,
+// return Error::Busy if it is still busy after all retries
+//
+// only beginning and committing transaction are retried, statements in a
+// transaction are not as the transaction must be rolled back on busy error
+fn retry_busy<F>(mut f: F) -> Result<c_int>
+where
+    F: FnMut() -> c_int,
+{
+    let mut delay = BUSY_RETRY_DELAY;
+    for _ in 0..BUSY_RETRY_CNT {
+        let result = f();
+        if !is_busy(result) {
+            return Ok(result);
+        }
+        thread::sleep(Duration::from_millis(delay));
+        delay *= 2;
+    }
+    let result = f();
+    if is_busy(result) {
+        warn!("sqlite database is busy, gave up after retries");
+        return Err(Error::Busy);
+    }
+    Ok(result)
+}
+
+// convert result code returned by sqlite to error
+fn to_error(result: c_int) -> Error {
+    if is_busy(result) {
+        Error::Busy
+    } else {
+        Error::from(ffi::Error::new(result))
+    }
+}
+
 // check result code returned by sqlite
 fn check_result(result: c_int) -> Result<()> {
     if result != ffi::SQLITE_OK {
-        let err = ffi::Error::new(result);
-        return Err(Error::from(err));
+        return Err(to_error(result));
     }
     Ok(())
 }
@@ -32,6 +83,20 @@ fn reset_stmt(stmt: *mut ffi::sqlite3_stmt) -> Result<()> {
     Ok(())
 }
 
+// step statement, the statement is reset if step failed so its error is not
+// reported again by next reset
+fn step_stmt(stmt: *mut ffi::sqlite3_stmt) -> Result<c_int> {
+    let result = unsafe { ffi::sqlite3_step(stmt) };
+    match result {
+        ffi::SQLITE_ROW | ffi::SQLITE_DONE => Ok(result),
+        _ => {
+            // reset gives the same error as step, which is returned here
+            unsafe { ffi::sqlite3_reset(stmt) };
+            Err(to_error(result))
+        }
+    }
+}
+
 // bind integer parameter
 fn bind_int(
     stmt: *mut ffi::sqlite3_stmt,
@@ -80,17 +145,36 @@ fn bind_blob(
 
 // run DML statement, such as INSERT and DELETE
 fn run_dml(stmt: *mut ffi::sqlite3_stmt) -> Result<()> {
-    let result = unsafe { ffi::sqlite3_step(stmt) };
-    match result {
+    match step_stmt(stmt)? {
         ffi::SQLITE_DONE => Ok(()),
-        _ => Err(Error::from(ffi::Error::new(result))),
+        result => Err(to_error(result)),
+    }
+}
+
+// run DML statement in its own transaction, so it can be retried if
+// database is busy
+fn run_dml_tx(
+    db: *mut ffi::sqlite3,
+    stmt: *mut ffi::sqlite3_stmt,
+) -> Result<()> {
+    run_in_tx(db, || run_dml(stmt))
+}
+
+// run SELECT statement and check if it returns any rows
+fn run_select_any(stmt: *mut ffi::sqlite3_stmt) -> Result<bool> {
+    match step_stmt(stmt)? {
+        ffi::SQLITE_ROW => {
+            // reset to end the read as the rest rows are not needed
+            check_result(unsafe { ffi::sqlite3_reset(stmt) })?;
+            Ok(true)
+        }
+        _ => Ok(false),
     }
 }
 
 // run SELECT statement on a blob column
 fn run_select_blob(stmt: *mut ffi::sqlite3_stmt) -> Result<Vec<u8>> {
-    let result = unsafe { ffi::sqlite3_step(stmt) };
-    match result {
+    match step_stmt(stmt)? {
         ffi::SQLITE_ROW => {
             //  get data and data size
             let (data, data_len) = unsafe {
@@ -109,10 +193,150 @@ fn run_select_blob(stmt: *mut ffi::sqlite3_stmt) -> Result<Vec<u8>> {
                     data_len,
                 );
             }
+
+            // reset to end the read, so it doesn't hold the shared lock
+            check_result(unsafe { ffi::sqlite3_reset(stmt) })?;
             Ok(ret)
         }
-        ffi::SQLITE_DONE => Err(Error::NotFound),
-        _ => Err(Error::from(ffi::Error::new(result))),
+        _ => Err(Error::NotFound),
+    }
+}
+
+// run sql statements which don't return data
+fn exec_sql(db: *mut ffi::sqlite3, sql: &str) -> Result<()> {
+    let sql = CString::new(sql).unwrap();
+    let result = unsafe {
+        ffi::sqlite3_exec(
+            db,
+            sql.as_ptr(),
+            None,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    check_result(result)
+}
+
+// run sql statements which don't return data, retry if database is busy
+fn exec_sql_retry(db: *mut ffi::sqlite3, sql: &str) -> Result<()> {
+    let sql = CString::new(sql).unwrap();
+    let result = retry_busy(|| unsafe {
+        ffi::sqlite3_exec(
+            db,
+            sql.as_ptr(),
+            None,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    })?;
+    check_result(result)
+}
+
+// run closure in a transaction, roll back if it failed
+//
+// the transaction is immediate so write lock is taken when it begins, thus
+// only beginning and committing need to be retried when database is busy
+fn run_in_tx<F>(db: *mut ffi::sqlite3, f: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    exec_sql_retry(db, "BEGIN IMMEDIATE")?;
+    match f().and_then(|_| exec_sql_retry(db, "COMMIT")) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            // some errors have rolled back the transaction already
+            if unsafe { ffi::sqlite3_get_autocommit(db) } == 0 {
+                if let Err(rb_err) = exec_sql(db, "ROLLBACK") {
+                    warn!("sqlite rollback failed: {}", rb_err);
+                }
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Sqlite journal mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+impl FromStr for JournalMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "delete" => Ok(JournalMode::Delete),
+            "truncate" => Ok(JournalMode::Truncate),
+            "persist" => Ok(JournalMode::Persist),
+            "memory" => Ok(JournalMode::Memory),
+            "wal" => Ok(JournalMode::Wal),
+            "off" => Ok(JournalMode::Off),
+            _ => Err(Error::InvalidUri),
+        }
+    }
+}
+
+/// Sqlite connection options
+#[derive(Debug, Default, Clone, PartialEq)]
+struct SqliteOpts {
+    journal: Option<JournalMode>,
+    busy_timeout: Option<c_int>, // in milliseconds
+
+    // SQLCipher database key
+    #[cfg(feature = "storage-sqlcipher")]
+    key: Option<String>,
+}
+
+impl SqliteOpts {
+    // parse options from uri query string
+    // example: journal=wal&busy_timeout=5000
+    fn parse(query: &str) -> Result<Self> {
+        let mut opts = SqliteOpts::default();
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let idx = param.find('=').ok_or(Error::InvalidUri)?;
+            let val = &param[idx + 1..];
+            match &param[..idx] {
+                "journal" => opts.journal = Some(val.parse()?),
+                "busy_timeout" => {
+                    let ms =
+                        val.parse::<c_int>().map_err(|_| Error::InvalidUri)?;
+                    opts.busy_timeout = Some(ms);
+                }
+                #[cfg(feature = "storage-sqlcipher")]
+                "key" if !val.is_empty() => opts.key = Some(val.to_string()),
+                _ => return Err(Error::InvalidUri),
+            }
+        }
+        Ok(opts)
+    }
+
+    // split location into file path and options, options start after the
+    // last '?' as they never contain '?', so a file path containing '?' must
+    // be followed by '?' even if there is no option
+    fn split(loc: &str) -> Result<(&str, Self)> {
+        match loc.rfind('?') {
+            Some(idx) => Ok((&loc[..idx], Self::parse(&loc[idx + 1..])?)),
+            None => Ok((loc, Self::default())),
+        }
     }
 }
 
@@ -120,6 +344,7 @@ fn run_select_blob(stmt: *mut ffi::sqlite3_stmt) -> Result<Vec<u8>> {
 pub struct SqliteStorage {
     is_attached: bool,  // attached to sqlite db
     file_path: CString, // database file path
+    opts: SqliteOpts,
     db: *mut ffi::sqlite3,
     stmts: Vec<*mut ffi::sqlite3_stmt>,
 }
@@ -132,13 +357,87 @@ impl SqliteStorage {
     const TBL_ADDRESSES: &'static str = "addresses";
     const TBL_BLOCKS: &'static str = "blocks";
 
-    pub fn new(file_path: &str) -> Self {
-        SqliteStorage {
+    // location format: <file_path>[?journal=<mode>&busy_timeout=<ms>]
+    pub fn new(loc: &str) -> Result<Self> {
+        let (file_path, opts) = SqliteOpts::split(loc)?;
+        #[cfg(feature = "storage-sqlcipher")]
+        {
+            // database key can only be used with sqlcipher storage
+            if opts.key.is_some() {
+                return Err(Error::InvalidUri);
+            }
+        }
+        let file_path =
+            CString::new(file_path).map_err(|_| Error::InvalidUri)?;
+
+        Ok(SqliteStorage {
+            is_attached: false,
+            file_path,
+            opts,
+            db: ptr::null_mut(),
+            stmts: Vec::with_capacity(14),
+        })
+    }
+
+    // create storage on SQLCipher encrypted database
+    // location format: <file_path>?key=<passphrase>[&<other options>]
+    #[cfg(feature = "storage-sqlcipher")]
+    pub fn new_sqlcipher(loc: &str) -> Result<Self> {
+        let (file_path, opts) = SqliteOpts::split(loc)?;
+        if opts.key.is_none() {
+            return Err(Error::InvalidUri);
+        }
+        let file_path =
+            CString::new(file_path).map_err(|_| Error::InvalidUri)?;
+
+        Ok(SqliteStorage {
             is_attached: false,
-            file_path: CString::new(file_path).unwrap(),
+            file_path,
+            opts,
             db: ptr::null_mut(),
             stmts: Vec::with_capacity(14),
+        })
+    }
+
+    // set SQLCipher key, this must be done before any other statements
+    #[cfg(feature = "storage-sqlcipher")]
+    fn apply_key(&mut self) -> Result<()> {
+        if let Some(ref key) = self.opts.key {
+            let sql = format!("PRAGMA key = '{}'", key.replace('\'', "''"));
+            exec_sql(self.db, &sql)?;
+
+            // read from database to verify the key, wrong key makes database
+            // file not recognisable
+            exec_sql(self.db, "SELECT count(*) FROM sqlite_master").map_err(
+                |err| match err {
+                    Error::Sqlite(ref e)
+                        if e.code == ffi::ErrorCode::NotADatabase =>
+                    {
+                        Error::Decrypt
+                    }
+                    _ => err,
+                },
+            )?;
         }
+        Ok(())
+    }
+
+    // apply connection options
+    fn apply_opts(&mut self) -> Result<()> {
+        #[cfg(feature = "storage-sqlcipher")]
+        self.apply_key()?;
+
+        if let Some(ms) = self.opts.busy_timeout {
+            let result = unsafe { ffi::sqlite3_busy_timeout(self.db, ms) };
+            check_result(result)?;
+        }
+        if let Some(journal) = self.opts.journal {
+            exec_sql(
+                self.db,
+                &format!("PRAGMA journal_mode = {}", journal.as_str()),
+            )?;
+        }
+        Ok(())
     }
 
     // prepare one sql statement
@@ -257,7 +556,7 @@ impl SqliteStorage {
         ))?;
         self.prepare_sql(format!(
             "
-            DELETE FROM {} WHERE blk_idx = ?
+            DELETE FROM {} WHERE blk_idx >= ? AND blk_idx < ?
         ",
             Self::TBL_BLOCKS
         ))?;
@@ -266,30 +565,28 @@ impl SqliteStorage {
     }
 
     fn lock_repo(&mut self, force: bool) -> Result<()> {
-        let stmt = self.stmts[0];
-        reset_stmt(stmt)?;
-        let result = unsafe { ffi::sqlite3_step(stmt) };
-        match result {
-            ffi::SQLITE_ROW => {
-                // repo is locked
-                if force {
-                    warn!("Repo was locked, forced to open");
-                    self.is_attached = true;
-                    Ok(())
-                } else {
-                    Err(Error::RepoOpened)
-                }
+        let (query, lock) = (self.stmts[0], self.stmts[1]);
+
+        // check and lock repo in one transaction
+        let mut is_locked = false;
+        run_in_tx(self.db, || {
+            reset_stmt(query)?;
+            is_locked = run_select_any(query)?;
+            if !is_locked {
+                reset_stmt(lock)?;
+                run_dml(lock)?;
             }
-            ffi::SQLITE_DONE => {
-                // repo is not locked yet, lock it now
-                let stmt = self.stmts[1];
-                reset_stmt(stmt)?;
-                run_dml(stmt)?;
-                self.is_attached = true;
-                Ok(())
+            Ok(())
+        })?;
+
+        if is_locked {
+            if !force {
+                return Err(Error::RepoOpened);
             }
-            _ => Err(Error::from(ffi::Error::new(result))),
+            warn!("Repo was locked, forced to open");
         }
+        self.is_attached = true;
+        Ok(())
     }
 }
 
@@ -330,7 +627,7 @@ impl Storable for SqliteStorage {
             return Err(Error::from(err));
         }
 
-        Ok(())
+        self.apply_opts()
     }
 
     fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
@@ -363,17 +660,7 @@ impl Storable for SqliteStorage {
             Self::TBL_ADDRESSES,
             Self::TBL_BLOCKS
         );
-        let sql = CString::new(sql).unwrap();
-        let result = unsafe {
-            ffi::sqlite3_exec(
-                self.db,
-                sql.as_ptr(),
-                None,
-                ptr::null_mut(),
-                ptr::null_mut(),
-            )
-        };
-        check_result(result)?;
+        exec_sql(self.db, &sql)?;
 
         self.prepare_stmts()?;
         self.lock_repo(false)
@@ -404,7 +691,7 @@ impl Storable for SqliteStorage {
         // bind parameters and run sql
         bind_int(stmt, 1, suffix as usize)?;
         bind_blob(stmt, 2, super_blk)?;
-        run_dml(stmt)
+        run_dml_tx(self.db, stmt)
     }
 
     fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
@@ -425,7 +712,7 @@ impl Storable for SqliteStorage {
         let id_str = CString::new(id.to_string()).unwrap();
         bind_id(stmt, 1, &id_str)?;
         bind_blob(stmt, 2, wal)?;
-        run_dml(stmt)
+        run_dml_tx(self.db, stmt)
     }
 
     fn del_wal(&mut self, id: &Eid) -> Result<()> {
@@ -435,7 +722,7 @@ impl Storable for SqliteStorage {
         // bind parameters and run sql
         let id_str = CString::new(id.to_string()).unwrap();
         bind_id(stmt, 1, &id_str)?;
-        run_dml(stmt)
+        run_dml_tx(self.db, stmt)
     }
 
     fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
@@ -456,7 +743,7 @@ impl Storable for SqliteStorage {
         let id_str = CString::new(id.to_string()).unwrap();
         bind_id(stmt, 1, &id_str)?;
         bind_blob(stmt, 2, addr)?;
-        run_dml(stmt)
+        run_dml_tx(self.db, stmt)
     }
 
     fn del_address(&mut self, id: &Eid) -> Result<()> {
@@ -466,7 +753,7 @@ impl Storable for SqliteStorage {
         // bind parameters and run sql
         let id_str = CString::new(id.to_string()).unwrap();
         bind_id(stmt, 1, &id_str)?;
-        run_dml(stmt)
+        run_dml_tx(self.db, stmt)
     }
 
     fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
@@ -491,34 +778,31 @@ impl Storable for SqliteStorage {
     fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
         let stmt = self.stmts[12];
 
-        for blk_idx in span {
-            // reset statement and binding
-            reset_stmt(stmt)?;
-
-            // bind parameters and run sql
-            bind_int(stmt, 1, blk_idx)?;
-            bind_blob(stmt, 2, &blks[..BLK_SIZE])?;
-            run_dml(stmt)?;
+        // insert all blocks in one transaction, so there is only one commit
+        run_in_tx(self.db, || {
+            for blk_idx in span {
+                // reset statement and binding
+                reset_stmt(stmt)?;
 
-            blks = &blks[BLK_SIZE..];
-        }
+                // bind parameters and run sql
+                bind_int(stmt, 1, blk_idx)?;
+                bind_blob(stmt, 2, &blks[..BLK_SIZE])?;
+                run_dml(stmt)?;
 
-        Ok(())
+                blks = &blks[BLK_SIZE..];
+            }
+            Ok(())
+        })
     }
 
     fn del_blocks(&mut self, span: Span) -> Result<()> {
         let stmt = self.stmts[13];
+        reset_stmt(stmt)?;
 
-        for blk_idx in span {
-            // reset statement and binding
-            reset_stmt(stmt)?;
-
-            // bind parameters and run sql
-            bind_int(stmt, 1, blk_idx)?;
-            run_dml(stmt)?;
-        }
-
-        Ok(())
+        // bind block index range and run sql
+        bind_int(stmt, 1, span.begin)?;
+        bind_int(stmt, 2, span.end())?;
+        run_dml_tx(self.db, stmt)
     }
 
     #[inline]
@@ -540,6 +824,38 @@ impl Storable for SqliteStorage {
         vio::remove_file(self.file_path.to_str().unwrap())?;
         Ok(())
     }
+
+    fn compact(
+        &mut self,
+        _blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        // vacuum fails if any statement is still in progress, so reset all
+        // of them first
+        for stmt in self.stmts.iter() {
+            check_result(unsafe { ffi::sqlite3_reset(*stmt) })?;
+        }
+
+        // deleted rows leave free pages in database file, vacuum rebuilds
+        // the database file to give them back to the file system
+        exec_sql(self.db, "VACUUM;")?;
+        progress(1, 1);
+        Ok(())
+    }
+
+    fn space(&mut self, _used_blocks: usize) -> Result<Space> {
+        let path = Path::new(self.file_path.to_str().unwrap());
+        let used = vio::metadata(path)?.len();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let free = utils::disk_free_space(dir)?;
+        Ok(Space {
+            total: free.map(|free| used + free),
+            used,
+        })
+    }
 }
 
 impl Drop for SqliteStorage {
@@ -599,7 +915,7 @@ mod tests {
         init_env();
         let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
         let dir = tmpdir.path().join("storage.db");
-        let mut ss = SqliteStorage::new(dir.to_str().unwrap());
+        let mut ss = SqliteStorage::new(dir.to_str().unwrap()).unwrap();
 
         ss.connect(false).unwrap();
         ss.init(Crypto::default(), Key::new_empty()).unwrap();
@@ -648,7 +964,7 @@ mod tests {
 
         // re-open
         drop(ss);
-        let mut ss = SqliteStorage::new(dir.to_str().unwrap());
+        let mut ss = SqliteStorage::new(dir.to_str().unwrap()).unwrap();
         ss.connect(false).unwrap();
         ss.open(Crypto::default(), Key::new_empty(), false).unwrap();
 
@@ -666,4 +982,166 @@ mod tests {
             Error::NotFound
         );
     }
+
+    #[test]
+    fn sqlite_blocks_batch() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let dir = tmpdir.path().join("storage.db");
+        let mut ss = SqliteStorage::new(dir.to_str().unwrap()).unwrap();
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        let blk_cnt = 256;
+        let mut blks = vec![0u8; BLK_SIZE * blk_cnt];
+        for (i, blk) in blks.chunks_mut(BLK_SIZE).enumerate() {
+            blk[0] = i as u8;
+        }
+        let mut dst = vec![0u8; BLK_SIZE * blk_cnt];
+        ss.put_blocks(Span::new(0, blk_cnt), &blks).unwrap();
+        ss.get_blocks(&mut dst, Span::new(0, blk_cnt)).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+
+        // delete a range in the middle, blocks around it are kept
+        ss.del_blocks(Span::new(10, 100)).unwrap();
+        for idx in &[10, 50, 109] {
+            assert_eq!(
+                ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(*idx, 1))
+                    .unwrap_err(),
+                Error::NotFound
+            );
+        }
+        for idx in &[9, 110] {
+            ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(*idx, 1))
+                .unwrap();
+            assert_eq!(dst[0], *idx as u8);
+        }
+
+        // failed write is rolled back as a whole
+        assert!(ss
+            .put_blocks(Span::new(108, 4), &blks[..BLK_SIZE * 4])
+            .is_err());
+        assert_eq!(
+            ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(108, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+    }
+
+    #[test]
+    fn sqlite_busy_retry() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let dir = tmpdir.path().join("storage.db");
+        let mut ss = SqliteStorage::new(dir.to_str().unwrap()).unwrap();
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        // another connection to the same database
+        let mut ss2 = SqliteStorage::new(dir.to_str().unwrap()).unwrap();
+        ss2.connect(false).unwrap();
+        ss2.get_super_block(0).unwrap_err();
+
+        // commit blocked by reader gives busy error and is rolled back
+        let id = Eid::new();
+        exec_sql(ss.db, "BEGIN").unwrap();
+        exec_sql(ss.db, "SELECT count(*) FROM wals").unwrap();
+        assert_eq!(ss2.put_wal(&id, &[1, 2, 3]).unwrap_err(), Error::Busy);
+        assert_ne!(unsafe { ffi::sqlite3_get_autocommit(ss2.db) }, 0);
+        exec_sql(ss.db, "COMMIT").unwrap();
+        assert_eq!(ss2.get_wal(&id).unwrap_err(), Error::NotFound);
+
+        // persistent lock gives busy error
+        exec_sql(ss.db, "BEGIN EXCLUSIVE").unwrap();
+        assert_eq!(ss2.put_wal(&id, &[1, 2, 3]).unwrap_err(), Error::Busy);
+
+        // lock released by other connection while retrying
+        let child = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            exec_sql(ss.db, "COMMIT").unwrap();
+            ss
+        });
+        ss2.put_wal(&id, &[1, 2, 3]).unwrap();
+        assert_eq!(&ss2.get_wal(&id).unwrap()[..], &[1, 2, 3]);
+        child.join().unwrap();
+    }
+
+    #[test]
+    fn sqlite_opts() {
+        assert_eq!(SqliteOpts::parse("").unwrap(), SqliteOpts::default());
+        let opts = SqliteOpts::parse("journal=wal&busy_timeout=5000").unwrap();
+        assert_eq!(opts.journal, Some(JournalMode::Wal));
+        assert_eq!(opts.busy_timeout, Some(5000));
+        assert!(SqliteOpts::parse("journal=foo").is_err());
+        assert!(SqliteOpts::parse("busy_timeout=x").is_err());
+        assert!(SqliteOpts::parse("foo=bar").is_err());
+        assert!(SqliteOpts::parse("journal").is_err());
+
+        // options start after the last '?'
+        let (path, opts) = SqliteOpts::split("./foo.db").unwrap();
+        assert_eq!(path, "./foo.db");
+        assert_eq!(opts, SqliteOpts::default());
+        let (path, opts) = SqliteOpts::split("./a?b.db?").unwrap();
+        assert_eq!(path, "./a?b.db");
+        assert_eq!(opts, SqliteOpts::default());
+        let (path, opts) = SqliteOpts::split("./a?b.db?journal=wal").unwrap();
+        assert_eq!(path, "./a?b.db");
+        assert_eq!(opts.journal, Some(JournalMode::Wal));
+
+        // file path containing '?' without trailing '?' is rejected
+        assert_eq!(
+            SqliteOpts::split("./a?b.db").unwrap_err(),
+            Error::InvalidUri
+        );
+    }
+
+    #[test]
+    fn sqlite_wal_journal() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let dir = tmpdir.path().join("storage.db");
+        let loc = format!("{}?journal=wal&busy_timeout=5000", dir.display());
+        let mut ss = SqliteStorage::new(&loc).unwrap();
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+        ss.put_super_block(&[1, 2, 3], 0).unwrap();
+        assert!(tmpdir.path().join("storage.db-wal").exists());
+        assert_eq!(&ss.get_super_block(0).unwrap()[..], &[1, 2, 3]);
+    }
+
+    #[cfg(feature = "storage-sqlcipher")]
+    #[test]
+    fn sqlcipher_storage() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let dir = tmpdir.path().join("storage.db");
+        let path = dir.to_str().unwrap();
+
+        // key must be specified and only for sqlcipher
+        assert!(SqliteStorage::new_sqlcipher(path).is_err());
+        assert!(SqliteStorage::new(&format!("{}?key=foo", path)).is_err());
+
+        let loc = format!("{}?key=foo'bar&journal=wal", path);
+        let mut ss = SqliteStorage::new_sqlcipher(&loc).unwrap();
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+        ss.put_super_block(&[1, 2, 3], 0).unwrap();
+        drop(ss);
+
+        // open with wrong key
+        let loc2 = format!("{}?key=bar", path);
+        let mut ss = SqliteStorage::new_sqlcipher(&loc2).unwrap();
+        assert_eq!(ss.connect(false).unwrap_err(), Error::Decrypt);
+
+        // open without key
+        let mut ss = SqliteStorage::new(path).unwrap();
+        ss.connect(false).unwrap();
+        assert!(ss.get_super_block(0).is_err());
+
+        // open with correct key
+        let mut ss = SqliteStorage::new_sqlcipher(&loc).unwrap();
+        ss.connect(false).unwrap();
+        ss.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        assert_eq!(&ss.get_super_block(0).unwrap()[..], &[1, 2, 3]);
+    }
 }
@@ -1,4 +1,5 @@
 use std::cmp::min;
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt::{self, Debug, Display};
 use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
@@ -7,14 +8,15 @@ use std::sync::{Arc, RwLock, Weak};
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
-use super::{DummyStorage, Storable};
+use super::sched::{IoSched, IoSchedRef};
+use super::{DummyStorage, Health, RetryPolicy, Space, Storable};
 use base::crypto::{Cipher, Cost, Crypto, Key};
 use base::lru::{CountMeter, Lru, Meter, PinChecker};
 use base::utils::align_ceil_chunk;
 use base::IntoRef;
 use error::{Error, Result};
 use trans::{Eid, Finish};
-use volume::address::Addr;
+use volume::address::{Addr, Span};
 use volume::{Allocator, AllocatorRef, BLKS_PER_FRAME, BLK_SIZE, FRAME_SIZE};
 
 // parse storage part in uri
@@ -31,6 +33,36 @@ fn parse_uri(uri: &str) -> Result<Box<dyn Storable>> {
     }
     let storage_type = &uri[..idx];
 
+    // local cache fronting another storage
+    if storage_type.starts_with("cache+") {
+        #[cfg(feature = "storage-cache")]
+        {
+            let (inner_uri, opts) = super::cache::parse_uri(&uri[6..])?;
+            let inner = parse_uri(&inner_uri)?;
+            let depot = super::cache::CacheStorage::new(inner, opts);
+            return Ok(Box::new(depot));
+        }
+        #[cfg(not(feature = "storage-cache"))]
+        {
+            return Err(Error::InvalidUri);
+        }
+    }
+
+    // throughput throttling over another storage
+    if storage_type.starts_with("throttle+") {
+        #[cfg(feature = "storage-throttle")]
+        {
+            let (inner_uri, opts) = super::throttle::parse_uri(&uri[9..])?;
+            let inner = parse_uri(&inner_uri)?;
+            let depot = super::throttle::ThrottleStorage::new(inner, opts);
+            return Ok(Box::new(depot));
+        }
+        #[cfg(not(feature = "storage-throttle"))]
+        {
+            return Err(Error::InvalidUri);
+        }
+    }
+
     match storage_type {
         "mem" => {
             #[cfg(feature = "storage-mem")]
@@ -54,10 +86,34 @@ fn parse_uri(uri: &str) -> Result<Box<dyn Storable>> {
                 Err(Error::InvalidUri)
             }
         }
+        "container" => {
+            #[cfg(feature = "storage-container")]
+            {
+                let path = std::path::Path::new(loc);
+                let depot = super::container::ContainerStorage::new(path);
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-container"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "http" | "https" => {
+            #[cfg(feature = "storage-http")]
+            {
+                let url = format!("{}://{}", storage_type, loc);
+                let depot = super::container::HttpStorage::new(&url)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-http"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
         "sqlite" => {
             #[cfg(feature = "storage-sqlite")]
             {
-                let depot = super::sqlite::SqliteStorage::new(loc);
+                let depot = super::sqlite::SqliteStorage::new(loc)?;
                 Ok(Box::new(depot))
             }
             #[cfg(not(feature = "storage-sqlite"))]
@@ -65,6 +121,17 @@ fn parse_uri(uri: &str) -> Result<Box<dyn Storable>> {
                 Err(Error::InvalidUri)
             }
         }
+        "sqlcipher" => {
+            #[cfg(feature = "storage-sqlcipher")]
+            {
+                let depot = super::sqlite::SqliteStorage::new_sqlcipher(loc)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-sqlcipher"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
         "redis" => {
             #[cfg(feature = "storage-redis")]
             {
@@ -76,6 +143,77 @@ fn parse_uri(uri: &str) -> Result<Box<dyn Storable>> {
                 Err(Error::InvalidUri)
             }
         }
+        "rediss" => {
+            #[cfg(feature = "storage-redis-tls")]
+            {
+                let depot = super::redis::RedisStorage::new_tls(loc)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-redis-tls"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "redis+sentinel" => {
+            #[cfg(feature = "storage-redis")]
+            {
+                let depot = super::redis::RedisStorage::new_sentinel(loc)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-redis"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "redis+cluster" => {
+            #[cfg(feature = "storage-redis")]
+            {
+                let depot = super::redis::RedisStorage::new_cluster(loc)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-redis"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "gs" => {
+            #[cfg(feature = "storage-gcs")]
+            {
+                let depot = super::gcs::GcsStorage::new(loc)?;
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-gcs"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "sled" => {
+            #[cfg(feature = "storage-sled")]
+            {
+                let path = std::path::Path::new(loc);
+                let depot = super::sled::SledStorage::new(path);
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-sled"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
+        "stripe" => {
+            #[cfg(feature = "storage-stripe")]
+            {
+                let members = super::stripe::parse_uri(loc)?
+                    .iter()
+                    .map(|member| parse_uri(member))
+                    .collect::<Result<Vec<_>>>()?;
+                let depot = super::stripe::StripeStorage::new(members);
+                Ok(Box::new(depot))
+            }
+            #[cfg(not(feature = "storage-stripe"))]
+            {
+                Err(Error::InvalidUri)
+            }
+        }
         "faulty" => {
             #[cfg(feature = "storage-faulty")]
             {
@@ -98,10 +236,27 @@ fn parse_uri(uri: &str) -> Result<Box<dyn Storable>> {
                 Err(Error::InvalidUri)
             }
         }
-        _ => Err(Error::InvalidUri),
+        _ => super::registry::create_storage(storage_type, loc),
     }
 }
 
+// remove SQLCipher database key from uri, so it is not saved in super block
+// or exposed in repo info, the '?' is kept as file path might contain '?'
+pub fn strip_db_key(uri: &str) -> String {
+    if !uri.contains("sqlcipher://") {
+        return uri.to_owned();
+    }
+    let idx = match uri.rfind('?') {
+        Some(idx) => idx,
+        None => return uri.to_owned(),
+    };
+    let params: Vec<&str> = uri[idx + 1..]
+        .split('&')
+        .filter(|param| !param.starts_with("key="))
+        .collect();
+    format!("{}{}", &uri[..=idx], params.join("&"))
+}
+
 // frame cache meter, measured by frame byte size
 #[derive(Debug, Default)]
 struct FrameCacheMeter;
@@ -125,11 +280,33 @@ pub struct Storage {
     crypto: Crypto,
     key: Key,
 
+    // crypto of the cipher being migrated from, entities which are not
+    // migrated yet are decrypted by it
+    old_crypto: Option<Crypto>,
+
+    // data key being rotated from, entities which are not re-encrypted yet
+    // are decrypted by it
+    old_key: Option<Key>,
+
+    // unlocked key domain keys, entities in a key domain are encrypted by
+    // its key rather than the data key
+    domains: HashMap<Eid, Key>,
+
+    // convergent key, file content is encrypted deterministically by it
+    // rather than the data key if it is set
+    convergent: Option<Key>,
+
     // decrypted frame cache, key is the begin block index
     frame_cache: Lru<usize, Vec<u8>, FrameCacheMeter, PinChecker<Vec<u8>>>,
 
     // entity address cache
     addr_cache: Lru<Eid, Addr, CountMeter<Addr>, PinChecker<Addr>>,
+
+    // reader IO scheduler
+    sched: IoSchedRef,
+
+    // if blocks are shredded rather than just deleted
+    shred: bool,
 }
 
 impl Storage {
@@ -153,8 +330,14 @@ impl Storage {
             allocator: Allocator::new().into_ref(),
             crypto: Crypto::default(),
             key: Key::new_empty(),
+            old_crypto: None,
+            old_key: None,
+            domains: HashMap::new(),
+            convergent: None,
             frame_cache,
             addr_cache: Lru::new(Self::ADDRESS_CACHE_SIZE),
+            sched: Arc::new(IoSched::default()),
+            shred: false,
         })
     }
 
@@ -186,15 +369,131 @@ impl Storage {
         &mut self,
         cost: Cost,
         cipher: Cipher,
-        key: Key,
+        depot_cipher: Cipher,
+        depot_key: &Key,
+        read_only: bool,
         force: bool,
     ) -> Result<()> {
         self.crypto = Crypto::new(cost, cipher)?;
+        self.key = depot_key.clone();
+
+        // open depot, which keeps using the cipher and key it was
+        // initialised with, read-only depot is opened in shared mode
+        let depot_crypto = Crypto::new(cost, depot_cipher)?;
+        let depot_key = depot_key.derive(0);
+        if read_only {
+            self.depot.open_shared(depot_crypto, depot_key)
+        } else {
+            self.depot.open(depot_crypto, depot_key, force)
+        }
+    }
+
+    // reload state changed by another writer, cached addresses and frames
+    // are discarded as well
+    pub fn refresh(&mut self) -> Result<()> {
+        self.addr_cache.clear();
+        self.frame_cache.clear();
+        self.depot.refresh()
+    }
+
+    // set cipher used to encrypt entities and wals, and the cipher being
+    // migrated from if a migration is in progress
+    pub fn set_cipher(
+        &mut self,
+        cipher: Cipher,
+        old_cipher: Option<Cipher>,
+    ) -> Result<()> {
+        let cost = self.crypto.cost;
+        self.crypto = Crypto::new(cost, cipher)?;
+        self.old_crypto = match old_cipher {
+            Some(old_cipher) => Some(Crypto::new(cost, old_cipher)?),
+            None => None,
+        };
+
+        // cached addresses may have stale cipher flags
+        self.addr_cache.clear();
+
+        Ok(())
+    }
+
+    // set key used to encrypt entities and wals, and the key being rotated
+    // from if a rotation is in progress
+    pub fn set_key(&mut self, key: Key, old_key: Option<Key>) {
         self.key = key;
+        self.old_key = old_key;
+
+        // cached addresses may have stale legacy flags
+        self.addr_cache.clear();
+    }
+
+    // set key of an unlocked key domain
+    #[inline]
+    pub fn set_domain_key(&mut self, domain: &Eid, key: Key) {
+        self.domains.insert(domain.clone(), key);
+    }
+
+    #[inline]
+    pub fn has_domain_key(&self, domain: &Eid) -> bool {
+        self.domains.contains_key(domain)
+    }
 
-        // open depot
-        self.depot
-            .open(self.crypto.clone(), self.key.derive(0), force)
+    // set convergent key, none to disable convergent encryption
+    #[inline]
+    pub fn set_convergent_key(&mut self, key: Option<Key>) {
+        self.convergent = key;
+    }
+
+    #[inline]
+    pub fn is_convergent(&self) -> bool {
+        self.convergent.is_some()
+    }
+
+    // get crypto and key being migrated from, none if neither cipher
+    // migration nor key rotation is in progress
+    fn old_crypto_key(&self) -> Option<(&Crypto, &Key)> {
+        if self.old_crypto.is_none() && self.old_key.is_none() {
+            return None;
+        }
+        Some((
+            self.old_crypto.as_ref().unwrap_or(&self.crypto),
+            self.old_key.as_ref().unwrap_or(&self.key),
+        ))
+    }
+
+    // decrypt an address or a wal, fall back to the cipher and key being
+    // migrated from, return decrypted data and whether the fallback is used
+    fn decrypt_any(&self, buf: &[u8]) -> Result<(Vec<u8>, bool)> {
+        match self.crypto.decrypt(buf, &self.key) {
+            Ok(dec) => Ok((dec, false)),
+            Err(err) => match self.old_crypto_key() {
+                Some((old_crypto, old_key)) => old_crypto
+                    .decrypt(buf, old_key)
+                    .map(|dec| (dec, true))
+                    .map_err(|_| err),
+                None => Err(err),
+            },
+        }
+    }
+
+    // get crypto and key used to encrypt blocks in an address, blocks in a
+    // key domain can only be accessed when the domain is unlocked, blocks
+    // encrypted convergently are not affected by data key rotation
+    fn crypto_of(&self, addr: &Addr) -> Result<(&Crypto, &Key)> {
+        let crypto = match self.old_crypto {
+            Some(ref old_crypto) if addr.legacy => old_crypto,
+            _ => &self.crypto,
+        };
+        let key = match addr.domain {
+            Some(ref domain) => {
+                self.domains.get(domain).ok_or(Error::DomainLocked)?
+            }
+            None if addr.convergent => {
+                self.convergent.as_ref().ok_or(Error::Decrypt)?
+            }
+            None if addr.legacy => self.old_key.as_ref().unwrap_or(&self.key),
+            None => &self.key,
+        };
+        Ok((crypto, key))
     }
 
     #[inline]
@@ -225,9 +524,10 @@ impl Storage {
 
         // if not in the cache, load if from depot
         let buf = self.depot.get_address(id)?;
-        let buf = self.crypto.decrypt(&buf, &self.key)?;
+        let (buf, legacy) = self.decrypt_any(&buf)?;
         let mut de = Deserializer::new(&buf[..]);
-        let addr: Addr = Deserialize::deserialize(&mut de)?;
+        let mut addr: Addr = Deserialize::deserialize(&mut de)?;
+        addr.legacy = legacy;
 
         // and then insert into address cache
         self.addr_cache.insert(id.clone(), addr.clone());
@@ -249,6 +549,34 @@ impl Storage {
         Ok(())
     }
 
+    // write an encrypted frame to newly allocated blocks and return the
+    // block span, the frame buffer must be large enough to hold padding
+    fn put_frame(&mut self, frame: &mut [u8], enc_len: usize) -> Result<Span> {
+        let blk_cnt = align_ceil_chunk(enc_len, BLK_SIZE);
+        let aligned_len = blk_cnt * BLK_SIZE;
+
+        // add padding bytes
+        Crypto::random_buf(&mut frame[enc_len..aligned_len]);
+
+        // allocate blocks
+        let span = {
+            let mut allocator = self.allocator.write().unwrap();
+            allocator.allocate(blk_cnt)
+        };
+
+        // write frame to depot
+        self.depot.put_blocks(span, &frame[..aligned_len])?;
+
+        Ok(span)
+    }
+
+    // turn on or off block shredding, when it is on, blocks are overwritten
+    // before they are deleted
+    #[inline]
+    pub fn set_shred(&mut self, shred: bool) {
+        self.shred = shred;
+    }
+
     // remove all blocks in a address
     fn remove_address_blocks(&mut self, addr: &Addr) -> Result<()> {
         let mut inaddr_idx = 0;
@@ -256,7 +584,15 @@ impl Storage {
             let blk_cnt = loc_span.span.cnt;
 
             // delete blocks
-            self.depot.del_blocks(loc_span.span)?;
+            if self.shred {
+                self.depot.shred_blocks(loc_span.span)?;
+            } else {
+                self.depot.del_blocks(loc_span.span)?;
+            }
+            {
+                let mut allocator = self.allocator.write().unwrap();
+                allocator.free(blk_cnt);
+            }
 
             let mut blk_idx = loc_span.span.begin;
             let end_idx = inaddr_idx + blk_cnt;
@@ -279,6 +615,15 @@ impl Storage {
         self.depot.del_wal(id)
     }
 
+    // check if an entity exists
+    pub fn contains(&mut self, id: &Eid) -> Result<bool> {
+        match self.get_address(id) {
+            Ok(_) => Ok(true),
+            Err(ref err) if *err == Error::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
     // delete an entity, including data and address
     pub fn del(&mut self, id: &Eid) -> Result<()> {
         // get address first
@@ -298,6 +643,100 @@ impl Storage {
         Ok(())
     }
 
+    // re-encrypt an entity which is encrypted by the cipher or key being
+    // migrated from, return true if it is re-encrypted
+    pub fn reencrypt(&mut self, id: &Eid) -> Result<bool> {
+        let addr = match self.get_address(id) {
+            Ok(ref addr) if !addr.legacy => return Ok(false),
+            Ok(addr) => addr,
+            Err(ref err) if *err == Error::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        // blocks in a key domain or encrypted convergently are not encrypted
+        // by the data key, so only the address needs to be re-encrypted
+        // unless cipher is changed
+        if (addr.domain.is_some() || addr.convergent)
+            && self.old_crypto.is_none()
+        {
+            self.put_address(id, &addr)?;
+            return Ok(true);
+        }
+
+        let (old_crypto, old_key) = {
+            let (crypto, key) = self.crypto_of(&addr)?;
+            (crypto.clone(), key.clone())
+        };
+
+        // read and decrypt all frames
+        let mut frame = vec![0u8; FRAME_SIZE];
+        let mut dec_frame = vec![0u8; old_crypto.decrypted_len(FRAME_SIZE)];
+        let mut data = Vec::new();
+        for frm_addr in addr.divide_to_frames() {
+            let mut read = 0;
+            for loc_span in frm_addr.iter() {
+                let read_len = loc_span.span.bytes_len();
+                self.depot.get_blocks(
+                    &mut frame[read..read + read_len],
+                    loc_span.span,
+                )?;
+                read += read_len;
+            }
+            let dec_len = old_crypto.decrypt_to(
+                &mut dec_frame,
+                &frame[..frm_addr.len],
+                &old_key,
+            )?;
+            data.extend_from_slice(&dec_frame[..dec_len]);
+        }
+
+        // encrypt with current cipher and write to new blocks
+        let mut new_addr = Addr {
+            compress: addr.compress,
+            domain: addr.domain.clone(),
+            convergent: addr.convergent,
+            ..Default::default()
+        };
+        let key = match addr.domain {
+            Some(ref domain) => self.domains[domain].clone(),
+            None if addr.convergent => old_key,
+            None => self.key.clone(),
+        };
+        let stg_size = self.crypto.decrypted_len(FRAME_SIZE);
+        for chunk in data.chunks(stg_size) {
+            let enc_len = if addr.convergent {
+                self.crypto.encrypt_convergent_to(&mut frame, chunk, &key)?
+            } else {
+                self.crypto.encrypt_to(&mut frame, chunk, &key)?
+            };
+            let span = self.put_frame(&mut frame, enc_len)?;
+            new_addr.append(span, enc_len);
+        }
+
+        // replace the old address
+        self.remove_address_blocks(&addr)?;
+        self.put_address(id, &new_addr)?;
+
+        Ok(true)
+    }
+
+    // re-encrypt a wal which is encrypted by the cipher or key being
+    // migrated from, return true if it is re-encrypted
+    pub fn reencrypt_wal(&mut self, id: &Eid) -> Result<bool> {
+        let wal = match self.depot.get_wal(id) {
+            Ok(wal) => wal,
+            Err(ref err) if *err == Error::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let (wal, legacy) = self.decrypt_any(&wal)?;
+        if !legacy {
+            return Ok(false);
+        }
+        let enc = self.crypto.encrypt(&wal, &self.key)?;
+        self.depot.put_wal(id, &enc)?;
+        Ok(true)
+    }
+
     // flush underlying storage
     #[inline]
     pub fn flush(&mut self) -> Result<()> {
@@ -308,6 +747,67 @@ impl Storage {
     pub fn destroy(&mut self) -> Result<()> {
         self.depot.destroy()
     }
+
+    pub fn space(&mut self) -> Result<Space> {
+        let (used_blocks, reserved_blocks) = {
+            let allocator = self.allocator.read().unwrap();
+            (allocator.used_blocks(), allocator.reserved_blocks())
+        };
+        let mut space = self.depot.space(used_blocks)?;
+
+        // reserved blocks are not written yet but cannot be used by others
+        space.used += (reserved_blocks * BLK_SIZE) as u64;
+        Ok(space)
+    }
+
+    // reserve blocks ahead of writes, fail if storage capacity is known and
+    // there is not enough space left
+    pub fn reserve(&mut self, blk_cnt: usize) -> Result<()> {
+        let space = self.space()?;
+        if let Some(total) = space.total {
+            if space.used + (blk_cnt * BLK_SIZE) as u64 > total {
+                return Err(Error::NoSpace);
+            }
+        }
+        let mut allocator = self.allocator.write().unwrap();
+        allocator.reserve(blk_cnt);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn release(&mut self, blk_cnt: usize) {
+        let mut allocator = self.allocator.write().unwrap();
+        allocator.release(blk_cnt);
+    }
+
+    // flush and then compact underlying storage up to block watermark
+    pub fn compact(
+        &mut self,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let blk_wmark = {
+            let allocator = self.allocator.read().unwrap();
+            allocator.block_wmark()
+        };
+        self.depot.flush()?;
+        self.depot.compact(blk_wmark, progress)?;
+        self.depot.flush()
+    }
+
+    #[inline]
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.depot.set_retry_policy(policy)
+    }
+
+    #[inline]
+    pub fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        self.depot.set_sector_size(size)
+    }
+
+    #[inline]
+    pub fn health_check(&mut self) -> Result<Health> {
+        self.depot.health_check()
+    }
 }
 
 impl Default for Storage {
@@ -318,8 +818,14 @@ impl Default for Storage {
             allocator: Allocator::default().into_ref(),
             crypto: Crypto::default(),
             key: Key::new_empty(),
+            old_crypto: None,
+            old_key: None,
+            domains: HashMap::new(),
+            convergent: None,
             frame_cache: Lru::default(),
             addr_cache: Lru::default(),
+            sched: Arc::new(IoSched::default()),
+            shred: false,
         }
     }
 }
@@ -381,8 +887,7 @@ impl Read for WalReader {
             })?;
 
             // decrypt wal
-            self.wal =
-                map_io_err!(storage.crypto.decrypt(&wal, &storage.key,))?;
+            self.wal = map_io_err!(storage.decrypt_any(&wal))?.0;
         }
 
         let copy_len = min(self.wal.len() - self.read, buf.len());
@@ -398,10 +903,18 @@ impl Read for WalReader {
 #[derive(Debug)]
 pub struct Reader {
     storage: StorageRef,
+    sched: IoSchedRef,
+
+    // crypto and key used to encrypt the entity
+    crypto: Crypto,
+    key: Key,
 
     // addresses split into frames
     addrs: Vec<Addr>,
 
+    // compression flag stored in address
+    compress: Option<bool>,
+
     // entity length in storage
     ent_len: usize,
 
@@ -424,11 +937,16 @@ pub struct Reader {
 
 impl Reader {
     pub fn new(id: &Eid, storage: &StorageRef) -> Result<Self> {
-        let (addr, dec_frame_size) = {
+        let (addr, crypto, key, sched) = {
             let mut storage = storage.write().unwrap();
             let addr = storage.get_address(id)?;
-            (addr, storage.crypto.decrypted_len(FRAME_SIZE))
+            let (crypto, key) = {
+                let (crypto, key) = storage.crypto_of(&addr)?;
+                (crypto.clone(), key.clone())
+            };
+            (addr, crypto, key, storage.sched.clone())
         };
+        let dec_frame_size = crypto.decrypted_len(FRAME_SIZE);
 
         // split address to frames and set the first frame key
         let addrs = addr.divide_to_frames();
@@ -436,7 +954,11 @@ impl Reader {
 
         let mut rdr = Reader {
             storage: storage.clone(),
+            sched,
+            crypto,
+            key,
             addrs,
+            compress: addr.compress,
             ent_len: addr.len,
             frame: vec![0u8; FRAME_SIZE],
             frm_idx: 0,
@@ -451,6 +973,13 @@ impl Reader {
         Ok(rdr)
     }
 
+    /// Returns whether the entity blocks are compressed, `None` if the
+    /// flag is not recorded in its address.
+    #[inline]
+    pub fn is_compressed(&self) -> Option<bool> {
+        self.compress
+    }
+
     // copy data out from decrypte frame to destination
     // return copied bytes length and flag if frame is exhausted
     fn copy_frame_out(
@@ -472,11 +1001,24 @@ impl Read for Reader {
             return Ok(0);
         }
 
+        let mut _turn = None;
         let mut storage = self.storage.write().unwrap();
 
+        // if a new frame has to be loaded from depot, wait for our turn to
+        // access storage, so that concurrent readers are served fairly, the
+        // storage lock is released while waiting
+        if self.dec_frame_len == 0
+            && !storage.frame_cache.contains_key(&self.frm_key)
+        {
+            drop(storage);
+            _turn = Some(self.sched.turn());
+            storage = self.storage.write().unwrap();
+        }
+
         // if decrypted frame has been exhausted and the
         // frame is not in the frame cache, read it from underlying depot
-        // and save to cache if it is necessary
+        // and save to cache if it is necessary, the frame might have been
+        // cached by other readers while waiting
         if self.dec_frame_len == 0
             && !storage.frame_cache.contains_key(&self.frm_key)
         {
@@ -504,10 +1046,10 @@ impl Read for Reader {
             }
 
             // decrypt frame
-            self.dec_frame_len = map_io_err!(storage.crypto.decrypt_to(
+            self.dec_frame_len = map_io_err!(self.crypto.decrypt_to(
                 &mut self.dec_frame,
                 &self.frame[..self.addrs[self.frm_idx].len],
-                &storage.key,
+                &self.key,
             ))?;
 
             // and then add the decrypted frame to cache if it is not too big
@@ -617,6 +1159,26 @@ impl Writer {
         Ok(wtr)
     }
 
+    /// Set compression flag which will be stored in the entity address.
+    #[inline]
+    pub fn set_compress(&mut self, compress: bool) {
+        self.addr.compress = Some(compress);
+    }
+
+    /// Set key domain which will be stored in the entity address, the
+    /// entity is encrypted by the domain key.
+    #[inline]
+    pub fn set_domain(&mut self, domain: &Eid) {
+        self.addr.domain = Some(domain.clone());
+    }
+
+    /// Set convergent flag which will be stored in the entity address, the
+    /// entity is encrypted deterministically by the convergent key.
+    #[inline]
+    pub fn set_convergent(&mut self) {
+        self.addr.convergent = true;
+    }
+
     // encrypt to frame and write to depot
     fn write_frame(&mut self) -> Result<()> {
         if self.stg_len == 0 {
@@ -627,27 +1189,26 @@ impl Writer {
         let mut storage = storage.write().unwrap();
 
         // encrypt source data to frame
-        let enc_len = storage.crypto.encrypt_to(
-            &mut self.frame,
-            &self.stg[..self.stg_len],
-            &storage.key,
-        )?;
-
-        let blk_cnt = align_ceil_chunk(enc_len, BLK_SIZE);
-        let aligned_len = blk_cnt * BLK_SIZE;
-
-        // add padding bytes
-        Crypto::random_buf(&mut self.frame[enc_len..aligned_len]);
-
-        // allocate blocks
-        let span = {
-            let allocator_ref = storage.get_allocator();
-            let mut allocator = allocator_ref.write().unwrap();
-            allocator.allocate(blk_cnt)
+        let key = match self.addr.domain {
+            Some(ref domain) => {
+                storage.domains.get(domain).ok_or(Error::DomainLocked)?
+            }
+            None if self.addr.convergent => {
+                storage.convergent.as_ref().ok_or(Error::InvalidArgument)?
+            }
+            None => &storage.key,
+        };
+        let src = &self.stg[..self.stg_len];
+        let enc_len = if self.addr.convergent {
+            storage
+                .crypto
+                .encrypt_convergent_to(&mut self.frame, src, key)?
+        } else {
+            storage.crypto.encrypt_to(&mut self.frame, src, key)?
         };
 
         // write frame to depot
-        storage.depot.put_blocks(span, &self.frame[..aligned_len])?;
+        let span = storage.put_frame(&mut self.frame, enc_len)?;
 
         // append to address and reset stage buffer
         self.addr.append(span, enc_len);
@@ -704,7 +1265,12 @@ mod tests {
 
     use std::time::Instant;
 
-    #[cfg(feature = "storage-file")]
+    #[cfg(any(
+        feature = "storage-file",
+        feature = "storage-sled",
+        feature = "storage-container",
+        feature = "storage-cache"
+    ))]
     use self::tempdir::TempDir;
     use super::*;
     use base::crypto::{Cipher, Cost, Crypto, RandomSeed, RANDOM_SEED_SIZE};
@@ -888,6 +1454,17 @@ mod tests {
         delete_test(&storage);
     }
 
+    #[test]
+    fn strip_uri_db_key() {
+        assert_eq!(strip_db_key("mem://foo?key=bar"), "mem://foo?key=bar");
+        assert_eq!(strip_db_key("sqlcipher://foo"), "sqlcipher://foo");
+        assert_eq!(strip_db_key("sqlcipher://foo?key=bar"), "sqlcipher://foo?");
+        assert_eq!(
+            strip_db_key("sqlcipher://f?o?journal=wal&key=bar&busy_timeout=5"),
+            "sqlcipher://f?o?journal=wal&busy_timeout=5"
+        );
+    }
+
     #[test]
     fn mem_depot() {
         init_env();
@@ -897,6 +1474,47 @@ mod tests {
         test_depot(storage.into_ref());
     }
 
+    #[cfg(feature = "storage-cache")]
+    #[test]
+    fn cache_depot() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let uri = format!(
+            "cache+mem://storage.cache_depot?cache_dir={}&cache_size=1mb",
+            tmpdir.path().display()
+        );
+        let mut storage = Storage::new(&uri).unwrap();
+        storage.connect(false).unwrap();
+        storage.init(Cost::default(), Cipher::default()).unwrap();
+        test_depot(storage.into_ref());
+    }
+
+    #[cfg(feature = "storage-throttle")]
+    #[test]
+    fn throttle_depot() {
+        init_env();
+        let mut storage = Storage::new(
+            "throttle+mem://storage.throttle_depot?throttle_ops=100000",
+        )
+        .unwrap();
+        storage.connect(false).unwrap();
+        storage.init(Cost::default(), Cipher::default()).unwrap();
+        test_depot(storage.into_ref());
+    }
+
+    #[cfg(feature = "storage-stripe")]
+    #[test]
+    fn stripe_depot() {
+        init_env();
+        let mut storage = Storage::new(
+            "stripe://mem://storage.stripe_depot.0|mem://storage.stripe_depot.1",
+        )
+        .unwrap();
+        storage.connect(false).unwrap();
+        storage.init(Cost::default(), Cipher::default()).unwrap();
+        test_depot(storage.into_ref());
+    }
+
     #[cfg(feature = "storage-file")]
     #[test]
     fn file_depot() {
@@ -917,6 +1535,31 @@ mod tests {
         test_depot(storage.into_ref());
     }
 
+    #[cfg(feature = "storage-container")]
+    #[test]
+    fn container_depot() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let path = tmpdir.path().join("repo.zbox");
+        let uri = format!("container://{}", path.display());
+        let mut storage = Storage::new(&uri).unwrap();
+        storage.connect(false).unwrap();
+        storage.init(Cost::default(), Cipher::default()).unwrap();
+        test_depot(storage.into_ref());
+    }
+
+    #[cfg(feature = "storage-sled")]
+    #[test]
+    fn sled_depot() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let uri = format!("sled://{}", tmpdir.path().join("db").display());
+        let mut storage = Storage::new(&uri).unwrap();
+        storage.connect(false).unwrap();
+        storage.init(Cost::default(), Cipher::default()).unwrap();
+        test_depot(storage.into_ref());
+    }
+
     #[cfg(feature = "storage-redis")]
     #[test]
     fn redis_depot() {
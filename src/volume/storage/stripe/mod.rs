@@ -0,0 +1,3 @@
+mod stripe;
+
+pub use self::stripe::{parse_uri, StripeStorage};
@@ -0,0 +1,443 @@
+use std::fmt::{self, Debug};
+
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use base::crypto::{Crypto, Key};
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{RetryPolicy, Space, Storable};
+use volume::BLK_SIZE;
+
+// separator between member storage uris
+const MEMBER_SEP: char = '|';
+
+// split member storage uris
+// example: file:///mnt/a|gs://bucket/repo
+// return: ["file:///mnt/a", "gs://bucket/repo"]
+pub fn parse_uri(loc: &str) -> Result<Vec<String>> {
+    let members: Vec<String> =
+        loc.split(MEMBER_SEP).map(|s| s.to_string()).collect();
+    if members.len() < 2 || members.iter().any(|m| m.is_empty()) {
+        return Err(Error::InvalidUri);
+    }
+    Ok(members)
+}
+
+/// Stripe mapping
+///
+/// Each member keeps its own copy of the mapping in front of its super
+/// block, so members can be put back in the right order when the repo is
+/// opened.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+struct StripeMap {
+    set_id: Eid,  // stripe set id, same for all members
+    count: usize, // number of members in the stripe set
+    index: usize, // index of this member in the stripe set
+}
+
+impl StripeMap {
+    // prepend stripe mapping to super block
+    fn wrap(&self, super_blk: &[u8]) -> Result<Vec<u8>> {
+        let mut map_buf = Vec::new();
+        self.serialize(&mut Serializer::new(&mut map_buf))?;
+        let mut buf = Vec::with_capacity(2 + map_buf.len() + super_blk.len());
+        buf.extend_from_slice(&(map_buf.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&map_buf);
+        buf.extend_from_slice(super_blk);
+        Ok(buf)
+    }
+
+    // split stripe mapping from super block
+    fn unwrap(buf: &[u8]) -> Result<(Self, &[u8])> {
+        if buf.len() < 2 {
+            return Err(Error::InvalidSuperBlk);
+        }
+        let mut len_buf = [0u8; 2];
+        len_buf.copy_from_slice(&buf[..2]);
+        let map_len = u16::from_le_bytes(len_buf) as usize;
+        if buf.len() < 2 + map_len {
+            return Err(Error::InvalidSuperBlk);
+        }
+        let mut de = Deserializer::new(&buf[2..2 + map_len]);
+        let map: StripeMap = Deserialize::deserialize(&mut de)
+            .map_err(|_| Error::InvalidSuperBlk)?;
+        Ok((map, &buf[2 + map_len..]))
+    }
+}
+
+/// Stripe Storage
+///
+/// Blocks are distributed across member storages in round-robin order of
+/// their indices, block `n` is saved as block `n / count` in member
+/// `n % count`. Super blocks are saved in all members, wals and addresses
+/// are saved in the first member.
+pub struct StripeStorage {
+    members: Vec<Box<dyn Storable>>,
+    set_id: Option<Eid>,
+}
+
+impl StripeStorage {
+    pub fn new(members: Vec<Box<dyn Storable>>) -> Self {
+        StripeStorage {
+            members,
+            set_id: None,
+        }
+    }
+
+    // re-order members according to the stripe mappings loaded from them
+    fn apply_maps(&mut self, maps: Vec<StripeMap>) -> Result<()> {
+        let count = self.members.len();
+        let set_id = maps[0].set_id.clone();
+
+        if let Some(ref id) = self.set_id {
+            if *id != set_id {
+                return Err(Error::InvalidSuperBlk);
+            }
+        }
+
+        let mut slots: Vec<Option<Box<dyn Storable>>> =
+            (0..count).map(|_| None).collect();
+        for (member, map) in self.members.drain(..).zip(maps) {
+            if map.set_id != set_id
+                || map.count != count
+                || map.index >= count
+                || slots[map.index].is_some()
+            {
+                // member storages don't match the stripe set
+                return Err(Error::InvalidSuperBlk);
+            }
+            slots[map.index] = Some(member);
+        }
+        self.members = slots.into_iter().map(|m| m.unwrap()).collect();
+        self.set_id = Some(set_id);
+
+        Ok(())
+    }
+
+    // split a span to member spans, return a list of (member index, span in
+    // member, first block index in the span)
+    fn split_span(&self, span: Span) -> Vec<(usize, Span, usize)> {
+        let count = self.members.len();
+        let mut ret = Vec::with_capacity(count);
+        for idx in 0..count {
+            let first = span.begin + (idx + count - span.begin % count) % count;
+            if first >= span.end() {
+                continue;
+            }
+            let cnt = (span.end() - 1 - first) / count + 1;
+            ret.push((idx, Span::new(first / count, cnt), first));
+        }
+        ret
+    }
+}
+
+impl Storable for StripeStorage {
+    #[inline]
+    fn exists(&self) -> Result<bool> {
+        self.members[0].exists()
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        for member in self.members.iter_mut() {
+            member.set_retry_policy(policy);
+        }
+    }
+
+    fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.set_sector_size(size)?;
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self, force: bool) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.connect(force)?;
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.init(crypto.clone(), key.clone())?;
+        }
+        self.set_id = Some(Eid::new());
+        Ok(())
+    }
+
+    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
+        if self.set_id.is_none() {
+            // load stripe mapping if super block hasn't been loaded
+            self.get_super_block(0)?;
+        }
+        for member in self.members.iter_mut() {
+            member.open(crypto.clone(), key.clone(), force)?;
+        }
+        Ok(())
+    }
+
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        if self.set_id.is_none() {
+            self.get_super_block(0)?;
+        }
+        for member in self.members.iter_mut() {
+            member.open_shared(crypto.clone(), key.clone())?;
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        let mut maps = Vec::with_capacity(self.members.len());
+        let mut ret = Vec::new();
+        for (idx, member) in self.members.iter_mut().enumerate() {
+            let buf = member.get_super_block(suffix)?;
+            let (map, super_blk) = StripeMap::unwrap(&buf)?;
+            if idx == 0 {
+                ret = super_blk.to_vec();
+            }
+            maps.push(map);
+        }
+        self.apply_maps(maps)?;
+        Ok(ret)
+    }
+
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        let set_id = self.set_id.clone().ok_or(Error::InvalidSuperBlk)?;
+        let count = self.members.len();
+        for (index, member) in self.members.iter_mut().enumerate() {
+            let map = StripeMap {
+                set_id: set_id.clone(),
+                count,
+                index,
+            };
+            member.put_super_block(&map.wrap(super_blk)?, suffix)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.members[0].get_wal(id)
+    }
+
+    #[inline]
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        self.members[0].put_wal(id, wal)
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.members[0].del_wal(id)
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.members[0].get_address(id)
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.members[0].put_address(id, addr)
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.members[0].del_address(id)
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let count = self.members.len();
+        for (idx, mspan, first) in self.split_span(span) {
+            let mut buf = vec![0u8; mspan.bytes_len()];
+            self.members[idx].get_blocks(&mut buf, mspan)?;
+
+            // scatter member blocks to their places in destination
+            for (n, blk) in buf.chunks(BLK_SIZE).enumerate() {
+                let pos = (first + n * count - span.begin) * BLK_SIZE;
+                dst[pos..pos + BLK_SIZE].copy_from_slice(blk);
+            }
+        }
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
+        let count = self.members.len();
+        for (idx, mspan, first) in self.split_span(span) {
+            // gather member blocks from source
+            let mut buf = Vec::with_capacity(mspan.bytes_len());
+            for n in 0..mspan.cnt {
+                let pos = (first + n * count - span.begin) * BLK_SIZE;
+                buf.extend_from_slice(&blks[pos..pos + BLK_SIZE]);
+            }
+            self.members[idx].put_blocks(mspan, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        for (idx, mspan, _) in self.split_span(span) {
+            self.members[idx].del_blocks(mspan)?;
+        }
+        Ok(())
+    }
+
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        for (idx, mspan, _) in self.split_span(span) {
+            self.members[idx].shred_blocks(mspan)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.flush()?;
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        for member in self.members.iter_mut() {
+            member.destroy()?;
+        }
+        Ok(())
+    }
+
+    fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        // member block indices never exceed the striped watermark, report
+        // progress as the number of compacted members
+        let count = self.members.len();
+        for (idx, member) in self.members.iter_mut().enumerate() {
+            member.compact(blk_wmark, &mut |_, _| {})?;
+            progress(idx + 1, count);
+        }
+        Ok(())
+    }
+
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        let mut total = Some(0);
+        let mut used = 0;
+        let member_blocks = used_blocks / self.members.len();
+        for member in self.members.iter_mut() {
+            let space = member.space(member_blocks)?;
+            total = total.and_then(|t| space.total.map(|s| t + s));
+            used += space.used;
+        }
+        Ok(Space { total, used })
+    }
+}
+
+impl Debug for StripeStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StripeStorage")
+            .field("members", &self.members)
+            .field("set_id", &self.set_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base::init_env;
+    use volume::storage::mem::MemStorage;
+
+    fn members(locs: &[&str]) -> Vec<Box<dyn Storable>> {
+        locs.iter()
+            .map(|loc| Box::new(MemStorage::new(loc)) as Box<dyn Storable>)
+            .collect()
+    }
+
+    #[test]
+    fn stripe_parse_uri() {
+        let members = parse_uri("mem://foo|file:///bar?x=1").unwrap();
+        assert_eq!(members, vec!["mem://foo", "file:///bar?x=1"]);
+        assert_eq!(parse_uri("mem://foo").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("mem://foo|").unwrap_err(), Error::InvalidUri);
+    }
+
+    #[test]
+    fn stripe_storage() {
+        init_env();
+        let locs = ["stripe_a", "stripe_b", "stripe_c"];
+        let mut ss = StripeStorage::new(members(&locs));
+
+        assert!(!ss.exists().unwrap());
+        ss.connect(false).unwrap();
+        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+        assert!(ss.exists().unwrap());
+
+        let id = Eid::new();
+        let buf = vec![1, 2, 3];
+        let mut blks = vec![0u8; BLK_SIZE * 7];
+        for (n, blk) in blks.chunks_mut(BLK_SIZE).enumerate() {
+            blk.iter_mut().for_each(|b| *b = n as u8 + 1);
+        }
+        let mut dst = vec![0u8; BLK_SIZE * 7];
+
+        // super block
+        ss.put_super_block(&buf, 0).unwrap();
+        let s = ss.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+
+        // wal
+        ss.put_wal(&id, &buf).unwrap();
+        let s = ss.get_wal(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.del_wal(&id).unwrap();
+        assert_eq!(ss.get_wal(&id).unwrap_err(), Error::NotFound);
+
+        // address
+        ss.put_address(&id, &buf).unwrap();
+        let s = ss.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.del_address(&id).unwrap();
+        assert_eq!(ss.get_address(&id).unwrap_err(), Error::NotFound);
+
+        // block
+        let span = Span::new(2, 7);
+        ss.put_blocks(span, &blks).unwrap();
+        ss.get_blocks(&mut dst, span).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        ss.get_blocks(&mut dst[..BLK_SIZE * 2], Span::new(5, 2))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE * 2], &blks[BLK_SIZE * 3..BLK_SIZE * 5]);
+        ss.del_blocks(Span::new(4, 1)).unwrap();
+        assert_eq!(ss.get_blocks(&mut dst, span).unwrap_err(), Error::NotFound);
+
+        // blocks are distributed across members
+        let mut mem = MemStorage::new("stripe_b");
+        mem.get_blocks(&mut dst[..BLK_SIZE], Span::new(2, 1))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE], &blks[BLK_SIZE * 5..BLK_SIZE * 6]);
+
+        // re-open with members in different order
+        drop(ss);
+        let mut ss =
+            StripeStorage::new(members(&["stripe_c", "stripe_a", "stripe_b"]));
+        ss.connect(false).unwrap();
+        let s = ss.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ss.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        ss.get_blocks(&mut dst[..BLK_SIZE * 2], Span::new(2, 2))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE * 2], &blks[..BLK_SIZE * 2]);
+
+        // missing member
+        let mut ss2 = StripeStorage::new(members(&["stripe_a", "stripe_b"]));
+        assert_eq!(ss2.get_super_block(0).unwrap_err(), Error::InvalidSuperBlk);
+    }
+}
@@ -0,0 +1,3 @@
+mod throttle;
+
+pub use self::throttle::{parse_uri, ThrottleStorage};
@@ -0,0 +1,363 @@
+use std::fmt::{self, Debug};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base::crypto::{Crypto, Key};
+use error::{Error, Result};
+use trans::Eid;
+use volume::address::Span;
+use volume::storage::{RetryPolicy, Space, Storable};
+
+/// Throttle options
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ThrottleOpts {
+    bps: Option<u64>, // bytes per second
+    ops: Option<u64>, // operations per second
+}
+
+// parse rate with an optional unit, such as 512kb or 2mb
+fn parse_rate(s: &str) -> Result<u64> {
+    let s = s.to_lowercase();
+    let (num, unit) = if s.ends_with("kb") {
+        (&s[..s.len() - 2], 1024)
+    } else if s.ends_with("mb") {
+        (&s[..s.len() - 2], 1024 * 1024)
+    } else {
+        (&s[..], 1)
+    };
+    let rate = num.parse::<u64>().map_err(|_| Error::InvalidUri)?;
+    if rate == 0 {
+        return Err(Error::InvalidUri);
+    }
+    rate.checked_mul(unit).ok_or(Error::InvalidUri)
+}
+
+// split throttle parameters from underlying storage uri, at least one
+// limit must be specified
+// example: gs://bucket?token=xxx&throttle_bps=1mb&throttle_ops=100
+// return: (
+//   underlying storage uri: "gs://bucket?token=xxx",
+//   throttle options,
+// )
+pub fn parse_uri(uri: &str) -> Result<(String, ThrottleOpts)> {
+    let mut opts = ThrottleOpts::default();
+
+    let idx = uri.find('?').ok_or(Error::InvalidUri)?;
+    let mut params = Vec::new();
+    for param in uri[idx + 1..].split('&') {
+        if !param.starts_with("throttle_") {
+            params.push(param);
+            continue;
+        }
+
+        let idx = param.find('=').ok_or(Error::InvalidUri)?;
+        let key = &param[..idx];
+        let value = &param[idx + 1..];
+        match key {
+            "throttle_bps" => opts.bps = Some(parse_rate(value)?),
+            "throttle_ops" => {
+                let ops =
+                    value.parse::<u64>().map_err(|_| Error::InvalidUri)?;
+                if ops == 0 {
+                    return Err(Error::InvalidUri);
+                }
+                opts.ops = Some(ops);
+            }
+            _ => return Err(Error::InvalidUri),
+        }
+    }
+    if opts.bps.is_none() && opts.ops.is_none() {
+        return Err(Error::InvalidUri);
+    }
+
+    let mut inner_uri = uri[..idx].to_string();
+    if !params.is_empty() {
+        inner_uri.push('?');
+        inner_uri.push_str(&params.join("&"));
+    }
+
+    Ok((inner_uri, opts))
+}
+
+// token bucket rate limiter
+//
+// The bucket holds at most one second worth of tokens, so bursts are
+// allowed up to the rate. Taking more tokens than available puts the bucket
+// in debt, and the caller waits until the debt is paid off.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64, // tokens per second
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    // take tokens, wait if there are not enough tokens
+    fn take(&mut self, cnt: usize) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.tokens -= cnt as f64;
+        if self.tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.tokens / self.rate));
+        }
+    }
+}
+
+/// Throttle Storage
+///
+/// This storage limits the throughput of an underlying storage, in bytes
+/// and operations per second. Each super block, wal, address and blocks
+/// read, write and deletion is counted as one operation. Requests exceeding
+/// the limits are delayed.
+pub struct ThrottleStorage {
+    inner: Box<dyn Storable>,
+    opts: ThrottleOpts,
+    bytes: Option<Bucket>,
+    ops: Option<Bucket>,
+}
+
+impl ThrottleStorage {
+    pub fn new(inner: Box<dyn Storable>, opts: ThrottleOpts) -> Self {
+        ThrottleStorage {
+            inner,
+            opts,
+            bytes: opts.bps.map(Bucket::new),
+            ops: opts.ops.map(Bucket::new),
+        }
+    }
+
+    // account for an operation which transfers `len` bytes
+    fn throttle(&mut self, len: usize) {
+        if let Some(ref mut ops) = self.ops {
+            ops.take(1);
+        }
+        if let Some(ref mut bytes) = self.bytes {
+            bytes.take(len);
+        }
+    }
+}
+
+impl Storable for ThrottleStorage {
+    #[inline]
+    fn exists(&self) -> Result<bool> {
+        self.inner.exists()
+    }
+
+    #[inline]
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.inner.set_retry_policy(policy);
+    }
+
+    #[inline]
+    fn set_sector_size(&mut self, size: usize) -> Result<()> {
+        self.inner.set_sector_size(size)
+    }
+
+    #[inline]
+    fn connect(&mut self, force: bool) -> Result<()> {
+        self.inner.connect(force)
+    }
+
+    #[inline]
+    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.inner.init(crypto, key)
+    }
+
+    #[inline]
+    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
+        self.inner.open(crypto, key, force)
+    }
+
+    #[inline]
+    fn open_shared(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+        self.inner.open_shared(crypto, key)
+    }
+
+    #[inline]
+    fn refresh(&mut self) -> Result<()> {
+        self.inner.refresh()
+    }
+
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        let super_blk = self.inner.get_super_block(suffix)?;
+        self.throttle(super_blk.len());
+        Ok(super_blk)
+    }
+
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        self.throttle(super_blk.len());
+        self.inner.put_super_block(super_blk, suffix)
+    }
+
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        let wal = self.inner.get_wal(id)?;
+        self.throttle(wal.len());
+        Ok(wal)
+    }
+
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        self.throttle(wal.len());
+        self.inner.put_wal(id, wal)
+    }
+
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.throttle(0);
+        self.inner.del_wal(id)
+    }
+
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        let addr = self.inner.get_address(id)?;
+        self.throttle(addr.len());
+        Ok(addr)
+    }
+
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.throttle(addr.len());
+        self.inner.put_address(id, addr)
+    }
+
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.throttle(0);
+        self.inner.del_address(id)
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        self.throttle(dst.len());
+        self.inner.get_blocks(dst, span)
+    }
+
+    fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
+        self.throttle(blks.len());
+        self.inner.put_blocks(span, blks)
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        self.throttle(0);
+        self.inner.del_blocks(span)
+    }
+
+    fn shred_blocks(&mut self, span: Span) -> Result<()> {
+        // shredding overwrites blocks, so it is throttled like a write
+        self.throttle(span.bytes_len());
+        self.inner.shred_blocks(span)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    #[inline]
+    fn destroy(&mut self) -> Result<()> {
+        self.inner.destroy()
+    }
+
+    #[inline]
+    fn compact(
+        &mut self,
+        blk_wmark: usize,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.inner.compact(blk_wmark, progress)
+    }
+
+    #[inline]
+    fn space(&mut self, used_blocks: usize) -> Result<Space> {
+        self.inner.space(used_blocks)
+    }
+}
+
+impl Debug for ThrottleStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThrottleStorage")
+            .field("inner", &self.inner)
+            .field("opts", &self.opts)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base::init_env;
+    #[cfg(feature = "storage-mem")]
+    use volume::storage::mem::MemStorage;
+    #[cfg(feature = "storage-mem")]
+    use volume::BLK_SIZE;
+
+    #[test]
+    fn throttle_parse_uri() {
+        let (uri, opts) = parse_uri(
+            "gs://bucket?throttle_bps=2mb&token=abc&throttle_ops=100",
+        )
+        .unwrap();
+        assert_eq!(uri, "gs://bucket?token=abc");
+        assert_eq!(opts.bps, Some(2 * 1024 * 1024));
+        assert_eq!(opts.ops, Some(100));
+
+        let (uri, opts) = parse_uri("mem://foo?throttle_bps=512KB").unwrap();
+        assert_eq!(uri, "mem://foo");
+        assert_eq!(opts.bps, Some(512 * 1024));
+        assert!(opts.ops.is_none());
+
+        let (_, opts) = parse_uri("mem://foo?throttle_bps=1000").unwrap();
+        assert_eq!(opts.bps, Some(1000));
+
+        for uri in &[
+            "mem://foo",
+            "mem://foo?bar=1",
+            "mem://foo?throttle_bps=0",
+            "mem://foo?throttle_bps=1gb",
+            "mem://foo?throttle_ops=-1",
+            "mem://foo?throttle_foo=1",
+        ] {
+            assert_eq!(parse_uri(uri).unwrap_err(), Error::InvalidUri);
+        }
+    }
+
+    #[cfg(feature = "storage-mem")]
+    #[test]
+    fn throttle_storage() {
+        init_env();
+        let opts = ThrottleOpts {
+            bps: Some(4 * BLK_SIZE as u64),
+            ops: Some(20),
+        };
+        let inner = Box::new(MemStorage::new("throttle_storage_test"));
+        let mut ts = ThrottleStorage::new(inner, opts);
+        ts.connect(false).unwrap();
+        ts.init(Crypto::default(), Key::new_empty()).unwrap();
+
+        // burst within limits is not delayed
+        let blks = vec![42u8; BLK_SIZE * 4];
+        let mut dst = vec![0u8; BLK_SIZE * 4];
+        let now = Instant::now();
+        ts.put_blocks(Span::new(0, 4), &blks).unwrap();
+        assert!(now.elapsed() < Duration::from_millis(200));
+
+        // bytes exceeding the limit are delayed
+        let now = Instant::now();
+        ts.get_blocks(&mut dst[..BLK_SIZE * 2], Span::new(0, 2))
+            .unwrap();
+        assert!(now.elapsed() >= Duration::from_millis(400));
+        assert_eq!(&dst[..BLK_SIZE * 2], &blks[..BLK_SIZE * 2]);
+
+        // operations exceeding the limit are delayed
+        let now = Instant::now();
+        for _ in 0..30 {
+            ts.del_wal(&Eid::new()).unwrap();
+        }
+        assert!(now.elapsed() >= Duration::from_millis(400));
+    }
+}
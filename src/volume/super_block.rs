@@ -1,25 +1,84 @@
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
-use super::storage::Storage;
+use super::key_provider::KeyProvider;
+use super::storage::{strip_db_key, Storage};
 use super::BLK_SIZE;
-use base::crypto::{Cipher, Cost, Crypto, Key, Salt, SALT_SIZE};
+use base::crypto::{
+    BoxPublicKey, BoxSecretKey, Cipher, Cost, Crypto, KdfParams, Key,
+    NonceStrategy, Salt, ScryptParams, KEY_SIZE, SALT_SIZE,
+};
 use base::{Time, Version};
 use error::{Error, Result};
 use trans::Eid;
 
+/// Volume key wrapped by key provider, not encrypted
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub(super) struct KeyWrap {
+    pub uri: String,
+    pub key: Vec<u8>,
+}
+
+/// Super block head extension, not encrypted
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub(super) struct HeadExt {
+    pub pwd_hint: String,
+
+    // custom password hash parameters, they must be stored in head because
+    // they are needed to derive the key which decrypts body
+    #[serde(default)]
+    kdf: Option<KdfParams>,
+
+    // scrypt parameters, present only if scrypt is the password hash
+    // algorithm
+    #[serde(default)]
+    scrypt: Option<ScryptParams>,
+
+    // volume key wrapped by key provider, present only if the volume is
+    // protected by key provider instead of password
+    #[serde(default)]
+    pub key_wrap: Option<KeyWrap>,
+
+    // drop box public key saved by earlier versions, it is not trusted as
+    // head is not authenticated, and it is not saved any more
+    #[serde(default)]
+    drop_pk: Option<BoxPublicKey>,
+
+    // volume key wrapped by password hash, present only if the volume key
+    // is a random key rather than the password hash itself
+    #[serde(default)]
+    pwd_wrap: Option<Vec<u8>>,
+}
+
+impl HeadExt {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.pwd_hint.is_empty()
+            && self.kdf.is_none()
+            && self.scrypt.is_none()
+            && self.key_wrap.is_none()
+            && self.drop_pk.is_none()
+            && self.pwd_wrap.is_none()
+    }
+}
+
 /// Super block head, not encrypted
 #[derive(Debug, Default)]
 pub(super) struct Head {
     pub salt: Salt,
     pub cost: Cost,
     pub cipher: Cipher,
+    pub ext: HeadExt,
 }
 
 impl Head {
     const BYTES_LEN: usize = SALT_SIZE + Cost::BYTES_LEN + Cipher::BYTES_LEN;
 
-    fn seri(&self) -> Vec<u8> {
+    // flag in cipher byte indicates head extension is present, which follows
+    // the fixed head fields as 2 bytes length and the serialized extension
+    const EXT_FLAG: u8 = 0x80;
+
+    fn seri(&self) -> Result<Vec<u8>> {
         let mut pos = 0;
         let mut buf = vec![0u8; Self::BYTES_LEN];
         buf[..SALT_SIZE].copy_from_slice(self.salt.as_ref());
@@ -27,10 +86,26 @@ impl Head {
         buf[pos] = self.cost.to_u8();
         pos += Cost::BYTES_LEN;
         buf[pos] = self.cipher.into();
-        buf
+
+        // append head extension if it is not empty, so that super block
+        // without extension keeps compatible with the old format
+        let mut ext = self.ext.clone();
+        ext.drop_pk = None;
+        ext.kdf = self.cost.kdf;
+        ext.scrypt = self.cost.scrypt;
+        if !ext.is_empty() {
+            buf[pos] |= Self::EXT_FLAG;
+            let mut ext_buf = Vec::new();
+            ext.serialize(&mut Serializer::new(&mut ext_buf))?;
+            buf.extend_from_slice(&(ext_buf.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&ext_buf);
+        }
+
+        Ok(buf)
     }
 
-    fn deseri(buf: &[u8]) -> Result<Self> {
+    // deserialize head, return the head and its length in bytes
+    fn deseri(buf: &[u8]) -> Result<(Self, usize)> {
         if buf.len() < Self::BYTES_LEN {
             return Err(Error::InvalidSuperBlk);
         }
@@ -38,11 +113,44 @@ impl Head {
         let mut pos = 0;
         let salt = Salt::from_slice(&buf[..SALT_SIZE]);
         pos += SALT_SIZE;
-        let cost = Cost::from_u8(buf[pos])?;
+        let mut cost = Cost::from_u8(buf[pos])?;
         pos += Cost::BYTES_LEN;
-        let cipher = Cipher::from_u8(buf[pos])?;
+        let cipher = Cipher::from_u8(buf[pos] & !Self::EXT_FLAG)?;
+        let has_ext = buf[pos] & Self::EXT_FLAG != 0;
+        pos += Cipher::BYTES_LEN;
+
+        // read head extension if it is present
+        let mut ext = HeadExt::default();
+        if has_ext {
+            if buf.len() < pos + 2 {
+                return Err(Error::InvalidSuperBlk);
+            }
+            let mut len_buf = [0u8; 2];
+            len_buf.copy_from_slice(&buf[pos..pos + 2]);
+            let ext_len = u16::from_le_bytes(len_buf) as usize;
+            pos += 2;
+            if buf.len() < pos + ext_len {
+                return Err(Error::InvalidSuperBlk);
+            }
+            let mut de = Deserializer::new(&buf[pos..pos + ext_len]);
+            ext = Deserialize::deserialize(&mut de)?;
+            pos += ext_len;
+        }
+
+        // verify the recorded password hash algorithm and its parameters
+        cost.kdf = ext.kdf;
+        cost.scrypt = ext.scrypt;
+        cost.check().map_err(|_| Error::InvalidSuperBlk)?;
 
-        Ok(Head { salt, cost, cipher })
+        Ok((
+            Head {
+                salt,
+                cost,
+                cipher,
+                ext,
+            },
+            pos,
+        ))
     }
 }
 
@@ -58,6 +166,59 @@ pub(super) struct Body {
     pub ctime: Time,
     pub mtime: Time,
     pub payload: Vec<u8>,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub app_tag: String,
+    #[serde(default)]
+    pub sector_size: usize,
+
+    // cipher used by storage internally, none if it is the head cipher
+    #[serde(default)]
+    pub depot_cipher: Option<Cipher>,
+
+    // cipher being migrated from, none if no migration is in progress
+    #[serde(default)]
+    pub old_cipher: Option<Cipher>,
+
+    // sealed volume can only be opened in read-only mode, older releases
+    // don't know this field and ignore it
+    #[serde(default)]
+    pub sealed: bool,
+
+    // nonce strategy of the head cipher, none if super block is saved
+    // before it was recorded
+    #[serde(default)]
+    nonce_strategy: Option<NonceStrategy>,
+
+    // drop box secret key, none if drop box is not enabled
+    #[serde(default)]
+    pub drop_sk: Option<BoxSecretKey>,
+
+    // drop box public key, none if drop box is not enabled or it is only
+    // saved in head by earlier versions
+    #[serde(default)]
+    pub drop_pk: Option<BoxPublicKey>,
+
+    // key used by storage internally, none if it is the data key, it is
+    // the data key the volume was created with and it is never rotated
+    #[serde(default)]
+    pub depot_key: Option<Key>,
+
+    // data key being rotated from, none if no rotation is in progress
+    #[serde(default)]
+    pub old_key: Option<Key>,
+
+    // number of entities re-encrypted by the data key rotation in
+    // progress, it is saved periodically as checkpoint
+    #[serde(default)]
+    pub rotated: usize,
+
+    // convergent key, none if convergent encryption is not enabled
+    #[serde(default)]
+    pub conv_key: Option<Key>,
 }
 
 impl Body {
@@ -76,27 +237,235 @@ impl Body {
     }
 }
 
+/// Credential used to decrypt super block
+#[derive(Debug, Clone, Copy)]
+pub enum Credential<'a> {
+    // user specified plaintext password
+    Password(&'a str),
+
+    // volume key derived from password in advance
+    Key(&'a Key),
+
+    // key provider which unwraps volume key
+    Provider(&'a dyn KeyProvider),
+
+    // recovery key which wraps volume key, and its recovery password
+    Recovery(&'a [u8], &'a str),
+}
+
 /// Super block
 #[derive(Debug, Default)]
 pub(super) struct SuperBlk {
     pub head: Head,
     pub body: Body,
+
+    // random volume key wrapped by password hash, it is kept in memory
+    // only and none if the volume key is the password hash itself
+    vkey: Option<Key>,
 }
 
 impl SuperBlk {
     // magic numbers for body AEAD encryption
     const MAGIC: [u8; 4] = [233, 239, 241, 251];
 
+    // magic numbers for volume key wrapping
+    const WRAP_MAGIC: [u8; 4] = [211, 223, 227, 229];
+
+    // magic numbers for volume key wrapping in recovery key
+    const RECOVERY_MAGIC: [u8; 4] = [239, 233, 229, 227];
+
+    // get the key from credential, which is either the password hash or
+    // the volume key unwrapped by key provider
+    fn cred_key(
+        head: &Head,
+        crypto: &Crypto,
+        cred: &Credential,
+    ) -> Result<Key> {
+        match *cred {
+            Credential::Password(pwd) => {
+                // password cannot be used if volume key is wrapped
+                if head.ext.key_wrap.is_some() {
+                    return Err(Error::KeyProvider);
+                }
+                crypto.hash_pwd(pwd, &head.salt).map(|hash| hash.value)
+            }
+            Credential::Key(key) => Ok(key.clone()),
+            Credential::Provider(provider) => {
+                let wrap =
+                    head.ext.key_wrap.as_ref().ok_or(Error::KeyProvider)?;
+                let mut key = Key::new_empty();
+                provider.unwrap_key(&wrap.key, key.as_mut_slice())?;
+                Ok(key)
+            }
+
+            // recovery key unwraps volume key directly
+            Credential::Recovery(..) => Err(Error::InvalidArgument),
+        }
+    }
+
+    // get the volume key which encrypts body from credential
+    fn vol_key(head: &Head, crypto: &Crypto, cred: &Credential) -> Result<Key> {
+        if let Credential::Recovery(recovery, recovery_pwd) = *cred {
+            return Self::unwrap_recovery(recovery, recovery_pwd);
+        }
+        let key = Self::cred_key(head, crypto, cred)?;
+        match head.ext.pwd_wrap {
+            Some(ref wrapped) => {
+                if wrapped.len() != crypto.encrypted_len(KEY_SIZE) {
+                    return Err(Error::InvalidSuperBlk);
+                }
+                let mut vkey = Key::new_empty();
+                crypto.decrypt_raw(
+                    vkey.as_mut_slice(),
+                    wrapped,
+                    &key,
+                    &Self::WRAP_MAGIC,
+                )?;
+                Ok(vkey)
+            }
+            None => Ok(key),
+        }
+    }
+
+    // create a recovery key, which is the volume key wrapped by recovery
+    // password hash, it is not affected by password reset
+    //
+    // recovery key is always wrapped using XChaCha20 and the default cost,
+    // so it can be unwrapped without knowing the volume settings, the format
+    // is: recovery password salt + wrapped volume key
+    pub fn export_recovery(
+        pwd: &str,
+        recovery_pwd: &str,
+        storage: &mut Storage,
+    ) -> Result<Vec<u8>> {
+        let mut super_blk = Self::load(pwd, storage)?;
+
+        // volume key which is the password hash itself would be changed by
+        // password reset, so convert it to a random volume key first
+        if super_blk.vkey.is_none() {
+            super_blk.rotate_vol_key();
+            super_blk.save(pwd, storage)?;
+        }
+        let vkey = super_blk.vkey.as_ref().unwrap();
+
+        let crypto = Crypto::new(Cost::default(), Cipher::Xchacha)?;
+        let salt = Salt::new();
+        let hash = crypto.hash_pwd(recovery_pwd, &salt)?;
+        let mut buf = salt.as_ref().to_vec();
+        buf.extend_from_slice(&crypto.encrypt_with_ad(
+            vkey.as_slice(),
+            &hash.value,
+            &Self::RECOVERY_MAGIC,
+        )?);
+        Ok(buf)
+    }
+
+    // unwrap volume key from recovery key using recovery password
+    fn unwrap_recovery(recovery: &[u8], recovery_pwd: &str) -> Result<Key> {
+        let crypto = Crypto::new(Cost::default(), Cipher::Xchacha)?;
+        if recovery.len() != SALT_SIZE + crypto.encrypted_len(KEY_SIZE) {
+            return Err(Error::InvalidArgument);
+        }
+        let salt = Salt::from_slice(&recovery[..SALT_SIZE]);
+        let hash = crypto.hash_pwd(recovery_pwd, &salt)?;
+        let mut vkey = Key::new_empty();
+        crypto.decrypt_raw(
+            vkey.as_mut_slice(),
+            &recovery[SALT_SIZE..],
+            &hash.value,
+            &Self::RECOVERY_MAGIC,
+        )?;
+        Ok(vkey)
+    }
+
+    // get drop box key pair, none if drop box is not enabled
+    //
+    // public key saved in head by earlier versions is used only if it
+    // matches the secret key
+    pub fn drop_box(&self) -> Result<Option<(BoxPublicKey, BoxSecretKey)>> {
+        let sk = match self.body.drop_sk {
+            Some(ref sk) => sk.clone(),
+            None => return Ok(None),
+        };
+        if let Some(pk) = self.body.drop_pk {
+            return Ok(Some((pk, sk)));
+        }
+        let pk = self.head.ext.drop_pk.ok_or(Error::InvalidSuperBlk)?;
+        let probe = Crypto::gen_master_key();
+        let sealed = Crypto::seal_key(&probe, &pk)?;
+        match Crypto::open_sealed_key(&sealed, &pk, &sk) {
+            Ok(ref key) if *key == probe => Ok(Some((pk, sk))),
+            _ => Err(Error::InvalidSuperBlk),
+        }
+    }
+
+    // derive password hash using current head, it can be used as key
+    // credential to load and save super block without hashing again
+    pub fn pwd_key(&self, pwd: &str) -> Result<Key> {
+        let crypto =
+            Crypto::new(self.head.cost, self.head.cipher.key_cipher())?;
+        Self::cred_key(&self.head, &crypto, &Credential::Password(pwd))
+    }
+
+    // replace the volume key with a new random key, the password hash salt
+    // is also renewed so keys derived before cannot be used any more
+    pub fn rotate_vol_key(&mut self) {
+        self.head.salt = Salt::new();
+        self.vkey = Some(Crypto::gen_master_key());
+    }
+
     // save super blocks
+    #[inline]
     pub fn save(&mut self, pwd: &str, storage: &mut Storage) -> Result<()> {
-        let crypto = Crypto::new(self.head.cost, self.head.cipher)?;
+        self.save_with(&Credential::Password(pwd), storage)
+    }
+
+    // save super blocks using password, volume key or key provider
+    pub fn save_with(
+        &mut self,
+        cred: &Credential,
+        storage: &mut Storage,
+    ) -> Result<()> {
+        // body contains keys, so it is encrypted even if the volume is in
+        // integrity-only mode
+        let crypto =
+            Crypto::new(self.head.cost, self.head.cipher.key_cipher())?;
+        self.body.nonce_strategy = Some(self.head.cipher.nonce_strategy());
+
+        // database key saved by older versions is removed on next save
+        self.body.uri = strip_db_key(&self.body.uri);
 
-        // hash user specified plaintext password
-        let pwd_hash = crypto.hash_pwd(pwd, &self.head.salt)?;
-        let vkey = &pwd_hash.value;
+        // generate a random volume key and wrap it if key provider is used
+        // for the first time
+        if let Credential::Provider(provider) = *cred {
+            if self.head.ext.key_wrap.is_none() {
+                let key = Crypto::gen_master_key();
+                self.head.ext.key_wrap = Some(KeyWrap {
+                    uri: provider.uri(),
+                    key: provider.wrap_key(key.as_slice())?,
+                });
+            }
+        }
+
+        // get volume key from credential, random volume key is wrapped by
+        // the password hash
+        let vkey = match (cred, &self.vkey) {
+            (Credential::Provider(_), _) | (_, None) => {
+                Self::vol_key(&self.head, &crypto, cred)?
+            }
+            (_, Some(vkey)) => {
+                let key = Self::cred_key(&self.head, &crypto, cred)?;
+                self.head.ext.pwd_wrap = Some(crypto.encrypt_with_ad(
+                    vkey.as_slice(),
+                    &key,
+                    &Self::WRAP_MAGIC,
+                )?);
+                vkey.clone()
+            }
+        };
 
         // serialize head and body
-        let head_buf = self.head.seri();
+        let head_buf = self.head.seri()?;
         let body_buf = self.body.seri()?;
 
         // compose buffer: body buffer length + body buffer + padding
@@ -111,9 +480,9 @@ impl SuperBlk {
         }
         comp_buf.resize(new_len, 0);
 
-        // encrypt composed buffer using the volume key, which is the user
-        // password hash
-        let enc_buf = crypto.encrypt_with_ad(&comp_buf, vkey, &Self::MAGIC)?;
+        // encrypt composed buffer using the volume key, which is either the
+        // user password hash or the key unwrapped by key provider
+        let enc_buf = crypto.encrypt_with_ad(&comp_buf, &vkey, &Self::MAGIC)?;
 
         // combine head and compose buffer and save 2 copies to storage
         let mut pos = 0;
@@ -127,38 +496,77 @@ impl SuperBlk {
     }
 
     // load a specific super block arm
-    fn load_arm(suffix: u64, pwd: &str, storage: &mut Storage) -> Result<Self> {
+    fn load_arm(
+        suffix: u64,
+        cred: &Credential,
+        storage: &mut Storage,
+    ) -> Result<Self> {
         // read raw bytes
         let buf = storage.get_super_block(suffix)?;
 
         // read header
-        let head = Head::deseri(&buf)?;
+        let (head, head_len) = Head::deseri(&buf)?;
 
-        // create crypto
-        let crypto = Crypto::new(head.cost, head.cipher)?;
+        // create crypto for body, which is always encrypted
+        let crypto = Crypto::new(head.cost, head.cipher.key_cipher())?;
 
-        // derive volume key and use it to decrypt body
-        let pwd_hash = crypto.hash_pwd(pwd, &head.salt)?;
-        let vkey = &pwd_hash.value;
+        // get volume key from credential and use it to decrypt body
+        let vkey = Self::vol_key(&head, &crypto, cred)?;
 
         // read encryped body
-        let comp_buf = crypto.decrypt_with_ad(
-            &buf[Head::BYTES_LEN..],
-            vkey,
-            &Self::MAGIC,
-        )?;
+        let comp_buf =
+            crypto.decrypt_with_ad(&buf[head_len..], &vkey, &Self::MAGIC)?;
         let mut buf: [u8; 8] = Default::default();
         buf.copy_from_slice(&comp_buf[..8]);
         let body_buf_len = u64::from_le_bytes(buf) as usize;
         let body = Body::deseri(&comp_buf[8..8 + body_buf_len])?;
 
-        Ok(SuperBlk { head, body })
+        // recorded nonce strategy must match the cipher
+        match body.nonce_strategy {
+            Some(ns) if ns != head.cipher.nonce_strategy() => {
+                return Err(Error::InvalidSuperBlk);
+            }
+            _ => {}
+        }
+
+        // keep the random volume key, so it can be wrapped again on save
+        let vkey = head.ext.pwd_wrap.as_ref().map(|_| vkey);
+
+        Ok(SuperBlk { head, body, vkey })
+    }
+
+    // load super block head only, no password is needed
+    pub fn load_head(storage: &mut Storage) -> Result<Head> {
+        // try left arm first and then the right arm
+        storage
+            .get_super_block(0)
+            .and_then(|buf| Head::deseri(&buf))
+            .or_else(|_| {
+                storage
+                    .get_super_block(1)
+                    .and_then(|buf| Head::deseri(&buf))
+            })
+            .map(|(head, _)| head)
+    }
+
+    // derive password hash which decrypts or unwraps volume key, no super
+    // block body is decrypted
+    pub fn derive_key(pwd: &str, storage: &mut Storage) -> Result<Key> {
+        let head = Self::load_head(storage)?;
+        let crypto = Crypto::new(head.cost, head.cipher.key_cipher())?;
+        Self::cred_key(&head, &crypto, &Credential::Password(pwd))
     }
 
     // load super block from both left and right arm
+    #[inline]
     pub fn load(pwd: &str, storage: &mut Storage) -> Result<Self> {
-        let left = Self::load_arm(0, pwd, storage)?;
-        let right = Self::load_arm(1, pwd, storage)?;
+        Self::load_with(&Credential::Password(pwd), storage)
+    }
+
+    // load super block using either password or volume key
+    pub fn load_with(cred: &Credential, storage: &mut Storage) -> Result<Self> {
+        let left = Self::load_arm(0, cred, storage)?;
+        let right = Self::load_arm(1, cred, storage)?;
 
         if left.body.seq == right.body.seq {
             Ok(left)
@@ -169,8 +577,9 @@ impl SuperBlk {
 
     // try to repair super block using at least one valid
     pub fn repair(pwd: &str, storage: &mut Storage) -> Result<()> {
-        let left_arm = Self::load_arm(0, pwd, storage);
-        let right_arm = Self::load_arm(1, pwd, storage);
+        let cred = Credential::Password(pwd);
+        let left_arm = Self::load_arm(0, &cred, storage);
+        let right_arm = Self::load_arm(1, &cred, storage);
 
         match left_arm {
             Ok(mut left) => match right_arm {
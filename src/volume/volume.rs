@@ -1,11 +1,14 @@
 use std::fmt::{self, Debug};
 use std::io::{Read, Result as IoResult, Write};
+use std::mem;
 use std::sync::{Arc, RwLock, Weak};
 
 use super::allocator::AllocatorRef;
-use super::storage::{self, Storage, StorageRef};
-use super::super_block::SuperBlk;
-use base::crypto::{Cipher, Cost, Salt};
+use super::storage::{self, Health, RetryPolicy, Space, Storage, StorageRef};
+use super::super_block::{Credential, SuperBlk};
+use base::crypto::{
+    BoxPublicKey, BoxSecretKey, Cipher, Cost, Crypto, Key, Salt,
+};
 use base::lz4::{
     BlockMode, BlockSize, ContentChecksum, Decoder as Lz4Decoder,
     Encoder as Lz4Encoder, EncoderBuilder as Lz4EncoderBuilder,
@@ -22,9 +25,40 @@ pub struct Info {
     pub ver: Version,
     pub uri: String,
     pub compress: bool,
+    pub sector_size: usize,
     pub cost: Cost,
     pub cipher: Cipher,
+    pub old_cipher: Option<Cipher>,
+    pub rotated: Option<usize>,
+    pub sealed: bool,
     pub ctime: Time,
+    pub label: String,
+    pub description: String,
+    pub app_tag: String,
+    pub drop_box: Option<(BoxPublicKey, BoxSecretKey)>,
+    pub convergent: bool,
+}
+
+/// Phase of opening a repository
+///
+/// Phases are reported in the below order by the callback set by
+/// [`RepoOpener::on_progress`].
+///
+/// [`RepoOpener::on_progress`]: struct.RepoOpener.html#method.on_progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPhase {
+    /// Deriving key from password and decrypting super block, which can be
+    /// slow depending on the password hash cost
+    KeyDerivation,
+
+    /// Opening storage using the keys stored in super block
+    SuperBlock,
+
+    /// Loading write-ahead log and redoing interrupted transactions
+    WalRedo,
+
+    /// Loading file system tree
+    TreeLoad,
 }
 
 /// Volume
@@ -35,19 +69,29 @@ pub struct Volume {
 }
 
 impl Volume {
+    // magic bytes authenticated in key domain key check
+    const DOMAIN_MAGIC: [u8; 4] = [199, 197, 193, 191];
+
     /// Create volume instance
     pub fn new(uri: &str) -> Result<Self> {
         let mut info = Info::default();
-        info.uri = uri.to_string();
+        info.uri = storage::strip_db_key(uri);
         let storage = Storage::new(uri)?.into_ref();
 
         Ok(Volume { info, storage })
     }
 
-    /// Initialise volume
+    /// Set retry policy for underlying storage
+    #[inline]
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_retry_policy(policy);
+    }
+
+    /// Initialise volume using either password or key provider
     pub fn init(
         &mut self,
-        pwd: &str,
+        cred: Credential,
         cfg: &Config,
         payload: &[u8],
     ) -> Result<()> {
@@ -55,31 +99,45 @@ impl Volume {
         storage.connect(false)?;
 
         // initialise storage
+        storage.set_sector_size(cfg.sector_size)?;
         storage.init(cfg.cost, cfg.cipher)?;
 
         // initialise info
         self.info.id = Eid::new();
         self.info.ver = Version::repo_version();
         self.info.compress = cfg.compress;
+        self.info.sector_size = cfg.sector_size;
         self.info.cost = cfg.cost;
         self.info.cipher = cfg.cipher;
         self.info.ctime = Time::now();
+        self.info.label = cfg.label.clone();
+        self.info.description = cfg.description.clone();
+        self.info.app_tag = cfg.app_tag.clone();
 
         // initialise super block
         let mut super_blk = SuperBlk::default();
         super_blk.head.salt = Salt::new();
         super_blk.head.cost = cfg.cost;
         super_blk.head.cipher = cfg.cipher;
+        super_blk.head.ext.pwd_hint = cfg.pwd_hint.clone();
         super_blk.body.volume_id = self.info.id.clone();
         super_blk.body.ver = self.info.ver.clone();
         super_blk.body.key = storage.get_key().clone();
         super_blk.body.uri = self.info.uri.clone();
         super_blk.body.compress = cfg.compress;
+        super_blk.body.sector_size = cfg.sector_size;
         super_blk.body.ctime = self.info.ctime;
         super_blk.body.payload = payload.to_vec();
+        super_blk.body.label = cfg.label.clone();
+        super_blk.body.description = cfg.description.clone();
+        super_blk.body.app_tag = cfg.app_tag.clone();
+
+        // password hash is used as volume key until it is rotated or a
+        // recovery key is exported, because the wrapped random volume key
+        // is kept in head extension which older versions cannot read
 
         // save super block
-        super_blk.save(pwd, &mut storage)?;
+        super_blk.save_with(&cred, &mut storage)?;
 
         debug!("volume initialised");
 
@@ -87,33 +145,74 @@ impl Volume {
     }
 
     /// Open volume, return super block payload and meta payload
-    pub fn open(&mut self, pwd: &str, force: bool) -> Result<Vec<u8>> {
+    pub fn open(
+        &mut self,
+        cred: Credential,
+        read_only: bool,
+        force: bool,
+        progress: &dyn Fn(OpenPhase),
+    ) -> Result<Vec<u8>> {
         let mut storage = self.storage.write().unwrap();
         storage.connect(force)?;
 
         // load super block from storage
-        let super_blk = SuperBlk::load(pwd, &mut storage)?;
+        progress(OpenPhase::KeyDerivation);
+        let super_blk = SuperBlk::load_with(&cred, &mut storage)?;
 
         // check volume version
         if !super_blk.body.ver.match_repo_version() {
             return Err(Error::WrongVersion);
         }
 
+        // sealed volume cannot be written
+        if super_blk.body.sealed && !read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let drop_box = super_blk.drop_box()?;
+
         // open storage
+        progress(OpenPhase::SuperBlock);
+        storage.set_sector_size(super_blk.body.sector_size)?;
         storage.open(
             super_blk.head.cost,
             super_blk.head.cipher,
-            super_blk.body.key.clone(),
+            super_blk.body.depot_cipher.unwrap_or(super_blk.head.cipher),
+            super_blk
+                .body
+                .depot_key
+                .as_ref()
+                .unwrap_or(&super_blk.body.key),
+            read_only,
             force,
         )?;
+        storage.set_cipher(super_blk.head.cipher, super_blk.body.old_cipher)?;
+        storage.set_key(
+            super_blk.body.key.clone(),
+            super_blk.body.old_key.clone(),
+        );
+        storage.set_convergent_key(super_blk.body.conv_key.clone());
 
         // set up info
         self.info.id = super_blk.body.volume_id.clone();
         self.info.ver = super_blk.body.ver;
         self.info.compress = super_blk.body.compress;
+        self.info.sector_size = super_blk.body.sector_size;
         self.info.cost = super_blk.head.cost;
         self.info.cipher = super_blk.head.cipher;
+        self.info.old_cipher = super_blk.body.old_cipher;
+        self.info.rotated = if super_blk.body.old_key.is_some() {
+            Some(super_blk.body.rotated)
+        } else {
+            None
+        };
+        self.info.sealed = super_blk.body.sealed;
         self.info.ctime = super_blk.body.ctime;
+        self.info.label = super_blk.body.label.clone();
+        self.info.description = super_blk.body.description.clone();
+        self.info.app_tag = super_blk.body.app_tag.clone();
+        self.info.drop_box = drop_box;
+        self.info.convergent = super_blk.body.conv_key.is_some();
 
         debug!("volume opened: {}", *storage);
 
@@ -154,12 +253,346 @@ impl Volume {
         Ok(())
     }
 
+    /// Reset volume password using recovery key and its recovery password
+    pub fn reset_password_with_recovery(
+        &mut self,
+        recovery: &[u8],
+        recovery_pwd: &str,
+        new_pwd: &str,
+        cost: Cost,
+    ) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+
+        // recovery key unwraps volume key, so super block can be saved
+        // with new password without knowing the old one
+        let cred = Credential::Recovery(recovery, recovery_pwd);
+        let mut super_blk = SuperBlk::load_with(&cred, &mut storage)?;
+        super_blk.head.cost = cost;
+        super_blk.save(new_pwd, &mut storage)?;
+
+        self.info.cost = cost;
+
+        Ok(())
+    }
+
+    /// Replace volume key with a new random key, only super block is
+    /// re-encrypted
+    pub fn rotate_master_key(&mut self, pwd: &str) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.rotate_vol_key();
+        super_blk.save(pwd, &mut storage)
+    }
+
+    /// Update volume label, description and application tag
+    pub fn set_label(
+        &mut self,
+        pwd: &str,
+        label: &str,
+        description: &str,
+        app_tag: &str,
+    ) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+
+        // load current super block and save it with new labels
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.body.label = label.to_string();
+        super_blk.body.description = description.to_string();
+        super_blk.body.app_tag = app_tag.to_string();
+        super_blk.save(pwd, &mut storage)?;
+
+        self.info.label = label.to_string();
+        self.info.description = description.to_string();
+        self.info.app_tag = app_tag.to_string();
+
+        Ok(())
+    }
+
+    /// Update compression setting, it only applies to new writes
+    pub fn set_compress(&mut self, pwd: &str, compress: bool) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.body.compress = compress;
+        super_blk.save(pwd, &mut storage)?;
+        self.info.compress = compress;
+        Ok(())
+    }
+
+    /// Begin migrating entities to a new cipher, entities are re-encrypted
+    /// by `reencrypt` and then the migration is ended by
+    /// `end_cipher_migration`
+    ///
+    /// If a migration to the same cipher is in progress, it is resumed.
+    pub fn begin_cipher_migration(
+        &mut self,
+        pwd: &str,
+        cipher: Cipher,
+    ) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        let head_cipher = super_blk.head.cipher;
+
+        // cannot migrate cipher during data key rotation
+        if super_blk.body.old_key.is_some() {
+            return Err(Error::InvalidArgument);
+        }
+
+        match super_blk.body.old_cipher {
+            // resume the migration in progress
+            Some(_) if head_cipher == cipher => return Ok(()),
+            // cannot switch target cipher during migration
+            Some(_) => return Err(Error::InvalidArgument),
+            None if head_cipher == cipher => return Ok(()),
+            None => {}
+        }
+
+        // make sure the new cipher is supported
+        Crypto::new(super_blk.head.cost, cipher)?;
+
+        // storage keeps using the original cipher internally
+        super_blk.body.depot_cipher.get_or_insert(head_cipher);
+        super_blk.body.old_cipher = Some(head_cipher);
+        super_blk.head.cipher = cipher;
+        super_blk.save(pwd, &mut storage)?;
+        storage.set_cipher(cipher, Some(head_cipher))?;
+
+        self.info.cipher = cipher;
+        self.info.old_cipher = Some(head_cipher);
+
+        Ok(())
+    }
+
+    /// End cipher migration, all entities and wals must have been
+    /// re-encrypted
+    pub fn end_cipher_migration(&mut self, pwd: &str) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.body.old_cipher = None;
+        super_blk.save(pwd, &mut storage)?;
+        storage.set_cipher(super_blk.head.cipher, None)?;
+        self.info.old_cipher = None;
+        Ok(())
+    }
+
+    /// Begin rotating data key, entities are re-encrypted by `reencrypt`
+    /// using a new random data key and then the rotation is ended by
+    /// `end_key_rotation`
+    ///
+    /// If a rotation is in progress, it is resumed. Return the number of
+    /// re-encrypted entities saved in the last checkpoint, and the password
+    /// hash which is used to update super block until the rotation is ended.
+    ///
+    /// Volume key is rotated as well, but storage keeps using the data key
+    /// the volume was created with internally.
+    pub fn begin_key_rotation(&mut self, pwd: &str) -> Result<(usize, Key)> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+
+        // cannot rotate data key during cipher migration
+        if super_blk.body.old_cipher.is_some() {
+            return Err(Error::InvalidArgument);
+        }
+
+        // resume the rotation in progress
+        if super_blk.body.old_key.is_some() {
+            let pwd_key = super_blk.pwd_key(pwd)?;
+            return Ok((super_blk.body.rotated, pwd_key));
+        }
+
+        // volume key might have been exposed along with data key
+        super_blk.rotate_vol_key();
+        let pwd_key = super_blk.pwd_key(pwd)?;
+
+        // storage keeps using the original key internally
+        let old_key =
+            mem::replace(&mut super_blk.body.key, Crypto::gen_master_key());
+        super_blk.body.depot_key.get_or_insert(old_key.clone());
+        super_blk.body.old_key = Some(old_key.clone());
+        super_blk.body.rotated = 0;
+        super_blk.save_with(&Credential::Key(&pwd_key), &mut storage)?;
+        storage.set_key(super_blk.body.key.clone(), Some(old_key));
+        self.info.rotated = Some(0);
+
+        Ok((0, pwd_key))
+    }
+
+    /// Save the number of re-encrypted entities as data key rotation
+    /// checkpoint, storage is flushed first so that the re-encrypted
+    /// entities are durable
+    pub fn checkpoint_key_rotation(
+        &mut self,
+        pwd_key: &Key,
+        rotated: usize,
+    ) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.flush()?;
+        let cred = Credential::Key(pwd_key);
+        let mut super_blk = SuperBlk::load_with(&cred, &mut storage)?;
+        super_blk.body.rotated = rotated;
+        super_blk.save_with(&cred, &mut storage)?;
+        self.info.rotated = Some(rotated);
+        Ok(())
+    }
+
+    /// End data key rotation, all entities and wals must have been
+    /// re-encrypted
+    pub fn end_key_rotation(&mut self, pwd_key: &Key) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let cred = Credential::Key(pwd_key);
+        let mut super_blk = SuperBlk::load_with(&cred, &mut storage)?;
+        super_blk.body.old_key = None;
+        super_blk.body.rotated = 0;
+        super_blk.save_with(&cred, &mut storage)?;
+        storage.set_key(super_blk.body.key.clone(), None);
+        self.info.rotated = None;
+        Ok(())
+    }
+
+    /// Update password hint
+    pub fn set_password_hint(&mut self, pwd: &str, hint: &str) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.head.ext.pwd_hint = hint.to_string();
+        super_blk.save(pwd, &mut storage)
+    }
+
+    /// Seal volume, so it can only be opened in read-only mode afterwards
+    pub fn seal(&mut self, pwd: &str) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        super_blk.body.sealed = true;
+        super_blk.save(pwd, &mut storage)?;
+        self.info.sealed = true;
+        Ok(())
+    }
+
+    /// Enable drop box, return its public key
+    pub fn enable_drop_box(&mut self, pwd: &str) -> Result<BoxPublicKey> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        if let Some(pk) = super_blk.body.drop_pk {
+            return Ok(pk);
+        }
+
+        // key pair saved by earlier versions is kept, its public key is
+        // moved to body so it is authenticated
+        let (pk, sk) = super_blk
+            .drop_box()?
+            .unwrap_or_else(Crypto::gen_box_keypair);
+        super_blk.body.drop_pk = Some(pk);
+        super_blk.body.drop_sk = Some(sk.clone());
+        super_blk.save(pwd, &mut storage)?;
+        self.info.drop_box = Some((pk, sk));
+        Ok(pk)
+    }
+
+    /// Enable convergent encryption using the specified convergent key
+    ///
+    /// The convergent key cannot be changed once it is set.
+    pub fn enable_convergent(&mut self, pwd: &str, key: Key) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        let mut super_blk = SuperBlk::load(pwd, &mut storage)?;
+        match super_blk.body.conv_key {
+            Some(ref conv_key) if *conv_key == key => return Ok(()),
+            Some(_) => return Err(Error::InvalidArgument),
+            None => {}
+        }
+        super_blk.body.conv_key = Some(key.clone());
+        super_blk.save(pwd, &mut storage)?;
+        storage.set_convergent_key(Some(key));
+        self.info.convergent = true;
+        Ok(())
+    }
+
+    /// Export recovery key which is protected by recovery password
+    pub fn export_recovery_key(
+        &mut self,
+        pwd: &str,
+        recovery_pwd: &str,
+    ) -> Result<Vec<u8>> {
+        let mut storage = self.storage.write().unwrap();
+        SuperBlk::export_recovery(pwd, recovery_pwd, &mut storage)
+    }
+
+    /// Create a new key domain and unlock it, return the domain id and its
+    /// key check
+    ///
+    /// The key check is the domain id authenticated by the domain key, it is
+    /// always encrypted by XChaCha20 so it is not affected by cipher
+    /// migration.
+    pub fn new_key_domain(&mut self, key: Key) -> Result<(Eid, Vec<u8>)> {
+        let id = Eid::new();
+        let crypto = Crypto::new(self.info.cost, Cipher::Xchacha)?;
+        let check =
+            crypto.encrypt_with_ad(&Self::DOMAIN_MAGIC, &key, id.as_ref())?;
+        let mut storage = self.storage.write().unwrap();
+        storage.set_domain_key(&id, key);
+        Ok((id, check))
+    }
+
+    /// Unlock key domain, the key is verified by the key check
+    pub fn unlock_key_domain(
+        &mut self,
+        id: &Eid,
+        key: Key,
+        check: &[u8],
+    ) -> Result<()> {
+        let crypto = Crypto::new(self.info.cost, Cipher::Xchacha)?;
+        let magic = crypto.decrypt_with_ad(check, &key, id.as_ref())?;
+        if magic[..] != Self::DOMAIN_MAGIC[..] {
+            return Err(Error::Decrypt);
+        }
+        let mut storage = self.storage.write().unwrap();
+        storage.set_domain_key(id, key);
+        Ok(())
+    }
+
+    /// Check if key domain is unlocked
+    #[inline]
+    pub fn is_domain_unlocked(&self, id: &Eid) -> bool {
+        let storage = self.storage.read().unwrap();
+        storage.has_domain_key(id)
+    }
+
+    /// Derive volume key from password, which can be used to open volume
+    pub fn derive_key(&mut self, pwd: &str) -> Result<Key> {
+        let mut storage = self.storage.write().unwrap();
+        storage.connect(false)?;
+        SuperBlk::derive_key(pwd, &mut storage)
+    }
+
+    /// Get password hint, no password is needed
+    pub fn password_hint(&mut self) -> Result<Option<String>> {
+        let mut storage = self.storage.write().unwrap();
+        storage.connect(false)?;
+        let head = SuperBlk::load_head(&mut storage)?;
+        if head.ext.pwd_hint.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(head.ext.pwd_hint))
+        }
+    }
+
+    /// Get key provider URI, no password is needed
+    pub fn key_provider_uri(&mut self) -> Result<Option<String>> {
+        let mut storage = self.storage.write().unwrap();
+        storage.connect(false)?;
+        let head = SuperBlk::load_head(&mut storage)?;
+        Ok(head.ext.key_wrap.map(|wrap| wrap.uri))
+    }
+
     // get volume info
     #[inline]
     pub fn info(&self) -> Info {
         self.info.clone()
     }
 
+    /// Check if volume is sealed
+    #[inline]
+    pub fn is_sealed(&self) -> bool {
+        self.info.sealed
+    }
+
     // get allocator from storage
     #[inline]
     pub fn get_allocator(&self) -> AllocatorRef {
@@ -174,6 +607,34 @@ impl Volume {
         storage.del_wal(id)
     }
 
+    // check if an entity exists
+    #[inline]
+    pub fn contains(&self, id: &Eid) -> Result<bool> {
+        let mut storage = self.storage.write().unwrap();
+        storage.contains(id)
+    }
+
+    // re-encrypt an entity using current cipher and key if it is not yet
+    #[inline]
+    pub fn reencrypt(&mut self, id: &Eid) -> Result<bool> {
+        let mut storage = self.storage.write().unwrap();
+        storage.reencrypt(id)
+    }
+
+    // re-encrypt a wal using current cipher and key if it is not yet
+    #[inline]
+    pub fn reencrypt_wal(&mut self, id: &Eid) -> Result<bool> {
+        let mut storage = self.storage.write().unwrap();
+        storage.reencrypt_wal(id)
+    }
+
+    // turn on or off shredding of deleted entities
+    #[inline]
+    pub fn set_shred(&mut self, shred: bool) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_shred(shred)
+    }
+
     // delete an entity
     #[inline]
     pub fn del(&mut self, id: &Eid) -> Result<()> {
@@ -187,12 +648,57 @@ impl Volume {
         storage.flush()
     }
 
+    // reload storage state changed by another writer
+    #[inline]
+    pub fn refresh(&mut self) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.refresh()
+    }
+
     // permanently destroy a volume
     #[inline]
     pub fn destroy(&mut self) -> Result<()> {
         let mut storage = self.storage.write().unwrap();
         storage.destroy()
     }
+
+    // get storage space usage
+    #[inline]
+    pub fn space(&self) -> Result<Space> {
+        let mut storage = self.storage.write().unwrap();
+        storage.space()
+    }
+
+    // reserve storage blocks ahead of writes
+    #[inline]
+    pub fn reserve(&self, blk_cnt: usize) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.reserve(blk_cnt)
+    }
+
+    // release reserved storage blocks
+    #[inline]
+    pub fn release(&self, blk_cnt: usize) {
+        let mut storage = self.storage.write().unwrap();
+        storage.release(blk_cnt)
+    }
+
+    // reclaim space occupied by deleted blocks in underlying storage
+    #[inline]
+    pub fn compact(
+        &mut self,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.compact(progress)
+    }
+
+    // check underlying storage health
+    #[inline]
+    pub fn health_check(&self) -> Result<Health> {
+        let mut storage = self.storage.write().unwrap();
+        storage.health_check()
+    }
 }
 
 impl IntoRef for Volume {}
@@ -232,7 +738,10 @@ impl Reader {
     pub fn new(id: &Eid, vol: &VolumeRef) -> Result<Self> {
         let vol = vol.read().unwrap();
         let rdr = storage::Reader::new(id, &vol.storage)?;
-        if vol.info.compress {
+
+        // use the flag stored with entity if it is present, so entities
+        // written with different compression settings are all readable
+        if rdr.is_compressed().unwrap_or(vol.info.compress) {
             Ok(Reader {
                 inner: Box::new(Lz4Decoder::new(rdr).unwrap()),
             })
@@ -304,10 +813,47 @@ pub struct Writer {
 }
 
 impl Writer {
+    #[inline]
     pub fn new(id: &Eid, vol: &VolumeWeakRef) -> Result<Self> {
+        Self::create(id, vol, |_, _| Ok(()))
+    }
+
+    // create a writer for file content, which is encrypted by the key domain
+    // key if it is in a key domain, otherwise by the convergent key if
+    // convergent encryption is enabled, the key domain must be unlocked
+    pub fn for_content(
+        id: &Eid,
+        vol: &VolumeWeakRef,
+        domain: Option<&Eid>,
+    ) -> Result<Self> {
+        Self::create(id, vol, |storage, wtr| {
+            match domain {
+                Some(domain) if !storage.has_domain_key(domain) => {
+                    return Err(Error::DomainLocked);
+                }
+                Some(domain) => wtr.set_domain(domain),
+                None if storage.is_convergent() => wtr.set_convergent(),
+                None => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn create<F>(id: &Eid, vol: &VolumeWeakRef, setup: F) -> Result<Self>
+    where
+        F: FnOnce(&Storage, &mut storage::Writer) -> Result<()>,
+    {
         let vol = vol.upgrade().ok_or(Error::RepoClosed)?;
         let vol = vol.read().unwrap();
-        let wtr = storage::Writer::new(id, &Arc::downgrade(&vol.storage))?;
+
+        // writers created before volume was sealed cannot write any more
+        if vol.info.sealed {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut wtr = storage::Writer::new(id, &Arc::downgrade(&vol.storage))?;
+        wtr.set_compress(vol.info.compress);
+        setup(&vol.storage.read().unwrap(), &mut wtr)?;
         let inner = if vol.info.compress {
             let comp = Lz4EncoderBuilder::new()
                 .block_size(BlockSize::Default)
@@ -368,7 +914,7 @@ mod tests {
     #[cfg(feature = "storage-file")]
     use self::tempdir::TempDir;
     use super::*;
-    use base::crypto::{Crypto, RandomSeed, RANDOM_SEED_SIZE};
+    use base::crypto::{Crypto, RandomSeed, RANDOM_SEED_SIZE, SALT_SIZE};
     use base::init_env;
     use base::utils::speed_str;
 
@@ -376,7 +922,8 @@ mod tests {
         init_env();
         let uri = format!("mem://{}", loc);
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init("pwd", &Config::default(), &Vec::new()).unwrap();
+        vol.init(Credential::Password("pwd"), &Config::default(), &Vec::new())
+            .unwrap();
         vol.into_ref()
     }
 
@@ -390,7 +937,8 @@ mod tests {
         }
         let uri = format!("file://{}", dir.display());
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init(pwd, &Config::default(), payload).unwrap();
+        vol.init(Credential::Password(pwd), &Config::default(), payload)
+            .unwrap();
         (vol.into_ref(), tmpdir)
     }
 
@@ -452,7 +1000,9 @@ mod tests {
         // re-open volume
         drop(vol);
         let mut vol = Volume::new(&uri).unwrap();
-        let buf = vol.open(&pwd, false).unwrap();
+        let buf = vol
+            .open(Credential::Password(&pwd), false, false, &|_| {})
+            .unwrap();
         assert_eq!(&buf[..], &payload[..]);
         {
             let storage = vol.storage.write().unwrap();
@@ -472,6 +1022,25 @@ mod tests {
         read_write_test(&vol);
     }
 
+    #[test]
+    fn super_blk_compat() {
+        let vol = setup_mem_vol("super_blk_compat");
+        let cipher_at = SALT_SIZE + Cost::BYTES_LEN;
+        let head_cipher = |vol: &VolumeRef| {
+            let vol = vol.read().unwrap();
+            let mut storage = vol.storage.write().unwrap();
+            let buf = storage.get_super_block(0).unwrap();
+            Cipher::from_u8(buf[cipher_at])
+        };
+
+        // new volume head has no extension, so older versions can read it
+        assert!(head_cipher(&vol).is_ok());
+
+        // rotating volume key wraps it in head extension
+        vol.write().unwrap().rotate_master_key("pwd").unwrap();
+        assert_eq!(head_cipher(&vol).unwrap_err(), Error::InvalidCipher);
+    }
+
     #[cfg(feature = "storage-file")]
     #[test]
     fn file_volume() {
@@ -489,7 +1058,8 @@ mod tests {
         let payload = [1, 2, 3];
         let uri = "zbox://accessKey456@repo456?cache_type=mem&cache_size=1mb";
         let mut vol = Volume::new(&uri).unwrap();
-        vol.init(&pwd, &Config::default(), &payload).unwrap();
+        vol.init(Credential::Password(pwd), &Config::default(), &payload)
+            .unwrap();
         let vol = vol.into_ref();
 
         reopen_test(&pwd, &payload, vol);
@@ -4,6 +4,7 @@ use std::fmt::{self, Debug};
 use std::ptr;
 
 // libsodium ffi
+#[cfg(not(feature = "crypto-rust"))]
 extern "C" {
     fn randombytes_buf(buf: *mut u8, size: usize);
     fn randombytes_uniform(upper_bound: u32) -> u32;
@@ -23,6 +24,74 @@ extern "C" {
     ) -> i32;
 }
 
+// pure Rust equivalents of the libsodium functions above
+#[cfg(feature = "crypto-rust")]
+mod ffi {
+    extern crate blake2;
+    extern crate chacha20;
+    extern crate getrandom;
+
+    use std::slice;
+
+    use self::blake2::digest::consts::U32;
+    use self::blake2::digest::Digest;
+    use self::blake2::Blake2b;
+    use self::chacha20::cipher::{KeyIvInit, StreamCipher};
+    use self::chacha20::ChaCha20;
+
+    pub unsafe fn randombytes_buf(buf: *mut u8, size: usize) {
+        getrandom::getrandom(slice::from_raw_parts_mut(buf, size)).unwrap();
+    }
+
+    pub unsafe fn randombytes_uniform(upper_bound: u32) -> u32 {
+        if upper_bound < 2 {
+            return 0;
+        }
+        let min = upper_bound.wrapping_neg() % upper_bound;
+        loop {
+            let mut r = [0u8; 4];
+            randombytes_buf(r.as_mut_ptr(), r.len());
+            let r = u32::from_le_bytes(r);
+            if r >= min {
+                return r % upper_bound;
+            }
+        }
+    }
+
+    pub unsafe fn randombytes_buf_deterministic(
+        buf: *mut u8,
+        size: usize,
+        seed: *const u8,
+    ) {
+        let buf = slice::from_raw_parts_mut(buf, size);
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        let mut stream = ChaCha20::new(
+            slice::from_raw_parts(seed, 32).into(),
+            b"LibsodiumDRG".into(),
+        );
+        stream.apply_keystream(buf);
+    }
+
+    pub unsafe fn crypto_generichash(
+        out: *mut u8,
+        outlen: usize,
+        inbuf: *const u8,
+        inlen: u64,
+        _key: *const u8,
+        _keylen: usize,
+    ) -> i32 {
+        let inbuf = slice::from_raw_parts(inbuf, inlen as usize);
+        slice::from_raw_parts_mut(out, outlen)
+            .copy_from_slice(&Blake2b::<U32>::digest(inbuf));
+        0
+    }
+}
+
+#[cfg(feature = "crypto-rust")]
+use self::ffi::*;
+
 pub fn random_buf(buf: &mut [u8]) {
     unsafe {
         randombytes_buf(buf.as_mut_ptr(), buf.len());
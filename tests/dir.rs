@@ -5,10 +5,12 @@ extern crate zbox;
 
 mod common;
 
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::{thread, time};
 
-use zbox::Error;
+use zbox::{DirOptions, Error, OpenOptions, RetentionPolicy, WatchEvent};
 
 #[test]
 fn dir_create_st() {
@@ -164,6 +166,13 @@ fn dir_rename() {
     // rename dir to its parent
     repo.create_dir_all("/5/1").unwrap();
     assert_eq!(repo.rename("/5/1", "/5").unwrap_err(), Error::NotEmpty);
+
+    // rename dir to symlink
+    repo.create_dir("/6").unwrap();
+    repo.symlink("/5", "/link").unwrap();
+    assert_eq!(repo.rename("/6", "/link").unwrap_err(), Error::NotDir);
+    assert!(repo.path_exists("/6").unwrap());
+    assert!(repo.metadata("/link").unwrap().is_symlink());
 }
 
 #[test]
@@ -217,4 +226,334 @@ fn dir_copy() {
     // #3: copy from child to parent
     repo.copy_dir_all("/ccc/ccc1", "/ccc").unwrap();
     assert!(repo.path_exists("/ccc/ccc11").unwrap());
+
+    // #4: copy is atomic, nothing is copied if it failed in the middle
+    {
+        repo.create_dir("/ddd").unwrap();
+        let mut file = repo.create_file("/ddd/a").unwrap();
+        file.write_once(&[1, 2, 3]).unwrap();
+        let _ = repo.create_file("/ddd/z").unwrap();
+        repo.create_dir_all("/eee/z").unwrap();
+        assert_eq!(
+            repo.copy_dir_all("/ddd", "/eee").unwrap_err(),
+            Error::NotFile
+        );
+        assert!(!repo.path_exists("/eee/a").unwrap());
+        assert!(repo.is_dir("/eee/z").unwrap());
+
+        repo.remove_dir("/eee/z").unwrap();
+        repo.copy_dir_all("/ddd", "/eee").unwrap();
+        let mut file = repo.open_file("/eee/a").unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        assert!(repo.is_file("/eee/z").unwrap());
+
+        // copy again to overwrite existing files and special files
+        repo.symlink("a", "/ddd/link").unwrap();
+        repo.copy_dir_all("/ddd", "/eee").unwrap();
+        repo.copy_dir_all("/ddd", "/eee").unwrap();
+        assert_eq!(repo.read_link("/eee/link").unwrap().to_str(), Some("a"));
+    }
+}
+
+#[test]
+fn dir_options() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/docs/sub").unwrap();
+    repo.create_dir("/logs").unwrap();
+    let _ = repo.create_file("/file").unwrap();
+
+    // #1: set options on dir
+    assert_eq!(repo.dir_options("/docs").unwrap(), DirOptions::new());
+    repo.set_dir_options("/docs", DirOptions::new().version_limit(10))
+        .unwrap();
+    repo.set_dir_options("/logs", DirOptions::new().version_limit(1))
+        .unwrap();
+    assert_eq!(
+        repo.dir_options("/docs").unwrap().get_version_limit(),
+        Some(10)
+    );
+    assert_eq!(
+        repo.set_dir_options("/docs", DirOptions::new().version_limit(0))
+            .unwrap_err(),
+        Error::InvalidArgument
+    );
+    assert_eq!(
+        repo.set_dir_options("/file", &DirOptions::new())
+            .unwrap_err(),
+        Error::NotDir
+    );
+    assert_eq!(repo.dir_options("/file").unwrap_err(), Error::NotDir);
+
+    // #2: files created beneath dir inherit its options
+    let write_versions = |repo: &mut zbox::Repo, path: &str| {
+        let mut f = repo.create_file(path).unwrap();
+        for i in 0..5u8 {
+            f.write_once(&[i; 8]).unwrap();
+        }
+    };
+    write_versions(repo, "/docs/a");
+    write_versions(repo, "/docs/sub/b");
+    write_versions(repo, "/logs/c");
+    assert_eq!(repo.history("/docs/a").unwrap().len(), 6);
+    assert_eq!(repo.history("/docs/sub/b").unwrap().len(), 6);
+    assert_eq!(repo.history("/logs/c").unwrap().len(), 1);
+
+    // #3: nearer dir and open options take precedence
+    repo.set_dir_options("/docs/sub", DirOptions::new().version_limit(2))
+        .unwrap();
+    write_versions(repo, "/docs/sub/d");
+    assert_eq!(repo.history("/docs/sub/d").unwrap().len(), 2);
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .version_limit(3)
+            .open(repo, "/docs/sub/e")
+            .unwrap();
+        for i in 0..5u8 {
+            f.write_once(&[i; 8]).unwrap();
+        }
+    }
+    assert_eq!(repo.history("/docs/sub/e").unwrap().len(), 3);
+
+    // #4: retention policy is inherited as well
+    let mut policy = RetentionPolicy::new();
+    policy.keep_for(time::Duration::from_millis(300));
+    repo.create_dir("/kept").unwrap();
+    repo.set_dir_options(
+        "/kept",
+        DirOptions::new().version_limit(10).retention(policy),
+    )
+    .unwrap();
+    assert_eq!(
+        repo.dir_options("/kept").unwrap().get_retention(),
+        Some(policy)
+    );
+    {
+        let mut f = repo.create_file("/kept/f").unwrap();
+        f.write_once(b"foo").unwrap();
+        f.write_once(b"bar").unwrap();
+        assert_eq!(f.history().unwrap().len(), 3);
+        thread::sleep(time::Duration::from_millis(400));
+        f.write_once(b"baz").unwrap();
+        assert_eq!(f.history().unwrap().len(), 1);
+    }
+    let mut bad = RetentionPolicy::new();
+    bad.thin_after(time::Duration::from_secs(1), time::Duration::default());
+    assert_eq!(
+        repo.set_dir_options("/kept", DirOptions::new().retention(bad))
+            .unwrap_err(),
+        Error::InvalidArgument
+    );
+}
+
+#[test]
+fn dir_walk() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/walk/a/b").unwrap();
+    repo.create_dir("/walk/c").unwrap();
+    repo.create_file("/walk/a/f1").unwrap();
+    repo.create_file("/walk/a/b/f2").unwrap();
+    repo.symlink("../a", "/walk/c/link").unwrap();
+    repo.symlink("/walk", "/walk/a/b/loop").unwrap();
+    repo.symlink("/non-existing", "/walk/c/dangling").unwrap();
+
+    let walk = |repo: &zbox::Repo, depth: Option<usize>, follow: bool| {
+        let mut walker = repo.walk_dir("/walk").follow_links(follow);
+        if let Some(depth) = depth {
+            walker = walker.max_depth(depth);
+        }
+        walker
+            .map(|ent| ent.unwrap().path().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // #1, walk whole tree without following links, in depth-first order
+    let paths = walk(repo, None, false);
+    assert_eq!(paths.len(), 8);
+    for path in &paths[1..] {
+        let parent = std::path::Path::new(path).parent().unwrap();
+        if parent.to_str().unwrap() != "/walk" {
+            let pos = paths.iter().position(|p| p == path).unwrap();
+            let par_pos = paths
+                .iter()
+                .position(|p| p == parent.to_str().unwrap())
+                .unwrap();
+            assert!(par_pos < pos);
+        }
+    }
+    assert!(repo
+        .walk_dir("/walk")
+        .any(|ent| ent.unwrap().metadata().is_symlink()));
+
+    // #2, depth limit
+    assert!(walk(repo, Some(0), false).is_empty());
+    assert_eq!(walk(repo, Some(1), false).len(), 2);
+    assert_eq!(walk(repo, Some(2), false).len(), 6);
+
+    // #3, follow links, loop and dangling link are not walked into
+    let paths = walk(repo, None, true);
+    assert_eq!(paths.len(), 12);
+    assert!(paths.contains(&"/walk/c/link/b/f2".to_string()));
+    assert!(paths.contains(&"/walk/a/b/loop".to_string()));
+    assert!(!paths.contains(&"/walk/a/b/loop/a".to_string()));
+    let ent = repo
+        .walk_dir("/walk/c")
+        .follow_links(true)
+        .map(|ent| ent.unwrap())
+        .find(|ent| ent.file_name() == "link")
+        .unwrap();
+    assert!(ent.metadata().is_dir());
+
+    // #4, errors
+    let mut walker = repo.walk_dir("/non-existing");
+    assert_eq!(walker.next().unwrap().unwrap_err(), Error::NotFound);
+    assert!(walker.next().is_none());
+    let mut walker = repo.walk_dir("/walk/a/f1");
+    assert_eq!(walker.next().unwrap().unwrap_err(), Error::NotDir);
+}
+
+#[test]
+fn dir_watch() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+    let p = |path: &str| PathBuf::from(path);
+
+    repo.create_dir("/dir").unwrap();
+    let rx = repo.watch("/dir", false).unwrap();
+    let rx_all = repo.watch("/", true).unwrap();
+    assert_eq!(
+        repo.watch("/non-existing", false).unwrap_err(),
+        Error::NotFound
+    );
+
+    // #1, directory operations
+    repo.create_dir_all("/dir/a/b").unwrap();
+    repo.rename("/dir/a/b", "/b").unwrap();
+    repo.remove_dir("/b").unwrap();
+    assert_eq!(
+        rx.try_iter().collect::<Vec<_>>(),
+        vec![WatchEvent::Create(p("/dir/a"))]
+    );
+    assert_eq!(
+        rx_all.try_iter().collect::<Vec<_>>(),
+        vec![
+            WatchEvent::Create(p("/dir/a")),
+            WatchEvent::Create(p("/dir/a/b")),
+            WatchEvent::Rename(p("/dir/a/b"), p("/b")),
+            WatchEvent::Remove(p("/b")),
+        ]
+    );
+
+    // #2, file changes made through file handle
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .open(repo, "/dir/file")
+            .unwrap();
+        f.write_once(b"foo").unwrap();
+        repo.rename("/dir/file", "/dir/a/file").unwrap();
+        f.set_len(1).unwrap();
+    }
+    repo.remove_file("/dir/a/file").unwrap();
+    assert_eq!(
+        rx_all.try_iter().collect::<Vec<_>>(),
+        vec![
+            WatchEvent::Create(p("/dir/file")),
+            WatchEvent::Modify(p("/dir/file")),
+            WatchEvent::Rename(p("/dir/file"), p("/dir/a/file")),
+            WatchEvent::Modify(p("/dir/a/file")),
+            WatchEvent::Remove(p("/dir/a/file")),
+        ]
+    );
+    assert_eq!(rx.try_iter().count(), 3);
+
+    // #3, failed operation doesn't produce event, dropped receiver stops
+    // watching
+    drop(rx);
+    assert!(repo.create_dir("/dir/a").is_err());
+    repo.copy_dir_all("/dir", "/dir2").unwrap();
+    assert_eq!(
+        rx_all.try_iter().collect::<Vec<_>>(),
+        vec![
+            WatchEvent::Create(p("/dir2")),
+            WatchEvent::Create(p("/dir2/a")),
+        ]
+    );
+}
+
+#[test]
+fn dir_quota() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/up/sub").unwrap();
+    {
+        let mut f = repo.create_file("/up/sub/a").unwrap();
+        f.write_once(&[1u8; 100]).unwrap();
+    }
+    assert!(repo.quota("/up").unwrap().is_none());
+    assert_eq!(repo.set_quota("/up/sub/a", 10).unwrap_err(), Error::NotDir);
+
+    // #1, initial usage is counted when quota is set
+    repo.set_quota("/up", 300).unwrap();
+    let quota = repo.quota("/up").unwrap().unwrap();
+    assert_eq!(quota.limit(), 300);
+    assert_eq!(quota.used(), 100);
+
+    // #2, writes within and beyond quota
+    {
+        let mut f = repo.create_file("/up/sub/b").unwrap();
+        f.write_once(&[2u8; 150]).unwrap();
+        assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 250);
+        let mut f = repo.create_file("/up/c").unwrap();
+        assert_eq!(
+            f.write_once(&[3u8; 100]).unwrap_err(),
+            Error::QuotaExceeded
+        );
+        f.set_len(50).unwrap();
+        assert_eq!(f.set_len(51).unwrap_err(), Error::QuotaExceeded);
+    }
+    assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 300);
+    assert_eq!(repo.metadata("/up/c").unwrap().content_len(), 50);
+
+    // #3, copy and rename into quota dir
+    {
+        let mut f = repo.create_file("/d").unwrap();
+        f.write_once(&[4u8; 10]).unwrap();
+    }
+    assert_eq!(repo.copy("/d", "/up/d").unwrap_err(), Error::QuotaExceeded);
+    assert_eq!(
+        repo.rename("/d", "/up/d").unwrap_err(),
+        Error::QuotaExceeded
+    );
+    assert!(repo.path_exists("/d").unwrap());
+    repo.remove_file("/up/c").unwrap();
+    assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 250);
+    repo.rename("/d", "/up/d").unwrap();
+    assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 260);
+
+    // #4, nested quota and moving dir out of quota
+    repo.set_quota("/up/sub", 1000).unwrap();
+    assert_eq!(repo.quota("/up/sub").unwrap().unwrap().used(), 250);
+    repo.rename("/up/sub", "/sub").unwrap();
+    assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 10);
+    assert_eq!(repo.quota("/sub").unwrap().unwrap().used(), 250);
+    repo.copy_dir_all("/sub", "/up/sub2").unwrap();
+    assert_eq!(repo.quota("/up").unwrap().unwrap().used(), 260);
+    assert_eq!(
+        repo.copy_dir_all("/sub", "/up/sub3").unwrap_err(),
+        Error::QuotaExceeded
+    );
+    assert!(!repo.path_exists("/up/sub3").unwrap());
+
+    // #5, remove quota
+    repo.remove_quota("/up").unwrap();
+    assert!(repo.quota("/up").unwrap().is_none());
+    repo.copy_dir_all("/sub", "/up/sub3").unwrap();
 }
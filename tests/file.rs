@@ -9,10 +9,15 @@ mod common;
 
 use rand::{RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 use std::thread;
-use zbox::{Error, File, OpenOptions};
+use std::time::{Duration, UNIX_EPOCH};
+use zbox::{
+    Advice, Error, File, FileType, OpenOptions, Permissions, RepoOpener,
+    RetentionPolicy,
+};
 
 #[test]
 fn file_open_close() {
@@ -513,6 +518,24 @@ fn file_content_dedup() {
         f.write_once(&buf).unwrap();
         f3.write_once(&buf).unwrap();
     }
+
+    // chunk deduplication can be turned on or off for each file
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 64 * 1024];
+    rng.fill_bytes(&mut buf);
+    for &(path, dedup) in [("/file4", true), ("/file5", false)].iter() {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .version_limit(2)
+            .dedup_chunk(dedup)
+            .open(&mut repo, path)
+            .unwrap();
+        f.write_once(&buf).unwrap();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_once(&buf).unwrap();
+        let info = f.dedup_info().unwrap();
+        assert_eq!(info.shared_chunks() > 0, dedup);
+    }
 }
 
 #[test]
@@ -638,6 +661,58 @@ fn file_copy() {
     }
 }
 
+#[test]
+fn file_clone() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 100 * 1024];
+    rng.fill_bytes(&mut buf);
+
+    let perms = Permissions::new(0o100_640, 1000, 100);
+    repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+    repo.set_permissions("/file", perms).unwrap();
+
+    // #1, clone shares all content chunks with source
+    repo.clone_file("/file", "/file2").unwrap();
+    {
+        let mut f = repo.open_file("/file2").unwrap();
+        verify_content(&mut f, &buf);
+        let info = f.dedup_info().unwrap();
+        assert!(info.chunks() > 0);
+        assert_eq!(info.shared_chunks(), info.chunks());
+        assert_eq!(info.exclusive_size(), 0);
+        assert_eq!(repo.metadata("/file2").unwrap().permissions(), Some(perms));
+    }
+
+    // #2, write to clone doesn't affect source
+    {
+        let mut f =
+            OpenOptions::new().write(true).open(repo, "/file2").unwrap();
+        f.write_once(b"foo").unwrap();
+        let mut dst = buf.clone();
+        dst[..3].copy_from_slice(b"foo");
+        verify_content(&mut f, &dst);
+
+        let mut f = repo.open_file("/file").unwrap();
+        verify_content(&mut f, &buf);
+    }
+
+    // #3, target must not exist
+    assert_eq!(
+        repo.clone_file("/file", "/file2").unwrap_err(),
+        Error::AlreadyExists
+    );
+
+    // #4, source must be a file
+    repo.create_dir("/dir").unwrap();
+    assert_eq!(
+        repo.clone_file("/dir", "/dir2").unwrap_err(),
+        Error::NotFile
+    );
+}
+
 #[test]
 fn file_seek() {
     let mut env = common::TestEnv::new();
@@ -773,6 +848,52 @@ fn file_delete() {
     }
 }
 
+#[test]
+fn file_version_remove_no_dedup() {
+    let mut repo = RepoOpener::new()
+        .create_new(true)
+        .dedup_file(false)
+        .open("mem://file_version_remove_no_dedup", "pwd")
+        .unwrap();
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 512 * 1024];
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(1)
+        .open(&mut repo, "/file")
+        .unwrap();
+    rng.fill_bytes(&mut buf);
+    f.write_once(&buf).unwrap();
+    repo.compact(|_, _| {}).unwrap();
+    let used = repo.info().unwrap().used_blocks();
+    let first = buf.clone();
+
+    // old versions are removed when new versions overwrite the whole file,
+    // their segments should be freed as content is not shared without file
+    // dedup
+    for _ in 0..4 {
+        rng.fill_bytes(&mut buf);
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_once(&buf).unwrap();
+    }
+    repo.compact(|_, _| {}).unwrap();
+    assert!(repo.info().unwrap().used_blocks() < used * 2);
+    verify_content(&mut f, &buf);
+
+    // chunks of freed segments must not be deduped by later writes
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(&first).unwrap();
+    verify_content(&mut f, &first);
+    assert!(repo.verify().unwrap().is_ok());
+
+    // removing the file frees its last version as well
+    drop(f);
+    repo.remove_file("/file").unwrap();
+    repo.compact(|_, _| {}).unwrap();
+    assert!(repo.info().unwrap().used_blocks() < used / 2);
+}
+
 #[test]
 fn file_rename() {
     let mut env = common::TestEnv::new();
@@ -844,4 +965,1195 @@ fn file_rename() {
         repo.rename("/file6", "/file6").unwrap();
         assert!(repo.path_exists("/file6").unwrap());
     }
+
+    // #7, rename non-existing file to itself
+    {
+        assert_eq!(
+            repo.rename("/file7", "/file7").unwrap_err(),
+            Error::NotFound
+        );
+    }
+
+    // #8, rename keeps version history
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .version_limit(3)
+            .open(repo, "/file8")
+            .unwrap();
+        f.write_once(&[1, 2, 3]).unwrap();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_once(&[4, 5, 6]).unwrap();
+        drop(f);
+        repo.create_dir_all("/dir8/sub").unwrap();
+        repo.rename("/file8", "/dir8/sub/file8").unwrap();
+        repo.rename("/dir8", "/dir8a").unwrap();
+
+        assert_eq!(repo.history("/dir8a/sub/file8").unwrap().len(), 3);
+        let mut f = OpenOptions::new().open(repo, "/dir8a/sub/file8").unwrap();
+        verify_content(&mut f, &[4, 5, 6]);
+    }
+}
+
+#[test]
+fn file_special() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir("/dir").unwrap();
+
+    // #1, symbolic link
+    repo.symlink("../target", "/dir/link").unwrap();
+    let md = repo.metadata("/dir/link").unwrap();
+    assert!(md.is_symlink());
+    assert_eq!(md.content_len(), "../target".len());
+    assert_eq!(repo.read_link("/dir/link").unwrap(), Path::new("../target"));
+    assert_eq!(
+        repo.symlink("/xxx", "/dir/link").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert_eq!(repo.read_link("/dir").unwrap_err(), Error::InvalidArgument);
+    assert_eq!(repo.open_file("/dir/link").unwrap_err(), Error::NotFile);
+
+    // #2, special nodes
+    repo.create_node("/dir/fifo", FileType::Fifo, 0).unwrap();
+    repo.create_node("/dir/sock", FileType::Socket, 0).unwrap();
+    repo.create_node("/dir/tty", FileType::CharDevice, 0x0501)
+        .unwrap();
+    repo.create_node("/dir/sda", FileType::BlockDevice, 0x0800)
+        .unwrap();
+    assert_eq!(
+        repo.create_node("/dir/file", FileType::File, 0)
+            .unwrap_err(),
+        Error::InvalidArgument
+    );
+    let md = repo.metadata("/dir/tty").unwrap();
+    assert_eq!(md.file_type(), FileType::CharDevice);
+    assert_eq!(md.rdev(), 0x0501);
+    assert_eq!(md.content_len(), 0);
+    assert_eq!(
+        repo.metadata("/dir/fifo").unwrap().file_type(),
+        FileType::Fifo
+    );
+    assert_eq!(repo.read_dir("/dir").unwrap().len(), 5);
+
+    // #3, copy, rename and remove
+    repo.copy_dir_all("/dir", "/dir2").unwrap();
+    assert_eq!(
+        repo.read_link("/dir2/link").unwrap(),
+        Path::new("../target")
+    );
+    assert_eq!(repo.metadata("/dir2/sda").unwrap().rdev(), 0x0800);
+    repo.rename("/dir2/link", "/dir2/link2").unwrap();
+    assert!(repo.metadata("/dir2/link2").unwrap().is_symlink());
+    assert_eq!(
+        repo.rename("/dir2/link2", "/dir").unwrap_err(),
+        Error::IsDir
+    );
+    repo.remove_file("/dir2/fifo").unwrap();
+    assert!(!repo.path_exists("/dir2/fifo").unwrap());
+    repo.remove_dir_all("/dir2").unwrap();
+    assert!(!repo.path_exists("/dir2").unwrap());
+}
+
+#[test]
+fn file_version_seq() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // versions across files are ordered by change sequence number
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(5)
+        .open(repo, "/file")
+        .unwrap();
+    let mut f2 = OpenOptions::new()
+        .create(true)
+        .version_limit(5)
+        .open(repo, "/file2")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    f2.write_once(b"bar").unwrap();
+    f.write_once(b"baz").unwrap();
+
+    let hist = repo.history("/file").unwrap();
+    let hist2 = repo.history("/file2").unwrap();
+    assert_eq!(hist.len(), 3);
+    assert!(hist[0].seq() < hist[1].seq());
+    assert!(hist[1].seq() < hist2[1].seq());
+    assert!(hist2[1].seq() < hist[2].seq());
+    assert!(hist[1].created_at() <= hist[2].created_at());
+
+    // metadata change sequence number
+    let md = repo.metadata("/file").unwrap();
+    assert_eq!(md.seq(), hist[2].seq());
+    let dir_md = repo.metadata("/").unwrap();
+    repo.create_dir("/dir").unwrap();
+    assert!(repo.metadata("/").unwrap().seq() > dir_md.seq());
+}
+
+#[test]
+fn file_dedup_info() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+    let dedup_file = repo.info().unwrap().dedup_file();
+
+    let mut buf = vec![0u8; 64 * 1024];
+    common::crypto::random_buf(&mut buf);
+    let buf2 = vec![42u8; 16 * 1024];
+
+    // single version file
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&buf).unwrap();
+    let info = f.dedup_info().unwrap();
+    assert!(info.chunks() > 0);
+    assert_eq!(info.shared_chunks(), 0);
+    assert_eq!(info.exclusive_size(), buf.len());
+
+    // append to file, new version shares chunks with the previous one
+    {
+        let mut f =
+            OpenOptions::new().append(true).open(repo, "/file").unwrap();
+        f.write_all(&buf2).unwrap();
+        f.finish().unwrap();
+    }
+    let info2 = f.dedup_info().unwrap();
+    assert!(info2.chunks() > info.chunks());
+    assert!(info2.shared_chunks() > 0);
+    assert_eq!(info2.exclusive_size(), buf.len() + buf2.len());
+
+    // another file with same content
+    let mut f2 = OpenOptions::new()
+        .create(true)
+        .version_limit(1)
+        .open(repo, "/file2")
+        .unwrap();
+    f2.write_once(&buf).unwrap();
+    let info3 = f2.dedup_info().unwrap();
+    assert_eq!(info3.chunks(), info.chunks());
+    if dedup_file {
+        assert_eq!(info3.shared_chunks(), info3.chunks());
+        assert_eq!(info3.exclusive_size(), 0);
+        let info2 = f.dedup_info().unwrap();
+        assert_eq!(info2.exclusive_size(), buf2.len());
+    } else {
+        assert_eq!(info3.shared_chunks(), 0);
+        assert_eq!(info3.exclusive_size(), buf.len());
+        let info2 = f.dedup_info().unwrap();
+        assert_eq!(info2.exclusive_size(), buf.len() + buf2.len());
+    }
+}
+
+#[test]
+fn file_permissions() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir("/dir").unwrap();
+    repo.create_file("/dir/file").unwrap();
+    assert!(repo.metadata("/dir/file").unwrap().permissions().is_none());
+
+    // #1, set permissions, file type bits are masked out
+    let perms = Permissions::new(0o100_4755, 1000, 100);
+    repo.set_permissions("/dir/file", perms).unwrap();
+    let md = repo.metadata("/dir/file").unwrap();
+    let perms = md.permissions().unwrap();
+    assert_eq!(perms.mode(), 0o4755);
+    assert_eq!(perms.uid(), 1000);
+    assert_eq!(perms.gid(), 100);
+    repo.set_permissions("/dir", Permissions::new(0o700, 0, 0))
+        .unwrap();
+    assert_eq!(
+        repo.metadata("/dir").unwrap().permissions().unwrap().mode(),
+        0o700
+    );
+    assert_eq!(
+        repo.set_permissions("/non-existing", perms).unwrap_err(),
+        Error::NotFound
+    );
+
+    // #2, permissions are kept through rename
+    repo.rename("/dir/file", "/dir/file2").unwrap();
+    assert_eq!(
+        repo.metadata("/dir/file2").unwrap().permissions(),
+        Some(perms)
+    );
+}
+
+#[test]
+fn file_set_times() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let ctime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mtime = UNIX_EPOCH + Duration::from_secs(2_000_000);
+
+    // #1, set times on file and dir
+    repo.create_dir("/dir").unwrap();
+    repo.create_file("/dir/file").unwrap();
+    repo.set_times("/dir/file", Some(ctime), Some(mtime))
+        .unwrap();
+    let md = repo.metadata("/dir/file").unwrap();
+    assert_eq!(md.created_at(), ctime);
+    assert_eq!(md.modified_at(), mtime);
+    repo.set_mtime("/dir", mtime).unwrap();
+    assert_eq!(repo.metadata("/dir").unwrap().modified_at(), mtime);
+    assert_eq!(
+        repo.set_mtime("/non-existing", mtime).unwrap_err(),
+        Error::NotFound
+    );
+
+    // #2, touch existing file updates mtime only
+    {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .open(repo, "/dir/file")
+            .unwrap();
+        f.write_once(b"foo").unwrap();
+    }
+    repo.set_times("/dir/file", None, Some(mtime)).unwrap();
+    repo.touch("/dir/file").unwrap();
+    let md = repo.metadata("/dir/file").unwrap();
+    assert_eq!(md.created_at(), ctime);
+    assert!(md.modified_at() > mtime);
+    assert_eq!(md.content_len(), 3);
+
+    // #3, touch non-existing file creates it
+    repo.touch("/dir/file2").unwrap();
+    assert!(repo.is_file("/dir/file2").unwrap());
+    assert_eq!(repo.metadata("/dir/file2").unwrap().content_len(), 0);
+}
+
+#[test]
+fn file_positional_io() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // #1, read and write at offset without moving cursor
+    let mut f = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .version_limit(8)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(b"Hello, world!").unwrap();
+    f.seek(SeekFrom::Start(2)).unwrap();
+    let mut buf = [0u8; 5];
+    assert_eq!(f.read_at(&mut buf, 7).unwrap(), 5);
+    assert_eq!(&buf, b"world");
+    assert_eq!(f.write_at(b"zbox!", 7).unwrap(), 5);
+    assert_eq!(f.stream_position().unwrap(), 2);
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "llo, zbox!!");
+    assert_eq!(f.read_at(&mut buf, 13).unwrap(), 0);
+    assert_eq!(f.read_at(&mut buf, 100).unwrap(), 0);
+
+    // #2, write beyond end of file fills zeros
+    f.write_at(b"end", 15).unwrap();
+    let mut buf = [1u8; 18];
+    assert_eq!(f.read_at(&mut buf, 0).unwrap(), 18);
+    assert_eq!(&buf, b"Hello, zbox!!\0\0end");
+
+    // #3, positional write from multiple threads
+    let children: Vec<_> = (0..4u8)
+        .map(|i| {
+            let f = f.try_clone().unwrap();
+            thread::spawn(move || {
+                f.write_at(&[b'0' + i; 2], u64::from(i) * 2).unwrap();
+            })
+        })
+        .collect();
+    for child in children {
+        child.join().unwrap();
+    }
+    let mut buf = [0u8; 10];
+    f.read_at(&mut buf, 0).unwrap();
+    assert_eq!(&buf, b"00112233bo");
+    assert_eq!(f.history().unwrap().len(), 8);
+
+    // #4, not opened for writing or reading
+    let f = OpenOptions::new().read(true).open(repo, "/file").unwrap();
+    assert_eq!(f.write_at(b"foo", 0).unwrap_err(), Error::CannotWrite);
+    let f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.read_at(&mut buf, 0).unwrap_err(), Error::CannotRead);
+}
+
+#[test]
+fn file_temp() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // #1, temporary file is removed when dropped
+    {
+        let mut f = repo.create_temp_file().unwrap();
+        f.write_once(b"foo").unwrap();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "foo");
+        assert!(repo.read_dir("/").unwrap().is_empty());
+        assert_eq!(repo.info().unwrap().file_count(), 0);
+        assert_eq!(repo.info().unwrap().logical_size(), 3);
+    }
+    assert_eq!(repo.info().unwrap().logical_size(), 0);
+    assert!(repo.verify().unwrap().is_ok());
+
+    // #2, persist temporary file replaces existing file
+    repo.create_dir("/dir").unwrap();
+    repo.create_file("/dir/file")
+        .unwrap()
+        .write_once(b"old")
+        .unwrap();
+    {
+        let mut f = repo.create_temp_file().unwrap();
+        f.write_all(b"new ").unwrap();
+        assert_eq!(f.persist("/dir/file").unwrap_err(), Error::NotFinish);
+        f.write_all(b"content").unwrap();
+        f.finish().unwrap();
+        f.persist("/dir/file").unwrap();
+        assert_eq!(
+            f.persist("/dir/file2").unwrap_err(),
+            Error::InvalidArgument
+        );
+        f.write_once(b"!").unwrap();
+    }
+    let mut s = String::new();
+    repo.open_file("/dir/file")
+        .unwrap()
+        .read_to_string(&mut s)
+        .unwrap();
+    assert_eq!(s, "new content!");
+    assert_eq!(repo.read_dir("/dir").unwrap().len(), 1);
+    assert_eq!(repo.info().unwrap().file_count(), 1);
+
+    // #3, persist to invalid path keeps temporary file
+    {
+        let mut f = repo.create_temp_file().unwrap();
+        assert_eq!(f.persist("/non-exist/file").unwrap_err(), Error::NotFound);
+        assert_eq!(repo.read_dir("/").unwrap().len(), 1);
+        f.persist("/file").unwrap();
+    }
+    assert!(repo.is_file("/file").unwrap());
+    assert_eq!(repo.info().unwrap().file_count(), 2);
+
+    // #4, regular file cannot be persisted
+    let mut f = repo.open_file("/file").unwrap();
+    assert_eq!(f.persist("/file2").unwrap_err(), Error::InvalidArgument);
+}
+
+#[test]
+fn file_sync() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut buf = vec![0u8; 300 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    // #1, synced data is not visible until the write is finished
+    let mut f = repo.create_file("/file").unwrap();
+    f.write_once(b"foo").unwrap();
+    f.write_all(&buf[..100 * 1024]).unwrap();
+    f.sync_data().unwrap();
+    f.write_all(&buf[100 * 1024..]).unwrap();
+    f.sync_data().unwrap();
+    assert_eq!(repo.metadata("/file").unwrap().content_len(), 3);
+    f.sync_all().unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 3 + buf.len());
+    assert_eq!(f.finish().unwrap_err(), Error::NotWrite);
+
+    let mut dst = Vec::new();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(&dst[..3], b"foo");
+    assert_eq!(&dst[3..], &buf[..]);
+
+    // #2, sync without writing
+    f.sync_data().unwrap();
+    f.sync_all().unwrap();
+    let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+    f.sync_all().unwrap();
+    assert_eq!(repo.history("/file").unwrap().len(), 1);
+}
+
+#[test]
+fn file_content_hash() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let hash = |repo: &zbox::Repo, path: &str| {
+        let ver = repo.history(path).unwrap().pop().unwrap();
+        ver.content_hash().unwrap().to_vec()
+    };
+
+    let mut buf = vec![0u8; 600 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    // #1, same content has same hash
+    repo.create_file("/foo").unwrap().write_once(&buf).unwrap();
+    repo.create_file("/bar").unwrap().write_once(&buf).unwrap();
+    repo.create_file("/baz")
+        .unwrap()
+        .write_once(&buf[1..])
+        .unwrap();
+    assert_eq!(hash(repo, "/foo").len(), 32);
+    assert_eq!(hash(repo, "/foo"), hash(repo, "/bar"));
+    assert_ne!(hash(repo, "/foo"), hash(repo, "/baz"));
+
+    // #2, hash is updated when content is changed in place
+    let mut f = OpenOptions::new().write(true).open(repo, "/baz").unwrap();
+    f.set_len(0).unwrap();
+    f.write_once(&buf[..1000]).unwrap();
+    repo.copy_file_range("/foo", 1000, "/baz", 1000, 1_000_000)
+        .unwrap();
+    assert_eq!(hash(repo, "/foo"), hash(repo, "/baz"));
+}
+
+#[test]
+fn file_version_tag() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(3)
+        .open(repo, "/file")
+        .unwrap();
+
+    // #1, tag a version and find it by tag
+    f.write_once(b"foo").unwrap();
+    let v1 = f.curr_version().unwrap();
+    f.tag_version(v1, "release").unwrap();
+    f.write_once(b"bar").unwrap();
+    let ver = f.version_by_tag("release").unwrap();
+    assert_eq!(ver.num(), v1);
+    assert_eq!(ver.tag(), Some("release"));
+    let mut buf = String::new();
+    f.version_reader(ver.num())
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "foo");
+
+    // #2, tag is persisted and shown in history
+    let hist = repo.history("/file").unwrap();
+    let tags: Vec<Option<&str>> = hist.iter().map(|v| v.tag()).collect();
+    assert_eq!(tags, vec![None, Some("release"), None]);
+
+    // #3, tag moves to another version, and can be removed
+    let v2 = f.curr_version().unwrap();
+    f.tag_version(v2, "release").unwrap();
+    assert_eq!(f.version_by_tag("release").unwrap().num(), v2);
+    f.tag_version(v1, "old").unwrap();
+    f.tag_version(v1, "older").unwrap();
+    assert_eq!(f.version_by_tag("old").unwrap_err(), Error::NoVersion);
+    f.untag_version(v1).unwrap();
+    assert_eq!(f.version_by_tag("older").unwrap_err(), Error::NoVersion);
+
+    // #4, tag is removed along with its version
+    f.tag_version(v1, "old").unwrap();
+    f.write_once(b"baz").unwrap();
+    f.write_once(b"qux").unwrap();
+    assert_eq!(f.version_by_tag("old").unwrap_err(), Error::NoVersion);
+    assert_eq!(f.version_by_tag("release").unwrap().num(), v2);
+
+    // #5, invalid arguments
+    assert_eq!(f.tag_version(v1, "x").unwrap_err(), Error::NoVersion);
+    assert_eq!(f.tag_version(v2, "").unwrap_err(), Error::InvalidArgument);
+    let long = "x".repeat(256);
+    assert_eq!(
+        f.tag_version(v2, &long).unwrap_err(),
+        Error::InvalidArgument
+    );
+    let mut f = OpenOptions::new().read(true).open(repo, "/file").unwrap();
+    assert_eq!(f.tag_version(v2, "x").unwrap_err(), Error::CannotWrite);
+}
+
+#[test]
+fn file_restore_version() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let read_all = |f: &mut File| {
+        let mut buf = Vec::new();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.read_to_end(&mut buf).unwrap();
+        buf
+    };
+
+    let mut buf = vec![0u8; 600 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(3)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&buf).unwrap();
+    let v1 = f.curr_version().unwrap();
+    f.write_once(b"foo").unwrap();
+    let v2 = f.curr_version().unwrap();
+
+    // #1, restore previous version
+    let buf2 = [&buf[..], b"foo"].concat();
+    assert_eq!(read_all(&mut f), buf2);
+    f.restore_version(v1).unwrap();
+    assert_eq!(f.curr_version().unwrap(), v2 + 1);
+    assert_eq!(read_all(&mut f), &buf[..]);
+    assert_eq!(f.history().unwrap().len(), 3);
+    assert_eq!(f.metadata().unwrap().content_len(), buf.len());
+
+    // #2, restore current version does nothing
+    f.restore_version(v2 + 1).unwrap();
+    assert_eq!(f.curr_version().unwrap(), v2 + 1);
+
+    // #3, restored content survives removal of its original version
+    f.restore_version(v2).unwrap();
+    f.restore_version(v2 + 1).unwrap();
+    assert!(f.history().unwrap().iter().all(|v| v.num() != v1));
+    assert_eq!(read_all(&mut f), &buf[..]);
+    drop(f);
+    repo.remove_file("/file").unwrap();
+
+    // #4, errors
+    let mut f = repo.create_file("/file2").unwrap();
+    assert_eq!(f.restore_version(42).unwrap_err(), Error::NoVersion);
+    f.write_all(b"foo").unwrap();
+    assert_eq!(f.restore_version(1).unwrap_err(), Error::NotFinish);
+    f.finish().unwrap();
+    let mut f = OpenOptions::new().read(true).open(repo, "/file2").unwrap();
+    assert_eq!(f.restore_version(1).unwrap_err(), Error::CannotWrite);
+}
+
+#[test]
+fn file_diff_versions() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut buf = vec![0u8; 2 * 1024 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(8)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&buf).unwrap();
+    let v1 = f.curr_version().unwrap();
+
+    // #1, same version has no difference
+    assert!(f.diff_versions(v1, v1).unwrap().is_empty());
+
+    // #2, overwrite in the middle
+    f.seek(SeekFrom::Start(1_000_000)).unwrap();
+    f.write_once(b"foo bar").unwrap();
+    let v2 = f.curr_version().unwrap();
+    let diffs = f.diff_versions(v1, v2).unwrap();
+    assert_eq!(diffs.len(), 1);
+    let (off, len) = diffs[0];
+    assert!(off <= 1_000_000 && 1_000_007 <= off + len);
+    assert!(len < 100 * 1024);
+    assert_eq!(f.diff_versions(v2, v1).unwrap(), diffs);
+
+    // #3, append and truncate
+    f.seek(SeekFrom::End(0)).unwrap();
+    f.write_once(b"baz").unwrap();
+    let v3 = f.curr_version().unwrap();
+    assert_eq!(f.diff_versions(v2, v3).unwrap(), vec![(buf.len(), 3)]);
+    f.set_len(1000).unwrap();
+    let v4 = f.curr_version().unwrap();
+    assert_eq!(
+        f.diff_versions(v1, v4).unwrap(),
+        vec![(1000, buf.len() - 1000)]
+    );
+
+    // #4, restored version has no difference with the original
+    f.restore_version(v1).unwrap();
+    let v5 = f.curr_version().unwrap();
+    assert!(f.diff_versions(v1, v5).unwrap().is_empty());
+
+    // #5, errors
+    assert_eq!(f.diff_versions(v1, 42).unwrap_err(), Error::NoVersion);
+    let f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.diff_versions(v1, v2).unwrap_err(), Error::CannotRead);
+}
+
+#[test]
+fn file_retention() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let ver_nums = |f: &File| -> Vec<usize> {
+        f.history().unwrap().iter().map(|v| v.num()).collect()
+    };
+
+    // #1, remove versions by age
+    let mut policy = RetentionPolicy::new();
+    policy.keep_for(Duration::from_millis(300));
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(10)
+        .retention(policy)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    f.write_once(b"bar").unwrap();
+    assert_eq!(ver_nums(&f), vec![1, 2, 3]);
+    thread::sleep(Duration::from_millis(400));
+    f.write_once(b"baz").unwrap();
+    assert_eq!(ver_nums(&f), vec![4]);
+
+    // #2, current version is always kept
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(ver_nums(&f), vec![4]);
+    f.write_once(b"qux").unwrap();
+    assert_eq!(ver_nums(&f), vec![5]);
+
+    // #3, thin out versions
+    let mut policy = RetentionPolicy::new();
+    policy.thin_after(
+        Duration::from_millis(300),
+        Duration::from_secs(1000 * 24 * 60 * 60),
+    );
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(10)
+        .retention(policy)
+        .open(repo, "/file2")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    f.write_once(b"bar").unwrap();
+    thread::sleep(Duration::from_millis(400));
+    f.write_once(b"baz").unwrap();
+    assert_eq!(ver_nums(&f), vec![3, 4]);
+    let mut buf = String::new();
+    f.version_reader(3)
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "foobar");
+
+    // #4, invalid policy
+    policy.thin_after(Duration::from_secs(1), Duration::default());
+    assert_eq!(
+        OpenOptions::new()
+            .create(true)
+            .retention(policy)
+            .open(repo, "/file3")
+            .unwrap_err(),
+        Error::InvalidArgument
+    );
+}
+
+#[test]
+fn file_lock() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let f = repo.create_file("/file").unwrap();
+    let f2 = repo.open_file("/file").unwrap();
+    let f3 = f.try_clone().unwrap();
+
+    // #1, shared locks can be held together
+    f.lock_shared().unwrap();
+    f2.try_lock_shared().unwrap();
+    assert_eq!(f3.try_lock_exclusive().unwrap_err(), Error::Locked);
+
+    // #2, lock conversion
+    assert_eq!(f.try_lock_exclusive().unwrap_err(), Error::Locked);
+    f2.unlock().unwrap();
+    f.try_lock_exclusive().unwrap();
+    assert_eq!(f2.try_lock_shared().unwrap_err(), Error::Locked);
+    f.lock_shared().unwrap();
+    f2.try_lock_shared().unwrap();
+    f.unlock().unwrap();
+    f2.unlock().unwrap();
+
+    // #3, blocking lock waits until lock is released
+    f.lock_exclusive().unwrap();
+    let locked = Arc::new(RwLock::new(false));
+    let child = {
+        let locked = locked.clone();
+        thread::spawn(move || {
+            f3.lock_exclusive().unwrap();
+            *locked.write().unwrap() = true;
+            f3
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    assert!(!*locked.read().unwrap());
+    f.unlock().unwrap();
+    let f3 = child.join().unwrap();
+    assert!(*locked.read().unwrap());
+
+    // #4, lock is released when file is dropped
+    assert_eq!(f2.try_lock_shared().unwrap_err(), Error::Locked);
+    drop(f3);
+    f2.try_lock_exclusive().unwrap();
+
+    // #5, locks on different files are independent
+    let g = repo.create_file("/file2").unwrap();
+    g.try_lock_exclusive().unwrap();
+}
+
+#[test]
+fn file_lock_range() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let f = repo.create_file("/file").unwrap();
+    let f2 = repo.open_file("/file").unwrap();
+
+    // #1, non-overlapped ranges and shared ranges
+    f.try_lock_range(0, 100, true).unwrap();
+    f2.try_lock_range(100, 100, true).unwrap();
+    f.try_lock_range(200, 100, false).unwrap();
+    f2.try_lock_range(250, 100, false).unwrap();
+    assert_eq!(f2.try_lock_range(99, 2, false).unwrap_err(), Error::Locked);
+    assert_eq!(f.try_lock_range(300, 1, true).unwrap_err(), Error::Locked);
+
+    // #2, zero length means to the end of file
+    f2.try_lock_range(1000, 0, true).unwrap();
+    assert_eq!(
+        f.try_lock_range(5000, 10, false).unwrap_err(),
+        Error::Locked
+    );
+    f2.unlock_range(0, 0).unwrap();
+    f.try_lock_range(1000, 0, true).unwrap();
+    assert_eq!(
+        f2.try_lock_range(u64::MAX - 1, 1, false).unwrap_err(),
+        Error::Locked
+    );
+
+    // #3, split and convert a range
+    f.unlock_range(0, 0).unwrap();
+    f.try_lock_range(0, 300, true).unwrap();
+    f.unlock_range(100, 100).unwrap();
+    f2.try_lock_range(100, 100, true).unwrap();
+    f2.unlock_range(0, 0).unwrap();
+    assert_eq!(f2.try_lock_range(0, 1, false).unwrap_err(), Error::Locked);
+    f.try_lock_range(0, 50, false).unwrap();
+    f2.try_lock_range(0, 50, false).unwrap();
+    assert_eq!(f2.try_lock_range(50, 1, false).unwrap_err(), Error::Locked);
+    assert_eq!(f2.try_lock_range(250, 1, false).unwrap_err(), Error::Locked);
+
+    // #4, range locks are independent of whole-file locks
+    f2.try_lock_exclusive().unwrap();
+    f2.unlock().unwrap();
+
+    // #5, blocking lock waits and locks are released when file is dropped
+    let f3 = f2.try_clone().unwrap();
+    let child = thread::spawn(move || {
+        f3.lock_range(200, 100, true).unwrap();
+        f3
+    });
+    thread::sleep(Duration::from_millis(100));
+    drop(f);
+    let f3 = child.join().unwrap();
+    assert_eq!(f2.try_lock_range(280, 1, false).unwrap_err(), Error::Locked);
+    drop(f3);
+    f2.try_lock_range(0, 0, true).unwrap();
+}
+
+#[test]
+fn file_auto_commit() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let read_all = |repo: &mut zbox::Repo, path: &str| {
+        let mut buf = Vec::new();
+        let mut f = repo.open_file(path).unwrap();
+        f.read_to_end(&mut buf).unwrap();
+        buf
+    };
+
+    // #1, pending write is finished when file is dropped
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .auto_commit(true)
+            .open(repo, "/file")
+            .unwrap();
+        f.write_all(b"foo").unwrap();
+        f.write_all(b"bar").unwrap();
+    }
+    assert_eq!(read_all(repo, "/file"), b"foobar");
+    assert_eq!(repo.history("/file").unwrap().len(), 1);
+
+    // #2, finished write is not committed again
+    {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .auto_commit(true)
+            .open(repo, "/file")
+            .unwrap();
+        f.write_all(b"baz").unwrap();
+        f.finish().unwrap();
+    }
+    assert_eq!(read_all(repo, "/file"), b"bazbar");
+
+    // #3, pending write is aborted when thread is panicking
+    let result = {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .auto_commit(true)
+            .open(repo, "/file")
+            .unwrap();
+        thread::spawn(move || {
+            f.write_all(b"qux").unwrap();
+            panic!("abort write");
+        })
+        .join()
+    };
+    assert!(result.is_err());
+    assert_eq!(read_all(repo, "/file"), b"bazbar");
+
+    // #4, pending write is discarded without auto commit
+    {
+        let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+        f.write_all(b"qux").unwrap();
+    }
+    assert_eq!(read_all(repo, "/file"), b"bazbar");
+    repo.create_file("/file2")
+        .unwrap()
+        .write_once(b"foo")
+        .unwrap();
+    assert_eq!(read_all(repo, "/file2"), b"foo");
+}
+
+#[test]
+fn file_multi_part_writer() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 10_000];
+    rng.fill_bytes(&mut buf);
+
+    // #1, write in parts and publish to a new file
+    {
+        let mut wtr = repo.multi_part_writer("/file").unwrap();
+        wtr.set_part_size(3000);
+        wtr.write_all(&buf).unwrap();
+        assert_eq!(wtr.committed_parts(), 3);
+        assert_eq!(wtr.len(), buf.len());
+        assert!(!repo.path_exists("/file").unwrap());
+        wtr.publish().unwrap();
+    }
+    let mut dst = Vec::new();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, buf);
+    assert_eq!(repo.history("/file").unwrap().len(), 1);
+    assert_eq!(repo.read_dir("/").unwrap().len(), 1);
+    assert_eq!(repo.info().unwrap().file_count(), 1);
+
+    // #2, publish to an existing file adds a new version
+    OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/file2")
+        .unwrap()
+        .write_once(&buf)
+        .unwrap();
+    {
+        let mut wtr = repo.multi_part_writer("/file2").unwrap();
+        wtr.write_all(b"foo").unwrap();
+        wtr.commit_part().unwrap();
+        wtr.write_all(b"bar").unwrap();
+        assert_eq!(wtr.committed_parts(), 1);
+        wtr.publish().unwrap();
+    }
+    let mut dst = Vec::new();
+    let mut f = repo.open_file("/file2").unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, b"foobar");
+    assert_eq!(repo.history("/file2").unwrap().len(), 2);
+
+    // #3, written data is discarded if not published
+    {
+        let mut wtr = repo.multi_part_writer("/file3").unwrap();
+        wtr.set_part_size(100);
+        wtr.write_all(&buf[..1000]).unwrap();
+    }
+    assert!(!repo.path_exists("/file3").unwrap());
+    assert_eq!(repo.info().unwrap().file_count(), 2);
+    assert!(repo.verify().unwrap().is_ok());
+
+    // #4, target must be a file
+    repo.create_dir("/dir").unwrap();
+    assert_eq!(repo.multi_part_writer("/dir").unwrap_err(), Error::NotFile);
+}
+
+#[test]
+fn file_version_reader_seek() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 200 * 1024];
+    rng.fill_bytes(&mut buf);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&buf).unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(&[0u8; 1000]).unwrap();
+    let ver_num = f.curr_version().unwrap() - 1;
+    let mut rdr = f.version_reader(ver_num).unwrap();
+    let mut dst = vec![0u8; 1000];
+
+    // #1, seek from start
+    let pos = rdr.seek(SeekFrom::Start(100_000)).unwrap();
+    assert_eq!(pos, 100_000);
+    rdr.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[100_000..101_000]);
+
+    // #2, seek backward from current position
+    let pos = rdr.seek(SeekFrom::Current(-2000)).unwrap();
+    assert_eq!(pos, 99_000);
+    rdr.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[99_000..100_000]);
+
+    // #3, seek from end
+    let pos = rdr.seek(SeekFrom::End(-1000)).unwrap();
+    assert_eq!(pos as usize, buf.len() - 1000);
+    rdr.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[buf.len() - 1000..]);
+    assert_eq!(rdr.read(&mut dst).unwrap(), 0);
+
+    // #4, seek to start is not affected by the current version
+    rdr.seek(SeekFrom::Start(0)).unwrap();
+    rdr.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[..1000]);
+}
+
+#[test]
+fn file_advise() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 3 * 1024 * 1024];
+    rng.fill_bytes(&mut buf);
+    repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+
+    // #1, sequential read
+    {
+        let mut f = repo.open_file("/file").unwrap();
+        f.advise(Advice::Sequential).unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf);
+
+        // read again after data is dropped from cache
+        f.seek(SeekFrom::Start(0)).unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf);
+    }
+
+    // #2, random read
+    {
+        let mut f = repo.open_file("/file").unwrap();
+        f.advise(Advice::Random).unwrap();
+        let mut dst = vec![0u8; 1000];
+        for &pos in [2_000_000usize, 10, 1_500_000, 3_000_000].iter() {
+            f.seek(SeekFrom::Start(pos as u64)).unwrap();
+            f.read_exact(&mut dst).unwrap();
+            assert_eq!(&dst[..], &buf[pos..pos + 1000]);
+        }
+    }
+
+    // #3, load and remove data in cache
+    {
+        let mut f = repo.open_file("/file").unwrap();
+        f.advise(Advice::WillNeed).unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf);
+        f.advise(Advice::DontNeed).unwrap();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf);
+    }
+
+    // #4, advice needs file readable
+    let mut f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.advise(Advice::WillNeed).unwrap_err(), Error::CannotRead);
+}
+
+#[test]
+fn file_vectored_io() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 100 * 1024];
+    rng.fill_bytes(&mut buf);
+    let (head, body) = buf.split_at(16);
+    let (body, tail) = body.split_at(80 * 1024);
+
+    // #1, vectored write
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    let bufs = [
+        IoSlice::new(head),
+        IoSlice::new(&[]),
+        IoSlice::new(body),
+        IoSlice::new(tail),
+    ];
+    assert_eq!(f.write_vectored(&bufs).unwrap(), buf.len());
+    f.finish().unwrap();
+    verify_content(&mut f, &buf);
+
+    // #2, vectored read
+    let mut dst_head = vec![0u8; 16];
+    let mut dst_body = vec![0u8; 80 * 1024];
+    let mut dst_tail = vec![0u8; 30 * 1024];
+    f.seek(SeekFrom::Start(0)).unwrap();
+    let read = {
+        let mut bufs = [
+            IoSliceMut::new(&mut dst_head),
+            IoSliceMut::new(&mut dst_body),
+            IoSliceMut::new(&mut dst_tail),
+        ];
+        f.read_vectored(&mut bufs).unwrap()
+    };
+    assert_eq!(read, buf.len());
+    assert_eq!(&dst_head[..], head);
+    assert_eq!(&dst_body[..], body);
+    assert_eq!(&dst_tail[..tail.len()], tail);
+
+    // #3, vectored read at end of file
+    let mut bufs = [IoSliceMut::new(&mut dst_head)];
+    assert_eq!(f.read_vectored(&mut bufs).unwrap(), 0);
+}
+
+#[test]
+fn file_read_block_ref() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    let mut buf = vec![0u8; 200 * 1024];
+    rng.fill_bytes(&mut buf);
+    repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+
+    // overwrite a part to split content into multiple blocks
+    {
+        let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+        f.seek(SeekFrom::Start(100_000)).unwrap();
+        f.write_once(&[42u8; 1000]).unwrap();
+        buf[100_000..101_000].copy_from_slice(&[42u8; 1000]);
+    }
+
+    // #1, iterate through all blocks
+    let f = repo.open_file("/file").unwrap();
+    let mut offset = 0;
+    let mut blks = 0;
+    loop {
+        let blk = f.read_block_ref(offset as u64).unwrap();
+        if blk.is_empty() {
+            break;
+        }
+        assert_eq!(&blk[..], &buf[offset..offset + blk.len()]);
+        offset += blk.len();
+        blks += 1;
+    }
+    assert_eq!(offset, buf.len());
+    assert!(blks > 1);
+
+    // #2, block starts at the offset
+    let blk = f.read_block_ref(12_345).unwrap();
+    assert!(!blk.is_empty());
+    assert_eq!(blk.as_ref(), &buf[12_345..12_345 + blk.len()]);
+
+    // #3, read beyond end of file
+    assert!(f.read_block_ref(buf.len() as u64 + 1).unwrap().is_empty());
+
+    // #4, file must be readable
+    let f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.read_block_ref(0).unwrap_err(), Error::CannotRead);
+}
+
+#[test]
+fn file_allocate() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir("/dir").unwrap();
+    repo.set_quota("/dir", 100).unwrap();
+
+    // #1, allocate within quota, content is not changed
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/dir/file")
+        .unwrap();
+    f.allocate(100).unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+    assert_eq!(f.allocate(101).unwrap_err(), Error::QuotaExceeded);
+
+    // #2, retained versions count in quota
+    f.write_once(&[1u8; 40]).unwrap();
+    f.allocate(40).unwrap();
+    f.allocate(60).unwrap();
+    assert_eq!(f.allocate(61).unwrap_err(), Error::QuotaExceeded);
+
+    // #3, storage capacity, storage may not report it
+    let mut f2 = repo.create_file("/file2").unwrap();
+    let result = f2.allocate(1 << 60);
+    assert!(result.is_ok() || result.unwrap_err() == Error::NoSpace);
+
+    // #4, not finished and read only
+    f2.write_all(b"foo").unwrap();
+    assert_eq!(f2.allocate(10).unwrap_err(), Error::NotFinish);
+    f2.finish().unwrap();
+    let f3 = repo.open_file("/file2").unwrap();
+    assert_eq!(f3.allocate(10).unwrap_err(), Error::CannotWrite);
+    drop(f2);
+
+    // #5, reserved blocks are counted as used space until released
+    let f4 = repo.create_file("/file4").unwrap();
+    let used = repo.stat_fs().unwrap().used_space();
+    f4.allocate(100_000).unwrap();
+    assert!(repo.stat_fs().unwrap().used_space() >= used + 100_000);
+    drop(f4);
+    assert_eq!(repo.stat_fs().unwrap().used_space(), used);
+
+    // #6, reservation is used up by writes
+    let mut f5 = repo.create_file("/file5").unwrap();
+    f5.allocate(100_000).unwrap();
+    f5.write_once(&[2u8; 100_000]).unwrap();
+    f5.allocate(100_000).unwrap();
+    assert_eq!(f5.metadata().unwrap().content_len(), 100_000);
 }
@@ -2,18 +2,31 @@
     feature = "storage-mem",
     feature = "storage-file",
     feature = "storage-sqlite",
-    feature = "storage-redis"
+    feature = "storage-redis",
+    feature = "storage-sled",
+    feature = "storage-container"
 ))]
 
+extern crate rand;
+extern crate rand_xorshift;
 extern crate tempdir;
 
 extern crate zbox;
 
-use std::io::{Read, Seek, SeekFrom};
+use rand::{RngCore, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tempdir::TempDir;
 #[allow(unused_imports)]
 use zbox::{
-    init_env, Cipher, Error, MemLimit, OpenOptions, OpsLimit, Repo, RepoOpener,
+    init_env, Cipher, DirOptions, DropPublicKey, DropWriter, Error, FileType,
+    ImportOptions, Kdf, KeyProvider, MemLimit, NonceStrategy, OpenOptions,
+    OpenPhase, OpsLimit, PathPolicy, PathViolation, Permissions, RecoveryKey,
+    Repo, RepoOpener, Result,
 };
 
 #[cfg(all(
@@ -276,6 +289,2538 @@ fn repo_oper() {
         assert!(RepoOpener::new().open(&path, &pwd).is_err());
     }
 
+    // case #14: test stat_fs
+    {
+        let path = base.clone() + "/repo14";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let stat = repo.stat_fs().unwrap();
+        let used = stat.used_space();
+        assert!(used > 0);
+        if let Some(total) = stat.total_space() {
+            assert!(total >= used);
+            assert_eq!(stat.free_space().unwrap(), total - used);
+        } else {
+            assert!(stat.free_space().is_none());
+        }
+
+        let mut file = repo.create_file("/file").unwrap();
+        file.write_once(&[42u8; 64 * 1024]).unwrap();
+        let stat = repo.stat_fs().unwrap();
+        assert!(stat.used_space() > used);
+
+        // root quota caps free space
+        repo.set_quota("/", 100 * 1024).unwrap();
+        let stat = repo.stat_fs().unwrap();
+        assert!(stat.free_space().unwrap() <= 100 * 1024 - 64 * 1024);
+    }
+
+    // case #15: test repo label, description and application tag
+    {
+        let path = base.clone() + "/repo15";
+        {
+            let repo = RepoOpener::new()
+                .create_new(true)
+                .label("my repo")
+                .description("repo for testing")
+                .app_tag("zbox-test")
+                .open(&path, &pwd)
+                .unwrap();
+            let info = repo.info().unwrap();
+            assert_eq!(info.label(), "my repo");
+            assert_eq!(info.description(), "repo for testing");
+            assert_eq!(info.app_tag(), "zbox-test");
+        }
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.label(), "my repo");
+        assert_eq!(info.description(), "repo for testing");
+        assert_eq!(info.app_tag(), "zbox-test");
+
+        // wrong password or too long label should fail
+        assert!(repo.set_label("wrong pwd", "new", "", "").is_err());
+        let long_str = "x".repeat(256);
+        assert_eq!(
+            repo.set_label(&pwd, &long_str, "", "").unwrap_err(),
+            Error::InvalidArgument
+        );
+
+        repo.set_label(&pwd, "new label", "", "app").unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.label(), "new label");
+        assert_eq!(info.description(), "");
+        assert_eq!(info.app_tag(), "app");
+        drop(repo);
+
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.label(), "new label");
+        assert_eq!(info.app_tag(), "app");
+    }
+
+    // case #16: test password hint
+    {
+        let path = base.clone() + "/repo16";
+        {
+            RepoOpener::new()
+                .create_new(true)
+                .open(&path, &pwd)
+                .unwrap();
+        }
+        assert!(Repo::password_hint(&path).unwrap().is_none());
+
+        let path = base.clone() + "/repo16b";
+        {
+            RepoOpener::new()
+                .create_new(true)
+                .password_hint("my hint")
+                .open(&path, &pwd)
+                .unwrap();
+        }
+        assert_eq!(Repo::password_hint(&path).unwrap().unwrap(), "my hint");
+        assert_eq!(
+            RepoOpener::new().open(&path, "wrong pwd").unwrap_err(),
+            Error::Decrypt
+        );
+
+        // hint should be kept after password reset
+        {
+            let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+            repo.reset_password(
+                &pwd,
+                "new pwd",
+                OpsLimit::Interactive,
+                MemLimit::Interactive,
+            )
+            .unwrap();
+        }
+        assert_eq!(Repo::password_hint(&path).unwrap().unwrap(), "my hint");
+
+        // update and remove hint
+        {
+            let mut repo = RepoOpener::new().open(&path, "new pwd").unwrap();
+            repo.set_password_hint("new pwd", "new hint").unwrap();
+            assert_eq!(
+                Repo::password_hint(&path).unwrap().unwrap(),
+                "new hint"
+            );
+            repo.set_password_hint("new pwd", "").unwrap();
+        }
+        assert!(Repo::password_hint(&path).unwrap().is_none());
+        RepoOpener::new().open(&path, "new pwd").unwrap();
+    }
+
+    // case #17: test storage health check
+    {
+        let path = base.clone() + "/repo17";
+        let repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let health = repo.check_storage().unwrap();
+        assert!(health.is_writable());
+        assert!(health.write_latency().is_some());
+    }
+
+    // case #18: test changing compression on existing repo
+    {
+        let path = base.clone() + "/repo18";
+        let content = vec![42u8; 64 * 1024];
+        {
+            let mut repo = RepoOpener::new()
+                .create_new(true)
+                .open(&path, &pwd)
+                .unwrap();
+            assert!(!repo.info().unwrap().compress());
+            let mut f = OpenOptions::new()
+                .create(true)
+                .open(&mut repo, "/plain")
+                .unwrap();
+            f.write_once(&content).unwrap();
+
+            assert!(repo.set_compress("wrong pwd", true).is_err());
+            repo.set_compress(&pwd, true).unwrap();
+            assert!(repo.info().unwrap().compress());
+            let mut f = OpenOptions::new()
+                .create(true)
+                .open(&mut repo, "/compressed")
+                .unwrap();
+            f.write_once(&content).unwrap();
+        }
+
+        // both files are readable after re-open
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(repo.info().unwrap().compress());
+        for name in &["/plain", "/compressed"] {
+            let mut f = repo.open_file(name).unwrap();
+            let mut buf = Vec::new();
+            f.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, content);
+        }
+    }
+
+    // case #19: test custom sector size
+    {
+        let path = base.clone() + "/repo19";
+        let content = vec![42u8; 3 * 1024 * 1024];
+        {
+            let mut repo = RepoOpener::new()
+                .create_new(true)
+                .sector_size(1024 * 1024)
+                .open(&path, &pwd)
+                .unwrap();
+            assert_eq!(repo.info().unwrap().sector_size(), 1024 * 1024);
+            let mut f = OpenOptions::new()
+                .create(true)
+                .open(&mut repo, "/file")
+                .unwrap();
+            f.write_once(&content).unwrap();
+        }
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.info().unwrap().sector_size(), 1024 * 1024);
+        let mut f = repo.open_file("/file").unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+    }
+
+    // case #20: test space accounting in repo info
+    {
+        let path = base.clone() + "/repo20";
+        let (used_blocks, physical_size) = {
+            let mut repo = RepoOpener::new()
+                .create_new(true)
+                .version_limit(1)
+                .open(&path, &pwd)
+                .unwrap();
+            let info = repo.info().unwrap();
+            assert_eq!(info.logical_size(), 0);
+            assert!(info.used_blocks() > 0);
+            assert!(info.retained_txs() <= 2);
+            let base_blocks = info.used_blocks();
+
+            repo.create_file("/a")
+                .unwrap()
+                .write_once(&[1u8; 3000])
+                .unwrap();
+            let mut f = repo.create_file("/b").unwrap();
+            f.write_once(&[2u8; 5000]).unwrap();
+            f.set_len(4000).unwrap();
+            drop(f);
+            repo.create_dir("/dir").unwrap();
+            repo.copy("/b", "/dir/c").unwrap();
+            let info = repo.info().unwrap();
+            assert_eq!(info.logical_size(), 11_000);
+            assert!(info.used_blocks() > base_blocks);
+
+            // removed file is not counted, renamed file is counted once
+            let mut f = repo.create_file("/big").unwrap();
+            f.write_once(&vec![3u8; 1024 * 1024]).unwrap();
+            drop(f);
+            let info = repo.info().unwrap();
+            assert_eq!(info.logical_size(), 11_000 + 1024 * 1024);
+            assert!(info.physical_size() > 1024 * 1024);
+            repo.remove_file("/big").unwrap();
+            repo.rename("/dir/c", "/d").unwrap();
+            for i in 0..3 {
+                repo.create_dir(format!("/dir{}", i)).unwrap();
+            }
+            let info = repo.info().unwrap();
+            assert_eq!(info.retained_txs(), 2);
+            assert_eq!(info.logical_size(), 11_000);
+            assert_eq!(
+                info.physical_size(),
+                info.used_blocks() as u64 * 8 * 1024
+            );
+            (info.used_blocks(), info.physical_size())
+        };
+
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.logical_size(), 11_000);
+        assert_eq!(info.used_blocks(), used_blocks);
+        assert_eq!(info.physical_size(), physical_size);
+    }
+
+    // case #21: test repo verification
+    {
+        let path = base.clone() + "/repo21";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .dedup_file(true)
+            .version_limit(3)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_dir_all("/dir/sub").unwrap();
+        let mut f = repo.create_file("/dir/file").unwrap();
+        f.write_once(&[1u8; 5000]).unwrap();
+        f.write_once(&[2u8; 3000]).unwrap();
+        drop(f);
+        repo.copy("/dir/file", "/dir/sub/copy").unwrap();
+        repo.symlink("/dir/file", "/link").unwrap();
+
+        let report = repo.verify().unwrap();
+        assert!(report.is_ok());
+        assert!(report.problems().is_empty());
+        assert_eq!(report.dirs(), 3);
+        assert_eq!(report.files(), 3);
+        assert_eq!(report.versions(), 5);
+        assert_eq!(report.contents(), 3);
+        assert!(report.segments() > 0);
+        assert_eq!(report.orphan_contents(), 0);
+        drop(repo);
+
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #22: test repo compaction
+    {
+        let path = base.clone() + "/repo22";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .sector_size(512 * 1024)
+            .open(&path, &pwd)
+            .unwrap();
+        for i in 0..20u8 {
+            let mut f = repo.create_file(format!("/file{}", i)).unwrap();
+            f.write_once(&vec![i; 100 * 1024]).unwrap();
+        }
+        for i in 0..20u8 {
+            if i % 5 == 0 {
+                repo.remove_file(format!("/file{}", i)).unwrap();
+            }
+        }
+        let info = repo.info().unwrap();
+        assert_eq!(info.retained_txs(), 2);
+
+        let report =
+            repo.compact(|done, total| assert!(done <= total)).unwrap();
+        assert_eq!(report.recycled_txs(), 2);
+        assert!(report.reclaimed_blocks() > 0);
+        let info = repo.info().unwrap();
+        assert_eq!(info.retained_txs(), 0);
+        assert!(repo.verify().unwrap().is_ok());
+
+        // compact again has nothing to reclaim
+        let report = repo.compact(|_, _| {}).unwrap();
+        assert_eq!(report.recycled_txs(), 0);
+        assert_eq!(report.reclaimed_blocks(), 0);
+        drop(repo);
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.info().unwrap().used_blocks(), info.used_blocks());
+        for i in 0..20u8 {
+            let path = format!("/file{}", i);
+            if i % 5 == 0 {
+                assert!(!repo.path_exists(&path).unwrap());
+                continue;
+            }
+            let mut f = repo.open_file(&path).unwrap();
+            let mut buf = Vec::new();
+            f.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, vec![i; 100 * 1024]);
+        }
+        drop(repo);
+
+        let mut repo =
+            RepoOpener::new().read_only(true).open(&path, &pwd).unwrap();
+        assert_eq!(repo.compact(|_, _| {}).unwrap_err(), Error::ReadOnly);
+    }
+
+    // case #23: test exporting directory tree to tar archive
+    {
+        // parse tar archive to (path, type flag, link, data) list
+        fn parse_tar(mut buf: &[u8]) -> Vec<(String, u8, String, Vec<u8>)> {
+            let field = |hdr: &[u8], off: usize, len: usize| {
+                let f = &hdr[off..off + len];
+                let end = f.iter().position(|b| *b == 0).unwrap_or(len);
+                String::from_utf8(f[..end].to_vec()).unwrap()
+            };
+            let mut ents = Vec::new();
+            let mut pax_path = None;
+            while buf[..512].iter().any(|b| *b != 0) {
+                let hdr = &buf[..512];
+                let chksum: u32 = hdr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| {
+                        if (148..156).contains(&i) {
+                            32
+                        } else {
+                            *b as u32
+                        }
+                    })
+                    .sum();
+                let field_sum = field(hdr, 148, 6);
+                assert_eq!(u32::from_str_radix(&field_sum, 8).unwrap(), chksum);
+                let size =
+                    usize::from_str_radix(&field(hdr, 124, 11), 8).unwrap();
+                let data = buf[512..512 + size].to_vec();
+                buf = &buf[512 + size.div_ceil(512) * 512..];
+
+                if hdr[156] == b'x' {
+                    let rec = String::from_utf8(data).unwrap();
+                    let idx = rec.find(" path=").unwrap();
+                    pax_path = Some(rec[idx + 6..rec.len() - 1].to_string());
+                    continue;
+                }
+                let prefix = field(hdr, 345, 155);
+                let mut path = field(hdr, 0, 100);
+                if !prefix.is_empty() {
+                    path = prefix + "/" + &path;
+                }
+                let path = pax_path.take().unwrap_or(path);
+                ents.push((path, hdr[156], field(hdr, 157, 100), data));
+            }
+            assert_eq!(buf.len(), 1024);
+            ents
+        }
+
+        let path = base.clone() + "/repo23";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let long_name = "n".repeat(120);
+        repo.create_dir_all("/exp/sub").unwrap();
+        repo.create_dir(format!("/exp/{}", long_name)).unwrap();
+        repo.create_file("/exp/a.txt")
+            .unwrap()
+            .write_once(b"hello")
+            .unwrap();
+        repo.create_file(format!("/exp/{}/{}", long_name, long_name))
+            .unwrap()
+            .write_once(&[7u8; 2000])
+            .unwrap();
+        repo.set_permissions("/exp/a.txt", Permissions::new(0o600, 1000, 100))
+            .unwrap();
+        repo.symlink("../a.txt", "/exp/sub/link").unwrap();
+        repo.create_node("/exp/sub/fifo", FileType::Fifo, 0)
+            .unwrap();
+        repo.create_node("/exp/sub/sock", FileType::Socket, 0)
+            .unwrap();
+        repo.create_file("/outside").unwrap();
+
+        let mut buf = Vec::new();
+        repo.export_tar("/exp", &mut buf).unwrap();
+        assert_eq!(buf.len() % 512, 0);
+        let mut ents = parse_tar(&buf);
+        ents.sort();
+        let long_path = format!("{}/{}", long_name, long_name);
+        assert_eq!(
+            ents,
+            vec![
+                ("a.txt".to_string(), b'0', String::new(), b"hello".to_vec()),
+                (long_name.clone() + "/", b'5', String::new(), Vec::new()),
+                (long_path, b'0', String::new(), vec![7u8; 2000]),
+                ("sub/".to_string(), b'5', String::new(), Vec::new()),
+                ("sub/fifo".to_string(), b'6', String::new(), Vec::new()),
+                (
+                    "sub/link".to_string(),
+                    b'2',
+                    "../a.txt".to_string(),
+                    Vec::new()
+                ),
+            ]
+        );
+
+        // permissions are preserved
+        let hdr = buf.chunks(512).find(|h| h.starts_with(b"a.txt\0")).unwrap();
+        assert_eq!(&hdr[100..107], b"0000600");
+        assert_eq!(&hdr[108..115], b"0001750");
+        assert_eq!(&hdr[116..123], b"0000144");
+
+        assert_eq!(
+            repo.export_tar("/outside", &mut Vec::new()).unwrap_err(),
+            Error::NotDir
+        );
+    }
+
+    // case #24: test importing host directory tree
+    {
+        let host = dir.join("import24");
+        std::fs::create_dir_all(host.join("sub/empty")).unwrap();
+        std::fs::create_dir_all(host.join(".git")).unwrap();
+        std::fs::write(host.join("a.txt"), b"hello world").unwrap();
+        std::fs::write(host.join("sub/b.bin"), vec![9u8; 200 * 1024]).unwrap();
+        std::fs::write(host.join("sub/zero"), b"").unwrap();
+        std::fs::write(host.join(".git/config"), b"secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("../a.txt", host.join("sub/link")).unwrap();
+
+        let path = base.clone() + "/repo24";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        let report = ImportOptions::new()
+            .filter(|path| !path.ends_with(".git"))
+            .progress(move |done, total| {
+                calls2.lock().unwrap().push((done, total))
+            })
+            .import(&mut repo, &host, "/imported")
+            .unwrap();
+        let links = if cfg!(unix) { 1 } else { 0 };
+        assert_eq!(report.dirs(), 2);
+        assert_eq!(report.files(), 3 + links);
+        assert_eq!(report.bytes(), 11 + 200 * 1024);
+        let total = 5 + links;
+        let calls = calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), total);
+        assert_eq!(calls.last(), Some(&(total, total)));
+
+        let mut buf = Vec::new();
+        repo.open_file("/imported/a.txt")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"hello world");
+        buf.clear();
+        repo.open_file("/imported/sub/b.bin")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, vec![9u8; 200 * 1024]);
+        assert_eq!(
+            repo.metadata("/imported/sub/zero").unwrap().content_len(),
+            0
+        );
+        assert!(repo.is_dir("/imported/sub/empty").unwrap());
+        assert!(!repo.path_exists("/imported/.git").unwrap());
+        #[cfg(unix)]
+        assert_eq!(
+            repo.read_link("/imported/sub/link").unwrap(),
+            std::path::PathBuf::from("../a.txt")
+        );
+        let secs = |t: std::time::SystemTime| {
+            t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+        };
+        let host_meta = std::fs::metadata(host.join("sub")).unwrap();
+        let meta = repo.metadata("/imported/sub").unwrap();
+        assert_eq!(
+            secs(meta.modified_at()),
+            secs(host_meta.modified().unwrap())
+        );
+
+        // import again merges directories and updates files
+        std::fs::write(host.join("a.txt"), b"bye").unwrap();
+        let report = repo.import_dir(&host, "/imported").unwrap();
+        assert_eq!(report.files(), 4 + links);
+        let mut buf = Vec::new();
+        repo.open_file("/imported/a.txt")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"bye");
+        assert!(repo.path_exists("/imported/.git/config").unwrap());
+
+        assert_eq!(
+            repo.import_dir(host.join("a.txt"), "/x").unwrap_err(),
+            Error::NotDir
+        );
+    }
+
+    // case #25: test named snapshots
+    {
+        let path = base.clone() + "/repo25";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .version_limit(5)
+            .open(&path, &pwd)
+            .unwrap();
+        assert!(repo.list_snapshots().is_empty());
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+
+        repo.create_dir_all("/a/b/c").unwrap();
+        write(&mut repo, "/a/b/c/f.txt", b"v1");
+        write(&mut repo, "/g.txt", b"foo");
+        repo.snapshot("s1").unwrap();
+        assert_eq!(repo.snapshot("s1").unwrap_err(), Error::AlreadyExists);
+        assert_eq!(repo.snapshot("").unwrap_err(), Error::InvalidArgument);
+
+        // changes after snapshot don't affect it
+        write(&mut repo, "/a/b/c/f.txt", b"v2");
+        repo.remove_file("/g.txt").unwrap();
+        repo.snapshot("s2").unwrap();
+
+        // snapshot keeps all versions and metadata
+        let perms = Permissions::new(0o600, 1000, 1000);
+        repo.set_permissions("/a/b/c/f.txt", perms).unwrap();
+        repo.set_dir_options("/a", DirOptions::new().version_limit(3))
+            .unwrap();
+        repo.set_quota("/a", 1024 * 1024).unwrap();
+        let view = repo.freeze_view().unwrap();
+        for path in &["/a", "/a/b/c", "/a/b/c/f.txt"] {
+            let md = repo.metadata(path).unwrap();
+            let view_md = view.metadata(path).unwrap();
+            assert_eq!(view_md.created_at(), md.created_at());
+            assert_eq!(view_md.modified_at(), md.modified_at());
+            assert_eq!(view_md.permissions(), md.permissions());
+        }
+        let hist = repo.history("/a/b/c/f.txt").unwrap();
+        let view_hist = view.history("/a/b/c/f.txt").unwrap();
+        assert_eq!(hist.len(), 3);
+        assert_eq!(
+            view_hist.iter().map(|v| v.num()).collect::<Vec<_>>(),
+            hist.iter().map(|v| v.num()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            view.dir_options("/a").unwrap(),
+            repo.dir_options("/a").unwrap()
+        );
+        assert_eq!(view.quota("/a").unwrap(), repo.quota("/a").unwrap());
+        drop(view);
+
+        let names: Vec<String> = repo
+            .list_snapshots()
+            .iter()
+            .map(|snap| snap.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["s1", "s2"]);
+
+        // snapshots are persisted
+        drop(repo);
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.list_snapshots().len(), 2);
+        let report = repo.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.orphan_chunks(), 0);
+
+        // removing snapshot doesn't affect live tree
+        repo.remove_snapshot("s1").unwrap();
+        assert_eq!(repo.remove_snapshot("s1").unwrap_err(), Error::NotFound);
+        assert_eq!(repo.list_snapshots()[0].name(), "s2");
+        let mut buf = Vec::new();
+        repo.open_file("/a/b/c/f.txt")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"v2");
+        repo.remove_snapshot("s2").unwrap();
+        assert!(repo.list_snapshots().is_empty());
+        assert!(repo.path_exists("/a/b/c/f.txt").unwrap());
+        let report = repo.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.orphan_chunks(), 0);
+    }
+
+    // case #26: test rolling back to snapshot
+    {
+        let path = base.clone() + "/repo26";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .version_limit(5)
+            .open(&path, &pwd)
+            .unwrap();
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+        let read = |repo: &mut Repo, path: &str| {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        };
+
+        repo.create_dir_all("/a/b").unwrap();
+        write(&mut repo, "/a/b/f.txt", b"v1");
+        write(&mut repo, "/a/x", b"file");
+        write(&mut repo, "/a/same", b"same");
+        repo.create_dir("/c").unwrap();
+        let perms = Permissions::new(0o644, 0, 0);
+        repo.set_permissions("/a/b/f.txt", perms).unwrap();
+        let x_vers = repo.history("/a/x").unwrap().len();
+        let same_vers = repo.history("/a/same").unwrap().len();
+        repo.snapshot("base").unwrap();
+
+        // change the tree in various ways
+        write(&mut repo, "/a/b/f.txt", b"v2");
+        repo.set_permissions("/a/b/f.txt", Permissions::new(0o600, 0, 0))
+            .unwrap();
+        repo.remove_file("/a/x").unwrap();
+        repo.create_dir_all("/a/x/y").unwrap();
+        write(&mut repo, "/a/x/y/z", b"z");
+        repo.remove_dir("/c").unwrap();
+        write(&mut repo, "/c", b"now a file");
+        repo.create_dir_all("/new/sub").unwrap();
+        write(&mut repo, "/new/sub/g", b"g");
+        repo.snapshot("later").unwrap();
+
+        // snapshot contents survive version pruning and compaction
+        for i in 0..6 {
+            write(&mut repo, "/a/x/y/z", format!("z{}", i).as_bytes());
+        }
+        repo.compact(|_, _| {}).unwrap();
+        assert!(repo.verify().unwrap().is_ok());
+
+        let vers = repo.history("/a/b/f.txt").unwrap().len();
+        repo.rollback_to("base").unwrap();
+        assert_eq!(read(&mut repo, "/a/b/f.txt"), b"v1");
+        assert_eq!(read(&mut repo, "/a/x"), b"file");
+        assert!(repo.is_dir("/c").unwrap());
+        assert!(!repo.path_exists("/new").unwrap());
+
+        // history is kept, unchanged file doesn't get a new version and
+        // removed file is restored with its history
+        assert_eq!(repo.history("/a/b/f.txt").unwrap().len(), vers + 1);
+        assert_eq!(repo.history("/a/same").unwrap().len(), same_vers);
+        assert_eq!(repo.history("/a/x").unwrap().len(), x_vers);
+        assert_eq!(
+            repo.metadata("/a/b/f.txt").unwrap().permissions(),
+            Some(perms)
+        );
+        assert_eq!(repo.list_snapshots().len(), 2);
+        assert!(repo.verify().unwrap().is_ok());
+
+        // roll forward to later snapshot
+        repo.rollback_to("later").unwrap();
+        assert_eq!(read(&mut repo, "/a/b/f.txt"), b"v2");
+        assert_eq!(read(&mut repo, "/a/x/y/z"), b"z");
+        assert_eq!(read(&mut repo, "/c"), b"now a file");
+        assert_eq!(read(&mut repo, "/new/sub/g"), b"g");
+
+        assert_eq!(repo.rollback_to("none").unwrap_err(), Error::NotFound);
+    }
+
+    // case #27: test cipher migration
+    {
+        let path = base.clone() + "/repo27";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .cipher(Cipher::Xchacha)
+            .version_limit(3)
+            .open(&path, &pwd)
+            .unwrap();
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+        let read = |repo: &mut Repo, path: &str| {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        };
+        let big = vec![7u8; 300 * 1024];
+
+        repo.create_dir_all("/a/b").unwrap();
+        write(&mut repo, "/a/b/f", b"v1");
+        write(&mut repo, "/a/big", &big);
+        repo.snapshot("snap").unwrap();
+        write(&mut repo, "/a/b/f", b"v2");
+        repo.remove_file("/a/big").unwrap();
+
+        repo.migrate_cipher(&pwd, Cipher::Aes).unwrap();
+        assert_eq!(repo.info().unwrap().cipher(), Cipher::Aes);
+        // migrating to the current cipher does nothing
+        repo.migrate_cipher(&pwd, Cipher::Aes).unwrap();
+        write(&mut repo, "/a/c", b"after");
+        drop(repo);
+
+        // re-open and check data encrypted by the new cipher
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.info().unwrap().cipher(), Cipher::Aes);
+        assert_eq!(read(&mut repo, "/a/b/f"), b"v2");
+        assert_eq!(read(&mut repo, "/a/c"), b"after");
+        assert_eq!(repo.history("/a/b/f").unwrap().len(), 3);
+        repo.rollback_to("snap").unwrap();
+        assert_eq!(read(&mut repo, "/a/big"), &big[..]);
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #28: test frozen view
+    {
+        let path = base.clone() + "/repo28";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+        let read = |repo: &mut Repo, path: &str| {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        };
+
+        repo.create_dir_all("/a/b").unwrap();
+        write(&mut repo, "/a/b/f", b"old");
+        write(&mut repo, "/a/g", b"g");
+        let mut view = repo.freeze_view().unwrap();
+        assert!(view.info().unwrap().is_read_only());
+        assert!(repo.list_snapshots().is_empty());
+
+        // change the tree through main repo
+        write(&mut repo, "/a/b/f", b"new");
+        repo.remove_file("/a/g").unwrap();
+        write(&mut repo, "/h", b"h");
+
+        assert_eq!(read(&mut view, "/a/b/f"), b"old");
+        assert_eq!(read(&mut view, "/a/g"), b"g");
+        assert!(!view.path_exists("/h").unwrap());
+        assert_eq!(view.create_dir("/x").unwrap_err(), Error::ReadOnly);
+        assert!(repo.verify().unwrap().is_ok());
+        assert!(view.verify().unwrap().is_ok());
+
+        // content held by the view is released when it is dropped
+        drop(view);
+        assert_eq!(read(&mut repo, "/a/b/f"), b"new");
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #29: test disk usage
+    {
+        let path = base.clone() + "/repo29";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .version_limit(3)
+            .open(&path, &pwd)
+            .unwrap();
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut rng = XorShiftRng::from_seed([42u8; 16]);
+        rng.fill_bytes(&mut buf);
+
+        repo.create_dir_all("/a/b").unwrap();
+        write(&mut repo, "/a/b/f", &buf[..1000]);
+        write(&mut repo, "/a/b/f", &buf[..3000]);
+        write(&mut repo, "/a/g", &buf);
+        write(&mut repo, "/h", b"h");
+
+        let du = repo.du("/a/b").unwrap();
+        assert_eq!(du.logical_size(), 3000);
+        assert_eq!(du.version_overhead(), 1000);
+        assert_eq!(du.file_count(), 1);
+        assert_eq!(du.dir_count(), 1);
+        assert!(du.physical_size() >= 3000);
+        assert!(du.physical_size() <= 4000);
+
+        let du = repo.du("/").unwrap();
+        assert_eq!(du.logical_size(), 3000 + buf.len() as u64 + 1);
+        assert_eq!(du.file_count(), 3);
+        assert_eq!(du.dir_count(), 3);
+
+        let du = repo.du("/h").unwrap();
+        assert_eq!(du.logical_size(), 1);
+        assert_eq!(du.physical_size(), 1);
+        assert_eq!(repo.du("/none").unwrap_err(), Error::NotFound);
+    }
+
+    // case #30: test trash mode
+    {
+        let path = base.clone() + "/repo30";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .trash(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+        let read = |repo: &mut Repo, path: &str| {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        };
+
+        repo.create_dir_all("/a/b").unwrap();
+        write(&mut repo, "/a/b/f", b"v1");
+        write(&mut repo, "/a/g", b"g");
+        repo.remove_file("/a/b/f").unwrap();
+        write(&mut repo, "/a/b/f", b"v2");
+        repo.remove_file("/a/b/f").unwrap();
+        repo.remove_dir_all("/a").unwrap();
+        assert!(!repo.path_exists("/a").unwrap());
+
+        let ents = repo.list_trash().unwrap();
+        assert_eq!(ents.len(), 3);
+        assert_eq!(ents[0].path(), Path::new("/a/b/f"));
+        assert_eq!(ents[0].file_type(), FileType::File);
+        assert_eq!(ents[2].path(), Path::new("/a"));
+        assert_eq!(ents[2].file_type(), FileType::Dir);
+
+        // trash is kept out of the tree, so it is neither listed nor counted
+        // and it doesn't reserve any path
+        assert!(repo.read_dir("/").unwrap().is_empty());
+        repo.create_dir("/.trash").unwrap();
+        let ents = repo.read_dir("/").unwrap();
+        assert_eq!(ents.len(), 1);
+        assert_eq!(ents[0].path(), Path::new("/.trash"));
+        assert_eq!(repo.list_trash().unwrap().len(), 3);
+        let info = repo.info().unwrap();
+        assert_eq!((info.file_count(), info.dir_count()), (0, 1));
+
+        // latest removed entry is restored, parent dirs are created
+        repo.restore_from_trash("/a/b/f").unwrap();
+        assert_eq!(read(&mut repo, "/a/b/f"), b"v2");
+        assert_eq!(
+            repo.restore_from_trash("/a/b/f").unwrap_err(),
+            Error::AlreadyExists
+        );
+        repo.remove_dir_all("/a").unwrap();
+        repo.restore_from_trash("/a").unwrap();
+        assert_eq!(read(&mut repo, "/a/b/f"), b"v2");
+        assert!(!repo.path_exists("/a/g").unwrap());
+        assert_eq!(repo.list_trash().unwrap().len(), 2);
+        let info = repo.info().unwrap();
+        assert_eq!((info.file_count(), info.dir_count()), (1, 3));
+        assert_eq!(
+            repo.restore_from_trash("/none").unwrap_err(),
+            Error::NotFound
+        );
+        assert!(repo.verify().unwrap().is_ok());
+        drop(repo);
+
+        // expired entries are removed on open, entries in trash are removed
+        // permanently
+        let mut repo = RepoOpener::new()
+            .trash(true)
+            .trash_expiry(Duration::from_secs(0))
+            .open(&path, &pwd)
+            .unwrap();
+        assert!(repo.list_trash().unwrap().is_empty());
+        repo.remove_file("/a/b/f").unwrap();
+        assert!(repo.list_trash().unwrap().is_empty());
+        repo.remove_dir_all("/").unwrap();
+        assert!(!repo.path_exists("/a").unwrap());
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #31: test file transfer between repos
+    {
+        let mut src = RepoOpener::new()
+            .create_new(true)
+            .version_limit(4)
+            .open(&(base.clone() + "/repo31a"), &pwd)
+            .unwrap();
+        let mut dst = RepoOpener::new()
+            .create_new(true)
+            .cipher(Cipher::Xchacha)
+            .open(&(base.clone() + "/repo31b"), "pwd2")
+            .unwrap();
+        let read = |repo: &mut Repo, path: &str, ver: usize| {
+            let f = repo.open_file(path).unwrap();
+            let mut buf = Vec::new();
+            f.version_reader(ver)
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            buf
+        };
+
+        let mut buf = vec![0u8; 300 * 1024];
+        XorShiftRng::from_seed([3u8; 16]).fill_bytes(&mut buf);
+        let mut f = src.create_file("/f").unwrap();
+        f.write_once(&buf).unwrap();
+        f.set_len(5).unwrap();
+        drop(f);
+        dst.create_dir("/d").unwrap();
+
+        // only current version is transferred by default
+        zbox::transfer(&mut src, "/f", &mut dst, "/d/f").unwrap();
+        let hist = dst.history("/d/f").unwrap();
+        assert_eq!(hist.len(), 1);
+        assert_eq!(read(&mut dst, "/d/f", hist[0].num()), &buf[..5]);
+        assert_eq!(
+            dst.metadata("/d/f").unwrap().modified_at(),
+            src.metadata("/f").unwrap().modified_at()
+        );
+        assert_eq!(
+            zbox::transfer(&mut src, "/f", &mut dst, "/d/f").unwrap_err(),
+            Error::AlreadyExists
+        );
+        assert_eq!(
+            zbox::transfer(&mut src, "/", &mut dst, "/g").unwrap_err(),
+            Error::NotFile
+        );
+
+        // transfer with history, shorter version replaces longer one
+        zbox::TransferOptions::new()
+            .history(true)
+            .transfer(&mut src, "/f", &mut dst, "/g")
+            .unwrap();
+        let hist = dst.history("/g").unwrap();
+        assert_eq!(hist.len(), 3);
+        assert_eq!(hist[1].content_len(), buf.len());
+        assert_eq!(read(&mut dst, "/g", hist[1].num()), buf);
+        assert_eq!(read(&mut dst, "/g", hist[2].num()), &buf[..5]);
+        assert!(src.is_file("/f").unwrap());
+        assert!(dst.verify().unwrap().is_ok());
+    }
+
+    // case #32: test metadata export
+    {
+        let path = base.clone() + "/repo32";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .version_limit(3)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_dir_all("/a/b").unwrap();
+        let mut f = repo.create_file("/a/f").unwrap();
+        f.write_once(b"foo").unwrap();
+        f.write_once(b"bar").unwrap();
+        drop(f);
+        repo.create_file("/a/b/\"g\"")
+            .unwrap()
+            .write_once(b"foobar")
+            .unwrap();
+        repo.symlink("/a/f", "/l").unwrap();
+
+        let mut buf = Vec::new();
+        repo.export_metadata(&mut buf).unwrap();
+        let json = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with(r#"{"path":"/","type":"Dir""#));
+        assert!(lines[1].starts_with(r#"{"path":"/a","type":"Dir""#));
+        assert!(lines[2].starts_with(r#"{"path":"/a/b","type":"Dir""#));
+        assert!(lines[3].starts_with(r#"{"path":"/a/b/\"g\"","#));
+        assert!(lines[4].starts_with(r#"{"path":"/a/f","type":"File","#));
+        assert!(lines[4].contains(r#""size":6,"#));
+        assert!(lines[4].contains(r#""versions":3,"#));
+        assert!(lines[5].starts_with(r#"{"path":"/l","type":"Symlink""#));
+        assert!(!lines[5].contains("hash"));
+
+        // same content has same hash
+        let hash =
+            |line: &str| line.split("\"hash\":").nth(1).unwrap().to_owned();
+        assert_eq!(hash(lines[3]), hash(lines[4]));
+    }
+
+    // case #33: test lock wait on repo open
+    {
+        let path = base.clone() + "/repo33";
+        let repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+
+        // wait should time out if repo is still opened
+        let started = Instant::now();
+        assert_eq!(
+            RepoOpener::new()
+                .lock_wait(Duration::from_millis(300))
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::RepoOpened
+        );
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        // repo should be opened once it is closed by another thread
+        let closer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            drop(repo);
+        });
+        RepoOpener::new()
+            .lock_wait(Duration::from_secs(10))
+            .open(&path, &pwd)
+            .unwrap();
+        closer.join().unwrap();
+    }
+
+    // case #34: test read-only open alongside a writer
+    #[cfg(feature = "storage-file")]
+    {
+        let path = base.clone() + "/repo34";
+        let mut writer = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        writer
+            .create_file("/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+
+        let mut reader =
+            RepoOpener::new().read_only(true).open(&path, &pwd).unwrap();
+        assert!(reader.is_file("/foo").unwrap());
+        assert_eq!(
+            RepoOpener::new().open(&path, &pwd).unwrap_err(),
+            Error::RepoOpened
+        );
+
+        // reader sees writer's changes only after refresh
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&mut writer, "/foo")
+            .unwrap();
+        file.write_once(b"bar").unwrap();
+        writer
+            .create_file("/bar")
+            .unwrap()
+            .write_once(b"baz")
+            .unwrap();
+        let old = reader.open_file("/foo").unwrap();
+        assert!(!reader.path_exists("/bar").unwrap());
+
+        reader.refresh().unwrap();
+        assert!(reader.path_exists("/bar").unwrap());
+        let mut buf = String::new();
+        reader
+            .open_file("/foo")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "foobar");
+        buf.clear();
+        reader
+            .open_file("/bar")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "baz");
+
+        // files opened before refresh are closed
+        assert_eq!(old.metadata().unwrap_err(), Error::RepoClosed);
+
+        // writer keeps removed data while reader is open
+        writer.remove_file("/bar").unwrap();
+        writer.compact(|_, _| {}).unwrap();
+        buf.clear();
+        reader
+            .open_file("/bar")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "baz");
+
+        // another reader can open at the same time
+        RepoOpener::new().read_only(true).open(&path, &pwd).unwrap();
+    }
+
+    // case #35: test sharing repo handles between threads
+    {
+        let path = base.clone() + "/repo35";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let mut repo = repo.clone();
+                thread::spawn(move || {
+                    let path = format!("/file{}", i);
+                    let mut file = repo.create_file(&path).unwrap();
+                    file.write_once(path.as_bytes()).unwrap();
+                    assert!(repo.is_file(&path).unwrap());
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(repo.read_dir("/").unwrap().len(), 4);
+        for i in 0..4 {
+            let path = format!("/file{}", i);
+            let mut buf = String::new();
+            let mut file = repo.open_file(&path).unwrap();
+            file.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, path);
+        }
+
+        // repo is still opened by the remaining handle
+        let handle = repo.clone();
+        drop(repo);
+        assert!(handle.is_dir("/").unwrap());
+        assert_eq!(
+            RepoOpener::new().open(&path, &pwd).unwrap_err(),
+            Error::RepoOpened
+        );
+        drop(handle);
+        RepoOpener::new().open(&path, &pwd).unwrap();
+    }
+
+    // case #36: test path validation policy
+    {
+        let path = base.clone() + "/repo36";
+        let mut policy = PathPolicy::portable();
+        policy.max_depth = Some(2);
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .path_policy(policy)
+            .open(&path, &pwd)
+            .unwrap();
+
+        repo.create_dir("/foo").unwrap();
+        repo.create_file("/foo/bar.txt").unwrap();
+        assert_eq!(
+            repo.create_file("/foo/a|b").unwrap_err(),
+            Error::PathViolation(PathViolation::ForbiddenChar {
+                name: "a|b".to_string(),
+                ch: '|',
+            })
+        );
+        assert_eq!(
+            repo.create_dir_all("/foo/bar/baz").unwrap_err(),
+            Error::PathViolation(PathViolation::TooDeep { depth: 3, max: 2 })
+        );
+        assert_eq!(
+            repo.rename("/foo/bar.txt", "/foo/COM1.txt").unwrap_err(),
+            Error::PathViolation(PathViolation::ReservedName(
+                "COM1.txt".to_string()
+            ))
+        );
+        assert_eq!(
+            repo.copy("/foo/bar.txt", "/foo/baz?").unwrap_err(),
+            Error::PathViolation(PathViolation::ForbiddenChar {
+                name: "baz?".to_string(),
+                ch: '?',
+            })
+        );
+        assert!(!repo.path_exists("/foo/baz?").unwrap());
+
+        // policy is not persisted
+        drop(repo);
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        repo.create_file("/foo/a|b").unwrap();
+    }
+
+    // case #37: test progress callback on repo open
+    {
+        let path = base.clone() + "/repo37";
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let mut opener = RepoOpener::new();
+        {
+            let phases = phases.clone();
+            opener.on_progress(move |phase| phases.lock().unwrap().push(phase));
+        }
+
+        // no phases are reported when creating repo
+        opener.create_new(true).open(&path, &pwd).unwrap();
+        assert!(phases.lock().unwrap().is_empty());
+
+        opener.create_new(false).open(&path, &pwd).unwrap();
+        assert_eq!(
+            *phases.lock().unwrap(),
+            vec![
+                OpenPhase::KeyDerivation,
+                OpenPhase::SuperBlock,
+                OpenPhase::WalRedo,
+                OpenPhase::TreeLoad,
+            ]
+        );
+
+        // phases before failure are reported
+        phases.lock().unwrap().clear();
+        assert_eq!(
+            opener.open(&path, "wrong pwd").unwrap_err(),
+            Error::Decrypt
+        );
+        assert_eq!(*phases.lock().unwrap(), vec![OpenPhase::KeyDerivation]);
+    }
+
+    // case #38: test repo seal
+    {
+        let path = base.clone() + "/repo38";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_file("/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        assert!(!repo.info().unwrap().is_sealed());
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&mut repo, "/foo")
+            .unwrap();
+        let mut pending = repo.create_file("/pending").unwrap();
+        pending.write_all(b"bar").unwrap();
+
+        repo.seal(&pwd).unwrap();
+        let info = repo.info().unwrap();
+        assert!(info.is_sealed());
+        assert!(info.is_read_only());
+        assert_eq!(repo.create_dir("/bar").unwrap_err(), Error::ReadOnly);
+
+        // files opened for writing before sealing cannot be changed either
+        assert!(pending.finish().is_err());
+        assert_eq!(file.write_once(b"bar").unwrap_err(), Error::ReadOnly);
+        assert_eq!(file.set_len(0).unwrap_err(), Error::ReadOnly);
+        drop(file);
+        drop(pending);
+        drop(repo);
+
+        // sealed repo cannot be opened for writing
+        assert_eq!(
+            RepoOpener::new().open(&path, &pwd).unwrap_err(),
+            Error::ReadOnly
+        );
+        assert_eq!(
+            RepoOpener::new().force(true).open(&path, &pwd).unwrap_err(),
+            Error::ReadOnly
+        );
+
+        let mut repo =
+            RepoOpener::new().read_only(true).open(&path, &pwd).unwrap();
+        assert!(repo.info().unwrap().is_sealed());
+        let mut buf = String::new();
+        repo.open_file("/foo")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "foo");
+        assert_eq!(repo.metadata("/pending").unwrap().content_len(), 0);
+        assert_eq!(repo.seal(&pwd).unwrap_err(), Error::ReadOnly);
+    }
+
+    // case #39: test open with derived key
+    {
+        let path = base.clone() + "/repo39";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_file("/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        drop(repo);
+
+        let key = Repo::derive_key(&path, &pwd).unwrap();
+        let mut repo = RepoOpener::new().open_with_key(&path, &key).unwrap();
+        let mut buf = String::new();
+        repo.open_file("/foo")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "foo");
+        repo.create_dir("/bar").unwrap();
+        drop(repo);
+
+        // key can be used again
+        let repo = RepoOpener::new()
+            .read_only(true)
+            .open_with_key(&path, &key)
+            .unwrap();
+        assert!(repo.path_exists("/bar").unwrap());
+        drop(repo);
+
+        // key derived from wrong password should fail
+        let key2 = Repo::derive_key(&path, "wrong pwd").unwrap();
+        assert_eq!(
+            RepoOpener::new().open_with_key(&path, &key2).unwrap_err(),
+            Error::Decrypt
+        );
+
+        // cannot create repo with derived key
+        let path2 = base.clone() + "/repo39_2";
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .open_with_key(&path2, &key)
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            RepoOpener::new()
+                .create_new(true)
+                .open_with_key(&path, &key)
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+    }
+
+    // case #40: test password validator
+    {
+        let path = base.clone() + "/repo40";
+        let mut opener = RepoOpener::new();
+        opener
+            .create(true)
+            .password_validator(|pwd| pwd.len() >= 8 && pwd != "password");
+        assert_eq!(
+            opener.open(&path, "short").unwrap_err(),
+            Error::WeakPassword
+        );
+        assert_eq!(
+            opener.open(&path, "password").unwrap_err(),
+            Error::WeakPassword
+        );
+        assert!(!Repo::exists(&path).unwrap());
+
+        let mut repo = opener.open(&path, "long enough").unwrap();
+        assert_eq!(
+            repo.reset_password(
+                "long enough",
+                "pwd",
+                OpsLimit::Interactive,
+                MemLimit::Interactive
+            )
+            .unwrap_err(),
+            Error::WeakPassword
+        );
+        repo.reset_password(
+            "long enough",
+            "still long enough",
+            OpsLimit::Interactive,
+            MemLimit::Interactive,
+        )
+        .unwrap();
+        drop(repo);
+
+        // existing password is not checked on open
+        let mut opener = RepoOpener::new();
+        opener.password_validator(|_| false);
+        opener.open(&path, "still long enough").unwrap();
+    }
+
+    // case #41: test clone repo to another uri
+    {
+        let path = base.clone() + "/repo41";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .compress(true)
+            .version_limit(3)
+            .label("origin")
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_dir_all("/dir/sub").unwrap();
+        repo.set_dir_options("/dir/sub", DirOptions::new().version_limit(5))
+            .unwrap();
+        repo.set_quota("/dir", 1 << 20).unwrap();
+        {
+            let mut file = repo.create_file("/dir/sub/foo").unwrap();
+            file.write_once(b"foo").unwrap();
+            file.write_once(b"foo2").unwrap();
+        }
+        repo.set_permissions(
+            "/dir/sub/foo",
+            Permissions::new(0o600, 1000, 100),
+        )
+        .unwrap();
+        repo.symlink("sub/foo", "/dir/link").unwrap();
+        repo.create_node("/fifo", FileType::Fifo, 0).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        repo.set_mtime("/dir", mtime).unwrap();
+
+        let path2 = base.clone() + "/repo41_2";
+        let copy = repo.clone_to(&path2, "new pwd").unwrap();
+        let info = copy.info().unwrap();
+        assert!(info.compress());
+        assert_eq!(info.version_limit(), 3);
+        assert_eq!(info.label(), "origin");
+        assert_eq!(copy.history("/dir/sub/foo").unwrap().len(), 3);
+        assert_eq!(
+            copy.dir_options("/dir/sub").unwrap().get_version_limit(),
+            Some(5)
+        );
+        assert_eq!(copy.quota("/dir").unwrap().unwrap().limit(), 1 << 20);
+        assert_eq!(
+            copy.metadata("/dir/sub/foo")
+                .unwrap()
+                .permissions()
+                .unwrap()
+                .mode(),
+            0o600
+        );
+        assert_eq!(copy.metadata("/dir").unwrap().modified_at(), mtime);
+        assert_eq!(
+            copy.read_link("/dir/link").unwrap(),
+            PathBuf::from("sub/foo")
+        );
+        assert_eq!(copy.metadata("/fifo").unwrap().file_type(), FileType::Fifo);
+        drop(copy);
+
+        let mut copy = RepoOpener::new().open(&path2, "new pwd").unwrap();
+        let mut buf = String::new();
+        copy.open_file("/dir/sub/foo")
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "foofoo2");
+        drop(copy);
+
+        assert_eq!(
+            repo.clone_to(&path2, "new pwd").unwrap_err(),
+            Error::RepoExists
+        );
+    }
+
+    // case #42: test entry counts in repo info
+    {
+        fn counts(repo: &Repo) -> (u64, u64, u64) {
+            let info = repo.info().unwrap();
+            (info.file_count(), info.dir_count(), info.version_count())
+        }
+
+        let path = base.clone() + "/repo42";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .version_limit(2)
+            .open(&path, &pwd)
+            .unwrap();
+        assert_eq!(counts(&repo), (0, 0, 0));
+
+        repo.create_dir_all("/dir/sub").unwrap();
+        repo.create_file("/dir/foo").unwrap();
+        repo.symlink("foo", "/dir/link").unwrap();
+        assert_eq!(counts(&repo), (2, 2, 1));
+
+        // versions beyond version limit are not counted
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&mut repo, "/dir/foo")
+                .unwrap();
+            file.write_once(b"foo").unwrap();
+            assert_eq!(counts(&repo), (2, 2, 2));
+            file.write_once(b"bar").unwrap();
+            assert_eq!(counts(&repo), (2, 2, 2));
+        }
+
+        repo.copy_dir_all("/dir", "/dir2").unwrap();
+        assert_eq!(counts(&repo), (4, 4, 4));
+
+        // entries in snapshot are not counted
+        repo.snapshot("snap").unwrap();
+        assert_eq!(counts(&repo), (4, 4, 4));
+
+        repo.rename("/dir2/link", "/dir/foo").unwrap();
+        assert_eq!(counts(&repo), (3, 4, 2));
+        repo.remove_dir_all("/dir2").unwrap();
+        assert_eq!(counts(&repo), (2, 2, 0));
+        repo.remove_snapshot("snap").unwrap();
+        assert_eq!(counts(&repo), (2, 2, 0));
+        drop(repo);
+
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(counts(&repo), (2, 2, 0));
+    }
+
+    // case #43: test copy file range
+    {
+        fn read_all(repo: &mut Repo, path: &str) -> Vec<u8> {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        }
+
+        let path = base.clone() + "/repo43";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+
+        let mut src = vec![0u8; 600 * 1024];
+        let mut rng = XorShiftRng::from_seed([43u8; 16]);
+        rng.fill_bytes(&mut src);
+        let mut dst = vec![0u8; 300 * 1024];
+        rng.fill_bytes(&mut dst);
+        repo.create_file("/src").unwrap().write_once(&src).unwrap();
+        repo.create_file("/dst").unwrap().write_once(&dst).unwrap();
+
+        // copy into the middle and extend destination
+        let copied = repo
+            .copy_file_range("/src", 1234, "/dst", 100_000, 400_000)
+            .unwrap();
+        assert_eq!(copied, 400_000);
+        dst.resize(500_000, 0);
+        dst[100_000..].copy_from_slice(&src[1234..401_234]);
+        assert_eq!(read_all(&mut repo, "/dst"), dst);
+
+        // copy stops at end of source
+        let copied = repo
+            .copy_file_range("/src", 610_000, "/dst", 0, 10_000)
+            .unwrap();
+        assert_eq!(copied, 600 * 1024 - 610_000);
+        dst[..copied as usize].copy_from_slice(&src[610_000..]);
+        assert_eq!(read_all(&mut repo, "/dst"), dst);
+        assert_eq!(
+            repo.copy_file_range("/src", 700_000, "/dst", 0, 10)
+                .unwrap(),
+            0
+        );
+
+        // copy within the same file
+        repo.copy_file_range("/src", 0, "/src", 5, 1000).unwrap();
+        let mut expected = src.clone();
+        expected[5..1005].copy_from_slice(&src[..1000]);
+        assert_eq!(read_all(&mut repo, "/src"), expected);
+
+        // holes are not allowed
+        assert_eq!(
+            repo.copy_file_range("/src", 0, "/dst", 500_001, 10)
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+        repo.create_dir("/dir").unwrap();
+        assert_eq!(
+            repo.copy_file_range("/dir", 0, "/dst", 0, 10).unwrap_err(),
+            Error::NotFile
+        );
+        drop(repo);
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(read_all(&mut repo, "/dst"), dst);
+        assert_eq!(read_all(&mut repo, "/src"), expected);
+    }
+
+    // case #44: test stale temporary files are removed on open
+    {
+        let path = base.clone() + "/repo44";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        // directory named like the old temporary directory is not touched
+        repo.create_dir("/.temp").unwrap();
+        repo.create_file("/.temp/keep").unwrap();
+        let mut file = repo.create_temp_file().unwrap();
+        file.write_once(b"foo").unwrap();
+        assert_eq!(repo.info().unwrap().file_count(), 1);
+        assert_eq!(repo.info().unwrap().logical_size(), 3);
+
+        // leak the file to simulate a crash before it is removed
+        std::mem::forget(file);
+        drop(repo);
+
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(repo.is_file("/.temp/keep").unwrap());
+        assert_eq!(repo.info().unwrap().file_count(), 1);
+        assert_eq!(repo.info().unwrap().logical_size(), 0);
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #45: test access time tracking
+    {
+        let path = base.clone() + "/repo45";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .atime(true)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_file("/file")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        let meta = repo.metadata("/file").unwrap();
+        assert!(meta.accessed_at().is_none());
+
+        let mut buf = Vec::new();
+        let mut file = repo.open_file("/file").unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        let atime = repo.metadata("/file").unwrap().accessed_at().unwrap();
+        assert!(atime >= meta.modified_at());
+
+        // access time is not updated again if it is recent
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        let mut file2 = repo.open_file("/file").unwrap();
+        file2.read_to_end(&mut buf).unwrap();
+        let meta = repo.metadata("/file").unwrap();
+        assert_eq!(meta.accessed_at().unwrap(), atime);
+
+        // access time is updated if file is modified after it
+        thread::sleep(Duration::from_millis(10));
+        OpenOptions::new()
+            .write(true)
+            .open(&mut repo, "/file")
+            .unwrap()
+            .write_once(b"bar")
+            .unwrap();
+        let mut file = repo.open_file("/file").unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        let meta = repo.metadata("/file").unwrap();
+        assert!(meta.accessed_at().unwrap() >= meta.modified_at());
+        assert!(meta.accessed_at().unwrap() > atime);
+        drop(file);
+        drop(file2);
+        drop(repo);
+
+        // access time is persisted but not updated without tracking
+        repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        repo.create_file("/file2")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        let mut file = repo.open_file("/file2").unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        assert!(repo.metadata("/file2").unwrap().accessed_at().is_none());
+        assert!(repo.metadata("/file").unwrap().accessed_at().is_some());
+    }
+
+    // case #46: test shred
+    {
+        let path = base.clone() + "/repo46";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .trash(true)
+            .open(&path, &pwd)
+            .unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .version_limit(2)
+            .open(&mut repo, "/file")
+            .unwrap();
+        file.write_once(b"foo").unwrap();
+        file.write_once(b"bar").unwrap();
+        drop(file);
+        repo.clone_file("/file", "/clone").unwrap();
+        repo.create_dir("/dir").unwrap();
+
+        // cannot shred directory
+        assert_eq!(repo.shred("/dir").unwrap_err(), Error::NotFile);
+
+        // cannot shred when other transaction is in progress
+        let mut file = repo.create_file("/file2").unwrap();
+        file.write_all(b"baz").unwrap();
+        assert_eq!(repo.shred("/file").unwrap_err(), Error::InTrans);
+        assert!(repo.path_exists("/file").unwrap());
+        file.finish().unwrap();
+
+        // shredded file bypasses trash, shared content is still intact
+        repo.shred("/file").unwrap();
+        assert!(!repo.path_exists("/file").unwrap());
+        assert!(repo.list_trash().unwrap().is_empty());
+        let mut buf = Vec::new();
+        repo.open_file("/clone")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], b"foobar");
+        assert!(repo.verify().unwrap().is_ok());
+        drop(repo);
+
+        repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(!repo.path_exists("/file").unwrap());
+        assert!(repo.path_exists("/file2").unwrap());
+    }
+
+    // case #47: test entity id handles
+    {
+        let path = base.clone() + "/repo47";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_dir_all("/a/b").unwrap();
+        repo.create_file("/a/b/file")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        let root_id = repo.path_to_id("/").unwrap();
+        let dir_id = repo.path_to_id("/a/b").unwrap();
+        let file_id = repo.path_to_id("/a/b/file").unwrap();
+        assert_ne!(dir_id, file_id);
+        assert_eq!(repo.id_to_path(&root_id).unwrap(), Path::new("/"));
+
+        // ids are kept across renames
+        repo.rename("/a/b", "/c").unwrap();
+        assert_eq!(repo.path_to_id("/c").unwrap(), dir_id);
+        assert_eq!(repo.id_to_path(&dir_id).unwrap(), Path::new("/c"));
+        assert_eq!(repo.id_to_path(&file_id).unwrap(), Path::new("/c/file"));
+        drop(repo);
+
+        // ids are persistent, and found by searching tree after re-open
+        repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.id_to_path(&file_id).unwrap(), Path::new("/c/file"));
+        let mut buf = Vec::new();
+        repo.open_file_by_id(&file_id)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], b"foo");
+
+        // removed entry cannot be found
+        repo.remove_file("/c/file").unwrap();
+        assert_eq!(repo.id_to_path(&file_id).unwrap_err(), Error::NotFound);
+        assert_eq!(
+            repo.open_file_by_id(&file_id).unwrap_err(),
+            Error::NotFound
+        );
+    }
+
+    // case #48: test AES-GCM-SIV cipher
+    #[cfg(not(feature = "aes-gcm-siv"))]
+    {
+        let path = base.clone() + "/repo48";
+        assert_eq!(
+            RepoOpener::new()
+                .create_new(true)
+                .cipher(Cipher::AesGcmSiv)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCipher
+        );
+    }
+    #[cfg(feature = "aes-gcm-siv")]
+    {
+        let path = base.clone() + "/repo48";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .cipher(Cipher::AesGcmSiv)
+            .open(&path, &pwd)
+            .unwrap();
+        repo.create_file("/file")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        drop(repo);
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.info().unwrap().cipher(), Cipher::AesGcmSiv);
+        let mut buf = Vec::new();
+        repo.open_file("/file")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], b"foo");
+
+        // migrate to another cipher and back
+        repo.migrate_cipher(&pwd, Cipher::Xchacha).unwrap();
+        repo.migrate_cipher(&pwd, Cipher::AesGcmSiv).unwrap();
+        assert_eq!(repo.info().unwrap().cipher(), Cipher::AesGcmSiv);
+        assert!(repo.verify().unwrap().is_ok());
+    }
+
+    // case #49: test nonce strategy
+    #[cfg(feature = "aes-gcm-siv")]
+    {
+        let path = base.clone() + "/repo49";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .cipher(Cipher::Xchacha)
+            .open(&path, &pwd)
+            .unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.cipher(), Cipher::Xchacha);
+        assert_eq!(info.nonce_strategy(), NonceStrategy::Random);
+
+        // nonce strategy follows cipher migration and is kept after re-open
+        repo.migrate_cipher(&pwd, Cipher::AesGcmSiv).unwrap();
+        drop(repo);
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(
+            repo.info().unwrap().nonce_strategy(),
+            NonceStrategy::MisuseResistant
+        );
+    }
+
+    // case #50: test custom password hash parameters
+    {
+        let path = base.clone() + "/repo50";
+
+        // invalid parameters
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .kdf_params(1024, 1, 0)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCost
+        );
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .kdf_params(8, 1, 2)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCost
+        );
+
+        // libsodium only supports single lane
+        #[cfg(not(feature = "argon2"))]
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .kdf_params(1024, 2, 2)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+
+        let lanes = if cfg!(feature = "argon2") { 2 } else { 1 };
+        let repo = RepoOpener::new()
+            .create_new(true)
+            .kdf_params(1024, 2, lanes)
+            .open(&path, &pwd)
+            .unwrap();
+        drop(repo);
+
+        // parameters are honored on open
+        assert_eq!(
+            RepoOpener::new().open(&path, "wrong pwd").unwrap_err(),
+            Error::Decrypt
+        );
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        let kdf = repo.info().unwrap().kdf_params().unwrap();
+        assert_eq!(kdf.mem_kib(), 1024);
+        assert_eq!(kdf.iterations(), 2);
+        assert_eq!(kdf.parallelism(), lanes);
+
+        // reset password replaces custom parameters with presets
+        repo.reset_password(
+            &pwd,
+            "new pwd",
+            OpsLimit::Interactive,
+            MemLimit::Interactive,
+        )
+        .unwrap();
+        drop(repo);
+        let repo = RepoOpener::new().open(&path, "new pwd").unwrap();
+        assert!(repo.info().unwrap().kdf_params().is_none());
+    }
+
+    // case #51: test scrypt password hash
+    {
+        let path = base.clone() + "/repo51";
+
+        // invalid parameters
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .scrypt_params(0, 8, 1)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCost
+        );
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .scrypt_params(30, 8, 1)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCost
+        );
+
+        // only one algorithm can be specified
+        assert_eq!(
+            RepoOpener::new()
+                .create(true)
+                .kdf_params(1024, 2, 2)
+                .scrypt_params(10, 8, 1)
+                .open(&path, &pwd)
+                .unwrap_err(),
+            Error::InvalidCost
+        );
+
+        let repo = RepoOpener::new()
+            .create_new(true)
+            .scrypt_params(10, 8, 2)
+            .open(&path, &pwd)
+            .unwrap();
+        drop(repo);
+
+        // algorithm is recorded and honored on open
+        assert_eq!(
+            RepoOpener::new().open(&path, "wrong pwd").unwrap_err(),
+            Error::Decrypt
+        );
+        let repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.kdf(), Kdf::Scrypt);
+        assert!(info.kdf_params().is_none());
+        let params = info.scrypt_params().unwrap();
+        assert_eq!(params.log_n(), 10);
+        assert_eq!(params.r(), 8);
+        assert_eq!(params.p(), 2);
+    }
+
+    // case #52: test key provider
+    {
+        #[derive(Debug)]
+        struct XorProvider(u8);
+
+        impl KeyProvider for XorProvider {
+            fn uri(&self) -> String {
+                "pkcs11:token=test;object=kek".to_string()
+            }
+
+            fn wrap_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+                Ok(key.iter().map(|b| b ^ self.0).collect())
+            }
+
+            fn unwrap_key(&self, wrapped: &[u8], key: &mut [u8]) -> Result<()> {
+                if wrapped.len() != key.len() {
+                    return Err(Error::Decrypt);
+                }
+                for (k, w) in key.iter_mut().zip(wrapped) {
+                    *k = w ^ self.0;
+                }
+                Ok(())
+            }
+        }
+
+        let path = base.clone() + "/repo52";
+        let provider = XorProvider(42);
+
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open_with_provider(&path, &provider)
+            .unwrap();
+        repo.create_file("/file").unwrap();
+        drop(repo);
+
+        assert_eq!(
+            Repo::key_provider_uri(&path).unwrap().unwrap(),
+            "pkcs11:token=test;object=kek"
+        );
+
+        // password cannot be used
+        assert_eq!(
+            RepoOpener::new().open(&path, &pwd).unwrap_err(),
+            Error::KeyProvider
+        );
+        assert_eq!(
+            Repo::derive_key(&path, &pwd).unwrap_err(),
+            Error::KeyProvider
+        );
+
+        // wrong key encryption key
+        assert_eq!(
+            RepoOpener::new()
+                .open_with_provider(&path, &XorProvider(43))
+                .unwrap_err(),
+            Error::Decrypt
+        );
+
+        let mut repo = RepoOpener::new()
+            .open_with_provider(&path, &provider)
+            .unwrap();
+        assert!(repo.is_file("/file").unwrap());
+        assert_eq!(
+            repo.set_password_hint(&pwd, "hint").unwrap_err(),
+            Error::KeyProvider
+        );
+        drop(repo);
+
+        // password protected repo cannot be opened by key provider
+        let path = base.clone() + "/repo52b";
+        RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        assert!(Repo::key_provider_uri(&path).unwrap().is_none());
+        assert_eq!(
+            RepoOpener::new()
+                .open_with_provider(&path, &provider)
+                .unwrap_err(),
+            Error::KeyProvider
+        );
+    }
+
+    // case #53: test drop box
+    {
+        let path = base.clone() + "/repo53";
+        let mut repo = RepoOpener::new()
+            .create_new(true)
+            .open(&path, &pwd)
+            .unwrap();
+        assert!(repo.drop_public_key().is_none());
+        assert_eq!(
+            repo.import_drop(&b""[..], "/").unwrap_err(),
+            Error::InvalidArgument
+        );
+        let pk = repo.enable_drop_box(&pwd).unwrap();
+        assert_eq!(repo.enable_drop_box(&pwd).unwrap(), pk);
+        assert_eq!(repo.drop_public_key(), Some(pk));
+        drop(repo);
+
+        // client creates package using public key distributed to it
+        let pk = DropPublicKey::from_bytes(pk.as_bytes()).unwrap();
+        assert!(DropWriter::new(&pk, "a/b", Vec::new()).is_err());
+        let mut buf = vec![0u8; 200 * 1024];
+        XorShiftRng::from_seed([7u8; 16]).fill_bytes(&mut buf);
+        let mut wtr = DropWriter::new(&pk, "file", Vec::new()).unwrap();
+        wtr.write_all(&buf).unwrap();
+        let package = wtr.finish().unwrap();
+
+        // incomplete package is rejected and nothing is created
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.drop_public_key(), Some(pk));
+        repo.create_dir("/inbox").unwrap();
+        assert_eq!(
+            repo.import_drop(&package[..package.len() - 10], "/inbox")
+                .unwrap_err(),
+            Error::Corrupted
+        );
+        assert!(!repo.path_exists("/inbox/file").unwrap());
+
+        // import package
+        let file_path = repo.import_drop(&package[..], "/inbox").unwrap();
+        assert_eq!(file_path, Path::new("/inbox/file"));
+        let mut dst = Vec::new();
+        repo.open_file("/inbox/file")
+            .unwrap()
+            .read_to_end(&mut dst)
+            .unwrap();
+        assert_eq!(dst, buf);
+        assert_eq!(
+            repo.import_drop(&package[..], "/inbox").unwrap_err(),
+            Error::AlreadyExists
+        );
+
+        // package for another repo cannot be imported
+        let path2 = base.clone() + "/repo53b";
+        let mut repo2 =
+            RepoOpener::new().create(true).open(&path2, &pwd).unwrap();
+        repo2.enable_drop_box(&pwd).unwrap();
+        assert_eq!(
+            repo2.import_drop(&package[..], "/").unwrap_err(),
+            Error::Decrypt
+        );
+    }
+
+    // case #54: test master key and data key rotation
+    {
+        let path = base.clone() + "/repo54";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        let mut buf = vec![0u8; 100 * 1024];
+        XorShiftRng::from_seed([54u8; 16]).fill_bytes(&mut buf);
+        repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+        repo.snapshot("snap").unwrap();
+        repo.create_dir("/dir").unwrap();
+        let key = Repo::derive_key(&path, &pwd).unwrap();
+
+        // rotate master key, derived key cannot be used any more
+        repo.rotate_master_key(&pwd).unwrap();
+        drop(repo);
+        assert_eq!(
+            RepoOpener::new().open_with_key(&path, &key).unwrap_err(),
+            Error::Decrypt
+        );
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+
+        // reset password keeps master key wrapped
+        repo.reset_password(
+            &pwd,
+            "pwd2",
+            OpsLimit::Interactive,
+            MemLimit::Interactive,
+        )
+        .unwrap();
+        assert!(repo.rotate_master_key(&pwd).is_err());
+        repo.rotate_master_key("pwd2").unwrap();
+
+        // rotate data key and verify all data is still readable
+        repo.rotate_data_key("pwd2").unwrap();
+        repo.create_file("/file2")
+            .unwrap()
+            .write_once(&buf)
+            .unwrap();
+        drop(repo);
+        let mut repo = RepoOpener::new().open(&path, "pwd2").unwrap();
+        assert!(repo.is_dir("/dir").unwrap());
+        for file_path in &["/file", "/file2"] {
+            let mut dst = Vec::new();
+            repo.open_file(file_path)
+                .unwrap()
+                .read_to_end(&mut dst)
+                .unwrap();
+            assert_eq!(dst, buf);
+        }
+        assert_eq!(repo.list_snapshots().len(), 1);
+        assert!(repo.verify().unwrap().is_ok());
+        repo.rotate_data_key("pwd2").unwrap();
+        drop(repo);
+
+        // rotation is not allowed in read-only mode
+        let mut repo = RepoOpener::new()
+            .read_only(true)
+            .open(&path, "pwd2")
+            .unwrap();
+        assert_eq!(repo.rotate_data_key("pwd2").unwrap_err(), Error::ReadOnly);
+        assert_eq!(
+            repo.rotate_master_key("pwd2").unwrap_err(),
+            Error::ReadOnly
+        );
+    }
+
+    // case #55: test re-encryption with progress
+    {
+        let path = base.clone() + "/repo55";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        assert!(repo.info().unwrap().reencrypt_checkpoint().is_none());
+        repo.create_dir("/dir").unwrap();
+        for i in 0..300 {
+            let file_path = format!("/dir/{}", i);
+            repo.create_file(&file_path)
+                .unwrap()
+                .write_once(file_path.as_bytes())
+                .unwrap();
+        }
+
+        let key = Repo::derive_key(&path, &pwd).unwrap();
+
+        // re-encryption spans multiple checkpoints
+        let mut last = (0, 0);
+        let cnt = repo
+            .reencrypt(&pwd, |done, total| {
+                assert!(done > last.0 && done <= total);
+                last = (done, total);
+            })
+            .unwrap();
+        assert!(cnt > 1024);
+        assert_eq!(last.0, last.1);
+        assert!(repo.info().unwrap().reencrypt_checkpoint().is_none());
+        drop(repo);
+
+        // master key is rotated as well
+        assert_eq!(
+            RepoOpener::new().open_with_key(&path, &key).unwrap_err(),
+            Error::Decrypt
+        );
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(repo.info().unwrap().reencrypt_checkpoint().is_none());
+        for i in 0..300 {
+            let file_path = format!("/dir/{}", i);
+            let mut content = String::new();
+            repo.open_file(&file_path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, file_path);
+        }
+    }
+
+    // case #56: test key domains
+    {
+        let path = base.clone() + "/repo56";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        let key = [42u8; 32];
+        repo.create_dir("/a").unwrap();
+        assert_eq!(
+            repo.set_key_domain("/a", &key[..16]).unwrap_err(),
+            Error::InvalidArgument
+        );
+        repo.set_key_domain("/a", &key).unwrap();
+        repo.create_dir("/a/sub").unwrap();
+        repo.create_file("/a/sub/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        repo.create_file("/bar")
+            .unwrap()
+            .write_once(b"bar")
+            .unwrap();
+        assert_eq!(
+            repo.set_key_domain("/a", &[0u8; 32]).unwrap_err(),
+            Error::Decrypt
+        );
+        assert_eq!(
+            repo.set_key_domain("/", &key).unwrap_err(),
+            Error::NotEmpty
+        );
+
+        // content cannot cross key domains, but domain root can be moved
+        assert_eq!(
+            repo.copy("/a/sub/foo", "/foo").unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            repo.rename("/a/sub/foo", "/foo").unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            repo.rename("/bar", "/a/bar").unwrap_err(),
+            Error::InvalidArgument
+        );
+        repo.copy("/a/sub/foo", "/a/foo").unwrap();
+        repo.create_dir("/a/nested").unwrap();
+        repo.rename("/a", "/b").unwrap();
+        drop(repo);
+
+        // key domain is locked after reopening, data key rotation doesn't
+        // need it unlocked
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        repo.rotate_data_key(&pwd).unwrap();
+        assert_eq!(
+            repo.open_file("/b/sub/foo").unwrap_err(),
+            Error::DomainLocked
+        );
+        assert_eq!(repo.metadata("/b/foo").unwrap().content_len(), 3);
+        let mut content = String::new();
+        repo.open_file("/bar")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "bar");
+
+        // nested key domain cannot be created in a locked one
+        assert_eq!(
+            repo.set_key_domain("/b/nested", &[1u8; 32]).unwrap_err(),
+            Error::DomainLocked
+        );
+
+        repo.set_key_domain("/b", &key).unwrap();
+        repo.set_key_domain("/b/nested", &[1u8; 32]).unwrap();
+        for file_path in &["/b/sub/foo", "/b/foo"] {
+            let mut content = String::new();
+            repo.open_file(file_path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, "foo");
+        }
+    }
+
+    // case #57: test snapshots with key domains
+    {
+        let path = base.clone() + "/repo57";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        let key = [7u8; 32];
+        let read = |repo: &mut Repo, path: &str| {
+            let mut buf = Vec::new();
+            repo.open_file(path).unwrap().read_to_end(&mut buf).unwrap();
+            buf
+        };
+        let write = |repo: &mut Repo, path: &str, buf: &[u8]| {
+            let mut f =
+                OpenOptions::new().create(true).open(repo, path).unwrap();
+            f.write_once(buf).unwrap();
+        };
+
+        repo.create_dir("/d").unwrap();
+        repo.set_key_domain("/d", &key).unwrap();
+        write(&mut repo, "/d/f", b"v1");
+        write(&mut repo, "/g", b"g");
+        repo.snapshot("s").unwrap();
+        let mut view = repo.freeze_view().unwrap();
+        assert_eq!(read(&mut view, "/d/f"), b"v1");
+        drop(view);
+
+        write(&mut repo, "/d/f", b"v2");
+        repo.rollback_to("s").unwrap();
+        assert_eq!(read(&mut repo, "/d/f"), b"v1");
+
+        // domain root replaced by a plain directory is restored
+        repo.remove_dir_all("/d").unwrap();
+        repo.create_dir("/d").unwrap();
+        write(&mut repo, "/d/f", b"plain");
+        repo.rollback_to("s").unwrap();
+        assert_eq!(read(&mut repo, "/d/f"), b"v1");
+        assert!(repo.verify().unwrap().is_ok());
+        drop(repo);
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.open_file("/d/f").unwrap_err(), Error::DomainLocked);
+        repo.set_key_domain("/d", &key).unwrap();
+        assert_eq!(read(&mut repo, "/d/f"), b"v1");
+    }
+
+    // case #58: test integrity-only mode
+    {
+        let path = base.clone() + "/repo58";
+        let mut repo = RepoOpener::new()
+            .create(true)
+            .cipher(Cipher::Integrity)
+            .open(&path, &pwd)
+            .unwrap();
+        let info = repo.info().unwrap();
+        assert_eq!(info.cipher(), Cipher::Integrity);
+        assert_eq!(info.nonce_strategy(), NonceStrategy::Deterministic);
+        repo.create_dir("/dir").unwrap();
+        repo.create_file("/dir/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&mut repo, "/dir/foo")
+                .unwrap();
+            file.write_once(b"foo2").unwrap();
+        }
+        repo.create_dir("/empty").unwrap();
+        assert_eq!(
+            repo.set_key_domain("/empty", &[42u8; 32]).unwrap_err(),
+            Error::InvalidArgument
+        );
+        drop(repo);
+
+        assert!(RepoOpener::new().open(&path, "wrong pwd").is_err());
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.metadata("/dir/foo").unwrap().curr_version(), 3);
+        let mut content = String::new();
+        repo.open_file("/dir/foo")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "foo2");
+
+        // migrate to an encrypted cipher
+        repo.migrate_cipher(&pwd, Cipher::Xchacha).unwrap();
+        drop(repo);
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert_eq!(repo.info().unwrap().cipher(), Cipher::Xchacha);
+        let mut content = String::new();
+        repo.open_file("/dir/foo")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "foo2");
+    }
+
+    // case #59: test convergent encryption
+    {
+        let path = base.clone() + "/repo59";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        assert!(!repo.info().unwrap().is_convergent());
+        assert_eq!(
+            repo.enable_convergent_encryption(&pwd, &[42u8; 16])
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+        repo.create_file("/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        repo.enable_convergent_encryption(&pwd, &[42u8; 32])
+            .unwrap();
+        repo.enable_convergent_encryption(&pwd, &[42u8; 32])
+            .unwrap();
+        assert_eq!(
+            repo.enable_convergent_encryption(&pwd, &[43u8; 32])
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert!(repo.info().unwrap().is_convergent());
+        repo.create_file("/bar")
+            .unwrap()
+            .write_once(&[7u8; 100_000])
+            .unwrap();
+        drop(repo);
+
+        let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+        assert!(repo.info().unwrap().is_convergent());
+        let mut content = String::new();
+        repo.open_file("/foo")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "foo");
+        let mut buf = Vec::new();
+        repo.open_file("/bar")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, vec![7u8; 100_000]);
+
+        // convergently encrypted content survives cipher migration
+        #[cfg(feature = "aes-gcm-siv")]
+        {
+            repo.migrate_cipher(&pwd, Cipher::AesGcmSiv).unwrap();
+            drop(repo);
+            let mut repo = RepoOpener::new().open(&path, &pwd).unwrap();
+            assert_eq!(repo.info().unwrap().cipher(), Cipher::AesGcmSiv);
+            let mut buf = Vec::new();
+            repo.open_file("/bar")
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            assert_eq!(buf, vec![7u8; 100_000]);
+        }
+    }
+
+    // case #60: test recovery key
+    {
+        let path = base.clone() + "/repo60";
+        let mut repo =
+            RepoOpener::new().create(true).open(&path, &pwd).unwrap();
+        repo.create_file("/foo")
+            .unwrap()
+            .write_once(b"foo")
+            .unwrap();
+        let key = repo.export_recovery_key(&pwd, "escrow").unwrap();
+        assert!(repo.export_recovery_key("wrong pwd", "escrow").is_err());
+
+        // recovery key survives password reset
+        repo.reset_password(
+            &pwd,
+            "forgotten",
+            OpsLimit::Interactive,
+            MemLimit::Interactive,
+        )
+        .unwrap();
+        drop(repo);
+
+        assert_eq!(
+            RepoOpener::new()
+                .open_with_recovery_key(&path, &key, "wrong")
+                .unwrap_err(),
+            Error::Decrypt
+        );
+        let bad_key = RecoveryKey::from_bytes(&key.as_bytes()[1..]);
+        assert_eq!(
+            RepoOpener::new()
+                .open_with_recovery_key(&path, &bad_key, "escrow")
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+        let mut repo = RepoOpener::new()
+            .open_with_recovery_key(&path, &key, "escrow")
+            .unwrap();
+        let mut content = String::new();
+        repo.open_file("/foo")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "foo");
+
+        // reset forgotten password using recovery key
+        assert_eq!(
+            repo.reset_password_with_recovery_key(
+                &key,
+                "wrong",
+                "new pwd",
+                OpsLimit::Interactive,
+                MemLimit::Interactive,
+            )
+            .unwrap_err(),
+            Error::Decrypt
+        );
+        repo.reset_password_with_recovery_key(
+            &key,
+            "escrow",
+            "new pwd",
+            OpsLimit::Interactive,
+            MemLimit::Interactive,
+        )
+        .unwrap();
+        drop(repo);
+        assert!(RepoOpener::new().open(&path, "forgotten").is_err());
+
+        // rotating master key makes recovery key outdated
+        let mut repo = RepoOpener::new().open(&path, "new pwd").unwrap();
+        repo.rotate_master_key("new pwd").unwrap();
+        drop(repo);
+        assert_eq!(
+            RepoOpener::new()
+                .open_with_recovery_key(&path, &key, "escrow")
+                .unwrap_err(),
+            Error::Decrypt
+        );
+    }
+
     // to suppress unused variable warning
     drop(dir);
     drop(tmpdir);
@@ -339,7 +2884,9 @@ fn repo_smoke_test() {
             feature = "storage-mem",
             not(feature = "storage-file"),
             not(feature = "storage-sqlite"),
-            not(feature = "storage-redis")
+            not(feature = "storage-redis"),
+            not(feature = "storage-sled"),
+            not(feature = "storage-container")
         ))]
         {
             "mem://repo_smoke_test".to_string()
@@ -360,6 +2907,18 @@ fn repo_smoke_test() {
         {
             "redis://localhost:6379".to_string()
         }
+
+        #[cfg(feature = "storage-sled")]
+        {
+            let dir = tmpdir.path().join("zbox.sled");
+            "sled://".to_string() + dir.to_str().unwrap()
+        }
+
+        #[cfg(feature = "storage-container")]
+        {
+            let file = tmpdir.path().join("repo.zbox");
+            "container://".to_string() + file.to_str().unwrap()
+        }
     };
 
     smoke_test(uri);
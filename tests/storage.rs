@@ -0,0 +1,181 @@
+#![cfg(feature = "storage-mem")]
+
+extern crate zbox;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use zbox::{
+    init_env, register_storage, Crypto, Eid, Error, Key, OpenOptions,
+    RepoOpener, Result, Span, Storable, BLK_SIZE,
+};
+
+// a simple custom storage keeps everything in a shared hash map
+#[derive(Debug, Default)]
+struct Depot {
+    super_blks: HashMap<u64, Vec<u8>>,
+    wals: HashMap<Eid, Vec<u8>>,
+    addrs: HashMap<Eid, Vec<u8>>,
+    blks: HashMap<usize, Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct MapStorage {
+    depot: Arc<Mutex<Depot>>,
+}
+
+fn get(map: &HashMap<Eid, Vec<u8>>, id: &Eid) -> Result<Vec<u8>> {
+    map.get(id).cloned().ok_or(Error::NotFound)
+}
+
+impl Storable for MapStorage {
+    fn exists(&self) -> Result<bool> {
+        Ok(!self.depot.lock().unwrap().super_blks.is_empty())
+    }
+
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+        Ok(())
+    }
+
+    fn open(&mut self, _crypto: Crypto, _key: Key, _force: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        let depot = self.depot.lock().unwrap();
+        depot
+            .super_blks
+            .get(&suffix)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        let mut depot = self.depot.lock().unwrap();
+        depot.super_blks.insert(suffix, super_blk.to_vec());
+        Ok(())
+    }
+
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        get(&self.depot.lock().unwrap().wals, id)
+    }
+
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        let mut depot = self.depot.lock().unwrap();
+        depot.wals.insert(id.clone(), wal.to_vec());
+        Ok(())
+    }
+
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.depot.lock().unwrap().wals.remove(id);
+        Ok(())
+    }
+
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        get(&self.depot.lock().unwrap().addrs, id)
+    }
+
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        let mut depot = self.depot.lock().unwrap();
+        depot.addrs.insert(id.clone(), addr.to_vec());
+        Ok(())
+    }
+
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.depot.lock().unwrap().addrs.remove(id);
+        Ok(())
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let depot = self.depot.lock().unwrap();
+        for (blk_idx, dst) in
+            (span.begin..span.end()).zip(dst.chunks_mut(BLK_SIZE))
+        {
+            let blk = depot.blks.get(&blk_idx).ok_or(Error::NotFound)?;
+            dst.copy_from_slice(blk);
+        }
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
+        let mut depot = self.depot.lock().unwrap();
+        for (blk_idx, blk) in
+            (span.begin..span.end()).zip(blks.chunks(BLK_SIZE))
+        {
+            depot.blks.insert(blk_idx, blk.to_vec());
+        }
+        Ok(())
+    }
+
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        let mut depot = self.depot.lock().unwrap();
+        for blk_idx in span.begin..span.end() {
+            depot.blks.remove(&blk_idx);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        *self.depot.lock().unwrap() = Depot::default();
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_storage() {
+    init_env();
+
+    let depots: Arc<Mutex<HashMap<String, Arc<Mutex<Depot>>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    let depots2 = depots.clone();
+    register_storage("mapstore", move |loc| {
+        let mut depots = depots2.lock().unwrap();
+        let depot = depots.entry(loc.to_string()).or_default().clone();
+        Ok(Box::new(MapStorage { depot }) as Box<dyn Storable>)
+    })
+    .unwrap();
+
+    // built-in scheme cannot be overridden
+    assert_eq!(
+        register_storage("mem", |_| Err(Error::NotFound)).unwrap_err(),
+        Error::AlreadyExists
+    );
+
+    // unknown scheme
+    assert_eq!(
+        RepoOpener::new()
+            .create(true)
+            .open("unknown://foo", "pwd")
+            .unwrap_err(),
+        Error::InvalidUri
+    );
+
+    let uri = "mapstore://foo";
+    let buf = vec![42u8; 3 * BLK_SIZE];
+    {
+        let mut repo = RepoOpener::new().create(true).open(uri, "pwd").unwrap();
+        let mut f = OpenOptions::new()
+            .create(true)
+            .open(&mut repo, "/file")
+            .unwrap();
+        f.write_all(&buf).unwrap();
+        f.finish().unwrap();
+    }
+    assert!(depots.lock().unwrap().contains_key("foo"));
+
+    // re-open and read back
+    let mut repo = RepoOpener::new().open(uri, "pwd").unwrap();
+    let mut f = repo.open_file("/file").unwrap();
+    let mut dst = Vec::new();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, buf);
+}
@@ -0,0 +1,147 @@
+#[macro_use]
+extern crate cfg_if;
+extern crate tempdir;
+extern crate zbox;
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tempdir::TempDir;
+use zbox::{Crypto, FileType, OpenOptions, Repo};
+
+// collect all entries with their types and sizes in a directory recursively
+fn collect_tree(repo: &Repo, path: &Path, out: &mut Vec<(String, usize)>) {
+    let mut dirs = repo.read_dir(path).unwrap();
+    dirs.sort_by(|a, b| a.path().cmp(b.path()));
+    for ent in dirs {
+        let md = ent.metadata();
+        let path = ent.path().to_str().unwrap().to_string();
+        if md.is_dir() {
+            out.push((path, 0));
+            collect_tree(repo, ent.path(), out);
+        } else {
+            out.push((path, md.content_len()));
+        }
+    }
+}
+
+#[test]
+fn trace_record_replay() {
+    let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+    let trace_path = tmpdir.path().join("trace");
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // record operations
+    repo.start_trace(&trace_path).unwrap();
+    repo.create_dir_all("/dir/sub").unwrap();
+    repo.create_dir("/dir2").unwrap();
+    {
+        let mut f = repo.create_file("/dir/file").unwrap();
+        f.write_once(&[1u8; 1000]).unwrap();
+        f.seek(SeekFrom::Start(500)).unwrap();
+        f.write_all(&[2u8; 1000]).unwrap();
+        f.finish().unwrap();
+        f.set_len(1200).unwrap();
+        f.write_at(&[5u8; 100], 1200).unwrap();
+    }
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .version_limit(3)
+            .open(repo, "/dir2/file2")
+            .unwrap();
+        f.write_once(&[3u8; 300]).unwrap();
+        f.write_once(&[4u8; 400]).unwrap();
+        f.restore_version(2).unwrap();
+        let mut buf = Vec::new();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.read_to_end(&mut buf).unwrap();
+    }
+    {
+        let mut f = repo.create_temp_file().unwrap();
+        f.write_once(&[6u8; 600]).unwrap();
+        f.persist("/dir2/temp").unwrap();
+    }
+    repo.copy_file_range("/dir2/temp", 100, "/dir2/file2", 50, 300)
+        .unwrap();
+    repo.copy("/dir/file", "/dir/sub/file").unwrap();
+    repo.clone_file("/dir2/temp", "/dir2/clone").unwrap();
+    let ctime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    repo.set_times("/dir2/clone", Some(ctime), None).unwrap();
+    repo.symlink("clone", "/dir2/link").unwrap();
+    repo.create_node("/dir2/fifo", FileType::Fifo, 0).unwrap();
+    repo.rename("/dir/file", "/dir/file3").unwrap();
+    repo.copy_dir_all("/dir", "/dir3").unwrap();
+    repo.remove_file("/dir3/file3").unwrap();
+    repo.shred("/dir3/sub/file").unwrap();
+    repo.remove_dir_all("/dir3").unwrap();
+    assert!(repo.remove_dir("/not_exist").is_err());
+    repo.stop_trace();
+
+    // not traced
+    repo.create_dir("/dir4").unwrap();
+
+    // replay trace against a new repo
+    let mut env2 = common::TestEnv::new();
+    let repo2 = &mut env2.repo;
+    assert!(repo2.replay_trace(&trace_path).unwrap() > 0);
+
+    let mut tree = Vec::new();
+    let mut tree2 = Vec::new();
+    repo.remove_dir("/dir4").unwrap();
+    collect_tree(repo, Path::new("/"), &mut tree);
+    collect_tree(repo2, Path::new("/"), &mut tree2);
+    assert_eq!(tree, tree2);
+    assert_eq!(
+        repo.history("/dir2/file2").unwrap().len(),
+        repo2.history("/dir2/file2").unwrap().len()
+    );
+    assert_eq!(repo2.metadata("/dir2/clone").unwrap().created_at(), ctime);
+    assert_eq!(repo2.read_link("/dir2/link").unwrap(), Path::new("clone"));
+    assert_eq!(
+        repo2.metadata("/dir2/fifo").unwrap().file_type(),
+        FileType::Fifo
+    );
+
+    // identical content should still be identical after replay
+    let mut buf = Vec::new();
+    let mut buf2 = Vec::new();
+    repo2
+        .open_file("/dir/file3")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    repo2
+        .open_file("/dir/sub/file")
+        .unwrap()
+        .read_to_end(&mut buf2)
+        .unwrap();
+    assert_eq!(buf.len(), 1300);
+    assert_eq!(buf, buf2);
+}
+
+#[test]
+fn trace_no_content_hash() {
+    let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+    let trace_path = tmpdir.path().join("trace");
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+    let content = b"guessable content";
+
+    repo.start_trace(&trace_path).unwrap();
+    repo.create_file("/file")
+        .unwrap()
+        .write_once(&content[..])
+        .unwrap();
+    repo.stop_trace();
+
+    // unkeyed content hash must not appear in trace
+    let trace = fs::read(&trace_path).unwrap();
+    let hash = Crypto::hash(&content[..]);
+    assert!(!trace.windows(hash.len()).any(|win| win == &hash[..]));
+}